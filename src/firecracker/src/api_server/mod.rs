@@ -276,6 +276,10 @@ mod tests {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                max_snapshot_size: None,
+                require_guest_quiesce: false,
+                abort_on_guest_reset: false,
+                quiesce_timeout_ms: None,
             })),
             start_time_us,
         );
@@ -289,6 +293,10 @@ mod tests {
                 snapshot_type: SnapshotType::Diff,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                max_snapshot_size: None,
+                require_guest_quiesce: false,
+                abort_on_guest_reset: false,
+                quiesce_timeout_ms: None,
             })),
             start_time_us,
         );
@@ -0,0 +1,54 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::clock::ClockConfig;
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_clock(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<ClockConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetClockConfig(cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_clock_request() {
+        parse_put_clock(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "vmclock": "yes"
+        }"#;
+        parse_put_clock(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "vmclock": true
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_clock(&Body::new(body)).unwrap()),
+            VmmAction::SetClockConfig(ClockConfig {
+                vmclock: true,
+                host_time: false,
+            })
+        );
+
+        let body = r#"{
+            "vmclock": false,
+            "host_time": true
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_clock(&Body::new(body)).unwrap()),
+            VmmAction::SetClockConfig(ClockConfig {
+                vmclock: false,
+                host_time: true,
+            })
+        );
+    }
+}
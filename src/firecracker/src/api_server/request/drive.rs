@@ -8,6 +8,13 @@ use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig};
 use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
 use super::{Body, StatusCode};
 
+pub(crate) fn parse_get_drive(id_from_path: Option<&str>) -> Result<ParsedRequest, RequestError> {
+    let id = id_from_path.ok_or(RequestError::EmptyID)?;
+    Ok(ParsedRequest::new_sync(VmmAction::GetBlockBackingInfo(
+        checked_id(id)?.to_string(),
+    )))
+}
+
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&str>,
@@ -74,6 +81,16 @@ mod tests {
     use super::*;
     use crate::api_server::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_drive_request() {
+        parse_get_drive(None).unwrap_err();
+        parse_get_drive(Some("bad id")).unwrap_err();
+        assert_eq!(
+            vmm_action_from_request(parse_get_drive(Some("foo")).unwrap()),
+            VmmAction::GetBlockBackingInfo("foo".to_string())
+        );
+    }
+
     #[test]
     fn test_parse_patch_drive_request() {
         parse_patch_drive(&Body::new("invalid_payload"), None).unwrap_err();
@@ -139,6 +156,8 @@ mod tests {
             drive_id: "foo".to_string(),
             path_on_host: Some("dummy".to_string()),
             rate_limiter: None,
+            cache_type: None,
+            enabled: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
@@ -185,6 +204,20 @@ mod tests {
         // Validate that updating both path and rate limiter succeds.
         parse_patch_drive(&Body::new(body), Some("foo")).unwrap();
 
+        let body = r#"{
+            "drive_id": "foo",
+            "enabled": false
+        }"#;
+        let expected_config = BlockDeviceUpdateConfig {
+            drive_id: "foo".to_string(),
+            enabled: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_patch_drive(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateBlockDevice(expected_config)
+        );
+
         let body = r#"{
             "drive_id": "foo",
             "path_on_host": "/there",
@@ -0,0 +1,70 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::fs::FsDeviceConfig;
+
+use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
+use super::{Body, StatusCode};
+
+pub(crate) fn parse_put_fs(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(RequestError::EmptyID);
+    };
+
+    let fs_cfg = serde_json::from_slice::<FsDeviceConfig>(body.raw())?;
+    if id != fs_cfg.fs_id.as_str() {
+        return Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "The id from the path [{}] does not match the id from the body [{}]!",
+                id,
+                fs_cfg.fs_id.as_str()
+            ),
+        ));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::InsertFsDevice(fs_cfg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_fs_request() {
+        let body = r#"{
+            "fs_id": "fs0",
+            "tag": "shared",
+            "socket": "/tmp/fs.sock"
+        }"#;
+        // Missing id.
+        parse_put_fs(&Body::new(body), None).unwrap_err();
+        // Mismatched id.
+        parse_put_fs(&Body::new(body), Some("fs1")).unwrap_err();
+
+        let expected_config = FsDeviceConfig {
+            fs_id: "fs0".to_string(),
+            tag: "shared".to_string(),
+            socket: "/tmp/fs.sock".to_string(),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_fs(&Body::new(body), Some("fs0")).unwrap()),
+            VmmAction::InsertFsDevice(expected_config)
+        );
+
+        // Unknown fields.
+        let body = r#"{
+            "fs_id": "fs0",
+            "tag": "shared",
+            "socket": "/tmp/fs.sock",
+            "cache": "always"
+        }"#;
+        parse_put_fs(&Body::new(body), Some("fs0")).unwrap_err();
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::input::InputDeviceConfig;
+
+use super::super::parsed_request::{checked_id, ParsedRequest, RequestError};
+use super::{Body, StatusCode};
+
+pub(crate) fn parse_put_input(
+    body: &Body,
+    id_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(RequestError::EmptyID);
+    };
+
+    let input_cfg = serde_json::from_slice::<InputDeviceConfig>(body.raw())?;
+    if id != input_cfg.input_id.as_str() {
+        return Err(RequestError::Generic(
+            StatusCode::BadRequest,
+            format!(
+                "The id from the path [{}] does not match the id from the body [{}]!",
+                id,
+                input_cfg.input_id.as_str()
+            ),
+        ));
+    }
+    Ok(ParsedRequest::new_sync(VmmAction::InsertInputDevice(
+        input_cfg,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use vmm::devices::virtio::input::InputKind;
+
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_input_request() {
+        let body = r#"{
+            "input_id": "input0",
+            "kind": "Tablet"
+        }"#;
+        // Missing id.
+        parse_put_input(&Body::new(body), None).unwrap_err();
+        // Mismatched id.
+        parse_put_input(&Body::new(body), Some("input1")).unwrap_err();
+
+        let expected_config = InputDeviceConfig {
+            input_id: "input0".to_string(),
+            kind: InputKind::Tablet,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_input(&Body::new(body), Some("input0")).unwrap()),
+            VmmAction::InsertInputDevice(expected_config)
+        );
+
+        // The kind defaults to a keyboard.
+        let body = r#"{
+            "input_id": "input0"
+        }"#;
+        let expected_config = InputDeviceConfig {
+            input_id: "input0".to_string(),
+            kind: InputKind::Keyboard,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_input(&Body::new(body), Some("input0")).unwrap()),
+            VmmAction::InsertInputDevice(expected_config)
+        );
+
+        // Unknown kind.
+        let body = r#"{
+            "input_id": "input0",
+            "kind": "Joystick"
+        }"#;
+        parse_put_input(&Body::new(body), Some("input0")).unwrap_err();
+    }
+}
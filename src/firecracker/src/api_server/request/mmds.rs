@@ -58,20 +58,41 @@ pub(crate) fn parse_put_mmds(
     }
 }
 
-pub(crate) fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, RequestError> {
+pub(crate) fn parse_patch_mmds(
+    body: &Body,
+    path_second_token: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
     METRICS.patch_api_requests.mmds_count.inc();
-    Ok(ParsedRequest::new_sync(VmmAction::PatchMMDS(
-        serde_json::from_slice(body.raw()).map_err(|err| {
+    match path_second_token {
+        None => Ok(ParsedRequest::new_sync(VmmAction::PatchMMDS(
+            serde_json::from_slice(body.raw()).map_err(|err| {
+                METRICS.patch_api_requests.mmds_fails.inc();
+                err
+            })?,
+        ))),
+        Some("merge") => Ok(ParsedRequest::new_sync(VmmAction::MergeMMDS(
+            serde_json::from_slice(body.raw()).map_err(|err| {
+                METRICS.patch_api_requests.mmds_fails.inc();
+                err
+            })?,
+        ))),
+        Some(unrecognized) => {
             METRICS.patch_api_requests.mmds_fails.inc();
-            err
-        })?,
-    )))
+            Err(RequestError::Generic(
+                StatusCode::BadRequest,
+                format!("Unrecognized PATCH request path `{}`.", unrecognized),
+            ))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use vmm::mmds::data_store::MmdsMergePolicy;
+    use vmm::vmm_config::mmds::MmdsMergeConfig;
+
     use super::*;
-    use crate::api_server::parsed_request::tests::depr_action_from_req;
+    use crate::api_server::parsed_request::tests::{depr_action_from_req, vmm_action_from_request};
 
     #[test]
     fn test_parse_get_mmds_request() {
@@ -169,9 +190,47 @@ mod tests {
         let body = r#"{
             "foo": "bar"
         }"#;
-        parse_patch_mmds(&Body::new(body)).unwrap();
+        parse_patch_mmds(&Body::new(body), None).unwrap();
         assert!(METRICS.patch_api_requests.mmds_count.count() > 0);
-        parse_patch_mmds(&Body::new("invalid_body")).unwrap_err();
+        parse_patch_mmds(&Body::new("invalid_body"), None).unwrap_err();
         assert!(METRICS.patch_api_requests.mmds_fails.count() > 0);
+        parse_patch_mmds(&Body::new(body), Some("invalid_path")).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_patch_mmds_merge_request() {
+        let body = r#"{
+            "data": {
+                "foo": "bar"
+            },
+            "policy": "Reject"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_mmds(&Body::new(body), Some("merge")).unwrap()),
+            VmmAction::MergeMMDS(MmdsMergeConfig {
+                data: serde_json::json!({"foo": "bar"}),
+                policy: MmdsMergePolicy::Reject,
+            })
+        );
+
+        // The policy defaults to last-writer-wins.
+        let body = r#"{
+            "data": {
+                "foo": "bar"
+            }
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_mmds(&Body::new(body), Some("merge")).unwrap()),
+            VmmAction::MergeMMDS(MmdsMergeConfig {
+                data: serde_json::json!({"foo": "bar"}),
+                policy: MmdsMergePolicy::LastWriterWins,
+            })
+        );
+
+        let body = r#"{
+            "data": {},
+            "invalid_field": true
+        }"#;
+        parse_patch_mmds(&Body::new(body), Some("merge")).unwrap_err();
     }
 }
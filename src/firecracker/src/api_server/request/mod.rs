@@ -4,15 +4,19 @@
 pub mod actions;
 pub mod balloon;
 pub mod boot_source;
+pub mod clock;
 pub mod cpu_configuration;
 pub mod drive;
 pub mod entropy;
+pub mod fs;
+pub mod input;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
 pub mod metrics;
 pub mod mmds;
 pub mod net;
+pub mod serial;
 pub mod snapshot;
 pub mod version;
 pub mod vsock;
@@ -152,5 +152,20 @@ mod tests {
             }
         }"#;
         parse_patch_net(&Body::new(body), Some("foo")).unwrap_err();
+
+        // 5. Success case for the enabled state.
+        let body = r#"{
+            "iface_id": "foo",
+            "enabled": false
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_net(&Body::new(body), Some("foo")).unwrap()),
+            VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+                iface_id: "foo".to_string(),
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                enabled: Some(false),
+            })
+        );
     }
 }
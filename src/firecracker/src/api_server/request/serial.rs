@@ -0,0 +1,79 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+use vmm::vmm_config::serial::{SerialConfig, SerialOutputUpdate};
+
+use super::super::parsed_request::{ParsedRequest, RequestError};
+use super::Body;
+
+pub(crate) fn parse_put_serial(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let cfg = serde_json::from_slice::<SerialConfig>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetSerialConfig(cfg)))
+}
+
+pub(crate) fn parse_patch_serial(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let update = serde_json::from_slice::<SerialOutputUpdate>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateSerialOutput(
+        update,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_server::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_serial_request() {
+        parse_put_serial(&Body::new("invalid_payload")).unwrap_err();
+
+        // PUT with invalid fields.
+        let body = r#"{
+            "panic_signature": 42
+        }"#;
+        parse_put_serial(&Body::new(body)).unwrap_err();
+
+        // PUT with valid fields.
+        let body = r#"{
+            "panic_signature": "Kernel panic",
+            "output_socket": "serial.sock"
+        }"#;
+        let expected_config = SerialConfig {
+            panic_signature: Some("Kernel panic".to_string()),
+            output_socket: Some("serial.sock".into()),
+            output_path: None,
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_serial(&Body::new(body)).unwrap()),
+            VmmAction::SetSerialConfig(expected_config)
+        );
+    }
+
+    #[test]
+    fn test_parse_patch_serial_request() {
+        parse_patch_serial(&Body::new("invalid_payload")).unwrap_err();
+
+        // PATCH with fields that cannot be updated.
+        let body = r#"{
+            "output_socket": "serial.sock"
+        }"#;
+        parse_patch_serial(&Body::new(body)).unwrap_err();
+
+        let body = r#"{
+            "output_path": "serial.out"
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_patch_serial(&Body::new(body)).unwrap()),
+            VmmAction::UpdateSerialOutput(SerialOutputUpdate {
+                output_path: Some("serial.out".into()),
+            })
+        );
+
+        // Without a path, the output goes back to stdout.
+        assert_eq!(
+            vmm_action_from_request(parse_patch_serial(&Body::new("{}")).unwrap()),
+            VmmAction::UpdateSerialOutput(SerialOutputUpdate::default())
+        );
+    }
+}
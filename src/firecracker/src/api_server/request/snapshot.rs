@@ -6,7 +6,7 @@ use vmm::logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
 use vmm::vmm_config::snapshot::{
     CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    QuiesceGuestParams, Vm, VmState,
 };
 
 use super::super::parsed_request::{ParsedRequest, RequestError};
@@ -30,6 +30,7 @@ pub(crate) fn parse_put_snapshot(
         Some(request_type) => match request_type {
             "create" => parse_put_snapshot_create(body),
             "load" => parse_put_snapshot_load(body),
+            "quiesce" => parse_put_snapshot_quiesce(body),
             _ => Err(RequestError::InvalidPathMethod(
                 format!("/snapshot/{}", request_type),
                 Method::Put,
@@ -42,6 +43,22 @@ pub(crate) fn parse_put_snapshot(
     }
 }
 
+pub(crate) fn parse_get_snapshot(
+    request_type_from_path: Option<&str>,
+) -> Result<ParsedRequest, RequestError> {
+    match request_type_from_path {
+        Some("quiesce") => Ok(ParsedRequest::new_sync(VmmAction::GetGuestQuiesceState)),
+        Some(request_type) => Err(RequestError::InvalidPathMethod(
+            format!("/snapshot/{}", request_type),
+            Method::Get,
+        )),
+        None => Err(RequestError::InvalidPathMethod(
+            "/snapshot".to_string(),
+            Method::Get,
+        )),
+    }
+}
+
 pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, RequestError> {
     let vm = serde_json::from_slice::<Vm>(body.raw())?;
 
@@ -58,6 +75,13 @@ fn parse_put_snapshot_create(body: &Body) -> Result<ParsedRequest, RequestError>
     )))
 }
 
+fn parse_put_snapshot_quiesce(body: &Body) -> Result<ParsedRequest, RequestError> {
+    let quiesce_params = serde_json::from_slice::<QuiesceGuestParams>(body.raw())?;
+    Ok(ParsedRequest::new_sync(VmmAction::QuiesceGuest(
+        quiesce_params,
+    )))
+}
+
 fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, RequestError> {
     let snapshot_config = serde_json::from_slice::<LoadSnapshotConfig>(body.raw())?;
 
@@ -105,6 +129,8 @@ fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, RequestError> {
         mem_backend,
         enable_diff_snapshots: snapshot_config.enable_diff_snapshots,
         resume_vm: snapshot_config.resume_vm,
+        relaxed_version_check: snapshot_config.relaxed_version_check,
+        preserve_device_uptime: snapshot_config.preserve_device_uptime,
     };
 
     // Construct the `ParsedRequest` object.
@@ -140,6 +166,10 @@ mod tests {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            max_snapshot_size: None,
+            require_guest_quiesce: false,
+            abort_on_guest_reset: false,
+            quiesce_timeout_ms: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
@@ -154,6 +184,10 @@ mod tests {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            max_snapshot_size: None,
+            require_guest_quiesce: false,
+            abort_on_guest_reset: false,
+            quiesce_timeout_ms: None,
         };
         assert_eq!(
             vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("create")).unwrap()),
@@ -181,6 +215,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -208,6 +244,8 @@ mod tests {
             },
             enable_diff_snapshots: true,
             resume_vm: false,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -225,7 +263,8 @@ mod tests {
                 "backend_path": "bar",
                 "backend_type": "Uffd"
             },
-            "resume_vm": true
+            "resume_vm": true,
+            "relaxed_version_check": true
         }"#;
         let expected_config = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
@@ -235,6 +274,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            relaxed_version_check: true,
+            preserve_device_uptime: false,
         };
         let mut parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert!(parsed_request
@@ -259,6 +300,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         };
         let parsed_request = parse_put_snapshot(&Body::new(body), Some("load")).unwrap();
         assert_eq!(
@@ -343,6 +386,25 @@ mod tests {
         parse_put_snapshot(&Body::new(body), None).unwrap_err();
     }
 
+    #[test]
+    fn test_parse_snapshot_quiesce() {
+        let body = r#"{
+            "timeout_ms": 500
+        }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some("quiesce")).unwrap()),
+            VmmAction::QuiesceGuest(QuiesceGuestParams { timeout_ms: 500 })
+        );
+        parse_put_snapshot(&Body::new("{}"), Some("quiesce")).unwrap_err();
+
+        assert_eq!(
+            vmm_action_from_request(parse_get_snapshot(Some("quiesce")).unwrap()),
+            VmmAction::GetGuestQuiesceState
+        );
+        parse_get_snapshot(Some("create")).unwrap_err();
+        parse_get_snapshot(None).unwrap_err();
+    }
+
     #[test]
     fn test_parse_patch_vm_state() {
         let body = r#"{
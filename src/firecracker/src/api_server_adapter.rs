@@ -143,6 +143,8 @@ pub(crate) fn run_with_api(
     instance_info: InstanceInfo,
     process_time_reporter: ProcessTimeReporter,
     boot_timer_enabled: bool,
+    device_self_test: bool,
+    max_guest_cid: Option<u32>,
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
@@ -211,6 +213,8 @@ pub(crate) fn run_with_api(
             json,
             instance_info,
             boot_timer_enabled,
+            device_self_test,
+            max_guest_cid,
             mmds_size_limit,
             metadata_json,
         )
@@ -223,6 +227,8 @@ pub(crate) fn run_with_api(
             &to_api,
             &api_event_fd,
             boot_timer_enabled,
+            device_self_test,
+            max_guest_cid,
             mmds_size_limit,
             metadata_json,
         )
@@ -229,6 +229,10 @@ fn main_exec() -> Result<(), MainError> {
                 "Whether or not to load boot timer device for logging elapsed time since \
                  InstanceStart command.",
             ))
+            .arg(Argument::new("device-self-test").takes_value(false).help(
+                "Whether or not to check that the host resources backing the devices work before \
+                 booting the microVM.",
+            ))
             .arg(
                 Argument::new("version")
                     .takes_value(false)
@@ -254,6 +258,11 @@ fn main_exec() -> Result<(), MainError> {
                 Argument::new("mmds-size-limit")
                     .takes_value(true)
                     .help("Mmds data store limit, in bytes."),
+            )
+            .arg(
+                Argument::new("max-guest-cid")
+                    .takes_value(true)
+                    .help("Maximum guest CID accepted for the vsock device."),
             );
 
     arg_parser.parse_from_cmdline()?;
@@ -363,6 +372,7 @@ fn main_exec() -> Result<(), MainError> {
         .map(|x| x.expect("Unable to open or read from the mmds content file"));
 
     let boot_timer_enabled = arguments.flag_present("boot-timer");
+    let device_self_test = arguments.flag_present("device-self-test");
     let api_enabled = !arguments.flag_present("no-api");
     let api_payload_limit = arg_parser
         .arguments()
@@ -385,6 +395,11 @@ fn main_exec() -> Result<(), MainError> {
         })
         .unwrap_or_else(|| api_payload_limit);
 
+    let max_guest_cid = arguments.single_value("max-guest-cid").map(|cid| {
+        cid.parse::<u32>()
+            .expect("'max-guest-cid' parameter expected to be of 'u32' type.")
+    });
+
     if api_enabled {
         let bind_path = arguments
             .single_value("api-sock")
@@ -416,6 +431,8 @@ fn main_exec() -> Result<(), MainError> {
             instance_info,
             process_time_reporter,
             boot_timer_enabled,
+            device_self_test,
+            max_guest_cid,
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
@@ -431,6 +448,8 @@ fn main_exec() -> Result<(), MainError> {
             vmm_config_json,
             instance_info,
             boot_timer_enabled,
+            device_self_test,
+            max_guest_cid,
             mmds_size_limit,
             metadata_json.as_deref(),
         )
@@ -555,12 +574,15 @@ pub enum BuildFromJsonError {
 }
 
 // Configure and start a microVM as described by the command-line JSON.
+#[allow(clippy::too_many_arguments)]
 fn build_microvm_from_json(
     seccomp_filters: &BpfThreadMap,
     event_manager: &mut EventManager,
     config_json: String,
     instance_info: InstanceInfo,
     boot_timer_enabled: bool,
+    device_self_test: bool,
+    max_guest_cid: Option<u32>,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
 ) -> Result<(VmResources, Arc<Mutex<vmm::Vmm>>), BuildFromJsonError> {
@@ -568,6 +590,11 @@ fn build_microvm_from_json(
         VmResources::from_json(&config_json, &instance_info, mmds_size_limit, metadata_json)
             .map_err(BuildFromJsonError::ParseFromJson)?;
     vm_resources.boot_timer = boot_timer_enabled;
+    vm_resources.device_self_test = device_self_test;
+    vm_resources
+        .vsock
+        .set_max_guest_cid(max_guest_cid)
+        .map_err(|err| BuildFromJsonError::ParseFromJson(err.into()))?;
     let vmm = vmm::builder::build_and_boot_microvm(
         &instance_info,
         &vm_resources,
@@ -589,11 +616,14 @@ enum RunWithoutApiError {
     BuildMicroVMFromJson(BuildFromJsonError),
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_without_api(
     seccomp_filters: &BpfThreadMap,
     config_json: Option<String>,
     instance_info: InstanceInfo,
     bool_timer_enabled: bool,
+    device_self_test: bool,
+    max_guest_cid: Option<u32>,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
 ) -> Result<(), RunWithoutApiError> {
@@ -611,6 +641,8 @@ fn run_without_api(
         config_json.unwrap(),
         instance_info,
         bool_timer_enabled,
+        device_self_test,
+        max_guest_cid,
         mmds_size_limit,
         metadata_json,
     )
@@ -22,6 +22,7 @@ pub mod validators;
 
 use std::num::Wrapping;
 use std::result::Result;
+use std::sync::atomic::{compiler_fence, Ordering};
 
 /// Return the default page size of the platform, in bytes.
 pub fn get_page_size() -> Result<usize, errno::Error> {
@@ -46,3 +47,27 @@ pub const fn u64_to_usize(num: u64) -> usize {
 pub const fn wrap_usize_to_u32(num: usize) -> Wrapping<u32> {
     Wrapping(((num as u64) & 0xFFFFFFFF) as u32)
 }
+
+/// Overwrites the given buffer with zeros in a way that is not optimized out by the compiler.
+///
+/// Meant for clearing sensitive data before the memory holding it is released.
+pub fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid and aligned reference to a `u8`.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zeroize() {
+        let mut buf = [0xffu8; 17];
+        zeroize(&mut buf[..16]);
+        assert_eq!(buf[..16], [0; 16]);
+        assert_eq!(buf[16], 0xff);
+    }
+}
@@ -10,6 +10,7 @@ use crate::acpi::x86_64::{
     apic_addr, rsdp_addr, setup_arch_dsdt, setup_arch_fadt, setup_interrupt_controllers,
 };
 use crate::device_manager::acpi::ACPIDeviceManager;
+use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::resources::ResourceAllocator;
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
@@ -79,6 +80,7 @@ impl<'a> AcpiTableWriter<'a> {
         &mut self,
         mmio_device_manager: &MMIODeviceManager,
         acpi_device_manager: &ACPIDeviceManager,
+        pio_device_manager: &PortIODeviceManager,
     ) -> Result<u64, AcpiError> {
         let mut dsdt_data = Vec::new();
 
@@ -89,7 +91,7 @@ impl<'a> AcpiTableWriter<'a> {
         acpi_device_manager.append_aml_bytes(&mut dsdt_data);
 
         // Architecture specific DSDT data
-        setup_arch_dsdt(&mut dsdt_data);
+        setup_arch_dsdt(pio_device_manager, &mut dsdt_data);
 
         let mut dsdt = Dsdt::new(OEM_ID, *b"FCVMDSDT", OEM_REVISION, dsdt_data);
         self.write_acpi_table(&mut dsdt)
@@ -164,6 +166,7 @@ pub(crate) fn create_acpi_tables(
     resource_allocator: &mut ResourceAllocator,
     mmio_device_manager: &MMIODeviceManager,
     acpi_device_manager: &ACPIDeviceManager,
+    pio_device_manager: &PortIODeviceManager,
     vcpus: &[Vcpu],
 ) -> Result<(), AcpiError> {
     let mut writer = AcpiTableWriter {
@@ -171,7 +174,8 @@ pub(crate) fn create_acpi_tables(
         resource_allocator,
     };
 
-    let dsdt_addr = writer.build_dsdt(mmio_device_manager, acpi_device_manager)?;
+    let dsdt_addr =
+        writer.build_dsdt(mmio_device_manager, acpi_device_manager, pio_device_manager)?;
     let fadt_addr = writer.build_fadt(dsdt_addr)?;
     let madt_addr = writer.build_madt(vcpus.len().try_into().unwrap())?;
     let xsdt_addr = writer.build_xsdt(fadt_addr, madt_addr)?;
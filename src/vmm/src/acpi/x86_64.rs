@@ -41,8 +41,8 @@ pub(crate) fn setup_arch_fadt(fadt: &mut Fadt) {
 }
 
 #[inline(always)]
-pub(crate) fn setup_arch_dsdt(dsdt_data: &mut Vec<u8>) {
-    PortIODeviceManager::append_aml_bytes(dsdt_data)
+pub(crate) fn setup_arch_dsdt(pio_device_manager: &PortIODeviceManager, dsdt_data: &mut Vec<u8>) {
+    pio_device_manager.append_aml_bytes(dsdt_data)
 }
 
 pub(crate) const fn apic_addr() -> u32 {
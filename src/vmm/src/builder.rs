@@ -41,13 +41,19 @@ use crate::device_manager::acpi::ACPIDeviceManager;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
-use crate::device_manager::persist::MMIODevManagerConstructorArgs;
+use crate::device_manager::persist::{
+    find_gsi_conflict, MMIODevManagerConstructorArgs, RestoreSource,
+};
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::{
     ACPIDeviceManagerConstructorArgs, ACPIDeviceManagerRestoreError,
 };
 use crate::device_manager::resources::ResourceAllocator;
 #[cfg(target_arch = "x86_64")]
+use crate::devices::acpi::hosttime::{HostTime, HostTimeError};
+#[cfg(target_arch = "x86_64")]
+use crate::devices::acpi::vmclock::{VmClock, VmClockError};
+#[cfg(target_arch = "x86_64")]
 use crate::devices::acpi::vmgenid::{VmGenId, VmGenIdError};
 use crate::devices::legacy::serial::SerialOut;
 #[cfg(target_arch = "aarch64")]
@@ -56,6 +62,8 @@ use crate::devices::legacy::{EventFdTrigger, SerialEventsWrapper, SerialWrapper}
 use crate::devices::virtio::balloon::Balloon;
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::fs::VhostUserFs;
+use crate::devices::virtio::input::Input;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::Net;
 use crate::devices::virtio::rng::Entropy;
@@ -95,6 +103,14 @@ pub enum StartMicrovmError {
     /// Error creating VMGenID device: {0}
     #[cfg(target_arch = "x86_64")]
     CreateVMGenID(VmGenIdError),
+    /// Error creating VMClock device: {0}
+    #[cfg(target_arch = "x86_64")]
+    CreateVmClock(VmClockError),
+    /// Error creating host time device: {0}
+    #[cfg(target_arch = "x86_64")]
+    CreateHostTime(HostTimeError),
+    /// Device self-test failed: {0}
+    DeviceSelfTest(device_manager::mmio::MmioError),
     /// Invalid Memory Configuration: {0}
     GuestMemory(crate::vstate::memory::MemoryError),
     /// Cannot load initrd due to an invalid memory configuration.
@@ -103,6 +119,8 @@ pub enum StartMicrovmError {
     InitrdRead(io::Error),
     /// Internal error while starting microVM: {0}
     Internal(VmmError),
+    /// Cannot redirect the serial output: {0}
+    SerialOutput(VmmError),
     /// Failed to get CPU template: {0}
     GetCpuTemplate(#[from] GetCpuTemplateError),
     /// Invalid kernel command line: {0}
@@ -201,7 +219,7 @@ fn create_vmm_and_vcpus(
         // create pio dev manager with legacy devices
         let pio_device_manager = {
             // TODO Remove these unwraps.
-            let mut pio_dev_mgr = PortIODeviceManager::new(serial_device, reset_evt).unwrap();
+            let mut pio_dev_mgr = PortIODeviceManager::new(serial_device, reset_evt, None).unwrap();
             pio_dev_mgr.register_devices(vm.fd()).unwrap();
             pio_dev_mgr
         };
@@ -266,11 +284,12 @@ pub fn build_microvm_for_boot(
         .block
         .devices
         .iter()
-        .any(|b| b.lock().expect("Poisoned lock").is_vhost_user());
+        .any(|b| b.lock().expect("Poisoned lock").is_vhost_user())
+        || !vm_resources.fs.devices.is_empty();
 
     // Page faults are more expensive for shared memory mapping, including  memfd.
     // For this reason, we only back guest memory with a memfd
-    // if a vhost-user-blk device is configured in the VM, otherwise we fall back to
+    // if a vhost-user device is configured in the VM, otherwise we fall back to
     // an anonymous private memory.
     //
     // The vhost-user-blk branch is not currently covered by integration tests in Rust,
@@ -316,7 +335,7 @@ pub fn build_microvm_for_boot(
     // to maintain the same MMIO address referenced in the documentation
     // and tests.
     if vm_resources.boot_timer {
-        attach_boot_timer_device(&mut vmm, request_ts)?;
+        attach_boot_timer_device(&mut vmm, &mut boot_cmdline, request_ts)?;
     }
 
     if let Some(balloon) = vm_resources.balloon.get() {
@@ -344,11 +363,49 @@ pub fn build_microvm_for_boot(
         attach_entropy_device(&mut vmm, &mut boot_cmdline, entropy, event_manager)?;
     }
 
+    attach_fs_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.fs.devices.iter(),
+        event_manager,
+    )?;
+
+    attach_input_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.input.devices.iter(),
+        event_manager,
+    )?;
+
+    if vm_resources.device_self_test {
+        vmm.mmio_device_manager
+            .self_test()
+            .map_err(DeviceSelfTest)?;
+    }
+
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
+    if let Some(path) = vm_resources.serial.output_socket.as_deref() {
+        vmm.set_serial_output_socket(path).map_err(SerialOutput)?;
+    }
+    if let Some(path) = vm_resources.serial.output_path.as_deref() {
+        vmm.set_serial_output(Some(path)).map_err(SerialOutput)?;
+    }
+    if let Some(signature) = vm_resources.serial.panic_signature.as_deref() {
+        vmm.watch_serial_for_panics(signature);
+    }
+
     #[cfg(target_arch = "x86_64")]
     attach_vmgenid_device(&mut vmm)?;
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources.clock.vmclock {
+        attach_vmclock_device(&mut vmm)?;
+    }
+    #[cfg(target_arch = "x86_64")]
+    if vm_resources.clock.host_time {
+        attach_hosttime_device(&mut vmm, event_manager)?;
+    }
 
     configure_system_for_boot(
         &mut vmm,
@@ -436,8 +493,21 @@ pub enum BuildMicrovmFromSnapshotError {
     VmUpdateConfig(#[from] VmConfigError),
     /// Failed to restore MMIO device: {0}
     RestoreMmioDevice(#[from] MicrovmStateError),
+    /// GSI {0} is used by both {1} and {2}. The guest already bound it, so it cannot be reassigned.
+    GsiConflict(u32, String, String),
     /// Failed to emulate MMIO serial: {0}
     EmulateSerialInit(#[from] crate::EmulateSerialInitError),
+    /// Failed to restore the RX trigger level of the serial console: {0}
+    SerialRxTriggerLevel(crate::VmmError),
+    /// Failed to restore the output buffering of the serial console: {0}
+    SerialOutputBuffering(crate::VmmError),
+    /// Failed to restore the serial console state: {0}
+    SerialState(crate::devices::legacy::SerialPersistError),
+    /// Failed to redirect the serial output: {0}
+    SerialOutput(crate::VmmError),
+    /// Failed to restore the i8042 device state: {0}
+    #[cfg(target_arch = "x86_64")]
+    I8042State(crate::devices::legacy::I8042DeviceError),
     /// Failed to start vCPUs as no vCPU seccomp filter found.
     MissingVcpuSeccompFilters,
     /// Failed to start vCPUs: {0}
@@ -453,6 +523,9 @@ pub enum BuildMicrovmFromSnapshotError {
     ACPIDeviManager(#[from] ACPIDeviceManagerRestoreError),
     /// VMGenID update failed: {0}
     VMGenIDUpdate(std::io::Error),
+    /// VMClock update failed: {0}
+    #[cfg(target_arch = "x86_64")]
+    VmClockUpdate(VmClockError),
 }
 
 /// Builds and starts a microVM based on the provided MicrovmState.
@@ -517,6 +590,18 @@ pub fn build_microvm_from_snapshot(
     // Restore the boot source config paths.
     vm_resources.set_boot_source_config(microvm_state.vm_info.boot_source);
 
+    // The guest bound the interrupts of its devices before the snapshot was taken, so a GSI
+    // shared by two devices cannot be moved to a free one.
+    #[allow(unused_mut)]
+    let mut gsi_owners = microvm_state.device_states.gsi_owners();
+    #[cfg(target_arch = "x86_64")]
+    gsi_owners.extend(microvm_state.acpi_dev_state.gsi_owners());
+    if let Some((gsi, first, second)) = find_gsi_conflict(gsi_owners) {
+        return Err(BuildMicrovmFromSnapshotError::GsiConflict(
+            gsi, first, second,
+        ));
+    }
+
     // Restore devices states.
     let mmio_ctor_args = MMIODevManagerConstructorArgs {
         mem: &guest_memory,
@@ -525,15 +610,46 @@ pub fn build_microvm_from_snapshot(
         resource_allocator: &mut vmm.resource_allocator,
         vm_resources,
         instance_id: &instance_info.id,
+        restore_source: if vmm.uffd.is_some() {
+            RestoreSource::Uffd
+        } else {
+            RestoreSource::File
+        },
     };
 
     vmm.mmio_device_manager =
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
             .map_err(MicrovmStateError::RestoreDevices)?;
-    vmm.emulate_serial_init()?;
+    vmm.set_serial_rx_trigger_level(microvm_state.device_states.serial_rx_trigger_level)
+        .map_err(BuildMicrovmFromSnapshotError::SerialRxTriggerLevel)?;
+    vmm.set_serial_output_buffering(microvm_state.device_states.serial_output_buffering)
+        .map_err(BuildMicrovmFromSnapshotError::SerialOutputBuffering)?;
+    match microvm_state.device_states.serial_state.as_ref() {
+        Some(serial_state) => vmm
+            .restore_serial_state(serial_state)
+            .map_err(BuildMicrovmFromSnapshotError::SerialState)?,
+        // Snapshots taken before the serial device state was saved.
+        None => vmm.emulate_serial_init()?,
+    }
+    if let Some(path) = vm_resources.serial.output_socket.as_deref() {
+        vmm.set_serial_output_socket(path)
+            .map_err(BuildMicrovmFromSnapshotError::SerialOutput)?;
+    }
+    if let Some(path) = vm_resources.serial.output_path.as_deref() {
+        vmm.set_serial_output(Some(path))
+            .map_err(BuildMicrovmFromSnapshotError::SerialOutput)?;
+    }
+    if let Some(signature) = vm_resources.serial.panic_signature.as_deref() {
+        vmm.watch_serial_for_panics(signature);
+    }
 
     #[cfg(target_arch = "x86_64")]
     {
+        if let Some(i8042_state) = microvm_state.device_states.i8042_state.as_ref() {
+            vmm.restore_i8042_state(i8042_state)
+                .map_err(BuildMicrovmFromSnapshotError::I8042State)?;
+        }
+
         let acpi_ctor_args = ACPIDeviceManagerConstructorArgs {
             mem: &guest_memory,
             resource_allocator: &mut vmm.resource_allocator,
@@ -549,6 +665,14 @@ pub fn build_microvm_from_snapshot(
         vmm.acpi_device_manager
             .notify_vmgenid()
             .map_err(BuildMicrovmFromSnapshotError::VMGenIDUpdate)?;
+        // Likewise, tell the guest that its clock was disrupted.
+        vmm.acpi_device_manager
+            .post_restore_vmclock(&vmm.guest_memory)
+            .map_err(BuildMicrovmFromSnapshotError::VmClockUpdate)?;
+        // The host time page was refreshed on restore, keep refreshing it.
+        if let Some(hosttime) = &vmm.acpi_device_manager.hosttime {
+            event_manager.add_subscriber(hosttime.clone());
+        }
     }
 
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
@@ -693,16 +817,16 @@ pub fn setup_serial_device(
     let interrupt_evt = EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).map_err(VmmError::EventFd)?);
     let kick_stdin_read_evt =
         EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).map_err(VmmError::EventFd)?);
-    let serial = Arc::new(Mutex::new(BusDevice::Serial(SerialWrapper {
-        serial: Serial::with_events(
+    let serial = Arc::new(Mutex::new(BusDevice::Serial(SerialWrapper::new(
+        Serial::with_events(
             interrupt_evt,
             SerialEventsWrapper {
                 buffer_ready_event_fd: Some(kick_stdin_read_evt),
             },
             SerialOut::Stdout(out),
         ),
-        input: Some(input),
-    })));
+        Some(input),
+    ))));
     event_manager.add_subscriber(serial.clone());
     Ok(serial)
 }
@@ -844,6 +968,7 @@ pub fn configure_system_for_boot(
             &mut vmm.resource_allocator,
             &vmm.mmio_device_manager,
             &vmm.acpi_device_manager,
+            &vmm.pio_device_manager,
             vcpus,
         )?;
     }
@@ -896,6 +1021,7 @@ fn attach_virtio_device<T: 'static + VirtioDevice + MutEventSubscriber + Debug>(
 
 pub(crate) fn attach_boot_timer_device(
     vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
     request_ts: TimestampUs,
 ) -> Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
@@ -905,6 +1031,9 @@ pub(crate) fn attach_boot_timer_device(
     vmm.mmio_device_manager
         .register_mmio_boot_timer(&mut vmm.resource_allocator, boot_timer)
         .map_err(RegisterMmioDevice)?;
+    vmm.mmio_device_manager
+        .add_boot_timer_to_cmdline(cmdline)
+        .map_err(RegisterMmioDevice)?;
 
     Ok(())
 }
@@ -921,6 +1050,31 @@ fn attach_vmgenid_device(vmm: &mut Vmm) -> Result<(), StartMicrovmError> {
     Ok(())
 }
 
+#[cfg(target_arch = "x86_64")]
+fn attach_vmclock_device(vmm: &mut Vmm) -> Result<(), StartMicrovmError> {
+    let vmclock = VmClock::new(&vmm.guest_memory, &mut vmm.resource_allocator)
+        .map_err(StartMicrovmError::CreateVmClock)?;
+
+    vmm.acpi_device_manager.attach_vmclock(vmclock);
+
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn attach_hosttime_device(
+    vmm: &mut Vmm,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    let hosttime = HostTime::new(&vmm.guest_memory, &mut vmm.resource_allocator)
+        .map_err(StartMicrovmError::CreateHostTime)?;
+    let hosttime = Arc::new(Mutex::new(hosttime));
+
+    event_manager.add_subscriber(hosttime.clone());
+    vmm.acpi_device_manager.attach_hosttime(hosttime);
+
+    Ok(())
+}
+
 fn attach_entropy_device(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -993,6 +1147,34 @@ fn attach_net_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Net>>> + Debug>(
     Ok(())
 }
 
+fn attach_fs_devices<'a, I: Iterator<Item = &'a Arc<Mutex<VhostUserFs>>> + Debug>(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    fs_devices: I,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    for fs_device in fs_devices {
+        let id = fs_device.lock().expect("Poisoned lock").id.clone();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(event_manager, vmm, id, fs_device.clone(), cmdline, true)?;
+    }
+    Ok(())
+}
+
+fn attach_input_devices<'a, I: Iterator<Item = &'a Arc<Mutex<Input>>> + Debug>(
+    vmm: &mut Vmm,
+    cmdline: &mut LoaderKernelCmdline,
+    input_devices: I,
+    event_manager: &mut EventManager,
+) -> Result<(), StartMicrovmError> {
+    for input_device in input_devices {
+        let id = input_device.lock().expect("Poisoned lock").id().to_string();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(event_manager, vmm, id, input_device.clone(), cmdline, false)?;
+    }
+    Ok(())
+}
+
 fn attach_unixsock_vsock_device(
     vmm: &mut Vmm,
     cmdline: &mut LoaderKernelCmdline,
@@ -1039,20 +1221,23 @@ pub mod tests {
     use super::*;
     use crate::arch::DeviceType;
     use crate::device_manager::resources::ResourceAllocator;
-    use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::block::{BackingInfo, CacheType};
+    use crate::devices::virtio::input::InputKind;
     use crate::devices::virtio::rng::device::ENTROPY_DEV_ID;
     use crate::devices::virtio::vsock::{TYPE_VSOCK, VSOCK_DEV_ID};
-    use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_RNG};
+    use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_INPUT, TYPE_RNG};
     use crate::mmds::data_store::{Mmds, MmdsVersion};
     use crate::mmds::ns::MmdsNetworkStack;
     use crate::utilities::test_utils::{arch_mem, single_region_mem, single_region_mem_at};
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
-    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
+    use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, FileEngineType};
     use crate::vmm_config::entropy::{EntropyDeviceBuilder, EntropyDeviceConfig};
+    use crate::vmm_config::input::{InputBuilder, InputDeviceConfig};
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
+    use crate::vstate::memory::{Address, GuestRegionMmap, MmapRegionBuilder};
 
     #[derive(Debug)]
     pub(crate) struct CustomBlockConfig {
@@ -1122,17 +1307,18 @@ pub mod tests {
         let acpi_device_manager = ACPIDeviceManager::new();
         #[cfg(target_arch = "x86_64")]
         let pio_device_manager = PortIODeviceManager::new(
-            Arc::new(Mutex::new(BusDevice::Serial(SerialWrapper {
-                serial: Serial::with_events(
+            Arc::new(Mutex::new(BusDevice::Serial(SerialWrapper::new(
+                Serial::with_events(
                     EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
                     SerialEventsWrapper {
                         buffer_ready_event_fd: None,
                     },
                     SerialOut::Sink(std::io::sink()),
                 ),
-                input: None,
-            }))),
+                None,
+            )))),
             EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            None,
         )
         .unwrap();
 
@@ -1193,6 +1379,15 @@ pub mod tests {
                 ),
                 rate_limiter: None,
                 file_engine_type: None,
+                interrupt_moderation: None,
+                io_retry: None,
+                geometry: None,
+                discard: None,
+                max_chain_len: None,
+                request_record_path: None,
+                restore_notification_window_ms: None,
+                write_batch: None,
+                fd_on_host: None,
 
                 socket: None,
             };
@@ -1210,6 +1405,48 @@ pub mod tests {
         block_files
     }
 
+    pub(crate) fn insert_vhost_user_block_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        drive_id: &str,
+        socket: &str,
+    ) {
+        let mut block_dev_configs = BlockBuilder::new();
+        block_dev_configs
+            .insert(BlockDeviceConfig {
+                drive_id: drive_id.to_string(),
+                partuuid: None,
+                is_root_device: false,
+                cache_type: CacheType::Unsafe,
+
+                is_read_only: None,
+                path_on_host: None,
+                rate_limiter: None,
+                file_engine_type: None,
+                interrupt_moderation: None,
+                io_retry: None,
+                geometry: None,
+                discard: None,
+                max_chain_len: None,
+                request_record_path: None,
+                restore_notification_window_ms: None,
+                write_batch: None,
+                fd_on_host: None,
+
+                socket: Some(socket.to_string()),
+            })
+            .unwrap();
+
+        attach_block_devices(
+            vmm,
+            cmdline,
+            block_dev_configs.devices.iter(),
+            event_manager,
+        )
+        .unwrap();
+    }
+
     pub(crate) fn insert_net_device(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -1217,7 +1454,9 @@ pub mod tests {
         net_config: NetworkInterfaceConfig,
     ) {
         let mut net_builder = NetBuilder::new();
-        net_builder.build(net_config).unwrap();
+        // Skip the config validation, the tap is created on the fly.
+        let net = NetBuilder::create_net(net_config).unwrap();
+        net_builder.add_device(Arc::new(Mutex::new(net)));
 
         let res = attach_net_devices(vmm, cmdline, net_builder.iter(), event_manager);
         res.unwrap();
@@ -1231,7 +1470,9 @@ pub mod tests {
         mmds_version: MmdsVersion,
     ) {
         let mut net_builder = NetBuilder::new();
-        net_builder.build(net_config).unwrap();
+        // Skip the config validation, the tap is created on the fly.
+        let net = NetBuilder::create_net(net_config).unwrap();
+        net_builder.add_device(Arc::new(Mutex::new(net)));
         let net = net_builder.iter().next().unwrap();
         let mut mmds = Mmds::default();
         mmds.set_version(mmds_version).unwrap();
@@ -1278,12 +1519,42 @@ pub mod tests {
             .is_some());
     }
 
+    pub(crate) fn insert_input_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        input_config: InputDeviceConfig,
+    ) {
+        let input_id = input_config.input_id.clone();
+        let mut builder = InputBuilder::new();
+        builder.insert(input_config).unwrap();
+
+        attach_input_devices(vmm, cmdline, builder.devices.iter(), event_manager).unwrap();
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_INPUT), &input_id)
+            .is_some());
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub(crate) fn insert_vmgenid_device(vmm: &mut Vmm) {
         attach_vmgenid_device(vmm).unwrap();
         assert!(vmm.acpi_device_manager.vmgenid.is_some());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) fn insert_vmclock_device(vmm: &mut Vmm) {
+        attach_vmclock_device(vmm).unwrap();
+        assert!(vmm.acpi_device_manager.vmclock.is_some());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) fn insert_hosttime_device(vmm: &mut Vmm, event_manager: &mut EventManager) {
+        attach_hosttime_device(vmm, event_manager).unwrap();
+        assert!(vmm.acpi_device_manager.hosttime.is_some());
+    }
+
     pub(crate) fn insert_balloon_device(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -1391,6 +1662,8 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1584,17 +1857,77 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_block_backing_info() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let block_configs = vec![
+            CustomBlockConfig::new(String::from("root"), true, None, true, CacheType::Unsafe),
+            CustomBlockConfig::new(
+                String::from("data"),
+                false,
+                None,
+                false,
+                CacheType::Writeback,
+            ),
+        ];
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let block_files =
+            insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+
+        assert_eq!(
+            vmm.block_backing_info("root").unwrap(),
+            BackingInfo {
+                path: block_files[0].as_path().to_str().unwrap().to_string(),
+                read_only: true,
+                direct: false,
+                cache_type: CacheType::Unsafe,
+                file_engine_type: Some(FileEngineType::Sync),
+            }
+        );
+        assert_eq!(
+            vmm.block_backing_info("data").unwrap(),
+            BackingInfo {
+                path: block_files[1].as_path().to_str().unwrap().to_string(),
+                read_only: false,
+                direct: false,
+                cache_type: CacheType::Writeback,
+                file_engine_type: Some(FileEngineType::Sync),
+            }
+        );
+        assert!(matches!(
+            vmm.block_backing_info("missing"),
+            Err(VmmError::DeviceManager(
+                device_manager::mmio::MmioError::DeviceNotFound
+            ))
+        ));
+    }
+
     #[test]
     fn test_attach_boot_timer_device() {
         let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
         let request_ts = TimestampUs::default();
 
-        let res = attach_boot_timer_device(&mut vmm, request_ts);
+        let res = attach_boot_timer_device(&mut vmm, &mut cmdline, request_ts);
         res.unwrap();
         assert!(vmm
             .mmio_device_manager
             .get_device(DeviceType::BootTimer, &DeviceType::BootTimer.to_string())
             .is_some());
+
+        // The boot timer is the first MMIO device on all architectures, and the guest finds its
+        // address in the cmdline.
+        let device_info = &vmm.mmio_device_manager.get_device_info()
+            [&(DeviceType::BootTimer, DeviceType::BootTimer.to_string())];
+        assert_eq!(device_info.addr, crate::arch::MMIO_MEM_START);
+        assert!(cmdline_contains(
+            &cmdline,
+            &format!(
+                "firecracker.boot_timer=0x{:08x}",
+                crate::arch::MMIO_MEM_START
+            )
+        ));
     }
 
     #[test]
@@ -1618,6 +1951,39 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn test_resize_guest_memory_updates_balloon() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let balloon_config = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+        };
+        let mut cmdline = default_kernel_cmdline();
+        insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_config);
+
+        // Append a 64 MiB region right after the existing guest memory.
+        let start = vmm.guest_memory().last_addr().unchecked_add(1);
+        let region = MmapRegionBuilder::new_with_bitmap(64 << 20, None)
+            .build()
+            .unwrap();
+        let guest_memory = vmm
+            .guest_memory()
+            .insert_region(Arc::new(GuestRegionMmap::new(region, start).unwrap()))
+            .unwrap();
+        vmm.resize_guest_memory(guest_memory, false).unwrap();
+        assert_eq!(crate::mem_size_mib(vmm.guest_memory()), 192);
+
+        vmm.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BALLOON, BALLOON_DEV_ID, |balloon: &mut Balloon| {
+                assert_eq!(balloon.guest_memory_mib(), 192);
+                Ok(())
+            })
+            .unwrap();
+    }
+
     #[test]
     fn test_attach_entropy_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -1635,6 +2001,26 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn test_attach_input_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let input_config = InputDeviceConfig {
+            input_id: "input0".to_string(),
+            kind: InputKind::Keyboard,
+        };
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_input_device(&mut vmm, &mut cmdline, &mut event_manager, input_config);
+        // Check if the input device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline_contains(
+            &cmdline,
+            "virtio_mmio.device=4K@0xd0000000:5"
+        ));
+    }
+
     #[test]
     fn test_attach_vsock_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
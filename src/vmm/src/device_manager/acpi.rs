@@ -1,32 +1,50 @@
 // Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::{Arc, Mutex};
+
 use acpi_tables::{aml, Aml};
-use kvm_ioctls::VmFd;
+use kvm_ioctls::{IoEventAddress, NoDatamatch, VmFd};
 
+use crate::devices::acpi::hosttime::HostTime;
+use crate::devices::acpi::vmclock::{VmClock, VmClockError};
 use crate::devices::acpi::vmgenid::VmGenId;
+use crate::vstate::memory::GuestMemoryMmap;
 
 #[derive(Debug)]
 pub struct ACPIDeviceManager {
     /// VMGenID device
     pub vmgenid: Option<VmGenId>,
+    /// VMClock device
+    pub vmclock: Option<VmClock>,
+    /// Host time device
+    pub hosttime: Option<Arc<Mutex<HostTime>>>,
 }
 
 impl ACPIDeviceManager {
     /// Create a new ACPIDeviceManager object
     pub fn new() -> Self {
-        Self { vmgenid: None }
+        Self {
+            vmgenid: None,
+            vmclock: None,
+            hosttime: None,
+        }
     }
 
     /// Attach a new VMGenID device to the microVM
     ///
-    /// This will register the device's interrupt with KVM
+    /// This will register the device's interrupt and doorbell with KVM
     pub fn attach_vmgenid(
         &mut self,
         vmgenid: VmGenId,
         vm_fd: &VmFd,
     ) -> Result<(), kvm_ioctls::Error> {
         vm_fd.register_irqfd(&vmgenid.interrupt_evt, vmgenid.gsi)?;
+        vm_fd.register_ioevent(
+            &vmgenid.doorbell_evt,
+            &IoEventAddress::Mmio(vmgenid.doorbell_address.0),
+            NoDatamatch,
+        )?;
         self.vmgenid = Some(vmgenid);
         Ok(())
     }
@@ -38,6 +56,33 @@ impl ACPIDeviceManager {
         }
         Ok(())
     }
+
+    /// Returns the current generation ID of the VMGenID device, if there is one.
+    pub fn vmgenid_value(&self) -> Option<u128> {
+        self.vmgenid.as_ref().map(|vmgenid| vmgenid.gen_id)
+    }
+
+    /// Attach a new VMClock device to the microVM
+    pub fn attach_vmclock(&mut self, vmclock: VmClock) {
+        self.vmclock = Some(vmclock);
+    }
+
+    /// Attach a new host time device to the microVM
+    ///
+    /// The device refreshes its page on a timer, so it also needs to be added to the event
+    /// manager.
+    pub fn attach_hosttime(&mut self, hosttime: Arc<Mutex<HostTime>>) {
+        self.hosttime = Some(hosttime);
+    }
+
+    /// If it exists, tell the guest through the VMClock device that its clock was disrupted by
+    /// resuming from a snapshot.
+    pub fn post_restore_vmclock(&self, mem: &GuestMemoryMmap) -> Result<(), VmClockError> {
+        if let Some(vmclock) = &self.vmclock {
+            vmclock.post_restore(mem)?;
+        }
+        Ok(())
+    }
 }
 
 impl Aml for ACPIDeviceManager {
@@ -81,5 +126,11 @@ impl Aml for ACPIDeviceManager {
             // AML for VMGenID itself.
             vmgenid.append_aml_bytes(v);
         });
+        if let Some(vmclock) = &self.vmclock {
+            vmclock.append_aml_bytes(v);
+        }
+        if let Some(hosttime) = &self.hosttime {
+            hosttime.lock().expect("Poisoned lock").append_aml_bytes(v);
+        }
     }
 }
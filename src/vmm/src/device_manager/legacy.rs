@@ -17,7 +17,9 @@ use vm_superio::Serial;
 
 use crate::devices::bus::BusDevice;
 use crate::devices::legacy::serial::SerialOut;
-use crate::devices::legacy::{EventFdTrigger, SerialDevice, SerialEventsWrapper};
+use crate::devices::legacy::{
+    EventFdTrigger, I8042DeviceError, SerialDevice, SerialEventsWrapper, SerialRxTrigger,
+};
 
 /// Errors corresponding to the `PortIODeviceManager`.
 #[derive(Debug, derive_more::From, thiserror::Error, displaydoc::Display)]
@@ -26,6 +28,9 @@ pub enum LegacyDeviceError {
     BusError(crate::devices::BusError),
     /// Failed to create EventFd: {0}
     EventFd(std::io::Error),
+    /// Invalid i8042 keyboard GSI: {0}
+    #[from(ignore)]
+    InvalidKbdGsi(u32),
 }
 
 /// The `PortIODeviceManager` is a wrapper that is used for registering legacy devices
@@ -45,6 +50,8 @@ pub struct PortIODeviceManager {
     pub com_evt_2_4: EventFdTrigger,
     // Keyboard event.
     pub kbd_evt: EventFd,
+    // GSI of the keyboard event.
+    kbd_gsi: u32,
 }
 
 impl PortIODeviceManager {
@@ -71,11 +78,24 @@ impl PortIODeviceManager {
     const I8042_KDB_DATA_REGISTER_SIZE: u64 = 0x5;
 
     /// Create a new DeviceManager handling legacy devices (uart, i8042).
+    ///
+    /// The i8042 keyboard interrupt is routed to `kbd_gsi`, or to the legacy IRQ 1 if `None`.
+    /// The GSI must be covered by the MP table and must not be shared with the serial ports.
+    /// GSIs starting from `IRQ_BASE` are also handed out to MMIO devices, so the caller has to
+    /// make sure a GSI in that range is not used by any of them.
     pub fn new(
         serial: Arc<Mutex<BusDevice>>,
         i8042_reset_evfd: EventFd,
+        kbd_gsi: Option<u32>,
     ) -> Result<Self, LegacyDeviceError> {
         debug_assert!(matches!(*serial.lock().unwrap(), BusDevice::Serial(_)));
+        let kbd_gsi = kbd_gsi.unwrap_or(Self::KBD_EVT_GSI);
+        if kbd_gsi > crate::arch::IRQ_MAX
+            || kbd_gsi == Self::COM_EVT_1_3_GSI
+            || kbd_gsi == Self::COM_EVT_2_4_GSI
+        {
+            return Err(LegacyDeviceError::InvalidKbdGsi(kbd_gsi));
+        }
         let io_bus = crate::devices::Bus::new();
         let com_evt_1_3 = serial
             .lock()
@@ -99,9 +119,25 @@ impl PortIODeviceManager {
             com_evt_1_3,
             com_evt_2_4,
             kbd_evt,
+            kbd_gsi,
         })
     }
 
+    /// GSI of the i8042 keyboard interrupt.
+    pub fn kbd_gsi(&self) -> u32 {
+        self.kbd_gsi
+    }
+
+    /// Injects the CTRL+ALT+DEL key sequence in the i8042 device, asking the guest to reboot.
+    pub fn send_ctrl_alt_del(&self) -> Result<(), I8042DeviceError> {
+        self.i8042
+            .lock()
+            .expect("Poisoned lock")
+            .i8042_device_mut()
+            .unwrap()
+            .trigger_ctrl_alt_del()
+    }
+
     /// Register supported legacy devices.
     pub fn register_devices(&mut self, vm_fd: &VmFd) -> Result<(), LegacyDeviceError> {
         let serial_2_4 = Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
@@ -113,6 +149,7 @@ impl PortIODeviceManager {
                 SerialOut::Sink(std::io::sink()),
             ),
             input: None,
+            rx_trigger: SerialRxTrigger::default(),
         })));
         let serial_1_3 = Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
             serial: Serial::with_events(
@@ -123,6 +160,7 @@ impl PortIODeviceManager {
                 SerialOut::Sink(std::io::sink()),
             ),
             input: None,
+            rx_trigger: SerialRxTrigger::default(),
         })));
         self.io_bus.insert(
             self.stdio_serial.clone(),
@@ -161,7 +199,7 @@ impl PortIODeviceManager {
                 LegacyDeviceError::EventFd(std::io::Error::from_raw_os_error(e.errno()))
             })?;
         vm_fd
-            .register_irqfd(&self.kbd_evt, Self::KBD_EVT_GSI)
+            .register_irqfd(&self.kbd_evt, self.kbd_gsi)
             .map_err(|e| {
                 LegacyDeviceError::EventFd(std::io::Error::from_raw_os_error(e.errno()))
             })?;
@@ -169,7 +207,7 @@ impl PortIODeviceManager {
         Ok(())
     }
 
-    pub(crate) fn append_aml_bytes(bytes: &mut Vec<u8>) {
+    pub(crate) fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
         // Set up COM devices
         let gsi = [
             Self::COM_EVT_1_3_GSI,
@@ -226,7 +264,7 @@ impl PortIODeviceManager {
                         ),
                         // Fake a command port so Linux stops complaining
                         &aml::Io::new(0x0064, 0x0064, 1u8, 1u8),
-                        &aml::Interrupt::new(true, true, false, false, Self::KBD_EVT_GSI),
+                        &aml::Interrupt::new(true, true, false, false, self.kbd_gsi),
                     ]),
                 ),
             ],
@@ -241,6 +279,20 @@ mod tests {
     use crate::utilities::test_utils::single_region_mem;
     use crate::Vm;
 
+    fn serial_device() -> Arc<Mutex<BusDevice>> {
+        Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None,
+            rx_trigger: SerialRxTrigger::default(),
+        })))
+    }
+
     #[test]
     fn test_register_legacy_devices() {
         let guest_mem = single_region_mem(0x1000);
@@ -248,19 +300,86 @@ mod tests {
         vm.memory_init(&guest_mem, false).unwrap();
         crate::builder::setup_interrupt_controller(&mut vm).unwrap();
         let mut ldm = PortIODeviceManager::new(
-            Arc::new(Mutex::new(BusDevice::Serial(SerialDevice {
-                serial: Serial::with_events(
-                    EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
-                    SerialEventsWrapper {
-                        buffer_ready_event_fd: None,
-                    },
-                    SerialOut::Sink(std::io::sink()),
-                ),
-                input: None,
-            }))),
+            serial_device(),
             EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            None,
         )
         .unwrap();
+        assert_eq!(ldm.kbd_gsi(), PortIODeviceManager::KBD_EVT_GSI);
         ldm.register_devices(vm.fd()).unwrap();
     }
+
+    #[test]
+    fn test_send_ctrl_alt_del() {
+        let ldm = PortIODeviceManager::new(
+            serial_device(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            None,
+        )
+        .unwrap();
+        ldm.send_ctrl_alt_del().unwrap();
+        assert!(ldm.kbd_evt.read().unwrap() > 0);
+
+        // The guest reads the scan codes of CTRL, ALT and the extended DEL key.
+        let mut i8042 = ldm.i8042.lock().unwrap();
+        let i8042 = i8042.i8042_device_mut().unwrap();
+        let mut data = [0u8];
+        for byte in [0x14, 0x11, 0xe0, 0x71] {
+            i8042.bus_read(0, &mut data);
+            assert_eq!(data[0], byte);
+        }
+        i8042.bus_read(0, &mut data);
+        assert_eq!(data[0], 0);
+    }
+
+    #[test]
+    fn test_kbd_gsi() {
+        // GSIs outside the MP table or shared with the serial ports are rejected.
+        for gsi in [
+            PortIODeviceManager::COM_EVT_1_3_GSI,
+            PortIODeviceManager::COM_EVT_2_4_GSI,
+            crate::arch::IRQ_MAX + 1,
+        ] {
+            let err = PortIODeviceManager::new(
+                serial_device(),
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+                Some(gsi),
+            )
+            .unwrap_err();
+            assert!(matches!(err, LegacyDeviceError::InvalidKbdGsi(g) if g == gsi));
+        }
+
+        let guest_mem = single_region_mem(0x1000);
+        let mut vm = Vm::new(vec![]).unwrap();
+        vm.memory_init(&guest_mem, false).unwrap();
+        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
+        let kbd_gsi = crate::arch::IRQ_MAX;
+        let mut ldm = PortIODeviceManager::new(
+            serial_device(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            Some(kbd_gsi),
+        )
+        .unwrap();
+        assert_eq!(ldm.kbd_gsi(), kbd_gsi);
+        ldm.register_devices(vm.fd()).unwrap();
+
+        // A key press is signaled on the keyboard eventfd registered at the custom GSI.
+        ldm.send_ctrl_alt_del().unwrap();
+        assert!(ldm.kbd_evt.read().unwrap() > 0);
+
+        // The ACPI description of the keyboard uses the same GSI.
+        let mut expected = Vec::new();
+        aml::Interrupt::new(true, true, false, false, kbd_gsi).append_aml_bytes(&mut expected);
+        let mut dsdt_data = Vec::new();
+        ldm.append_aml_bytes(&mut dsdt_data);
+        assert!(dsdt_data
+            .windows(expected.len())
+            .any(|window| window == expected.as_slice()));
+        let mut default_irq = Vec::new();
+        aml::Interrupt::new(true, true, false, false, PortIODeviceManager::KBD_EVT_GSI)
+            .append_aml_bytes(&mut default_irq);
+        assert!(!dsdt_data
+            .windows(default_irq.len())
+            .any(|window| window == default_irq.as_slice()));
+    }
 }
@@ -8,6 +8,7 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[cfg(target_arch = "x86_64")]
 use acpi_tables::{aml, Aml};
@@ -17,9 +18,12 @@ use linux_loader::cmdline as kernel_cmdline;
 use log::debug;
 use log::info;
 use serde::{Deserialize, Serialize};
+use utils::time::TimestampUs;
 use vm_allocator::AllocPolicy;
 
+use super::persist::{DevicePersistTiming, RestoreSource, SkippedDevice};
 use super::resources::ResourceAllocator;
+use crate::arch;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::DeviceInfoForFDT;
 use crate::arch::DeviceType;
@@ -27,15 +31,20 @@ use crate::arch::DeviceType::Virtio;
 #[cfg(target_arch = "aarch64")]
 use crate::devices::legacy::RTCDevice;
 use crate::devices::pseudo::BootTimer;
-use crate::devices::virtio::balloon::Balloon;
+use crate::devices::virtio::balloon::{Balloon, MemoryStats, BALLOON_DEV_ID};
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::input::{Input, InputEvent};
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::Net;
 use crate::devices::virtio::rng::Entropy;
 use crate::devices::virtio::vsock::TYPE_VSOCK;
-use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG};
+use crate::devices::virtio::{
+    QuiesceError, SelfTestError, TYPE_BALLOON, TYPE_BLOCK, TYPE_INPUT, TYPE_NET, TYPE_RNG,
+};
 use crate::devices::BusDevice;
+use crate::rate_limiter::RateLimiterBudgets;
+use crate::resources::ResourcesError;
 #[cfg(target_arch = "x86_64")]
 use crate::vstate::memory::GuestAddress;
 
@@ -50,6 +59,12 @@ pub enum MmioError {
     Cmdline(linux_loader::cmdline::Error),
     /// Failed to find the device on the bus.
     DeviceNotFound,
+    /// Device {0} cannot be disabled.
+    DisableNotSupported(String),
+    /// Device id {0} is already in use.
+    DuplicateDeviceId(String),
+    /// Device {0} cannot limit its host memory.
+    HostMemoryLimitNotSupported(String),
     /// Invalid device type found on the MMIO bus.
     InvalidDeviceType,
     /// {0}
@@ -60,6 +75,14 @@ pub enum MmioError {
     RegisterIoEvent(kvm_ioctls::Error),
     /// Failed to register irqfd: {0}
     RegisterIrqFd(kvm_ioctls::Error),
+    /// Failed to quiesce device {0}: {1}
+    Quiesce(String, QuiesceError),
+    /// Device {0} failed its self-test: {1}
+    SelfTest(String, SelfTestError),
+    /// Device {0} cannot be renamed.
+    RenameNotSupported(String),
+    /// Time offset of {0} seconds is out of the range of the guest clock.
+    InvalidTimeOffset(i64),
 }
 
 /// This represents the size of the mmio device specified to the kernel through ACPI and as a
@@ -80,6 +103,157 @@ pub struct MMIODeviceInfo {
     pub irqs: Vec<u32>,
 }
 
+impl MMIODeviceInfo {
+    /// Checks that the MMIO range of device `id` is a properly aligned range of `MMIO_LEN`
+    /// bytes within the MMIO window.
+    pub fn validate(&self, id: &str) -> Result<(), ResourcesError> {
+        if self.len != MMIO_LEN || self.addr % MMIO_LEN != 0 {
+            return Err(ResourcesError::MisalignedMmioAddress(
+                id.to_string(),
+                self.addr,
+            ));
+        }
+        let window_end = arch::MMIO_MEM_START + arch::MMIO_MEM_SIZE;
+        match self.addr.checked_add(self.len) {
+            Some(end) if self.addr >= arch::MMIO_MEM_START && end <= window_end => Ok(()),
+            _ => Err(ResourcesError::MmioRangeOutOfWindow(
+                id.to_string(),
+                self.addr,
+            )),
+        }
+    }
+}
+
+/// Debug view of a virtio queue.
+#[derive(Debug, Serialize)]
+pub struct QueueDebugInfo {
+    /// Maximum size of the queue.
+    pub max_size: u16,
+    /// Size of the queue set by the driver.
+    pub size: u16,
+    /// Whether the queue is ready to be used.
+    pub ready: bool,
+    /// Index of the next avail ring entry to process.
+    pub next_avail: u16,
+    /// Index of the next used ring entry to fill.
+    pub next_used: u16,
+    /// Number of times the driver kicked the device about the queue.
+    pub kicks: u64,
+    /// Number of avail ring entries the device did not process yet, if it is activated.
+    pub pending: Option<u16>,
+}
+
+/// Debug view of a virtio device.
+#[derive(Debug, Serialize)]
+pub struct VirtioDebugInfo {
+    /// Whether the driver activated the device.
+    pub activated: bool,
+    /// Microseconds since the driver activated the device, if it is active.
+    pub uptime_us: Option<u64>,
+    /// Whether the device processes its queues.
+    pub enabled: bool,
+    /// Features offered by the device.
+    pub avail_features: u64,
+    /// Features negotiated with the driver.
+    pub acked_features: u64,
+    /// Approximate number of host fds owned by the device.
+    pub host_fds: usize,
+    /// Approximate host memory, in bytes, allocated by the device on behalf of the guest.
+    pub host_memory: usize,
+    /// Queues of the device.
+    pub queues: Vec<QueueDebugInfo>,
+    /// Most recent error hit by the device.
+    pub last_error: Option<LastErrorDebugInfo>,
+}
+
+/// Debug view of the most recent error hit by a virtio device.
+#[derive(Debug, Serialize)]
+pub struct LastErrorDebugInfo {
+    /// Monotonic time at which the error was recorded, in microseconds.
+    pub timestamp_us: u64,
+    /// Description of the error.
+    pub error: String,
+}
+
+/// Debug view of a device registered on the MMIO bus.
+#[derive(Debug, Serialize)]
+pub struct DeviceDebugInfo {
+    /// Type of the device.
+    pub device_type: DeviceType,
+    /// Identifier of the device.
+    pub id: String,
+    /// Transport of the device.
+    pub transport: &'static str,
+    /// Address range and irqs of the device.
+    #[serde(flatten)]
+    pub info: MMIODeviceInfo,
+    /// Virtio state of the device, for virtio devices.
+    pub virtio: Option<VirtioDebugInfo>,
+}
+
+/// Summary of a virtio device attached to the VM.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeviceSummary {
+    /// Identifier of the device.
+    pub id: String,
+    /// Virtio type of the device.
+    pub virtio_type: u32,
+    /// Transport of the device.
+    pub transport: &'static str,
+    /// Whether the driver activated the device.
+    pub activated: bool,
+    /// Address range and irqs of the device.
+    #[serde(flatten)]
+    pub info: MMIODeviceInfo,
+}
+
+/// Indices of a virtio queue, as seen by the device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QueueStats {
+    /// Identifier of the device owning the queue.
+    pub device_id: String,
+    /// Virtio type of the device owning the queue.
+    pub virtio_type: u32,
+    /// Index of the queue within the device.
+    pub queue_index: usize,
+    /// Size of the queue, as negotiated by the driver.
+    pub size: u16,
+    /// Next available ring index the device will consume.
+    pub avail_idx: u16,
+    /// Next used ring index the device will produce.
+    pub used_idx: u16,
+    /// Number of times the driver kicked the device about the queue.
+    pub kicks: u64,
+}
+
+/// Time a virtio device has been active for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeviceUptime {
+    /// Identifier of the device.
+    pub device_id: String,
+    /// Virtio type of the device.
+    pub virtio_type: u32,
+    /// Microseconds since the driver activated the device, if it is active.
+    pub uptime_us: Option<u64>,
+}
+
+/// Readings of the clocks perceived by the guest, taken together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TimeSnapshot {
+    /// Wall-clock time of the guest, i.e. the host's shifted by the time offset, in seconds
+    /// since the epoch.
+    pub wall_clock_secs: i64,
+    /// Time of the RTC, in seconds since the epoch, if the VM has one (aarch64 only).
+    pub rtc_secs: Option<u32>,
+    /// Time elapsed since the boot timer started, in microseconds, if the VM has one.
+    pub boot_timer_us: Option<u64>,
+    /// Number of clock disruptions reported through the VMClock device, if the VM has one
+    /// (x86_64 only). The device lives outside of the MMIO bus, see [`Vmm::time_sources`].
+    ///
+    /// [`Vmm::time_sources`]: crate::Vmm::time_sources
+    pub vmclock_disruption_marker: Option<u64>,
+}
+
 #[cfg(target_arch = "x86_64")]
 fn add_virtio_aml(dsdt_data: &mut Vec<u8>, addr: u64, len: u64, irq: u32) {
     let dev_id = irq - crate::arch::IRQ_BASE;
@@ -123,6 +297,14 @@ pub struct MMIODeviceManager {
     // devices in the order they were added.
     #[cfg(target_arch = "x86_64")]
     pub(crate) dsdt_data: Vec<u8>,
+    // Time it took to restore each device, if restored from a snapshot.
+    pub(crate) restore_timings: Vec<DevicePersistTiming>,
+    // Devices left out of the snapshot this manager was restored from.
+    pub(crate) skipped_devices: Vec<SkippedDevice>,
+    // How the guest memory was populated, if restored from a snapshot.
+    pub(crate) restore_source: Option<RestoreSource>,
+    // Offset, in seconds, of the guest's wall-clock time from the host's.
+    time_offset: i64,
 }
 
 impl MMIODeviceManager {
@@ -133,7 +315,101 @@ impl MMIODeviceManager {
             id_to_dev_info: HashMap::new(),
             #[cfg(target_arch = "x86_64")]
             dsdt_data: vec![],
+            restore_timings: Vec::new(),
+            skipped_devices: Vec::new(),
+            restore_source: None,
+            time_offset: 0,
+        }
+    }
+
+    /// Time it took to restore each device, empty if not restored from a snapshot.
+    pub fn restore_timings(&self) -> &[DevicePersistTiming] {
+        &self.restore_timings
+    }
+
+    /// Devices left out of the snapshot this manager was restored from, and why. These devices
+    /// are missing from the restored microVM.
+    pub fn skipped_devices(&self) -> &[SkippedDevice] {
+        &self.skipped_devices
+    }
+
+    /// How the guest memory was populated when restoring from a snapshot, `None` if the microVM
+    /// was booted instead.
+    pub fn restore_source(&self) -> Option<RestoreSource> {
+        self.restore_source
+    }
+
+    /// Offset, in seconds, of the guest's wall-clock time from the host's.
+    pub fn time_offset(&self) -> i64 {
+        self.time_offset
+    }
+
+    /// Shifts the wall-clock time perceived by the guest by `delta_secs` seconds.
+    ///
+    /// The offset is applied to the RTC, which is only present on aarch64, including an RTC
+    /// registered after this call. The VMClock device only reports clock disruptions and carries
+    /// no time, so it is not affected.
+    pub fn set_time_offset(&mut self, delta_secs: i64) -> Result<(), MmioError> {
+        #[cfg(target_arch = "aarch64")]
+        self.apply_rtc_time_offset(delta_secs)?;
+        self.time_offset = delta_secs;
+        Ok(())
+    }
+
+    /// Reads the wall-clock time of the guest, its RTC and its boot timer, if any, in one go so
+    /// they can be compared.
+    pub fn time_sources(&self) -> TimeSnapshot {
+        let host_secs = utils::time::get_time_ns(utils::time::ClockType::Real) / 1_000_000_000;
+        let wall_clock_secs = i64::try_from(host_secs)
+            .unwrap_or(i64::MAX)
+            .saturating_add(self.time_offset);
+        #[cfg(target_arch = "aarch64")]
+        let rtc_secs = self
+            .get_device(DeviceType::Rtc, &DeviceType::Rtc.to_string())
+            .and_then(|device| {
+                device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .rtc_device_mut()
+                    .map(|rtc| rtc.time())
+            });
+        #[cfg(target_arch = "x86_64")]
+        let rtc_secs = None;
+        let boot_timer_us = self
+            .get_device(DeviceType::BootTimer, &DeviceType::BootTimer.to_string())
+            .and_then(|device| {
+                device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .boot_timer_ref()
+                    .map(BootTimer::elapsed_us)
+            });
+        TimeSnapshot {
+            wall_clock_secs,
+            rtc_secs,
+            boot_timer_us,
+            vmclock_disruption_marker: None,
+        }
+    }
+
+    /// Sets the time of the RTC, if any, to the host time shifted by `delta_secs` seconds.
+    #[cfg(target_arch = "aarch64")]
+    fn apply_rtc_time_offset(&self, delta_secs: i64) -> Result<(), MmioError> {
+        if let Some(device) = self.get_device(DeviceType::Rtc, &DeviceType::Rtc.to_string()) {
+            let host_secs = utils::time::get_time_ns(utils::time::ClockType::Real) / 1_000_000_000;
+            let guest_secs = i64::try_from(host_secs)
+                .ok()
+                .and_then(|secs| secs.checked_add(delta_secs))
+                .and_then(|secs| u32::try_from(secs).ok())
+                .ok_or(MmioError::InvalidTimeOffset(delta_secs))?;
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .rtc_device_mut()
+                .ok_or(MmioError::InvalidDeviceType)?
+                .set_time(guest_secs);
         }
+        Ok(())
     }
 
     /// Allocates resources for a new device to be added.
@@ -141,8 +417,9 @@ impl MMIODeviceManager {
         &mut self,
         resource_allocator: &mut ResourceAllocator,
         irq_count: u32,
+        owner: &str,
     ) -> Result<MMIODeviceInfo, MmioError> {
-        let irqs = resource_allocator.allocate_gsi(irq_count)?;
+        let irqs = resource_allocator.allocate_gsi(irq_count, owner)?;
         let device_info = MMIODeviceInfo {
             addr: resource_allocator.allocate_mmio_memory(
                 MMIO_LEN,
@@ -201,7 +478,8 @@ impl MMIODeviceManager {
             identifier,
             device_info.clone(),
             Arc::new(Mutex::new(BusDevice::MmioTransport(mmio_device))),
-        )
+        )?;
+        Ok(())
     }
 
     /// Append a registered virtio-over-MMIO device to the kernel cmdline.
@@ -235,7 +513,7 @@ impl MMIODeviceManager {
         mmio_device: MmioTransport,
         _cmdline: &mut kernel_cmdline::Cmdline,
     ) -> Result<MMIODeviceInfo, MmioError> {
-        let device_info = self.allocate_mmio_resources(resource_allocator, 1)?;
+        let device_info = self.allocate_mmio_resources(resource_allocator, 1, &device_id)?;
         self.register_mmio_virtio(vm, device_id, mmio_device, &device_info)?;
         #[cfg(target_arch = "x86_64")]
         {
@@ -267,7 +545,7 @@ impl MMIODeviceManager {
         let device_info = if let Some(device_info) = device_info_opt {
             device_info
         } else {
-            self.allocate_mmio_resources(resource_allocator, 1)?
+            self.allocate_mmio_resources(resource_allocator, 1, &DeviceType::Serial.to_string())?
         };
 
         vm.register_irqfd(
@@ -316,7 +594,7 @@ impl MMIODeviceManager {
         let device_info = if let Some(device_info) = device_info_opt {
             device_info
         } else {
-            self.allocate_mmio_resources(resource_allocator, 1)?
+            self.allocate_mmio_resources(resource_allocator, 1, &DeviceType::Rtc.to_string())?
         };
 
         // Create a new identifier for the RTC device.
@@ -326,7 +604,11 @@ impl MMIODeviceManager {
             identifier,
             device_info,
             Arc::new(Mutex::new(BusDevice::RTCDevice(rtc))),
-        )
+        )?;
+        if self.time_offset != 0 {
+            self.apply_rtc_time_offset(self.time_offset)?;
+        }
+        Ok(())
     }
 
     /// Register a boot timer device.
@@ -336,7 +618,11 @@ impl MMIODeviceManager {
         device: BootTimer,
     ) -> Result<(), MmioError> {
         // Attach a new boot timer device.
-        let device_info = self.allocate_mmio_resources(resource_allocator, 0)?;
+        let device_info = self.allocate_mmio_resources(
+            resource_allocator,
+            0,
+            &DeviceType::BootTimer.to_string(),
+        )?;
 
         let identifier = (DeviceType::BootTimer, DeviceType::BootTimer.to_string());
         self.register_mmio_device(
@@ -346,6 +632,24 @@ impl MMIODeviceManager {
         )
     }
 
+    /// Append the address of the registered boot timer to the kernel cmdline, so that the guest
+    /// can find it.
+    pub fn add_boot_timer_to_cmdline(
+        &self,
+        cmdline: &mut kernel_cmdline::Cmdline,
+    ) -> Result<(), MmioError> {
+        let device_info = self
+            .id_to_dev_info
+            .get(&(DeviceType::BootTimer, DeviceType::BootTimer.to_string()))
+            .ok_or(MmioError::DeviceNotFound)?;
+        cmdline
+            .insert(
+                "firecracker.boot_timer",
+                &format!("0x{:08x}", device_info.addr),
+            )
+            .map_err(MmioError::Cmdline)
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -404,6 +708,31 @@ impl MMIODeviceManager {
         Ok(())
     }
 
+    /// Run fn `f()` for the registered virtio devices until it returns `Some`, and return that
+    /// value. Devices are visited in no particular order.
+    pub fn try_find_virtio_device<T, F>(&self, mut f: F) -> Option<T>
+    where
+        F: FnMut(u32, &String, &MMIODeviceInfo, Arc<Mutex<dyn VirtioDevice>>) -> Option<T>,
+    {
+        self.get_device_info()
+            .iter()
+            .find_map(|((device_type, device_id), device_info)| {
+                let Virtio(virtio_type) = device_type else {
+                    return None;
+                };
+                let virtio_device = self
+                    .get_device(*device_type, device_id)
+                    // Safe to unwrap() because we know the device exists.
+                    .unwrap()
+                    .lock()
+                    .expect("Poisoned lock")
+                    .mmio_transport_ref()
+                    .expect("Unexpected device type")
+                    .device();
+                f(*virtio_type, device_id, device_info, virtio_device)
+            })
+    }
+
     /// Run fn `f()` for the virtio device matching `virtio_type` and `id`.
     pub fn with_virtio_device_with_id<T, F>(
         &self,
@@ -434,6 +763,477 @@ impl MMIODeviceManager {
         Ok(())
     }
 
+    /// Notifies devices that the guest memory has been resized to `mem_size_mib` MiB.
+    ///
+    /// The balloon device updates its view of the guest memory and re-validates its target.
+    pub fn on_memory_resized(&self, mem_size_mib: u64) -> Result<(), MmioError> {
+        self.for_each_virtio_device(|virtio_type, id, _info, dev| {
+            if virtio_type == TYPE_BALLOON {
+                let mut virtio = dev.lock().expect("Poisoned lock");
+                let balloon = virtio.as_mut_any().downcast_mut::<Balloon>().unwrap();
+                balloon
+                    .update_guest_memory_size(mem_size_mib)
+                    .map_err(|err| MmioError::InternalDeviceError(format!("{id}: {err}")))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the guest memory usage reported through the balloon statistics.
+    ///
+    /// Returns `None` if there is no balloon device, its statistics are disabled, or the
+    /// guest hasn't reported its memory usage yet.
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        let busdev = self.get_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let mut virtio = virtio_device.lock().expect("Poisoned lock");
+        let balloon = virtio.as_mut_any().downcast_mut::<Balloon>().unwrap();
+        balloon.latest_stats()?.memory_stats()
+    }
+
+    /// Delivers the interrupts that devices have queued but not raised yet.
+    pub fn flush_pending_interrupts(&self) -> Result<(), MmioError> {
+        self.for_each_virtio_device(|_virtio_type, id, _info, dev| {
+            let mut virtio = dev.lock().expect("Poisoned lock");
+            if virtio.is_activated() {
+                virtio
+                    .flush_pending_interrupts()
+                    .map_err(|err| MmioError::InternalDeviceError(format!("{id}: {err}")))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the current budgets of the rate limiters of the virtio device matching
+    /// `virtio_type` and `id`.
+    pub fn rate_limiter_state(
+        &self,
+        virtio_type: u32,
+        id: &str,
+    ) -> Result<Vec<(&'static str, RateLimiterBudgets)>, MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let budgets = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .rate_limiter_state();
+        Ok(budgets)
+    }
+
+    /// Grows the block device with `id` to `disk_size` bytes, after its backing file grew on the
+    /// host. The driver is notified of the change through a configuration change interrupt, and
+    /// the configuration generation of the transport is advanced.
+    pub fn resize_block_device(&self, id: &str, disk_size: u64) -> Result<(), MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let mut locked_busdev = busdev.lock().expect("Poisoned lock");
+        let transport = locked_busdev
+            .mmio_transport_mut()
+            .expect("Unexpected device type");
+        transport
+            .locked_device()
+            .as_mut_any()
+            .downcast_mut::<Block>()
+            .ok_or(MmioError::InvalidDeviceType)?
+            .update_disk_size(disk_size)
+            .map_err(|err| MmioError::InternalDeviceError(err.to_string()))?;
+        transport.config_generation = transport.config_generation.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Feeds `events` to the input device with `id`, which delivers them to the guest as the
+    /// guest provides buffers for them.
+    pub fn send_input_event(&self, id: &str, events: &[InputEvent]) -> Result<(), MmioError> {
+        self.with_virtio_device_with_id(TYPE_INPUT, id, |input: &mut Input| {
+            input.send_events(events).map_err(|err| err.to_string())
+        })
+    }
+
+    /// Returns the most recent error hit by the virtio device matching `virtio_type` and `id`,
+    /// together with the monotonic time at which it was recorded.
+    pub fn device_last_error(
+        &self,
+        virtio_type: u32,
+        id: &str,
+    ) -> Result<Option<(TimestampUs, String)>, MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let last_error = virtio_device.lock().expect("Poisoned lock").last_error();
+        Ok(last_error)
+    }
+
+    /// Approximate host memory, in bytes, allocated by the registered virtio devices on behalf
+    /// of the guest.
+    pub fn host_memory_estimate(&self) -> usize {
+        let mut total = 0;
+        let _: Result<(), ()> = self.for_each_virtio_device(|_virtio_type, _id, _info, dev| {
+            total += dev.lock().expect("Poisoned lock").host_memory_estimate();
+            Ok(())
+        });
+        total
+    }
+
+    /// Caps the host memory the virtio device matching `virtio_type` and `id` allocates on
+    /// behalf of the guest. Once reached, the device stops taking requests from the guest until
+    /// its buffers drain.
+    pub fn set_device_host_memory_limit(
+        &self,
+        virtio_type: u32,
+        id: &str,
+        limit: Option<usize>,
+    ) -> Result<(), MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let supported = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .set_host_memory_limit(limit);
+        if !supported {
+            return Err(MmioError::HostMemoryLimitNotSupported(id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Releases the host resources of the registered virtio devices before the VMM exits.
+    pub fn teardown(&self) {
+        let _: Result<(), ()> = self.for_each_virtio_device(|_virtio_type, id, _info, dev| {
+            debug!("Tearing down device {id}");
+            dev.lock().expect("Poisoned lock").teardown();
+            Ok(())
+        });
+    }
+
+    /// Runs the self-test of every registered virtio device, so a device whose host resources
+    /// are broken is rejected before the guest sees it. Stops at the first failing device.
+    pub fn self_test(&self) -> Result<(), MmioError> {
+        self.for_each_virtio_device(|_virtio_type, id, _info, dev| {
+            dev.lock()
+                .expect("Poisoned lock")
+                .self_test()
+                .map_err(|err| MmioError::SelfTest(id.clone(), err))
+        })
+    }
+
+    /// Stops or resumes the queue processing of the virtio device matching `virtio_type` and
+    /// `id`. A disabled device stays attached and keeps its configuration.
+    pub fn set_device_enabled(
+        &self,
+        virtio_type: u32,
+        id: &str,
+        enabled: bool,
+    ) -> Result<(), MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let supported = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .set_enabled(enabled);
+        if !supported {
+            return Err(MmioError::DisableNotSupported(id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Stops the queue processing of the virtio device matching `virtio_type` and `id`, waits at
+    /// most `timeout` for its in-flight requests to complete and flushes its backing store, so
+    /// the backing store can be copied consistently. [`Self::set_device_enabled`] resumes it.
+    pub fn quiesce_device(
+        &self,
+        virtio_type: u32,
+        id: &str,
+        timeout: Duration,
+    ) -> Result<(), MmioError> {
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let res = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .quiesce(timeout);
+        res.map_err(|err| MmioError::Quiesce(id.to_string(), err))
+    }
+
+    /// Waits at most `timeout` per device for the in-flight requests of the virtio devices to
+    /// complete and flushes their backing stores, so a snapshot taken right after captures no
+    /// half-processed request. Devices that cannot be quiesced are skipped.
+    ///
+    /// Each device is left enabled or disabled as it was before the call. Stops at the first
+    /// device that fails to quiesce.
+    pub fn quiesce(&self, timeout: Duration) -> Result<(), MmioError> {
+        self.for_each_virtio_device(|_, id, _, device| {
+            let mut locked = device.lock().expect("Poisoned lock");
+            let enabled = locked.is_enabled();
+            match locked.quiesce(timeout) {
+                Ok(()) => {
+                    locked.set_enabled(enabled);
+                    Ok(())
+                }
+                Err(QuiesceError::NotSupported) => Ok(()),
+                Err(err) => {
+                    locked.set_enabled(enabled);
+                    Err(MmioError::Quiesce(id.clone(), err))
+                }
+            }
+        })
+    }
+
+    /// Changes the id of the virtio device matching `virtio_type` and `old_id` to `new_id`.
+    ///
+    /// The device keeps its MMIO range and interrupts, and its metrics are reported under the
+    /// new id from then on.
+    pub fn rename_device(
+        &mut self,
+        virtio_type: u32,
+        old_id: &str,
+        new_id: &str,
+    ) -> Result<(), MmioError> {
+        let old_key = (DeviceType::Virtio(virtio_type), old_id.to_string());
+        let new_key = (DeviceType::Virtio(virtio_type), new_id.to_string());
+        let busdev = self
+            .get_device(DeviceType::Virtio(virtio_type), old_id)
+            .ok_or(MmioError::DeviceNotFound)?;
+        if old_id == new_id {
+            return Ok(());
+        }
+        if self.id_to_dev_info.contains_key(&new_key) {
+            return Err(MmioError::DuplicateDeviceId(new_id.to_string()));
+        }
+
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let supported = virtio_device.lock().expect("Poisoned lock").set_id(new_id);
+        if !supported {
+            return Err(MmioError::RenameNotSupported(old_id.to_string()));
+        }
+
+        // Safe to unwrap() because we know the device exists.
+        let device_info = self.id_to_dev_info.remove(&old_key).unwrap();
+        self.id_to_dev_info.insert(new_key, device_info);
+        Ok(())
+    }
+
+    /// Lists the attached virtio devices, ordered by MMIO address.
+    ///
+    /// Each device is locked only for as long as it takes to read its activation state.
+    pub fn list_devices(&self) -> Vec<DeviceSummary> {
+        let mut devices = Vec::with_capacity(self.id_to_dev_info.len());
+        let _: Result<(), MmioError> =
+            self.for_each_virtio_device(|virtio_type, id, info, device| {
+                let activated = device.lock().expect("Poisoned lock").is_activated();
+                devices.push(DeviceSummary {
+                    id: id.clone(),
+                    virtio_type,
+                    transport: "mmio",
+                    activated,
+                    info: info.clone(),
+                });
+                Ok(())
+            });
+        devices.sort_by_key(|device| device.info.addr);
+        devices
+    }
+
+    /// Returns a JSON view of the runtime state of the registered devices, meant for debugging.
+    ///
+    /// The dump holds no guest data: only device identities, resources, negotiated features
+    /// and queue indices.
+    pub fn debug_dump(&self) -> serde_json::Value {
+        let mut devices: Vec<DeviceDebugInfo> = self
+            .id_to_dev_info
+            .iter()
+            .map(|((device_type, id), info)| {
+                let virtio = self
+                    .get_device(*device_type, id)
+                    .and_then(|busdev| {
+                        busdev
+                            .lock()
+                            .expect("Poisoned lock")
+                            .mmio_transport_ref()
+                            .map(|transport| {
+                                (
+                                    transport.device(),
+                                    transport.mem().clone(),
+                                    transport.is_vhost_user,
+                                    transport.uptime(),
+                                )
+                            })
+                    })
+                    .map(|(device, mem, is_vhost_user, uptime)| {
+                        let locked = device.lock().expect("Poisoned lock");
+                        // The queues of vhost-user devices are processed by their backend, so
+                        // their kicks and device side indices are not tracked.
+                        let mem = (locked.is_activated() && !is_vhost_user).then_some(&mem);
+                        VirtioDebugInfo {
+                            activated: locked.is_activated(),
+                            uptime_us: uptime.map(|uptime| {
+                                u64::try_from(uptime.as_micros()).unwrap_or(u64::MAX)
+                            }),
+                            enabled: locked.is_enabled(),
+                            avail_features: locked.avail_features(),
+                            acked_features: locked.acked_features(),
+                            host_fds: locked.host_fd_count(),
+                            host_memory: locked.host_memory_estimate(),
+                            queues: locked
+                                .queues()
+                                .iter()
+                                .map(|queue| QueueDebugInfo {
+                                    max_size: queue.max_size,
+                                    size: queue.size,
+                                    ready: queue.ready,
+                                    next_avail: queue.next_avail.0,
+                                    next_used: queue.next_used.0,
+                                    kicks: queue.num_kicks,
+                                    pending: mem.map(|mem| queue.len(mem)),
+                                })
+                                .collect(),
+                            last_error: locked.last_error().map(|(timestamp, error)| {
+                                LastErrorDebugInfo {
+                                    timestamp_us: timestamp.time_us,
+                                    error,
+                                }
+                            }),
+                        }
+                    });
+                DeviceDebugInfo {
+                    device_type: *device_type,
+                    id: id.clone(),
+                    transport: "mmio",
+                    info: info.clone(),
+                    virtio,
+                }
+            })
+            .collect();
+        devices.sort_by_key(|device| device.info.addr);
+        let host_fd_count: usize = devices
+            .iter()
+            .filter_map(|device| device.virtio.as_ref())
+            .map(|virtio| virtio.host_fds)
+            .sum();
+        let host_memory: usize = devices
+            .iter()
+            .filter_map(|device| device.virtio.as_ref())
+            .map(|virtio| virtio.host_memory)
+            .sum();
+
+        serde_json::json!({
+            "host_fd_count": host_fd_count,
+            "host_memory": host_memory,
+            "devices": devices,
+        })
+    }
+
+    /// Reports how long each registered virtio device has been active for.
+    pub fn device_uptimes(&self) -> Vec<DeviceUptime> {
+        let mut uptimes = Vec::new();
+        let _: Result<(), MmioError> =
+            self.for_each_device(|device_type, device_id, _, bus_device| {
+                if let Virtio(virtio_type) = device_type {
+                    let uptime = bus_device
+                        .lock()
+                        .expect("Poisoned lock")
+                        .mmio_transport_ref()
+                        .expect("Unexpected device type")
+                        .uptime();
+                    uptimes.push(DeviceUptime {
+                        device_id: device_id.clone(),
+                        virtio_type: *virtio_type,
+                        uptime_us: uptime
+                            .map(|uptime| u64::try_from(uptime.as_micros()).unwrap_or(u64::MAX)),
+                    });
+                }
+                Ok(())
+            });
+        uptimes
+    }
+
+    /// Counts the uptime of the active virtio devices from now on, as if they had just been
+    /// activated.
+    pub fn restart_device_uptimes(&self) {
+        let _: Result<(), MmioError> = self.for_each_device(|device_type, _, _, bus_device| {
+            if let Virtio(_) = device_type {
+                bus_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .mmio_transport_mut()
+                    .expect("Unexpected device type")
+                    .restart_uptime();
+            }
+            Ok(())
+        });
+    }
+
+    /// Collects the indices of every queue of the attached virtio devices.
+    ///
+    /// Only the device side of the queues is read, so the device state is left untouched and
+    /// devices that were not activated are reported as well.
+    pub fn collect_queue_stats(&self) -> Vec<QueueStats> {
+        let mut stats = Vec::new();
+        let _: Result<(), MmioError> = self.for_each_virtio_device(|virtio_type, id, _, device| {
+            let locked = device.lock().expect("Poisoned lock");
+            stats.extend(
+                locked
+                    .queues()
+                    .iter()
+                    .enumerate()
+                    .map(|(queue_index, queue)| QueueStats {
+                        device_id: id.clone(),
+                        virtio_type,
+                        queue_index,
+                        size: queue.size,
+                        avail_idx: queue.next_avail.0,
+                        used_idx: queue.next_used.0,
+                        kicks: queue.num_kicks,
+                    }),
+            );
+            Ok(())
+        });
+        stats
+    }
+
     /// Artificially kick devices as if they had external events.
     pub fn kick_devices(&self) {
         info!("Artificially kick devices.");
@@ -491,6 +1291,15 @@ impl MMIODeviceManager {
                             entropy.process_virtio_queues();
                         }
                     }
+                    TYPE_INPUT => {
+                        let input = virtio.as_mut_any().downcast_mut::<Input>().unwrap();
+                        // If device is activated, deliver the events restored with the device
+                        // to the buffers the guest made available before the snapshot.
+                        if input.is_activated() {
+                            info!("kick input {id}.");
+                            input.process_virtio_queues();
+                        }
+                    }
                     _ => (),
                 }
                 Ok(())
@@ -513,17 +1322,33 @@ impl DeviceInfoForFDT for MMIODeviceInfo {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::atomic::AtomicU32;
+    use std::num::Wrapping;
+    use std::sync::atomic::{AtomicU32, Ordering};
     use std::sync::Arc;
 
     use utils::eventfd::EventFd;
+    use utils::tempfile::TempFile;
 
     use super::*;
-    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::block::virtio::test_utils::{
+        default_block_with_path, default_engine_type_for_kv, read_blk_req_descriptors, set_queue,
+        simulate_queue_event,
+    };
+    use crate::devices::virtio::device::{IrqType, LastError, VirtioDevice};
+    use crate::devices::virtio::gen::virtio_blk::VIRTIO_BLK_T_OUT;
+    use crate::devices::virtio::input::device::EV_KEY;
+    use crate::devices::virtio::input::InputKind;
+    use crate::devices::virtio::mmio::VIRTIO_MMIO_INT_VRING;
+    use crate::devices::virtio::net::metrics::NetMetricsPerDevice;
+    use crate::devices::virtio::net::test_utils::default_net;
     use crate::devices::virtio::queue::Queue;
+    use crate::devices::virtio::queue::VIRTQ_DESC_F_NEXT;
+    use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
     use crate::devices::virtio::ActivateError;
+    use crate::logger::IncMetric;
+    use crate::rate_limiter::{RateLimiter, TokenType};
     use crate::utilities::test_utils::multi_region_mem;
-    use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
+    use crate::vstate::memory::{Bytes, GuestAddress, GuestMemoryMmap};
     use crate::{builder, Vm};
 
     const QUEUE_SIZES: &[u16] = &[64];
@@ -567,6 +1392,7 @@ mod tests {
         queues: Vec<Queue>,
         queue_evts: [EventFd; 1],
         interrupt_evt: EventFd,
+        last_error: LastError,
     }
 
     impl DummyDevice {
@@ -576,6 +1402,7 @@ mod tests {
                 queues: QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect(),
                 queue_evts: [EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD")],
                 interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).expect("cannot create eventFD"),
+                last_error: LastError::default(),
             }
         }
     }
@@ -637,24 +1464,39 @@ mod tests {
         fn is_activated(&self) -> bool {
             false
         }
+
+        fn last_error(&self) -> Option<(TimestampUs, String)> {
+            self.last_error.get()
+        }
     }
 
-    #[test]
-    fn test_register_virtio_device() {
+    /// Creates a VM with an interrupt controller and two memory regions, along with the resource
+    /// allocator and kernel command line needed to register devices on it.
+    fn test_setup() -> (
+        Vm,
+        GuestMemoryMmap,
+        ResourceAllocator,
+        kernel_cmdline::Cmdline,
+    ) {
         let start_addr1 = GuestAddress(0x0);
         let start_addr2 = GuestAddress(0x1000);
         let guest_mem = multi_region_mem(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]);
         let mut vm = Vm::new(vec![]).unwrap();
         vm.memory_init(&guest_mem, false).unwrap();
-        let mut device_manager = MMIODeviceManager::new();
-        let mut resource_allocator = ResourceAllocator::new().unwrap();
-
-        let mut cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
-        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
         #[cfg(target_arch = "x86_64")]
         builder::setup_interrupt_controller(&mut vm).unwrap();
         #[cfg(target_arch = "aarch64")]
         builder::setup_interrupt_controller(&mut vm, 1).unwrap();
+        let resource_allocator = ResourceAllocator::new().unwrap();
+        let cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
+        (vm, guest_mem, resource_allocator, cmdline)
+    }
+
+    #[test]
+    fn test_register_virtio_device() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
 
         device_manager
             .register_virtio_test_device(
@@ -669,77 +1511,934 @@ mod tests {
     }
 
     #[test]
-    fn test_register_too_many_devices() {
-        let start_addr1 = GuestAddress(0x0);
-        let start_addr2 = GuestAddress(0x1000);
-        let guest_mem = multi_region_mem(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]);
-        let mut vm = Vm::new(vec![]).unwrap();
-        vm.memory_init(&guest_mem, false).unwrap();
+    fn test_on_memory_resized() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
         let mut device_manager = MMIODeviceManager::new();
-        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let balloon = Arc::new(Mutex::new(Balloon::new(16, false, 0, false).unwrap()));
 
-        let mut cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
-        #[cfg(target_arch = "x86_64")]
-        builder::setup_interrupt_controller(&mut vm).unwrap();
-        #[cfg(target_arch = "aarch64")]
-        builder::setup_interrupt_controller(&mut vm, 1).unwrap();
+        // Without a balloon device there is nothing to update.
+        device_manager.on_memory_resized(256).unwrap();
 
-        for _i in crate::arch::IRQ_BASE..=crate::arch::IRQ_MAX {
-            device_manager
-                .register_virtio_test_device(
-                    vm.fd(),
-                    guest_mem.clone(),
-                    &mut resource_allocator,
-                    Arc::new(Mutex::new(DummyDevice::new())),
-                    &mut cmdline,
-                    "dummy1",
-                )
-                .unwrap();
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                balloon.clone(),
+                &mut cmdline,
+                "balloon",
+            )
+            .unwrap();
+        balloon.lock().unwrap().activate(guest_mem).unwrap();
+
+        device_manager.on_memory_resized(256).unwrap();
+        {
+            let balloon = balloon.lock().unwrap();
+            assert_eq!(balloon.guest_memory_mib(), 256);
+            assert_eq!(balloon.size_mb(), 16);
+            assert!(balloon.irq_trigger.has_pending_irq(IrqType::Config));
         }
-        assert_eq!(
-            format!(
-                "{}",
-                device_manager
-                    .register_virtio_test_device(
-                        vm.fd(),
-                        guest_mem,
-                        &mut resource_allocator,
-                        Arc::new(Mutex::new(DummyDevice::new())),
-                        &mut cmdline,
-                        "dummy2"
-                    )
-                    .unwrap_err()
-            ),
-            "Failed to allocate requested resource: The requested resource is not available."
-                .to_string()
-        );
+
+        // Shrinking the guest memory below the balloon target clamps the target.
+        device_manager.on_memory_resized(8).unwrap();
+        let balloon = balloon.lock().unwrap();
+        assert_eq!(balloon.guest_memory_mib(), 8);
+        assert_eq!(balloon.size_mb(), 8);
+        assert!(balloon.irq_trigger.has_pending_irq(IrqType::Config));
     }
 
     #[test]
-    fn test_dummy_device() {
-        let dummy = DummyDevice::new();
-        assert_eq!(dummy.device_type(), 0);
-        assert_eq!(dummy.queues().len(), QUEUE_SIZES.len());
+    fn test_memory_stats() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let balloon = Arc::new(Mutex::new(Balloon::new(16, false, 1, false).unwrap()));
+
+        // Without a balloon device there are no memory stats.
+        assert_eq!(device_manager.memory_stats(), None);
+
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                balloon.clone(),
+                &mut cmdline,
+                BALLOON_DEV_ID,
+            )
+            .unwrap();
+
+        // The guest hasn't reported its memory usage yet.
+        assert_eq!(device_manager.memory_stats(), None);
+
+        {
+            let mut balloon = balloon.lock().unwrap();
+            balloon.config_space.actual_pages = 256;
+            balloon.latest_stats.total_memory = Some(512 << 20);
+            balloon.latest_stats.free_memory = Some(128 << 20);
+            balloon.latest_stats.available_memory = Some(256 << 20);
+        }
+        assert_eq!(
+            device_manager.memory_stats(),
+            Some(MemoryStats {
+                total_memory: 512 << 20,
+                free_memory: 128 << 20,
+                used_memory: 384 << 20,
+                available_memory: Some(256 << 20),
+                balloon_mib: 1,
+            })
+        );
     }
 
     #[test]
-    fn test_device_info() {
-        let start_addr1 = GuestAddress(0x0);
-        let start_addr2 = GuestAddress(0x1000);
-        let guest_mem = multi_region_mem(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]);
-        let mut vm = Vm::new(vec![]).unwrap();
-        vm.memory_init(&guest_mem, false).unwrap();
+    fn test_flush_pending_interrupts() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let balloon = Arc::new(Mutex::new(Balloon::new(0, false, 0, false).unwrap()));
 
-        let mem_clone = guest_mem.clone();
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                balloon.clone(),
+                &mut cmdline,
+                "balloon",
+            )
+            .unwrap();
+        balloon.lock().unwrap().activate(guest_mem).unwrap();
+
+        // Nothing pending, nothing delivered.
+        device_manager.flush_pending_interrupts().unwrap();
+        {
+            let balloon = balloon.lock().unwrap();
+            assert!(!balloon.irq_trigger.has_pending_irq(IrqType::Vring));
+            // Leave a Vring interrupt pending without raising it.
+            balloon
+                .irq_trigger
+                .irq_status
+                .store(VIRTIO_MMIO_INT_VRING, Ordering::SeqCst);
+        }
+
+        device_manager.flush_pending_interrupts().unwrap();
+        let balloon = balloon.lock().unwrap();
+        assert!(balloon.irq_trigger.has_pending_irq(IrqType::Vring));
+    }
+
+    #[test]
+    fn test_rate_limiter_state() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let mut net = default_net();
+        // Limit tx bandwidth at a refill rate of 1 token per second.
+        net.tx_rate_limiter = RateLimiter::new(1000, 0, 1_000_000, 0, 0, 0).unwrap();
+        let net = Arc::new(Mutex::new(net));
+
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                net.clone(),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            device_manager.rate_limiter_state(TYPE_NET, "foo"),
+            Err(MmioError::DeviceNotFound)
+        ));
+
+        assert!(net
+            .lock()
+            .unwrap()
+            .tx_rate_limiter
+            .consume(300, TokenType::Bytes));
+
+        let state = device_manager.rate_limiter_state(TYPE_NET, "net").unwrap();
+        assert_eq!(state.len(), 2);
+        assert_eq!(state[0], ("rx_rate_limiter", RateLimiterBudgets::default()));
+        assert_eq!(state[1].0, "tx_rate_limiter");
+        assert!(state[1].1.ops.is_none());
+        let bandwidth = state[1].1.bandwidth.unwrap();
+        assert_eq!(bandwidth.capacity, 1000);
+        assert_eq!(bandwidth.refill_time_ms, 1_000_000);
+        assert_eq!(bandwidth.budget, 700);
+    }
+
+    #[test]
+    fn test_try_find_virtio_device() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        for id in ["dummy0", "dummy1", "dummy2"] {
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    Arc::new(Mutex::new(DummyDevice::new())),
+                    &mut cmdline,
+                    id,
+                )
+                .unwrap();
+        }
+
+        // The search stops at the first match.
+        let mut visited = 0;
+        let found = device_manager.try_find_virtio_device(|_, id, _, _| {
+            visited += 1;
+            Some(id.clone())
+        });
+        assert!(found.is_some());
+        assert_eq!(visited, 1);
+
+        let found = device_manager
+            .try_find_virtio_device(|_, id, info, _| (id == "dummy1").then_some(info.addr));
+        let info = device_manager
+            .id_to_dev_info
+            .get(&(DeviceType::Virtio(0), "dummy1".to_string()))
+            .unwrap();
+        assert_eq!(found, Some(info.addr));
+
+        let mut visited = 0;
+        let found: Option<()> = device_manager.try_find_virtio_device(|_, _, _, _| {
+            visited += 1;
+            None
+        });
+        assert!(found.is_none());
+        assert_eq!(visited, 3);
+    }
+
+    #[test]
+    fn test_send_input_event() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let input = Arc::new(Mutex::new(
+            Input::new("keyboard".to_string(), InputKind::Keyboard).unwrap(),
+        ));
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                input.clone(),
+                &mut cmdline,
+                "keyboard",
+            )
+            .unwrap();
+
+        let events = [InputEvent::new(EV_KEY, 30, 1), InputEvent::sync()];
+        // Events are rejected until the guest driver activates the device.
+        assert!(matches!(
+            device_manager.send_input_event("keyboard", &events),
+            Err(MmioError::InternalDeviceError(_))
+        ));
+
+        // Without buffers from the guest, the events wait in the device.
+        input.lock().unwrap().set_activated(guest_mem);
+        device_manager
+            .send_input_event("keyboard", &events)
+            .unwrap();
+        assert_eq!(input.lock().unwrap().pending_events(), events);
+
+        assert!(matches!(
+            device_manager.send_input_event("foo", &events),
+            Err(MmioError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_resize_block_device() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Arc::new(Mutex::new(Block::Virtio(default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        ))));
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                block.clone(),
+                &mut cmdline,
+                "block",
+            )
+            .unwrap();
+        let config_generation = || {
+            device_manager
+                .get_device(DeviceType::Virtio(TYPE_BLOCK), "block")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .mmio_transport_ref()
+                .unwrap()
+                .config_generation
+        };
+        let capacity = || {
+            let mut data = [0u8; 8];
+            block.lock().unwrap().read_config(0, &mut data);
+            u64::from_le_bytes(data)
+        };
+        assert_eq!(capacity(), 8);
+
+        // The size must be sector aligned, may not shrink, and must fit in the backing file.
+        for size in [0x1001, 0x800, 0x2000] {
+            device_manager
+                .resize_block_device("block", size)
+                .unwrap_err();
+        }
+        assert_eq!(config_generation(), 0);
+        assert_eq!(capacity(), 8);
+
+        f.as_file().set_len(0x2000).unwrap();
+        device_manager.resize_block_device("block", 0x2000).unwrap();
+        assert_eq!(config_generation(), 1);
+        assert_eq!(capacity(), 16);
+        if let Block::Virtio(block) = &*block.lock().unwrap() {
+            assert!(block.irq_trigger.has_pending_irq(IrqType::Config));
+        }
+
+        assert!(matches!(
+            device_manager.resize_block_device("foo", 0x2000),
+            Err(MmioError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_list_devices() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        assert!(device_manager.list_devices().is_empty());
+
+        let net_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                Arc::new(Mutex::new(default_net())),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+        let dummy_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                Arc::new(Mutex::new(DummyDevice::new())),
+                &mut cmdline,
+                "dummy",
+            )
+            .unwrap();
+        // Legacy devices are not part of the listing.
+        device_manager
+            .register_mmio_boot_timer(
+                &mut resource_allocator,
+                BootTimer::new(TimestampUs::default()),
+            )
+            .unwrap();
+
+        let devices = device_manager.list_devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, "net");
+        assert_eq!(devices[0].virtio_type, TYPE_NET);
+        assert_eq!(devices[0].transport, "mmio");
+        assert!(!devices[0].activated);
+        assert_eq!(devices[0].info.addr, net_addr);
+        assert_eq!(devices[0].info.len, MMIO_LEN);
+        assert_eq!(devices[1].id, "dummy");
+        assert_eq!(devices[1].virtio_type, 0);
+        assert_eq!(devices[1].info.addr, dummy_addr);
+    }
+
+    #[test]
+    fn test_device_last_error() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let net = Arc::new(Mutex::new(default_net()));
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                net.clone(),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+
+        assert!(device_manager
+            .device_last_error(TYPE_NET, "net")
+            .unwrap()
+            .is_none());
+        net.lock().unwrap().last_error.record("net error");
+        let (_, err) = device_manager
+            .device_last_error(TYPE_NET, "net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(err, "net error");
+
+        assert!(matches!(
+            device_manager.device_last_error(TYPE_NET, "foo"),
+            Err(MmioError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let net = Arc::new(Mutex::new(default_net()));
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+
+        let net_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                net.clone(),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+        let dummy_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                dummy.clone(),
+                &mut cmdline,
+                "dummy",
+            )
+            .unwrap();
+        dummy.lock().unwrap().queues_mut()[0].num_kicks = 2;
+        dummy.lock().unwrap().last_error.record("dummy error");
+
+        let dump = device_manager.debug_dump();
+        let devices = dump["devices"].as_array().unwrap();
+        assert_eq!(devices.len(), 2);
+
+        let net_dump = &devices[0];
+        assert_eq!(net_dump["id"], "net");
+        assert_eq!(net_dump["device_type"]["Virtio"], TYPE_NET);
+        assert_eq!(net_dump["transport"], "mmio");
+        assert_eq!(net_dump["addr"], net_addr);
+        assert_eq!(net_dump["len"], MMIO_LEN);
+        assert_eq!(net_dump["irqs"].as_array().unwrap().len(), 1);
+        let net = net.lock().unwrap();
+        assert_eq!(net_dump["virtio"]["activated"], false);
+        assert_eq!(net_dump["virtio"]["enabled"], true);
+        assert_eq!(net_dump["virtio"]["avail_features"], net.avail_features());
+        assert_eq!(net_dump["virtio"]["acked_features"], 0);
+        assert_eq!(net_dump["virtio"]["host_fds"], net.host_fd_count());
+        assert_eq!(
+            dump["host_fd_count"],
+            net.host_fd_count() + DummyDevice::new().host_fd_count()
+        );
+        assert_eq!(net_dump["virtio"]["host_memory"], 0);
+        assert_eq!(dump["host_memory"], 0);
+        let queues = net_dump["virtio"]["queues"].as_array().unwrap();
+        assert_eq!(queues.len(), net.queues().len());
+        assert_eq!(queues[0]["max_size"], net.queues()[0].max_size);
+        assert_eq!(queues[0]["next_avail"], 0);
+        assert_eq!(queues[0]["kicks"], 0);
+        // The queues of a device that is not activated have no pending entries.
+        assert!(queues[0]["pending"].is_null());
+        assert!(net_dump["virtio"]["last_error"].is_null());
+
+        let dummy_dump = &devices[1];
+        assert_eq!(dummy_dump["id"], "dummy");
+        assert_eq!(dummy_dump["addr"], dummy_addr);
+        let queues = dummy_dump["virtio"]["queues"].as_array().unwrap();
+        assert_eq!(queues.len(), QUEUE_SIZES.len());
+        assert_eq!(queues[0]["kicks"], 2);
+        let (timestamp, _) = dummy.lock().unwrap().last_error().unwrap();
+        assert_eq!(
+            dummy_dump["virtio"]["last_error"]["timestamp_us"],
+            timestamp.time_us
+        );
+        assert_eq!(dummy_dump["virtio"]["last_error"]["error"], "dummy error");
+    }
+
+    #[test]
+    fn test_device_uptimes() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        assert!(device_manager.device_uptimes().is_empty());
+
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                Arc::new(Mutex::new(DummyDevice::new())),
+                &mut cmdline,
+                "dummy",
+            )
+            .unwrap();
+        assert_eq!(
+            device_manager.device_uptimes(),
+            vec![DeviceUptime {
+                device_id: "dummy".to_string(),
+                virtio_type: 0,
+                uptime_us: None,
+            }]
+        );
+
+        // Activate the device as the transport does when the driver is ready.
+        device_manager
+            .get_device(DeviceType::Virtio(0), "dummy")
+            .unwrap()
+            .lock()
+            .unwrap()
+            .mmio_transport_mut()
+            .unwrap()
+            .activated_at = Some(TimestampUs::default());
+        std::thread::sleep(Duration::from_millis(10));
+        let uptime = device_manager.device_uptimes()[0].uptime_us.unwrap();
+        assert!(uptime >= 10_000);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(device_manager.device_uptimes()[0].uptime_us.unwrap() > uptime);
+
+        // A restored device counts its uptime from the restore.
+        device_manager.restart_device_uptimes();
+        assert!(device_manager.device_uptimes()[0].uptime_us.unwrap() < uptime);
+    }
+
+    #[test]
+    fn test_collect_queue_stats() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        assert!(device_manager.collect_queue_stats().is_empty());
+
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                dummy.clone(),
+                &mut cmdline,
+                "dummy",
+            )
+            .unwrap();
+        {
+            let mut locked = dummy.lock().unwrap();
+            locked.queues_mut()[0].next_avail = Wrapping(5);
+            locked.queues_mut()[0].next_used = Wrapping(3);
+            locked.queues_mut()[0].num_kicks = 2;
+        }
+
+        // The device is not activated, yet its queues are reported.
+        let stats = device_manager.collect_queue_stats();
+        assert_eq!(stats.len(), QUEUE_SIZES.len());
+        assert_eq!(stats[0].device_id, "dummy");
+        assert_eq!(stats[0].virtio_type, 0);
+        assert_eq!(stats[0].queue_index, 0);
+        assert_eq!(stats[0].size, 0);
+        assert_eq!(stats[0].avail_idx, 5);
+        assert_eq!(stats[0].used_idx, 3);
+        assert_eq!(stats[0].kicks, 2);
+        // Collecting the stats leaves the queues untouched.
+        assert_eq!(device_manager.collect_queue_stats(), stats);
+        assert!(!dummy.lock().unwrap().is_activated());
+    }
+
+    #[test]
+    fn test_set_device_enabled() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let net = Arc::new(Mutex::new(default_net()));
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                net.clone(),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+        device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem,
+                &mut resource_allocator,
+                dummy,
+                &mut cmdline,
+                "dummy",
+            )
+            .unwrap();
+
+        assert!(matches!(
+            device_manager.set_device_enabled(TYPE_NET, "foo", false),
+            Err(MmioError::DeviceNotFound)
+        ));
+        assert!(matches!(
+            device_manager.set_device_enabled(0, "dummy", false),
+            Err(MmioError::DisableNotSupported(_))
+        ));
+
+        device_manager
+            .set_device_enabled(TYPE_NET, "net", false)
+            .unwrap();
+        assert!(!net.lock().unwrap().is_enabled());
+        device_manager
+            .set_device_enabled(TYPE_NET, "net", true)
+            .unwrap();
+        assert!(net.lock().unwrap().is_enabled());
+
+        assert_eq!(device_manager.host_memory_estimate(), 0);
+        assert!(matches!(
+            device_manager.set_device_host_memory_limit(0, "dummy", Some(4096)),
+            Err(MmioError::HostMemoryLimitNotSupported(_))
+        ));
+        device_manager
+            .set_device_host_memory_limit(TYPE_NET, "net", Some(4096))
+            .unwrap();
+        assert_eq!(net.lock().unwrap().host_memory_limit, Some(4096));
+    }
+
+    #[test]
+    fn test_quiesce() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Arc::new(Mutex::new(default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        )));
+        // Devices that cannot be quiesced are skipped.
+        let devices: [(Arc<Mutex<dyn VirtioDevice>>, &str); 3] = [
+            (block.clone(), "block"),
+            (Arc::new(Mutex::new(default_net())), "net"),
+            (Arc::new(Mutex::new(DummyDevice::new())), "dummy"),
+        ];
+        for (device, id) in devices {
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    device,
+                    &mut cmdline,
+                    id,
+                )
+                .unwrap();
+        }
+        device_manager.quiesce(Duration::from_secs(5)).unwrap();
+
+        // Submit a write whose completion is not processed yet.
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        {
+            let mut locked = block.lock().unwrap();
+            set_queue(&mut locked, 0, vq.create_queue());
+            locked.activate(mem.clone()).unwrap();
+            read_blk_req_descriptors(&vq);
+            mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(vq.dtable[0].addr.get()))
+                .unwrap();
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            simulate_queue_event(&mut locked, None);
+        }
+
+        // Quiescing completes the write and leaves the device enabled.
+        device_manager.quiesce(Duration::from_secs(5)).unwrap();
+        assert!(block.lock().unwrap().in_flight_requests.is_empty());
+        assert!(block.lock().unwrap().is_enabled());
+        assert_eq!(vq.used.idx.get(), 1);
+
+        // A disabled device stays disabled.
+        device_manager
+            .set_device_enabled(TYPE_BLOCK, "block", false)
+            .unwrap();
+        device_manager.quiesce(Duration::from_secs(5)).unwrap();
+        assert!(!block.lock().unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_rename_device() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let net = Arc::new(Mutex::new(default_net()));
+
+        let net_addr = device_manager
+            .register_virtio_test_device(
+                vm.fd(),
+                guest_mem.clone(),
+                &mut resource_allocator,
+                net.clone(),
+                &mut cmdline,
+                "net",
+            )
+            .unwrap();
+        for id in ["dummy", "dummy2"] {
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    Arc::new(Mutex::new(DummyDevice::new())),
+                    &mut cmdline,
+                    id,
+                )
+                .unwrap();
+        }
+
+        assert!(matches!(
+            device_manager.rename_device(TYPE_NET, "foo", "renamed_net"),
+            Err(MmioError::DeviceNotFound)
+        ));
+        assert!(matches!(
+            device_manager.rename_device(0, "dummy", "dummy2"),
+            Err(MmioError::DuplicateDeviceId(_))
+        ));
+        assert!(matches!(
+            device_manager.rename_device(0, "dummy", "dummy3"),
+            Err(MmioError::RenameNotSupported(_))
+        ));
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(0), "dummy")
+            .is_some());
+
+        net.lock().unwrap().metrics.rx_count.inc();
+        device_manager
+            .rename_device(TYPE_NET, "net", "renamed_net")
+            .unwrap();
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(TYPE_NET), "net")
+            .is_none());
+        assert!(device_manager
+            .get_device(DeviceType::Virtio(TYPE_NET), "renamed_net")
+            .is_some());
+        assert_eq!(net.lock().unwrap().id(), "renamed_net");
+        // The device keeps its resources.
+        assert_eq!(
+            device_manager.id_to_dev_info
+                [&(DeviceType::Virtio(TYPE_NET), "renamed_net".to_string())]
+                .addr,
+            net_addr
+        );
+        // Its metrics, counts included, are now registered under the new id.
+        let metrics = NetMetricsPerDevice::alloc("renamed_net".to_string());
+        assert!(Arc::ptr_eq(&metrics, &net.lock().unwrap().metrics));
+        assert_eq!(metrics.rx_count.count(), 1);
+    }
+
+    #[test]
+    fn test_quiesce_device() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = Arc::new(Mutex::new(default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        )));
+        let devices: [(Arc<Mutex<dyn VirtioDevice>>, &str); 2] = [
+            (block.clone(), "block"),
+            (Arc::new(Mutex::new(DummyDevice::new())), "dummy"),
+        ];
+        for (device, id) in devices {
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    device,
+                    &mut cmdline,
+                    id,
+                )
+                .unwrap();
+        }
+
+        assert!(matches!(
+            device_manager.quiesce_device(TYPE_BLOCK, "foo", Duration::from_secs(5)),
+            Err(MmioError::DeviceNotFound)
+        ));
+        assert!(matches!(
+            device_manager.quiesce_device(0, "dummy", Duration::from_secs(5)),
+            Err(MmioError::Quiesce(_, QuiesceError::NotSupported))
+        ));
+
+        // Submit a write of the whole disk whose completion is not processed yet.
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        {
+            let mut locked = block.lock().unwrap();
+            set_queue(&mut locked, 0, vq.create_queue());
+            locked.activate(mem.clone()).unwrap();
+            read_blk_req_descriptors(&vq);
+            mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, GuestAddress(vq.dtable[0].addr.get()))
+                .unwrap();
+            vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+            mem.write_slice(&[0xaa; 0x1000], data_addr).unwrap();
+            simulate_queue_event(&mut locked, None);
+        }
+
+        // Quiescing the device completes the write, reaches the backing file and leaves the
+        // device disabled.
+        device_manager
+            .quiesce_device(TYPE_BLOCK, "block", Duration::from_secs(5))
+            .unwrap();
+        assert!(block.lock().unwrap().in_flight_requests.is_empty());
+        assert!(!block.lock().unwrap().is_enabled());
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(std::fs::read(f.as_path()).unwrap(), [0xaa; 0x1000]);
+
+        // The same write, with other data, waits until the device is resumed.
+        mem.write_slice(&[0xbb; 0x1000], data_addr).unwrap();
+        vq.avail.ring[1].set(0);
+        vq.avail.idx.set(2);
+        simulate_queue_event(&mut block.lock().unwrap(), None);
+        assert_eq!(vq.used.idx.get(), 1);
+
+        device_manager
+            .set_device_enabled(TYPE_BLOCK, "block", true)
+            .unwrap();
+        device_manager
+            .quiesce_device(TYPE_BLOCK, "block", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(vq.used.idx.get(), 2);
+        assert_eq!(std::fs::read(f.as_path()).unwrap(), [0xbb; 0x1000]);
+    }
+
+    #[test]
+    fn test_register_too_many_devices() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mut device_manager = MMIODeviceManager::new();
+        for _i in crate::arch::IRQ_BASE..=crate::arch::IRQ_MAX {
+            device_manager
+                .register_virtio_test_device(
+                    vm.fd(),
+                    guest_mem.clone(),
+                    &mut resource_allocator,
+                    Arc::new(Mutex::new(DummyDevice::new())),
+                    &mut cmdline,
+                    "dummy1",
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            format!(
+                "{}",
+                device_manager
+                    .register_virtio_test_device(
+                        vm.fd(),
+                        guest_mem,
+                        &mut resource_allocator,
+                        Arc::new(Mutex::new(DummyDevice::new())),
+                        &mut cmdline,
+                        "dummy2"
+                    )
+                    .unwrap_err()
+            ),
+            "Failed to allocate requested resource: The requested resource is not available."
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_time_sources() {
+        let mut device_manager = MMIODeviceManager::new();
+        let host_secs = |offset: i64| {
+            i64::try_from(utils::time::get_time_ns(utils::time::ClockType::Real) / 1_000_000_000)
+                .unwrap()
+                + offset
+        };
+
+        let before = host_secs(0);
+        let time = device_manager.time_sources();
+        assert!((before..=host_secs(0)).contains(&time.wall_clock_secs));
+        assert_eq!(time.rtc_secs, None);
+        assert_eq!(time.boot_timer_us, None);
+        assert_eq!(time.vmclock_disruption_marker, None);
+
+        device_manager.set_time_offset(3600).unwrap();
+        let before = host_secs(3600);
+        let time = device_manager.time_sources();
+        assert!((before..=host_secs(3600)).contains(&time.wall_clock_secs));
+
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let start = TimestampUs::default();
+        let start_us = start.time_us;
+        device_manager
+            .register_mmio_boot_timer(&mut resource_allocator, BootTimer::new(start))
+            .unwrap();
+        let elapsed = TimestampUs::default().time_us - start_us;
+        let boot_timer_us = device_manager.time_sources().boot_timer_us.unwrap();
+        assert!(boot_timer_us >= elapsed);
 
-        #[cfg(target_arch = "x86_64")]
-        builder::setup_interrupt_controller(&mut vm).unwrap();
         #[cfg(target_arch = "aarch64")]
-        builder::setup_interrupt_controller(&mut vm, 1).unwrap();
+        {
+            let rtc = RTCDevice(vm_superio::Rtc::with_events(
+                &crate::devices::legacy::rtc_pl031::METRICS,
+            ));
+            device_manager
+                .register_mmio_rtc(&mut resource_allocator, rtc, None)
+                .unwrap();
+            // The RTC picks up the offset and ticks along with the guest wall clock.
+            let time = device_manager.time_sources();
+            let rtc_secs = i64::from(time.rtc_secs.unwrap());
+            assert!((rtc_secs - time.wall_clock_secs).abs() <= 1);
+        }
+    }
 
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_time_offset() {
         let mut device_manager = MMIODeviceManager::new();
         let mut resource_allocator = ResourceAllocator::new().unwrap();
-        let mut cmdline = kernel_cmdline::Cmdline::new(4096).unwrap();
+        let rtc = RTCDevice(vm_superio::Rtc::with_events(
+            &crate::devices::legacy::rtc_pl031::METRICS,
+        ));
+        device_manager
+            .register_mmio_rtc(&mut resource_allocator, rtc, None)
+            .unwrap();
+        let rtc_time = |device_manager: &MMIODeviceManager| {
+            device_manager
+                .get_device(DeviceType::Rtc, &DeviceType::Rtc.to_string())
+                .unwrap()
+                .lock()
+                .unwrap()
+                .rtc_device_mut()
+                .unwrap()
+                .time()
+        };
+
+        let time = rtc_time(&device_manager);
+        device_manager.set_time_offset(3600).unwrap();
+        assert_eq!(device_manager.time_offset(), 3600);
+        // Allow for the clock to tick between the two reads.
+        assert!((time + 3600..=time + 3601).contains(&rtc_time(&device_manager)));
+
+        // The guest clock can't go before the epoch.
+        assert!(matches!(
+            device_manager.set_time_offset(i64::MIN),
+            Err(MmioError::InvalidTimeOffset(i64::MIN))
+        ));
+        assert_eq!(device_manager.time_offset(), 3600);
+    }
+
+    #[test]
+    fn test_dummy_device() {
+        let dummy = DummyDevice::new();
+        assert_eq!(dummy.device_type(), 0);
+        assert_eq!(dummy.queues().len(), QUEUE_SIZES.len());
+    }
+
+    #[test]
+    fn test_device_info() {
+        let (vm, guest_mem, mut resource_allocator, mut cmdline) = test_setup();
+        let mem_clone = guest_mem.clone();
+        let mut device_manager = MMIODeviceManager::new();
         let dummy = Arc::new(Mutex::new(DummyDevice::new()));
 
         let type_id = dummy.lock().unwrap().device_type();
@@ -804,11 +2503,11 @@ mod tests {
         let mut device_manager = MMIODeviceManager::new();
         let mut resource_allocator = ResourceAllocator::new().unwrap();
         let device_info = device_manager
-            .allocate_mmio_resources(&mut resource_allocator, 0)
+            .allocate_mmio_resources(&mut resource_allocator, 0, "dummy")
             .unwrap();
         assert_eq!(device_info.irqs.len(), 0);
         let device_info = device_manager
-            .allocate_mmio_resources(&mut resource_allocator, 1)
+            .allocate_mmio_resources(&mut resource_allocator, 1, "dummy")
             .unwrap();
         assert_eq!(device_info.irqs[0], crate::arch::IRQ_BASE);
         assert_eq!(
@@ -817,7 +2516,8 @@ mod tests {
                 device_manager
                     .allocate_mmio_resources(
                         &mut resource_allocator,
-                        crate::arch::IRQ_MAX - crate::arch::IRQ_BASE + 1
+                        crate::arch::IRQ_MAX - crate::arch::IRQ_BASE + 1,
+                        "dummy",
                     )
                     .unwrap_err()
             ),
@@ -829,6 +2529,7 @@ mod tests {
             .allocate_mmio_resources(
                 &mut resource_allocator,
                 crate::arch::IRQ_MAX - crate::arch::IRQ_BASE - 1,
+                "dummy",
             )
             .unwrap();
         assert_eq!(device_info.irqs[16], crate::arch::IRQ_BASE + 17);
@@ -836,14 +2537,14 @@ mod tests {
             format!(
                 "{}",
                 device_manager
-                    .allocate_mmio_resources(&mut resource_allocator, 2)
+                    .allocate_mmio_resources(&mut resource_allocator, 2, "dummy")
                     .unwrap_err()
             ),
             "Failed to allocate requested resource: The requested resource is not available."
                 .to_string()
         );
         device_manager
-            .allocate_mmio_resources(&mut resource_allocator, 0)
+            .allocate_mmio_resources(&mut resource_allocator, 0, "dummy")
             .unwrap();
     }
 }
@@ -3,8 +3,10 @@
 
 //! Provides functionality for saving/restoring the MMIO device manager and its devices.
 
+use std::collections::btree_map::{BTreeMap, Entry};
 use std::fmt::{self, Debug};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use event_manager::{MutEventSubscriber, SubscriberOps};
 use kvm_ioctls::VmFd;
@@ -19,13 +21,26 @@ use super::resources::ResourceAllocator;
 #[cfg(target_arch = "aarch64")]
 use crate::arch::DeviceType;
 #[cfg(target_arch = "x86_64")]
+use crate::devices::acpi::hosttime::{
+    HostTime, HostTimeConstructorArgs, HostTimeError, HostTimeState,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::devices::acpi::vmclock::{VmClock, VmClockConstructorArgs, VmClockError, VmClockState};
+#[cfg(target_arch = "x86_64")]
 use crate::devices::acpi::vmgenid::{VMGenIDState, VMGenIdConstructorArgs, VmGenId, VmGenIdError};
+#[cfg(target_arch = "x86_64")]
+use crate::devices::legacy::I8042DeviceState;
+use crate::devices::legacy::{SerialDeviceState, SerialOutputBuffering, SerialRxTriggerLevel};
 use crate::devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use crate::devices::virtio::balloon::{Balloon, BalloonError};
 use crate::devices::virtio::block::device::Block;
 use crate::devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
 use crate::devices::virtio::block::BlockError;
 use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::fs::persist::{FsConstructorArgs, VhostUserFsState};
+use crate::devices::virtio::fs::{VhostUserFs, VhostUserFsError};
+use crate::devices::virtio::input::persist::{InputConstructorArgs, InputPersistError, InputState};
+use crate::devices::virtio::input::Input;
 use crate::devices::virtio::mmio::MmioTransport;
 use crate::devices::virtio::net::persist::{
     NetConstructorArgs, NetPersistError as NetError, NetState,
@@ -42,10 +57,11 @@ use crate::devices::virtio::vsock::persist::{
 use crate::devices::virtio::vsock::{
     Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError, TYPE_VSOCK,
 };
-use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_RNG};
+use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_FS, TYPE_INPUT, TYPE_NET, TYPE_RNG};
 use crate::mmds::data_store::MmdsVersion;
 use crate::resources::{ResourcesError, VmResources};
-use crate::snapshot::Persist;
+use crate::snapshot::crc::CRC64Writer;
+use crate::snapshot::{Persist, Snapshot, SnapshotError};
 use crate::vmm_config::mmds::MmdsConfigError;
 use crate::vstate::memory::GuestMemoryMmap;
 use crate::EventManager;
@@ -74,8 +90,16 @@ pub enum DevicePersistError {
     MmdsConfig(#[from] MmdsConfigError),
     /// Entropy: {0}
     Entropy(#[from] EntropyError),
+    /// Input: {0}
+    Input(#[from] InputPersistError),
+    /// Fs: {0}
+    Fs(#[from] VhostUserFsError),
     /// Resource misconfiguration: {0}. Is the snapshot file corrupted?
     ResourcesError(#[from] ResourcesError),
+    /// Failed to hash the state of {0}: {1}
+    StateHash(String, SnapshotError),
+    /// State hash mismatch for {0}: expected {1:#x}, computed {2:#x}. Is the snapshot file corrupted?
+    StateHashMismatch(String, u64, u64),
 }
 
 /// Holds the state of a balloon device connected to the MMIO space.
@@ -89,6 +113,8 @@ pub struct ConnectedBalloonState {
     pub transport_state: MmioTransportState,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
 }
 
 /// Holds the state of a virtio block device connected to the MMIO space.
@@ -102,6 +128,8 @@ pub struct ConnectedBlockState {
     pub transport_state: MmioTransportState,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
 }
 
 /// Holds the state of a net device connected to the MMIO space.
@@ -115,6 +143,8 @@ pub struct ConnectedNetState {
     pub transport_state: MmioTransportState,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
 }
 
 /// Holds the state of a vsock device connected to the MMIO space.
@@ -128,6 +158,8 @@ pub struct ConnectedVsockState {
     pub transport_state: MmioTransportState,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
 }
 
 /// Holds the state of an entropy device connected to the MMIO space.
@@ -141,6 +173,38 @@ pub struct ConnectedEntropyState {
     pub transport_state: MmioTransportState,
     /// VmmResources.
     pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
+}
+
+/// Holds the state of an input device connected to the MMIO space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectedInputState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: InputState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
+}
+
+/// Holds the state of an fs device connected to the MMIO space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectedFsState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: VhostUserFsState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub device_info: MMIODeviceInfo,
+    /// CRC64 of the serialized device and transport state, if it could be computed.
+    pub state_hash: Option<u64>,
 }
 
 /// Holds the state of a legacy device connected to the MMIO space.
@@ -178,6 +242,24 @@ impl From<MmdsVersion> for MmdsVersionState {
     }
 }
 
+/// Time it took to save or restore the state of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePersistTiming {
+    /// Id of the device.
+    pub device_id: String,
+    /// Duration of the save or restore.
+    pub duration: Duration,
+}
+
+/// A device left out of the snapshot, which is missing once restored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedDevice {
+    /// Id of the device.
+    pub device_id: String,
+    /// Why the device was skipped.
+    pub reason: String,
+}
+
 /// Holds the device states.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct DeviceStates {
@@ -196,6 +278,282 @@ pub struct DeviceStates {
     pub mmds_version: Option<MmdsVersionState>,
     /// Entropy device state.
     pub entropy_device: Option<ConnectedEntropyState>,
+    /// Input device states.
+    pub input_devices: Vec<ConnectedInputState>,
+    /// Fs device states.
+    pub fs_devices: Vec<ConnectedFsState>,
+    /// Offset, in seconds, of the guest's wall-clock time from the host's.
+    pub time_offset: i64,
+    /// RX trigger level of the serial console.
+    pub serial_rx_trigger_level: SerialRxTriggerLevel,
+    /// Whether the output of the serial console is forwarded byte by byte or one line at a time.
+    pub serial_output_buffering: SerialOutputBuffering,
+    /// Registers and buffered input of the serial console. Missing from older snapshots.
+    pub serial_state: Option<SerialDeviceState>,
+    /// Registers and buffered input of the i8042 device. Missing from older snapshots.
+    #[cfg(target_arch = "x86_64")]
+    pub i8042_state: Option<I8042DeviceState>,
+    /// Devices that are not part of the snapshot.
+    pub skipped_devices: Vec<SkippedDevice>,
+    /// Time it took to save each device. Not persisted.
+    #[serde(skip)]
+    pub save_timings: Vec<DevicePersistTiming>,
+}
+
+/// Reads the monotonic clock used to time the save and restore of each device.
+#[cfg(not(test))]
+fn persist_clock() -> Duration {
+    Duration::from_nanos(utils::time::get_time_ns(utils::time::ClockType::Monotonic))
+}
+
+/// Step by which the fake clock advances on every read.
+#[cfg(test)]
+const FAKE_CLOCK_STEP: Duration = Duration::from_millis(1);
+
+#[cfg(test)]
+thread_local! {
+    static FAKE_CLOCK: std::cell::Cell<Duration> = const { std::cell::Cell::new(Duration::ZERO) };
+}
+
+/// Fake clock which advances by `FAKE_CLOCK_STEP` on every read, so that tests can check the
+/// exact timings.
+#[cfg(test)]
+fn persist_clock() -> Duration {
+    FAKE_CLOCK.with(|clock| {
+        let now = clock.get() + FAKE_CLOCK_STEP;
+        clock.set(now);
+        now
+    })
+}
+
+/// Computes the CRC64 of the serialized device and transport state of a device, the same way the
+/// snapshot file checksum is computed.
+fn device_state_hash<S: Serialize + Debug>(
+    device_state: &S,
+    transport_state: &MmioTransportState,
+) -> Result<u64, SnapshotError> {
+    let mut crc_writer = CRC64Writer::new(std::io::sink());
+    Snapshot::serialize(&mut crc_writer, &(device_state, transport_state))?;
+    Ok(crc_writer.checksum())
+}
+
+/// Hashes the state of a device being saved. A state that cannot be serialized fails the
+/// snapshot creation right after, so the error is only logged here.
+fn save_state_hash<S: Serialize + Debug>(
+    id: &str,
+    device_state: &S,
+    transport_state: &MmioTransportState,
+) -> Option<u64> {
+    device_state_hash(device_state, transport_state)
+        .map_err(|err| error!("Failed to hash the state of device {id}: {err}"))
+        .ok()
+}
+
+fn check_state_hash<S: Serialize + Debug>(
+    id: &str,
+    expected: Option<u64>,
+    device_state: &S,
+    transport_state: &MmioTransportState,
+) -> Result<(), DevicePersistError> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = device_state_hash(device_state, transport_state)
+        .map_err(|err| DevicePersistError::StateHash(id.to_string(), err))?;
+    if expected != actual {
+        return Err(DevicePersistError::StateHashMismatch(
+            id.to_string(),
+            expected,
+            actual,
+        ));
+    }
+    Ok(())
+}
+
+/// Finds a GSI that is owned by more than one device.
+///
+/// Returns the GSI along with the first two of its owners.
+pub fn find_gsi_conflict(
+    owners: impl IntoIterator<Item = (u32, String)>,
+) -> Option<(u32, String, String)> {
+    let mut seen = BTreeMap::new();
+    for (gsi, owner) in owners {
+        match seen.entry(gsi) {
+            Entry::Occupied(first) => return Some((gsi, first.remove(), owner)),
+            Entry::Vacant(entry) => {
+                entry.insert(owner);
+            }
+        }
+    }
+    None
+}
+
+impl DeviceStates {
+    /// Returns the GSIs recorded for the devices, along with the device owning each of them.
+    pub fn gsi_owners(&self) -> Vec<(u32, String)> {
+        let legacy = self
+            .legacy_devices
+            .iter()
+            .map(|state| (format!("{:?}", state.type_), &state.device_info));
+        let virtio = self
+            .block_devices
+            .iter()
+            .map(|state| (&state.device_id, &state.device_info))
+            .chain(
+                self.net_devices
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .chain(
+                self.vsock_device
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .chain(
+                self.balloon_device
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .chain(
+                self.entropy_device
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .chain(
+                self.input_devices
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .chain(
+                self.fs_devices
+                    .iter()
+                    .map(|state| (&state.device_id, &state.device_info)),
+            )
+            .map(|(id, info)| (id.clone(), info));
+
+        legacy
+            .chain(virtio)
+            .flat_map(|(owner, info)| info.irqs.iter().map(move |irq| (*irq, owner.clone())))
+            .collect()
+    }
+
+    /// Recomputes the device state hashes and compares them with the persisted ones.
+    pub fn verify_hashes(&self) -> Result<(), DevicePersistError> {
+        for state in &self.block_devices {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        for state in &self.net_devices {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        if let Some(state) = &self.vsock_device {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        if let Some(state) = &self.balloon_device {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        if let Some(state) = &self.entropy_device {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        for state in &self.input_devices {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        for state in &self.fs_devices {
+            check_state_hash(
+                &state.device_id,
+                state.state_hash,
+                &state.device_state,
+                &state.transport_state,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the persisted MMIO ranges are aligned, within the MMIO window, and do not
+    /// overlap.
+    pub fn verify_device_infos(&self) -> Result<(), ResourcesError> {
+        let mut infos: Vec<(String, &MMIODeviceInfo)> = Vec::new();
+        #[cfg(target_arch = "aarch64")]
+        infos.extend(
+            self.legacy_devices
+                .iter()
+                .map(|state| (format!("{:?}", state.type_), &state.device_info)),
+        );
+        infos.extend(
+            self.block_devices
+                .iter()
+                .map(|state| (state.device_id.clone(), &state.device_info)),
+        );
+        infos.extend(
+            self.net_devices
+                .iter()
+                .map(|state| (state.device_id.clone(), &state.device_info)),
+        );
+        if let Some(state) = &self.vsock_device {
+            infos.push((state.device_id.clone(), &state.device_info));
+        }
+        if let Some(state) = &self.balloon_device {
+            infos.push((state.device_id.clone(), &state.device_info));
+        }
+        if let Some(state) = &self.entropy_device {
+            infos.push((state.device_id.clone(), &state.device_info));
+        }
+        infos.extend(
+            self.input_devices
+                .iter()
+                .map(|state| (state.device_id.clone(), &state.device_info)),
+        );
+        infos.extend(
+            self.fs_devices
+                .iter()
+                .map(|state| (state.device_id.clone(), &state.device_info)),
+        );
+
+        for (id, info) in &infos {
+            info.validate(id)?;
+        }
+        infos.sort_by_key(|(_, info)| info.addr);
+        for pair in infos.windows(2) {
+            let ((prev_id, prev), (id, info)) = (&pair[0], &pair[1]);
+            // Validated ranges cannot overflow.
+            if prev.addr + prev.len > info.addr {
+                return Err(ResourcesError::OverlappingMmioRanges(
+                    prev_id.clone(),
+                    id.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A type used to extract the concrete `Arc<Mutex<T>>` for each of the device
@@ -207,6 +565,17 @@ pub enum SharedDeviceType {
     Balloon(Arc<Mutex<Balloon>>),
     Vsock(Arc<Mutex<Vsock<VsockUnixBackend>>>),
     Entropy(Arc<Mutex<Entropy>>),
+    Input(Arc<Mutex<Input>>),
+    Fs(Arc<Mutex<VhostUserFs>>),
+}
+
+/// How the guest memory of a microVM restored from a snapshot is populated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreSource {
+    /// Guest memory is loaded from a file.
+    File,
+    /// Guest memory is served through UFFD by a separate process.
+    Uffd,
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
@@ -216,6 +585,7 @@ pub struct MMIODevManagerConstructorArgs<'a> {
     pub resource_allocator: &'a mut ResourceAllocator,
     pub vm_resources: &'a mut VmResources,
     pub instance_id: &'a str,
+    pub restore_source: RestoreSource,
 }
 impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -226,6 +596,7 @@ impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
             .field("for_each_restored_device", &"?")
             .field("vm_resources", &self.vm_resources)
             .field("instance_id", &self.instance_id)
+            .field("restore_source", &self.restore_source)
             .finish()
     }
 }
@@ -234,6 +605,19 @@ impl fmt::Debug for MMIODevManagerConstructorArgs<'_> {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ACPIDeviceManagerState {
     vmgenid: Option<VMGenIDState>,
+    vmclock: Option<VmClockState>,
+    hosttime: Option<HostTimeState>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl ACPIDeviceManagerState {
+    /// Returns the GSIs recorded for the ACPI devices, along with the device owning each of them.
+    pub fn gsi_owners(&self) -> Vec<(u32, String)> {
+        self.vmgenid
+            .iter()
+            .map(|state| (state.gsi, "VMGenID".to_string()))
+            .collect()
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -250,6 +634,10 @@ pub enum ACPIDeviceManagerRestoreError {
     Interrupt(#[from] kvm_ioctls::Error),
     /// Could not create VMGenID device: {0}
     VMGenID(#[from] VmGenIdError),
+    /// Could not create VMClock device: {0}
+    VmClock(#[from] VmClockError),
+    /// Could not create host time device: {0}
+    HostTime(#[from] HostTimeError),
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -261,6 +649,11 @@ impl<'a> Persist<'a> for ACPIDeviceManager {
     fn save(&self) -> Self::State {
         ACPIDeviceManagerState {
             vmgenid: self.vmgenid.as_ref().map(|dev| dev.save()),
+            vmclock: self.vmclock.as_ref().map(|dev| dev.save()),
+            hosttime: self
+                .hosttime
+                .as_ref()
+                .map(|dev| dev.lock().expect("Poisoned lock").save()),
         }
     }
 
@@ -279,10 +672,66 @@ impl<'a> Persist<'a> for ACPIDeviceManager {
             )?;
             dev_manager.attach_vmgenid(vmgenid, constructor_args.vm)?;
         }
+        if let Some(vmclock_args) = &state.vmclock {
+            let vmclock = VmClock::restore(
+                VmClockConstructorArgs {
+                    resource_allocator: constructor_args.resource_allocator,
+                },
+                vmclock_args,
+            )?;
+            dev_manager.attach_vmclock(vmclock);
+        }
+        if let Some(hosttime_args) = &state.hosttime {
+            let hosttime = HostTime::restore(
+                HostTimeConstructorArgs {
+                    mem: constructor_args.mem,
+                    resource_allocator: constructor_args.resource_allocator,
+                },
+                hosttime_args,
+            )?;
+            dev_manager.attach_hosttime(Arc::new(Mutex::new(hosttime)));
+        }
         Ok(dev_manager)
     }
 }
 
+impl MMIODeviceManager {
+    /// Prepares the devices for being snapshotted.
+    ///
+    /// Block devices complete the requests they have in flight. This fails for vhost-user
+    /// devices whose backend still processes requests.
+    pub fn prepare_save(&self) -> Result<(), DevicePersistError> {
+        self.for_each_virtio_device(|virtio_type, _, _, device| {
+            let mut locked_device = device.lock().expect("Poisoned lock");
+            match virtio_type {
+                TYPE_BLOCK => {
+                    let block = locked_device.as_mut_any().downcast_mut::<Block>().unwrap();
+                    block.prepare_save()?;
+                }
+                TYPE_FS => {
+                    let fs = locked_device
+                        .as_mut_any()
+                        .downcast_mut::<VhostUserFs>()
+                        .unwrap();
+                    fs.prepare_save()?;
+                }
+                _ => (),
+            }
+            Ok(())
+        })
+    }
+
+    /// Estimates the number of bytes the device states take in the microVM state file.
+    ///
+    /// The estimate is the sum of the serialized sizes of the per-device states.
+    pub fn estimate_snapshot_size(&self) -> usize {
+        let states = self.save();
+        let size =
+            bincode::serialized_size(&states).expect("Device state serialization should not fail");
+        usize::try_from(size).unwrap_or(usize::MAX)
+    }
+}
+
 impl<'a> Persist<'a> for MMIODeviceManager {
     type State = DeviceStates;
     type ConstructorArgs = MMIODevManagerConstructorArgs<'a>;
@@ -307,6 +756,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 }
             }
 
+            let save_start = persist_clock();
             let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
 
             let mmio_transport = locked_bus_dev
@@ -323,30 +773,27 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         .downcast_ref::<Balloon>()
                         .unwrap()
                         .save();
+                    let state_hash = save_state_hash(devid, &balloon_state, &transport_state);
                     states.balloon_device = Some(ConnectedBalloonState {
                         device_id: devid.clone(),
                         device_state: balloon_state,
                         transport_state,
                         device_info: device_info.clone(),
+                        state_hash,
                     });
                 }
                 // Both virtio-block and vhost-user-block share same device type.
                 TYPE_BLOCK => {
-                    let block = locked_device.as_mut_any().downcast_mut::<Block>().unwrap();
-                    if block.is_vhost_user() {
-                        warn!(
-                            "Skipping vhost-user-block device. VhostUserBlock does not support \
-                             snapshotting yet"
-                        );
-                    } else {
-                        block.prepare_save();
-                        states.block_devices.push(ConnectedBlockState {
-                            device_id: devid.clone(),
-                            device_state: block.save(),
-                            transport_state,
-                            device_info: device_info.clone(),
-                        })
-                    }
+                    let block = locked_device.as_any().downcast_ref::<Block>().unwrap();
+                    let device_state = block.save();
+                    let state_hash = save_state_hash(devid, &device_state, &transport_state);
+                    states.block_devices.push(ConnectedBlockState {
+                        device_id: devid.clone(),
+                        device_state,
+                        transport_state,
+                        device_info: device_info.clone(),
+                        state_hash,
+                    })
                 }
                 TYPE_NET => {
                     let net = locked_device.as_any().downcast_ref::<Net>().unwrap();
@@ -357,11 +804,14 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                             Some(mmds_ns.mmds.lock().expect("Poisoned lock").version().into());
                     }
 
+                    let device_state = net.save();
+                    let state_hash = save_state_hash(devid, &device_state, &transport_state);
                     states.net_devices.push(ConnectedNetState {
                         device_id: devid.clone(),
-                        device_state: net.save(),
+                        device_state,
                         transport_state,
                         device_info: device_info.clone(),
+                        state_hash,
                     });
                 }
                 TYPE_VSOCK => {
@@ -384,11 +834,13 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         });
                     }
 
+                    let state_hash = save_state_hash(devid, &vsock_state, &transport_state);
                     states.vsock_device = Some(ConnectedVsockState {
                         device_id: devid.clone(),
                         device_state: vsock_state,
                         transport_state,
                         device_info: device_info.clone(),
+                        state_hash,
                     });
                 }
                 TYPE_RNG => {
@@ -397,18 +849,55 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         .downcast_mut::<Entropy>()
                         .unwrap();
 
+                    let device_state = entropy.save();
+                    let state_hash = save_state_hash(devid, &device_state, &transport_state);
                     states.entropy_device = Some(ConnectedEntropyState {
                         device_id: devid.clone(),
-                        device_state: entropy.save(),
+                        device_state,
                         transport_state,
                         device_info: device_info.clone(),
+                        state_hash,
+                    });
+                }
+                TYPE_INPUT => {
+                    let input = locked_device.as_any().downcast_ref::<Input>().unwrap();
+
+                    let device_state = input.save();
+                    let state_hash = save_state_hash(devid, &device_state, &transport_state);
+                    states.input_devices.push(ConnectedInputState {
+                        device_id: devid.clone(),
+                        device_state,
+                        transport_state,
+                        device_info: device_info.clone(),
+                        state_hash,
+                    });
+                }
+                TYPE_FS => {
+                    let fs = locked_device
+                        .as_any()
+                        .downcast_ref::<VhostUserFs>()
+                        .unwrap();
+
+                    let device_state = fs.save();
+                    let state_hash = save_state_hash(devid, &device_state, &transport_state);
+                    states.fs_devices.push(ConnectedFsState {
+                        device_id: devid.clone(),
+                        device_state,
+                        transport_state,
+                        device_info: device_info.clone(),
+                        state_hash,
                     });
                 }
                 _ => unreachable!(),
             };
 
+            states.save_timings.push(DevicePersistTiming {
+                device_id: devid.clone(),
+                duration: persist_clock().saturating_sub(save_start),
+            });
             Ok(())
         });
+        states.time_offset = self.time_offset();
         states
     }
 
@@ -416,6 +905,10 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
+        // Catch corrupted device states before any device gets created or activated.
+        state.verify_hashes()?;
+        state.verify_device_infos()?;
+
         let mut dev_manager = MMIODeviceManager::new();
         let mem = constructor_args.mem;
         let vm = constructor_args.vm;
@@ -471,13 +964,17 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             }
         }
 
+        dev_manager.set_time_offset(state.time_offset)?;
+
+        let mut restore_timings = Vec::new();
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
                                   is_vhost_user: bool,
                                   as_subscriber: Arc<Mutex<dyn MutEventSubscriber>>,
                                   id: &String,
                                   state: &MmioTransportState,
                                   device_info: &MMIODeviceInfo,
-                                  event_manager: &mut EventManager|
+                                  event_manager: &mut EventManager,
+                                  restore_start: Duration|
          -> Result<(), Self::Error> {
             let restore_args = MmioTransportConstructorArgs {
                 mem: mem.clone(),
@@ -511,10 +1008,15 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             dev_manager.register_mmio_virtio(vm, id.clone(), mmio_transport, device_info)?;
 
             event_manager.add_subscriber(as_subscriber);
+            restore_timings.push(DevicePersistTiming {
+                device_id: id.clone(),
+                duration: persist_clock().saturating_sub(restore_start),
+            });
             Ok(())
         };
 
         if let Some(balloon_state) = &state.balloon_device {
+            let restore_start = persist_clock();
             let device = Arc::new(Mutex::new(Balloon::restore(
                 BalloonConstructorArgs { mem: mem.clone() },
                 &balloon_state.device_state,
@@ -532,10 +1034,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 &balloon_state.transport_state,
                 &balloon_state.device_info,
                 constructor_args.event_manager,
+                restore_start,
             )?;
         }
 
         for block_state in &state.block_devices {
+            let restore_start = persist_clock();
             let device = Arc::new(Mutex::new(Block::restore(
                 BlockConstructorArgs { mem: mem.clone() },
                 &block_state.device_state,
@@ -547,12 +1051,13 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                false,
+                matches!(block_state.device_state, BlockState::VhostUser(_)),
                 device,
                 &block_state.device_id,
                 &block_state.transport_state,
                 &block_state.device_info,
                 constructor_args.event_manager,
+                restore_start,
             )?;
         }
 
@@ -573,6 +1078,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         }
 
         for net_state in &state.net_devices {
+            let restore_start = persist_clock();
             let device = Arc::new(Mutex::new(Net::restore(
                 NetConstructorArgs {
                     mem: mem.clone(),
@@ -598,10 +1104,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 &net_state.transport_state,
                 &net_state.device_info,
                 constructor_args.event_manager,
+                restore_start,
             )?;
         }
 
         if let Some(vsock_state) = &state.vsock_device {
+            let restore_start = persist_clock();
             let ctor_args = VsockUdsConstructorArgs {
                 cid: vsock_state.device_state.frontend.cid,
             };
@@ -626,10 +1134,12 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 &vsock_state.transport_state,
                 &vsock_state.device_info,
                 constructor_args.event_manager,
+                restore_start,
             )?;
         }
 
         if let Some(entropy_state) = &state.entropy_device {
+            let restore_start = persist_clock();
             let ctor_args = EntropyConstructorArgs::new(mem.clone());
 
             let device = Arc::new(Mutex::new(Entropy::restore(
@@ -649,26 +1159,93 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 &entropy_state.transport_state,
                 &entropy_state.device_info,
                 constructor_args.event_manager,
+                restore_start,
             )?;
         }
 
+        for input_state in &state.input_devices {
+            let restore_start = persist_clock();
+            let ctor_args = InputConstructorArgs::new(mem.clone());
+
+            let device = Arc::new(Mutex::new(Input::restore(
+                ctor_args,
+                &input_state.device_state,
+            )?));
+
+            constructor_args
+                .vm_resources
+                .update_from_restored_device(SharedDeviceType::Input(device.clone()))?;
+
+            restore_helper(
+                device.clone(),
+                false,
+                device,
+                &input_state.device_id,
+                &input_state.transport_state,
+                &input_state.device_info,
+                constructor_args.event_manager,
+                restore_start,
+            )?;
+        }
+
+        for fs_state in &state.fs_devices {
+            let restore_start = persist_clock();
+            let device = Arc::new(Mutex::new(VhostUserFs::restore(
+                FsConstructorArgs { mem: mem.clone() },
+                &fs_state.device_state,
+            )?));
+
+            constructor_args
+                .vm_resources
+                .update_from_restored_device(SharedDeviceType::Fs(device.clone()))?;
+
+            restore_helper(
+                device.clone(),
+                true,
+                device,
+                &fs_state.device_id,
+                &fs_state.transport_state,
+                &fs_state.device_info,
+                constructor_args.event_manager,
+                restore_start,
+            )?;
+        }
+
+        for skipped in &state.skipped_devices {
+            warn!(
+                "Device {} is missing from the snapshot: {}",
+                skipped.device_id, skipped.reason
+            );
+        }
+        dev_manager.restore_timings = restore_timings;
+        dev_manager.skipped_devices = state.skipped_devices.clone();
+        dev_manager.restore_source = Some(constructor_args.restore_source);
         Ok(dev_manager)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+
     use utils::tempfile::TempFile;
 
     use super::*;
     use crate::builder::tests::*;
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+    use crate::devices::virtio::input::InputKind;
     use crate::resources::VmmConfig;
-    use crate::snapshot::Snapshot;
+    use crate::utilities::test_utils::create_tmp_socket;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::entropy::EntropyDeviceConfig;
+    use crate::vmm_config::input::InputDeviceConfig;
+    use crate::vmm_config::mmds::MmdsConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
     use crate::vmm_config::vsock::VsockDeviceConfig;
+    #[cfg(target_arch = "x86_64")]
+    use crate::vstate::memory::Bytes;
 
     impl PartialEq for ConnectedBalloonState {
         fn eq(&self, other: &ConnectedBalloonState) -> bool {
@@ -773,6 +1350,8 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                tx_delay: None,
+                flow_stats: false,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -787,13 +1366,31 @@ mod tests {
                 vsock_id: Some(vsock_dev_id.to_string()),
                 guest_cid: 3,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                max_connections: None,
+                log_port: None,
+                quiesce_port: None,
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
             // Add an entropy device.
             let entropy_config = EntropyDeviceConfig::default();
             insert_entropy_device(&mut vmm, &mut cmdline, &mut event_manager, entropy_config);
-
-            Snapshot::serialize(&mut buf.as_mut_slice(), &vmm.mmio_device_manager.save()).unwrap();
+            // Add an input device.
+            let input_config = InputDeviceConfig {
+                input_id: String::from("keyboard"),
+                kind: InputKind::Keyboard,
+            };
+            insert_input_device(&mut vmm, &mut cmdline, &mut event_manager, input_config);
+            // A booted microVM was not restored from anything.
+            assert_eq!(vmm.mmio_device_manager.restore_source(), None);
+
+            let device_states = vmm.mmio_device_manager.save();
+            // One timing per virtio device.
+            assert_eq!(device_states.save_timings.len(), 6);
+            assert!(device_states
+                .save_timings
+                .iter()
+                .all(|timing| timing.duration == FAKE_CLOCK_STEP));
+            Snapshot::serialize(&mut buf.as_mut_slice(), &device_states).unwrap();
 
             // We only want to keep the device map from the original MmioDeviceManager.
             vmm.mmio_device_manager.soft_clone()
@@ -811,6 +1408,7 @@ mod tests {
             resource_allocator: &mut resource_allocator,
             vm_resources,
             instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
@@ -832,6 +1430,15 @@ mod tests {
       "path_on_host": "{}",
       "rate_limiter": null,
       "io_engine": "Sync",
+      "interrupt_moderation": null,
+      "io_retry": null,
+      "geometry": null,
+      "discard": false,
+      "max_chain_len": 256,
+      "request_record_path": null,
+      "restore_notification_window_ms": null,
+      "write_batch": null,
+      "fd_on_host": null,
       "socket": null
     }}
   ],
@@ -863,16 +1470,28 @@ mod tests {
       "host_dev_name": "hostname",
       "guest_mac": null,
       "rx_rate_limiter": null,
-      "tx_rate_limiter": null
+      "tx_rate_limiter": null,
+      "tx_delay": null,
+      "flow_stats": false
     }}
   ],
   "vsock": {{
     "guest_cid": 3,
-    "uds_path": "{}"
+    "uds_path": "{}",
+    "max_connections": 1023,
+    "log_port": null,
+    "quiesce_port": null
   }},
   "entropy": {{
     "rate_limiter": null
-  }}
+  }},
+  "fs": [],
+  "input": [
+    {{
+      "input_id": "keyboard",
+      "kind": "Keyboard"
+    }}
+  ]
 }}"#,
             _block_files.last().unwrap().as_path().to_str().unwrap(),
             tmp_sock_file.as_path().to_str().unwrap()
@@ -891,9 +1510,550 @@ mod tests {
         assert_eq!(device_states.mmds_version.unwrap(), MmdsVersion::V2.into());
 
         assert_eq!(restored_dev_manager, original_mmio_device_manager);
+        assert_eq!(
+            restored_dev_manager.restore_source(),
+            Some(RestoreSource::File)
+        );
+        assert_eq!(restored_dev_manager.restore_timings().len(), 6);
+        assert!(restored_dev_manager
+            .restore_timings()
+            .iter()
+            .all(|timing| timing.duration == FAKE_CLOCK_STEP));
         assert_eq!(
             expected_vm_resources,
             serde_json::to_string_pretty(&VmmConfig::from(&*vm_resources)).unwrap()
         );
     }
+
+    /// Serves the vhost-user connections of block devices on `listener`, offering `features`.
+    ///
+    /// Only the messages exchanged while connecting to the backend are answered.
+    fn spawn_vhost_user_block_backend(listener: UnixListener, features: u64) {
+        // VHOST_USER_GET_FEATURES
+        const GET_FEATURES: u32 = 1;
+        // Version 1 of the protocol, for a reply.
+        const REPLY_FLAGS: u32 = 0x1 | 0x4;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    let mut hdr = [0u8; 12];
+                    while stream.read_exact(&mut hdr).is_ok() {
+                        let request = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
+                        let size = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
+                        let mut body = vec![0u8; usize::try_from(size).unwrap()];
+                        stream.read_exact(&mut body).unwrap();
+                        if request == GET_FEATURES {
+                            let mut reply = Vec::new();
+                            reply.extend_from_slice(&GET_FEATURES.to_le_bytes());
+                            reply.extend_from_slice(&REPLY_FLAGS.to_le_bytes());
+                            reply.extend_from_slice(&8u32.to_le_bytes());
+                            reply.extend_from_slice(&features.to_le_bytes());
+                            stream.write_all(&reply).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_skipped_devices() {
+        let mut buf = vec![0; 16384];
+        let vmm = default_vmm();
+        let mut device_states = vmm.mmio_device_manager.save();
+        assert!(device_states.skipped_devices.is_empty());
+        // A device that could not be saved, like a vhost-user-fs device.
+        device_states.skipped_devices.push(SkippedDevice {
+            device_id: "fs0".to_string(),
+            reason: "vhost-user-fs does not support snapshotting yet".to_string(),
+        });
+        Snapshot::serialize(&mut buf.as_mut_slice(), &device_states).unwrap();
+        let device_states: DeviceStates = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+        assert_eq!(
+            restored_dev_manager.skipped_devices(),
+            &[SkippedDevice {
+                device_id: "fs0".to_string(),
+                reason: "vhost-user-fs does not support snapshotting yet".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_gsi_conflict() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let balloon_cfg = BalloonDeviceConfig {
+            amount_mib: 0,
+            deflate_on_oom: false,
+            stats_polling_interval_s: 0,
+        };
+        insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
+        let entropy_config = EntropyDeviceConfig::default();
+        insert_entropy_device(&mut vmm, &mut cmdline, &mut event_manager, entropy_config);
+
+        let mut device_states = vmm.mmio_device_manager.save();
+        let gsi_owners = device_states.gsi_owners();
+        assert_eq!(gsi_owners.len(), 2);
+        assert_eq!(find_gsi_conflict(gsi_owners), None);
+
+        // Force the entropy device onto the GSI of the balloon.
+        let balloon_gsi = device_states
+            .balloon_device
+            .as_ref()
+            .unwrap()
+            .device_info
+            .irqs[0];
+        device_states
+            .entropy_device
+            .as_mut()
+            .unwrap()
+            .device_info
+            .irqs = vec![balloon_gsi];
+        assert_eq!(
+            find_gsi_conflict(device_states.gsi_owners()),
+            Some((balloon_gsi, "balloon".to_string(), "rng".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_device_manager_persistence_vhost_user_block() {
+        let mut buf = vec![0; 16384];
+        let (_tmp_dir, socket_path) = create_tmp_socket();
+        std::fs::remove_file(&socket_path).unwrap();
+        spawn_vhost_user_block_backend(
+            UnixListener::bind(&socket_path).unwrap(),
+            1 << VIRTIO_F_VERSION_1,
+        );
+
+        let original_mmio_device_manager = {
+            let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+            let mut vmm = default_vmm();
+            let mut cmdline = default_kernel_cmdline();
+            insert_vhost_user_block_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                "vhost_user_drive",
+                &socket_path,
+            );
+
+            // The vhost-user block device is saved rather than skipped.
+            let device_states = vmm.mmio_device_manager.save();
+            assert_eq!(device_states.block_devices.len(), 1);
+            assert!(matches!(
+                device_states.block_devices[0].device_state,
+                BlockState::VhostUser(_)
+            ));
+            Snapshot::serialize(&mut buf.as_mut_slice(), &device_states).unwrap();
+
+            vmm.mmio_device_manager.soft_clone()
+        };
+        let device_states: DeviceStates = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+
+        // The restored device connects to the backend again.
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vmm = default_vmm();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+        assert_eq!(restored_dev_manager, original_mmio_device_manager);
+        let block = vm_resources.block.devices[0].lock().unwrap();
+        assert!(block.is_vhost_user());
+        assert_eq!(block.id(), "vhost_user_drive");
+        assert_eq!(block.avail_features(), 1 << VIRTIO_F_VERSION_1,);
+        drop(block);
+
+        // Without a backend to reconnect to, restoring fails.
+        std::fs::remove_file(&socket_path).unwrap();
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vmm = default_vmm();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        let err = MMIODeviceManager::restore(restore_args, &device_states).unwrap_err();
+        assert!(matches!(err, DevicePersistError::Block(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_mmds_interfaces_persistence() {
+        let mut buf = vec![0; 16384];
+        let net_config = |iface_id: &str, host_dev_name: &str| NetworkInterfaceConfig {
+            iface_id: iface_id.to_string(),
+            host_dev_name: host_dev_name.to_string(),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
+        };
+        {
+            let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+            let mut vmm = default_vmm();
+            let mut cmdline = default_kernel_cmdline();
+            // Only the second interface intercepts the MMDS requests.
+            insert_net_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                net_config("plain", "hostname0"),
+            );
+            insert_net_device_with_mmds(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                net_config("with_mmds", "hostname1"),
+                MmdsVersion::V2,
+            );
+
+            let device_states = vmm.mmio_device_manager.save();
+            Snapshot::serialize(&mut buf.as_mut_slice(), &device_states).unwrap();
+        }
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vmm = default_vmm();
+        let device_states: DeviceStates = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+
+        let intercepting: Vec<_> = vm_resources
+            .net_builder
+            .iter()
+            .map(|net| {
+                let net = net.lock().unwrap();
+                (net.id().clone(), net.mmds_ns().is_some())
+            })
+            .collect();
+        assert_eq!(intercepting.len(), 2);
+        assert!(intercepting.contains(&("plain".to_string(), false)));
+        assert!(intercepting.contains(&("with_mmds".to_string(), true)));
+        let vmm_config = serde_json::to_value(VmmConfig::from(&*vm_resources)).unwrap();
+        let mmds_config: MmdsConfig =
+            serde_json::from_value(vmm_config["mmds-config"].clone()).unwrap();
+        assert_eq!(
+            mmds_config.network_interfaces,
+            vec!["with_mmds".to_string()]
+        );
+        assert_eq!(mmds_config.version, MmdsVersion::V2);
+    }
+
+    #[test]
+    fn test_device_state_hash_mismatch() {
+        let mut buf = vec![0; 16384];
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        {
+            let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+            let mut vmm = default_vmm();
+            let mut cmdline = default_kernel_cmdline();
+
+            let network_interface = NetworkInterfaceConfig {
+                iface_id: String::from("netif"),
+                host_dev_name: String::from("hostname"),
+                guest_mac: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                tx_delay: None,
+                flow_stats: false,
+            };
+            insert_net_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                network_interface,
+            );
+
+            let device_states = vmm.mmio_device_manager.save();
+            assert!(device_states.net_devices[0].state_hash.is_some());
+            device_states.verify_hashes().unwrap();
+            Snapshot::serialize(&mut buf.as_mut_slice(), &device_states).unwrap();
+        }
+
+        // Flip a byte of the tap name inside the serialized net device state.
+        let pos = buf
+            .windows(b"hostname".len())
+            .position(|window| window == b"hostname")
+            .unwrap();
+        buf[pos + b"hostname".len() - 1] ^= 0x1;
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let vmm = default_vmm();
+        let device_states: DeviceStates = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        let err = MMIODeviceManager::restore(restore_args, &device_states).unwrap_err();
+        assert!(
+            matches!(err, DevicePersistError::StateHashMismatch(ref id, _, _) if id == "netif"),
+            "{:?}",
+            err
+        );
+        // Nothing was restored into the VM resources.
+        assert!(vm_resources.net_builder.is_empty());
+    }
+
+    #[test]
+    fn test_estimate_snapshot_size() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        let empty_size = vmm.mmio_device_manager.estimate_snapshot_size();
+
+        let network_interface = NetworkInterfaceConfig {
+            iface_id: String::from("netif"),
+            host_dev_name: String::from("hostname"),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
+        };
+        insert_net_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            network_interface,
+        );
+
+        let estimate = vmm.mmio_device_manager.estimate_snapshot_size();
+        let net_state = &vmm.mmio_device_manager.save().net_devices[0];
+        let net_size = usize::try_from(bincode::serialized_size(net_state).unwrap()).unwrap();
+        assert!(net_size > 0);
+        assert_eq!(estimate, empty_size + net_size);
+
+        let mut buf = vec![0; 16384];
+        let mut writer = buf.as_mut_slice();
+        Snapshot::serialize(&mut writer, &vmm.mmio_device_manager.save()).unwrap();
+        let remaining = writer.len();
+        assert_eq!(estimate, buf.len() - remaining);
+    }
+
+    #[test]
+    fn test_misaligned_device_info() {
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let mut device_states = {
+            let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+            let mut vmm = default_vmm();
+            let mut cmdline = default_kernel_cmdline();
+
+            let network_interface = NetworkInterfaceConfig {
+                iface_id: String::from("netif"),
+                host_dev_name: String::from("hostname"),
+                guest_mac: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                tx_delay: None,
+                flow_stats: false,
+            };
+            insert_net_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                network_interface,
+            );
+            vmm.mmio_device_manager.save()
+        };
+        device_states.verify_device_infos().unwrap();
+
+        // The device info is not covered by the state hashes.
+        let addr = device_states.net_devices[0].device_info.addr;
+        device_states.net_devices[0].device_info.addr = 0;
+        assert!(matches!(
+            device_states.verify_device_infos(),
+            Err(ResourcesError::MmioRangeOutOfWindow(_, 0))
+        ));
+        device_states.net_devices[0].device_info.addr = addr + 0x10;
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let vmm = default_vmm();
+        let vm_resources = &mut VmResources::default();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            resource_allocator: &mut resource_allocator,
+            vm_resources,
+            instance_id: "microvm-id",
+            restore_source: RestoreSource::File,
+        };
+        let err = MMIODeviceManager::restore(restore_args, &device_states).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DevicePersistError::ResourcesError(ResourcesError::MisalignedMmioAddress(ref id, a))
+                    if id == "netif" && a == addr + 0x10
+            ),
+            "{:?}",
+            err
+        );
+        assert!(vm_resources.net_builder.is_empty());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_acpi_device_manager_vmgenid_persistence() {
+        let mut vmm = default_vmm();
+        assert_eq!(vmm.vmgenid_value(), None);
+        insert_vmgenid_device(&mut vmm);
+        let gen_id = vmm.vmgenid_value().unwrap();
+        let vmgenid_addr = vmm
+            .acpi_device_manager
+            .vmgenid
+            .as_ref()
+            .unwrap()
+            .guest_address;
+
+        let mut buf = vec![0; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &vmm.acpi_device_manager.save()).unwrap();
+        let state: ACPIDeviceManagerState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+
+        // Restore in a new microVM, the generation ID must not be reused.
+        let mut restored_vmm = default_vmm();
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        restored_vmm.acpi_device_manager = ACPIDeviceManager::restore(
+            ACPIDeviceManagerConstructorArgs {
+                mem: &restored_vmm.guest_memory,
+                resource_allocator: &mut resource_allocator,
+                vm: restored_vmm.vm.fd(),
+            },
+            &state,
+        )
+        .unwrap();
+        let restored_gen_id = restored_vmm.vmgenid_value().unwrap();
+        assert_ne!(restored_gen_id, gen_id);
+        let guest_gen_id: u128 = restored_vmm.guest_memory.read_obj(vmgenid_addr).unwrap();
+        assert_eq!(guest_gen_id, restored_gen_id);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_acpi_device_manager_vmclock_persistence() {
+        let mut vmm = default_vmm();
+        insert_vmclock_device(&mut vmm);
+        let vmclock_addr = vmm
+            .acpi_device_manager
+            .vmclock
+            .as_ref()
+            .unwrap()
+            .guest_address;
+
+        let mut buf = vec![0; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &vmm.acpi_device_manager.save()).unwrap();
+        let state: ACPIDeviceManagerState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+        assert_eq!(
+            state.vmclock,
+            Some(VmClockState {
+                addr: vmclock_addr.0
+            })
+        );
+
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let restored = ACPIDeviceManager::restore(
+            ACPIDeviceManagerConstructorArgs {
+                mem: &vmm.guest_memory,
+                resource_allocator: &mut resource_allocator,
+                vm: vmm.vm.fd(),
+            },
+            &state,
+        )
+        .unwrap();
+        let vmclock = restored.vmclock.as_ref().unwrap();
+        assert_eq!(vmclock.guest_address, vmclock_addr);
+
+        // Restoring tells the guest about the clock disruption.
+        assert_eq!(vmclock.disruption_marker(&vmm.guest_memory).unwrap(), 0);
+        restored.post_restore_vmclock(&vmm.guest_memory).unwrap();
+        assert_eq!(vmclock.disruption_marker(&vmm.guest_memory).unwrap(), 1);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_acpi_device_manager_hosttime_persistence() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        insert_hosttime_device(&mut vmm, &mut event_manager);
+        let hosttime_addr = vmm
+            .acpi_device_manager
+            .hosttime
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .guest_address;
+
+        let mut buf = vec![0; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &vmm.acpi_device_manager.save()).unwrap();
+        let state: ACPIDeviceManagerState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+        assert_eq!(
+            state.hosttime,
+            Some(HostTimeState {
+                addr: hosttime_addr.0
+            })
+        );
+
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let restored = ACPIDeviceManager::restore(
+            ACPIDeviceManagerConstructorArgs {
+                mem: &vmm.guest_memory,
+                resource_allocator: &mut resource_allocator,
+                vm: vmm.vm.fd(),
+            },
+            &state,
+        )
+        .unwrap();
+        let hosttime = restored.hosttime.as_ref().unwrap().lock().unwrap();
+        assert_eq!(hosttime.guest_address, hosttime_addr);
+        assert!(restored.vmclock.is_none());
+    }
 }
@@ -1,6 +1,8 @@
 // Copyright 2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
+
 pub use vm_allocator::AllocPolicy;
 use vm_allocator::{AddressAllocator, IdAllocator};
 
@@ -17,6 +19,8 @@ use crate::arch;
 pub struct ResourceAllocator {
     // Allocator for device interrupt lines
     gsi_allocator: IdAllocator,
+    // Owners of the allocated interrupt lines, by GSI
+    gsi_owners: BTreeMap<u32, String>,
     // Allocator for memory in the MMIO address space
     mmio_memory: AddressAllocator,
     // Memory allocator for system data
@@ -29,6 +33,7 @@ impl ResourceAllocator {
     pub fn new() -> Result<Self, vm_allocator::Error> {
         Ok(Self {
             gsi_allocator: IdAllocator::new(arch::IRQ_BASE, arch::IRQ_MAX)?,
+            gsi_owners: BTreeMap::new(),
             mmio_memory: AddressAllocator::new(arch::MMIO_MEM_START, arch::MMIO_MEM_SIZE)?,
             #[cfg(target_arch = "x86_64")]
             system_memory: AddressAllocator::new(arch::SYSTEM_MEM_START, arch::SYSTEM_MEM_SIZE)?,
@@ -40,7 +45,12 @@ impl ResourceAllocator {
     /// # Arguments
     ///
     /// * `gsi_count` - The number of GSIs to allocate
-    pub fn allocate_gsi(&mut self, gsi_count: u32) -> Result<Vec<u32>, vm_allocator::Error> {
+    /// * `owner` - A description of the user of the GSIs, reported by [`Self::gsi_map`]
+    pub fn allocate_gsi(
+        &mut self,
+        gsi_count: u32,
+        owner: &str,
+    ) -> Result<Vec<u32>, vm_allocator::Error> {
         let mut gsis = Vec::with_capacity(gsi_count as usize);
 
         for _ in 0..gsi_count {
@@ -56,9 +66,20 @@ impl ResourceAllocator {
             }
         }
 
+        for gsi in &gsis {
+            self.gsi_owners.insert(*gsi, owner.to_string());
+        }
         Ok(gsis)
     }
 
+    /// Returns the allocated GSIs, in ascending order, along with their owner
+    ///
+    /// GSIs aren't allocated again when restoring a microVM from a snapshot, so the map is only
+    /// populated for microVMs that were booted.
+    pub fn gsi_map(&self) -> &BTreeMap<u32, String> {
+        &self.gsi_owners
+    }
+
     /// Allocate a memory range in MMIO address space
     ///
     /// If it succeeds, it returns the first address of the allocated range
@@ -111,38 +132,69 @@ mod tests {
     fn test_allocate_gsi() {
         let mut allocator = ResourceAllocator::new().unwrap();
         // asking for 0 IRQs should return us an empty vector
-        assert_eq!(allocator.allocate_gsi(0), Ok(vec![]));
+        assert_eq!(allocator.allocate_gsi(0, "test"), Ok(vec![]));
         // We cannot allocate more GSIs than available
         assert_eq!(
-            allocator.allocate_gsi(MAX_IRQS + 1),
+            allocator.allocate_gsi(MAX_IRQS + 1, "test"),
             Err(vm_allocator::Error::ResourceNotAvailable)
         );
         // But allocating all of them at once should work
         assert_eq!(
-            allocator.allocate_gsi(MAX_IRQS),
+            allocator.allocate_gsi(MAX_IRQS, "test"),
             Ok((arch::IRQ_BASE..=arch::IRQ_MAX).collect::<Vec<_>>())
         );
         // And now we ran out of GSIs
         assert_eq!(
-            allocator.allocate_gsi(1),
+            allocator.allocate_gsi(1, "test"),
             Err(vm_allocator::Error::ResourceNotAvailable)
         );
         // But we should be able to ask for 0 GSIs
-        assert_eq!(allocator.allocate_gsi(0), Ok(vec![]));
+        assert_eq!(allocator.allocate_gsi(0, "test"), Ok(vec![]));
 
         let mut allocator = ResourceAllocator::new().unwrap();
         // We should be able to allocate 1 GSI
-        assert_eq!(allocator.allocate_gsi(1), Ok(vec![arch::IRQ_BASE]));
+        assert_eq!(allocator.allocate_gsi(1, "test"), Ok(vec![arch::IRQ_BASE]));
         // We can't allocate MAX_IRQS any more
         assert_eq!(
-            allocator.allocate_gsi(MAX_IRQS),
+            allocator.allocate_gsi(MAX_IRQS, "test"),
             Err(vm_allocator::Error::ResourceNotAvailable)
         );
         // We can allocate another one and it should be the second available
-        assert_eq!(allocator.allocate_gsi(1), Ok(vec![arch::IRQ_BASE + 1]));
+        assert_eq!(
+            allocator.allocate_gsi(1, "test"),
+            Ok(vec![arch::IRQ_BASE + 1])
+        );
         // Let's allocate the rest in a loop
         for i in arch::IRQ_BASE + 2..=arch::IRQ_MAX {
-            assert_eq!(allocator.allocate_gsi(1), Ok(vec![i]));
+            assert_eq!(allocator.allocate_gsi(1, "test"), Ok(vec![i]));
         }
     }
+
+    #[test]
+    fn test_gsi_map() {
+        let mut allocator = ResourceAllocator::new().unwrap();
+        assert!(allocator.gsi_map().is_empty());
+
+        allocator.allocate_gsi(1, "VMGenID").unwrap();
+        allocator.allocate_gsi(2, "rootfs").unwrap();
+        allocator.allocate_gsi(0, "BootTimer").unwrap();
+        allocator.allocate_gsi(1, "Serial").unwrap();
+        // Failed allocations don't show up in the map.
+        allocator.allocate_gsi(MAX_IRQS, "too_many").unwrap_err();
+
+        let map = allocator
+            .gsi_map()
+            .iter()
+            .map(|(gsi, owner)| (*gsi, owner.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            map,
+            vec![
+                (arch::IRQ_BASE, "VMGenID"),
+                (arch::IRQ_BASE + 1, "rootfs"),
+                (arch::IRQ_BASE + 2, "rootfs"),
+                (arch::IRQ_BASE + 3, "Serial"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,307 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+use std::sync::atomic::{fence, Ordering};
+use std::time::Duration;
+
+use acpi_tables::{aml, Aml};
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::EventSet;
+use utils::time::{get_time_ns, ClockType};
+use vm_memory::{GuestAddress, GuestMemoryError};
+
+use crate::device_manager::resources::ResourceAllocator;
+use crate::snapshot::Persist;
+use crate::vstate::memory::{Bytes, GuestMemoryMmap};
+
+/// Size of the host time page.
+pub const HOSTTIME_SIZE: u64 = 0x1000;
+/// Interval at which the host time page is refreshed.
+pub const HOSTTIME_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Layout of the host time page. All fields are little-endian.
+const HOSTTIME_MAGIC: u32 = 0x4854_4346;
+const HOSTTIME_VERSION: u16 = 1;
+const MAGIC_OFFSET: u64 = 0;
+const VERSION_OFFSET: u64 = 4;
+const SEQ_COUNT_OFFSET: u64 = 8;
+const REALTIME_NS_OFFSET: u64 = 16;
+const MONOTONIC_NS_OFFSET: u64 = 24;
+
+/// Host time device
+///
+/// The host time device exposes to the guest a page of memory holding the host's wall-clock
+/// (`CLOCK_REALTIME`) and monotonic (`CLOCK_MONOTONIC`) times, in nanoseconds. Firecracker
+/// refreshes the page every `HOSTTIME_UPDATE_INTERVAL` and right after restoring from a snapshot,
+/// so that an agent in the guest can correct the drift of the guest clock.
+///
+/// Updates follow a sequence counter protocol: the counter is odd while an update is in progress,
+/// so readers retry until they read the same even counter before and after the times.
+#[derive(Debug)]
+pub struct HostTime {
+    /// Guest physical address of the host time page.
+    pub guest_address: GuestAddress,
+    mem: GuestMemoryMmap,
+    timer: TimerFd,
+}
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum HostTimeError {
+    /// Error accessing host time memory: {0}
+    GuestMemory(#[from] GuestMemoryError),
+    /// Failed to allocate requested resource: {0}
+    Allocator(#[from] vm_allocator::Error),
+    /// Failed to create the update timer: {0}
+    Timer(io::Error),
+}
+
+impl HostTime {
+    /// Create a new host time device with its page at `guest_address`, initialize the page and
+    /// start refreshing it.
+    pub fn from_parts(
+        guest_address: GuestAddress,
+        mem: &GuestMemoryMmap,
+    ) -> Result<Self, HostTimeError> {
+        debug!(
+            "hosttime: building host time device. Address: {:#010x}",
+            guest_address.0
+        );
+        mem.write_obj(HOSTTIME_MAGIC, guest_address.unchecked_add(MAGIC_OFFSET))
+            .and_then(|_| {
+                mem.write_obj(
+                    HOSTTIME_VERSION,
+                    guest_address.unchecked_add(VERSION_OFFSET),
+                )
+            })
+            .inspect_err(|err| {
+                error!("hosttime: could not write host time page to guest: {err}")
+            })?;
+
+        let mut timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(HostTimeError::Timer)?;
+        timer.set_state(
+            TimerState::Periodic {
+                current: HOSTTIME_UPDATE_INTERVAL,
+                interval: HOSTTIME_UPDATE_INTERVAL,
+            },
+            SetTimeFlags::Default,
+        );
+
+        let hosttime = Self {
+            guest_address,
+            mem: mem.clone(),
+            timer,
+        };
+        hosttime.update()?;
+        Ok(hosttime)
+    }
+
+    /// Create a new host time device
+    ///
+    /// Allocate memory for the host time page and build the device
+    pub fn new(
+        mem: &GuestMemoryMmap,
+        resource_allocator: &mut ResourceAllocator,
+    ) -> Result<Self, HostTimeError> {
+        let addr = resource_allocator.allocate_system_memory(
+            HOSTTIME_SIZE,
+            HOSTTIME_SIZE,
+            vm_allocator::AllocPolicy::LastMatch,
+        )?;
+
+        Self::from_parts(GuestAddress(addr), mem)
+    }
+
+    /// Write the current host times to the page.
+    pub fn update(&self) -> Result<(), HostTimeError> {
+        let seq_addr = self.guest_address.unchecked_add(SEQ_COUNT_OFFSET);
+
+        let seq_count: u32 = self.mem.read_obj(seq_addr)?;
+        self.mem.write_obj(seq_count.wrapping_add(1), seq_addr)?;
+        fence(Ordering::Release);
+        self.mem.write_obj(
+            get_time_ns(ClockType::Real),
+            self.guest_address.unchecked_add(REALTIME_NS_OFFSET),
+        )?;
+        self.mem.write_obj(
+            get_time_ns(ClockType::Monotonic),
+            self.guest_address.unchecked_add(MONOTONIC_NS_OFFSET),
+        )?;
+        fence(Ordering::Release);
+        self.mem.write_obj(seq_count.wrapping_add(2), seq_addr)?;
+        Ok(())
+    }
+}
+
+impl MutEventSubscriber for HostTime {
+    fn process(&mut self, event: Events, _ops: &mut EventOps) {
+        if !EventSet::IN.contains(event.event_set()) {
+            warn!("hosttime: received unknown event: {:?}", event.event_set());
+            return;
+        }
+        self.timer.read();
+        if let Err(err) = self.update() {
+            error!("hosttime: could not update host time page: {err}");
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::new(&self.timer, EventSet::IN)) {
+            error!("hosttime: failed to register update timer event: {err}");
+        }
+    }
+}
+
+/// Logic to save/restore the state of a host time device
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostTimeState {
+    /// Guest physical address of the host time page
+    pub addr: u64,
+}
+
+#[derive(Debug)]
+pub struct HostTimeConstructorArgs<'a> {
+    pub mem: &'a GuestMemoryMmap,
+    pub resource_allocator: &'a mut ResourceAllocator,
+}
+
+impl<'a> Persist<'a> for HostTime {
+    type State = HostTimeState;
+    type ConstructorArgs = HostTimeConstructorArgs<'a>;
+    type Error = HostTimeError;
+
+    fn save(&self) -> Self::State {
+        HostTimeState {
+            addr: self.guest_address.0,
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        constructor_args.resource_allocator.allocate_system_memory(
+            HOSTTIME_SIZE,
+            HOSTTIME_SIZE,
+            vm_allocator::AllocPolicy::ExactMatch(state.addr),
+        )?;
+        // The sequence counter carries on from the page restored with the guest memory, and the
+        // page is refreshed before the vCPUs resume.
+        Self::from_parts(GuestAddress(state.addr), constructor_args.mem)
+    }
+}
+
+impl Aml for HostTime {
+    fn append_aml_bytes(&self, v: &mut Vec<u8>) {
+        aml::Device::new(
+            "_SB_.HTIM".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"FCHT0001"),
+                &aml::Name::new("_DDN".into(), &"HOSTTIME"),
+                &aml::Method::new("_STA".into(), 0, false, vec![&aml::Return::new(&0x0fu8)]),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::AddressSpace::new_memory(
+                        aml::AddressSpaceCachable::Cacheable,
+                        false,
+                        self.guest_address.0,
+                        self.guest_address.0 + HOSTTIME_SIZE - 1,
+                    )]),
+                ),
+            ],
+        )
+        .append_aml_bytes(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+    use crate::utilities::test_utils::single_region_mem;
+
+    // Reads the times of the page the way a guest agent does.
+    fn read_page(mem: &GuestMemoryMmap, addr: GuestAddress) -> (u32, u64, u64) {
+        loop {
+            let seq_before: u32 = mem.read_obj(addr.unchecked_add(SEQ_COUNT_OFFSET)).unwrap();
+            let realtime: u64 = mem
+                .read_obj(addr.unchecked_add(REALTIME_NS_OFFSET))
+                .unwrap();
+            let monotonic: u64 = mem
+                .read_obj(addr.unchecked_add(MONOTONIC_NS_OFFSET))
+                .unwrap();
+            let seq_after: u32 = mem.read_obj(addr.unchecked_add(SEQ_COUNT_OFFSET)).unwrap();
+            if seq_before % 2 == 0 && seq_before == seq_after {
+                return (seq_before, realtime, monotonic);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hosttime_page() {
+        let mem = single_region_mem(0x2000);
+        let before = get_time_ns(ClockType::Monotonic);
+        let hosttime = HostTime::from_parts(GuestAddress(0x1000), &mem).unwrap();
+
+        let magic: u32 = mem.read_obj(GuestAddress(0x1000)).unwrap();
+        assert_eq!(magic, HOSTTIME_MAGIC);
+        let version: u16 = mem.read_obj(GuestAddress(0x1004)).unwrap();
+        assert_eq!(version, HOSTTIME_VERSION);
+
+        // The page is filled in as soon as the device is built.
+        let (seq_count, realtime, monotonic) = read_page(&mem, hosttime.guest_address);
+        assert_eq!(seq_count, 2);
+        assert!(realtime > 0);
+        assert!(monotonic >= before);
+
+        // Every update advances the times along with the sequence counter.
+        std::thread::sleep(Duration::from_millis(1));
+        hosttime.update().unwrap();
+        let (next_seq_count, next_realtime, next_monotonic) =
+            read_page(&mem, hosttime.guest_address);
+        assert_eq!(next_seq_count, seq_count + 2);
+        assert!(next_monotonic >= monotonic + 1_000_000);
+        assert!(next_realtime > realtime);
+        assert!(next_monotonic <= get_time_ns(ClockType::Monotonic));
+    }
+
+    #[test]
+    fn test_hosttime_persistence() {
+        // Large enough to hold the system memory region.
+        let mem = single_region_mem(0x10_0000);
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let hosttime = HostTime::new(&mem, &mut resource_allocator).unwrap();
+        hosttime.update().unwrap();
+
+        let mut buf = vec![0u8; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &hosttime.save()).unwrap();
+        let state: HostTimeState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let restored = HostTime::restore(
+            HostTimeConstructorArgs {
+                mem: &mem,
+                resource_allocator: &mut resource_allocator,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored.guest_address, hosttime.guest_address);
+        // The restored device refreshed the page, carrying on the sequence counter.
+        let (seq_count, _, _) = read_page(&mem, restored.guest_address);
+        assert_eq!(seq_count, 6);
+        // The page of the restored device is reserved.
+        resource_allocator
+            .allocate_system_memory(
+                HOSTTIME_SIZE,
+                HOSTTIME_SIZE,
+                vm_allocator::AllocPolicy::ExactMatch(state.addr),
+            )
+            .unwrap_err();
+    }
+}
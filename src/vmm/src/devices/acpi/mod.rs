@@ -1,4 +1,6 @@
 // Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod hosttime;
+pub mod vmclock;
 pub mod vmgenid;
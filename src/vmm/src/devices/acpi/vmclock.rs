@@ -0,0 +1,368 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Debug;
+use std::sync::atomic::{fence, Ordering};
+use std::time::Duration;
+
+use acpi_tables::{aml, Aml};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use utils::time::{get_time_ns, ClockType};
+use vm_memory::{GuestAddress, GuestMemoryError};
+
+use crate::device_manager::resources::ResourceAllocator;
+use crate::snapshot::Persist;
+use crate::vstate::memory::{Bytes, GuestMemoryMmap};
+
+/// Size of the VMClock page.
+pub const VMCLOCK_SIZE: u64 = 0x1000;
+
+// Fields of `struct vmclock_abi` (see include/uapi/linux/vmclock-abi.h in Linux) that we use.
+const VMCLOCK_MAGIC: u32 = 0x4b4c_4356;
+const VMCLOCK_VERSION: u16 = 1;
+// We don't provide a reference counter, the guest only relies on the disruption marker.
+const VMCLOCK_COUNTER_INVALID: u8 = 0xff;
+// The time base we report is the host's monotonic clock, not UTC or TAI.
+const VMCLOCK_TIME_MONOTONIC: u8 = 2;
+const MAGIC_OFFSET: u64 = 0;
+const SIZE_OFFSET: u64 = 4;
+const VERSION_OFFSET: u64 = 8;
+const COUNTER_ID_OFFSET: u64 = 10;
+const TIME_TYPE_OFFSET: u64 = 11;
+const SEQ_COUNT_OFFSET: u64 = 12;
+const DISRUPTION_MARKER_OFFSET: u64 = 16;
+const TIME_SEC_OFFSET: u64 = 72;
+const TIME_FRAC_SEC_OFFSET: u64 = 80;
+
+/// Source of the time base the VMClock device reports to the guest.
+pub trait VmClockSource: Debug + Send + Sync {
+    /// Returns the current time base.
+    fn time_base(&self) -> Duration;
+}
+
+/// Clock source reading the host's monotonic clock (`CLOCK_MONOTONIC`).
+#[derive(Debug, Default)]
+pub struct HostMonotonicClock;
+
+impl VmClockSource for HostMonotonicClock {
+    fn time_base(&self) -> Duration {
+        Duration::from_nanos(get_time_ns(ClockType::Monotonic))
+    }
+}
+
+/// Virtual Machine Clock device
+///
+/// VMClock exposes to the guest a page of memory describing the state of its clock. Firecracker
+/// uses it to tell the guest that its clock was disrupted, by incrementing the disruption marker
+/// of the page every time the microVM is restored from a snapshot. The page also carries the
+/// time base read from the clock source of the device when the page is written.
+///
+/// The guest driver lives in drivers/ptp/ptp_vmclock.c in Linux.
+#[derive(Debug)]
+pub struct VmClock {
+    /// Guest physical address of the VMClock page.
+    pub guest_address: GuestAddress,
+    clock_source: Box<dyn VmClockSource>,
+}
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum VmClockError {
+    /// Error accessing VMClock memory: {0}
+    GuestMemory(#[from] GuestMemoryError),
+    /// Failed to allocate requested resource: {0}
+    Allocator(#[from] vm_allocator::Error),
+}
+
+impl VmClock {
+    /// Create a new VMClock device with its page at `guest_address`, and initialize the page.
+    ///
+    /// The device reads its time base from the host's monotonic clock.
+    pub fn from_parts(
+        guest_address: GuestAddress,
+        mem: &GuestMemoryMmap,
+    ) -> Result<Self, VmClockError> {
+        Self::from_parts_with_clock(guest_address, mem, Box::new(HostMonotonicClock))
+    }
+
+    /// Create a new VMClock device with its page at `guest_address`, reading its time base from
+    /// `clock_source`, and initialize the page.
+    pub fn from_parts_with_clock(
+        guest_address: GuestAddress,
+        mem: &GuestMemoryMmap,
+        clock_source: Box<dyn VmClockSource>,
+    ) -> Result<Self, VmClockError> {
+        debug!(
+            "vmclock: building VMClock device. Address: {:#010x}",
+            guest_address.0
+        );
+        let vmclock = Self {
+            guest_address,
+            clock_source,
+        };
+        // The page is zeroed, only the header and the time base need to be written.
+        mem.write_obj(VMCLOCK_MAGIC, guest_address.unchecked_add(MAGIC_OFFSET))
+            .and_then(|_| {
+                mem.write_obj(
+                    u32::try_from(VMCLOCK_SIZE).unwrap(),
+                    guest_address.unchecked_add(SIZE_OFFSET),
+                )
+            })
+            .and_then(|_| {
+                mem.write_obj(VMCLOCK_VERSION, guest_address.unchecked_add(VERSION_OFFSET))
+            })
+            .and_then(|_| {
+                mem.write_obj(
+                    VMCLOCK_COUNTER_INVALID,
+                    guest_address.unchecked_add(COUNTER_ID_OFFSET),
+                )
+            })
+            .and_then(|_| {
+                mem.write_obj(
+                    VMCLOCK_TIME_MONOTONIC,
+                    guest_address.unchecked_add(TIME_TYPE_OFFSET),
+                )
+            })
+            .and_then(|_| vmclock.write_time(mem))
+            .inspect_err(|err| error!("vmclock: could not write VMClock page to guest: {err}"))?;
+
+        Ok(vmclock)
+    }
+
+    /// Create a new VMClock device
+    ///
+    /// Allocate memory for the VMClock page and build the device
+    pub fn new(
+        mem: &GuestMemoryMmap,
+        resource_allocator: &mut ResourceAllocator,
+    ) -> Result<Self, VmClockError> {
+        let addr = resource_allocator.allocate_system_memory(
+            VMCLOCK_SIZE,
+            VMCLOCK_SIZE,
+            vm_allocator::AllocPolicy::LastMatch,
+        )?;
+
+        Self::from_parts(GuestAddress(addr), mem)
+    }
+
+    /// Replaces the clock source the device reads its time base from. The page is updated with
+    /// the new time base on the next restore.
+    pub fn set_clock_source(&mut self, clock_source: Box<dyn VmClockSource>) {
+        self.clock_source = clock_source;
+    }
+
+    /// Writes the current time base of the clock source to the page, as seconds and fractions
+    /// of a second in units of 2^-64 seconds.
+    fn write_time(&self, mem: &GuestMemoryMmap) -> Result<(), GuestMemoryError> {
+        let time = self.clock_source.time_base();
+        let frac_sec = (u128::from(time.subsec_nanos()) << 64) / 1_000_000_000;
+        mem.write_obj(
+            time.as_secs(),
+            self.guest_address.unchecked_add(TIME_SEC_OFFSET),
+        )?;
+        mem.write_obj(
+            // Below 2^64 since the nanoseconds are below one second.
+            u64::try_from(frac_sec).unwrap(),
+            self.guest_address.unchecked_add(TIME_FRAC_SEC_OFFSET),
+        )
+    }
+
+    /// Returns the number of clock disruptions the guest was told about.
+    pub fn disruption_marker(&self, mem: &GuestMemoryMmap) -> Result<u64, VmClockError> {
+        Ok(mem.read_obj(self.guest_address.unchecked_add(DISRUPTION_MARKER_OFFSET))?)
+    }
+
+    /// Tell the guest that its clock was disrupted, and give it the current time base.
+    ///
+    /// This runs after restoring from a snapshot, before resuming the vCPUs. The update follows
+    /// the sequence counter protocol of the page: the counter is odd while the update is in
+    /// progress.
+    pub fn post_restore(&self, mem: &GuestMemoryMmap) -> Result<(), VmClockError> {
+        let seq_addr = self.guest_address.unchecked_add(SEQ_COUNT_OFFSET);
+        let marker_addr = self.guest_address.unchecked_add(DISRUPTION_MARKER_OFFSET);
+
+        let seq_count: u32 = mem.read_obj(seq_addr)?;
+        mem.write_obj(seq_count.wrapping_add(1), seq_addr)?;
+        fence(Ordering::Release);
+        let marker: u64 = mem.read_obj(marker_addr)?;
+        mem.write_obj(marker.wrapping_add(1), marker_addr)?;
+        self.write_time(mem)?;
+        fence(Ordering::Release);
+        mem.write_obj(seq_count.wrapping_add(2), seq_addr)?;
+
+        debug!("vmclock: notifying guest about clock disruption");
+        Ok(())
+    }
+}
+
+/// Logic to save/restore the state of a VMClock device
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VmClockState {
+    /// Guest physical address of the VMClock page
+    pub addr: u64,
+}
+
+#[derive(Debug)]
+pub struct VmClockConstructorArgs<'a> {
+    pub resource_allocator: &'a mut ResourceAllocator,
+}
+
+impl<'a> Persist<'a> for VmClock {
+    type State = VmClockState;
+    type ConstructorArgs = VmClockConstructorArgs<'a>;
+    type Error = VmClockError;
+
+    fn save(&self) -> Self::State {
+        VmClockState {
+            addr: self.guest_address.0,
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        // The page itself is part of the guest memory restored from the snapshot.
+        constructor_args.resource_allocator.allocate_system_memory(
+            VMCLOCK_SIZE,
+            VMCLOCK_SIZE,
+            vm_allocator::AllocPolicy::ExactMatch(state.addr),
+        )?;
+        Ok(Self {
+            guest_address: GuestAddress(state.addr),
+            clock_source: Box::new(HostMonotonicClock),
+        })
+    }
+}
+
+impl Aml for VmClock {
+    fn append_aml_bytes(&self, v: &mut Vec<u8>) {
+        aml::Device::new(
+            "_SB_.VCLK".into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"AMZNC10C"),
+                &aml::Name::new("_CID".into(), &"VMCLOCK"),
+                &aml::Name::new("_DDN".into(), &"VMCLOCK"),
+                &aml::Method::new("_STA".into(), 0, false, vec![&aml::Return::new(&0x0fu8)]),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::AddressSpace::new_memory(
+                        aml::AddressSpaceCachable::Cacheable,
+                        false,
+                        self.guest_address.0,
+                        self.guest_address.0 + VMCLOCK_SIZE - 1,
+                    )]),
+                ),
+            ],
+        )
+        .append_aml_bytes(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+    use crate::utilities::test_utils::single_region_mem;
+
+    #[test]
+    fn test_vmclock_page() {
+        let mem = single_region_mem(0x2000);
+        let vmclock = VmClock::from_parts(GuestAddress(0x1000), &mem).unwrap();
+
+        let magic: u32 = mem.read_obj(GuestAddress(0x1000)).unwrap();
+        assert_eq!(magic, VMCLOCK_MAGIC);
+        let size: u32 = mem.read_obj(GuestAddress(0x1004)).unwrap();
+        assert_eq!(u64::from(size), VMCLOCK_SIZE);
+        let version: u16 = mem.read_obj(GuestAddress(0x1008)).unwrap();
+        assert_eq!(version, VMCLOCK_VERSION);
+        assert_eq!(vmclock.disruption_marker(&mem).unwrap(), 0);
+
+        // Every restore is a disruption, and leaves the sequence counter even.
+        vmclock.post_restore(&mem).unwrap();
+        vmclock.post_restore(&mem).unwrap();
+        assert_eq!(vmclock.disruption_marker(&mem).unwrap(), 2);
+        let seq_count: u32 = mem
+            .read_obj(GuestAddress(0x1000 + SEQ_COUNT_OFFSET))
+            .unwrap();
+        assert_eq!(seq_count, 4);
+    }
+
+    #[derive(Debug)]
+    struct FixedClock(Duration);
+
+    impl VmClockSource for FixedClock {
+        fn time_base(&self) -> Duration {
+            self.0
+        }
+    }
+
+    fn read_time(mem: &GuestMemoryMmap, guest_address: GuestAddress) -> (u64, u64) {
+        (
+            mem.read_obj(guest_address.unchecked_add(TIME_SEC_OFFSET))
+                .unwrap(),
+            mem.read_obj(guest_address.unchecked_add(TIME_FRAC_SEC_OFFSET))
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_vmclock_clock_source() {
+        let mem = single_region_mem(0x2000);
+        let clock = FixedClock(Duration::new(42, 500_000_000));
+        let mut vmclock =
+            VmClock::from_parts_with_clock(GuestAddress(0x1000), &mem, Box::new(clock)).unwrap();
+
+        // The page carries the time base of the clock source, half a second being 2^63.
+        let time_type: u8 = mem
+            .read_obj(GuestAddress(0x1000 + TIME_TYPE_OFFSET))
+            .unwrap();
+        assert_eq!(time_type, VMCLOCK_TIME_MONOTONIC);
+        assert_eq!(read_time(&mem, vmclock.guest_address), (42, 1 << 63));
+
+        // A restore reports the time base of the new clock source.
+        vmclock.set_clock_source(Box::new(FixedClock(Duration::from_secs(100))));
+        assert_eq!(read_time(&mem, vmclock.guest_address), (42, 1 << 63));
+        vmclock.post_restore(&mem).unwrap();
+        assert_eq!(read_time(&mem, vmclock.guest_address), (100, 0));
+        assert_eq!(vmclock.disruption_marker(&mem).unwrap(), 1);
+
+        // The default clock source is the host's monotonic clock.
+        let before = get_time_ns(ClockType::Monotonic) / 1_000_000_000;
+        let vmclock = VmClock::from_parts(GuestAddress(0x1000), &mem).unwrap();
+        let (time_sec, _) = read_time(&mem, vmclock.guest_address);
+        assert!(time_sec >= before);
+        assert!(time_sec <= get_time_ns(ClockType::Monotonic) / 1_000_000_000);
+    }
+
+    #[test]
+    fn test_vmclock_persistence() {
+        // Large enough to hold the system memory region.
+        let mem = single_region_mem(0x10_0000);
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let vmclock = VmClock::new(&mem, &mut resource_allocator).unwrap();
+
+        let mut buf = vec![0u8; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &vmclock.save()).unwrap();
+        let state: VmClockState = Snapshot::deserialize(&mut buf.as_slice()).unwrap();
+
+        let mut resource_allocator = ResourceAllocator::new().unwrap();
+        let restored = VmClock::restore(
+            VmClockConstructorArgs {
+                resource_allocator: &mut resource_allocator,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored.guest_address, vmclock.guest_address);
+        // The page of the restored device is reserved.
+        resource_allocator
+            .allocate_system_memory(
+                VMCLOCK_SIZE,
+                VMCLOCK_SIZE,
+                vm_allocator::AllocPolicy::ExactMatch(state.addr),
+            )
+            .unwrap_err();
+    }
+}
@@ -4,7 +4,7 @@
 use acpi_tables::{aml, Aml};
 use aws_lc_rs::error::Unspecified as RandError;
 use aws_lc_rs::rand;
-use log::{debug, error};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use vm_memory::{GuestAddress, GuestMemoryError};
@@ -12,8 +12,9 @@ use vm_superio::Trigger;
 
 use super::super::legacy::EventFdTrigger;
 use crate::device_manager::resources::ResourceAllocator;
+use crate::rate_limiter::{BucketReduction, TokenBucket};
 use crate::snapshot::Persist;
-use crate::vstate::memory::{Bytes, GuestMemoryMmap};
+use crate::vstate::memory::{ByteValued, Bytes, GuestMemoryMmap};
 
 /// Virtual Machine Generation ID device
 ///
@@ -23,6 +24,9 @@ use crate::vstate::memory::{Bytes, GuestMemoryMmap};
 /// microVM is created, either from scratch or restored from a snapshot.
 ///
 /// The device specification can be found here: https://go.microsoft.com/fwlink/?LinkId=260709
+///
+/// On top of the specification, the guest can ask for a new generation ID by writing to a doorbell
+/// register in the MMIO address space, advertised through the `DBEL` object of the device.
 #[derive(Debug)]
 pub struct VmGenId {
     /// Current generation ID of guest VM
@@ -33,8 +37,19 @@ pub struct VmGenId {
     pub guest_address: GuestAddress,
     /// GSI number for the device
     pub gsi: u32,
+    /// Guest physical address of the doorbell register.
+    pub doorbell_address: GuestAddress,
+    /// Event signaled by KVM when the guest writes to the doorbell register.
+    pub doorbell_evt: EventFd,
+    /// Rate limiter of the generation ID changes requested by the guest.
+    doorbell_limiter: TokenBucket,
 }
 
+/// Size of the doorbell register.
+pub const DOORBELL_LEN: u64 = 8;
+/// Minimum time, in milliseconds, between two generation ID changes requested by the guest.
+const DOORBELL_REFILL_TIME_MS: u64 = 1000;
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum VmGenIdError {
     /// Error with VMGenID interrupt: {0}
@@ -45,6 +60,8 @@ pub enum VmGenIdError {
     GenerationId(#[from] RandError),
     /// Failed to allocate requested resource: {0}
     Allocator(#[from] vm_allocator::Error),
+    /// The generation ID address reported through ACPI is {0:#x}, it cannot move to {1:#x}.
+    FixedAddress(u64, u64),
 }
 
 impl VmGenId {
@@ -53,6 +70,7 @@ impl VmGenId {
     pub fn from_parts(
         guest_address: GuestAddress,
         gsi: u32,
+        doorbell_address: GuestAddress,
         mem: &GuestMemoryMmap,
     ) -> Result<Self, VmGenIdError> {
         debug!(
@@ -60,6 +78,9 @@ impl VmGenId {
             guest_address.0, gsi
         );
         let interrupt_evt = EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK)?);
+        let doorbell_evt = EventFd::new(libc::EFD_NONBLOCK)?;
+        // The bucket parameters are not zero, hence rate limiting is always enabled.
+        let doorbell_limiter = TokenBucket::new(1, 0, DOORBELL_REFILL_TIME_MS).unwrap();
         let gen_id = Self::make_genid()?;
 
         // Write generation ID in guest memory
@@ -75,6 +96,9 @@ impl VmGenId {
             interrupt_evt,
             guest_address,
             gsi,
+            doorbell_address,
+            doorbell_evt,
+            doorbell_limiter,
         })
     }
 
@@ -85,14 +109,19 @@ impl VmGenId {
         mem: &GuestMemoryMmap,
         resource_allocator: &mut ResourceAllocator,
     ) -> Result<Self, VmGenIdError> {
-        let gsi = resource_allocator.allocate_gsi(1)?;
+        let gsi = resource_allocator.allocate_gsi(1, "VMGenID")?;
         let addr = resource_allocator.allocate_system_memory(
             4096,
             8,
             vm_allocator::AllocPolicy::LastMatch,
         )?;
+        let doorbell_addr = resource_allocator.allocate_mmio_memory(
+            DOORBELL_LEN,
+            DOORBELL_LEN,
+            vm_allocator::AllocPolicy::LastMatch,
+        )?;
 
-        Self::from_parts(GuestAddress(addr), gsi[0], mem)
+        Self::from_parts(GuestAddress(addr), gsi[0], GuestAddress(doorbell_addr), mem)
     }
 
     // Create a 16-bytes random number
@@ -100,7 +129,70 @@ impl VmGenId {
         let mut gen_id_bytes = [0u8; 16];
         rand::fill(&mut gen_id_bytes)
             .inspect_err(|err| error!("vmgenid: could not create new generation ID: {err}"))?;
-        Ok(u128::from_le_bytes(gen_id_bytes))
+        let gen_id = u128::from_le_bytes(gen_id_bytes);
+        utils::zeroize(&mut gen_id_bytes);
+        Ok(gen_id)
+    }
+
+    /// Replaces the generation ID with a new random one, and notifies the guest about it.
+    pub fn regenerate(&mut self, mem: &GuestMemoryMmap) -> Result<(), VmGenIdError> {
+        let gen_id = Self::make_genid()?;
+        debug!(
+            "vmgenid: writing new generation ID to guest: {:#034x}",
+            gen_id
+        );
+        mem.write_slice(&gen_id.to_le_bytes(), self.guest_address)
+            .inspect_err(|err| error!("vmgenid: could not write generation ID to guest: {err}"))?;
+        self.gen_id = gen_id;
+        self.notify_guest()?;
+        Ok(())
+    }
+
+    /// Rewrites the generation ID at `new_addr` in `mem` and notifies the guest, for when the
+    /// memory backing the generation ID was remapped, e.g. during a live migration.
+    ///
+    /// The guest finds the generation ID through the `ADDR` object of the ACPI tables, which are
+    /// written once when the microVM boots. Only the memory behind the address can change, so
+    /// `new_addr` must be the address the device already uses.
+    pub fn relocate(
+        &mut self,
+        new_addr: GuestAddress,
+        mem: &GuestMemoryMmap,
+    ) -> Result<(), VmGenIdError> {
+        if new_addr != self.guest_address {
+            return Err(VmGenIdError::FixedAddress(self.guest_address.0, new_addr.0));
+        }
+        debug!(
+            "vmgenid: rewriting generation ID to guest at {:#010x}",
+            new_addr.0
+        );
+        mem.write_slice(&self.gen_id.to_le_bytes(), new_addr)
+            .inspect_err(|err| error!("vmgenid: could not write generation ID to guest: {err}"))?;
+        self.notify_guest()?;
+        Ok(())
+    }
+
+    /// Handles a write of the guest to the doorbell register, by regenerating the generation ID
+    /// unless the guest rang the doorbell too often.
+    ///
+    /// Returns whether the generation ID changed.
+    pub fn handle_doorbell(&mut self, mem: &GuestMemoryMmap) -> Result<bool, VmGenIdError> {
+        // The event only tells that the doorbell was rung, what the guest wrote doesn't matter.
+        let _ = self.doorbell_evt.read();
+        if let BucketReduction::Failure = self.doorbell_limiter.reduce(1) {
+            warn!("vmgenid: ignoring generation ID change request, the guest is rate limited");
+            return Ok(false);
+        }
+        self.regenerate(mem)?;
+        Ok(true)
+    }
+
+    /// Clears the generation ID held by the device.
+    ///
+    /// This runs when the device is dropped, so that the value is not left behind in host
+    /// memory.
+    pub fn zeroize(&mut self) {
+        utils::zeroize(self.gen_id.as_mut_slice());
     }
 
     /// Send an ACPI notification to guest device.
@@ -116,6 +208,12 @@ impl VmGenId {
     }
 }
 
+impl Drop for VmGenId {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 /// Logic to save/restore the state of a VMGenID device
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -124,6 +222,8 @@ pub struct VMGenIDState {
     pub gsi: u32,
     /// memory address of generation ID
     pub addr: u64,
+    /// MMIO address of the doorbell register
+    pub doorbell_addr: u64,
 }
 
 #[derive(Debug)]
@@ -141,6 +241,7 @@ impl<'a> Persist<'a> for VmGenId {
         VMGenIDState {
             gsi: self.gsi,
             addr: self.guest_address.0,
+            doorbell_addr: self.doorbell_address.0,
         }
     }
 
@@ -153,7 +254,17 @@ impl<'a> Persist<'a> for VmGenId {
             8,
             vm_allocator::AllocPolicy::ExactMatch(state.addr),
         )?;
-        Self::from_parts(GuestAddress(state.addr), state.gsi, constructor_args.mem)
+        constructor_args.resource_allocator.allocate_mmio_memory(
+            DOORBELL_LEN,
+            DOORBELL_LEN,
+            vm_allocator::AllocPolicy::ExactMatch(state.doorbell_addr),
+        )?;
+        Self::from_parts(
+            GuestAddress(state.addr),
+            state.gsi,
+            GuestAddress(state.doorbell_addr),
+            constructor_args.mem,
+        )
     }
 }
 
@@ -162,6 +273,9 @@ impl Aml for VmGenId {
         #[allow(clippy::cast_possible_truncation)]
         let addr_low = self.guest_address.0 as u32;
         let addr_high = (self.guest_address.0 >> 32) as u32;
+        #[allow(clippy::cast_possible_truncation)]
+        let doorbell_low = self.doorbell_address.0 as u32;
+        let doorbell_high = (self.doorbell_address.0 >> 32) as u32;
         aml::Device::new(
             "_SB_.VGEN".into(),
             vec![
@@ -172,8 +286,88 @@ impl Aml for VmGenId {
                     "ADDR".into(),
                     &aml::Package::new(vec![&addr_low, &addr_high]),
                 ),
+                &aml::Name::new(
+                    "DBEL".into(),
+                    &aml::Package::new(vec![&doorbell_low, &doorbell_high]),
+                ),
             ],
         )
         .append_aml_bytes(v)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::test_utils::single_region_mem;
+
+    #[test]
+    fn test_zeroize() {
+        let mem = single_region_mem(0x1000);
+        let mut vmgenid =
+            VmGenId::from_parts(GuestAddress(0), 5, GuestAddress(0xd000_0000), &mem).unwrap();
+        let gen_id: u128 = mem.read_obj(GuestAddress(0)).unwrap();
+        assert_eq!(vmgenid.gen_id, gen_id);
+        assert_ne!(vmgenid.gen_id, 0);
+
+        // Dropping the device clears the generation ID it holds.
+        let mut vmgenid = std::mem::ManuallyDrop::new(vmgenid);
+        let gen_id_ptr = std::ptr::addr_of!(vmgenid.gen_id);
+        // SAFETY: `vmgenid` is dropped once and not used afterwards. Its memory is still
+        // allocated, so the integer it held can be read back.
+        unsafe {
+            std::mem::ManuallyDrop::drop(&mut vmgenid);
+            assert_eq!(gen_id_ptr.read_volatile(), 0);
+        }
+    }
+
+    #[test]
+    fn test_doorbell() {
+        let mem = single_region_mem(0x1000);
+        let mut vmgenid =
+            VmGenId::from_parts(GuestAddress(0), 5, GuestAddress(0xd000_0000), &mem).unwrap();
+        let gen_id = vmgenid.gen_id;
+
+        // The guest rings the doorbell: the generation ID changes and the guest is notified.
+        vmgenid.doorbell_evt.write(1).unwrap();
+        assert!(vmgenid.handle_doorbell(&mem).unwrap());
+        assert_ne!(vmgenid.gen_id, gen_id);
+        let new_gen_id: u128 = mem.read_obj(GuestAddress(0)).unwrap();
+        assert_eq!(vmgenid.gen_id, new_gen_id);
+        assert_eq!(vmgenid.interrupt_evt.read().unwrap(), 1);
+        // The doorbell event was consumed.
+        vmgenid.doorbell_evt.read().unwrap_err();
+
+        // Ringing again right away is rate limited.
+        vmgenid.doorbell_evt.write(1).unwrap();
+        assert!(!vmgenid.handle_doorbell(&mem).unwrap());
+        assert_eq!(vmgenid.gen_id, new_gen_id);
+        vmgenid.interrupt_evt.read().unwrap_err();
+    }
+
+    #[test]
+    fn test_relocate() {
+        let mem = single_region_mem(0x2000);
+        let mut vmgenid =
+            VmGenId::from_parts(GuestAddress(0), 5, GuestAddress(0xd000_0000), &mem).unwrap();
+        let gen_id = vmgenid.gen_id;
+
+        // The address reported through ACPI cannot change.
+        assert!(matches!(
+            vmgenid.relocate(GuestAddress(0x1000), &mem),
+            Err(VmGenIdError::FixedAddress(0, 0x1000))
+        ));
+        assert_eq!(vmgenid.guest_address, GuestAddress(0));
+        let value: u128 = mem.read_obj(GuestAddress(0x1000)).unwrap();
+        assert_eq!(value, 0);
+        vmgenid.interrupt_evt.read().unwrap_err();
+
+        // The memory behind it can: the generation ID is rewritten and the guest notified.
+        let remapped_mem = single_region_mem(0x2000);
+        vmgenid.relocate(GuestAddress(0), &remapped_mem).unwrap();
+        let value: u128 = remapped_mem.read_obj(GuestAddress(0)).unwrap();
+        assert_eq!(value, gen_id);
+        assert_eq!(vmgenid.gen_id, gen_id);
+        assert_eq!(vmgenid.interrupt_evt.read().unwrap(), 1);
+    }
+}
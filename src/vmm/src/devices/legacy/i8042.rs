@@ -9,10 +9,11 @@ use std::io;
 use std::num::Wrapping;
 
 use log::warn;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 
 use crate::logger::{error, IncMetric, SharedIncMetric};
+use crate::snapshot::Persist;
 
 /// Errors thrown by the i8042 device.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
@@ -25,6 +26,16 @@ pub enum I8042Error {
     KbdInterruptFailure(io::Error),
 }
 
+/// Behavior of the i8042 device when its internal buffer is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum I8042OverflowPolicy {
+    /// Reject new bytes with `InternalBufferFull`.
+    #[default]
+    Reject,
+    /// Discard the oldest unread bytes to make room for the new ones.
+    DropOldest,
+}
+
 /// Metrics specific to the i8042 device.
 #[derive(Debug, Serialize)]
 pub(super) struct I8042DeviceMetrics {
@@ -34,6 +45,8 @@ pub(super) struct I8042DeviceMetrics {
     missed_read_count: SharedIncMetric,
     /// Number of superfluous write intents on this i8042 device.
     missed_write_count: SharedIncMetric,
+    /// Number of unread bytes discarded because the internal buffer was full.
+    overflow_drop_count: SharedIncMetric,
     /// Bytes read by this device.
     read_count: SharedIncMetric,
     /// Number of resets done by this device.
@@ -48,6 +61,7 @@ impl I8042DeviceMetrics {
             error_count: SharedIncMetric::new(),
             missed_read_count: SharedIncMetric::new(),
             missed_write_count: SharedIncMetric::new(),
+            overflow_drop_count: SharedIncMetric::new(),
             read_count: SharedIncMetric::new(),
             reset_count: SharedIncMetric::new(),
             write_count: SharedIncMetric::new(),
@@ -68,10 +82,16 @@ const OFS_DATA: u64 = 0;
 /// These values are written by the guest driver to port 0x64.
 const CMD_READ_CTR: u8 = 0x20; // Read control register
 const CMD_WRITE_CTR: u8 = 0x60; // Write control register
+const CMD_SELF_TEST: u8 = 0xAA; // Controller self-test
 const CMD_READ_OUTP: u8 = 0xD0; // Read output port
 const CMD_WRITE_OUTP: u8 = 0xD1; // Write output port
+const CMD_DISABLE_A20: u8 = 0xDD; // Disable the A20 gate
+const CMD_ENABLE_A20: u8 = 0xDF; // Enable the A20 gate
 const CMD_RESET_CPU: u8 = 0xFE; // Reset CPU
 
+/// Response of a successful controller self-test
+const SELF_TEST_OK: u8 = 0x55;
+
 /// i8042 status register bits
 const SB_OUT_DATA_AVAIL: u8 = 0x0001; // Data available at port 0x60
 const SB_I8042_CMD_DATA: u8 = 0x0008; // i8042 expecting command parameter at port 0x60
@@ -81,6 +101,9 @@ const SB_KBD_ENABLED: u8 = 0x0010; // 1 = kbd enabled, 0 = kbd locked
 const CB_KBD_INT: u8 = 0x0001; // kbd interrupt enabled
 const CB_POST_OK: u8 = 0x0004; // POST ok (should always be 1)
 
+/// i8042 output port bits
+const OB_A20: u8 = 0x0002; // A20 gate enabled
+
 /// Key scan codes
 const KEY_CTRL: u16 = 0x0014;
 const KEY_ALT: u16 = 0x0011;
@@ -114,6 +137,9 @@ pub struct I8042Device {
     buf: [u8; BUF_SIZE],
     bhead: Wrapping<usize>,
     btail: Wrapping<usize>,
+
+    /// What to do when pushing into a full internal buffer.
+    overflow_policy: I8042OverflowPolicy,
 }
 
 impl I8042Device {
@@ -124,22 +150,38 @@ impl I8042Device {
             kbd_interrupt_evt,
             control: CB_POST_OK | CB_KBD_INT,
             cmd: 0,
-            outp: 0,
+            // The A20 line is never masked under KVM, report the gate as enabled.
+            outp: OB_A20,
             status: SB_KBD_ENABLED,
             buf: [0; BUF_SIZE],
             bhead: Wrapping(0),
             btail: Wrapping(0),
+            overflow_policy: I8042OverflowPolicy::default(),
         }
     }
 
+    /// Returns the registers to their initial values and discards the unread bytes, as after
+    /// construction. Unlike `CMD_RESET_CPU`, this does not signal the reset event.
+    pub fn reset_state(&mut self) {
+        self.flush_buf();
+        self.status = SB_KBD_ENABLED;
+        self.control = CB_POST_OK | CB_KBD_INT;
+        self.outp = OB_A20;
+        self.cmd = 0;
+        METRICS.reset_count.inc();
+    }
+
+    /// Sets the behavior of the device when its internal buffer is full.
+    pub fn set_overflow_policy(&mut self, overflow_policy: I8042OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
     /// Signal a ctrl-alt-del (reset) event.
     #[inline]
     pub fn trigger_ctrl_alt_del(&mut self) -> Result<(), I8042Error> {
         // The CTRL+ALT+DEL sequence is 4 bytes in total (1 extended key + 2 normal keys).
-        // Fail if we don't have room for the whole sequence.
-        if BUF_SIZE - self.buf_len() < 4 {
-            return Err(I8042Error::InternalBufferFull);
-        }
+        // Make sure we have room for the whole sequence.
+        self.reserve(4)?;
         self.trigger_key(KEY_CTRL)?;
         self.trigger_key(KEY_ALT)?;
         self.trigger_key(KEY_DEL)?;
@@ -158,10 +200,8 @@ impl I8042Device {
 
     fn trigger_key(&mut self, key: u16) -> Result<(), I8042Error> {
         if key & 0xff00 != 0 {
-            // Check if there is enough room in the buffer, before pushing an extended (2-byte) key.
-            if BUF_SIZE - self.buf_len() < 2 {
-                return Err(I8042Error::InternalBufferFull);
-            }
+            // Make sure there is enough room in the buffer, before pushing an extended (2-byte) key.
+            self.reserve(2)?;
             self.push_byte((key >> 8) as u8)?;
         }
         self.push_byte((key & 0xff) as u8)?;
@@ -172,12 +212,27 @@ impl I8042Device {
         }
     }
 
+    /// Makes room for `len` more bytes in the internal buffer, according to the overflow policy.
+    fn reserve(&mut self, len: usize) -> Result<(), I8042Error> {
+        let free = BUF_SIZE - self.buf_len();
+        if free >= len {
+            return Ok(());
+        }
+        match self.overflow_policy {
+            I8042OverflowPolicy::Reject => Err(I8042Error::InternalBufferFull),
+            I8042OverflowPolicy::DropOldest => {
+                let dropped = len - free;
+                self.bhead += Wrapping(dropped);
+                METRICS.overflow_drop_count.add(dropped as u64);
+                Ok(())
+            }
+        }
+    }
+
     #[inline]
     fn push_byte(&mut self, byte: u8) -> Result<(), I8042Error> {
         self.status |= SB_OUT_DATA_AVAIL;
-        if self.buf_len() == BUF_SIZE {
-            return Err(I8042Error::InternalBufferFull);
-        }
+        self.reserve(1)?;
         self.buf[self.btail.0 % BUF_SIZE] = byte;
         self.btail += Wrapping(1usize);
         Ok(())
@@ -284,6 +339,13 @@ impl I8042Device {
                 self.status |= SB_I8042_CMD_DATA;
                 self.cmd = data[0];
             }
+            OFS_STATUS if data[0] == CMD_SELF_TEST => {
+                // The guest wants the controller to test itself. There is nothing to test, so
+                // report success, for the next inb(0x60).
+                self.flush_buf();
+                // Buffer is empty, push() will always succeed.
+                self.push_byte(SELF_TEST_OK).unwrap();
+            }
             OFS_STATUS if data[0] == CMD_READ_OUTP => {
                 // The guest wants to read the output port (for lack of a better name - this is
                 // just another register on the 8042, that happens to also have its bits connected
@@ -300,6 +362,8 @@ impl I8042Device {
                 self.status |= SB_I8042_CMD_DATA;
                 self.cmd = data[0];
             }
+            OFS_STATUS if data[0] == CMD_DISABLE_A20 => self.outp &= !OB_A20,
+            OFS_STATUS if data[0] == CMD_ENABLE_A20 => self.outp |= OB_A20,
             OFS_DATA if (self.status & SB_I8042_CMD_DATA) != 0 => {
                 // The guest is writing to port 0x60. This byte can either be:
                 // 1. the payload byte of a CMD_WRITE_CTR or CMD_WRITE_OUTP command, in which case
@@ -338,6 +402,78 @@ impl I8042Device {
     }
 }
 
+/// State of an i8042 device, saved in snapshots.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct I8042DeviceState {
+    /// Status register.
+    pub status: u8,
+    /// Control register.
+    pub control: u8,
+    /// Output port.
+    pub outp: u8,
+    /// Last command sent to port 0x64.
+    pub cmd: u8,
+    /// Bytes of the internal buffer not yet read by the guest.
+    pub buffer: Vec<u8>,
+}
+
+/// Arguments needed to restore an i8042 device.
+#[derive(Debug)]
+pub struct I8042ConstructorArgs {
+    /// CPU reset event.
+    pub reset_evt: EventFd,
+    /// Keyboard interrupt event.
+    pub kbd_interrupt_evt: EventFd,
+}
+
+impl Persist<'_> for I8042Device {
+    type State = I8042DeviceState;
+    type ConstructorArgs = I8042ConstructorArgs;
+    type Error = I8042Error;
+
+    fn save(&self) -> Self::State {
+        I8042DeviceState {
+            status: self.status,
+            control: self.control,
+            outp: self.outp,
+            cmd: self.cmd,
+            buffer: (0..self.buf_len())
+                .map(|i| self.buf[(self.bhead.0 + i) % BUF_SIZE])
+                .collect(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let mut i8042 = I8042Device::new(
+            constructor_args.reset_evt,
+            constructor_args.kbd_interrupt_evt,
+        );
+        i8042.restore_state(state)?;
+        Ok(i8042)
+    }
+}
+
+impl I8042Device {
+    /// Restores the registers and the unread bytes of the device from `state`, keeping its
+    /// events and overflow policy.
+    pub fn restore_state(&mut self, state: &I8042DeviceState) -> Result<(), I8042Error> {
+        if state.buffer.len() > BUF_SIZE {
+            return Err(I8042Error::InternalBufferFull);
+        }
+        self.buf[..state.buffer.len()].copy_from_slice(&state.buffer);
+        self.bhead = Wrapping(0);
+        self.btail = Wrapping(state.buffer.len());
+        self.status = state.status;
+        self.control = state.control;
+        self.outp = state.outp;
+        self.cmd = state.cmd;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -428,6 +564,80 @@ mod tests {
         assert_eq!(data[0], 0xFA);
     }
 
+    #[test]
+    fn test_i8042_self_test() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        let mut data = [1];
+
+        // Unread bytes are discarded in favor of the self-test response.
+        i8042.push_byte(0x52).unwrap();
+        data[0] = CMD_SELF_TEST;
+        i8042.bus_write(OFS_STATUS, &data);
+        assert_ne!(i8042.status & SB_OUT_DATA_AVAIL, 0);
+        i8042.bus_read(OFS_DATA, &mut data);
+        assert_eq!(data[0], SELF_TEST_OK);
+        assert_eq!(i8042.status & SB_OUT_DATA_AVAIL, 0);
+    }
+
+    #[test]
+    fn test_i8042_reset_state() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        let reset_evt = i8042.reset_evt.try_clone().unwrap();
+
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_CTR]);
+        i8042.bus_write(OFS_DATA, &[0x52]);
+        i8042.bus_write(OFS_STATUS, &[CMD_DISABLE_A20]);
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_OUTP]);
+        i8042.push_byte(0x52).unwrap();
+
+        let before = METRICS.reset_count.count();
+        i8042.reset_state();
+        assert_eq!(METRICS.reset_count.count(), before + 1);
+        assert_eq!(i8042.status, SB_KBD_ENABLED);
+        assert_eq!(i8042.control, CB_POST_OK | CB_KBD_INT);
+        assert_eq!(i8042.outp, OB_A20);
+        assert_eq!(i8042.cmd, 0);
+        assert_eq!(i8042.buf_len(), 0);
+        // The CPU reset event is left alone.
+        assert!(reset_evt.read().is_err());
+    }
+
+    #[test]
+    fn test_i8042_a20_gate() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        let read_outp = |i8042: &mut I8042Device| {
+            let mut data = [CMD_READ_OUTP];
+            i8042.bus_write(OFS_STATUS, &data);
+            i8042.bus_read(OFS_DATA, &mut data);
+            data[0]
+        };
+
+        // The gate starts enabled.
+        assert_ne!(read_outp(&mut i8042) & OB_A20, 0);
+
+        i8042.bus_write(OFS_STATUS, &[CMD_DISABLE_A20]);
+        assert_eq!(read_outp(&mut i8042) & OB_A20, 0);
+        i8042.bus_write(OFS_STATUS, &[CMD_ENABLE_A20]);
+        assert_ne!(read_outp(&mut i8042) & OB_A20, 0);
+
+        // Writing the output port sets the gate as well.
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_OUTP]);
+        i8042.bus_write(OFS_DATA, &[0x01]);
+        assert_eq!(read_outp(&mut i8042), 0x01);
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_OUTP]);
+        i8042.bus_write(OFS_DATA, &[0x01 | OB_A20]);
+        assert_eq!(read_outp(&mut i8042), 0x01 | OB_A20);
+    }
+
     #[test]
     fn test_i8042_buffer() {
         let mut i8042 = I8042Device::new(
@@ -455,6 +665,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_i8042_overflow_drop_oldest() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        i8042.set_overflow_policy(I8042OverflowPolicy::DropOldest);
+
+        // Fill up the buffer.
+        for i in 0..BUF_SIZE {
+            i8042.push_byte(i.try_into().unwrap()).unwrap();
+        }
+        assert_eq!(i8042.buf_len(), BUF_SIZE);
+
+        // New keys are still accepted, evicting the oldest unread bytes.
+        let before = METRICS.overflow_drop_count.count();
+        i8042.trigger_key(KEY_CTRL).unwrap();
+        i8042.trigger_key(KEY_DEL).unwrap();
+        i8042.trigger_ctrl_alt_del().unwrap();
+        assert_eq!(i8042.buf_len(), BUF_SIZE);
+        // 1 byte for CTRL, 2 bytes for DEL and 4 bytes for CTRL+ALT+DEL.
+        assert_eq!(METRICS.overflow_drop_count.count(), before + 7);
+
+        // The oldest bytes were dropped and the newest ones are still there, in order.
+        assert_eq!(i8042.pop_byte().unwrap(), 7);
+        for _ in 8..BUF_SIZE {
+            i8042.pop_byte().unwrap();
+        }
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_CTRL & 0xFF) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_DEL >> 8) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_DEL & 0xFF) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_CTRL & 0xFF) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_ALT & 0xFF) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_DEL >> 8) as u8);
+        assert_eq!(i8042.pop_byte().unwrap(), (KEY_DEL & 0xFF) as u8);
+        assert!(i8042.pop_byte().is_none());
+    }
+
     #[test]
     fn test_i8042_kbd() {
         let mut i8042 = I8042Device::new(
@@ -539,4 +787,66 @@ mod tests {
             I8042Error::KbdInterruptDisabled
         )
     }
+
+    #[test]
+    fn test_i8042_persistence() {
+        let mut i8042 = I8042Device::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        );
+        // The guest disables the keyboard interrupt and starts writing the output port.
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_CTR]);
+        i8042.bus_write(OFS_DATA, &[CB_POST_OK]);
+        i8042.bus_write(OFS_STATUS, &[CMD_WRITE_OUTP]);
+        // Wrap the unread bytes around the end of the internal buffer.
+        for _ in 0..BUF_SIZE - 1 {
+            i8042.push_byte(0).unwrap();
+            i8042.pop_byte().unwrap();
+        }
+        i8042.push_byte(0x12).unwrap();
+        i8042.push_byte(0x34).unwrap();
+
+        let mut mem = vec![0u8; 4096];
+        crate::snapshot::Snapshot::serialize(&mut mem.as_mut_slice(), &i8042.save()).unwrap();
+        let state: I8042DeviceState =
+            crate::snapshot::Snapshot::deserialize(&mut mem.as_slice()).unwrap();
+        assert_eq!(state.buffer, vec![0x12, 0x34]);
+
+        let mut restored = I8042Device::restore(
+            I8042ConstructorArgs {
+                reset_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+                kbd_interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored.save(), i8042.save());
+
+        // The pending command completes, and the registers read back unchanged.
+        let mut data = [0];
+        restored.bus_read(OFS_DATA, &mut data);
+        assert_eq!(data[0], 0x12);
+        restored.bus_read(OFS_DATA, &mut data);
+        assert_eq!(data[0], 0x34);
+        restored.bus_write(OFS_DATA, &[0x01]);
+        assert_eq!(restored.outp, 0x01);
+        restored.bus_write(OFS_STATUS, &[CMD_READ_CTR]);
+        restored.bus_read(OFS_DATA, &mut data);
+        assert_eq!(data[0], CB_POST_OK);
+        // With the keyboard interrupt disabled, key presses do not raise it.
+        assert!(matches!(
+            restored.trigger_ctrl_alt_del(),
+            Err(I8042Error::KbdInterruptDisabled)
+        ));
+
+        // A buffer larger than the device's is rejected.
+        let state = I8042DeviceState {
+            buffer: vec![0; BUF_SIZE + 1],
+            ..Default::default()
+        };
+        assert!(matches!(
+            restored.restore_state(&state),
+            Err(I8042Error::InternalBufferFull)
+        ));
+    }
 }
@@ -19,11 +19,16 @@ use serde::Serializer;
 use utils::eventfd::EventFd;
 use vm_superio::Trigger;
 
-pub use self::i8042::{I8042Device, I8042Error as I8042DeviceError};
+pub use self::i8042::{
+    I8042ConstructorArgs, I8042Device, I8042DeviceState, I8042Error as I8042DeviceError,
+    I8042OverflowPolicy,
+};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTCDevice;
 pub use self::serial::{
-    SerialDevice, SerialEventsWrapper, SerialWrapper, IER_RDA_BIT, IER_RDA_OFFSET,
+    PanicWatcher, SerialConstructorArgs, SerialDevice, SerialDeviceState, SerialEventsWrapper,
+    SerialFile, SerialOut, SerialOutputBuffering, SerialPersistError, SerialRxTrigger,
+    SerialRxTriggerLevel, SerialSocket, SerialWrapper, IER_RDA_BIT, IER_RDA_OFFSET,
 };
 
 /// Wrapper for implementing the trigger functionality for `EventFd`.
@@ -57,6 +57,11 @@ impl RtcEvents for &'static RTCDeviceMetrics {
 /// Stores aggregated metrics
 pub static METRICS: RTCDeviceMetrics = RTCDeviceMetrics::new();
 
+/// Offset of the data register, holding the current time of the RTC.
+const RTCDR: u16 = 0x000;
+/// Offset of the load register, used to set the current time of the RTC.
+const RTCLR: u16 = 0x008;
+
 /// Wrapper over vm_superio's RTC implementation.
 #[derive(Debug)]
 pub struct RTCDevice(pub vm_superio::Rtc<&'static RTCDeviceMetrics>);
@@ -77,6 +82,18 @@ impl std::ops::DerefMut for RTCDevice {
 
 // Implements Bus functions for AMBA PL031 RTC device
 impl RTCDevice {
+    /// Returns the current time of the RTC, in seconds since the epoch.
+    pub fn time(&mut self) -> u32 {
+        let mut data = [0u8; 4];
+        self.read(RTCDR, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    /// Sets the current time of the RTC, in seconds since the epoch.
+    pub fn set_time(&mut self, secs: u32) {
+        self.write(RTCLR, &secs.to_le_bytes());
+    }
+
     pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
         if let (Ok(offset), 4) = (u16::try_from(offset), data.len()) {
             // read() function from RTC implementation expects a slice of
@@ -132,6 +149,17 @@ mod tests {
         assert_eq!(error_count_after - error_count_before, 1);
     }
 
+    #[test]
+    fn test_rtc_set_time() {
+        static TEST_RTC_SET_TIME_METRICS: RTCDeviceMetrics = RTCDeviceMetrics::new();
+        let mut rtc_pl031 = RTCDevice(Rtc::with_events(&TEST_RTC_SET_TIME_METRICS));
+
+        let time = rtc_pl031.time() + 3600;
+        rtc_pl031.set_time(time);
+        // Allow for the clock to tick between the two calls.
+        assert!((time..=time + 1).contains(&rtc_pl031.time()));
+    }
+
     #[test]
     fn test_rtc_invalid_buf_len() {
         static TEST_RTC_INVALID_BUF_LEN_METRICS: RTCDeviceMetrics = RTCDeviceMetrics::new();
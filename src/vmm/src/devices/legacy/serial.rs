@@ -7,19 +7,26 @@
 
 //! Implements a wrapper over an UART serial device.
 use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use log::{error, warn};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::EventSet;
-use vm_superio::serial::{Error as SerialError, SerialEvents};
+use vm_superio::serial::{Error as SerialError, SerialEvents, SerialState};
 use vm_superio::{Serial, Trigger};
 
 use crate::devices::legacy::EventFdTrigger;
 use crate::logger::{IncMetric, SharedIncMetric};
+use crate::snapshot::Persist;
 
 /// Received Data Available interrupt - for letting the driver know that
 /// there is some pending data to be processed.
@@ -27,6 +34,13 @@ pub const IER_RDA_BIT: u8 = 0b0000_0001;
 /// Received Data Available interrupt offset
 pub const IER_RDA_OFFSET: u8 = 1;
 
+/// Time after which received bytes below the RX trigger level are handed to the guest anyway.
+/// This is the character timeout of 16550 UARTs: four character times at 9600 baud.
+const RX_CHAR_TIMEOUT: Duration = Duration::from_micros(4200);
+
+/// Longest partial line held back when the serial output is line-buffered.
+const MAX_PENDING_LINE: usize = 4096;
+
 /// Metrics specific to the UART device.
 #[derive(Debug, Serialize)]
 pub struct SerialDeviceMetrics {
@@ -38,6 +52,10 @@ pub struct SerialDeviceMetrics {
     pub missed_read_count: SharedIncMetric,
     /// Number of write calls that did not trigger a write.
     pub missed_write_count: SharedIncMetric,
+    /// Number of output bytes dropped because no client was connected to the output socket.
+    pub dropped_bytes_count: SharedIncMetric,
+    /// Number of guest panics seen on the serial output.
+    pub panic_count: SharedIncMetric,
     /// Number of succeeded read calls.
     pub read_count: SharedIncMetric,
     /// Number of succeeded write calls.
@@ -51,6 +69,8 @@ impl SerialDeviceMetrics {
             flush_count: SharedIncMetric::new(),
             missed_read_count: SharedIncMetric::new(),
             missed_write_count: SharedIncMetric::new(),
+            dropped_bytes_count: SharedIncMetric::new(),
+            panic_count: SharedIncMetric::new(),
             read_count: SharedIncMetric::new(),
             write_count: SharedIncMetric::new(),
         }
@@ -88,6 +108,55 @@ impl<EV: SerialEvents + Debug, W: Write + Debug> RawIOHandler for Serial<EventFd
     }
 }
 
+/// Number of received bytes raising the Received Data Available interrupt, as the RX FIFO
+/// trigger levels of 16550 UARTs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SerialRxTriggerLevel {
+    /// Interrupt on every received byte.
+    #[default]
+    One,
+    /// Interrupt once 4 bytes were received.
+    Four,
+    /// Interrupt once 8 bytes were received.
+    Eight,
+    /// Interrupt once 14 bytes were received.
+    Fourteen,
+}
+
+impl SerialRxTriggerLevel {
+    /// Number of bytes raising the interrupt.
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::One => 1,
+            Self::Four => 4,
+            Self::Eight => 8,
+            Self::Fourteen => 14,
+        }
+    }
+}
+
+/// How the serial output is forwarded to its destination.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SerialOutputBuffering {
+    /// Forward every byte as soon as the guest writes it.
+    #[default]
+    Byte,
+    /// Hold the bytes back until the guest completes a line, so that the output doesn't
+    /// interleave with other writers of the same destination mid-line.
+    Line,
+}
+
+/// Received bytes held back until the RX trigger level is reached.
+#[derive(Debug, Default)]
+pub struct SerialRxTrigger {
+    level: SerialRxTriggerLevel,
+    pending: Vec<u8>,
+    // Delivers the pending bytes after the character timeout. Only created for trigger levels
+    // above one byte.
+    timer: Option<TimerFd>,
+    timer_registered: bool,
+}
+
 /// Wrapper over available events (i.e metrics, buffer ready etc).
 #[derive(Debug)]
 pub struct SerialEventsWrapper {
@@ -123,22 +192,305 @@ impl SerialEvents for SerialEventsWrapper {
     }
 }
 
+/// Scans the serial output for the signature of a guest panic.
+///
+/// The callback is invoked the first time the signature is seen, later occurrences are ignored.
+pub struct PanicWatcher {
+    signature: Vec<u8>,
+    // The latest output bytes, up to the length of the signature.
+    window: Vec<u8>,
+    notified: bool,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+impl Debug for PanicWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PanicWatcher")
+            .field("signature", &String::from_utf8_lossy(&self.signature))
+            .field("notified", &self.notified)
+            .finish()
+    }
+}
+
+impl PanicWatcher {
+    /// Signature printed by Linux guests when they panic.
+    pub const DEFAULT_SIGNATURE: &'static str = "Kernel panic";
+
+    /// Creates a watcher invoking `callback` when `signature` is written to the serial output.
+    pub fn new(signature: &str, callback: Box<dyn FnMut() + Send>) -> Self {
+        PanicWatcher {
+            signature: signature.as_bytes().to_vec(),
+            window: Vec::with_capacity(signature.len()),
+            notified: false,
+            callback,
+        }
+    }
+
+    /// Whether a guest panic was seen.
+    pub fn notified(&self) -> bool {
+        self.notified
+    }
+
+    fn scan(&mut self, buf: &[u8]) {
+        if self.notified || self.signature.is_empty() {
+            return;
+        }
+        for &byte in buf {
+            if self.window.len() == self.signature.len() {
+                self.window.remove(0);
+            }
+            self.window.push(byte);
+            if self.window == self.signature {
+                self.notified = true;
+                METRICS.panic_count.inc();
+                warn!("Guest panic detected on the serial console.");
+                (self.callback)();
+                return;
+            }
+        }
+    }
+}
+
+/// Serial output streamed to the client of a Unix domain socket.
+///
+/// Clients are accepted lazily when there is output to send. Output is dropped while no client is
+/// connected, or when the client doesn't keep up, so that a slow consumer never stalls the guest.
+#[derive(Debug)]
+pub struct SerialSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    client: Option<UnixStream>,
+}
+
+impl SerialSocket {
+    /// Listens for a client of the serial output on `path`.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let listener = UnixListener::bind(path.as_ref())?;
+        listener.set_nonblocking(true)?;
+        Ok(SerialSocket {
+            path: path.as_ref().to_path_buf(),
+            listener,
+            client: None,
+        })
+    }
+
+    /// Path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn client(&mut self) -> Option<&UnixStream> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => self.client = Some(stream),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => warn!("Failed to accept a serial output client: {}", err),
+            }
+        }
+        self.client.as_ref()
+    }
+
+    fn send(&mut self, buf: &[u8]) -> usize {
+        let client = match self.client() {
+            Some(client) => client,
+            None => return 0,
+        };
+        // SAFETY: The buffer is valid for reads of `buf.len()` bytes. MSG_NOSIGNAL makes a
+        // disconnected client return EPIPE instead of raising SIGPIPE.
+        let sent = unsafe {
+            libc::send(
+                client.as_raw_fd(),
+                buf.as_ptr().cast(),
+                buf.len(),
+                libc::MSG_NOSIGNAL | libc::MSG_DONTWAIT,
+            )
+        };
+        match usize::try_from(sent) {
+            Ok(sent) => sent,
+            Err(_) => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    warn!("Serial output client disconnected: {}", err);
+                    self.client = None;
+                }
+                0
+            }
+        }
+    }
+}
+
+impl Drop for SerialSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Serial output written to a file or a named pipe.
+///
+/// The file is opened lazily when there is output to write, so a named pipe may be created, and
+/// its reader started, after the output is redirected to it. Writes never block: output is dropped
+/// while the path doesn't exist, while a pipe has no reader, or when the reader doesn't keep up.
+#[derive(Debug)]
+pub struct SerialFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl SerialFile {
+    /// Writes the serial output to `path`. Fails if `path` exists but cannot be opened for
+    /// writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut serial_file = SerialFile {
+            path: path.as_ref().to_path_buf(),
+            file: None,
+        };
+        match serial_file.open_file() {
+            Ok(file) => serial_file.file = Some(file),
+            Err(err) if Self::is_not_ready(&err) => (),
+            Err(err) => return Err(err),
+        }
+        Ok(serial_file)
+    }
+
+    /// Path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open_file(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .append(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+    }
+
+    // Whether opening failed because the file or the reader of the pipe is yet to come.
+    fn is_not_ready(err: &io::Error) -> bool {
+        err.kind() == io::ErrorKind::NotFound || err.raw_os_error() == Some(libc::ENXIO)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> usize {
+        if self.file.is_none() {
+            self.file = self.open_file().ok();
+        }
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return 0,
+        };
+        match file.write(buf) {
+            Ok(written) => written,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(err) => {
+                // The file is reopened on the next write, e.g. once a new reader opened the pipe.
+                warn!("Serial output file {:?} closed: {}", self.path, err);
+                self.file = None;
+                0
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum SerialOut {
     Sink(std::io::Sink),
     Stdout(std::io::Stdout),
+    /// Output scanned for guest panics before being forwarded.
+    Watched(Box<SerialOut>, PanicWatcher),
+    /// Output streamed to the client of a Unix domain socket.
+    UnixSocket(SerialSocket),
+    /// Output written to a file or a named pipe.
+    File(SerialFile),
+    /// Output forwarded one complete line at a time, with the partial line held back.
+    LineBuffered(Box<SerialOut>, Vec<u8>),
+}
+
+impl SerialOut {
+    // Destination of the output, under the wrappers watching or buffering it.
+    fn destination_mut(&mut self) -> &mut SerialOut {
+        match self {
+            Self::Watched(out, _) | Self::LineBuffered(out, _) => out.destination_mut(),
+            _ => self,
+        }
+    }
+
+    fn buffering(&self) -> SerialOutputBuffering {
+        match self {
+            Self::Watched(out, _) => out.buffering(),
+            Self::LineBuffered(..) => SerialOutputBuffering::Line,
+            _ => SerialOutputBuffering::Byte,
+        }
+    }
+
+    // Adds or removes the line buffer around the destination of the output. Removing it writes
+    // out the partial line it holds.
+    fn set_buffering(&mut self, buffering: SerialOutputBuffering) -> io::Result<()> {
+        match (self, buffering) {
+            (Self::Watched(out, _), _) => out.set_buffering(buffering),
+            (this @ Self::LineBuffered(..), SerialOutputBuffering::Byte) => {
+                let Self::LineBuffered(mut out, pending) =
+                    std::mem::replace(this, Self::Sink(io::sink()))
+                else {
+                    unreachable!()
+                };
+                let res = out.write_all(&pending);
+                *this = *out;
+                res
+            }
+            (this, SerialOutputBuffering::Line) if !matches!(this, Self::LineBuffered(..)) => {
+                let out = std::mem::replace(this, Self::Sink(io::sink()));
+                *this = Self::LineBuffered(Box::new(out), Vec::new());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
 }
+
 impl std::io::Write for SerialOut {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match self {
             Self::Sink(sink) => sink.write(buf),
             Self::Stdout(stdout) => stdout.write(buf),
+            Self::Watched(out, watcher) => {
+                let count = out.write(buf)?;
+                watcher.scan(&buf[..count]);
+                Ok(count)
+            }
+            Self::UnixSocket(socket) => {
+                let sent = socket.send(buf);
+                METRICS.dropped_bytes_count.add((buf.len() - sent) as u64);
+                Ok(buf.len())
+            }
+            Self::File(file) => {
+                let written = file.send(buf);
+                METRICS
+                    .dropped_bytes_count
+                    .add((buf.len() - written) as u64);
+                Ok(buf.len())
+            }
+            Self::LineBuffered(out, pending) => {
+                pending.extend_from_slice(buf);
+                // A line longer than the buffer is forwarded in pieces.
+                let end = match pending.iter().rposition(|&byte| byte == b'\n') {
+                    Some(newline) => newline + 1,
+                    None if pending.len() >= MAX_PENDING_LINE => pending.len(),
+                    None => return Ok(buf.len()),
+                };
+                // Bytes that couldn't be written stay pending until the next line.
+                out.write_all(&pending[..end])?;
+                pending.drain(..end);
+                Ok(buf.len())
+            }
         }
     }
     fn flush(&mut self) -> std::io::Result<()> {
         match self {
             Self::Sink(sink) => sink.flush(),
             Self::Stdout(stdout) => stdout.flush(),
+            Self::Watched(out, _) => out.flush(),
+            Self::UnixSocket(_) | Self::File(_) => Ok(()),
+            // The partial line is held back until the guest completes it.
+            Self::LineBuffered(out, _) => out.flush(),
         }
     }
 }
@@ -150,6 +502,20 @@ pub struct SerialWrapper<T: Trigger, EV: SerialEvents, I: Read + AsRawFd + Send>
     pub serial: Serial<T, EV, SerialOut>,
     /// Input to the serial device (needs to be readable).
     pub input: Option<I>,
+    /// Batching of the input according to the RX trigger level.
+    pub rx_trigger: SerialRxTrigger,
+}
+
+impl<T: Trigger, EV: SerialEvents, I: Read + AsRawFd + Send> SerialWrapper<T, EV, I> {
+    /// Creates a serial device reading its input from `input`, with an RX trigger level of one
+    /// byte.
+    pub fn new(serial: Serial<T, EV, SerialOut>, input: Option<I>) -> Self {
+        SerialWrapper {
+            serial,
+            input,
+            rx_trigger: SerialRxTrigger::default(),
+        }
+    }
 }
 
 impl<I: Read + AsRawFd + Send + Debug> SerialWrapper<EventFdTrigger, SerialEventsWrapper, I> {
@@ -176,7 +542,10 @@ impl<I: Read + AsRawFd + Send + Debug> SerialWrapper<EventFdTrigger, SerialEvent
     }
 
     fn recv_bytes(&mut self) -> io::Result<usize> {
-        let avail_cap = self.serial.fifo_capacity();
+        let avail_cap = self
+            .serial
+            .fifo_capacity()
+            .saturating_sub(self.rx_trigger.pending.len());
         if avail_cap == 0 {
             return Err(io::Error::from_raw_os_error(libc::ENOBUFS));
         }
@@ -185,9 +554,7 @@ impl<I: Read + AsRawFd + Send + Debug> SerialWrapper<EventFdTrigger, SerialEvent
             let mut out = vec![0u8; avail_cap];
             let count = input.read(&mut out)?;
             if count > 0 {
-                self.serial
-                    .raw_input(&out[..count])
-                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOBUFS))?;
+                self.receive(&out[..count])?;
             }
 
             return Ok(count);
@@ -196,6 +563,122 @@ impl<I: Read + AsRawFd + Send + Debug> SerialWrapper<EventFdTrigger, SerialEvent
         Err(io::Error::from_raw_os_error(libc::ENOTTY))
     }
 
+    /// Sets the number of received bytes raising the Received Data Available interrupt.
+    ///
+    /// Bytes below the trigger level are handed to the guest after a character timeout.
+    pub fn set_rx_trigger_level(&mut self, level: SerialRxTriggerLevel) -> io::Result<()> {
+        if level != SerialRxTriggerLevel::One && self.rx_trigger.timer.is_none() {
+            self.rx_trigger.timer = Some(TimerFd::new_custom(ClockId::Monotonic, true, true)?);
+        }
+        self.rx_trigger.level = level;
+        if self.rx_trigger.pending.len() >= level.bytes() {
+            self.flush_rx_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of received bytes raising the Received Data Available interrupt.
+    pub fn rx_trigger_level(&self) -> SerialRxTriggerLevel {
+        self.rx_trigger.level
+    }
+
+    /// Replaces the destination of the serial output. A panic watcher or a line buffer on the
+    /// current output carries over to the new one.
+    pub fn set_output(&mut self, out: SerialOut) {
+        *self.serial.writer_mut().destination_mut() = out;
+    }
+
+    /// Scans the output for guest panics with `watcher`, whichever destination it is written to.
+    pub fn watch_for_panics(&mut self, watcher: PanicWatcher) {
+        let writer = self.serial.writer_mut();
+        let out = std::mem::replace(writer, SerialOut::Sink(io::sink()));
+        *writer = SerialOut::Watched(Box::new(out), watcher);
+    }
+
+    /// Sets whether the serial output is forwarded byte by byte or one line at a time.
+    ///
+    /// Switching to byte-by-byte forwarding writes out the partial line held back so far.
+    pub fn set_output_buffering(&mut self, buffering: SerialOutputBuffering) -> io::Result<()> {
+        self.serial.writer_mut().set_buffering(buffering)
+    }
+
+    /// Returns whether the serial output is forwarded byte by byte or one line at a time.
+    pub fn output_buffering(&mut self) -> SerialOutputBuffering {
+        self.serial.writer_mut().buffering()
+    }
+
+    /// Hands the received bytes to the guest once the RX trigger level is reached.
+    fn receive(&mut self, data: &[u8]) -> io::Result<()> {
+        let level = self.rx_trigger.level.bytes();
+        if self.rx_trigger.pending.is_empty() && data.len() >= level {
+            return self.enqueue(data);
+        }
+
+        self.rx_trigger.pending.extend_from_slice(data);
+        if self.rx_trigger.pending.len() >= level {
+            return self.flush_rx_pending();
+        }
+        // As for the 16550 character timeout, every new byte restarts the timer.
+        if let Some(timer) = self.rx_trigger.timer.as_mut() {
+            timer.set_state(TimerState::Oneshot(RX_CHAR_TIMEOUT), SetTimeFlags::Default);
+        }
+        Ok(())
+    }
+
+    fn flush_rx_pending(&mut self) -> io::Result<()> {
+        if let Some(timer) = self.rx_trigger.timer.as_mut() {
+            timer.set_state(TimerState::Disarmed, SetTimeFlags::Default);
+        }
+        let pending = std::mem::take(&mut self.rx_trigger.pending);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.enqueue(&pending)
+    }
+
+    fn enqueue(&mut self, data: &[u8]) -> io::Result<()> {
+        self.serial
+            .raw_input(data)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOBUFS))
+    }
+
+    /// Hands the bytes below the RX trigger level to the guest once the character timeout expired.
+    fn process_rx_timeout(&mut self) {
+        let expired = self
+            .rx_trigger
+            .timer
+            .as_mut()
+            .map_or(0, |timer| timer.read());
+        if expired == 0 {
+            return;
+        }
+        if let Err(err) = self.flush_rx_pending() {
+            error!("Failed to deliver the pending serial input: {:?}", err);
+            METRICS.error_count.inc();
+        }
+    }
+
+    #[inline]
+    fn rx_timer_fd(&self) -> RawFd {
+        self.rx_trigger
+            .timer
+            .as_ref()
+            .map_or(-1, |timer| timer.as_raw_fd())
+    }
+
+    fn register_rx_timer(&mut self, ops: &mut EventOps) {
+        let timer_fd = self.rx_timer_fd();
+        if timer_fd < 0 || self.rx_trigger.timer_registered {
+            return;
+        }
+        match ops.add(Events::new(&timer_fd, EventSet::IN)) {
+            Ok(()) | Err(event_manager::Error::FdAlreadyRegistered) => {
+                self.rx_trigger.timer_registered = true
+            }
+            Err(err) => error!("Failed to register serial RX timer: {:?}", err),
+        }
+    }
+
     #[inline]
     fn buffer_ready_evt_fd(&self) -> RawFd {
         self.serial
@@ -222,6 +705,154 @@ impl<I: Read + AsRawFd + Send + Debug> SerialWrapper<EventFdTrigger, SerialEvent
 /// Type for representing a serial device.
 pub type SerialDevice<I> = SerialWrapper<EventFdTrigger, SerialEventsWrapper, I>;
 
+/// State of a serial device, saved in snapshots.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerialDeviceState {
+    /// Divisor latch, low byte.
+    pub baud_divisor_low: u8,
+    /// Divisor latch, high byte.
+    pub baud_divisor_high: u8,
+    /// Interrupt Enable Register.
+    pub interrupt_enable: u8,
+    /// Interrupt Identification Register.
+    pub interrupt_identification: u8,
+    /// Line Control Register.
+    pub line_control: u8,
+    /// Line Status Register.
+    pub line_status: u8,
+    /// Modem Control Register.
+    pub modem_control: u8,
+    /// Modem Status Register.
+    pub modem_status: u8,
+    /// Scratch Register.
+    pub scratch: u8,
+    /// Received bytes not yet read by the guest.
+    pub in_buffer: Vec<u8>,
+    /// Received bytes held back until the RX trigger level is reached.
+    pub rx_pending: Vec<u8>,
+}
+
+/// Errors for restoring a serial device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SerialPersistError {
+    /// The saved input buffer holds more bytes than the FIFO: {0}
+    FullFifo(usize),
+    /// Failed to clone the serial event file descriptors: {0}
+    EventFd(#[from] io::Error),
+}
+
+/// Arguments needed to restore a serial device.
+#[derive(Debug)]
+pub struct SerialConstructorArgs<I> {
+    /// Interrupt of the device.
+    pub interrupt_evt: EventFdTrigger,
+    /// Events of the device.
+    pub events: SerialEventsWrapper,
+    /// Output of the device.
+    pub out: SerialOut,
+    /// Input of the device.
+    pub input: Option<I>,
+}
+
+fn serial_from_state(
+    state: &SerialDeviceState,
+    interrupt_evt: EventFdTrigger,
+    events: SerialEventsWrapper,
+    out: SerialOut,
+) -> Result<Serial<EventFdTrigger, SerialEventsWrapper, SerialOut>, SerialPersistError> {
+    let serial_state = SerialState {
+        baud_divisor_low: state.baud_divisor_low,
+        baud_divisor_high: state.baud_divisor_high,
+        interrupt_enable: state.interrupt_enable,
+        interrupt_identification: state.interrupt_identification,
+        line_control: state.line_control,
+        line_status: state.line_status,
+        modem_control: state.modem_control,
+        modem_status: state.modem_status,
+        scratch: state.scratch,
+        in_buffer: state.in_buffer.clone(),
+    };
+    Serial::from_state(&serial_state, interrupt_evt, events, out)
+        .map_err(|_| SerialPersistError::FullFifo(state.in_buffer.len()))
+}
+
+impl<I: Read + AsRawFd + Send + Debug> Persist<'_> for SerialDevice<I> {
+    type State = SerialDeviceState;
+    type ConstructorArgs = SerialConstructorArgs<I>;
+    type Error = SerialPersistError;
+
+    fn save(&self) -> Self::State {
+        let state = self.serial.state();
+        SerialDeviceState {
+            baud_divisor_low: state.baud_divisor_low,
+            baud_divisor_high: state.baud_divisor_high,
+            interrupt_enable: state.interrupt_enable,
+            interrupt_identification: state.interrupt_identification,
+            line_control: state.line_control,
+            line_status: state.line_status,
+            modem_control: state.modem_control,
+            modem_status: state.modem_status,
+            scratch: state.scratch,
+            in_buffer: state.in_buffer,
+            rx_pending: self.rx_trigger.pending.clone(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let serial = serial_from_state(
+            state,
+            constructor_args.interrupt_evt,
+            constructor_args.events,
+            constructor_args.out,
+        )?;
+        Ok(SerialWrapper {
+            serial,
+            input: constructor_args.input,
+            rx_trigger: SerialRxTrigger {
+                pending: state.rx_pending.clone(),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl<I: Read + AsRawFd + Send + Debug> SerialDevice<I> {
+    /// Restores the registers and the buffered input of the device from `state`, keeping its
+    /// input, output and RX trigger level.
+    pub fn restore_state(&mut self, state: &SerialDeviceState) -> Result<(), SerialPersistError> {
+        let events = SerialEventsWrapper {
+            buffer_ready_event_fd: self
+                .serial
+                .events()
+                .buffer_ready_event_fd
+                .as_ref()
+                .map(EventFdTrigger::try_clone)
+                .transpose()?,
+        };
+        let mut serial = serial_from_state(
+            state,
+            self.serial.interrupt_evt().try_clone()?,
+            events,
+            SerialOut::Sink(std::io::sink()),
+        )?;
+        std::mem::swap(serial.writer_mut(), self.serial.writer_mut());
+        self.serial = serial;
+
+        self.rx_trigger.pending.clone_from(&state.rx_pending);
+        // The bytes held back before the snapshot are handed to the guest after a character
+        // timeout, as if they were just received.
+        if !self.rx_trigger.pending.is_empty() {
+            if let Some(timer) = self.rx_trigger.timer.as_mut() {
+                timer.set_state(TimerState::Oneshot(RX_CHAR_TIMEOUT), SetTimeFlags::Default);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<I: Read + AsRawFd + Send + Debug> MutEventSubscriber
     for SerialWrapper<EventFdTrigger, SerialEventsWrapper, I>
 {
@@ -242,6 +873,11 @@ impl<I: Read + AsRawFd + Send + Debug> MutEventSubscriber
             return;
         }
 
+        if self.rx_timer_fd() == event.fd() {
+            self.process_rx_timeout();
+            return;
+        }
+
         if buffer_ready_fd == event.fd() {
             match self.consume_buffer_ready_event() {
                 Ok(_) => (),
@@ -263,6 +899,9 @@ impl<I: Read + AsRawFd + Send + Debug> MutEventSubscriber
         // read from the serial input.
         match self.recv_bytes() {
             Ok(count) => {
+                if !self.rx_trigger.pending.is_empty() {
+                    self.register_rx_timer(ops);
+                }
                 // Handle EOF if the event came from the input source.
                 if input_fd == event.fd() && count == 0 {
                     unregister_source(ops, &input_fd);
@@ -365,6 +1004,9 @@ impl<I: Read + AsRawFd + Send + Debug + 'static>
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
 
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
     use utils::eventfd::EventFd;
 
     use super::*;
@@ -385,6 +1027,7 @@ mod tests {
                 SerialOut::Sink(std::io::sink()),
             ),
             input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
         };
         serial.serial.raw_input(&[b'a', b'b', b'c']).unwrap();
 
@@ -404,6 +1047,337 @@ mod tests {
         assert_eq!(invalid_reads_after_2, invalid_reads_after);
     }
 
+    fn serial_registers(serial: &mut SerialDevice<std::io::Stdin>) -> Vec<u8> {
+        let mut registers = Vec::new();
+        // Interrupt Enable, Line Control, Modem Control and Scratch registers.
+        for offset in [1, 3, 4, 7] {
+            let mut data = [0u8];
+            serial.bus_read(offset, &mut data);
+            registers.push(data[0]);
+        }
+        registers
+    }
+
+    #[test]
+    fn test_serial_persistence() {
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+
+        // Program the registers as a guest driver would, and receive some input.
+        serial.bus_write(1, &[0x03]);
+        serial.bus_write(3, &[0x1b]);
+        serial.bus_write(4, &[0x0b]);
+        serial.bus_write(7, &[0x42]);
+        serial.serial.raw_input(b"abc").unwrap();
+        serial.rx_trigger.pending.extend_from_slice(b"de");
+        let registers = serial_registers(&mut serial);
+
+        let mut mem = vec![0u8; 4096];
+        crate::snapshot::Snapshot::serialize(&mut mem.as_mut_slice(), &serial.save()).unwrap();
+        let state: SerialDeviceState =
+            crate::snapshot::Snapshot::deserialize(&mut mem.as_slice()).unwrap();
+
+        let mut restored = SerialDevice::restore(
+            SerialConstructorArgs {
+                interrupt_evt: EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                events: SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                out: SerialOut::Sink(std::io::sink()),
+                input: None::<std::io::Stdin>,
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored.save(), serial.save());
+        assert_eq!(serial_registers(&mut restored), registers);
+        assert_eq!(restored.rx_trigger.pending, b"de");
+        let mut data = [0u8];
+        restored.bus_read(0, &mut data);
+        assert_eq!(data[0], b'a');
+
+        // Restoring in place keeps the output of the device.
+        let mut fresh = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Stdout(std::io::stdout()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+        fresh.restore_state(&state).unwrap();
+        assert_eq!(fresh.save(), state);
+        assert_eq!(serial_registers(&mut fresh), registers);
+        assert!(matches!(fresh.serial.writer_mut(), SerialOut::Stdout(_)));
+
+        // An input buffer larger than the FIFO is rejected.
+        let state = SerialDeviceState {
+            in_buffer: vec![0; 1024],
+            ..state
+        };
+        assert!(matches!(
+            fresh.restore_state(&state),
+            Err(SerialPersistError::FullFifo(1024))
+        ));
+    }
+
+    #[test]
+    fn test_panic_watcher() {
+        let intr_evt = EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let panics = Arc::new(AtomicUsize::new(0));
+        let panics_cb = panics.clone();
+        let watcher = PanicWatcher::new(
+            PanicWatcher::DEFAULT_SIGNATURE,
+            Box::new(move || {
+                panics_cb.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                intr_evt,
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+        serial.watch_for_panics(watcher);
+        // The watcher stays in place when the destination of the output changes.
+        serial.set_output(SerialOut::Sink(std::io::sink()));
+
+        // The guest writes one byte at a time to the data register.
+        let mut write_line = |line: &[u8]| {
+            for byte in line {
+                serial.bus_write(0, &[*byte]);
+            }
+        };
+
+        write_line(b"Kernel command line: console=ttyS0\n");
+        assert_eq!(panics.load(Ordering::SeqCst), 0);
+
+        let panic_count = METRICS.panic_count.count();
+        write_line(b"Kernel panic - not syncing: VFS: Unable to mount root fs\n");
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+        assert!(METRICS.panic_count.count() > panic_count);
+
+        // The callback only fires once.
+        write_line(b"Kernel panic - not syncing: Attempted to kill init!\n");
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_serial_output_socket() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("serial.sock");
+        let mut out = SerialOut::UnixSocket(SerialSocket::bind(&path).unwrap());
+
+        // Output is dropped while no client is connected.
+        let dropped = METRICS.dropped_bytes_count.count();
+        assert_eq!(out.write(b"lost").unwrap(), 4);
+        assert!(METRICS.dropped_bytes_count.count() >= dropped + 4);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        out.write_all(b"console output").unwrap();
+        let mut data = [0u8; 14];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"console output");
+
+        // A new client is accepted once the previous one disconnected.
+        drop(client);
+        out.write_all(b"to nobody").unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        out.write_all(b"reconnected").unwrap();
+        let mut data = [0u8; 11];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"reconnected");
+
+        drop(out);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_serial_output_file() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("serial.log");
+        let intr_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(intr_evt.try_clone().unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+        serial.serial.write(IER_RDA_OFFSET, IER_RDA_BIT).unwrap();
+        serial.receive(b"in").unwrap();
+
+        // Output is dropped until the file exists.
+        serial.set_output(SerialOut::File(SerialFile::open(&path).unwrap()));
+        let dropped = METRICS.dropped_bytes_count.count();
+        serial.bus_write(0, b"x");
+        assert!(METRICS.dropped_bytes_count.count() > dropped);
+
+        File::create(&path).unwrap();
+        for byte in b"console output" {
+            serial.bus_write(0, &[*byte]);
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"console output");
+
+        // The input side of the device is left untouched.
+        let state = serial.serial.state();
+        assert_eq!(state.ier & IER_RDA_BIT, IER_RDA_BIT);
+        let mut data = [0u8; 1];
+        for byte in b"in" {
+            serial.bus_read(0, &mut data);
+            assert_eq!(data[0], *byte);
+        }
+
+        // Writes to a named pipe without a reader don't block.
+        let fifo = tmp_dir.as_path().join("serial.fifo");
+        let fifo_path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o600) }, 0);
+        serial.set_output(SerialOut::File(SerialFile::open(&fifo).unwrap()));
+        serial.bus_write(0, b"x");
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&fifo)
+            .unwrap();
+        serial.bus_write(0, b"y");
+        let mut data = [0u8; 2];
+        assert_eq!(reader.read(&mut data).unwrap(), 1);
+        assert_eq!(data[0], b'y');
+
+        // Paths that cannot be written to are rejected.
+        SerialFile::open(tmp_dir.as_path()).unwrap_err();
+    }
+
+    #[test]
+    fn test_serial_output_buffering() {
+        let tmp_dir = utils::tempdir::TempDir::new().unwrap();
+        let path = tmp_dir.as_path().join("serial.sock");
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::UnixSocket(SerialSocket::bind(&path).unwrap()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut data = [0u8; 32];
+
+        assert_eq!(serial.output_buffering(), SerialOutputBuffering::Byte);
+        serial
+            .set_output_buffering(SerialOutputBuffering::Line)
+            .unwrap();
+        assert_eq!(serial.output_buffering(), SerialOutputBuffering::Line);
+
+        // The partial line is only emitted once the guest writes the newline.
+        for byte in b"partial" {
+            serial.bus_write(0, &[*byte]);
+        }
+        assert_eq!(
+            client.read(&mut data).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        serial.bus_write(0, b"\n");
+        let count = client.read(&mut data).unwrap();
+        assert_eq!(&data[..count], b"partial\n");
+
+        // The line buffer carries over to a new output.
+        let path = tmp_dir.as_path().join("serial2.sock");
+        serial.set_output(SerialOut::UnixSocket(SerialSocket::bind(&path).unwrap()));
+        assert_eq!(serial.output_buffering(), SerialOutputBuffering::Line);
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.set_nonblocking(true).unwrap();
+        for byte in b"held" {
+            serial.bus_write(0, &[*byte]);
+        }
+        assert_eq!(
+            client.read(&mut data).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // Going back to byte-by-byte forwarding writes out the partial line.
+        serial
+            .set_output_buffering(SerialOutputBuffering::Byte)
+            .unwrap();
+        assert_eq!(serial.output_buffering(), SerialOutputBuffering::Byte);
+        let count = client.read(&mut data).unwrap();
+        assert_eq!(&data[..count], b"held");
+        serial.bus_write(0, b"!");
+        let count = client.read(&mut data).unwrap();
+        assert_eq!(&data[..count], b"!");
+    }
+
+    #[test]
+    fn test_serial_rx_trigger_level() {
+        let intr_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut serial = SerialDevice {
+            serial: Serial::with_events(
+                EventFdTrigger::new(intr_evt.try_clone().unwrap()),
+                SerialEventsWrapper {
+                    buffer_ready_event_fd: None,
+                },
+                SerialOut::Sink(std::io::sink()),
+            ),
+            input: None::<std::io::Stdin>,
+            rx_trigger: SerialRxTrigger::default(),
+        };
+        serial.serial.write(IER_RDA_OFFSET, IER_RDA_BIT).unwrap();
+        serial
+            .set_rx_trigger_level(SerialRxTriggerLevel::Eight)
+            .unwrap();
+        assert_eq!(serial.rx_trigger_level(), SerialRxTriggerLevel::Eight);
+
+        // No interrupt below the trigger level.
+        for byte in b"abcdefg" {
+            serial.receive(&[*byte]).unwrap();
+        }
+        intr_evt.read().unwrap_err();
+
+        // The eighth byte raises the interrupt, with all the bytes available to the guest.
+        serial.receive(b"h").unwrap();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        let mut data = [0u8; 1];
+        for byte in b"abcdefgh" {
+            serial.bus_read(0, &mut data);
+            assert_eq!(data[0], *byte);
+        }
+
+        // Bytes below the trigger level are delivered after the character timeout.
+        serial.receive(b"ij").unwrap();
+        intr_evt.read().unwrap_err();
+        std::thread::sleep(RX_CHAR_TIMEOUT * 2);
+        serial.process_rx_timeout();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        serial.bus_read(0, &mut data);
+        assert_eq!(data[0], b'i');
+    }
+
     #[test]
     fn test_is_fifo() {
         // invalid file descriptors arent fifos
@@ -42,4 +42,11 @@ impl BootTimer {
     pub fn new(start_ts: TimestampUs) -> BootTimer {
         BootTimer { start_ts }
     }
+
+    /// Time elapsed since the device's starting point, in microseconds.
+    pub fn elapsed_us(&self) -> u64 {
+        TimestampUs::default()
+            .time_us
+            .saturating_sub(self.start_ts.time_us)
+    }
 }
@@ -10,9 +10,10 @@ use log::error;
 use serde::Serialize;
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 use utils::u64_to_usize;
 
-use super::super::device::{DeviceState, VirtioDevice};
+use super::super::device::{DeviceState, LastError, VirtioDevice};
 use super::super::queue::Queue;
 use super::super::{ActivateError, TYPE_BALLOON};
 use super::metrics::METRICS;
@@ -26,6 +27,7 @@ use super::{
     VIRTIO_BALLOON_S_MEMTOT, VIRTIO_BALLOON_S_MINFLT, VIRTIO_BALLOON_S_SWAP_IN,
     VIRTIO_BALLOON_S_SWAP_OUT,
 };
+use crate::devices::virtio::balloon::report_balloon_event_fail;
 use crate::devices::virtio::balloon::BalloonError;
 use crate::devices::virtio::device::{IrqTrigger, IrqType};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
@@ -41,7 +43,7 @@ fn mib_to_pages(amount_mib: u32) -> Result<u32, BalloonError> {
         .ok_or(BalloonError::TooManyPagesRequested)
 }
 
-fn pages_to_mib(amount_pages: u32) -> u32 {
+pub(crate) fn pages_to_mib(amount_pages: u32) -> u32 {
     amount_pages / MIB_TO_4K_PAGES
 }
 
@@ -55,6 +57,9 @@ pub(crate) struct ConfigSpace {
 // SAFETY: Safe because ConfigSpace only contains plain data.
 unsafe impl ByteValued for ConfigSpace {}
 
+// Offset of `actual_pages` in the config space, the only field the driver may write.
+const ACTUAL_PAGES_OFFSET: usize = std::mem::size_of::<u32>();
+
 // This structure needs the `packed` attribute, otherwise Rust will assume
 // the size to be 16 bytes.
 #[derive(Copy, Clone, Debug, Default)]
@@ -128,7 +133,37 @@ pub struct BalloonStats {
     pub hugetlb_failures: Option<u64>,
 }
 
+/// Guest memory usage, as reported by the guest through the balloon statistics.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    /// Total amount of guest memory (in bytes).
+    pub total_memory: u64,
+    /// Amount of guest memory not being used for any purpose (in bytes).
+    pub free_memory: u64,
+    /// Amount of guest memory in use (in bytes).
+    pub used_memory: u64,
+    /// An estimate of how much memory is available (in bytes) for starting new
+    /// applications, if reported by the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_memory: Option<u64>,
+    /// The number of MiB the balloon is currently holding.
+    pub balloon_mib: u32,
+}
+
 impl BalloonStats {
+    /// Returns the guest memory usage, if the guest reported its total and free memory.
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        let total_memory = self.total_memory?;
+        let free_memory = self.free_memory?;
+        Some(MemoryStats {
+            total_memory,
+            free_memory,
+            used_memory: total_memory.saturating_sub(free_memory),
+            available_memory: self.available_memory,
+            balloon_mib: self.actual_mib,
+        })
+    }
+
     fn update_with_stat(&mut self, stat: &BalloonStat) -> Result<(), BalloonError> {
         let val = Some(stat.val);
         match stat.tag {
@@ -167,6 +202,8 @@ pub struct Balloon {
 
     // Implementation specific fields.
     pub(crate) restored: bool,
+    // Size of the guest memory the balloon operates on, in MiB.
+    pub(crate) guest_mem_mib: u64,
     pub(crate) stats_polling_interval_s: u16,
     pub(crate) stats_timer: TimerFd,
     // The index of the previous stats descriptor is saved because
@@ -175,6 +212,8 @@ pub struct Balloon {
     pub(crate) latest_stats: BalloonStats,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
+    // Most recent error hit while processing the queues.
+    pub(crate) last_error: LastError,
 }
 
 // TODO Use `#[derive(Debug)]` when a new release of
@@ -192,10 +231,12 @@ impl fmt::Debug for Balloon {
             .field("device_state", &self.device_state)
             .field("irq_trigger", &self.irq_trigger)
             .field("restored", &self.restored)
+            .field("guest_mem_mib", &self.guest_mem_mib)
             .field("stats_polling_interval_s", &self.stats_polling_interval_s)
             .field("stats_desc_index", &self.stats_desc_index)
             .field("latest_stats", &self.latest_stats)
             .field("pfn_buffer", &self.pfn_buffer)
+            .field("last_error", &self.last_error)
             .finish()
     }
 }
@@ -248,31 +289,33 @@ impl Balloon {
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             restored,
+            guest_mem_mib: 0,
             stats_polling_interval_s,
             stats_timer,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
+            last_error: LastError::default(),
         })
     }
 
     pub(crate) fn process_inflate_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[INFLATE_INDEX]
-            .read()
+        self.queues[INFLATE_INDEX]
+            .read_kicks(&self.queue_evts[INFLATE_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_inflate()
     }
 
     pub(crate) fn process_deflate_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[DEFLATE_INDEX]
-            .read()
+        self.queues[DEFLATE_INDEX]
+            .read_kicks(&self.queue_evts[DEFLATE_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_deflate_queue()
     }
 
     pub(crate) fn process_stats_queue_event(&mut self) -> Result<(), BalloonError> {
-        self.queue_evts[STATS_INDEX]
-            .read()
+        self.queues[STATS_INDEX]
+            .read_kicks(&self.queue_evts[STATS_INDEX])
             .map_err(BalloonError::EventFd)?;
         self.process_stats_queue()
     }
@@ -282,6 +325,12 @@ impl Balloon {
         self.trigger_stats_update()
     }
 
+    // Reports a failure to process an event and keeps it as the most recent error.
+    pub(crate) fn report_event_fail(&mut self, err: BalloonError) {
+        self.last_error.record(&err);
+        report_balloon_event_fail(err);
+    }
+
     pub(crate) fn process_inflate(&mut self) -> Result<(), BalloonError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -362,6 +411,8 @@ impl Balloon {
                     self.restored,
                 ) {
                     error!("Error removing memory range: {:?}", err);
+                    self.last_error
+                        .record(format!("Error removing memory range: {err:?}"));
                 }
             }
         }
@@ -480,6 +531,31 @@ impl Balloon {
         }
     }
 
+    /// Update the size of the guest memory the balloon operates on.
+    ///
+    /// A target larger than the new guest memory is clamped to it. If the device is activated,
+    /// the driver is notified of the change through a config interrupt.
+    pub fn update_guest_memory_size(&mut self, mem_size_mib: u64) -> Result<(), BalloonError> {
+        self.guest_mem_mib = mem_size_mib;
+        if u64::from(self.size_mb()) > mem_size_mib {
+            // The current target fits in `u32`, so does the smaller guest memory size.
+            let amount_mib = u32::try_from(mem_size_mib).unwrap();
+            self.config_space.num_pages = mib_to_pages(amount_mib)?;
+        }
+
+        if self.is_activated() {
+            self.irq_trigger
+                .trigger_irq(IrqType::Config)
+                .map_err(BalloonError::InterruptError)?;
+        }
+        Ok(())
+    }
+
+    /// Size of the guest memory the balloon operates on, in MiB.
+    pub fn guest_memory_mib(&self) -> u64 {
+        self.guest_mem_mib
+    }
+
     /// Update the statistics polling interval.
     pub fn update_stats_polling_interval(&mut self, interval_s: u16) -> Result<(), BalloonError> {
         if self.stats_polling_interval_s == interval_s {
@@ -605,11 +681,14 @@ impl VirtioDevice for Balloon {
         let config_space_bytes = self.config_space.as_mut_slice();
         let start = usize::try_from(offset).ok();
         let end = start.and_then(|s| s.checked_add(data.len()));
+        // The driver only owns `actual_pages`, `num_pages` is set by the device.
         let Some(dst) = start
             .zip(end)
+            .filter(|(start, _)| *start >= ACTUAL_PAGES_OFFSET)
             .and_then(|(start, end)| config_space_bytes.get_mut(start..end))
         else {
             error!("Failed to write config space");
+            METRICS.cfg_fails.inc();
             return;
         };
 
@@ -617,6 +696,7 @@ impl VirtioDevice for Balloon {
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        self.guest_mem_mib = crate::mem_size_mib(&mem);
         self.device_state = DeviceState::Activated(mem);
         if self.activate_evt.write(1).is_err() {
             error!("Balloon: Cannot write to activate_evt");
@@ -635,6 +715,10 @@ impl VirtioDevice for Balloon {
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
 }
 
 #[cfg(test)]
@@ -644,7 +728,6 @@ pub(crate) mod tests {
     use super::super::BALLOON_CONFIG_SPACE_SIZE;
     use super::*;
     use crate::check_metric_after_block;
-    use crate::devices::virtio::balloon::report_balloon_event_fail;
     use crate::devices::virtio::balloon::test_utils::{
         check_request_completion, invoke_handler_for_queue_event, set_request,
     };
@@ -801,14 +884,15 @@ pub(crate) mod tests {
         let mut balloon = Balloon::new(0, true, 0, false).unwrap();
 
         let expected_config_space: [u8; BALLOON_CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
-        balloon.write_config(0, &expected_config_space);
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00];
+        balloon.write_config(4, &expected_config_space[4..]);
 
         let mut actual_config_space = [0u8; BALLOON_CONFIG_SPACE_SIZE];
         balloon.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
 
         // Invalid write.
+        let cfg_fails = METRICS.cfg_fails.count();
         let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         balloon.write_config(5, &new_config_space);
         // Make sure nothing got written.
@@ -820,6 +904,12 @@ pub(crate) mod tests {
         // Make sure nothing got written.
         balloon.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
+
+        // The driver can't write `num_pages`.
+        balloon.write_config(0, &new_config_space);
+        balloon.read_config(0, &mut actual_config_space);
+        assert_eq!(actual_config_space, expected_config_space);
+        assert_eq!(METRICS.cfg_fails.count(), cfg_fails + 3);
     }
 
     #[test]
@@ -915,10 +1005,12 @@ pub(crate) mod tests {
                 1,
                 balloon
                     .process_inflate_queue_event()
-                    .unwrap_or_else(report_balloon_event_fail)
+                    .unwrap_or_else(|err| balloon.report_event_fail(err))
             );
             // Verify that nothing got processed.
             assert_eq!(infq.used.idx.get(), 0);
+            let (_, last_error) = balloon.last_error().unwrap();
+            assert!(last_error.starts_with("EventFd error"));
 
             // Check that the page was not zeroed.
             for i in 0..0x1000 {
@@ -1151,10 +1243,34 @@ pub(crate) mod tests {
         assert_eq!(balloon.actual_pages(), 0x1234);
         assert_eq!(balloon.size_mb(), 16);
 
-        // Update fields through the config space.
-        let expected_config = vec![0x44, 0x33, 0x22, 0x11, 0x78, 0x56, 0x34, 0x12];
-        balloon.write_config(0, &expected_config);
-        assert_eq!(balloon.num_pages(), 0x1122_3344);
+        // Update `actual_pages` through the config space.
+        balloon.write_config(4, &[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(balloon.num_pages(), 0x1000);
         assert_eq!(balloon.actual_pages(), 0x1234_5678);
     }
+
+    #[test]
+    fn test_update_guest_memory_size() {
+        let mut balloon = Balloon::new(64, true, 0, false).unwrap();
+        // An inactive device only updates its accounting.
+        balloon.update_guest_memory_size(128).unwrap();
+        assert_eq!(balloon.guest_memory_mib(), 128);
+        assert!(!balloon.irq_trigger.has_pending_irq(IrqType::Config));
+
+        let mem = default_mem();
+        balloon.activate(mem.clone()).unwrap();
+        assert_eq!(balloon.guest_memory_mib(), crate::mem_size_mib(&mem));
+
+        // Growing the memory keeps the target and notifies the driver.
+        balloon.update_guest_memory_size(256).unwrap();
+        assert_eq!(balloon.guest_memory_mib(), 256);
+        assert_eq!(balloon.size_mb(), 64);
+        assert!(balloon.irq_trigger.has_pending_irq(IrqType::Config));
+
+        // Shrinking the memory below the target clamps the target.
+        balloon.update_guest_memory_size(32).unwrap();
+        assert_eq!(balloon.guest_memory_mib(), 32);
+        assert_eq!(balloon.size_mb(), 32);
+        assert!(balloon.irq_trigger.has_pending_irq(IrqType::Config));
+    }
 }
@@ -4,7 +4,7 @@
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::epoll::EventSet;
 
-use super::{report_balloon_event_fail, DEFLATE_INDEX, INFLATE_INDEX, STATS_INDEX};
+use super::{DEFLATE_INDEX, INFLATE_INDEX, STATS_INDEX};
 use crate::devices::virtio::balloon::device::Balloon;
 use crate::devices::virtio::device::VirtioDevice;
 use crate::logger::{error, warn};
@@ -93,16 +93,16 @@ impl MutEventSubscriber for Balloon {
                 Self::PROCESS_ACTIVATE => self.process_activate_event(ops),
                 Self::PROCESS_VIRTQ_INFLATE => self
                     .process_inflate_queue_event()
-                    .unwrap_or_else(report_balloon_event_fail),
+                    .unwrap_or_else(|err| self.report_event_fail(err)),
                 Self::PROCESS_VIRTQ_DEFLATE => self
                     .process_deflate_queue_event()
-                    .unwrap_or_else(report_balloon_event_fail),
+                    .unwrap_or_else(|err| self.report_event_fail(err)),
                 Self::PROCESS_VIRTQ_STATS => self
                     .process_stats_queue_event()
-                    .unwrap_or_else(report_balloon_event_fail),
+                    .unwrap_or_else(|err| self.report_event_fail(err)),
                 Self::PROCESS_STATS_TIMER => self
                     .process_stats_timer_event()
-                    .unwrap_or_else(report_balloon_event_fail),
+                    .unwrap_or_else(|err| self.report_event_fail(err)),
                 _ => {
                     warn!("Balloon: Spurious event received: {:?}", source);
                 }
@@ -53,6 +53,8 @@ pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
 pub(super) struct BalloonDeviceMetrics {
     /// Number of times when activate failed on a balloon device.
     pub activate_fails: SharedIncMetric,
+    /// Number of times when the guest failed to write the balloon config space.
+    pub cfg_fails: SharedIncMetric,
     /// Number of balloon device inflations.
     pub inflate_count: SharedIncMetric,
     // Number of balloon statistics updates from the driver.
@@ -69,6 +71,7 @@ impl BalloonDeviceMetrics {
     const fn new() -> Self {
         Self {
             activate_fails: SharedIncMetric::new(),
+            cfg_fails: SharedIncMetric::new(),
             inflate_count: SharedIncMetric::new(),
             stats_updates_count: SharedIncMetric::new(),
             stats_update_fails: SharedIncMetric::new(),
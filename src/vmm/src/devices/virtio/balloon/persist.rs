@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 use timerfd::{SetTimeFlags, TimerState};
 
 use super::*;
-use crate::devices::virtio::balloon::device::{BalloonStats, ConfigSpace};
+use crate::devices::virtio::balloon::device::{pages_to_mib, BalloonStats, ConfigSpace};
 use crate::devices::virtio::device::DeviceState;
 use crate::devices::virtio::persist::VirtioDeviceState;
 use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
@@ -119,6 +119,13 @@ impl Persist<'_> for Balloon {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
+        // The balloon cannot have a target size greater than the size of
+        // the guest memory it is restored onto.
+        let guest_mem_mib = crate::mem_size_mib(&constructor_args.mem);
+        if u64::from(pages_to_mib(state.config_space.num_pages)) > guest_mem_mib {
+            return Err(Self::Error::TooManyPagesRequested);
+        }
+
         // We can safely create the balloon with arbitrary flags and
         // num_pages because we will overwrite them after.
         let mut balloon = Balloon::new(0, false, state.stats_polling_interval_s, true)?;
@@ -149,6 +156,7 @@ impl Persist<'_> for Balloon {
         };
 
         if state.virtio_state.activated {
+            balloon.guest_mem_mib = guest_mem_mib;
             balloon.device_state = DeviceState::Activated(constructor_args.mem);
 
             if balloon.stats_enabled() {
@@ -179,10 +187,11 @@ mod tests {
     use crate::devices::virtio::test_utils::default_mem;
     use crate::devices::virtio::TYPE_BALLOON;
     use crate::snapshot::Snapshot;
+    use crate::utilities::test_utils::single_region_mem;
 
     #[test]
     fn test_persistence() {
-        let guest_mem = default_mem();
+        let guest_mem = single_region_mem(0x42 << 20);
         let mut mem = vec![0; 4096];
 
         // Create and save the balloon device.
@@ -217,4 +226,28 @@ mod tests {
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
     }
+
+    #[test]
+    fn test_restore_target_exceeds_guest_memory() {
+        let mut mem = vec![0; 4096];
+
+        // The balloon target is larger than the guest memory it is restored onto.
+        let balloon = Balloon::new(0x42, false, 0, false).unwrap();
+        Snapshot::serialize(&mut mem.as_mut_slice(), &balloon.save()).unwrap();
+        let state: BalloonState = Snapshot::deserialize(&mut mem.as_slice()).unwrap();
+        assert!(matches!(
+            Balloon::restore(BalloonConstructorArgs { mem: default_mem() }, &state),
+            Err(BalloonError::TooManyPagesRequested)
+        ));
+
+        // Restoring onto a guest memory larger than the target is fine.
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs {
+                mem: single_region_mem(0x80 << 20),
+            },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.config_space, balloon.config_space);
+    }
 }
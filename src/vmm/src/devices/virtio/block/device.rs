@@ -3,18 +3,20 @@
 
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
+use std::time::Duration;
 
 use event_manager::{EventOps, Events, MutEventSubscriber};
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 
 use super::persist::{BlockConstructorArgs, BlockState};
 use super::vhost_user::device::{VhostUserBlock, VhostUserBlockConfig};
 use super::virtio::device::{VirtioBlock, VirtioBlockConfig};
-use super::BlockError;
+use super::{BackingInfo, BlockError, CacheType};
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::queue::Queue;
-use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
-use crate::rate_limiter::BucketUpdate;
+use crate::devices::virtio::{ActivateError, QuiesceError, SelfTestError, TYPE_BLOCK};
+use crate::rate_limiter::{BucketUpdate, RateLimiterBudgets};
 use crate::snapshot::Persist;
 use crate::vmm_config::drive::BlockDeviceConfig;
 use crate::vstate::memory::GuestMemoryMmap;
@@ -49,6 +51,13 @@ impl Block {
         }
     }
 
+    pub fn backing_info(&self) -> BackingInfo {
+        match self {
+            Self::Virtio(b) => b.backing_info(),
+            Self::VhostUser(b) => b.backing_info(),
+        }
+    }
+
     pub fn update_disk_image(&mut self, disk_image_path: String) -> Result<(), BlockError> {
         match self {
             Self::Virtio(b) => b
@@ -58,6 +67,15 @@ impl Block {
         }
     }
 
+    pub fn update_disk_size(&mut self, disk_size: u64) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => b
+                .update_disk_size(disk_size)
+                .map_err(BlockError::VirtioBackend),
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
     pub fn update_rate_limiter(
         &mut self,
         bytes: BucketUpdate,
@@ -72,6 +90,16 @@ impl Block {
         }
     }
 
+    pub fn set_cache_type(&mut self, cache_type: CacheType) -> Result<(), BlockError> {
+        match self {
+            Self::Virtio(b) => {
+                b.set_cache_type(cache_type);
+                Ok(())
+            }
+            Self::VhostUser(_) => Err(BlockError::InvalidBlockBackend),
+        }
+    }
+
     pub fn update_config(&mut self) -> Result<(), BlockError> {
         match self {
             Self::Virtio(_) => Err(BlockError::InvalidBlockBackend),
@@ -79,10 +107,13 @@ impl Block {
         }
     }
 
-    pub fn prepare_save(&mut self) {
+    pub fn prepare_save(&mut self) -> Result<(), BlockError> {
         match self {
-            Self::Virtio(b) => b.prepare_save(),
-            Self::VhostUser(b) => b.prepare_save(),
+            Self::Virtio(b) => {
+                b.prepare_save();
+                Ok(())
+            }
+            Self::VhostUser(b) => b.prepare_save().map_err(BlockError::VhostUserBackend),
         }
     }
 
@@ -100,6 +131,13 @@ impl Block {
         }
     }
 
+    pub fn cache_type(&self) -> CacheType {
+        match self {
+            Self::Virtio(b) => b.cache_type,
+            Self::VhostUser(b) => b.cache_type,
+        }
+    }
+
     pub fn root_device(&self) -> bool {
         match self {
             Self::Virtio(b) => b.root_device,
@@ -217,6 +255,68 @@ impl VirtioDevice for Block {
             Self::VhostUser(b) => b.device_state.is_activated(),
         }
     }
+
+    fn host_fd_count(&self) -> usize {
+        // Queue eventfds, interrupt eventfd and either the backing file or the
+        // vhost-user socket.
+        self.queue_events().len() + 2
+    }
+
+    fn flush_pending_interrupts(&mut self) -> Result<(), std::io::Error> {
+        match self {
+            Self::Virtio(b) => b.flush_pending_interrupts(),
+            Self::VhostUser(b) => b.flush_pending_interrupts(),
+        }
+    }
+
+    fn rate_limiter_state(&self) -> Vec<(&'static str, RateLimiterBudgets)> {
+        match self {
+            Self::Virtio(b) => b.rate_limiter_state(),
+            Self::VhostUser(b) => b.rate_limiter_state(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        match self {
+            Self::Virtio(b) => b.is_enabled(),
+            Self::VhostUser(b) => b.is_enabled(),
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> bool {
+        match self {
+            Self::Virtio(b) => b.set_enabled(enabled),
+            Self::VhostUser(b) => b.set_enabled(enabled),
+        }
+    }
+
+    fn quiesce(&mut self, timeout: Duration) -> Result<(), QuiesceError> {
+        match self {
+            Self::Virtio(b) => b.quiesce(timeout),
+            Self::VhostUser(b) => b.quiesce(timeout),
+        }
+    }
+
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        match self {
+            Self::Virtio(b) => b.self_test(),
+            Self::VhostUser(b) => b.self_test(),
+        }
+    }
+
+    fn set_id(&mut self, id: &str) -> bool {
+        match self {
+            Self::Virtio(b) => b.set_id(id),
+            Self::VhostUser(b) => b.set_id(id),
+        }
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        match self {
+            Self::Virtio(b) => b.last_error(),
+            Self::VhostUser(b) => b.last_error(),
+        }
+    }
 }
 
 impl MutEventSubscriber for Block {
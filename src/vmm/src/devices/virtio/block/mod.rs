@@ -3,6 +3,7 @@
 use serde::{Deserialize, Serialize};
 
 use self::vhost_user::VhostUserBlockError;
+use self::virtio::device::FileEngineType;
 use self::virtio::VirtioBlockError;
 
 pub mod device;
@@ -22,6 +23,21 @@ pub enum CacheType {
     Writeback,
 }
 
+/// Host file backing a block device, as currently open by the device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BackingInfo {
+    /// Path of the backing file, or of the socket of a vhost-user backend.
+    pub path: String,
+    /// Whether the backing file is open read-only.
+    pub read_only: bool,
+    /// Whether the backing file is open with `O_DIRECT`.
+    pub direct: bool,
+    /// Caching strategy of the device.
+    pub cache_type: CacheType,
+    /// IO engine of the device. Vhost-user backends do not have one.
+    pub file_engine_type: Option<FileEngineType>,
+}
+
 /// Errors the block device can trigger.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum BlockError {
@@ -9,13 +9,14 @@ use std::sync::Arc;
 
 use log::error;
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 use utils::u64_to_usize;
 use vhost::vhost_user::message::*;
 use vhost::vhost_user::Frontend;
 
 use super::{VhostUserBlockError, NUM_QUEUES, QUEUE_SIZE};
-use crate::devices::virtio::block::CacheType;
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::block::{BackingInfo, CacheType};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_F_VERSION_1,
 };
@@ -71,6 +72,15 @@ impl TryFrom<&BlockDeviceConfig> for VhostUserBlockConfig {
             && value.path_on_host.is_none()
             && value.rate_limiter.is_none()
             && value.file_engine_type.is_none()
+            && value.interrupt_moderation.is_none()
+            && value.io_retry.is_none()
+            && value.geometry.is_none()
+            && value.discard.is_none()
+            && value.max_chain_len.is_none()
+            && value.request_record_path.is_none()
+            && value.restore_notification_window_ms.is_none()
+            && value.write_batch.is_none()
+            && value.fd_on_host.is_none()
         {
             Ok(Self {
                 drive_id: value.drive_id.clone(),
@@ -98,6 +108,15 @@ impl From<VhostUserBlockConfig> for BlockDeviceConfig {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: Some(value.socket),
         }
@@ -131,6 +150,8 @@ pub struct VhostUserBlockImpl<T: VhostUserHandleBackend> {
     pub vu_handle: VhostUserHandleImpl<T>,
     pub vu_acked_protocol_features: u64,
     pub metrics: Arc<VhostUserDeviceMetrics>,
+    // Most recent error hit while serving the guest.
+    pub last_error: LastError,
 }
 
 // Need custom implementation because otherwise `Debug` is required for `vhost::Master`
@@ -156,6 +177,7 @@ impl<T: VhostUserHandleBackend> std::fmt::Debug for VhostUserBlockImpl<T> {
                 &self.vu_acked_protocol_features,
             )
             .field("metrics", &self.metrics)
+            .field("last_error", &self.last_error)
             .finish()
     }
 }
@@ -237,12 +259,28 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
             vu_handle,
             vu_acked_protocol_features: acked_protocol_features,
             metrics,
+            last_error: LastError::default(),
         })
     }
 
     /// Prepare device for being snapshotted.
-    pub fn prepare_save(&mut self) {
-        unimplemented!("VhostUserBlock does not support snapshotting yet");
+    ///
+    /// The vrings are stopped to learn which requests the backend took from the queues, and
+    /// started again afterwards. The backend state is not part of the snapshot, so preparing
+    /// fails if the backend has not completed all of these requests.
+    pub fn prepare_save(&mut self) -> Result<(), VhostUserBlockError> {
+        let DeviceState::Activated(mem) = &self.device_state else {
+            return Ok(());
+        };
+
+        let in_flight = self
+            .vu_handle
+            .save_vring_bases(mem, &mut self.queues, &self.queue_evts, &self.irq_trigger)
+            .map_err(VhostUserBlockError::VhostUser)?;
+        if in_flight != 0 {
+            return Err(VhostUserBlockError::RequestsInFlight(in_flight));
+        }
+        Ok(())
     }
 
     pub fn config(&self) -> VhostUserBlockConfig {
@@ -255,6 +293,16 @@ impl<T: VhostUserHandleBackend> VhostUserBlockImpl<T> {
         }
     }
 
+    pub fn backing_info(&self) -> BackingInfo {
+        BackingInfo {
+            path: self.vu_handle.socket_path.clone(),
+            read_only: self.read_only,
+            direct: false,
+            cache_type: self.cache_type,
+            file_engine_type: None,
+        }
+    }
+
     pub fn config_update(&mut self) -> Result<(), VhostUserBlockError> {
         let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
@@ -342,7 +390,10 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
         // with guest driver as well.
         self.vu_handle
             .set_features(self.acked_features)
-            .map_err(ActivateError::VhostUser)?;
+            .map_err(|err| {
+                self.last_error.record(&err);
+                ActivateError::VhostUser(err)
+            })?;
         self.vu_handle
             .setup_backend(
                 &mem,
@@ -351,6 +402,7 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
             )
             .map_err(|err| {
                 self.metrics.activate_fails.inc();
+                self.last_error.record(&err);
                 ActivateError::VhostUser(err)
             })?;
         self.device_state = DeviceState::Activated(mem);
@@ -362,12 +414,23 @@ impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserBlock
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
+
+    fn set_id(&mut self, id: &str) -> bool {
+        VhostUserMetricsPerDevice::rename(&format!("block_{}", self.id), format!("block_{id}"));
+        self.id = id.to_string();
+        true
+    }
 }
 
 #[cfg(test)]
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
 
+    use std::num::Wrapping;
     use std::os::unix::net::UnixStream;
     use std::sync::atomic::Ordering;
 
@@ -392,6 +455,15 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: Some("sock".to_string()),
         };
@@ -407,6 +479,15 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -422,6 +503,15 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: Some("sock".to_string()),
         };
@@ -666,6 +756,7 @@ mod tests {
             features_are_set: std::cell::UnsafeCell<bool>,
             memory_is_set: std::cell::UnsafeCell<bool>,
             vring_enabled: std::cell::UnsafeCell<bool>,
+            vring_base: std::cell::UnsafeCell<u32>,
         }
 
         impl VhostUserHandleBackend for MockMaster {
@@ -674,6 +765,7 @@ mod tests {
                     features_are_set: std::cell::UnsafeCell::new(false),
                     memory_is_set: std::cell::UnsafeCell::new(false),
                     vring_enabled: std::cell::UnsafeCell::new(false),
+                    vring_base: std::cell::UnsafeCell::new(0),
                 }
             }
 
@@ -733,10 +825,16 @@ mod tests {
                 Ok(())
             }
 
-            fn set_vring_base(&self, _queue_index: usize, _base: u16) -> Result<(), vhost::Error> {
+            fn set_vring_base(&self, _queue_index: usize, base: u16) -> Result<(), vhost::Error> {
+                unsafe { (*self.vring_base.get()) = u32::from(base) };
                 Ok(())
             }
 
+            fn get_vring_base(&self, _queue_index: usize) -> Result<u32, vhost::Error> {
+                unsafe { (*self.vring_enabled.get()) = false };
+                Ok(unsafe { *self.vring_base.get() })
+            }
+
             fn set_vring_call(
                 &self,
                 _queue_index: usize,
@@ -791,5 +889,22 @@ mod tests {
         assert!(unsafe { *vhost_block.vu_handle.vu.memory_is_set.get() });
         assert!(unsafe { *vhost_block.vu_handle.vu.vring_enabled.get() });
         assert!(vhost_block.is_activated());
+
+        // Preparing for a snapshot stops the vring and starts it again from the same index.
+        vhost_block.prepare_save().unwrap();
+        assert!(unsafe { *vhost_block.vu_handle.vu.vring_enabled.get() });
+        assert_eq!(vhost_block.queues[0].next_avail, Wrapping(0));
+        assert_eq!(vhost_block.queues[0].next_used, Wrapping(0));
+
+        // The backend took 2 requests from the queue, but none of them is in the used ring.
+        unsafe { (*vhost_block.vu_handle.vu.vring_base.get()) = 2 };
+        let err = vhost_block.prepare_save().unwrap_err();
+        assert!(
+            matches!(err, VhostUserBlockError::RequestsInFlight(2)),
+            "{:?}",
+            err
+        );
+        assert!(unsafe { *vhost_block.vu_handle.vu.vring_enabled.get() });
+        assert_eq!(vhost_block.queues[0].next_avail, Wrapping(2));
     }
 }
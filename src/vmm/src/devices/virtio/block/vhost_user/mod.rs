@@ -21,6 +21,10 @@ pub enum VhostUserBlockError {
     Config,
     /// Persistence error: {0}
     Persist(crate::devices::virtio::persist::PersistError),
+    /// The backend no longer offers the features of the snapshot: {0:#x}
+    MissingFeatures(u64),
+    /// The backend has not completed {0} requests
+    RequestsInFlight(u16),
     /// Vhost-user error: {0}
     VhostUser(VhostUserError),
     /// Vhost error: {0}
@@ -3,13 +3,24 @@
 
 //! Defines the structures needed for saving/restoring block devices.
 
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
+use utils::eventfd::EventFd;
+use utils::u64_to_usize;
+use vhost::vhost_user::message::VhostUserProtocolFeatures;
 
 use super::device::VhostUserBlock;
-use super::VhostUserBlockError;
+use super::{VhostUserBlockError, NUM_QUEUES, QUEUE_SIZE};
 use crate::devices::virtio::block::persist::BlockConstructorArgs;
 use crate::devices::virtio::block::CacheType;
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, LastError};
+use crate::devices::virtio::gen::virtio_blk::VIRTIO_BLK_F_RO;
 use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::vhost_user::VhostUserHandleImpl;
+use crate::devices::virtio::vhost_user_metrics::VhostUserMetricsPerDevice;
+use crate::devices::virtio::TYPE_BLOCK;
 use crate::snapshot::Persist;
 
 /// vhost-user block device state.
@@ -31,13 +42,94 @@ impl Persist<'_> for VhostUserBlock {
     type Error = VhostUserBlockError;
 
     fn save(&self) -> Self::State {
-        unimplemented!("VhostUserBlock does not support snapshotting yet");
+        VhostUserBlockState {
+            id: self.id.clone(),
+            partuuid: self.partuuid.clone(),
+            cache_type: self.cache_type,
+            root_device: self.root_device,
+            socket_path: self.vu_handle.socket_path.clone(),
+            vu_acked_protocol_features: self.vu_acked_protocol_features,
+            config_space: self.config_space.clone(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
     }
 
     fn restore(
-        _constructor_args: Self::ConstructorArgs,
-        _state: &Self::State,
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
     ) -> Result<Self, Self::Error> {
-        unimplemented!("VhostUserBlock does not support snapshotting yet");
+        // The backend must still be reachable and offer the features the guest may have
+        // negotiated, a device that cannot be reconnected fails the restore.
+        let mut vu_handle = VhostUserHandleImpl::new(&state.socket_path, NUM_QUEUES)
+            .map_err(VhostUserBlockError::VhostUser)?;
+        let avail_features = state.virtio_state.avail_features;
+        let (acked_features, acked_protocol_features) = vu_handle
+            .negotiate_features(
+                avail_features,
+                VhostUserProtocolFeatures::from_bits_truncate(state.vu_acked_protocol_features),
+            )
+            .map_err(VhostUserBlockError::VhostUser)?;
+        if acked_features != avail_features {
+            return Err(VhostUserBlockError::MissingFeatures(
+                avail_features & !acked_features,
+            ));
+        }
+
+        let queues = state
+            .virtio_state
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_BLOCK,
+                u64_to_usize(NUM_QUEUES),
+                QUEUE_SIZE,
+            )
+            .map_err(VhostUserBlockError::Persist)?;
+        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserBlockError::EventFd)?;
+            u64_to_usize(NUM_QUEUES)];
+
+        let mut irq_trigger = IrqTrigger::new().map_err(VhostUserBlockError::IrqTrigger)?;
+        irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
+
+        let mut block = VhostUserBlock {
+            avail_features,
+            acked_features: state.virtio_state.acked_features,
+            config_space: state.config_space.clone(),
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserBlockError::EventFd)?,
+
+            queues,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            irq_trigger,
+
+            id: state.id.clone(),
+            partuuid: state.partuuid.clone(),
+            cache_type: state.cache_type,
+            root_device: state.root_device,
+            read_only: avail_features & (1 << VIRTIO_BLK_F_RO) != 0,
+
+            vu_handle,
+            vu_acked_protocol_features: acked_protocol_features,
+            metrics: VhostUserMetricsPerDevice::alloc(format!("block_{}", state.id)),
+            last_error: LastError::default(),
+        };
+
+        if state.virtio_state.activated {
+            // Send the features negotiated by the guest, the memory table and the vrings again.
+            block
+                .vu_handle
+                .set_features(block.acked_features)
+                .map_err(VhostUserBlockError::VhostUser)?;
+            block
+                .vu_handle
+                .setup_backend(
+                    &constructor_args.mem,
+                    &[(0, &block.queues[0], &block.queue_evts[0])],
+                    &block.irq_trigger,
+                )
+                .map_err(VhostUserBlockError::VhostUser)?;
+            block.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(block)
     }
 }
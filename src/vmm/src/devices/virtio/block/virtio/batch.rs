@@ -0,0 +1,244 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Batching of sequential block write requests on the sync IO engine.
+
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+use super::io::FileEngine;
+use super::metrics::BlockDeviceMetrics;
+use super::request::{FinishedRequest, IoErr, PendingRequest};
+use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
+use crate::logger::IncMetric;
+use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
+
+/// Write batching parameters of a block device.
+///
+/// Sequential write requests are coalesced into a single vectored write once `max_batch_size`
+/// of them are pending or `flush_interval_us` microseconds after the first one, whichever
+/// comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteBatchConfig {
+    /// Maximum number of write requests in a batch.
+    pub max_batch_size: u32,
+    /// Maximum delay, in microseconds, before a partial batch is written.
+    pub flush_interval_us: u64,
+}
+
+/// Errors associated with the write batching of a block device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum WriteBatchError {
+    /// Write batching requires a batch size between 1 and the queue size: {0}
+    InvalidMaxBatchSize(u32),
+    /// Write batching requires a non-zero flush interval.
+    InvalidFlushInterval,
+    /// Write batching is only supported by the Sync IO engine.
+    UnsupportedEngine,
+    /// Cannot create the write batching timer: {0}
+    Timer(std::io::Error),
+}
+
+#[derive(Debug)]
+struct BatchedWrite {
+    addr: GuestAddress,
+    len: u32,
+    pending: PendingRequest,
+}
+
+/// Coalesces sequential write requests according to a [`WriteBatchConfig`].
+///
+/// The requests of a batch are only completed once the batch is written to the backing file.
+pub struct WriteBatcher {
+    config: WriteBatchConfig,
+    timer: TimerFd,
+    timer_armed: bool,
+    start: u64,
+    end: u64,
+    writes: Vec<BatchedWrite>,
+}
+
+// TODO Use `#[derive(Debug)]` when a new release of
+// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
+// https://github.com/main--/rust-timerfd/pull/12.
+impl fmt::Debug for WriteBatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteBatcher")
+            .field("config", &self.config)
+            .field("timer_armed", &self.timer_armed)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("writes", &self.writes)
+            .finish()
+    }
+}
+
+impl WriteBatcher {
+    /// Creates a new batcher from the given config.
+    pub fn new(config: WriteBatchConfig) -> Result<Self, WriteBatchError> {
+        if config.max_batch_size == 0
+            || config.max_batch_size > u32::from(FIRECRACKER_MAX_QUEUE_SIZE)
+        {
+            return Err(WriteBatchError::InvalidMaxBatchSize(config.max_batch_size));
+        }
+        if config.flush_interval_us == 0 {
+            return Err(WriteBatchError::InvalidFlushInterval);
+        }
+
+        Ok(Self {
+            config,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(WriteBatchError::Timer)?,
+            timer_armed: false,
+            start: 0,
+            end: 0,
+            writes: Vec::new(),
+        })
+    }
+
+    /// Returns the config of this batcher.
+    pub fn config(&self) -> WriteBatchConfig {
+        self.config
+    }
+
+    /// Number of write requests waiting in the batch.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Whether the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Whether a write at `offset` extends the current batch.
+    pub fn is_sequential(&self, offset: u64) -> bool {
+        self.writes.is_empty() || offset == self.end
+    }
+
+    /// Adds a write of `len` bytes from `addr` at `offset` to the batch. Returns `true` if the
+    /// batch is full and should be written now.
+    ///
+    /// The write must be sequential with the ones already batched.
+    pub(crate) fn push(
+        &mut self,
+        offset: u64,
+        addr: GuestAddress,
+        len: u32,
+        pending: PendingRequest,
+    ) -> bool {
+        debug_assert!(self.is_sequential(offset));
+        if self.writes.is_empty() {
+            self.start = offset;
+        }
+        self.end = offset + u64::from(len);
+        self.writes.push(BatchedWrite { addr, len, pending });
+
+        if !self.timer_armed {
+            self.timer.set_state(
+                TimerState::Oneshot(Duration::from_micros(self.config.flush_interval_us)),
+                SetTimeFlags::Default,
+            );
+            self.timer_armed = true;
+        }
+        self.writes.len() >= self.config.max_batch_size as usize
+    }
+
+    /// Handles a timer expiration. Returns `true` if there is a batch to write.
+    pub fn on_timer(&mut self) -> bool {
+        self.timer.read();
+        self.timer_armed = false;
+        !self.writes.is_empty()
+    }
+
+    /// Writes the batch to the backing file with a single vectored write and finishes its
+    /// requests.
+    ///
+    /// If the write is short, the requests are completed in order with the bytes that were
+    /// written; those left incomplete fail.
+    pub(crate) fn flush(
+        &mut self,
+        file_engine: &mut FileEngine<PendingRequest>,
+        mem: &GuestMemoryMmap,
+        block_metrics: &BlockDeviceMetrics,
+    ) -> Vec<FinishedRequest> {
+        if self.timer_armed {
+            self.timer
+                .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+            self.timer_armed = false;
+        }
+        if self.writes.is_empty() {
+            return Vec::new();
+        }
+
+        let bufs: Vec<_> = self
+            .writes
+            .iter()
+            .map(|write| (write.addr, write.len))
+            .collect();
+        let res = {
+            let _metric = block_metrics.write_agg.record_latency_metrics();
+            file_engine.write_vectored(self.start, mem, &bufs)
+        };
+        block_metrics.write_batch_count.inc();
+
+        let (mut written, mut error) = match res {
+            Ok(written) => (written, None),
+            Err(err) => (0, Some(IoErr::FileEngine(err))),
+        };
+        self.writes
+            .drain(..)
+            .map(|write| {
+                let res = match error.take() {
+                    Some(err) => Err(err),
+                    None => {
+                        let count = u32::try_from(written.min(u64::from(write.len))).unwrap();
+                        written -= u64::from(count);
+                        Ok(count)
+                    }
+                };
+                write.pending.finish(mem, res, block_metrics)
+            })
+            .collect()
+    }
+}
+
+impl AsRawFd for WriteBatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_config() {
+        let err = WriteBatcher::new(WriteBatchConfig {
+            max_batch_size: 0,
+            flush_interval_us: 100,
+        })
+        .unwrap_err();
+        assert!(matches!(err, WriteBatchError::InvalidMaxBatchSize(0)));
+
+        let err = WriteBatcher::new(WriteBatchConfig {
+            max_batch_size: u32::from(FIRECRACKER_MAX_QUEUE_SIZE) + 1,
+            flush_interval_us: 100,
+        })
+        .unwrap_err();
+        assert!(matches!(err, WriteBatchError::InvalidMaxBatchSize(_)));
+
+        let err = WriteBatcher::new(WriteBatchConfig {
+            max_batch_size: 4,
+            flush_interval_us: 0,
+        })
+        .unwrap_err();
+        assert!(matches!(err, WriteBatchError::InvalidFlushInterval));
+    }
+}
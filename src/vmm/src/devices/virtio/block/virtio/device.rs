@@ -10,37 +10,52 @@ use std::convert::From;
 use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::PathBuf;
-use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use block_io::FileEngine;
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 use utils::kernel_version::{min_kernel_version_for_io_uring, KernelVersion};
+use utils::time::TimestampUs;
 use utils::u64_to_usize;
 
+use super::batch::{WriteBatchConfig, WriteBatchError, WriteBatcher};
+use super::discard;
+use super::geometry::DiskGeometryConfig;
 use super::io::async_io;
+use super::moderation::{InterruptModerationConfig, InterruptModerator};
+use super::record::RequestRecorder;
 use super::request::*;
+use super::retry::{IoRetrier, IoRetryConfig};
+use super::suppression::NotificationSuppressor;
 use super::{
     io as block_io, VirtioBlockError, BLOCK_CONFIG_SPACE_SIZE, BLOCK_QUEUE_SIZES, SECTOR_SHIFT,
     SECTOR_SIZE,
 };
 use crate::devices::virtio::block::virtio::metrics::{BlockDeviceMetrics, BlockMetricsPerDevice};
-use crate::devices::virtio::block::CacheType;
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::block::{BackingInfo, CacheType};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::{
-    VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
+    VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_GEOMETRY, VIRTIO_BLK_F_RO,
+    VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_F_VERSION_1,
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use crate::devices::virtio::queue::Queue;
-use crate::devices::virtio::{ActivateError, TYPE_BLOCK};
+use crate::devices::virtio::queue::{DescriptorChain, Queue};
+use crate::devices::virtio::{ActivateError, QuiesceError, SelfTestError, TYPE_BLOCK};
 use crate::logger::{error, warn, IncMetric};
-use crate::rate_limiter::{BucketUpdate, RateLimiter};
+use crate::rate_limiter::{BucketUpdate, RateLimiter, RateLimiterBudgets};
 use crate::vmm_config::drive::BlockDeviceConfig;
 use crate::vmm_config::RateLimiterConfig;
 use crate::vstate::memory::GuestMemoryMmap;
 
+/// Number of bytes at the start of the backing file hashed to identify the image.
+pub const DISK_HEADER_SIZE: usize = 4096;
+
 /// The engine file type, either Sync or Async (through io_uring).
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum FileEngineType {
@@ -68,6 +83,8 @@ pub struct DiskProperties {
     pub file_engine: FileEngine<PendingRequest>,
     pub nsectors: u64,
     pub image_id: [u8; VIRTIO_BLK_ID_BYTES as usize],
+    /// File descriptor the backing file was inherited through, if any.
+    pub fd: Option<RawFd>,
 }
 
 impl DiskProperties {
@@ -80,6 +97,42 @@ impl DiskProperties {
             .map_err(|x| VirtioBlockError::BackingFile(x, disk_image_path.to_string()))
     }
 
+    // Helper function that duplicates an inherited file descriptor and checks that its
+    // access mode matches the requested permissions
+    fn dup_fd(fd: RawFd, is_disk_read_only: bool) -> Result<File, VirtioBlockError> {
+        let fd_path = Self::fd_path(fd);
+        // SAFETY: `fcntl` does not touch memory; the result is checked below.
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(VirtioBlockError::BackingFile(
+                std::io::Error::last_os_error(),
+                fd_path,
+            ));
+        }
+        if !is_disk_read_only && flags & libc::O_ACCMODE == libc::O_RDONLY {
+            return Err(VirtioBlockError::BackingFile(
+                std::io::Error::from_raw_os_error(libc::EBADF),
+                fd_path,
+            ));
+        }
+        // Duplicate the descriptor so that the caller keeps ownership of the original.
+        // SAFETY: `fcntl` does not touch memory; the result is checked below.
+        let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+        if dup < 0 {
+            return Err(VirtioBlockError::BackingFile(
+                std::io::Error::last_os_error(),
+                fd_path,
+            ));
+        }
+        // SAFETY: `dup` is a freshly duplicated descriptor that nothing else owns.
+        Ok(unsafe { File::from_raw_fd(dup) })
+    }
+
+    /// Path under which the backing file of an inherited descriptor is reported.
+    pub fn fd_path(fd: RawFd) -> String {
+        format!("/proc/self/fd/{fd}")
+    }
+
     // Helper function that gets the size of the file
     fn file_size(disk_image_path: &str, disk_image: &mut File) -> Result<u64, VirtioBlockError> {
         let disk_size = disk_image
@@ -105,7 +158,27 @@ impl DiskProperties {
         is_disk_read_only: bool,
         file_engine_type: FileEngineType,
     ) -> Result<Self, VirtioBlockError> {
-        let mut disk_image = Self::open_file(&disk_image_path, is_disk_read_only)?;
+        let disk_image = Self::open_file(&disk_image_path, is_disk_read_only)?;
+        Self::from_file(disk_image, disk_image_path, None, file_engine_type)
+    }
+
+    /// Create a new file for the block device from an already open file descriptor.
+    /// The descriptor is duplicated, so the caller keeps ownership of `fd`.
+    pub fn from_fd(
+        fd: RawFd,
+        is_disk_read_only: bool,
+        file_engine_type: FileEngineType,
+    ) -> Result<Self, VirtioBlockError> {
+        let disk_image = Self::dup_fd(fd, is_disk_read_only)?;
+        Self::from_file(disk_image, Self::fd_path(fd), Some(fd), file_engine_type)
+    }
+
+    fn from_file(
+        mut disk_image: File,
+        disk_image_path: String,
+        fd: Option<RawFd>,
+        file_engine_type: FileEngineType,
+    ) -> Result<Self, VirtioBlockError> {
         let disk_size = Self::file_size(&disk_image_path, &mut disk_image)?;
         let image_id = Self::build_disk_image_id(&disk_image);
 
@@ -115,6 +188,7 @@ impl DiskProperties {
                 .map_err(VirtioBlockError::FileEngine)?,
             nsectors: disk_size >> SECTOR_SHIFT,
             image_id,
+            fd,
         })
     }
 
@@ -133,6 +207,7 @@ impl DiskProperties {
             .map_err(VirtioBlockError::FileEngine)?;
         self.nsectors = disk_size >> SECTOR_SHIFT;
         self.file_path = disk_image_path;
+        self.fd = None;
 
         Ok(())
     }
@@ -168,6 +243,25 @@ impl DiskProperties {
         default_id
     }
 
+    /// Computes the CRC64 of the first `DISK_HEADER_SIZE` bytes of the backing file, which
+    /// identifies the image the device is backed by.
+    pub fn header_hash(&self) -> Result<u64, VirtioBlockError> {
+        let mut header = [0u8; DISK_HEADER_SIZE];
+        let mut len = 0;
+        while len < header.len() {
+            let bytes_read = self
+                .file_engine
+                .file()
+                .read_at(&mut header[len..], len as u64)
+                .map_err(|err| VirtioBlockError::BackingFile(err, self.file_path.clone()))?;
+            if bytes_read == 0 {
+                break;
+            }
+            len += bytes_read;
+        }
+        Ok(crc64::crc64(0, &header[..len]))
+    }
+
     /// Provides vec containing the virtio block configuration space
     /// buffer. The config space is populated with the disk size based
     /// on the backing file size.
@@ -210,13 +304,43 @@ pub struct VirtioBlockConfig {
     #[serde(default)]
     #[serde(rename = "io_engine")]
     pub file_engine_type: FileEngineType,
+    /// Interrupt moderation for request completions.
+    #[serde(default)]
+    pub interrupt_moderation: Option<InterruptModerationConfig>,
+    /// I/O retry policy for backing store errors.
+    #[serde(default)]
+    pub io_retry: Option<IoRetryConfig>,
+    /// C/H/S geometry reported to the guest.
+    #[serde(default)]
+    pub geometry: Option<DiskGeometryConfig>,
+    /// If set to true, discard and write zeroes requests are offered to the guest.
+    /// Read-only drives never offer them.
+    #[serde(default)]
+    pub discard: bool,
+    /// Maximum number of descriptors in a request's descriptor chain. Defaults to the queue
+    /// size.
+    #[serde(default)]
+    pub max_chain_len: Option<u16>,
+    /// Path of a file to record the requests submitted by the guest to, for debugging.
+    #[serde(default)]
+    pub request_record_path: Option<String>,
+    /// Window, in milliseconds, during which queue notifications are coalesced after a restore.
+    #[serde(default)]
+    pub restore_notification_window_ms: Option<u64>,
+    /// Batching of sequential write requests. Only supported by the Sync IO engine.
+    #[serde(default)]
+    pub write_batch: Option<WriteBatchConfig>,
+    /// File descriptor of the backing file, already open. When set, it is used instead of
+    /// opening `path_on_host`.
+    #[serde(default)]
+    pub fd_on_host: Option<RawFd>,
 }
 
 impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
     type Error = VirtioBlockError;
 
     fn try_from(value: &BlockDeviceConfig) -> Result<Self, Self::Error> {
-        if value.path_on_host.is_some() && value.socket.is_none() {
+        if value.path_on_host.is_some() != value.fd_on_host.is_some() && value.socket.is_none() {
             Ok(Self {
                 drive_id: value.drive_id.clone(),
                 partuuid: value.partuuid.clone(),
@@ -224,9 +348,21 @@ impl TryFrom<&BlockDeviceConfig> for VirtioBlockConfig {
                 cache_type: value.cache_type,
 
                 is_read_only: value.is_read_only.unwrap_or(false),
-                path_on_host: value.path_on_host.as_ref().unwrap().clone(),
+                path_on_host: match value.fd_on_host {
+                    Some(fd) => DiskProperties::fd_path(fd),
+                    None => value.path_on_host.as_ref().unwrap().clone(),
+                },
                 rate_limiter: value.rate_limiter,
                 file_engine_type: value.file_engine_type.unwrap_or_default(),
+                interrupt_moderation: value.interrupt_moderation,
+                io_retry: value.io_retry,
+                geometry: value.geometry,
+                discard: value.discard.unwrap_or(false),
+                max_chain_len: value.max_chain_len,
+                request_record_path: value.request_record_path.clone(),
+                restore_notification_window_ms: value.restore_notification_window_ms,
+                write_batch: value.write_batch,
+                fd_on_host: value.fd_on_host,
             })
         } else {
             Err(VirtioBlockError::Config)
@@ -243,9 +379,18 @@ impl From<VirtioBlockConfig> for BlockDeviceConfig {
             cache_type: value.cache_type,
 
             is_read_only: Some(value.is_read_only),
-            path_on_host: Some(value.path_on_host),
+            path_on_host: value.fd_on_host.is_none().then_some(value.path_on_host),
             rate_limiter: value.rate_limiter,
             file_engine_type: Some(value.file_engine_type),
+            interrupt_moderation: value.interrupt_moderation,
+            io_retry: value.io_retry,
+            geometry: value.geometry,
+            discard: Some(value.discard),
+            max_chain_len: value.max_chain_len,
+            request_record_path: value.request_record_path,
+            restore_notification_window_ms: value.restore_notification_window_ms,
+            write_batch: value.write_batch,
+            fd_on_host: value.fd_on_host,
 
             socket: None,
         }
@@ -279,6 +424,25 @@ pub struct VirtioBlock {
     pub rate_limiter: RateLimiter,
     pub is_io_engine_throttled: bool,
     pub metrics: Arc<BlockDeviceMetrics>,
+    pub interrupt_moderator: Option<InterruptModerator>,
+    pub io_retrier: Option<IoRetrier>,
+    pub enabled: bool,
+    pub geometry: Option<DiskGeometryConfig>,
+    pub discard: bool,
+    /// Maximum number of descriptors in a request, longer chains fail with an IO error.
+    pub max_chain_len: u16,
+    /// Requests submitted to the IO engine that did not complete yet.
+    pub in_flight_requests: Vec<InFlightRequest>,
+    /// Requests that failed in the IO engine after being taken from the queue, waiting to be
+    /// submitted again.
+    pub retry_requests: Vec<PendingRequest>,
+    /// Requests that were in flight when the device was snapshotted, waiting to be executed
+    /// again after restore.
+    pub restored_requests: Vec<InFlightRequest>,
+    pub last_error: LastError,
+    pub request_recorder: Option<RequestRecorder>,
+    pub notification_suppressor: Option<NotificationSuppressor>,
+    pub write_batcher: Option<WriteBatcher>,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
@@ -298,11 +462,14 @@ impl VirtioBlock {
     ///
     /// The given file must be seekable and sizable.
     pub fn new(config: VirtioBlockConfig) -> Result<VirtioBlock, VirtioBlockError> {
-        let disk_properties = DiskProperties::new(
-            config.path_on_host,
-            config.is_read_only,
-            config.file_engine_type,
-        )?;
+        let disk_properties = match config.fd_on_host {
+            Some(fd) => DiskProperties::from_fd(fd, config.is_read_only, config.file_engine_type)?,
+            None => DiskProperties::new(
+                config.path_on_host,
+                config.is_read_only,
+                config.file_engine_type,
+            )?,
+        };
 
         let rate_limiter = config
             .rate_limiter
@@ -311,6 +478,18 @@ impl VirtioBlock {
             .map_err(VirtioBlockError::RateLimiter)?
             .unwrap_or_default();
 
+        let interrupt_moderator = config
+            .interrupt_moderation
+            .map(InterruptModerator::new)
+            .transpose()
+            .map_err(VirtioBlockError::InterruptModeration)?;
+
+        let io_retrier = config
+            .io_retry
+            .map(IoRetrier::new)
+            .transpose()
+            .map_err(VirtioBlockError::IoRetry)?;
+
         let mut avail_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
 
         if config.cache_type == CacheType::Writeback {
@@ -321,14 +500,51 @@ impl VirtioBlock {
             avail_features |= 1u64 << VIRTIO_BLK_F_RO;
         };
 
+        if config.geometry.is_some() {
+            avail_features |= 1u64 << VIRTIO_BLK_F_GEOMETRY;
+        }
+
+        let discard = config.discard && !config.is_read_only;
+        if discard {
+            avail_features |= (1u64 << VIRTIO_BLK_F_DISCARD) | (1u64 << VIRTIO_BLK_F_WRITE_ZEROES);
+        }
+
+        let config_space =
+            Self::build_config_space(&disk_properties, config.geometry.as_ref(), discard)?;
+
         let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?];
 
-        let queues = BLOCK_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let queues: Vec<Queue> = BLOCK_QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let max_chain_len = config.max_chain_len.unwrap_or(BLOCK_QUEUE_SIZES[0]);
+        if max_chain_len == 0 || max_chain_len > BLOCK_QUEUE_SIZES[0] {
+            return Err(VirtioBlockError::InvalidMaxChainLen(max_chain_len));
+        }
+
+        let request_recorder = config
+            .request_record_path
+            .map(RequestRecorder::new)
+            .transpose()
+            .map_err(VirtioBlockError::RequestRecord)?;
+
+        let notification_suppressor = config
+            .restore_notification_window_ms
+            .map(NotificationSuppressor::new)
+            .transpose()
+            .map_err(VirtioBlockError::NotificationSuppression)?;
+
+        let write_batcher = config
+            .write_batch
+            .map(|write_batch| match disk_properties.file_engine {
+                FileEngine::Sync(_) => WriteBatcher::new(write_batch),
+                FileEngine::Async(_) => Err(WriteBatchError::UnsupportedEngine),
+            })
+            .transpose()
+            .map_err(VirtioBlockError::WriteBatch)?;
 
         Ok(VirtioBlock {
             avail_features,
             acked_features: 0u64,
-            config_space: disk_properties.virtio_block_config_space(),
+            config_space,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?,
 
             queues,
@@ -346,9 +562,42 @@ impl VirtioBlock {
             rate_limiter,
             is_io_engine_throttled: false,
             metrics: BlockMetricsPerDevice::alloc(config.drive_id),
+            interrupt_moderator,
+            io_retrier,
+            enabled: true,
+            geometry: config.geometry,
+            discard,
+            max_chain_len,
+            in_flight_requests: Vec::new(),
+            retry_requests: Vec::new(),
+            restored_requests: Vec::new(),
+            last_error: LastError::default(),
+            request_recorder,
+            notification_suppressor,
+            write_batcher,
         })
     }
 
+    /// Builds the config space of the device, including the geometry if one is configured and
+    /// the discard limits if discard is offered.
+    pub(crate) fn build_config_space(
+        disk: &DiskProperties,
+        geometry: Option<&DiskGeometryConfig>,
+        discard: bool,
+    ) -> Result<Vec<u8>, VirtioBlockError> {
+        let mut config_space = disk.virtio_block_config_space();
+        if let Some(geometry) = geometry {
+            geometry
+                .resolve(disk.nsectors)
+                .map_err(VirtioBlockError::Geometry)?
+                .write_config(&mut config_space);
+        }
+        if discard {
+            discard::write_config(&mut config_space);
+        }
+        Ok(config_space)
+    }
+
     /// Returns a copy of a device config
     pub fn config(&self) -> VirtioBlockConfig {
         let rl: RateLimiterConfig = (&self.rate_limiter).into();
@@ -361,6 +610,45 @@ impl VirtioBlock {
             cache_type: self.cache_type,
             rate_limiter: rl.into_option(),
             file_engine_type: self.file_engine_type(),
+            interrupt_moderation: self.interrupt_moderation(),
+            io_retry: self.io_retry(),
+            geometry: self.geometry,
+            discard: self.discard,
+            max_chain_len: Some(self.max_chain_len),
+            request_record_path: self
+                .request_recorder
+                .as_ref()
+                .map(|recorder| recorder.path().to_string()),
+            restore_notification_window_ms: self.restore_notification_window_ms(),
+            write_batch: self.write_batch(),
+            fd_on_host: self.disk.fd,
+        }
+    }
+
+    /// Returns the backing file of the device, with the flags it is currently open with.
+    pub fn backing_info(&self) -> BackingInfo {
+        // The flags are read from the open file rather than from the config, to catch a device
+        // left on a stale or differently opened file.
+        // SAFETY: `fcntl` does not touch memory; the result is checked below.
+        let flags = unsafe { libc::fcntl(self.disk.file_engine.file().as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            warn!(
+                target: &self.id,
+                "Could not read the flags of the backing file of block {}: {}",
+                self.id,
+                std::io::Error::last_os_error()
+            );
+        }
+        BackingInfo {
+            path: self.disk.file_path.clone(),
+            read_only: if flags < 0 {
+                self.read_only
+            } else {
+                flags & libc::O_ACCMODE == libc::O_RDONLY
+            },
+            direct: flags >= 0 && flags & libc::O_DIRECT != 0,
+            cache_type: self.cache_type,
+            file_engine_type: Some(self.file_engine_type()),
         }
     }
 
@@ -370,13 +658,19 @@ impl VirtioBlock {
     /// about new buffers in the queue.
     pub(crate) fn process_queue_event(&mut self) {
         self.metrics.queue_event_count.inc();
-        if let Err(err) = self.queue_evts[0].read() {
+        if let Err(err) = self.queues[0].read_kicks(&self.queue_evts[0]) {
             error!("Failed to get queue event: {:?}", err);
             self.metrics.event_fails.inc();
         } else if self.rate_limiter.is_blocked() {
             self.metrics.rate_limiter_throttled_events.inc();
         } else if self.is_io_engine_throttled {
             self.metrics.io_engine_throttled_events.inc();
+        } else if !self
+            .notification_suppressor
+            .as_mut()
+            .map_or(true, NotificationSuppressor::on_notification)
+        {
+            self.metrics.suppressed_queue_events.inc();
         } else {
             self.process_virtio_queues();
         }
@@ -402,12 +696,28 @@ impl VirtioBlock {
         len: u32,
         mem: &GuestMemoryMmap,
         irq_trigger: &IrqTrigger,
+        interrupt_moderator: Option<&mut InterruptModerator>,
         block_metrics: &BlockDeviceMetrics,
     ) {
         queue.add_used(mem, index, len).unwrap_or_else(|err| {
             error!("Failed to add available descriptor head {}: {}", index, err)
         });
 
+        if let Some(moderator) = interrupt_moderator {
+            if !moderator.on_completion() {
+                return;
+            }
+        }
+
+        Self::signal_used_queue(queue, mem, irq_trigger, block_metrics);
+    }
+
+    fn signal_used_queue(
+        queue: &mut Queue,
+        mem: &GuestMemoryMmap,
+        irq_trigger: &IrqTrigger,
+        block_metrics: &BlockDeviceMetrics,
+    ) {
         if queue.prepare_kick(mem) {
             irq_trigger.trigger_irq(IrqType::Vring).unwrap_or_else(|_| {
                 block_metrics.event_fails.inc();
@@ -417,16 +727,146 @@ impl VirtioBlock {
 
     /// Device specific function for peaking inside a queue and processing descriptors.
     pub fn process_queue(&mut self, queue_index: usize) {
-        // This is safe since we checked in the event handler that the device is activated.
+        // Requests are left in the queue while the device is disabled.
+        if !self.enabled {
+            return;
+        }
+
+        // A failed request is waiting for its backoff, keep the queue order until it is retried.
+        if self
+            .io_retrier
+            .as_ref()
+            .map_or(false, IoRetrier::is_pending)
+        {
+            return;
+        }
+
+        // The failed requests go first, the queue waits until they are all submitted again.
+        if self.resubmit_retry_requests() {
+            let mut used_any = false;
+            loop {
+                let (used, flush_batch) = self.process_queue_requests(queue_index);
+                used_any |= used;
+                if !flush_batch {
+                    break;
+                }
+                // The batched writes land before the requests after them are processed.
+                self.flush_write_batch();
+            }
+
+            if !used_any {
+                self.metrics.no_avail_buffer.inc();
+            }
+        }
+
+        if let FileEngine::Async(ref mut engine) = self.disk.file_engine {
+            if let Err(err) = engine.kick_submission_queue() {
+                error!("BlockError submitting pending block requests: {:?}", err);
+            }
+        }
+
+        if let Some(recorder) = self.request_recorder.as_mut() {
+            if let Err(err) = recorder.flush() {
+                error!("Failed to write recorded block requests: {}", err);
+            }
+        }
+    }
+
+    /// Submits the requests that failed in the IO engine again. Returns `false` if some of them
+    /// wait for another retry or for room in the IO engine.
+    fn resubmit_retry_requests(&mut self) -> bool {
+        if self.retry_requests.is_empty() {
+            return true;
+        }
+        // This is safe since failed requests only exist on an activated device.
         let mem = self.device_state.mem().unwrap();
 
+        let mut requests = std::mem::take(&mut self.retry_requests).into_iter();
+        while let Some(pending) = requests.next() {
+            match pending.retry(
+                &mut self.disk,
+                mem,
+                self.io_retrier.as_mut(),
+                self.cache_type == CacheType::Unsafe,
+                &self.metrics,
+            ) {
+                ProcessingResult::Submitted => (),
+                ProcessingResult::Throttled(pending) => {
+                    self.is_io_engine_throttled = true;
+                    self.retry_requests.push(pending);
+                    self.retry_requests.extend(requests);
+                    return false;
+                }
+                ProcessingResult::Retry(pending) => {
+                    self.retry_requests.push(pending);
+                    self.retry_requests.extend(requests);
+                    return false;
+                }
+                ProcessingResult::Executed(finished) => {
+                    self.in_flight_requests
+                        .retain(|request| request.desc_idx != finished.desc_idx);
+                    if let Some(err) = finished.error {
+                        self.last_error.record(err);
+                    }
+                    Self::add_used_descriptor(
+                        &mut self.queues[0],
+                        finished.desc_idx,
+                        finished.num_bytes_to_mem,
+                        mem,
+                        &self.irq_trigger,
+                        self.interrupt_moderator.as_mut(),
+                        &self.metrics,
+                    );
+                }
+            }
+        }
+        true
+    }
+
+    /// Pops and processes the available requests of the queue, until the queue is empty or
+    /// processing must stop. Returns whether any request was taken, and whether processing
+    /// stopped for the batched writes to be written first.
+    fn process_queue_requests(&mut self, queue_index: usize) -> (bool, bool) {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
+        let mut flush_batch = false;
 
         while let Some(head) = queue.pop_or_enable_notification(mem) {
             self.metrics.remaining_reqs_count.add(queue.len(mem).into());
-            let processing_result = match Request::parse(&head, mem, self.disk.nsectors) {
-                Ok(request) => {
+            let parsed =
+                match Request::reject_too_long(&head, mem, self.max_chain_len, &self.metrics) {
+                    Some(finished) => Err(finished),
+                    None => Request::parse(&head, mem, self.disk.nsectors).map_err(|err| {
+                        let msg = format!("Failed to parse available descriptor chain: {:?}", err);
+                        error!("{}", msg);
+                        self.metrics.execute_fails.inc();
+                        FinishedRequest {
+                            num_bytes_to_mem: 0,
+                            desc_idx: head.index,
+                            error: Some(msg),
+                        }
+                    }),
+                };
+            let processing_result = match parsed {
+                Ok(mut request) => {
+                    if !self.discard
+                        && matches!(
+                            request.r#type,
+                            RequestType::Discard | RequestType::WriteZeroes
+                        )
+                    {
+                        // The guest was not offered these requests.
+                        request.r#type = RequestType::Unsupported(request.r#type.into());
+                    }
+
+                    if Self::needs_batch_flush(self.write_batcher.as_ref(), &request) {
+                        queue.undo_pop();
+                        flush_batch = true;
+                        break;
+                    }
+
                     if request.rate_limit(&mut self.rate_limiter) {
                         // Stop processing the queue and return this descriptor chain to the
                         // avail ring, for later processing.
@@ -436,32 +876,170 @@ impl VirtioBlock {
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem, &self.metrics)
+                    let in_flight = request.to_in_flight(head.index);
+                    let result = match self.write_batcher.as_mut() {
+                        Some(batcher) if request.r#type == RequestType::Out => {
+                            flush_batch = request.batch(batcher, head.index);
+                            ProcessingResult::Submitted
+                        }
+                        _ => request.process(
+                            &mut self.disk,
+                            head.index,
+                            mem,
+                            self.io_retrier.as_mut(),
+                            self.cache_type == CacheType::Unsafe,
+                            &self.metrics,
+                        ),
+                    };
+                    if let ProcessingResult::Submitted = result {
+                        self.in_flight_requests.push(in_flight);
+                    }
+                    result
                 }
-                Err(err) => {
-                    error!("Failed to parse available descriptor chain: {:?}", err);
+                Err(finished) => ProcessingResult::Executed(finished),
+            };
+
+            Self::record_request(
+                self.request_recorder.as_mut(),
+                &head,
+                mem,
+                &processing_result,
+            );
+
+            match processing_result {
+                ProcessingResult::Submitted => (),
+                ProcessingResult::Throttled(_) => {
+                    queue.undo_pop();
+                    self.is_io_engine_throttled = true;
+                    break;
+                }
+                ProcessingResult::Retry(pending) => {
+                    // Return the failed request to the avail ring, it is processed again
+                    // once the retry timer fires. It already went through the rate limiter.
+                    pending.refund_rate_limit(&mut self.rate_limiter);
+                    queue.undo_pop();
+                    break;
+                }
+                ProcessingResult::Executed(finished) => {
+                    if let Some(retrier) = self.io_retrier.as_mut() {
+                        retrier.reset();
+                    }
+                    if let Some(err) = finished.error {
+                        self.last_error.record(err);
+                    }
+                    Self::add_used_descriptor(
+                        queue,
+                        head.index,
+                        finished.num_bytes_to_mem,
+                        mem,
+                        &self.irq_trigger,
+                        self.interrupt_moderator.as_mut(),
+                        &self.metrics,
+                    );
+                }
+            }
+
+            if flush_batch {
+                break;
+            }
+        }
+
+        (used_any, flush_batch)
+    }
+
+    /// Whether the batched writes must be written before `request` is executed, which is the
+    /// case unless it is a write that extends the batch.
+    fn needs_batch_flush(batcher: Option<&WriteBatcher>, request: &Request) -> bool {
+        batcher.map_or(false, |batcher| {
+            !batcher.is_empty()
+                && !(request.r#type == RequestType::Out && batcher.is_sequential(request.offset()))
+        })
+    }
+
+    /// Records a request taken from the queue. Requests returned to the avail ring are recorded
+    /// once they are taken for good.
+    fn record_request(
+        recorder: Option<&mut RequestRecorder>,
+        head: &DescriptorChain,
+        mem: &GuestMemoryMmap,
+        processing_result: &ProcessingResult,
+    ) {
+        if let Some(recorder) = recorder {
+            if matches!(
+                processing_result,
+                ProcessingResult::Submitted | ProcessingResult::Executed(_)
+            ) {
+                if let Err(err) = recorder.record(head, mem) {
+                    error!("Failed to record block request: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Executes again the requests that were in flight when the device was snapshotted.
+    ///
+    /// Their descriptor chains were already popped from the avail ring before the snapshot, so
+    /// they are looked up by index and checked against what was persisted.
+    pub(crate) fn process_restored_requests(&mut self) {
+        if !self.enabled || self.restored_requests.is_empty() {
+            return;
+        }
+
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let queue = &mut self.queues[0];
+
+        let mut restored = std::mem::take(&mut self.restored_requests).into_iter();
+        while let Some(in_flight) = restored.next() {
+            let request = queue
+                .get_desc_chain(mem, in_flight.desc_idx)
+                .and_then(|head| Request::parse(&head, mem, self.disk.nsectors).ok())
+                .filter(|request| request.to_in_flight(in_flight.desc_idx) == in_flight);
+
+            let processing_result = match request {
+                Some(request) => request.process(
+                    &mut self.disk,
+                    in_flight.desc_idx,
+                    mem,
+                    None,
+                    self.cache_type == CacheType::Unsafe,
+                    &self.metrics,
+                ),
+                None => {
+                    let msg = format!(
+                        "Failed to restore in-flight block request at descriptor {}",
+                        in_flight.desc_idx
+                    );
+                    error!("{}", msg);
                     self.metrics.execute_fails.inc();
                     ProcessingResult::Executed(FinishedRequest {
                         num_bytes_to_mem: 0,
-                        desc_idx: head.index,
+                        desc_idx: in_flight.desc_idx,
+                        error: Some(msg),
                     })
                 }
             };
 
             match processing_result {
-                ProcessingResult::Submitted => {}
-                ProcessingResult::Throttled => {
-                    queue.undo_pop();
+                ProcessingResult::Submitted => self.in_flight_requests.push(in_flight),
+                ProcessingResult::Throttled(_) | ProcessingResult::Retry(_) => {
+                    // Keep the request and the ones after it for when the IO engine frees up.
+                    self.restored_requests.push(in_flight);
+                    self.restored_requests.extend(restored.by_ref());
                     self.is_io_engine_throttled = true;
                     break;
                 }
                 ProcessingResult::Executed(finished) => {
+                    if let Some(err) = finished.error {
+                        self.last_error.record(err);
+                    }
                     Self::add_used_descriptor(
                         queue,
-                        head.index,
+                        finished.desc_idx,
                         finished.num_bytes_to_mem,
                         mem,
                         &self.irq_trigger,
+                        self.interrupt_moderator.as_mut(),
                         &self.metrics,
                     );
                 }
@@ -473,10 +1051,6 @@ impl VirtioBlock {
                 error!("BlockError submitting pending block requests: {:?}", err);
             }
         }
-
-        if !used_any {
-            self.metrics.no_avail_buffer.inc();
-        }
     }
 
     fn process_async_completion_queue(&mut self) {
@@ -495,18 +1069,31 @@ impl VirtioBlock {
                 Ok(None) => break,
                 Ok(Some(cqe)) => {
                     let res = cqe.result();
-                    let user_data = cqe.user_data();
-
-                    let (pending, res) = match res {
-                        Ok(count) => (user_data, Ok(count)),
-                        Err(error) => (
-                            user_data,
-                            Err(IoErr::FileEngine(block_io::BlockIoError::Async(
-                                async_io::AsyncIoError::IO(error),
-                            ))),
-                        ),
+                    let pending = cqe.user_data();
+
+                    let res = match res {
+                        Ok(count) => {
+                            if let Some(retrier) = self.io_retrier.as_mut() {
+                                retrier.reset();
+                            }
+                            Ok(count)
+                        }
+                        Err(_) if self.io_retrier.as_mut().map_or(false, IoRetrier::schedule) => {
+                            // The request is submitted again once the retry timer fires.
+                            self.metrics.io_retry_count.inc();
+                            self.retry_requests.push(pending);
+                            continue;
+                        }
+                        Err(error) => Err(IoErr::FileEngine(block_io::BlockIoError::Async(
+                            async_io::AsyncIoError::IO(error),
+                        ))),
                     };
                     let finished = pending.finish(mem, res, &self.metrics);
+                    self.in_flight_requests
+                        .retain(|request| request.desc_idx != finished.desc_idx);
+                    if let Some(err) = finished.error {
+                        self.last_error.record(err);
+                    }
 
                     Self::add_used_descriptor(
                         queue,
@@ -514,6 +1101,7 @@ impl VirtioBlock {
                         finished.num_bytes_to_mem,
                         mem,
                         &self.irq_trigger,
+                        self.interrupt_moderator.as_mut(),
                         &self.metrics,
                     );
                 }
@@ -531,87 +1119,325 @@ impl VirtioBlock {
 
             if self.is_io_engine_throttled {
                 self.is_io_engine_throttled = false;
+                self.process_restored_requests();
                 self.process_queue(0);
             }
         }
     }
 
-    /// Update the backing file and the config space of the block device.
-    pub fn update_disk_image(&mut self, disk_image_path: String) -> Result<(), VirtioBlockError> {
-        self.disk.update(disk_image_path, self.read_only)?;
-        self.config_space = self.disk.virtio_block_config_space();
-
-        // Kick the driver to pick up the changes.
-        self.irq_trigger.trigger_irq(IrqType::Config).unwrap();
+    pub(crate) fn process_moderation_timer_event(&mut self) {
+        let notify = self
+            .interrupt_moderator
+            .as_mut()
+            .map_or(false, InterruptModerator::on_timer);
+        if notify {
+            self.signal_moderated_completions();
+        }
+    }
 
-        self.metrics.update_count.inc();
-        Ok(())
+    pub(crate) fn process_notification_window_event(&mut self) {
+        let suppressed = self
+            .notification_suppressor
+            .as_mut()
+            .map_or(false, NotificationSuppressor::on_timer);
+        if suppressed {
+            self.process_virtio_queues();
+        }
     }
 
-    /// Updates the parameters for the rate limiter
-    pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
-        self.rate_limiter.update_buckets(bytes, ops);
+    /// Returns the window during which queue notifications are coalesced after a restore, if any.
+    pub fn restore_notification_window_ms(&self) -> Option<u64> {
+        self.notification_suppressor
+            .as_ref()
+            .map(NotificationSuppressor::window_ms)
     }
 
-    /// Retrieve the file engine type.
-    pub fn file_engine_type(&self) -> FileEngineType {
-        match self.disk.file_engine {
-            FileEngine::Sync(_) => FileEngineType::Sync,
-            FileEngine::Async(_) => FileEngineType::Async,
+    pub(crate) fn process_write_batch_timer_event(&mut self) {
+        let flush = self
+            .write_batcher
+            .as_mut()
+            .map_or(false, WriteBatcher::on_timer);
+        if flush {
+            self.flush_write_batch();
         }
     }
 
-    fn drain_and_flush(&mut self, discard: bool) {
-        if let Err(err) = self.disk.file_engine.drain_and_flush(discard) {
-            error!("Failed to drain ops and flush block data: {:?}", err);
+    /// Writes the batched writes to the backing file and completes their requests.
+    fn flush_write_batch(&mut self) {
+        let batcher = match self.write_batcher.as_mut() {
+            Some(batcher) if !batcher.is_empty() => batcher,
+            _ => return,
+        };
+        // This is safe since batched writes only exist on an activated device.
+        let mem = self.device_state.mem().unwrap();
+
+        for finished in batcher.flush(&mut self.disk.file_engine, mem, &self.metrics) {
+            self.in_flight_requests
+                .retain(|request| request.desc_idx != finished.desc_idx);
+            if let Some(err) = finished.error {
+                self.last_error.record(err);
+            }
+            Self::add_used_descriptor(
+                &mut self.queues[0],
+                finished.desc_idx,
+                finished.num_bytes_to_mem,
+                mem,
+                &self.irq_trigger,
+                self.interrupt_moderator.as_mut(),
+                &self.metrics,
+            );
         }
     }
 
-    /// Prepare device for being snapshotted.
-    pub fn prepare_save(&mut self) {
-        if !self.is_activated() {
-            return;
+    pub(crate) fn process_io_retry_timer_event(&mut self) {
+        let retry = self.io_retrier.as_mut().map_or(false, IoRetrier::on_timer);
+        if retry {
+            self.process_queue(0);
         }
+    }
 
-        self.drain_and_flush(false);
-        if let FileEngine::Async(ref _engine) = self.disk.file_engine {
-            self.process_async_completion_queue();
+    /// Notifies the guest about completions held back by interrupt moderation.
+    fn flush_moderated_completions(&mut self) {
+        let notify = self
+            .interrupt_moderator
+            .as_mut()
+            .map_or(false, InterruptModerator::flush);
+        if notify {
+            self.signal_moderated_completions();
         }
     }
-}
 
-impl VirtioDevice for VirtioBlock {
-    fn avail_features(&self) -> u64 {
-        self.avail_features
+    fn signal_moderated_completions(&mut self) {
+        // This is safe since moderated completions only exist on an activated device.
+        let mem = self.device_state.mem().unwrap();
+        Self::signal_used_queue(&mut self.queues[0], mem, &self.irq_trigger, &self.metrics);
     }
 
-    fn acked_features(&self) -> u64 {
-        self.acked_features
+    /// Returns the interrupt moderation config, if any.
+    pub fn interrupt_moderation(&self) -> Option<InterruptModerationConfig> {
+        self.interrupt_moderator
+            .as_ref()
+            .map(InterruptModerator::config)
     }
 
-    fn set_acked_features(&mut self, acked_features: u64) {
-        self.acked_features = acked_features;
+    /// Returns the I/O retry policy, if any.
+    pub fn io_retry(&self) -> Option<IoRetryConfig> {
+        self.io_retrier.as_ref().map(IoRetrier::config)
     }
 
-    fn device_type(&self) -> u32 {
-        TYPE_BLOCK
+    /// Returns the write batching config, if any.
+    pub fn write_batch(&self) -> Option<WriteBatchConfig> {
+        self.write_batcher.as_ref().map(WriteBatcher::config)
     }
 
-    fn queues(&self) -> &[Queue] {
-        &self.queues
-    }
+    /// Update the backing file and the config space of the block device.
+    pub fn update_disk_image(&mut self, disk_image_path: String) -> Result<(), VirtioBlockError> {
+        self.disk.update(disk_image_path, self.read_only)?;
+        self.config_space =
+            Self::build_config_space(&self.disk, self.geometry.as_ref(), self.discard)?;
 
-    fn queues_mut(&mut self) -> &mut [Queue] {
-        &mut self.queues
-    }
+        // Kick the driver to pick up the changes.
+        self.irq_trigger.trigger_irq(IrqType::Config).unwrap();
 
-    fn queue_events(&self) -> &[EventFd] {
-        &self.queue_evts
+        self.metrics.update_count.inc();
+        Ok(())
     }
 
-    fn interrupt_evt(&self) -> &EventFd {
-        &self.irq_trigger.irq_evt
-    }
+    /// Grows the disk to `disk_size` bytes, after its backing file grew on the host, and notifies
+    /// the driver of the new capacity.
+    pub fn update_disk_size(&mut self, disk_size: u64) -> Result<(), VirtioBlockError> {
+        if disk_size % u64::from(SECTOR_SIZE) != 0 {
+            return Err(VirtioBlockError::InvalidDiskSize(disk_size));
+        }
+        let current_size = self.disk.nsectors << SECTOR_SHIFT;
+        if disk_size < current_size {
+            return Err(VirtioBlockError::DiskShrink(current_size, disk_size));
+        }
+        let file_size = self
+            .disk
+            .file_engine
+            .file()
+            .metadata()
+            .map_err(VirtioBlockError::GetFileMetadata)?
+            .len();
+        if file_size < disk_size {
+            return Err(VirtioBlockError::BackingFileTooSmall(disk_size));
+        }
+
+        let nsectors = self.disk.nsectors;
+        self.disk.nsectors = disk_size >> SECTOR_SHIFT;
+        self.config_space =
+            match Self::build_config_space(&self.disk, self.geometry.as_ref(), self.discard) {
+                Ok(config_space) => config_space,
+                Err(err) => {
+                    self.disk.nsectors = nsectors;
+                    return Err(err);
+                }
+            };
+
+        // Kick the driver to pick up the changes.
+        if let Err(err) = self.irq_trigger.trigger_irq(IrqType::Config) {
+            error!("Failed to signal the new disk size of {}: {}", self.id, err);
+        }
+
+        self.metrics.update_count.inc();
+        Ok(())
+    }
+
+    /// Updates the parameters for the rate limiter
+    pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.rate_limiter.update_buckets(bytes, ops);
+    }
+
+    /// Switches the cache type of the device.
+    ///
+    /// Moving from `Unsafe` to `Writeback` first flushes everything written so far, so data the
+    /// guest wrote while flushes were ignored isn't left unsynced. The flush feature offered to
+    /// the driver follows the new cache type, but only takes effect when the driver negotiates
+    /// features again; until then, a driver that acked `VIRTIO_BLK_F_FLUSH` keeps sending flush
+    /// requests, which are synced or ignored according to the current cache type.
+    pub fn set_cache_type(&mut self, cache_type: CacheType) {
+        if self.cache_type == cache_type {
+            return;
+        }
+
+        match cache_type {
+            CacheType::Writeback => {
+                self.drain_and_flush(false);
+                if self.is_activated() {
+                    if let FileEngine::Async(ref _engine) = self.disk.file_engine {
+                        self.process_async_completion_queue();
+                    }
+                    if self.acked_features & (1u64 << VIRTIO_BLK_F_FLUSH) == 0 {
+                        warn!(
+                            target: &self.id,
+                            "Block device {} switched to writeback, but the driver didn't \
+                             negotiate flushes; they take effect after the device is reset.",
+                            self.id
+                        );
+                    }
+                }
+                self.avail_features |= 1u64 << VIRTIO_BLK_F_FLUSH;
+            }
+            CacheType::Unsafe => {
+                self.avail_features &= !(1u64 << VIRTIO_BLK_F_FLUSH);
+            }
+        }
+        self.cache_type = cache_type;
+    }
+
+    /// Retrieve the file engine type.
+    pub fn file_engine_type(&self) -> FileEngineType {
+        match self.disk.file_engine {
+            FileEngine::Sync(_) => FileEngineType::Sync,
+            FileEngine::Async(_) => FileEngineType::Async,
+        }
+    }
+
+    /// Waits at most `timeout` for the requests submitted to the IO engine to complete.
+    ///
+    /// The kernel posts completions to the io_uring completion queue without any syscall on our
+    /// side, so they are reaped through the regular completion path, yielding the CPU in between.
+    fn wait_for_in_flight_requests(&mut self, timeout: Duration) -> Result<(), QuiesceError> {
+        // Batched writes are written right away.
+        self.flush_write_batch();
+        match self.disk.file_engine {
+            // Requests pushed since the last kick must reach the kernel to complete.
+            FileEngine::Async(ref mut engine) => {
+                if let Err(err) = engine.kick_submission_queue() {
+                    error!("BlockError submitting pending block requests: {:?}", err);
+                }
+            }
+            FileEngine::Sync(_) => return Ok(()),
+        }
+        // A timeout too large to represent never expires.
+        let deadline = Instant::now().checked_add(timeout);
+
+        loop {
+            self.process_async_completion_queue();
+            // The queue is not processed while quiescing, so the failed requests are submitted
+            // again from here once their backoff elapsed.
+            if let Some(retrier) = self.io_retrier.as_mut() {
+                retrier.on_timer();
+                if !retrier.is_pending() && !self.retry_requests.is_empty() {
+                    self.resubmit_retry_requests();
+                    if let FileEngine::Async(ref mut engine) = self.disk.file_engine {
+                        if let Err(err) = engine.kick_submission_queue() {
+                            error!("BlockError submitting pending block requests: {:?}", err);
+                        }
+                    }
+                }
+            }
+            if self.in_flight_requests.is_empty() {
+                return Ok(());
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(QuiesceError::Timeout(self.in_flight_requests.len()));
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    fn drain_and_flush(&mut self, discard: bool) {
+        self.flush_write_batch();
+        if let Err(err) = self.disk.file_engine.drain_and_flush(discard) {
+            error!("Failed to drain ops and flush block data: {:?}", err);
+        }
+    }
+
+    /// Prepare device for being snapshotted.
+    pub fn prepare_save(&mut self) {
+        if !self.is_activated() {
+            return;
+        }
+
+        self.drain_and_flush(false);
+        if let FileEngine::Async(ref _engine) = self.disk.file_engine {
+            self.process_async_completion_queue();
+            // The failed requests are retried right away, until they complete or run out of
+            // retries.
+            while !self.retry_requests.is_empty() {
+                self.resubmit_retry_requests();
+                self.drain_and_flush(false);
+                self.process_async_completion_queue();
+            }
+        }
+        self.flush_moderated_completions();
+    }
+}
+
+impl VirtioDevice for VirtioBlock {
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn device_type(&self) -> u32 {
+        TYPE_BLOCK
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
 
     /// Returns the current device interrupt status.
     fn interrupt_status(&self) -> Arc<AtomicU32> {
@@ -635,18 +1461,13 @@ impl VirtioDevice for VirtioBlock {
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        let start = usize::try_from(offset).ok();
-        let end = start.and_then(|s| s.checked_add(data.len()));
-        let Some(dst) = start
-            .zip(end)
-            .and_then(|(start, end)| self.config_space.get_mut(start..end))
-        else {
-            error!("Failed to write config space");
-            self.metrics.cfg_fails.inc();
-            return;
-        };
-
-        dst.copy_from_slice(data);
+        // All the fields of the config space are owned by the device: the only field the driver
+        // may write is `writeback`, which needs VIRTIO_BLK_F_CONFIG_WCE and we don't offer it.
+        error!(
+            "Failed to write config space: offset {offset:#x}, length {}",
+            data.len()
+        );
+        self.metrics.cfg_fails.inc();
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
@@ -668,10 +1489,83 @@ impl VirtioDevice for VirtioBlock {
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn flush_pending_interrupts(&mut self) -> Result<(), std::io::Error> {
+        // Completions held back by interrupt moderation are signalled right away.
+        self.flush_moderated_completions();
+        if self.irq_trigger.irq_status.load(Ordering::SeqCst) != 0 {
+            self.irq_trigger.irq_evt.write(1)?;
+        }
+        Ok(())
+    }
+
+    fn rate_limiter_state(&self) -> Vec<(&'static str, RateLimiterBudgets)> {
+        vec![("rate_limiter", self.rate_limiter.budgets())]
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> bool {
+        let resume = enabled && !self.enabled;
+        self.enabled = enabled;
+        if resume && self.is_activated() {
+            // Pick up the requests that queued up while the device was disabled.
+            self.process_restored_requests();
+            self.process_queue(0);
+        }
+        true
+    }
+
+    fn quiesce(&mut self, timeout: Duration) -> Result<(), QuiesceError> {
+        self.enabled = false;
+        let res = if self.is_activated() {
+            self.wait_for_in_flight_requests(timeout)
+        } else {
+            Ok(())
+        }
+        .and_then(|()| {
+            self.disk
+                .file_engine
+                .drain_and_flush(false)
+                .map_err(|err| QuiesceError::Flush(err.to_string()))
+        });
+
+        if res.is_err() {
+            self.set_enabled(true);
+        } else if self.is_activated() {
+            self.flush_moderated_completions();
+        }
+        res
+    }
+
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        // Read the first sector and, unless the disk is read-only, write it back unchanged.
+        let file = self.disk.file_engine.file();
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        let count = file.read_at(&mut sector, 0).map_err(SelfTestError::Read)?;
+        if !self.read_only {
+            file.write_at(&sector[..count], 0)
+                .map_err(SelfTestError::Write)?;
+        }
+        Ok(())
+    }
+
+    fn set_id(&mut self, id: &str) -> bool {
+        BlockMetricsPerDevice::rename(&self.id, id.to_string());
+        self.id = id.to_string();
+        true
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
 }
 
 impl Drop for VirtioBlock {
     fn drop(&mut self) {
+        self.flush_write_batch();
         match self.cache_type {
             CacheType::Unsafe => {
                 if let Err(err) = self.disk.file_engine.drain(true) {
@@ -690,6 +1584,7 @@ mod tests {
     use std::fs::metadata;
     use std::io::Read;
     use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileExt;
     use std::time::Duration;
     use std::{thread, u32};
 
@@ -698,15 +1593,23 @@ mod tests {
 
     use super::*;
     use crate::check_metric_after_block;
+    use crate::devices::virtio::block::persist::BlockConstructorArgs;
+    use crate::devices::virtio::block::virtio::discard::{
+        DiscardSegment, DISCARD_CONFIG_SPACE_SIZE,
+    };
+    use crate::devices::virtio::block::virtio::geometry::{
+        GEOMETRY_CONFIG_OFFSET, GEOMETRY_CONFIG_SPACE_SIZE,
+    };
     use crate::devices::virtio::block::virtio::test_utils::{
-        default_block, default_engine_type_for_kv, read_blk_req_descriptors, set_queue,
-        set_rate_limiter, simulate_async_completion_event,
+        default_block, default_block_with_path, default_engine_type_for_kv,
+        read_blk_req_descriptors, set_queue, set_rate_limiter, simulate_async_completion_event,
         simulate_queue_and_async_completion_events, simulate_queue_event,
     };
     use crate::devices::virtio::block::virtio::IO_URING_NUM_ENTRIES;
     use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
     use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
     use crate::rate_limiter::TokenType;
+    use crate::snapshot::Persist;
     use crate::vstate::memory::{Address, Bytes, GuestAddress};
 
     #[test]
@@ -721,6 +1624,15 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -736,6 +1648,15 @@ mod tests {
             path_on_host: None,
             rate_limiter: None,
             file_engine_type: Default::default(),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: Some("sock".to_string()),
         };
@@ -751,10 +1672,84 @@ mod tests {
             path_on_host: Some("path".to_string()),
             rate_limiter: None,
             file_engine_type: Default::default(),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: Some("sock".to_string()),
         };
         VirtioBlockConfig::try_from(&block_config).unwrap_err();
+
+        // A descriptor is used instead of the path.
+        let mut block_config = BlockDeviceConfig {
+            drive_id: "".to_string(),
+            partuuid: None,
+            is_root_device: false,
+            cache_type: CacheType::Unsafe,
+
+            is_read_only: None,
+            path_on_host: None,
+            rate_limiter: None,
+            file_engine_type: Default::default(),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: Some(7),
+
+            socket: None,
+        };
+        let config = VirtioBlockConfig::try_from(&block_config).unwrap();
+        assert_eq!(config.path_on_host, "/proc/self/fd/7");
+        assert_eq!(config.fd_on_host, Some(7));
+        // The descriptor, not its path, survives the round trip.
+        let round_trip = BlockDeviceConfig::from(config);
+        assert_eq!(round_trip.path_on_host, None);
+        assert_eq!(round_trip.fd_on_host, Some(7));
+
+        // But not together with it.
+        block_config.path_on_host = Some("path".to_string());
+        VirtioBlockConfig::try_from(&block_config).unwrap_err();
+    }
+
+    #[test]
+    fn test_max_chain_len() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            FileEngineType::default(),
+        );
+        let config_with = |max_chain_len| {
+            let mut config = block.config();
+            config.max_chain_len = max_chain_len;
+            config
+        };
+
+        // The maximum chain length defaults to the queue size.
+        let block = VirtioBlock::new(config_with(None)).unwrap();
+        assert_eq!(block.config().max_chain_len, Some(BLOCK_QUEUE_SIZES[0]));
+
+        let block = VirtioBlock::new(config_with(Some(4))).unwrap();
+        assert_eq!(block.max_chain_len, 4);
+        assert_eq!(block.config().max_chain_len, Some(4));
+
+        for max_chain_len in [0, BLOCK_QUEUE_SIZES[0] + 1] {
+            assert!(matches!(
+                VirtioBlock::new(config_with(Some(max_chain_len))),
+                Err(VirtioBlockError::InvalidMaxChainLen(len)) if len == max_chain_len
+            ));
+        }
     }
 
     #[test]
@@ -840,38 +1835,99 @@ mod tests {
         assert_eq!(actual_config_space, expected_config_space);
     }
 
+    #[test]
+    fn test_geometry() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let block = default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            FileEngineType::Sync,
+        );
+        // No geometry is reported by default.
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_GEOMETRY), 0);
+        assert_eq!(block.config_space.len(), BLOCK_CONFIG_SPACE_SIZE);
+
+        let mut config = block.config();
+        config.geometry = Some(DiskGeometryConfig {
+            cylinders: None,
+            heads: Some(2),
+            sectors: Some(2),
+        });
+        drop(block);
+        let mut block = VirtioBlock::new(config).unwrap();
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_GEOMETRY), 0);
+
+        // The 8 sectors of the disk make for 2 cylinders of 2 heads and 2 sectors.
+        let mut config_space = [0u8; GEOMETRY_CONFIG_SPACE_SIZE];
+        block.read_config(0, &mut config_space);
+        assert_eq!(config_space[..8], [0x08, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            config_space[GEOMETRY_CONFIG_OFFSET..],
+            [0x02, 0x00, 0x02, 0x02]
+        );
+
+        // The cylinders follow the capacity of a new disk image.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x2000).unwrap();
+        block
+            .update_disk_image(f.as_path().to_str().unwrap().to_string())
+            .unwrap();
+        block.read_config(0, &mut config_space);
+        assert_eq!(
+            config_space[GEOMETRY_CONFIG_OFFSET..],
+            [0x04, 0x00, 0x02, 0x02]
+        );
+
+        // An invalid geometry is rejected.
+        let mut config = block.config();
+        config.geometry = Some(DiskGeometryConfig {
+            heads: Some(0),
+            ..Default::default()
+        });
+        drop(block);
+        assert!(matches!(
+            VirtioBlock::new(config),
+            Err(VirtioBlockError::Geometry(_))
+        ));
+    }
+
     #[test]
     fn test_virtio_write_config() {
         let mut block = default_block(default_engine_type_for_kv());
 
-        let expected_config_space: [u8; BLOCK_CONFIG_SPACE_SIZE] =
-            [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
-        block.write_config(0, &expected_config_space);
-
+        let mut expected_config_space = [0u8; BLOCK_CONFIG_SPACE_SIZE];
+        block.read_config(0, &mut expected_config_space);
         let mut actual_config_space = [0u8; BLOCK_CONFIG_SPACE_SIZE];
+
+        // The driver can't change the capacity of the disk.
+        let cfg_fails = block.metrics.cfg_fails.count();
+        block.write_config(0, &[0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
+        assert_eq!(block.metrics.cfg_fails.count(), cfg_fails + 1);
 
         // If priviledged user writes to `/dev/mem`, in block config space - byte by byte.
-        let expected_config_space = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00, 0x11];
-        for i in 0..expected_config_space.len() {
-            block.write_config(i as u64, &expected_config_space[i..=i]);
+        let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+        for i in 0..new_config_space.len() {
+            block.write_config(i as u64, &new_config_space[i..=i]);
         }
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
+        assert_eq!(block.metrics.cfg_fails.count(), cfg_fails + 9);
 
-        // Invalid write.
-        let new_config_space = [0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
+        // Out of range write.
         block.write_config(5, &new_config_space);
         // Make sure nothing got written.
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
+        assert_eq!(block.metrics.cfg_fails.count(), cfg_fails + 10);
 
         // Large offset that may cause an overflow.
         block.write_config(u64::MAX, &new_config_space);
         // Make sure nothing got written.
         block.read_config(0, &mut actual_config_space);
         assert_eq!(actual_config_space, expected_config_space);
+        assert_eq!(block.metrics.cfg_fails.count(), cfg_fails + 11);
     }
 
     #[test]
@@ -892,11 +1948,14 @@ mod tests {
         mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
             .unwrap();
 
+        assert!(block.last_error().is_none());
         simulate_queue_event(&mut block, Some(true));
 
         assert_eq!(vq.used.idx.get(), 1);
         assert_eq!(vq.used.ring[0].get().id, 0);
         assert_eq!(vq.used.ring[0].get().len, 0);
+        let (_, err) = block.last_error().unwrap();
+        assert!(err.starts_with("Failed to parse available descriptor chain"));
     }
 
     #[test]
@@ -1022,8 +2081,8 @@ mod tests {
         let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
         let status_addr = GuestAddress(vq.dtable[2].addr.get());
 
-        // Currently only VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
-        // VIRTIO_BLK_T_FLUSH and VIRTIO_BLK_T_GET_ID  are supported.
+        // Currently only VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_FLUSH,
+        // VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_DISCARD and VIRTIO_BLK_T_WRITE_ZEROES are supported.
         // Generate an unsupported request.
         let request_header = RequestHeader::new(42, 0);
         mem.write_obj::<RequestHeader>(request_header, request_type_addr)
@@ -1039,6 +2098,37 @@ mod tests {
             VIRTIO_BLK_S_UNSUPP
         );
     }
+
+    #[test]
+    fn test_chain_too_long() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+        block.max_chain_len = 2;
+
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u8>(0xff, status_addr).unwrap();
+
+        // The 3 descriptors of the read request are too many, the request fails without being
+        // parsed and the driver is notified.
+        simulate_queue_event(&mut block, Some(true));
+
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(vq.used.ring[0].get().len, 1);
+        assert_eq!(
+            u32::from(mem.read_obj::<u8>(status_addr).unwrap()),
+            VIRTIO_BLK_S_IOERR
+        );
+        assert_eq!(block.metrics.chains_too_long_count.count(), 1);
+        assert_eq!(block.metrics.read_count.count(), 0);
+        let (_, err) = block.last_error().unwrap();
+        assert!(err.starts_with("Descriptor chain of 3 descriptors"));
+    }
+
     #[test]
     fn test_end_of_region() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1439,6 +2529,158 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_cache_type() {
+        let mut block = default_block(default_engine_type_for_kv());
+        // Don't share the flush metrics with the other tests using the default drive id.
+        block.metrics = BlockMetricsPerDevice::alloc("test_set_cache_type".to_string());
+        assert_eq!(block.cache_type, CacheType::Unsafe);
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH), 0);
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        vq.dtable[0].next.set(2);
+
+        // In unsafe mode, flushes are acknowledged without syncing.
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+            .unwrap();
+        simulate_queue_and_async_completion_events(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert_eq!(block.metrics.flush_ignored_count.count(), 1);
+        assert_eq!(block.metrics.flush_count.count(), 0);
+
+        // After switching to writeback, flushes reach the disk.
+        block.set_cache_type(CacheType::Writeback);
+        assert_eq!(block.cache_type, CacheType::Writeback);
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH), 0);
+
+        vq.used.idx.set(0);
+        set_queue(&mut block, 0, vq.create_queue());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_FLUSH, request_type_addr)
+            .unwrap();
+        simulate_queue_and_async_completion_events(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert_eq!(block.metrics.flush_ignored_count.count(), 1);
+        assert_eq!(block.metrics.flush_count.count(), 1);
+
+        // Switching back stops offering flushes.
+        block.set_cache_type(CacheType::Unsafe);
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_FLUSH), 0);
+    }
+
+    #[test]
+    fn test_discard() {
+        let f = TempFile::new().unwrap();
+        f.as_file().write_all(&[0xaa; 0x1000]).unwrap();
+        let mut block = default_block_with_path(
+            f.as_path().to_str().unwrap().to_string(),
+            default_engine_type_for_kv(),
+        );
+        // Discard is not offered by default.
+        assert_eq!(block.avail_features() & (1u64 << VIRTIO_BLK_F_DISCARD), 0);
+        assert_eq!(
+            block.avail_features() & (1u64 << VIRTIO_BLK_F_WRITE_ZEROES),
+            0
+        );
+        assert_eq!(block.config_space.len(), BLOCK_CONFIG_SPACE_SIZE);
+
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+
+        // The segment is read by the device.
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1]
+            .len
+            .set(u32::try_from(std::mem::size_of::<DiscardSegment>()).unwrap());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_DISCARD, request_type_addr)
+            .unwrap();
+        let segment = DiscardSegment {
+            sector: 2,
+            num_sectors: 3,
+            flags: 0,
+        };
+        mem.write_obj(segment, data_addr).unwrap();
+
+        // Discard requests are unsupported when discard is not offered.
+        {
+            simulate_queue_and_async_completion_events(&mut block, true);
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(
+                mem.read_obj::<u32>(status_addr).unwrap(),
+                VIRTIO_BLK_S_UNSUPP
+            );
+        }
+
+        let mut config = block.config();
+        config.discard = true;
+        drop(block);
+        let mut block = VirtioBlock::new(config).unwrap();
+        assert_ne!(block.avail_features() & (1u64 << VIRTIO_BLK_F_DISCARD), 0);
+        assert_ne!(
+            block.avail_features() & (1u64 << VIRTIO_BLK_F_WRITE_ZEROES),
+            0
+        );
+        assert_eq!(block.config_space.len(), DISCARD_CONFIG_SPACE_SIZE);
+        vq.used.idx.set(0);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+
+        // The sectors of the segment are discarded.
+        {
+            check_metric_after_block!(
+                &block.metrics.discard_sectors,
+                3,
+                simulate_queue_and_async_completion_events(&mut block, true)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 1);
+            assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+
+            let data = std::fs::read(f.as_path()).unwrap();
+            let start = 2 << SECTOR_SHIFT;
+            let end = 5 << SECTOR_SHIFT;
+            assert!(data[..start].iter().all(|&b| b == 0xaa));
+            assert!(data[start..end].iter().all(|&b| b == 0));
+            assert!(data[end..].iter().all(|&b| b == 0xaa));
+        }
+
+        // Segments reaching past the end of the disk are rejected.
+        {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            let segment = DiscardSegment {
+                sector: 6,
+                num_sectors: 3,
+                flags: 0,
+            };
+            mem.write_obj(segment, data_addr).unwrap();
+
+            check_metric_after_block!(
+                &block.metrics.discard_sectors,
+                0,
+                simulate_queue_and_async_completion_events(&mut block, true)
+            );
+            assert_eq!(vq.used.idx.get(), 1);
+            assert_eq!(vq.used.ring[0].get().len, 0);
+        }
+    }
+
     #[test]
     fn test_get_device_id() {
         let mut block = default_block(default_engine_type_for_kv());
@@ -1811,4 +3053,470 @@ mod tests {
         );
         assert_eq!(block.disk.image_id, id.as_slice());
     }
+
+    #[test]
+    fn test_interrupt_moderation() {
+        let mut block = default_block(FileEngineType::Sync);
+        block.interrupt_moderator = Some(
+            InterruptModerator::new(InterruptModerationConfig {
+                max_completions: 4,
+                max_delay_us: 1000,
+            })
+            .unwrap(),
+        );
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 32);
+        block.activate(mem.clone()).unwrap();
+
+        // 10 completions are signalled in 2 full batches, the last 2 are held back.
+        add_flush_requests_batch(&mut block, &vq, 10);
+        simulate_queue_event(&mut block, None);
+        check_flush_requests_batch(10, &vq);
+        assert_eq!(block.irq_trigger.irq_evt.read().unwrap(), 2);
+        assert_eq!(block.interrupt_moderator.as_ref().unwrap().pending(), 2);
+
+        // The moderation timer signals the remaining completions.
+        thread::sleep(Duration::from_millis(2));
+        block.process_moderation_timer_event();
+        assert_eq!(block.irq_trigger.irq_evt.read().unwrap(), 1);
+        assert_eq!(block.interrupt_moderator.as_ref().unwrap().pending(), 0);
+
+        // Without pending completions the timer does not notify the guest.
+        block.process_moderation_timer_event();
+        assert!(!block.irq_trigger.has_pending_irq(IrqType::Vring));
+
+        // Flushing pending interrupts signals held back completions.
+        add_flush_requests_batch(&mut block, &vq, 1);
+        simulate_queue_event(&mut block, Some(false));
+        assert_eq!(block.interrupt_moderator.as_ref().unwrap().pending(), 1);
+        block.flush_pending_interrupts().unwrap();
+        assert!(block.irq_trigger.has_pending_irq(IrqType::Vring));
+        assert_eq!(block.interrupt_moderator.as_ref().unwrap().pending(), 0);
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let mut block = default_block(FileEngineType::Sync);
+        block.metrics = BlockMetricsPerDevice::alloc("test_write_batch".to_string());
+        block.write_batcher = Some(
+            WriteBatcher::new(WriteBatchConfig {
+                max_batch_size: 4,
+                flush_interval_us: 1000,
+            })
+            .unwrap(),
+        );
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 32);
+        block.activate(mem.clone()).unwrap();
+        set_queue(&mut block, 0, vq.create_queue());
+
+        // 6 sequential single sector writes, each with its header, data and status in its own
+        // chunk of guest memory.
+        let base = vq
+            .end()
+            .checked_align_up(std::mem::align_of::<RequestHeader>() as u64)
+            .unwrap();
+        for i in 0..6u16 {
+            let hdr_addr = base.unchecked_add(u64::from(i) * 0x400);
+            let data_addr = hdr_addr.unchecked_add(0x100);
+            let status_addr = data_addr.unchecked_add(u64::from(SECTOR_SIZE));
+            mem.write_obj(RequestHeader::new(VIRTIO_BLK_T_OUT, u64::from(i)), hdr_addr)
+                .unwrap();
+            mem.write_slice(
+                &[u8::try_from(i).unwrap() + 1; SECTOR_SIZE as usize],
+                data_addr,
+            )
+            .unwrap();
+
+            let idx = i * 3;
+            vq.dtable[usize::from(idx)].set(hdr_addr.0, 16, VIRTQ_DESC_F_NEXT, idx + 1);
+            vq.dtable[usize::from(idx) + 1].set(
+                data_addr.0,
+                SECTOR_SIZE,
+                VIRTQ_DESC_F_NEXT,
+                idx + 2,
+            );
+            vq.dtable[usize::from(idx) + 2].set(status_addr.0, 1, VIRTQ_DESC_F_WRITE, 0);
+            vq.avail.ring[usize::from(i)].set(idx);
+        }
+        vq.avail.idx.set(6);
+
+        // The first 4 writes are written together, the last 2 wait in the batch.
+        check_metric_after_block!(
+            &block.metrics.write_batch_count,
+            1,
+            simulate_queue_event(&mut block, Some(true))
+        );
+        assert_eq!(vq.used.idx.get(), 4);
+        assert_eq!(block.write_batcher.as_ref().unwrap().len(), 2);
+        assert_eq!(block.in_flight_requests.len(), 2);
+
+        // The timer writes the rest of the batch, then its requests complete.
+        thread::sleep(Duration::from_millis(2));
+        check_metric_after_block!(
+            &block.metrics.write_batch_count,
+            1,
+            block.process_write_batch_timer_event()
+        );
+        assert_eq!(vq.used.idx.get(), 6);
+        assert!(block.write_batcher.as_ref().unwrap().is_empty());
+        assert!(block.in_flight_requests.is_empty());
+        assert_eq!(block.metrics.write_count.count(), 6);
+
+        for i in 0..6u16 {
+            let used = vq.used.ring[usize::from(i)].get();
+            assert_eq!(used.len, 1);
+            let status_addr = vq.dtable[used.id as usize + 2].addr.get();
+            assert_eq!(
+                u32::from(mem.read_obj::<u8>(GuestAddress(status_addr)).unwrap()),
+                VIRTIO_BLK_S_OK
+            );
+        }
+        let mut data = vec![0u8; 6 * SECTOR_SIZE as usize];
+        block
+            .disk
+            .file_engine
+            .file()
+            .read_exact_at(&mut data, 0)
+            .unwrap();
+        for (i, sector) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+            assert!(sector.iter().all(|&b| usize::from(b) == i + 1));
+        }
+
+        // Without batched writes the timer writes nothing.
+        check_metric_after_block!(
+            &block.metrics.write_batch_count,
+            0,
+            block.process_write_batch_timer_event()
+        );
+    }
+
+    #[test]
+    fn test_io_retry() {
+        let mut block = default_block(FileEngineType::Sync);
+        block.io_retrier = Some(
+            IoRetrier::new(IoRetryConfig {
+                max_retries: 1,
+                backoff_ms: 1,
+            })
+            .unwrap(),
+        );
+        // A single op of budget, refilled every 1000 seconds.
+        block.rate_limiter = RateLimiter::new(0, 0, 0, 1, 0, 1_000_000).unwrap();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+
+        // Reads past the end of the backing file fail, the request waits for a retry.
+        let file_len = block.disk.file_engine.file().metadata().unwrap().len();
+        block.disk.file_engine.file().set_len(0).unwrap();
+        check_metric_after_block!(
+            &block.metrics.io_retry_count,
+            1,
+            simulate_queue_event(&mut block, Some(false))
+        );
+        assert_eq!(vq.used.idx.get(), 0);
+        assert!(block.io_retrier.as_ref().unwrap().is_pending());
+
+        // Queue events are ignored while the retry is pending.
+        simulate_queue_event(&mut block, Some(false));
+        assert_eq!(vq.used.idx.get(), 0);
+
+        // The backing store recovered, the request goes through when retried. It is not
+        // charged twice by the rate limiter.
+        block.disk.file_engine.file().set_len(file_len).unwrap();
+        thread::sleep(Duration::from_millis(2));
+        check_metric_after_block!(
+            &block.metrics.rate_limiter_throttled_events,
+            0,
+            block.process_io_retry_timer_event()
+        );
+        assert!(block.irq_trigger.has_pending_irq(IrqType::Vring));
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert!(!block.io_retrier.as_ref().unwrap().is_pending());
+    }
+
+    #[test]
+    fn test_async_io_retry() {
+        skip_if_io_uring_unsupported!();
+
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let mut block = default_block_with_path(path.clone(), FileEngineType::Async);
+        block.io_retrier = Some(
+            IoRetrier::new(IoRetryConfig {
+                max_retries: 1,
+                backoff_ms: 1,
+            })
+            .unwrap(),
+        );
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+
+        // Writes to a backing file opened read-only fail once submitted, the request waits
+        // for a retry.
+        block
+            .disk
+            .file_engine
+            .update_file_path(File::open(&path).unwrap())
+            .unwrap();
+        simulate_queue_event(&mut block, None);
+        check_metric_after_block!(
+            &block.metrics.io_retry_count,
+            1,
+            simulate_async_completion_event(&mut block, false)
+        );
+        assert_eq!(vq.used.idx.get(), 0);
+        assert_eq!(block.retry_requests.len(), 1);
+        assert_eq!(block.in_flight_requests.len(), 1);
+
+        // The backing store recovered, the request goes through when retried.
+        block
+            .disk
+            .file_engine
+            .update_file_path(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .unwrap(),
+            )
+            .unwrap();
+        thread::sleep(Duration::from_millis(2));
+        block.process_io_retry_timer_event();
+        assert!(block.retry_requests.is_empty());
+        simulate_async_completion_event(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        assert!(block.in_flight_requests.is_empty());
+    }
+
+    #[test]
+    fn test_quiesce() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+
+        let mut block = default_block_with_path(path.clone(), default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        let data = utils::rand::rand_alphanumerics(512).as_bytes().to_vec();
+
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(512);
+        mem.write_slice(&data, data_addr).unwrap();
+
+        // The write is submitted, but its completion is not processed yet.
+        simulate_queue_event(&mut block, None);
+
+        // Quiescing completes the pending write and flushes it to the backing file.
+        block.quiesce(Duration::from_secs(5)).unwrap();
+        assert!(!block.is_enabled());
+        assert!(block.in_flight_requests.is_empty());
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        let mut buf = vec![0u8; 512];
+        File::open(&path).unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        // New requests are left in the queue while the device is quiesced.
+        vq.used.idx.set(0);
+        set_queue(&mut block, 0, vq.create_queue());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+        vq.dtable[1]
+            .flags
+            .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        mem.write_slice(&[0u8; 512], data_addr).unwrap();
+        simulate_queue_event(&mut block, None);
+        assert_eq!(vq.used.idx.get(), 0);
+
+        // Resuming picks them up.
+        assert!(block.set_enabled(true));
+        simulate_async_completion_event(&mut block, true);
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        mem.read_slice(&mut buf, data_addr).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_restored_requests() {
+        let f = TempFile::new().unwrap();
+        let data = utils::rand::rand_alphanumerics(512).as_bytes().to_vec();
+        f.as_file().write_all(&data).unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+
+        let mut block = default_block_with_path(path, FileEngineType::Sync);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+            .unwrap();
+        vq.dtable[1].len.set(512);
+
+        // Pop the request as if it was submitted to the IO engine and did not complete yet.
+        let head = block.queues[0].pop(&mem).unwrap();
+        let request = Request::parse(&head, &mem, block.disk.nsectors).unwrap();
+        block
+            .in_flight_requests
+            .push(request.to_in_flight(head.index));
+
+        let mut restored_block =
+            VirtioBlock::restore(BlockConstructorArgs { mem: mem.clone() }, &block.save()).unwrap();
+        assert!(restored_block.in_flight_requests.is_empty());
+        assert_eq!(restored_block.restored_requests, block.in_flight_requests);
+        assert_eq!(vq.used.idx.get(), 0);
+
+        // The request is executed again after restore.
+        restored_block.process_restored_requests();
+        assert!(restored_block.restored_requests.is_empty());
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(vq.used.ring[0].get().id, 0);
+        assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
+        let mut buf = [0u8; 512];
+        mem.read_slice(&mut buf, data_addr).unwrap();
+        assert_eq!(buf, data.as_slice());
+
+        // A request that doesn't match its descriptor chain anymore is failed.
+        let mut in_flight = block.in_flight_requests[0];
+        in_flight.sector = 1;
+        restored_block.restored_requests.push(in_flight);
+        check_metric_after_block!(
+            &restored_block.metrics.execute_fails,
+            1,
+            restored_block.process_restored_requests()
+        );
+        assert_eq!(vq.used.idx.get(), 2);
+        assert_eq!(vq.used.ring[1].get().len, 0);
+    }
+
+    #[test]
+    fn test_self_test() {
+        let f = TempFile::new().unwrap();
+        let data = utils::rand::rand_alphanumerics(512).as_bytes().to_vec();
+        f.as_file().write_all(&data).unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+
+        // The first sector is read and written back unchanged.
+        let mut block = default_block_with_path(path.clone(), FileEngineType::Sync);
+        block.self_test().unwrap();
+        let mut buf = vec![0u8; 512];
+        File::open(&path).unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        // A backing file that rejects writes, as on a read-only mount, fails the self-test.
+        block.disk.file_engine =
+            FileEngine::from_file(File::open(&path).unwrap(), FileEngineType::Sync).unwrap();
+        let err = block.self_test().unwrap_err();
+        assert!(matches!(err, SelfTestError::Write(_)), "{err}");
+        assert!(err.to_string().starts_with("Cannot write the backing file"));
+
+        // Read-only disks are only read.
+        block.read_only = true;
+        block.self_test().unwrap();
+    }
+
+    #[test]
+    fn test_fd_on_host() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let rw_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let ro_file = File::open(&path).unwrap();
+
+        let config = |fd, is_read_only| VirtioBlockConfig {
+            drive_id: "fd".to_string(),
+            path_on_host: DiskProperties::fd_path(fd),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only,
+            cache_type: CacheType::Unsafe,
+            rate_limiter: None,
+            file_engine_type: FileEngineType::Sync,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: false,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: Some(fd),
+        };
+
+        // The device works on a duplicate of the descriptor, which the caller keeps owning.
+        let mut block = VirtioBlock::new(config(rw_file.as_raw_fd(), false)).unwrap();
+        assert_eq!(block.disk.nsectors, 0x1000 >> SECTOR_SHIFT);
+        assert_eq!(block.disk.fd, Some(rw_file.as_raw_fd()));
+        assert_eq!(block.config().fd_on_host, Some(rw_file.as_raw_fd()));
+        assert_eq!(block.config().path_on_host, None);
+        assert_ne!(
+            block.disk.file_engine.file().as_raw_fd(),
+            rw_file.as_raw_fd()
+        );
+        block.self_test().unwrap();
+        block
+            .disk
+            .file_engine
+            .file()
+            .write_all_at(b"written through the fd", 0)
+            .unwrap();
+        let mut buf = [0u8; 22];
+        File::open(&path).unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"written through the fd");
+
+        // A descriptor opened read-only only backs a read-only disk.
+        let res = VirtioBlock::new(config(ro_file.as_raw_fd(), false));
+        assert!(
+            matches!(res, Err(VirtioBlockError::BackingFile(_, _))),
+            "{:?}",
+            res
+        );
+        VirtioBlock::new(config(ro_file.as_raw_fd(), true)).unwrap();
+
+        // Patching the path drops the descriptor.
+        let mut block = VirtioBlock::new(config(rw_file.as_raw_fd(), false)).unwrap();
+        block.update_disk_image(path).unwrap();
+        assert_eq!(block.disk.fd, None);
+    }
 }
@@ -0,0 +1,72 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Discard and write zeroes limits of a block device, as reported to the guest.
+
+use super::SECTOR_SHIFT;
+use crate::vstate::memory::ByteValued;
+
+/// Offset of the discard and write zeroes limits within the virtio block config space.
+pub const DISCARD_CONFIG_OFFSET: usize = 36;
+/// Size of the virtio block config space up to and including the write zeroes limits.
+pub const DISCARD_CONFIG_SPACE_SIZE: usize = 60;
+
+/// Maximum number of sectors of a discard or write zeroes segment.
+///
+/// This keeps the length in bytes of a segment within a `u32`.
+pub const MAX_DISCARD_SECTORS: u32 = u32::MAX >> SECTOR_SHIFT;
+/// Maximum number of segments of a discard or write zeroes request.
+pub const MAX_DISCARD_SEGMENTS: u32 = 1;
+
+/// A range of sectors to discard or to fill with zeroes.
+///
+/// This is the data of discard and write zeroes requests, laid out as in the virtio spec.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DiscardSegment {
+    /// First sector of the range.
+    pub sector: u64,
+    /// Number of sectors of the range.
+    pub num_sectors: u32,
+    /// Flags of the range. The unmap hint of write zeroes requests is ignored.
+    pub flags: u32,
+}
+
+// SAFETY: Safe because DiscardSegment only contains plain data.
+unsafe impl ByteValued for DiscardSegment {}
+
+/// Writes the discard and write zeroes limits into the given virtio block config space.
+pub fn write_config(config: &mut Vec<u8>) {
+    config.resize(DISCARD_CONFIG_SPACE_SIZE, 0);
+    let limits = &mut config[DISCARD_CONFIG_OFFSET..];
+    // The config space is little endian.
+    limits[..4].copy_from_slice(&MAX_DISCARD_SECTORS.to_le_bytes());
+    limits[4..8].copy_from_slice(&MAX_DISCARD_SEGMENTS.to_le_bytes());
+    // Any sector can be discarded.
+    limits[8..12].copy_from_slice(&1u32.to_le_bytes());
+    limits[12..16].copy_from_slice(&MAX_DISCARD_SECTORS.to_le_bytes());
+    limits[16..20].copy_from_slice(&MAX_DISCARD_SEGMENTS.to_le_bytes());
+    // `write_zeroes_may_unmap` is left cleared, zeroed ranges always stay allocated.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_config() {
+        let mut config = vec![0xff; 8];
+        write_config(&mut config);
+        assert_eq!(config.len(), DISCARD_CONFIG_SPACE_SIZE);
+        // The capacity is left untouched, the fields in between are zeroed.
+        assert_eq!(config[..8], [0xff; 8]);
+        assert_eq!(config[8..DISCARD_CONFIG_OFFSET], [0; 28]);
+        assert_eq!(
+            config[DISCARD_CONFIG_OFFSET..],
+            [
+                0xff, 0xff, 0x7f, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xff, 0xff,
+                0x7f, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ]
+        );
+    }
+}
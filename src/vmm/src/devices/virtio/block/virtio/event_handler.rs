@@ -13,6 +13,10 @@ impl VirtioBlock {
     const PROCESS_QUEUE: u32 = 1;
     const PROCESS_RATE_LIMITER: u32 = 2;
     const PROCESS_ASYNC_COMPLETION: u32 = 3;
+    const PROCESS_MODERATION_TIMER: u32 = 4;
+    const PROCESS_IO_RETRY_TIMER: u32 = 5;
+    const PROCESS_NOTIFICATION_WINDOW: u32 = 6;
+    const PROCESS_WRITE_BATCH_TIMER: u32 = 7;
 
     fn register_runtime_events(&self, ops: &mut EventOps) {
         if let Err(err) = ops.add(Events::with_data(
@@ -38,6 +42,48 @@ impl VirtioBlock {
                 error!("Failed to register IO engine completion event: {}", err);
             }
         }
+        if let Some(ref moderator) = self.interrupt_moderator {
+            if let Err(err) = ops.add(Events::with_data(
+                moderator,
+                Self::PROCESS_MODERATION_TIMER,
+                EventSet::IN,
+            )) {
+                error!(
+                    "Failed to register interrupt moderation timer event: {}",
+                    err
+                );
+            }
+        }
+        if let Some(ref retrier) = self.io_retrier {
+            if let Err(err) = ops.add(Events::with_data(
+                retrier,
+                Self::PROCESS_IO_RETRY_TIMER,
+                EventSet::IN,
+            )) {
+                error!("Failed to register I/O retry timer event: {}", err);
+            }
+        }
+        if let Some(ref suppressor) = self.notification_suppressor {
+            if let Err(err) = ops.add(Events::with_data(
+                suppressor,
+                Self::PROCESS_NOTIFICATION_WINDOW,
+                EventSet::IN,
+            )) {
+                error!(
+                    "Failed to register notification suppression timer event: {}",
+                    err
+                );
+            }
+        }
+        if let Some(ref batcher) = self.write_batcher {
+            if let Err(err) = ops.add(Events::with_data(
+                batcher,
+                Self::PROCESS_WRITE_BATCH_TIMER,
+                EventSet::IN,
+            )) {
+                error!("Failed to register write batching timer event: {}", err);
+            }
+        }
     }
 
     fn register_activate_event(&self, ops: &mut EventOps) {
@@ -76,6 +122,7 @@ impl MutEventSubscriber for VirtioBlock {
         let supported_events = EventSet::IN;
         if !supported_events.contains(event_set) {
             warn!(
+                target: &self.id,
                 "Block: Received unknown event: {:?} from source: {:?}",
                 event_set, source
             );
@@ -88,10 +135,15 @@ impl MutEventSubscriber for VirtioBlock {
                 Self::PROCESS_QUEUE => self.process_queue_event(),
                 Self::PROCESS_RATE_LIMITER => self.process_rate_limiter_event(),
                 Self::PROCESS_ASYNC_COMPLETION => self.process_async_completion_event(),
-                _ => warn!("Block: Spurious event received: {:?}", source),
+                Self::PROCESS_MODERATION_TIMER => self.process_moderation_timer_event(),
+                Self::PROCESS_IO_RETRY_TIMER => self.process_io_retry_timer_event(),
+                Self::PROCESS_NOTIFICATION_WINDOW => self.process_notification_window_event(),
+                Self::PROCESS_WRITE_BATCH_TIMER => self.process_write_batch_timer_event(),
+                _ => warn!(target: &self.id, "Block: Spurious event received: {:?}", source),
             }
         } else {
             warn!(
+                target: &self.id,
                 "Block: The device is not yet activated. Spurious event received: {:?}",
                 source
             );
@@ -105,6 +157,8 @@ impl MutEventSubscriber for VirtioBlock {
         //  - on device restore from snapshot.
         if self.is_activated() {
             self.register_runtime_events(ops);
+            // Requests that were in flight when the device was snapshotted are executed again.
+            self.process_restored_requests();
         } else {
             self.register_activate_event(ops);
         }
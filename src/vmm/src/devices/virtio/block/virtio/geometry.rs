@@ -0,0 +1,165 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! C/H/S geometry of a block device, as reported to the guest.
+
+use serde::{Deserialize, Serialize};
+
+/// Offset of the geometry within the virtio block config space.
+pub const GEOMETRY_CONFIG_OFFSET: usize = 16;
+/// Size of the virtio block config space up to and including the geometry.
+pub const GEOMETRY_CONFIG_SPACE_SIZE: usize = 20;
+
+// Conventional number of heads and sectors per track of a computed geometry.
+const DEFAULT_HEADS: u8 = 16;
+const DEFAULT_SECTORS: u8 = 63;
+
+/// Geometry configuration of a block device.
+///
+/// Fields left out are computed from the capacity of the disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskGeometryConfig {
+    /// Number of cylinders.
+    pub cylinders: Option<u16>,
+    /// Number of heads.
+    pub heads: Option<u8>,
+    /// Number of sectors per track.
+    pub sectors: Option<u8>,
+}
+
+/// Errors associated with the geometry of a block device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum DiskGeometryError {
+    /// The disk geometry requires a non-zero number of cylinders.
+    InvalidCylinders,
+    /// The disk geometry requires a non-zero number of heads.
+    InvalidHeads,
+    /// The disk geometry requires a non-zero number of sectors per track.
+    InvalidSectors,
+}
+
+/// C/H/S geometry reported to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskGeometry {
+    /// Number of cylinders.
+    pub cylinders: u16,
+    /// Number of heads.
+    pub heads: u8,
+    /// Number of sectors per track.
+    pub sectors: u8,
+}
+
+impl DiskGeometryConfig {
+    /// Computes the geometry of a disk with `nsectors` sectors.
+    pub fn resolve(&self, nsectors: u64) -> Result<DiskGeometry, DiskGeometryError> {
+        let heads = self.heads.unwrap_or(DEFAULT_HEADS);
+        if heads == 0 {
+            return Err(DiskGeometryError::InvalidHeads);
+        }
+        let sectors = self.sectors.unwrap_or(DEFAULT_SECTORS);
+        if sectors == 0 {
+            return Err(DiskGeometryError::InvalidSectors);
+        }
+        let cylinders = match self.cylinders {
+            Some(0) => return Err(DiskGeometryError::InvalidCylinders),
+            Some(cylinders) => cylinders,
+            None => {
+                let cylinders = nsectors / (u64::from(heads) * u64::from(sectors));
+                u16::try_from(cylinders).unwrap_or(u16::MAX).max(1)
+            }
+        };
+
+        Ok(DiskGeometry {
+            cylinders,
+            heads,
+            sectors,
+        })
+    }
+}
+
+impl DiskGeometry {
+    /// Writes the geometry into the given virtio block config space.
+    pub fn write_config(&self, config: &mut Vec<u8>) {
+        config.resize(GEOMETRY_CONFIG_SPACE_SIZE, 0);
+        let geometry = &mut config[GEOMETRY_CONFIG_OFFSET..];
+        // The config space is little endian.
+        geometry[..2].copy_from_slice(&self.cylinders.to_le_bytes());
+        geometry[2] = self.heads;
+        geometry[3] = self.sectors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        // All fields are computed from the capacity.
+        let geometry = DiskGeometryConfig::default().resolve(16 * 63 * 10).unwrap();
+        assert_eq!(
+            geometry,
+            DiskGeometry {
+                cylinders: 10,
+                heads: 16,
+                sectors: 63
+            }
+        );
+
+        // Small disks still have one cylinder, huge ones are capped.
+        let geometry = DiskGeometryConfig::default().resolve(8).unwrap();
+        assert_eq!(geometry.cylinders, 1);
+        let geometry = DiskGeometryConfig::default().resolve(u64::MAX).unwrap();
+        assert_eq!(geometry.cylinders, u16::MAX);
+
+        // The cylinders are computed from the configured heads and sectors.
+        let config = DiskGeometryConfig {
+            cylinders: None,
+            heads: Some(4),
+            sectors: Some(32),
+        };
+        assert_eq!(config.resolve(4 * 32 * 7).unwrap().cylinders, 7);
+
+        let config = DiskGeometryConfig {
+            cylinders: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.resolve(8),
+            Err(DiskGeometryError::InvalidCylinders)
+        ));
+        let config = DiskGeometryConfig {
+            heads: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.resolve(8),
+            Err(DiskGeometryError::InvalidHeads)
+        ));
+        let config = DiskGeometryConfig {
+            sectors: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.resolve(8),
+            Err(DiskGeometryError::InvalidSectors)
+        ));
+    }
+
+    #[test]
+    fn test_write_config() {
+        let geometry = DiskGeometry {
+            cylinders: 0x1234,
+            heads: 16,
+            sectors: 63,
+        };
+        let mut config = vec![0xff; 8];
+        geometry.write_config(&mut config);
+        assert_eq!(config.len(), GEOMETRY_CONFIG_SPACE_SIZE);
+        // The capacity is left untouched, size_max and seg_max are zeroed.
+        assert_eq!(config[..8], [0xff; 8]);
+        assert_eq!(config[8..16], [0; 8]);
+        assert_eq!(config[16..], [0x34, 0x12, 16, 63]);
+    }
+}
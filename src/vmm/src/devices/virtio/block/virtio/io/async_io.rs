@@ -27,6 +27,8 @@ pub enum AsyncIoError {
     Submit(std::io::Error),
     /// SyncAll: {0}
     SyncAll(std::io::Error),
+    /// Fallocate: {0}
+    Fallocate(std::io::Error),
     /// EventFd: {0}
     EventFd(std::io::Error),
     /// GuestMemory: {0}
@@ -201,6 +203,16 @@ impl<T: Debug> AsyncFileEngine<T> {
             })
     }
 
+    /// Executes a `fallocate` call right away, it is not submitted to the ring.
+    pub fn fallocate(
+        &mut self,
+        mode: libc::c_int,
+        offset: u64,
+        count: u32,
+    ) -> Result<(), AsyncIoError> {
+        super::fallocate(&self.file, mode, offset, count).map_err(AsyncIoError::Fallocate)
+    }
+
     pub fn kick_submission_queue(&mut self) -> Result<(), AsyncIoError> {
         self.ring
             .submit()
@@ -6,6 +6,8 @@ pub mod sync_io;
 
 use std::fmt::Debug;
 use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
 
 pub use self::async_io::{AsyncFileEngine, AsyncIoError};
 pub use self::sync_io::{SyncFileEngine, SyncIoError};
@@ -87,7 +89,6 @@ impl<T: Debug> FileEngine<T> {
         Ok(())
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         match self {
             FileEngine::Async(engine) => engine.file(),
@@ -151,6 +152,21 @@ impl<T: Debug> FileEngine<T> {
         }
     }
 
+    /// Writes `bufs` back to back at `offset` in one go. Only supported by the sync engine.
+    pub fn write_vectored(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        bufs: &[(GuestAddress, u32)],
+    ) -> Result<u64, BlockIoError> {
+        match self {
+            FileEngine::Async(_) => Err(BlockIoError::UnsupportedEngine(FileEngineType::Async)),
+            FileEngine::Sync(engine) => engine
+                .write_vectored(offset, mem, bufs)
+                .map_err(BlockIoError::Sync),
+        }
+    }
+
     pub fn flush(
         &mut self,
         user_data: T,
@@ -176,6 +192,58 @@ impl<T: Debug> FileEngine<T> {
         }
     }
 
+    pub fn discard(
+        &mut self,
+        offset: u64,
+        count: u32,
+        user_data: T,
+    ) -> Result<FileEngineOk<T>, UserDataError<T, BlockIoError>> {
+        self.fallocate(
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            count,
+            user_data,
+        )
+    }
+
+    pub fn write_zeroes(
+        &mut self,
+        offset: u64,
+        count: u32,
+        user_data: T,
+    ) -> Result<FileEngineOk<T>, UserDataError<T, BlockIoError>> {
+        self.fallocate(
+            libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            count,
+            user_data,
+        )
+    }
+
+    // io_uring is not used for `fallocate`, both engines execute it right away.
+    fn fallocate(
+        &mut self,
+        mode: libc::c_int,
+        offset: u64,
+        count: u32,
+        user_data: T,
+    ) -> Result<FileEngineOk<T>, UserDataError<T, BlockIoError>> {
+        let res = match self {
+            FileEngine::Async(engine) => engine
+                .fallocate(mode, offset, count)
+                .map_err(BlockIoError::Async),
+            FileEngine::Sync(engine) => engine
+                .fallocate(mode, offset, count)
+                .map(|_| ())
+                .map_err(BlockIoError::Sync),
+        };
+
+        match res {
+            Ok(()) => Ok(FileEngineOk::Executed(UserDataOk { user_data, count })),
+            Err(error) => Err(UserDataError { user_data, error }),
+        }
+    }
+
     pub fn drain(&mut self, discard: bool) -> Result<(), BlockIoError> {
         match self {
             FileEngine::Async(engine) => engine.drain(discard).map_err(BlockIoError::Async),
@@ -193,6 +261,40 @@ impl<T: Debug> FileEngine<T> {
     }
 }
 
+/// Calls `fallocate` on a range of `count` bytes of `file`, starting at `offset`.
+///
+/// Filesystems that do not support `FALLOC_FL_ZERO_RANGE` (e.g. tmpfs) get the zeroes
+/// written instead.
+fn fallocate(file: &File, mode: libc::c_int, offset: u64, count: u32) -> std::io::Result<()> {
+    let off = libc::off_t::try_from(offset)
+        .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+    // SAFETY: Safe because the file descriptor is valid and no memory is passed to the kernel.
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), mode, off, libc::off_t::from(count)) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if mode & libc::FALLOC_FL_ZERO_RANGE != 0 && err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            return write_zeroes(file, offset, count);
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Writes `count` zeroes to `file`, starting at `offset`.
+fn write_zeroes(mut file: &File, offset: u64, count: u32) -> std::io::Result<()> {
+    const CHUNK_LEN: u32 = 64 << 10;
+
+    let zeroes = vec![0u8; CHUNK_LEN.min(count) as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    let mut left = count;
+    while left > 0 {
+        let len = CHUNK_LEN.min(left);
+        file.write_all(&zeroes[..len as usize])?;
+        left -= len;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 pub mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
@@ -300,6 +402,8 @@ pub mod tests {
         assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Seek(_e)));
         let res = engine.flush(());
         assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::SyncAll(_e)));
+        let res = engine.discard(0, 0, ());
+        assert_err!(res, BlockIoError::Sync(sync_io::SyncIoError::Fallocate(_e)));
 
         // Create backing file.
         let file = TempFile::new().unwrap().into_file();
@@ -359,6 +463,59 @@ pub mod tests {
         mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
         assert_eq!(buf, data.as_slice());
 
+        // Discard
+        assert_sync_execution!(engine.discard(offset, partial_len, ()), partial_len);
+        let mem = create_mem();
+        assert_sync_execution!(
+            engine.read(0, &mem, GuestAddress(0), FILE_LEN, ()),
+            FILE_LEN
+        );
+        // Check data
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        let offset = u64_to_usize(offset);
+        let end = offset + partial_len as usize;
+        assert_eq!(buf[..offset], data[..offset]);
+        assert!(buf[offset..end].iter().all(|&b| b == 0));
+        assert_eq!(buf[end..], data[end..]);
+
+        // Write zeroes without `FALLOC_FL_ZERO_RANGE` support.
+        mem.write(&data, GuestAddress(0)).unwrap();
+        assert_sync_execution!(
+            engine.write(0, &mem, GuestAddress(0), FILE_LEN, ()),
+            FILE_LEN
+        );
+        write_zeroes(engine.file(), offset as u64, partial_len).unwrap();
+        let mem = create_mem();
+        assert_sync_execution!(
+            engine.read(0, &mem, GuestAddress(0), FILE_LEN, ()),
+            FILE_LEN
+        );
+        mem.read_slice(&mut buf, GuestAddress(0)).unwrap();
+        assert_eq!(buf[..offset], data[..offset]);
+        assert!(buf[offset..end].iter().all(|&b| b == 0));
+        assert_eq!(buf[end..], data[end..]);
+
+        // Vectored write of two buffers, out of order in guest memory.
+        mem.write(&data, GuestAddress(0)).unwrap();
+        let bufs = [(GuestAddress(100), 50), (GuestAddress(0), 100)];
+        assert_eq!(engine.write_vectored(0, &mem, &bufs).unwrap(), 150);
+        let mem = create_mem();
+        assert_sync_execution!(engine.read(0, &mem, GuestAddress(0), 150, ()), 150);
+        mem.read_slice(&mut buf[..150], GuestAddress(0)).unwrap();
+        assert_eq!(buf[..50], data[100..150]);
+        assert_eq!(buf[50..150], data[..100]);
+        // The write lands at its offset, wherever the previous operation left the file.
+        assert_eq!(
+            engine
+                .write_vectored(150, &mem, &[(GuestAddress(0), 50)])
+                .unwrap(),
+            50
+        );
+        let mem = create_mem();
+        assert_sync_execution!(engine.read(150, &mem, GuestAddress(0), 50, ()), 50);
+        mem.read_slice(&mut buf[..50], GuestAddress(0)).unwrap();
+        assert_eq!(buf[..50], data[100..150]);
+
         // Check other ops
         engine.flush(()).unwrap();
         engine.drain(true).unwrap();
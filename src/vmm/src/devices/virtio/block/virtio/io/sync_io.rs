@@ -3,6 +3,7 @@
 
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
 
 use vm_memory::{GuestMemoryError, ReadVolatile, WriteVolatile};
 
@@ -16,8 +17,12 @@ pub enum SyncIoError {
     Seek(std::io::Error),
     /// SyncAll: {0}
     SyncAll(std::io::Error),
+    /// Fallocate: {0}
+    Fallocate(std::io::Error),
     /// Transfer: {0}
     Transfer(GuestMemoryError),
+    /// Vectored write: {0}
+    WriteVectored(std::io::Error),
 }
 
 #[derive(Debug)]
@@ -33,7 +38,6 @@ impl SyncFileEngine {
         SyncFileEngine { file }
     }
 
-    #[cfg(test)]
     pub fn file(&self) -> &File {
         &self.file
     }
@@ -75,10 +79,52 @@ impl SyncFileEngine {
         Ok(count)
     }
 
+    /// Writes the guest memory buffers `bufs`, back to back, at `offset` with a single
+    /// `writev`. Returns the number of bytes written, which may be short.
+    pub fn write_vectored(
+        &mut self,
+        offset: u64,
+        mem: &GuestMemoryMmap,
+        bufs: &[(GuestAddress, u32)],
+    ) -> Result<u64, SyncIoError> {
+        let iovecs = bufs
+            .iter()
+            .map(|&(addr, count)| {
+                mem.get_slice(addr, count as usize)
+                    .map(|slice| libc::iovec {
+                        iov_base: slice.ptr_guard().as_ptr().cast(),
+                        iov_len: slice.len(),
+                    })
+                    .map_err(SyncIoError::Transfer)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let iovcnt = libc::c_int::try_from(iovecs.len()).map_err(|_| {
+            SyncIoError::WriteVectored(std::io::Error::from_raw_os_error(libc::EINVAL))
+        })?;
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(SyncIoError::Seek)?;
+
+        // SAFETY: Safe because the file descriptor is valid and the iovecs point to guest memory
+        // slices that were bounds checked and outlive the call.
+        let ret = unsafe { libc::writev(self.file.as_raw_fd(), iovecs.as_ptr(), iovcnt) };
+        u64::try_from(ret).map_err(|_| SyncIoError::WriteVectored(std::io::Error::last_os_error()))
+    }
+
     pub fn flush(&mut self) -> Result<(), SyncIoError> {
         // flush() first to force any cached data out of rust buffers.
         self.file.flush().map_err(SyncIoError::Flush)?;
         // Sync data out to physical media on host.
         self.file.sync_all().map_err(SyncIoError::SyncAll)
     }
+
+    pub fn fallocate(
+        &mut self,
+        mode: libc::c_int,
+        offset: u64,
+        count: u32,
+    ) -> Result<u32, SyncIoError> {
+        super::fallocate(&self.file, mode, offset, count).map_err(SyncIoError::Fallocate)?;
+        Ok(count)
+    }
 }
@@ -109,6 +109,15 @@ impl BlockMetricsPerDevice {
                 .or_insert_with(|| Arc::new(BlockDeviceMetrics::default())),
         )
     }
+
+    /// Moves the metrics of the block device having id `old_id` under `new_id`, so that
+    /// they keep being reported after the device is renamed.
+    pub fn rename(old_id: &str, new_id: String) {
+        let mut per_device = METRICS.write().unwrap();
+        if let Some(metrics) = per_device.metrics.remove(old_id) {
+            per_device.metrics.insert(new_id, metrics);
+        }
+    }
 }
 
 /// Pool of block-related metrics per device behind a lock to
@@ -183,6 +192,24 @@ pub struct BlockDeviceMetrics {
     pub io_engine_throttled_events: SharedIncMetric,
     /// Number of remaining requests in the queue.
     pub remaining_reqs_count: SharedIncMetric,
+    /// Number of requests retried after a backing store error.
+    pub io_retry_count: SharedIncMetric,
+    /// Number of successful discard operations.
+    pub discard_count: SharedIncMetric,
+    /// Number of sectors discarded by this block device.
+    pub discard_sectors: SharedIncMetric,
+    /// Number of successful write zeroes operations.
+    pub write_zeroes_count: SharedIncMetric,
+    /// Number of sectors zeroed by this block device.
+    pub write_zeroes_sectors: SharedIncMetric,
+    /// Number of flush requests acknowledged without syncing because of the `Unsafe` cache type.
+    pub flush_ignored_count: SharedIncMetric,
+    /// Number of queue events held back by the notification suppression window after a restore.
+    pub suppressed_queue_events: SharedIncMetric,
+    /// Number of batches of sequential writes written to the backing file.
+    pub write_batch_count: SharedIncMetric,
+    /// Number of requests failed for having more descriptors than the maximum chain length.
+    pub chains_too_long_count: SharedIncMetric,
 }
 
 impl BlockDeviceMetrics {
@@ -229,6 +256,21 @@ impl BlockDeviceMetrics {
             .add(other.io_engine_throttled_events.fetch_diff());
         self.remaining_reqs_count
             .add(other.remaining_reqs_count.fetch_diff());
+        self.io_retry_count.add(other.io_retry_count.fetch_diff());
+        self.discard_count.add(other.discard_count.fetch_diff());
+        self.discard_sectors.add(other.discard_sectors.fetch_diff());
+        self.write_zeroes_count
+            .add(other.write_zeroes_count.fetch_diff());
+        self.write_zeroes_sectors
+            .add(other.write_zeroes_sectors.fetch_diff());
+        self.flush_ignored_count
+            .add(other.flush_ignored_count.fetch_diff());
+        self.suppressed_queue_events
+            .add(other.suppressed_queue_events.fetch_diff());
+        self.write_batch_count
+            .add(other.write_batch_count.fetch_diff());
+        self.chains_too_long_count
+            .add(other.chains_too_long_count.fetch_diff());
     }
 }
 
@@ -3,12 +3,19 @@
 
 //! Implements a virtio block device.
 
+pub mod batch;
 pub mod device;
+pub mod discard;
 mod event_handler;
+pub mod geometry;
 mod io;
 pub mod metrics;
+pub mod moderation;
 pub mod persist;
+pub mod record;
 pub mod request;
+pub mod retry;
+pub mod suppression;
 pub mod test_utils;
 
 use vm_memory::GuestMemoryError;
@@ -57,6 +64,14 @@ pub enum VirtioBlockError {
     FileEngine(io::BlockIoError),
     /// Error manipulating the backing file: {0} {1}
     BackingFile(std::io::Error, String),
+    /// Backing file {0} does not match the snapshot: header hash {1:#x}, expected {2:#x}
+    BackingFileMismatch(String, u64, u64),
+    /// The disk size must be a multiple of the sector size: {0}
+    InvalidDiskSize(u64),
+    /// The disk cannot shrink from {0} to {1} bytes
+    DiskShrink(u64, u64),
+    /// The backing file is smaller than the disk size of {0} bytes
+    BackingFileTooSmall(u64),
     /// Error opening eventfd: {0}
     EventFd(std::io::Error),
     /// Error creating an irqfd: {0}
@@ -65,4 +80,18 @@ pub enum VirtioBlockError {
     RateLimiter(std::io::Error),
     /// Persistence error: {0}
     Persist(crate::devices::virtio::persist::PersistError),
+    /// Interrupt moderation error: {0}
+    InterruptModeration(moderation::InterruptModerationError),
+    /// I/O retry error: {0}
+    IoRetry(retry::IoRetryError),
+    /// Notification suppression error: {0}
+    NotificationSuppression(suppression::NotificationSuppressionError),
+    /// Disk geometry error: {0}
+    Geometry(geometry::DiskGeometryError),
+    /// The maximum descriptor chain length must be between 1 and the queue size: {0}
+    InvalidMaxChainLen(u16),
+    /// Request recording error: {0}
+    RequestRecord(record::RequestRecordError),
+    /// Write batching error: {0}
+    WriteBatch(batch::WriteBatchError),
 }
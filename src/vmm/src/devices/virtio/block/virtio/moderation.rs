@@ -0,0 +1,179 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interrupt moderation for block request completions.
+
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+/// Interrupt moderation parameters of a block device.
+///
+/// The guest is notified after `max_completions` completed requests or `max_delay_us`
+/// microseconds after the first unsignalled completion, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterruptModerationConfig {
+    /// Number of completions after which the guest is notified.
+    pub max_completions: u32,
+    /// Maximum delay, in microseconds, of a completion notification.
+    pub max_delay_us: u64,
+}
+
+/// Errors associated with the interrupt moderation of a block device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum InterruptModerationError {
+    /// Interrupt moderation requires a non-zero number of completions.
+    InvalidMaxCompletions,
+    /// Interrupt moderation requires a non-zero delay.
+    InvalidMaxDelay,
+    /// Cannot create the interrupt moderation timer: {0}
+    Timer(std::io::Error),
+}
+
+/// Batches completion notifications according to an [`InterruptModerationConfig`].
+pub struct InterruptModerator {
+    config: InterruptModerationConfig,
+    timer: TimerFd,
+    timer_armed: bool,
+    pending: u32,
+}
+
+// TODO Use `#[derive(Debug)]` when a new release of
+// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
+// https://github.com/main--/rust-timerfd/pull/12.
+impl fmt::Debug for InterruptModerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterruptModerator")
+            .field("config", &self.config)
+            .field("timer_armed", &self.timer_armed)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl InterruptModerator {
+    /// Creates a new moderator from the given config.
+    pub fn new(config: InterruptModerationConfig) -> Result<Self, InterruptModerationError> {
+        if config.max_completions == 0 {
+            return Err(InterruptModerationError::InvalidMaxCompletions);
+        }
+        if config.max_delay_us == 0 {
+            return Err(InterruptModerationError::InvalidMaxDelay);
+        }
+
+        Ok(Self {
+            config,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(InterruptModerationError::Timer)?,
+            timer_armed: false,
+            pending: 0,
+        })
+    }
+
+    /// Returns the config of this moderator.
+    pub fn config(&self) -> InterruptModerationConfig {
+        self.config
+    }
+
+    /// Number of completions that have not been signalled to the guest yet.
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    /// Records a completion. Returns `true` if the guest should be notified now.
+    pub fn on_completion(&mut self) -> bool {
+        self.pending += 1;
+        if self.pending >= self.config.max_completions {
+            self.reset();
+            return true;
+        }
+
+        if !self.timer_armed {
+            self.timer.set_state(
+                TimerState::Oneshot(Duration::from_micros(self.config.max_delay_us)),
+                SetTimeFlags::Default,
+            );
+            self.timer_armed = true;
+        }
+        false
+    }
+
+    /// Handles a timer expiration. Returns `true` if there are completions to signal.
+    pub fn on_timer(&mut self) -> bool {
+        self.timer.read();
+        self.flush()
+    }
+
+    /// Clears the pending completions. Returns `true` if there were any to signal.
+    pub fn flush(&mut self) -> bool {
+        let has_pending = self.pending > 0;
+        self.reset();
+        has_pending
+    }
+
+    fn reset(&mut self) {
+        self.pending = 0;
+        if self.timer_armed {
+            self.timer
+                .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+            self.timer_armed = false;
+        }
+    }
+}
+
+impl AsRawFd for InterruptModerator {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_config() {
+        let err = InterruptModerator::new(InterruptModerationConfig {
+            max_completions: 0,
+            max_delay_us: 100,
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            InterruptModerationError::InvalidMaxCompletions
+        ));
+
+        let err = InterruptModerator::new(InterruptModerationConfig {
+            max_completions: 4,
+            max_delay_us: 0,
+        })
+        .unwrap_err();
+        assert!(matches!(err, InterruptModerationError::InvalidMaxDelay));
+    }
+
+    #[test]
+    fn test_moderation() {
+        let mut moderator = InterruptModerator::new(InterruptModerationConfig {
+            max_completions: 3,
+            max_delay_us: 1_000_000,
+        })
+        .unwrap();
+
+        assert!(!moderator.on_completion());
+        assert!(!moderator.on_completion());
+        assert_eq!(moderator.pending(), 2);
+        assert!(moderator.on_completion());
+        assert_eq!(moderator.pending(), 0);
+
+        // The timer flushes a partial batch.
+        assert!(!moderator.on_completion());
+        assert!(moderator.on_timer());
+        assert_eq!(moderator.pending(), 0);
+        // Nothing left to signal.
+        assert!(!moderator.flush());
+    }
+}
@@ -3,19 +3,26 @@
 
 //! Defines the structures needed for saving/restoring block devices.
 
+use std::os::unix::io::RawFd;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use utils::eventfd::EventFd;
 
+use super::batch::{WriteBatchConfig, WriteBatcher};
 use super::device::DiskProperties;
+use super::geometry::DiskGeometryConfig;
+use super::moderation::{InterruptModerationConfig, InterruptModerator};
+use super::request::InFlightRequest;
+use super::retry::{IoRetrier, IoRetryConfig};
+use super::suppression::NotificationSuppressor;
 use super::*;
 use crate::devices::virtio::block::persist::BlockConstructorArgs;
 use crate::devices::virtio::block::virtio::device::FileEngineType;
 use crate::devices::virtio::block::virtio::metrics::BlockMetricsPerDevice;
-use crate::devices::virtio::device::{DeviceState, IrqTrigger};
-use crate::devices::virtio::gen::virtio_blk::VIRTIO_BLK_F_RO;
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, LastError};
+use crate::devices::virtio::gen::virtio_blk::{VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_RO};
 use crate::devices::virtio::persist::VirtioDeviceState;
 use crate::devices::virtio::TYPE_BLOCK;
 use crate::logger::warn;
@@ -61,9 +68,19 @@ pub struct VirtioBlockState {
     cache_type: CacheType,
     root_device: bool,
     disk_path: String,
+    disk_header_hash: Option<u64>,
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
     file_engine_type: FileEngineTypeState,
+    interrupt_moderation: Option<InterruptModerationConfig>,
+    io_retry: Option<IoRetryConfig>,
+    enabled: bool,
+    geometry: Option<DiskGeometryConfig>,
+    max_chain_len: u16,
+    in_flight_requests: Vec<InFlightRequest>,
+    restore_notification_window_ms: Option<u64>,
+    write_batch: Option<WriteBatchConfig>,
+    disk_fd: Option<RawFd>,
 }
 
 impl Persist<'_> for VirtioBlock {
@@ -79,9 +96,35 @@ impl Persist<'_> for VirtioBlock {
             cache_type: self.cache_type,
             root_device: self.root_device,
             disk_path: self.disk.file_path.clone(),
+            disk_header_hash: self
+                .disk
+                .header_hash()
+                .map_err(|err| {
+                    warn!(
+                        "Could not hash the backing file of block device {}, it will not be \
+                         checked on restore: {err}",
+                        self.id
+                    )
+                })
+                .ok(),
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter.save(),
             file_engine_type: FileEngineTypeState::from(self.file_engine_type()),
+            interrupt_moderation: self.interrupt_moderation(),
+            io_retry: self.io_retry(),
+            enabled: self.enabled,
+            geometry: self.geometry,
+            max_chain_len: self.max_chain_len,
+            // Requests restored but not yet executed again are still in flight.
+            in_flight_requests: self
+                .in_flight_requests
+                .iter()
+                .chain(&self.restored_requests)
+                .copied()
+                .collect(),
+            restore_notification_window_ms: self.restore_notification_window_ms(),
+            write_batch: self.write_batch(),
+            disk_fd: self.disk.fd,
         }
     }
 
@@ -90,28 +133,73 @@ impl Persist<'_> for VirtioBlock {
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
         let is_read_only = state.virtio_state.avail_features & (1u64 << VIRTIO_BLK_F_RO) != 0;
+        let discard = state.virtio_state.avail_features & (1u64 << VIRTIO_BLK_F_DISCARD) != 0;
         let rate_limiter = RateLimiter::restore((), &state.rate_limiter_state)
             .map_err(VirtioBlockError::RateLimiter)?;
 
-        let disk_properties = DiskProperties::new(
-            state.disk_path.clone(),
-            is_read_only,
-            state.file_engine_type.into(),
-        )
-        .or_else(|err| match err {
-            VirtioBlockError::FileEngine(io::BlockIoError::UnsupportedEngine(
-                FileEngineType::Async,
-            )) => {
-                // If the kernel does not support `Async`, fallback to `Sync`.
-                warn!(
-                    "The \"Async\" io_engine is supported for kernels starting with {}. \
-                     Defaulting to \"Sync\" mode.",
-                    utils::kernel_version::min_kernel_version_for_io_uring()
-                );
-                DiskProperties::new(state.disk_path.clone(), is_read_only, FileEngineType::Sync)
+        // A disk inherited through a file descriptor is reopened through the same descriptor
+        // number, which the restoring process must have inherited as well.
+        let open_disk = |file_engine_type| match state.disk_fd {
+            Some(fd) => DiskProperties::from_fd(fd, is_read_only, file_engine_type),
+            None => DiskProperties::new(state.disk_path.clone(), is_read_only, file_engine_type),
+        };
+        let disk_properties =
+            open_disk(state.file_engine_type.into()).or_else(|err| match err {
+                VirtioBlockError::FileEngine(io::BlockIoError::UnsupportedEngine(
+                    FileEngineType::Async,
+                )) => {
+                    // If the kernel does not support `Async`, fallback to `Sync`.
+                    warn!(
+                        "The \"Async\" io_engine is supported for kernels starting with {}. \
+                         Defaulting to \"Sync\" mode.",
+                        utils::kernel_version::min_kernel_version_for_io_uring()
+                    );
+                    open_disk(FileEngineType::Sync)
+                }
+                other => Err(other),
+            })?;
+
+        // Catch a snapshot restored against another image than the one it was taken with.
+        if let Some(expected) = state.disk_header_hash {
+            let actual = disk_properties.header_hash()?;
+            if actual != expected {
+                return Err(VirtioBlockError::BackingFileMismatch(
+                    disk_properties.file_path.clone(),
+                    actual,
+                    expected,
+                ));
             }
-            other => Err(other),
-        })?;
+        }
+
+        let interrupt_moderator = state
+            .interrupt_moderation
+            .map(InterruptModerator::new)
+            .transpose()
+            .map_err(VirtioBlockError::InterruptModeration)?;
+
+        let io_retrier = state
+            .io_retry
+            .map(IoRetrier::new)
+            .transpose()
+            .map_err(VirtioBlockError::IoRetry)?;
+
+        let mut notification_suppressor = state
+            .restore_notification_window_ms
+            .map(NotificationSuppressor::new)
+            .transpose()
+            .map_err(VirtioBlockError::NotificationSuppression)?;
+        if let Some(suppressor) = notification_suppressor.as_mut() {
+            // The burst of notifications of the resuming guest is coalesced.
+            if state.virtio_state.activated {
+                suppressor.start();
+            }
+        }
+
+        let write_batcher = state
+            .write_batch
+            .map(WriteBatcher::new)
+            .transpose()
+            .map_err(VirtioBlockError::WriteBatch)?;
 
         let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?];
 
@@ -128,6 +216,8 @@ impl Persist<'_> for VirtioBlock {
         let mut irq_trigger = IrqTrigger::new().map_err(VirtioBlockError::IrqTrigger)?;
         irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
 
+        let config_space =
+            VirtioBlock::build_config_space(&disk_properties, state.geometry.as_ref(), discard)?;
         let avail_features = state.virtio_state.avail_features;
         let acked_features = state.virtio_state.acked_features;
 
@@ -140,7 +230,7 @@ impl Persist<'_> for VirtioBlock {
         Ok(VirtioBlock {
             avail_features,
             acked_features,
-            config_space: disk_properties.virtio_block_config_space(),
+            config_space,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VirtioBlockError::EventFd)?,
 
             queues,
@@ -158,21 +248,41 @@ impl Persist<'_> for VirtioBlock {
             rate_limiter,
             is_io_engine_throttled: false,
             metrics: BlockMetricsPerDevice::alloc(state.id.clone()),
+            interrupt_moderator,
+            io_retrier,
+            enabled: state.enabled,
+            geometry: state.geometry,
+            discard,
+            max_chain_len: state.max_chain_len,
+            in_flight_requests: Vec::new(),
+            retry_requests: Vec::new(),
+            restored_requests: state.in_flight_requests.clone(),
+            last_error: LastError::default(),
+            request_recorder: None,
+            notification_suppressor,
+            write_batcher,
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
     use std::sync::atomic::Ordering;
 
     use utils::tempfile::TempFile;
 
     use super::*;
     use crate::devices::virtio::block::virtio::device::VirtioBlockConfig;
+    use crate::devices::virtio::block::virtio::test_utils::{
+        default_block_with_path, read_blk_req_descriptors, set_queue,
+    };
     use crate::devices::virtio::device::VirtioDevice;
-    use crate::devices::virtio::test_utils::default_mem;
+    use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::logger::IncMetric;
     use crate::snapshot::Snapshot;
+    use crate::vstate::memory::{Bytes, GuestAddress};
 
     #[test]
     fn test_cache_semantic_ser() {
@@ -189,6 +299,15 @@ mod tests {
             cache_type: CacheType::Writeback,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: false,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
         };
 
         let block = VirtioBlock::new(config).unwrap();
@@ -233,6 +352,15 @@ mod tests {
                 // Need to use Sync because it will otherwise return an error.
                 // We'll overwrite the state instead.
                 file_engine_type: FileEngineType::Sync,
+                interrupt_moderation: None,
+                io_retry: None,
+                geometry: None,
+                discard: false,
+                max_chain_len: None,
+                request_record_path: None,
+                restore_notification_window_ms: None,
+                write_batch: None,
+                fd_on_host: None,
             };
 
             let block = VirtioBlock::new(config).unwrap();
@@ -274,9 +402,29 @@ mod tests {
             cache_type: CacheType::Unsafe,
             rate_limiter: None,
             file_engine_type: FileEngineType::default(),
+            interrupt_moderation: Some(InterruptModerationConfig {
+                max_completions: 8,
+                max_delay_us: 500,
+            }),
+            io_retry: Some(IoRetryConfig {
+                max_retries: 3,
+                backoff_ms: 10,
+            }),
+            geometry: Some(DiskGeometryConfig {
+                cylinders: Some(2),
+                heads: None,
+                sectors: None,
+            }),
+            discard: true,
+            max_chain_len: Some(8),
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
         };
 
-        let block = VirtioBlock::new(config).unwrap();
+        let mut block = VirtioBlock::new(config).unwrap();
+        block.enabled = false;
         let guest_mem = default_mem();
 
         // Save the block device.
@@ -304,5 +452,181 @@ mod tests {
 
         // Test that block specific fields are the same.
         assert_eq!(restored_block.disk.file_path, block.disk.file_path);
+        assert_eq!(
+            restored_block.interrupt_moderation(),
+            block.interrupt_moderation()
+        );
+        assert!(restored_block.interrupt_moderation().is_some());
+        assert_eq!(restored_block.io_retry(), block.io_retry());
+        assert!(restored_block.io_retry().is_some());
+        assert!(!restored_block.enabled);
+        assert_eq!(restored_block.geometry, block.geometry);
+        assert_eq!(restored_block.config_space, block.config_space);
+        assert!(restored_block.discard);
+        assert_eq!(restored_block.max_chain_len, 8);
+    }
+
+    #[test]
+    fn test_persistence_fd_on_host() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(f.as_path())
+            .unwrap();
+        let fd = file.as_raw_fd();
+
+        let config = VirtioBlockConfig {
+            drive_id: "fd".to_string(),
+            path_on_host: DiskProperties::fd_path(fd),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            cache_type: CacheType::Unsafe,
+            rate_limiter: None,
+            file_engine_type: FileEngineType::Sync,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: false,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: Some(fd),
+        };
+        let block = VirtioBlock::new(config).unwrap();
+
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &block.save()).unwrap();
+
+        // The disk is reopened through the same descriptor number.
+        let restored_block = VirtioBlock::restore(
+            BlockConstructorArgs { mem: default_mem() },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.disk.fd, Some(fd));
+        assert_eq!(restored_block.disk.file_path, block.disk.file_path);
+        assert_eq!(restored_block.disk.nsectors, block.disk.nsectors);
+
+        // Restoring fails clearly if the descriptor was not inherited.
+        let mut state = block.save();
+        state.disk_fd = Some(-1);
+        let res = VirtioBlock::restore(BlockConstructorArgs { mem: default_mem() }, &state);
+        assert!(
+            matches!(res, Err(VirtioBlockError::BackingFile(_, _))),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn test_backing_file_mismatch() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x2000).unwrap();
+        f.as_file().write_all_at(b"image header", 0).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let block = default_block_with_path(path.clone(), FileEngineType::Sync);
+        let state = block.save();
+        assert_eq!(
+            state.disk_header_hash,
+            Some(block.disk.header_hash().unwrap())
+        );
+
+        // Writes past the header don't change the identity of the image.
+        f.as_file().write_all_at(b"data", 0x1000).unwrap();
+        VirtioBlock::restore(BlockConstructorArgs { mem: default_mem() }, &state).unwrap();
+
+        // Restoring against an image with another header fails.
+        f.as_file().write_all_at(b"other header", 0).unwrap();
+        let res = VirtioBlock::restore(BlockConstructorArgs { mem: default_mem() }, &state);
+        assert!(
+            matches!(
+                res,
+                Err(VirtioBlockError::BackingFileMismatch(ref file_path, _, expected))
+                    if *file_path == path && Some(expected) == state.disk_header_hash
+            ),
+            "{:?}",
+            res
+        );
+
+        // Snapshots without the hash are restored unchecked.
+        let mut state = state;
+        state.disk_header_hash = None;
+        VirtioBlock::restore(BlockConstructorArgs { mem: default_mem() }, &state).unwrap();
+    }
+
+    #[test]
+    fn test_restore_notification_window() {
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+
+        let config = VirtioBlockConfig {
+            drive_id: "notification_window".to_string(),
+            path_on_host: f.as_path().to_str().unwrap().to_string(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            cache_type: CacheType::Unsafe,
+            rate_limiter: None,
+            file_engine_type: FileEngineType::Sync,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: false,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: Some(50),
+            write_batch: None,
+            fd_on_host: None,
+        };
+
+        let mut block = VirtioBlock::new(config).unwrap();
+        let guest_mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &guest_mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(guest_mem.clone()).unwrap();
+        // The window only applies after a restore.
+        assert!(!block.notification_suppressor.as_ref().unwrap().is_active());
+
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &block.save()).unwrap();
+        let mut restored_block = VirtioBlock::restore(
+            BlockConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_block.restore_notification_window_ms(), Some(50));
+
+        // The notifications of the resuming guest are held back.
+        read_blk_req_descriptors(&vq);
+        guest_mem
+            .write_obj(VIRTIO_BLK_T_IN, GuestAddress(vq.dtable[0].addr.get()))
+            .unwrap();
+        for _ in 0..3 {
+            restored_block.queue_evts[0].write(1).unwrap();
+            restored_block.process_queue_event();
+        }
+        assert_eq!(vq.used.idx.get(), 0);
+        assert_eq!(restored_block.metrics.suppressed_queue_events.count(), 3);
+
+        // They are coalesced into a single pass once the window is over.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        restored_block.process_notification_window_event();
+        assert_eq!(vq.used.idx.get(), 1);
+        assert!(!restored_block
+            .notification_suppressor
+            .as_ref()
+            .unwrap()
+            .is_active());
+
+        // Later notifications are processed right away.
+        restored_block.queue_evts[0].write(1).unwrap();
+        restored_block.process_queue_event();
+        assert_eq!(restored_block.metrics.suppressed_queue_events.count(), 3);
     }
 }
@@ -0,0 +1,259 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording of the requests the guest submits to a block device, and their replay.
+//!
+//! Every request taken off the queue is written as a line of JSON holding its descriptor chain
+//! and its request header. The data the request reads or writes is not recorded. Feeding the
+//! recording back into a queue reproduces the sequence of requests the device processed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::queue::{DescriptorChain, Queue};
+use crate::vstate::memory::{Address, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+// Offset of the sector in the request header, after the request type and a reserved field.
+const HEADER_SECTOR_OFFSET: u64 = 8;
+// Size of an entry of the descriptor table.
+const DESC_SIZE: u64 = 16;
+
+/// Errors triggered while recording or replaying block requests.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum RequestRecordError {
+    /// Failed to access the recording file: {0}
+    Io(#[from] std::io::Error),
+    /// Failed to (de)serialize a recorded request: {0}
+    Serde(#[from] serde_json::Error),
+    /// Failed to access guest memory: {0}
+    GuestMemory(#[from] GuestMemoryError),
+}
+
+/// A descriptor of a recorded request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedDescriptor {
+    /// Index of the descriptor in the descriptor table.
+    pub index: u16,
+    /// Guest physical address of the descriptor buffer.
+    pub addr: u64,
+    /// Length of the descriptor buffer.
+    pub len: u32,
+    /// Descriptor flags.
+    pub flags: u16,
+    /// Index of the next descriptor of the chain.
+    pub next: u16,
+}
+
+/// A request as the guest submitted it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// Type of the request, as found in the request header.
+    pub request_type: u32,
+    /// Sector of the request, as found in the request header.
+    pub sector: u64,
+    /// The descriptor chain of the request, starting with its head.
+    pub descriptors: Vec<RecordedDescriptor>,
+}
+
+impl RecordedRequest {
+    /// Captures the descriptor chain starting at `head`. The header fields are left zeroed if the
+    /// head doesn't point to readable guest memory.
+    pub fn from_chain(head: &DescriptorChain, mem: &GuestMemoryMmap) -> Self {
+        let request_type = mem.read_obj(head.addr).unwrap_or_default();
+        let sector = mem
+            .read_obj(head.addr.unchecked_add(HEADER_SECTOR_OFFSET))
+            .unwrap_or_default();
+        let mut descriptors = vec![RecordedDescriptor::from(head)];
+        let mut desc = head.next_descriptor();
+        while let Some(d) = desc {
+            descriptors.push(RecordedDescriptor::from(&d));
+            desc = d.next_descriptor();
+        }
+
+        RecordedRequest {
+            request_type,
+            sector,
+            descriptors,
+        }
+    }
+
+    /// Writes the request header and the descriptor chain to guest memory and makes the chain
+    /// available in `queue`, as the guest driver would.
+    pub fn replay(&self, mem: &GuestMemoryMmap, queue: &Queue) -> Result<(), RequestRecordError> {
+        let head = match self.descriptors.first() {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+
+        for desc in &self.descriptors {
+            let desc_addr = queue
+                .desc_table
+                .unchecked_add(u64::from(desc.index) * DESC_SIZE);
+            mem.write_obj(desc.addr, desc_addr)?;
+            mem.write_obj(desc.len, desc_addr.unchecked_add(8))?;
+            mem.write_obj(desc.flags, desc_addr.unchecked_add(12))?;
+            mem.write_obj(desc.next, desc_addr.unchecked_add(14))?;
+        }
+        let header_addr = GuestAddress(head.addr);
+        mem.write_obj(self.request_type, header_addr)?;
+        mem.write_obj(self.sector, header_addr.unchecked_add(HEADER_SECTOR_OFFSET))?;
+
+        // The avail ring holds the u16 flags, the u16 index of the next free slot, then the slots.
+        let idx_addr = queue.avail_ring.unchecked_add(2);
+        let idx: u16 = mem.read_obj(idx_addr)?;
+        let slot = u64::from(idx % queue.actual_size());
+        mem.write_obj(head.index, queue.avail_ring.unchecked_add(4 + slot * 2))?;
+        mem.write_obj(idx.wrapping_add(1), idx_addr)?;
+        Ok(())
+    }
+}
+
+impl From<&DescriptorChain<'_>> for RecordedDescriptor {
+    fn from(desc: &DescriptorChain) -> Self {
+        RecordedDescriptor {
+            index: desc.index,
+            addr: desc.addr.raw_value(),
+            len: desc.len,
+            flags: desc.flags,
+            next: desc.next,
+        }
+    }
+}
+
+/// Appends the requests taken off a queue to a recording file.
+#[derive(Debug)]
+pub struct RequestRecorder {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl RequestRecorder {
+    /// Creates the recording file at `path`, truncating it if it exists.
+    pub fn new(path: String) -> Result<Self, RequestRecordError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(RequestRecorder {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Path of the recording file.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Records the request whose descriptor chain starts at `head`.
+    pub fn record(
+        &mut self,
+        head: &DescriptorChain,
+        mem: &GuestMemoryMmap,
+    ) -> Result<(), RequestRecordError> {
+        serde_json::to_writer(&mut self.writer, &RecordedRequest::from_chain(head, mem))?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Writes the buffered requests to the recording file.
+    pub fn flush(&mut self) -> Result<(), RequestRecordError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the requests of the recording file at `path`, in the order they were recorded.
+pub fn read_recording(path: &str) -> Result<Vec<RecordedRequest>, RequestRecordError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut requests = Vec::new();
+    for line in reader.lines() {
+        requests.push(serde_json::from_str(&line?)?);
+    }
+    Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::devices::virtio::block::virtio::device::FileEngineType;
+    use crate::devices::virtio::block::virtio::request::{
+        VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    };
+    use crate::devices::virtio::block::virtio::test_utils::{
+        default_block, read_blk_req_descriptors, set_queue, simulate_queue_event,
+    };
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::queue::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use crate::devices::virtio::test_utils::{default_mem, VirtQueue};
+
+    #[test]
+    fn test_record_and_replay() {
+        let record_file = TempFile::new().unwrap();
+        let record_path = record_file.as_path().to_str().unwrap().to_string();
+
+        // Record a few requests.
+        let mut block = default_block(FileEngineType::Sync);
+        block.request_recorder = Some(RequestRecorder::new(record_path.clone()).unwrap());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        read_blk_req_descriptors(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let status_addr = GuestAddress(vq.dtable[2].addr.get());
+        let mut completions = Vec::new();
+        for request_type in [
+            VIRTIO_BLK_T_OUT,
+            VIRTIO_BLK_T_IN,
+            VIRTIO_BLK_T_FLUSH,
+            VIRTIO_BLK_T_GET_ID,
+        ] {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj(request_type, request_type_addr).unwrap();
+            let flags = match request_type {
+                VIRTIO_BLK_T_OUT => VIRTQ_DESC_F_NEXT,
+                _ => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+            };
+            vq.dtable[1].flags.set(flags);
+            vq.dtable[1].len.set(512);
+
+            simulate_queue_event(&mut block, Some(true));
+            assert_eq!(vq.used.idx.get(), 1);
+            completions.push((
+                vq.used.ring[0].get().len,
+                mem.read_obj::<u8>(status_addr).unwrap(),
+            ));
+        }
+        drop(block);
+
+        let recording = read_recording(&record_path).unwrap();
+        assert_eq!(recording.len(), completions.len());
+        assert_eq!(recording[0].request_type, VIRTIO_BLK_T_OUT);
+        assert_eq!(recording[0].descriptors.len(), 3);
+        assert_eq!(recording[0].descriptors[1].len, 512);
+
+        // Replaying them on a fresh device completes them in the same way.
+        let mut block = default_block(FileEngineType::Sync);
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let queue = vq.create_queue();
+        set_queue(&mut block, 0, queue.clone());
+        block.activate(mem.clone()).unwrap();
+
+        for (i, (request, completion)) in recording.iter().zip(completions).enumerate() {
+            request.replay(&mem, &queue).unwrap();
+            simulate_queue_event(&mut block, Some(true));
+            assert_eq!(usize::from(vq.used.idx.get()), i + 1);
+            assert_eq!(vq.used.ring[i].get().len, completion.0);
+            assert_eq!(mem.read_obj::<u8>(status_addr).unwrap(), completion.1);
+        }
+    }
+}
@@ -7,14 +7,19 @@
 
 use std::convert::From;
 
+use serde::{Deserialize, Serialize};
 use vm_memory::GuestMemoryError;
 
+use super::batch::WriteBatcher;
+use super::discard::{DiscardSegment, MAX_DISCARD_SECTORS};
+use super::retry::IoRetrier;
 use super::{io as block_io, VirtioBlockError, SECTOR_SHIFT, SECTOR_SIZE};
 use crate::devices::virtio::block::virtio::device::DiskProperties;
 use crate::devices::virtio::block::virtio::metrics::BlockDeviceMetrics;
 pub use crate::devices::virtio::gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
-    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN,
+    VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
 };
 use crate::devices::virtio::queue::DescriptorChain;
 use crate::logger::{error, IncMetric};
@@ -34,6 +39,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceID,
+    Discard,
+    WriteZeroes,
     Unsupported(u32),
 }
 
@@ -44,15 +51,49 @@ impl From<u32> for RequestType {
             VIRTIO_BLK_T_OUT => RequestType::Out,
             VIRTIO_BLK_T_FLUSH => RequestType::Flush,
             VIRTIO_BLK_T_GET_ID => RequestType::GetDeviceID,
+            VIRTIO_BLK_T_DISCARD => RequestType::Discard,
+            VIRTIO_BLK_T_WRITE_ZEROES => RequestType::WriteZeroes,
             t => RequestType::Unsupported(t),
         }
     }
 }
 
+impl From<RequestType> for u32 {
+    fn from(value: RequestType) -> Self {
+        match value {
+            RequestType::In => VIRTIO_BLK_T_IN,
+            RequestType::Out => VIRTIO_BLK_T_OUT,
+            RequestType::Flush => VIRTIO_BLK_T_FLUSH,
+            RequestType::GetDeviceID => VIRTIO_BLK_T_GET_ID,
+            RequestType::Discard => VIRTIO_BLK_T_DISCARD,
+            RequestType::WriteZeroes => VIRTIO_BLK_T_WRITE_ZEROES,
+            RequestType::Unsupported(t) => t,
+        }
+    }
+}
+
+/// A request submitted to the IO engine that did not complete yet.
+///
+/// In-flight requests are persisted in snapshots and executed again as a whole after restore.
+/// This is idempotent even if the request partially completed before the snapshot: a read or a
+/// write covers the same sectors and the same guest memory the second time around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InFlightRequest {
+    /// Index of the head of the descriptor chain of the request.
+    pub desc_idx: u16,
+    /// Type of the request, as found in the request header.
+    pub request_type: u32,
+    /// First sector of the request.
+    pub sector: u64,
+    /// Length of the data of the request.
+    pub data_len: u32,
+}
+
 #[derive(Debug)]
 pub enum ProcessingResult {
     Submitted,
-    Throttled,
+    Throttled(PendingRequest),
+    Retry(PendingRequest),
     Executed(FinishedRequest),
 }
 
@@ -60,6 +101,8 @@ pub enum ProcessingResult {
 pub struct FinishedRequest {
     pub num_bytes_to_mem: u32,
     pub desc_idx: u16,
+    /// Description of the error the request failed with, if any.
+    pub error: Option<String>,
 }
 
 #[derive(Debug)]
@@ -94,6 +137,8 @@ pub struct PendingRequest {
     r#type: RequestType,
     data_len: u32,
     status_addr: GuestAddress,
+    sector: u64,
+    data_addr: GuestAddress,
     desc_idx: u16,
 }
 
@@ -104,25 +149,33 @@ impl PendingRequest {
         mem: &GuestMemoryMmap,
         block_metrics: &BlockDeviceMetrics,
     ) -> FinishedRequest {
-        let (num_bytes_to_mem, status_code) = match status {
-            Status::Ok { num_bytes_to_mem } => {
-                (*num_bytes_to_mem, u8::try_from(VIRTIO_BLK_S_OK).unwrap())
-            }
+        let (num_bytes_to_mem, status_code, mut error) = match status {
+            Status::Ok { num_bytes_to_mem } => (
+                *num_bytes_to_mem,
+                u8::try_from(VIRTIO_BLK_S_OK).unwrap(),
+                None,
+            ),
             Status::IoErr {
                 num_bytes_to_mem,
                 err,
             } => {
                 block_metrics.invalid_reqs_count.inc();
-                error!(
+                let msg = format!(
                     "Failed to execute {:?} virtio block request: {:?}",
                     self.r#type, err
                 );
-                (*num_bytes_to_mem, u8::try_from(VIRTIO_BLK_S_IOERR).unwrap())
+                error!("{}", msg);
+                (
+                    *num_bytes_to_mem,
+                    u8::try_from(VIRTIO_BLK_S_IOERR).unwrap(),
+                    Some(msg),
+                )
             }
             Status::Unsupported { op } => {
                 block_metrics.invalid_reqs_count.inc();
-                error!("Received unsupported virtio block request: {}", op);
-                (0, u8::try_from(VIRTIO_BLK_S_UNSUPP).unwrap())
+                let msg = format!("Received unsupported virtio block request: {}", op);
+                error!("{}", msg);
+                (0, u8::try_from(VIRTIO_BLK_S_UNSUPP).unwrap(), Some(msg))
             }
         };
 
@@ -133,7 +186,9 @@ impl PendingRequest {
                 num_bytes_to_mem + 1
             })
             .unwrap_or_else(|err| {
-                error!("Failed to write virtio block status: {:?}", err);
+                let msg = format!("Failed to write virtio block status: {:?}", err);
+                error!("{}", msg);
+                error = Some(msg);
                 // If we can't write the status, discard the virtio descriptor
                 0
             });
@@ -141,6 +196,7 @@ impl PendingRequest {
         FinishedRequest {
             num_bytes_to_mem,
             desc_idx: self.desc_idx,
+            error,
         }
     }
 
@@ -176,6 +232,24 @@ impl PendingRequest {
             (Ok(transferred_data_len), RequestType::GetDeviceID) => {
                 Status::from_data(self.data_len, transferred_data_len, true)
             }
+            (Ok(_), RequestType::Discard) => {
+                block_metrics.discard_count.inc();
+                block_metrics
+                    .discard_sectors
+                    .add((self.data_len >> SECTOR_SHIFT).into());
+                Status::Ok {
+                    num_bytes_to_mem: 0,
+                }
+            }
+            (Ok(_), RequestType::WriteZeroes) => {
+                block_metrics.write_zeroes_count.inc();
+                block_metrics
+                    .write_zeroes_sectors
+                    .add((self.data_len >> SECTOR_SHIFT).into());
+                Status::Ok {
+                    num_bytes_to_mem: 0,
+                }
+            }
             (_, RequestType::Unsupported(op)) => Status::Unsupported { op },
             (Err(err), _) => Status::IoErr {
                 num_bytes_to_mem: 0,
@@ -185,6 +259,41 @@ impl PendingRequest {
 
         self.write_status_and_finish(&status, mem, block_metrics)
     }
+
+    /// Gives back the rate limiter tokens taken by the request, so that it isn't charged again
+    /// when it is taken from the queue for a retry.
+    pub(crate) fn refund_rate_limit(&self, rate_limiter: &mut RateLimiter) {
+        rate_limiter.manual_replenish(1, TokenType::Ops);
+        if self.r#type == RequestType::In || self.r#type == RequestType::Out {
+            rate_limiter.manual_replenish(u64::from(self.data_len), TokenType::Bytes);
+        }
+    }
+
+    /// Submits the request to the IO engine again, after it failed.
+    pub(crate) fn retry(
+        self,
+        disk: &mut DiskProperties,
+        mem: &GuestMemoryMmap,
+        io_retrier: Option<&mut IoRetrier>,
+        ignore_flush: bool,
+        block_metrics: &BlockDeviceMetrics,
+    ) -> ProcessingResult {
+        let request = Request {
+            r#type: self.r#type,
+            data_len: self.data_len,
+            status_addr: self.status_addr,
+            sector: self.sector,
+            data_addr: self.data_addr,
+        };
+        request.process(
+            disk,
+            self.desc_idx,
+            mem,
+            io_retrier,
+            ignore_flush,
+            block_metrics,
+        )
+    }
 }
 
 /// The request header represents the mandatory fields of each block device request.
@@ -241,6 +350,16 @@ pub struct Request {
 }
 
 impl Request {
+    /// Describes this request as being in flight.
+    pub fn to_in_flight(&self, desc_idx: u16) -> InFlightRequest {
+        InFlightRequest {
+            desc_idx,
+            request_type: self.r#type.into(),
+            sector: self.sector,
+            data_len: self.data_len,
+        }
+    }
+
     pub fn parse(
         avail_desc: &DescriptorChain,
         mem: &GuestMemoryMmap,
@@ -278,7 +397,12 @@ impl Request {
                 .next_descriptor()
                 .ok_or(VirtioBlockError::DescriptorChainTooShort)?;
 
-            if data_desc.is_write_only() && req.r#type == RequestType::Out {
+            if data_desc.is_write_only()
+                && matches!(
+                    req.r#type,
+                    RequestType::Out | RequestType::Discard | RequestType::WriteZeroes
+                )
+            {
                 return Err(VirtioBlockError::UnexpectedWriteOnlyDescriptor);
             }
             if !data_desc.is_write_only() && req.r#type == RequestType::In {
@@ -313,6 +437,29 @@ impl Request {
                     return Err(VirtioBlockError::InvalidDataLength);
                 }
             }
+            RequestType::Discard | RequestType::WriteZeroes => {
+                // Only a single segment per request is offered to the guest.
+                if req.data_len as usize != std::mem::size_of::<DiscardSegment>() {
+                    return Err(VirtioBlockError::InvalidDataLength);
+                }
+                let segment: DiscardSegment = mem
+                    .read_obj(req.data_addr)
+                    .map_err(VirtioBlockError::GuestMemory)?;
+                if segment.num_sectors > MAX_DISCARD_SECTORS {
+                    return Err(VirtioBlockError::InvalidDataLength);
+                }
+                let top_sector = segment
+                    .sector
+                    .checked_add(u64::from(segment.num_sectors))
+                    .ok_or(VirtioBlockError::InvalidOffset)?;
+                if top_sector > num_disk_sectors {
+                    return Err(VirtioBlockError::InvalidOffset);
+                }
+                // From now on, the request covers the range of the segment. Its length is in
+                // bytes, like that of reads and writes.
+                req.sector = segment.sector;
+                req.data_len = segment.num_sectors << SECTOR_SHIFT;
+            }
             _ => {}
         }
 
@@ -330,6 +477,49 @@ impl Request {
         Ok(req)
     }
 
+    /// Fails a descriptor chain longer than `max_chain_len` descriptors with
+    /// `VIRTIO_BLK_S_IOERR`, which is written to the last descriptor if it can hold the status.
+    /// Returns `None` if the chain is short enough to be parsed.
+    pub fn reject_too_long(
+        avail_desc: &DescriptorChain,
+        mem: &GuestMemoryMmap,
+        max_chain_len: u16,
+        block_metrics: &BlockDeviceMetrics,
+    ) -> Option<FinishedRequest> {
+        // The walk is bounded by the queue size, which also breaks cycles in the chain.
+        let mut chain_len = 1u16;
+        let mut last_desc = None;
+        let mut next_desc = avail_desc.next_descriptor();
+        while let Some(desc) = next_desc {
+            chain_len += 1;
+            next_desc = desc.next_descriptor();
+            last_desc = Some(desc);
+        }
+        if chain_len <= max_chain_len {
+            return None;
+        }
+
+        // The guest controls the chains, so these are only counted instead of logged.
+        block_metrics.invalid_reqs_count.inc();
+        block_metrics.chains_too_long_count.inc();
+        let num_bytes_to_mem = last_desc
+            .filter(|desc| desc.is_write_only() && desc.len >= 1)
+            .and_then(|desc| {
+                mem.write_obj(u8::try_from(VIRTIO_BLK_S_IOERR).unwrap(), desc.addr)
+                    .ok()
+            })
+            .map_or(0, |_| 1);
+
+        Some(FinishedRequest {
+            num_bytes_to_mem,
+            desc_idx: avail_desc.index,
+            error: Some(format!(
+                "Descriptor chain of {} descriptors is longer than the maximum of {}",
+                chain_len, max_chain_len
+            )),
+        })
+    }
+
     pub(crate) fn rate_limit(&self, rate_limiter: &mut RateLimiter) -> bool {
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
@@ -350,15 +540,28 @@ impl Request {
         false
     }
 
-    fn offset(&self) -> u64 {
+    pub(crate) fn offset(&self) -> u64 {
         self.sector << SECTOR_SHIFT
     }
 
+    /// Adds this write request to `batcher` instead of executing it right away. Returns `true`
+    /// if the batch is full.
+    pub(crate) fn batch(self, batcher: &mut WriteBatcher, desc_idx: u16) -> bool {
+        batcher.push(
+            self.offset(),
+            self.data_addr,
+            self.data_len,
+            self.to_pending_request(desc_idx),
+        )
+    }
+
     fn to_pending_request(&self, desc_idx: u16) -> PendingRequest {
         PendingRequest {
             r#type: self.r#type,
             data_len: self.data_len,
             status_addr: self.status_addr,
+            sector: self.sector,
+            data_addr: self.data_addr,
             desc_idx,
         }
     }
@@ -368,6 +571,8 @@ impl Request {
         disk: &mut DiskProperties,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
+        io_retrier: Option<&mut IoRetrier>,
+        ignore_flush: bool,
         block_metrics: &BlockDeviceMetrics,
     ) -> ProcessingResult {
         let pending = self.to_pending_request(desc_idx);
@@ -382,7 +587,24 @@ impl Request {
                 disk.file_engine
                     .write(self.offset(), mem, self.data_addr, self.data_len, pending)
             }
+            RequestType::Flush if ignore_flush => {
+                block_metrics.flush_ignored_count.inc();
+                return ProcessingResult::Executed(pending.write_status_and_finish(
+                    &Status::Ok {
+                        num_bytes_to_mem: 0,
+                    },
+                    mem,
+                    block_metrics,
+                ));
+            }
             RequestType::Flush => disk.file_engine.flush(pending),
+            RequestType::Discard => disk
+                .file_engine
+                .discard(self.offset(), self.data_len, pending),
+            RequestType::WriteZeroes => {
+                disk.file_engine
+                    .write_zeroes(self.offset(), self.data_len, pending)
+            }
             RequestType::GetDeviceID => {
                 let res = mem
                     .write_slice(&disk.image_id, self.data_addr)
@@ -402,7 +624,10 @@ impl Request {
             }
             Err(err) => {
                 if err.error.is_throttling_err() {
-                    ProcessingResult::Throttled
+                    ProcessingResult::Throttled(err.user_data)
+                } else if io_retrier.map_or(false, IoRetrier::schedule) {
+                    block_metrics.io_retry_count.inc();
+                    ProcessingResult::Retry(err.user_data)
                 } else {
                     ProcessingResult::Executed(err.user_data.finish(
                         mem,
@@ -438,6 +663,8 @@ mod tests {
             VIRTIO_BLK_T_OUT,
             VIRTIO_BLK_T_FLUSH,
             VIRTIO_BLK_T_GET_ID,
+            VIRTIO_BLK_T_DISCARD,
+            VIRTIO_BLK_T_WRITE_ZEROES,
         ];
 
         for request_type in supported_request_types {
@@ -464,6 +691,14 @@ mod tests {
             RequestType::from(VIRTIO_BLK_T_GET_ID),
             RequestType::GetDeviceID
         );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_DISCARD),
+            RequestType::Discard
+        );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_WRITE_ZEROES),
+            RequestType::WriteZeroes
+        );
         assert_eq!(RequestType::from(42), RequestType::Unsupported(42));
     }
 
@@ -659,6 +894,54 @@ mod tests {
         chain.check_parse(true);
     }
 
+    #[test]
+    fn test_parse_discard() {
+        let mem = &default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), mem, 16);
+        let chain = RequestDescriptorChain::new(&queue);
+        let data_addr = GuestAddress(chain.data_desc.addr.get());
+        let segment_len = u32::try_from(std::mem::size_of::<DiscardSegment>()).unwrap();
+
+        for request_type in [VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_WRITE_ZEROES] {
+            let request_header = RequestHeader::new(request_type, 0);
+            chain.set_header(request_header);
+            let mut segment = DiscardSegment {
+                sector: NUM_DISK_SECTORS - 8,
+                num_sectors: 8,
+                flags: 0,
+            };
+            mem.write_obj(segment, data_addr).unwrap();
+
+            // Write only data descriptor.
+            chain
+                .data_desc
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            chain.data_desc.len.set(segment_len);
+            chain.check_parse_err(VirtioBlockError::UnexpectedWriteOnlyDescriptor);
+
+            // Only a single segment is accepted.
+            chain.data_desc.flags.set(VIRTQ_DESC_F_NEXT);
+            chain.data_desc.len.set(2 * segment_len);
+            chain.check_parse_err(VirtioBlockError::InvalidDataLength);
+
+            // The segment reaches past the end of the disk.
+            chain.data_desc.len.set(segment_len);
+            segment.sector = NUM_DISK_SECTORS - 7;
+            mem.write_obj(segment, data_addr).unwrap();
+            chain.check_parse_err(VirtioBlockError::InvalidOffset);
+
+            // The request covers the range of the segment.
+            segment.sector = NUM_DISK_SECTORS - 8;
+            mem.write_obj(segment, data_addr).unwrap();
+            let mut q = queue.create_queue();
+            let request = Request::parse(&q.pop(mem).unwrap(), mem, NUM_DISK_SECTORS).unwrap();
+            assert_eq!(request.r#type, RequestType::from(request_type));
+            assert_eq!(request.sector, NUM_DISK_SECTORS - 8);
+            assert_eq!(request.data_len, 8 * SECTOR_SIZE);
+        }
+    }
+
     use std::convert::TryInto;
 
     /// -------------------------------------
@@ -704,27 +987,15 @@ mod tests {
                     1u32,
                     std::sync::Arc::new(Strategy::prop_map(any::<u32>(), |id| {
                         // Random unsupported requests for our implementation start at
-                        // VIRTIO_BLK_T_GET_ID + 1 = 9.
-                        // This can be further refined to include unsupported requests ids < 9.
-                        RequestType::Unsupported(id.checked_add(9).unwrap_or(9))
+                        // VIRTIO_BLK_T_WRITE_ZEROES + 1 = 14.
+                        // This can be further refined to include unsupported requests ids < 14.
+                        RequestType::Unsupported(id.checked_add(14).unwrap_or(14))
                     })),
                 ),
             ))
         }
     }
 
-    impl From<RequestType> for u32 {
-        fn from(request_type: RequestType) -> u32 {
-            match request_type {
-                RequestType::In => VIRTIO_BLK_T_IN,
-                RequestType::Out => VIRTIO_BLK_T_OUT,
-                RequestType::Flush => VIRTIO_BLK_T_FLUSH,
-                RequestType::GetDeviceID => VIRTIO_BLK_T_GET_ID,
-                RequestType::Unsupported(id) => id,
-            }
-        }
-    }
-
     // Returns flags based on the request type.
     fn request_type_flags(request_type: RequestType) -> u16 {
         match request_type {
@@ -732,6 +1003,7 @@ mod tests {
             RequestType::Out => VIRTQ_DESC_F_NEXT,
             RequestType::Flush => VIRTQ_DESC_F_NEXT,
             RequestType::GetDeviceID => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+            RequestType::Discard | RequestType::WriteZeroes => VIRTQ_DESC_F_NEXT,
             RequestType::Unsupported(_) => VIRTQ_DESC_F_NEXT,
         }
     }
@@ -0,0 +1,195 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retries of block requests failing because of the backing store.
+
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+/// I/O retry policy of a block device.
+///
+/// A request failing because of the backing store is retried up to `max_retries` times,
+/// `backoff_ms` milliseconds apart, before the error is reported to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoRetryConfig {
+    /// Number of times a failed request is retried.
+    pub max_retries: u32,
+    /// Delay, in milliseconds, before retrying a failed request.
+    pub backoff_ms: u64,
+}
+
+/// Errors associated with the I/O retry policy of a block device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum IoRetryError {
+    /// I/O retries require a non-zero number of retries.
+    InvalidMaxRetries,
+    /// I/O retries require a non-zero backoff.
+    InvalidBackoff,
+    /// Cannot create the I/O retry timer: {0}
+    Timer(std::io::Error),
+}
+
+/// Schedules the retries of a failed request according to an [`IoRetryConfig`].
+///
+/// Retries are driven by a timer so that waiting for the backoff does not block the
+/// event loop. The retries are counted per device: requests failing one after the other, with
+/// no request going through in between, share the same `max_retries`.
+pub struct IoRetrier {
+    config: IoRetryConfig,
+    timer: TimerFd,
+    attempts: u32,
+    pending: bool,
+}
+
+// TODO Use `#[derive(Debug)]` when a new release of
+// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
+// https://github.com/main--/rust-timerfd/pull/12.
+impl fmt::Debug for IoRetrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoRetrier")
+            .field("config", &self.config)
+            .field("attempts", &self.attempts)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl IoRetrier {
+    /// Creates a new retrier from the given config.
+    pub fn new(config: IoRetryConfig) -> Result<Self, IoRetryError> {
+        if config.max_retries == 0 {
+            return Err(IoRetryError::InvalidMaxRetries);
+        }
+        if config.backoff_ms == 0 {
+            return Err(IoRetryError::InvalidBackoff);
+        }
+
+        Ok(Self {
+            config,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(IoRetryError::Timer)?,
+            attempts: 0,
+            pending: false,
+        })
+    }
+
+    /// Returns the config of this retrier.
+    pub fn config(&self) -> IoRetryConfig {
+        self.config
+    }
+
+    /// Whether a retry is waiting for its backoff to elapse.
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Schedules a retry of the failed request. Returns `false` if the request has run out
+    /// of retries, in which case the error should be reported.
+    pub fn schedule(&mut self) -> bool {
+        if self.attempts >= self.config.max_retries {
+            self.attempts = 0;
+            return false;
+        }
+
+        self.attempts += 1;
+        self.timer.set_state(
+            TimerState::Oneshot(Duration::from_millis(self.config.backoff_ms)),
+            SetTimeFlags::Default,
+        );
+        self.pending = true;
+        true
+    }
+
+    /// Handles a timer expiration. Returns `true` if the failed request should be retried now.
+    pub fn on_timer(&mut self) -> bool {
+        // The timer is non-blocking, nothing is read if it did not expire yet.
+        if self.timer.read() == 0 {
+            return false;
+        }
+        let retry = self.pending;
+        self.pending = false;
+        retry
+    }
+
+    /// Resets the retry count once a request went through.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+impl AsRawFd for IoRetrier {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_config() {
+        let err = IoRetrier::new(IoRetryConfig {
+            max_retries: 0,
+            backoff_ms: 10,
+        })
+        .unwrap_err();
+        assert!(matches!(err, IoRetryError::InvalidMaxRetries));
+
+        let err = IoRetrier::new(IoRetryConfig {
+            max_retries: 2,
+            backoff_ms: 0,
+        })
+        .unwrap_err();
+        assert!(matches!(err, IoRetryError::InvalidBackoff));
+    }
+
+    #[test]
+    fn test_timer_not_expired() {
+        let mut retrier = IoRetrier::new(IoRetryConfig {
+            max_retries: 1,
+            backoff_ms: 1000,
+        })
+        .unwrap();
+
+        assert!(retrier.schedule());
+        // The retry waits for its backoff.
+        assert!(!retrier.on_timer());
+        assert!(retrier.is_pending());
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut retrier = IoRetrier::new(IoRetryConfig {
+            max_retries: 2,
+            backoff_ms: 1,
+        })
+        .unwrap();
+
+        assert!(retrier.schedule());
+        assert!(retrier.is_pending());
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(retrier.on_timer());
+        assert!(!retrier.is_pending());
+        assert!(retrier.schedule());
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(retrier.on_timer());
+        // Out of retries.
+        assert!(!retrier.schedule());
+        assert!(!retrier.is_pending());
+
+        // The count starts over for the next failure.
+        assert!(retrier.schedule());
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(retrier.on_timer());
+        retrier.reset();
+        assert!(retrier.schedule());
+        assert!(retrier.schedule());
+        assert!(!retrier.schedule());
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Coalescing of the queue notifications a block device receives right after a restore.
+
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+
+/// Errors associated with the notification suppression window of a block device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum NotificationSuppressionError {
+    /// The notification suppression window must be non-zero.
+    InvalidWindow,
+    /// Cannot create the notification suppression timer: {0}
+    Timer(std::io::Error),
+}
+
+/// Holds back the processing of queue notifications for a window of time after a restore.
+///
+/// Notifications received during the window are coalesced into a single pass over the queue
+/// once the window is over.
+pub struct NotificationSuppressor {
+    window_ms: u64,
+    timer: TimerFd,
+    active: bool,
+    pending: bool,
+}
+
+// TODO Use `#[derive(Debug)]` when a new release of
+// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
+// https://github.com/main--/rust-timerfd/pull/12.
+impl fmt::Debug for NotificationSuppressor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotificationSuppressor")
+            .field("window_ms", &self.window_ms)
+            .field("active", &self.active)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl NotificationSuppressor {
+    /// Creates a new suppressor holding back notifications for `window_ms` milliseconds.
+    pub fn new(window_ms: u64) -> Result<Self, NotificationSuppressionError> {
+        if window_ms == 0 {
+            return Err(NotificationSuppressionError::InvalidWindow);
+        }
+
+        Ok(Self {
+            window_ms,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(NotificationSuppressionError::Timer)?,
+            active: false,
+            pending: false,
+        })
+    }
+
+    /// Returns the length of the window, in milliseconds.
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    /// Whether notifications are currently held back.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts holding back notifications.
+    pub fn start(&mut self) {
+        self.timer.set_state(
+            TimerState::Oneshot(Duration::from_millis(self.window_ms)),
+            SetTimeFlags::Default,
+        );
+        self.active = true;
+    }
+
+    /// Records a notification. Returns `true` if the queue should be processed now.
+    pub fn on_notification(&mut self) -> bool {
+        if self.active {
+            self.pending = true;
+        }
+        !self.active
+    }
+
+    /// Handles the end of the window. Returns `true` if notifications were held back.
+    pub fn on_timer(&mut self) -> bool {
+        self.timer.read();
+        self.active = false;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl AsRawFd for NotificationSuppressor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppression() {
+        let err = NotificationSuppressor::new(0).unwrap_err();
+        assert!(matches!(err, NotificationSuppressionError::InvalidWindow));
+
+        let mut suppressor = NotificationSuppressor::new(1_000).unwrap();
+        // Nothing is held back until the window is started.
+        assert!(suppressor.on_notification());
+
+        suppressor.start();
+        assert!(suppressor.is_active());
+        assert!(!suppressor.on_notification());
+        assert!(!suppressor.on_notification());
+        assert!(suppressor.on_timer());
+        assert!(!suppressor.is_active());
+        assert!(suppressor.on_notification());
+    }
+}
@@ -66,6 +66,15 @@ pub fn default_block_with_path(path: String, file_engine_type: FileEngineType) -
             }),
         }),
         file_engine_type,
+        interrupt_moderation: None,
+        io_retry: None,
+        geometry: None,
+        discard: false,
+        max_chain_len: None,
+        request_record_path: None,
+        restore_notification_window_ms: None,
+        write_batch: None,
+        fd_on_host: None,
     };
 
     // The default block device is read-write and non-root.
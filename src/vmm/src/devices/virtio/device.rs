@@ -8,14 +8,17 @@
 use std::fmt;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 
 use super::mmio::{VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING};
 use super::queue::Queue;
-use super::ActivateError;
+use super::{ActivateError, QuiesceError, SelfTestError};
 use crate::devices::virtio::AsAny;
 use crate::logger::{error, warn};
+use crate::rate_limiter::RateLimiterBudgets;
 use crate::vstate::memory::GuestMemoryMmap;
 
 /// Enum that indicates if a VirtioDevice is inactive or has been activated
@@ -84,6 +87,22 @@ impl IrqTrigger {
     }
 }
 
+/// The most recent error hit by a device, kept for troubleshooting.
+#[derive(Debug, Default)]
+pub struct LastError(Option<(TimestampUs, String)>);
+
+impl LastError {
+    /// Records `err` as the most recent error, timestamped with the current time.
+    pub fn record(&mut self, err: impl fmt::Display) {
+        self.0 = Some((TimestampUs::default(), err.to_string()));
+    }
+
+    /// Returns the most recent error, and when it was recorded.
+    pub fn get(&self) -> Option<(TimestampUs, String)> {
+        self.0.clone()
+    }
+}
+
 /// Trait for virtio devices to be driven by a virtio transport.
 ///
 /// The lifecycle of a virtio device is to be moved to a virtio transport, which will then query the
@@ -107,6 +126,11 @@ pub trait VirtioDevice: AsAny + Send {
         (self.acked_features() & 1 << feature) != 0
     }
 
+    /// Get the features offered by the device that the driver did not acknowledge.
+    fn rejected_features(&self) -> u64 {
+        self.avail_features() & !self.acked_features()
+    }
+
     /// The virtio device type.
     fn device_type(&self) -> u32;
 
@@ -179,6 +203,82 @@ pub trait VirtioDevice: AsAny + Send {
     fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
         None
     }
+
+    /// Approximate number of host file descriptors owned by this device.
+    ///
+    /// By default this accounts for the queue eventfds and the interrupt eventfd. Devices
+    /// with a host backend (tap, backing file, socket) should account for it as well.
+    fn host_fd_count(&self) -> usize {
+        self.queue_events().len() + 1
+    }
+
+    /// Approximate host memory, in bytes, allocated by this device on behalf of the guest.
+    ///
+    /// Only the buffers that grow with the guest load are accounted, not the fixed size state
+    /// of the device.
+    fn host_memory_estimate(&self) -> usize {
+        0
+    }
+
+    /// Caps [`Self::host_memory_estimate`]. Once the limit is reached, the device stops taking
+    /// requests from the guest until its buffers drain. Returns `false` if the device cannot
+    /// be capped.
+    fn set_host_memory_limit(&mut self, _limit: Option<usize>) -> bool {
+        false
+    }
+
+    /// Delivers the interrupts this device has queued but not raised yet.
+    ///
+    /// By default the interrupt is raised again if the interrupt status has pending bits.
+    fn flush_pending_interrupts(&mut self) -> Result<(), std::io::Error> {
+        if self.interrupt_status().load(Ordering::SeqCst) != 0 {
+            self.interrupt_evt().write(1)?;
+        }
+        Ok(())
+    }
+
+    /// Current budgets of the rate limiters of this device, keyed by the name under which
+    /// each limiter is configured.
+    fn rate_limiter_state(&self) -> Vec<(&'static str, RateLimiterBudgets)> {
+        Vec::new()
+    }
+
+    /// Whether the device processes its queues.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    /// Stops or resumes the processing of the device queues while keeping the device
+    /// attached. Returns `false` if the device cannot be disabled.
+    fn set_enabled(&mut self, _enabled: bool) -> bool {
+        false
+    }
+
+    /// Stops the processing of the device queues, waits at most `timeout` for the requests in
+    /// flight to complete and flushes the backing store. `set_enabled(true)` resumes the device.
+    fn quiesce(&mut self, _timeout: Duration) -> Result<(), QuiesceError> {
+        Err(QuiesceError::NotSupported)
+    }
+
+    /// Checks that the host resources backing the device work, before the guest sees it.
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        Ok(())
+    }
+
+    /// Releases the host resources of the device that would outlive the process, such as
+    /// socket files, or that the next microVM may reuse, such as tap interfaces, before the
+    /// VMM exits.
+    fn teardown(&mut self) {}
+
+    /// Changes the id of the device. Returns `false` if the device has a fixed id.
+    fn set_id(&mut self, _id: &str) -> bool {
+        false
+    }
+
+    /// The most recent error hit by the device, and when it happened.
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        None
+    }
 }
 
 impl fmt::Debug for dyn VirtioDevice {
@@ -234,12 +334,13 @@ pub(crate) mod tests {
 
     #[derive(Debug)]
     struct MockVirtioDevice {
+        avail_features: u64,
         acked_features: u64,
     }
 
     impl VirtioDevice for MockVirtioDevice {
         fn avail_features(&self) -> u64 {
-            todo!()
+            self.avail_features
         }
 
         fn acked_features(&self) -> u64 {
@@ -293,7 +394,10 @@ pub(crate) mod tests {
 
     #[test]
     fn test_has_feature() {
-        let mut device = MockVirtioDevice { acked_features: 0 };
+        let mut device = MockVirtioDevice {
+            avail_features: 0,
+            acked_features: 0,
+        };
 
         let mock_feature_1 = 1u64;
         assert!(!device.has_feature(mock_feature_1));
@@ -306,4 +410,20 @@ pub(crate) mod tests {
         assert!(device.has_feature(mock_feature_1));
         assert!(device.has_feature(mock_feature_2));
     }
+
+    #[test]
+    fn test_rejected_features() {
+        let mut device = MockVirtioDevice {
+            avail_features: (1 << 1) | (1 << 2) | (1 << 32),
+            acked_features: 0,
+        };
+        // Nothing is acknowledged before the negotiation.
+        assert_eq!(device.rejected_features(), device.avail_features);
+
+        device.acked_features = 1 << 2;
+        assert_eq!(device.rejected_features(), (1 << 1) | (1 << 32));
+
+        device.acked_features = device.avail_features;
+        assert_eq!(device.rejected_features(), 0);
+    }
 }
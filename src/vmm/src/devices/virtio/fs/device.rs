@@ -0,0 +1,370 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use log::error;
+use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
+use utils::u64_to_usize;
+use vhost::vhost_user::message::*;
+use vhost::vhost_user::Frontend;
+
+use super::{VhostUserFsError, FS_TAG_LEN, NUM_QUEUES, QUEUE_SIZE};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, LastError, VirtioDevice};
+use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use crate::devices::virtio::queue::Queue;
+use crate::devices::virtio::vhost_user::{VhostUserHandleBackend, VhostUserHandleImpl};
+use crate::devices::virtio::vhost_user_metrics::{
+    VhostUserDeviceMetrics, VhostUserMetricsPerDevice,
+};
+use crate::devices::virtio::{ActivateError, TYPE_FS};
+use crate::logger::{log_dev_preview_warning, IncMetric, StoreMetric};
+use crate::vmm_config::fs::FsDeviceConfig;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Number of request queues advertised in the config space.
+const NUM_REQUEST_QUEUES: u32 = 1;
+
+const AVAILABLE_FEATURES: u64 = (1 << VIRTIO_F_VERSION_1)
+    | (1 << VIRTIO_RING_F_EVENT_IDX)
+    // vhost-user specific bit. Not defined in standart virtio spec.
+    // Specifies ability of frontend to negotiate protocol features.
+    | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+
+/// Builds the config space of a virtio-fs device: the tag padded with zeros to
+/// `FS_TAG_LEN` bytes, followed by the number of request queues.
+fn build_config_space(tag: &str) -> Vec<u8> {
+    let mut config_space = vec![0u8; FS_TAG_LEN];
+    config_space[..tag.len()].copy_from_slice(tag.as_bytes());
+    config_space.extend_from_slice(&NUM_REQUEST_QUEUES.to_le_bytes());
+    config_space
+}
+
+pub type VhostUserFs = VhostUserFsImpl<Frontend>;
+
+/// vhost-user fs device.
+pub struct VhostUserFsImpl<T: VhostUserHandleBackend> {
+    // Virtio fields.
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config_space: Vec<u8>,
+    pub activate_evt: EventFd,
+
+    // Transport related fields.
+    pub queues: Vec<Queue>,
+    pub queue_evts: [EventFd; u64_to_usize(NUM_QUEUES)],
+    pub device_state: DeviceState,
+    pub irq_trigger: IrqTrigger,
+
+    // Implementation specific fields.
+    pub id: String,
+    pub tag: String,
+
+    // Vhost user protocol handle
+    pub vu_handle: VhostUserHandleImpl<T>,
+    pub vu_acked_protocol_features: u64,
+    pub metrics: Arc<VhostUserDeviceMetrics>,
+    // Most recent error hit while serving the guest.
+    pub last_error: LastError,
+}
+
+// Need custom implementation because otherwise `Debug` is required for `vhost::Master`
+impl<T: VhostUserHandleBackend> std::fmt::Debug for VhostUserFsImpl<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VhostUserFsImpl")
+            .field("avail_features", &self.avail_features)
+            .field("acked_features", &self.acked_features)
+            .field("config_space", &self.config_space)
+            .field("activate_evt", &self.activate_evt)
+            .field("queues", &self.queues)
+            .field("queue_evts", &self.queue_evts)
+            .field("device_state", &self.device_state)
+            .field("irq_trigger", &self.irq_trigger)
+            .field("id", &self.id)
+            .field("tag", &self.tag)
+            .field("vu_handle", &self.vu_handle)
+            .field(
+                "vu_acked_protocol_features",
+                &self.vu_acked_protocol_features,
+            )
+            .field("metrics", &self.metrics)
+            .field("last_error", &self.last_error)
+            .finish()
+    }
+}
+
+impl<T: VhostUserHandleBackend> VhostUserFsImpl<T> {
+    pub fn new(config: FsDeviceConfig) -> Result<Self, VhostUserFsError> {
+        log_dev_preview_warning("vhost-user-fs device", Option::None);
+        if config.tag.is_empty() || config.tag.len() > FS_TAG_LEN {
+            return Err(VhostUserFsError::InvalidTag(config.tag));
+        }
+
+        let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+
+        let mut vu_handle = VhostUserHandleImpl::<T>::new(&config.socket, NUM_QUEUES)
+            .map_err(VhostUserFsError::VhostUser)?;
+        let (acked_features, acked_protocol_features) = vu_handle
+            .negotiate_features(AVAILABLE_FEATURES, VhostUserProtocolFeatures::empty())
+            .map_err(VhostUserFsError::VhostUser)?;
+
+        let activate_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?;
+
+        let queues = vec![Queue::new(QUEUE_SIZE), Queue::new(QUEUE_SIZE)];
+        let queue_evts = [
+            EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?,
+        ];
+        let device_state = DeviceState::Inactive;
+        let irq_trigger = IrqTrigger::new().map_err(VhostUserFsError::IrqTrigger)?;
+
+        // We negotiated features with backend. Now these acked_features
+        // are available for guest driver to choose from.
+        let avail_features = acked_features;
+        let acked_features = acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+
+        let metrics = VhostUserMetricsPerDevice::alloc(format!("fs_{}", config.fs_id));
+        let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
+        metrics.init_time_us.store(delta_us);
+
+        Ok(Self {
+            avail_features,
+            acked_features,
+            config_space: build_config_space(&config.tag),
+            activate_evt,
+
+            queues,
+            queue_evts,
+            device_state,
+            irq_trigger,
+
+            id: config.fs_id,
+            tag: config.tag,
+
+            vu_handle,
+            vu_acked_protocol_features: acked_protocol_features,
+            metrics,
+            last_error: LastError::default(),
+        })
+    }
+
+    /// Prepare device for being snapshotted.
+    ///
+    /// The vrings are stopped to learn which requests the backend took from the queues, and
+    /// started again afterwards. The backend state is not part of the snapshot, so preparing
+    /// fails if the backend has not completed all of these requests.
+    pub fn prepare_save(&mut self) -> Result<(), VhostUserFsError> {
+        let DeviceState::Activated(mem) = &self.device_state else {
+            return Ok(());
+        };
+
+        let in_flight = self
+            .vu_handle
+            .save_vring_bases(mem, &mut self.queues, &self.queue_evts, &self.irq_trigger)
+            .map_err(VhostUserFsError::VhostUser)?;
+        if in_flight != 0 {
+            return Err(VhostUserFsError::RequestsInFlight(in_flight));
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> FsDeviceConfig {
+        FsDeviceConfig {
+            fs_id: self.id.clone(),
+            tag: self.tag.clone(),
+            socket: self.vu_handle.socket_path.clone(),
+        }
+    }
+}
+
+impl<T: VhostUserHandleBackend + Send + 'static> VirtioDevice for VhostUserFsImpl<T> {
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn device_type(&self) -> u32 {
+        TYPE_FS
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    /// Returns the current device interrupt status.
+    fn interrupt_status(&self) -> Arc<AtomicU32> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        if let Some(config_space_bytes) = self.config_space.as_slice().get(u64_to_usize(offset)..) {
+            let len = config_space_bytes.len().min(data.len());
+            data[..len].copy_from_slice(&config_space_bytes[..len]);
+        } else {
+            error!("Failed to read config space");
+            self.metrics.cfg_fails.inc();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The virtio-fs config space is read-only.
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        let start_time = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        // Setting features again, because now we negotiated them
+        // with guest driver as well.
+        self.vu_handle
+            .set_features(self.acked_features)
+            .map_err(|err| {
+                self.last_error.record(&err);
+                ActivateError::VhostUser(err)
+            })?;
+        let queues: Vec<_> = self
+            .queues
+            .iter()
+            .zip(self.queue_evts.iter())
+            .enumerate()
+            .map(|(index, (queue, queue_evt))| (index, queue, queue_evt))
+            .collect();
+        self.vu_handle
+            .setup_backend(&mem, &queues, &self.irq_trigger)
+            .map_err(|err| {
+                self.metrics.activate_fails.inc();
+                self.last_error.record(&err);
+                ActivateError::VhostUser(err)
+            })?;
+        self.device_state = DeviceState::Activated(mem);
+        let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
+        self.metrics.activate_time_us.store(delta_us);
+        Ok(())
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
+
+    fn host_fd_count(&self) -> usize {
+        // Queue eventfds, interrupt eventfd and the vhost-user socket.
+        self.queue_evts.len() + 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::undocumented_unsafe_blocks)]
+
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::utilities::test_utils::create_tmp_socket;
+
+    struct MockMaster {
+        features: u64,
+        protocol_features: VhostUserProtocolFeatures,
+    }
+
+    impl VhostUserHandleBackend for MockMaster {
+        fn from_stream(_sock: UnixStream, _max_queue_num: u64) -> Self {
+            Self {
+                features: AVAILABLE_FEATURES,
+                protocol_features: VhostUserProtocolFeatures::all(),
+            }
+        }
+
+        fn set_owner(&self) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_hdr_flags(&self, _flags: VhostUserHeaderFlag) {}
+
+        fn get_features(&self) -> Result<u64, vhost::Error> {
+            Ok(self.features)
+        }
+
+        fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures, vhost::Error> {
+            Ok(self.protocol_features)
+        }
+
+        fn set_protocol_features(
+            &mut self,
+            features: VhostUserProtocolFeatures,
+        ) -> Result<(), vhost::Error> {
+            self.protocol_features = features;
+            Ok(())
+        }
+    }
+
+    fn fs_config(tag: &str, socket: &str) -> FsDeviceConfig {
+        FsDeviceConfig {
+            fs_id: "fs0".to_string(),
+            tag: tag.to_string(),
+            socket: socket.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+
+        let fs = VhostUserFsImpl::<MockMaster>::new(fs_config("shared", &tmp_socket_path)).unwrap();
+        assert_eq!(fs.device_type(), TYPE_FS);
+        assert_eq!(fs.avail_features, AVAILABLE_FEATURES);
+        assert_eq!(
+            fs.acked_features,
+            VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+        );
+        // No protocol feature is requested from the backend.
+        assert_eq!(fs.vu_acked_protocol_features, 0);
+        assert_eq!(fs.queues.len(), u64_to_usize(NUM_QUEUES));
+        assert_eq!(fs.config(), fs_config("shared", &tmp_socket_path));
+
+        // The guest reads the tag followed by the number of request queues.
+        let mut tag = [0xffu8; FS_TAG_LEN];
+        fs.read_config(0, &mut tag);
+        assert_eq!(&tag[..6], b"shared");
+        assert!(tag[6..].iter().all(|b| *b == 0));
+        let mut num_request_queues = [0u8; 4];
+        fs.read_config(FS_TAG_LEN as u64, &mut num_request_queues);
+        assert_eq!(u32::from_le_bytes(num_request_queues), NUM_REQUEST_QUEUES);
+    }
+
+    #[test]
+    fn test_invalid_tag() {
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+
+        for tag in ["", &"t".repeat(FS_TAG_LEN + 1)] {
+            let err =
+                VhostUserFsImpl::<MockMaster>::new(fs_config(tag, &tmp_socket_path)).unwrap_err();
+            assert!(matches!(err, VhostUserFsError::InvalidTag(_)), "{:?}", err);
+        }
+        VhostUserFsImpl::<MockMaster>::new(fs_config(&"t".repeat(FS_TAG_LEN), &tmp_socket_path))
+            .unwrap();
+    }
+}
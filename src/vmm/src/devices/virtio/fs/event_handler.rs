@@ -0,0 +1,74 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use event_manager::{EventOps, Events, MutEventSubscriber};
+use utils::epoll::EventSet;
+
+use super::device::VhostUserFsImpl;
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::vhost_user::VhostUserHandleBackend;
+use crate::logger::{error, warn};
+
+impl<T: VhostUserHandleBackend + Send + 'static> VhostUserFsImpl<T> {
+    const PROCESS_ACTIVATE: u32 = 0;
+
+    fn register_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = ops.add(Events::with_data(
+            &self.activate_evt,
+            Self::PROCESS_ACTIVATE,
+            EventSet::IN,
+        )) {
+            error!("Failed to register activate event: {}", err);
+        }
+    }
+
+    fn process_activate_event(&self, ops: &mut EventOps) {
+        if let Err(err) = self.activate_evt.read() {
+            error!("Failed to consume fs activate event: {:?}", err);
+        }
+        if let Err(err) = ops.remove(Events::with_data(
+            &self.activate_evt,
+            Self::PROCESS_ACTIVATE,
+            EventSet::IN,
+        )) {
+            error!("Failed to un-register activate event: {}", err);
+        }
+    }
+}
+
+impl<T: VhostUserHandleBackend + Send + 'static> MutEventSubscriber for VhostUserFsImpl<T> {
+    // The queues are processed by the backend, only the activate event is handled here.
+    fn process(&mut self, event: Events, ops: &mut EventOps) {
+        let source = event.data();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            if Self::PROCESS_ACTIVATE == source {
+                self.process_activate_event(ops)
+            } else {
+                warn!("FsVhost: Spurious event received: {:?}", source)
+            }
+        } else {
+            warn!(
+                "FsVhost: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn init(&mut self, ops: &mut EventOps) {
+        if self.is_activated() {
+            warn!("Vhost-user fs: unexpected init event");
+        } else {
+            self.register_activate_event(ops);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a vhost-user virtio-fs device, sharing a host directory with the guest.
+//!
+//! The FUSE requests of the guest are served by an external vhost-user backend (e.g.
+//! virtiofsd), which owns the shared directory.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+pub use self::device::VhostUserFs;
+use crate::devices::virtio::vhost_user::VhostUserError;
+
+/// Number of queues of the virtio-fs device: one high priority queue and one request queue.
+pub const NUM_QUEUES: u64 = 2;
+
+/// Queue size for the virtio-fs device.
+pub const QUEUE_SIZE: u16 = 256;
+
+/// Maximum length of the tag under which the guest mounts the shared directory.
+pub const FS_TAG_LEN: usize = 36;
+
+/// Vhost-user fs device error.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum VhostUserFsError {
+    /// The tag must be non-empty and at most 36 bytes long: {0}
+    InvalidTag(String),
+    /// Persistence error: {0}
+    Persist(crate::devices::virtio::persist::PersistError),
+    /// The backend no longer offers the features of the snapshot: {0:#x}
+    MissingFeatures(u64),
+    /// The backend has not completed {0} requests
+    RequestsInFlight(u16),
+    /// Vhost-user error: {0}
+    VhostUser(VhostUserError),
+    /// Error opening eventfd: {0}
+    EventFd(std::io::Error),
+    /// Error creating irqfd: {0}
+    IrqTrigger(std::io::Error),
+}
@@ -0,0 +1,220 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring fs devices.
+
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use utils::eventfd::EventFd;
+use utils::u64_to_usize;
+use vhost::vhost_user::message::VhostUserProtocolFeatures;
+
+use super::device::VhostUserFsImpl;
+use super::{VhostUserFsError, NUM_QUEUES, QUEUE_SIZE};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, LastError};
+use crate::devices::virtio::persist::VirtioDeviceState;
+use crate::devices::virtio::vhost_user::{VhostUserHandleBackend, VhostUserHandleImpl};
+use crate::devices::virtio::vhost_user_metrics::VhostUserMetricsPerDevice;
+use crate::devices::virtio::TYPE_FS;
+use crate::snapshot::Persist;
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// vhost-user fs device state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VhostUserFsState {
+    id: String,
+    tag: String,
+    socket_path: String,
+    vu_acked_protocol_features: u64,
+    config_space: Vec<u8>,
+    virtio_state: VirtioDeviceState,
+}
+
+/// Auxiliary structure for creating a device when resuming from a snapshot.
+#[derive(Debug)]
+pub struct FsConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl<T: VhostUserHandleBackend + Send + 'static> Persist<'_> for VhostUserFsImpl<T> {
+    type State = VhostUserFsState;
+    type ConstructorArgs = FsConstructorArgs;
+    type Error = VhostUserFsError;
+
+    fn save(&self) -> Self::State {
+        VhostUserFsState {
+            id: self.id.clone(),
+            tag: self.tag.clone(),
+            socket_path: self.vu_handle.socket_path.clone(),
+            vu_acked_protocol_features: self.vu_acked_protocol_features,
+            config_space: self.config_space.clone(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        // The backend must still be reachable and offer the features the guest may have
+        // negotiated, a device that cannot be reconnected fails the restore.
+        let mut vu_handle = VhostUserHandleImpl::new(&state.socket_path, NUM_QUEUES)
+            .map_err(VhostUserFsError::VhostUser)?;
+        let avail_features = state.virtio_state.avail_features;
+        let (acked_features, acked_protocol_features) = vu_handle
+            .negotiate_features(
+                avail_features,
+                VhostUserProtocolFeatures::from_bits_truncate(state.vu_acked_protocol_features),
+            )
+            .map_err(VhostUserFsError::VhostUser)?;
+        if acked_features != avail_features {
+            return Err(VhostUserFsError::MissingFeatures(
+                avail_features & !acked_features,
+            ));
+        }
+
+        let queues = state
+            .virtio_state
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_FS,
+                u64_to_usize(NUM_QUEUES),
+                QUEUE_SIZE,
+            )
+            .map_err(VhostUserFsError::Persist)?;
+        let queue_evts = [
+            EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?,
+        ];
+
+        let mut irq_trigger = IrqTrigger::new().map_err(VhostUserFsError::IrqTrigger)?;
+        irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
+
+        let mut fs = VhostUserFsImpl {
+            avail_features,
+            acked_features: state.virtio_state.acked_features,
+            config_space: state.config_space.clone(),
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VhostUserFsError::EventFd)?,
+
+            queues,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            irq_trigger,
+
+            id: state.id.clone(),
+            tag: state.tag.clone(),
+
+            vu_handle,
+            vu_acked_protocol_features: acked_protocol_features,
+            metrics: VhostUserMetricsPerDevice::alloc(format!("fs_{}", state.id)),
+            last_error: LastError::default(),
+        };
+
+        if state.virtio_state.activated {
+            // Send the features negotiated by the guest, the memory table and the vrings again.
+            fs.vu_handle
+                .set_features(fs.acked_features)
+                .map_err(VhostUserFsError::VhostUser)?;
+            let queues: Vec<_> = fs
+                .queues
+                .iter()
+                .zip(fs.queue_evts.iter())
+                .enumerate()
+                .map(|(index, (queue, queue_evt))| (index, queue, queue_evt))
+                .collect();
+            fs.vu_handle
+                .setup_backend(&constructor_args.mem, &queues, &fs.irq_trigger)
+                .map_err(VhostUserFsError::VhostUser)?;
+            fs.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(fs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::os::unix::net::UnixStream;
+
+    use vhost::vhost_user::message::VhostUserHeaderFlag;
+
+    use super::*;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::test_utils::default_mem;
+    use crate::utilities::test_utils::create_tmp_socket;
+    use crate::vmm_config::fs::FsDeviceConfig;
+
+    thread_local! {
+        // Features offered by the mock backend.
+        static BACKEND_FEATURES: Cell<u64> = Cell::new(u64::MAX);
+    }
+
+    struct MockMaster;
+
+    impl VhostUserHandleBackend for MockMaster {
+        fn from_stream(_sock: UnixStream, _max_queue_num: u64) -> Self {
+            Self
+        }
+
+        fn set_owner(&self) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+
+        fn set_hdr_flags(&self, _flags: VhostUserHeaderFlag) {}
+
+        fn get_features(&self) -> Result<u64, vhost::Error> {
+            Ok(BACKEND_FEATURES.with(Cell::get))
+        }
+
+        fn get_protocol_features(&mut self) -> Result<VhostUserProtocolFeatures, vhost::Error> {
+            Ok(VhostUserProtocolFeatures::empty())
+        }
+
+        fn set_protocol_features(
+            &mut self,
+            _features: VhostUserProtocolFeatures,
+        ) -> Result<(), vhost::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_persistence() {
+        let (_tmp_dir, tmp_socket_path) = create_tmp_socket();
+        let config = FsDeviceConfig {
+            fs_id: "fs0".to_string(),
+            tag: "shared".to_string(),
+            socket: tmp_socket_path,
+        };
+        let fs = VhostUserFsImpl::<MockMaster>::new(config.clone()).unwrap();
+        let state = fs.save();
+
+        let restored_fs = VhostUserFsImpl::<MockMaster>::restore(
+            FsConstructorArgs { mem: default_mem() },
+            &state,
+        )
+        .unwrap();
+        assert_eq!(restored_fs.config(), config);
+        assert_eq!(restored_fs.config_space, fs.config_space);
+        assert_eq!(restored_fs.avail_features(), fs.avail_features());
+        assert_eq!(restored_fs.acked_features(), fs.acked_features());
+        assert_eq!(restored_fs.queues(), fs.queues());
+        assert!(!restored_fs.is_activated());
+
+        // A backend that lost a feature offered to the guest cannot serve the restored device.
+        BACKEND_FEATURES.with(|features| features.set(0));
+        let err = VhostUserFsImpl::<MockMaster>::restore(
+            FsConstructorArgs { mem: default_mem() },
+            &state,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, VhostUserFsError::MissingFeatures(_)),
+            "{:?}",
+            err
+        );
+    }
+}
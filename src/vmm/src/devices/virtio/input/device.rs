@@ -0,0 +1,700 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
+use utils::u64_to_usize;
+
+use super::metrics::METRICS;
+use super::{EVENT_QUEUE, INPUT_NUM_QUEUES, STATUS_QUEUE};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
+use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
+use crate::devices::virtio::iovec::IoVecBufferMut;
+use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
+use crate::devices::virtio::{ActivateError, TYPE_INPUT};
+use crate::devices::DeviceError;
+use crate::logger::{error, IncMetric};
+use crate::vstate::memory::GuestMemoryMmap;
+
+/// Event type marking the end of a group of events.
+pub const EV_SYN: u16 = 0x00;
+/// Event type of key and button state changes.
+pub const EV_KEY: u16 = 0x01;
+/// Event type of relative axis moves.
+pub const EV_REL: u16 = 0x02;
+/// Event type of absolute axis moves.
+pub const EV_ABS: u16 = 0x03;
+/// Code of the `EV_SYN` event closing a group of events.
+pub const SYN_REPORT: u16 = 0x00;
+/// Maximum value of the absolute axes of a tablet. Their minimum value is 0.
+pub const ABS_MAX_VALUE: u32 = 0x7fff;
+/// Maximum number of events waiting for the guest to provide buffers on the event queue.
+pub const MAX_PENDING_EVENTS: usize = 256;
+
+// Keyboards report the codes of the first page of keys, starting at `KEY_ESC`.
+const KEY_FIRST: u16 = 0x01;
+const KEY_LAST: u16 = 0xff;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+
+// Layout of the configuration space, a `struct virtio_input_config`.
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;
+const VIRTIO_INPUT_CFG_ID_SERIAL: u8 = 0x02;
+const VIRTIO_INPUT_CFG_ID_DEVIDS: u8 = 0x03;
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;
+const VIRTIO_INPUT_CFG_ABS_INFO: u8 = 0x12;
+const CONFIG_SELECT_OFFSET: usize = 0;
+const CONFIG_SUBSEL_OFFSET: usize = 1;
+const CONFIG_SIZE_OFFSET: usize = 2;
+const CONFIG_PAYLOAD_OFFSET: usize = 8;
+const CONFIG_PAYLOAD_SIZE: usize = 128;
+const CONFIG_SPACE_SIZE: usize = CONFIG_PAYLOAD_OFFSET + CONFIG_PAYLOAD_SIZE;
+
+const BUS_VIRTUAL: u16 = 0x06;
+/// Size of a `struct virtio_input_event`.
+const INPUT_EVENT_SIZE: u32 = 8;
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum InputError {
+    /// Error while handling an Event file descriptor: {0}
+    EventFd(#[from] io::Error),
+    /// The input device is not activated yet.
+    NotActivated,
+    /// Event type {0:#x} with code {1:#x} is not supported by the input device.
+    UnsupportedEvent(u16, u16),
+    /// Too many events are waiting for the guest.
+    QueueFull,
+}
+
+/// Kind of input device, which determines the events the device reports.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputKind {
+    /// Keyboard reporting key presses and releases.
+    #[default]
+    Keyboard,
+    /// Mouse reporting button presses and relative moves.
+    Mouse,
+    /// Tablet reporting button presses and absolute positions.
+    Tablet,
+}
+
+impl InputKind {
+    fn name(self) -> &'static str {
+        match self {
+            InputKind::Keyboard => "Firecracker Virtio Keyboard",
+            InputKind::Mouse => "Firecracker Virtio Mouse",
+            InputKind::Tablet => "Firecracker Virtio Tablet",
+        }
+    }
+
+    fn product(self) -> u16 {
+        match self {
+            InputKind::Keyboard => 0x01,
+            InputKind::Mouse => 0x02,
+            InputKind::Tablet => 0x03,
+        }
+    }
+
+    /// Codes of the events of type `ev_type` the device reports.
+    fn event_codes(self, ev_type: u16) -> Vec<u16> {
+        match (self, ev_type) {
+            (InputKind::Keyboard, EV_KEY) => (KEY_FIRST..=KEY_LAST).collect(),
+            (InputKind::Mouse | InputKind::Tablet, EV_KEY) => vec![BTN_LEFT, BTN_RIGHT, BTN_MIDDLE],
+            (InputKind::Mouse, EV_REL) => vec![REL_X, REL_Y, REL_WHEEL],
+            (InputKind::Tablet, EV_ABS) => vec![ABS_X, ABS_Y],
+            _ => Vec::new(),
+        }
+    }
+
+    fn supports(self, event: &InputEvent) -> bool {
+        event.type_ == EV_SYN || self.event_codes(event.type_).contains(&event.code)
+    }
+}
+
+/// An input event, as defined by the Linux evdev interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputEvent {
+    /// Type of the event, e.g. `EV_KEY`.
+    pub type_: u16,
+    /// Code of the event, e.g. the key code for `EV_KEY` events.
+    pub code: u16,
+    /// Value of the event, e.g. 1 for a key press and 0 for a key release.
+    pub value: i32,
+}
+
+impl InputEvent {
+    /// Creates a new input event.
+    pub fn new(type_: u16, code: u16, value: i32) -> Self {
+        Self { type_, code, value }
+    }
+
+    /// Creates an event closing a group of events, which the guest handles as a whole.
+    pub fn sync() -> Self {
+        Self::new(EV_SYN, SYN_REPORT, 0)
+    }
+
+    /// Encodes the event as a `struct virtio_input_event`.
+    fn to_le_bytes(self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..2].copy_from_slice(&self.type_.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.code.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+}
+
+// Bitmap with the bits of `codes` set, as reported for `VIRTIO_INPUT_CFG_EV_BITS`.
+fn bitmap(codes: &[u16]) -> Vec<u8> {
+    let Some(max) = codes.iter().max() else {
+        return Vec::new();
+    };
+    let mut bitmap = vec![0u8; usize::from(max / 8) + 1];
+    for code in codes {
+        bitmap[usize::from(code / 8)] |= 1 << (code % 8);
+    }
+    bitmap
+}
+
+#[derive(Debug)]
+pub struct Input {
+    // VirtIO fields
+    avail_features: u64,
+    acked_features: u64,
+    activate_event: EventFd,
+
+    // Transport fields
+    device_state: DeviceState,
+    pub(crate) queues: Vec<Queue>,
+    queue_events: Vec<EventFd>,
+    irq_trigger: IrqTrigger,
+
+    // Device specific fields
+    id: String,
+    kind: InputKind,
+    // Configuration space selector written by the driver.
+    config_select: u8,
+    config_subsel: u8,
+    // Events waiting for the guest to provide buffers on the event queue.
+    pending_events: VecDeque<InputEvent>,
+    // Most recent error hit while serving the guest.
+    last_error: LastError,
+}
+
+impl Input {
+    pub fn new(id: String, kind: InputKind) -> Result<Self, InputError> {
+        let queues = vec![Queue::new(FIRECRACKER_MAX_QUEUE_SIZE); INPUT_NUM_QUEUES];
+        Self::new_with_queues(queues, id, kind)
+    }
+
+    pub fn new_with_queues(
+        queues: Vec<Queue>,
+        id: String,
+        kind: InputKind,
+    ) -> Result<Self, InputError> {
+        let activate_event = EventFd::new(libc::EFD_NONBLOCK)?;
+        let queue_events = (0..INPUT_NUM_QUEUES)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK))
+            .collect::<Result<Vec<EventFd>, io::Error>>()?;
+        let irq_trigger = IrqTrigger::new()?;
+
+        Ok(Self {
+            avail_features: 1 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_event,
+            device_state: DeviceState::Inactive,
+            queues,
+            queue_events,
+            irq_trigger,
+            id,
+            kind,
+            config_select: 0,
+            config_subsel: 0,
+            pending_events: VecDeque::new(),
+            last_error: LastError::default(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Kind of the input device.
+    pub fn kind(&self) -> InputKind {
+        self.kind
+    }
+
+    /// Queues `events` for the guest, and delivers as many of them as the guest has buffers for.
+    ///
+    /// The events are rejected as a whole if any of them is not reported by this kind of device,
+    /// or if they do not fit in the events waiting for the guest.
+    pub fn send_events(&mut self, events: &[InputEvent]) -> Result<(), InputError> {
+        if !self.is_activated() {
+            return Err(InputError::NotActivated);
+        }
+        if let Some(event) = events.iter().find(|event| !self.kind.supports(event)) {
+            return Err(InputError::UnsupportedEvent(event.type_, event.code));
+        }
+        if self.pending_events.len() + events.len() > MAX_PENDING_EVENTS {
+            METRICS.events_dropped.add(events.len() as u64);
+            return Err(InputError::QueueFull);
+        }
+
+        self.pending_events.extend(events);
+        self.process_event_queue();
+        Ok(())
+    }
+
+    fn signal_used_queue(&self) -> Result<(), DeviceError> {
+        self.irq_trigger
+            .trigger_irq(IrqType::Vring)
+            .map_err(DeviceError::FailedSignalingIrq)
+    }
+
+    fn process_event_queue(&mut self) {
+        // This is safe since we only deliver events to an activated device.
+        let mem = self.device_state.mem().unwrap();
+
+        let mut used_any = false;
+        while let Some(&event) = self.pending_events.front() {
+            let Some(desc) = self.queues[EVENT_QUEUE].pop(mem) else {
+                break;
+            };
+            let index = desc.index;
+
+            let len = match IoVecBufferMut::from_descriptor_chain(desc) {
+                Ok(mut iovec) => match iovec.write_all_volatile_at(&event.to_le_bytes(), 0) {
+                    Ok(()) => {
+                        self.pending_events.pop_front();
+                        METRICS.events_sent.inc();
+                        INPUT_EVENT_SIZE
+                    }
+                    Err(err) => {
+                        error!("input: Could not write event to guest buffer: {err}");
+                        METRICS.event_fails.inc();
+                        self.last_error
+                            .record(format!("Could not write event to guest buffer: {err}"));
+                        0
+                    }
+                },
+                Err(err) => {
+                    error!("input: Could not parse descriptor chain: {err}");
+                    METRICS.event_fails.inc();
+                    self.last_error
+                        .record(format!("Could not parse descriptor chain: {err}"));
+                    0
+                }
+            };
+
+            match self.queues[EVENT_QUEUE].add_used(mem, index, len) {
+                Ok(_) => used_any = true,
+                Err(err) => {
+                    error!("input: Could not add used descriptor to queue: {err}");
+                    METRICS.event_fails.inc();
+                    self.last_error
+                        .record(format!("Could not add used descriptor to queue: {err}"));
+                    break;
+                }
+            }
+        }
+
+        if used_any {
+            if let Err(err) = self.signal_used_queue() {
+                error!("input: {err:?}");
+                METRICS.event_fails.inc();
+                self.last_error.record(err);
+            }
+        }
+    }
+
+    fn process_status_queue(&mut self) {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+
+        // The device has no LEDs, so the status updates of the guest are only consumed.
+        let mut used_any = false;
+        while let Some(desc) = self.queues[STATUS_QUEUE].pop(mem) {
+            METRICS.status_events.inc();
+            match self.queues[STATUS_QUEUE].add_used(mem, desc.index, 0) {
+                Ok(_) => used_any = true,
+                Err(err) => {
+                    error!("input: Could not add used descriptor to queue: {err}");
+                    METRICS.event_fails.inc();
+                    self.last_error
+                        .record(format!("Could not add used descriptor to queue: {err}"));
+                    break;
+                }
+            }
+        }
+
+        if used_any {
+            if let Err(err) = self.signal_used_queue() {
+                error!("input: {err:?}");
+                METRICS.event_fails.inc();
+                self.last_error.record(err);
+            }
+        }
+    }
+
+    pub(crate) fn process_event_queue_event(&mut self) {
+        if let Err(err) = self.queues[EVENT_QUEUE].read_kicks(&self.queue_events[EVENT_QUEUE]) {
+            error!("Failed to read input event queue event: {err}");
+            METRICS.event_fails.inc();
+        } else {
+            // The guest made buffers available for the pending events.
+            self.process_event_queue();
+        }
+    }
+
+    pub(crate) fn process_status_queue_event(&mut self) {
+        if let Err(err) = self.queues[STATUS_QUEUE].read_kicks(&self.queue_events[STATUS_QUEUE]) {
+            error!("Failed to read input status queue event: {err}");
+            METRICS.event_fails.inc();
+        } else {
+            self.process_status_queue();
+        }
+    }
+
+    pub fn process_virtio_queues(&mut self) {
+        self.process_event_queue();
+        self.process_status_queue();
+    }
+
+    // Payload of the configuration space for the current selector.
+    fn config_payload(&self) -> Vec<u8> {
+        match (self.config_select, self.config_subsel) {
+            (VIRTIO_INPUT_CFG_ID_NAME, 0) => self.kind.name().as_bytes().to_vec(),
+            (VIRTIO_INPUT_CFG_ID_SERIAL, 0) => self.id.as_bytes().to_vec(),
+            (VIRTIO_INPUT_CFG_ID_DEVIDS, 0) => [BUS_VIRTUAL, 0, self.kind.product(), 1]
+                .iter()
+                .flat_map(|id| id.to_le_bytes())
+                .collect(),
+            (VIRTIO_INPUT_CFG_EV_BITS, ev_type) => bitmap(&self.kind.event_codes(ev_type.into())),
+            (VIRTIO_INPUT_CFG_ABS_INFO, axis)
+                if self.kind.event_codes(EV_ABS).contains(&axis.into()) =>
+            {
+                // min, max, fuzz, flat and res of the axis.
+                [0, ABS_MAX_VALUE, 0, 0, 0]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes())
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn config_space(&self) -> [u8; CONFIG_SPACE_SIZE] {
+        let mut config_space = [0u8; CONFIG_SPACE_SIZE];
+        let payload = self.config_payload();
+        let size = payload.len().min(CONFIG_PAYLOAD_SIZE);
+        config_space[CONFIG_SELECT_OFFSET] = self.config_select;
+        config_space[CONFIG_SUBSEL_OFFSET] = self.config_subsel;
+        config_space[CONFIG_SIZE_OFFSET] = u8::try_from(size).unwrap();
+        config_space[CONFIG_PAYLOAD_OFFSET..CONFIG_PAYLOAD_OFFSET + size]
+            .copy_from_slice(&payload[..size]);
+        config_space
+    }
+
+    pub(crate) fn config_selector(&self) -> (u8, u8) {
+        (self.config_select, self.config_subsel)
+    }
+
+    pub(crate) fn set_config_selector(&mut self, select: u8, subsel: u8) {
+        self.config_select = select;
+        self.config_subsel = subsel;
+    }
+
+    pub(crate) fn pending_events(&self) -> Vec<InputEvent> {
+        self.pending_events.iter().copied().collect()
+    }
+
+    pub(crate) fn set_pending_events(&mut self, events: &[InputEvent]) {
+        self.pending_events = events.iter().copied().collect();
+    }
+
+    pub(crate) fn set_avail_features(&mut self, features: u64) {
+        self.avail_features = features;
+    }
+
+    pub(crate) fn set_acked_features(&mut self, features: u64) {
+        self.acked_features = features;
+    }
+
+    pub(crate) fn set_irq_status(&mut self, status: u32) {
+        self.irq_trigger.irq_status = Arc::new(AtomicU32::new(status));
+    }
+
+    pub(crate) fn set_activated(&mut self, mem: GuestMemoryMmap) {
+        self.device_state = DeviceState::Activated(mem);
+    }
+
+    pub(crate) fn activate_event(&self) -> &EventFd {
+        &self.activate_event
+    }
+}
+
+impl VirtioDevice for Input {
+    fn device_type(&self) -> u32 {
+        TYPE_INPUT
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_events
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.irq_trigger.irq_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicU32> {
+        self.irq_trigger.irq_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        let config_space = self.config_space();
+        if let Some(config_space_bytes) = config_space.get(u64_to_usize(offset)..) {
+            let len = config_space_bytes.len().min(data.len());
+            data[..len].copy_from_slice(&config_space_bytes[..len]);
+        } else {
+            error!("Failed to read config space");
+            METRICS.cfg_fails.inc();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The driver only owns `select` and `subsel`, the rest is set by the device.
+        let mut selector = [self.config_select, self.config_subsel];
+        let start = u64_to_usize(offset);
+        let Some(dst) = start
+            .checked_add(data.len())
+            .and_then(|end| selector.get_mut(start..end))
+        else {
+            error!("Failed to write config space");
+            METRICS.cfg_fails.inc();
+            return;
+        };
+
+        dst.copy_from_slice(data);
+        self.set_config_selector(selector[0], selector[1]);
+    }
+
+    fn is_activated(&self) -> bool {
+        self.device_state.is_activated()
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        self.activate_event.write(1).map_err(|err| {
+            error!("input: Cannot write to activate_evt: {err}");
+            METRICS.activate_fails.inc();
+            ActivateError::BadActivate
+        })?;
+        self.device_state = DeviceState::Activated(mem);
+        Ok(())
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Wrapping;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::devices::virtio::queue::VIRTQ_DESC_F_WRITE;
+    use crate::devices::virtio::test_utils::test::{
+        create_virtio_mem, VirtioTestDevice, VirtioTestHelper,
+    };
+    use crate::vstate::memory::{Bytes, GuestAddress};
+
+    impl VirtioTestDevice for Input {
+        fn set_queues(&mut self, queues: Vec<Queue>) {
+            self.queues = queues;
+        }
+
+        fn num_queues() -> usize {
+            INPUT_NUM_QUEUES
+        }
+    }
+
+    fn read_payload(input: &mut Input, select: u8, subsel: u16) -> Vec<u8> {
+        let subsel = u8::try_from(subsel).unwrap();
+        input.write_config(0, &[select, subsel]);
+        let mut config_space = [0u8; CONFIG_SPACE_SIZE];
+        input.read_config(0, &mut config_space);
+        assert_eq!(config_space[..2], [select, subsel]);
+        let size = usize::from(config_space[CONFIG_SIZE_OFFSET]);
+        config_space[CONFIG_PAYLOAD_OFFSET..CONFIG_PAYLOAD_OFFSET + size].to_vec()
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut keyboard = Input::new("kbd0".to_string(), InputKind::Keyboard).unwrap();
+        assert_eq!(
+            read_payload(&mut keyboard, VIRTIO_INPUT_CFG_ID_NAME, 0),
+            b"Firecracker Virtio Keyboard"
+        );
+        assert_eq!(
+            read_payload(&mut keyboard, VIRTIO_INPUT_CFG_ID_SERIAL, 0),
+            b"kbd0"
+        );
+        assert_eq!(
+            read_payload(&mut keyboard, VIRTIO_INPUT_CFG_ID_DEVIDS, 0),
+            [0x06, 0, 0, 0, 0x01, 0, 0x01, 0]
+        );
+        // Keys 1 to 255.
+        let keys = read_payload(&mut keyboard, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY);
+        assert_eq!(keys.len(), 32);
+        assert_eq!(keys[0], 0xfe);
+        assert!(keys[1..].iter().all(|byte| *byte == 0xff));
+        assert!(read_payload(&mut keyboard, VIRTIO_INPUT_CFG_EV_BITS, EV_REL).is_empty());
+        assert!(read_payload(&mut keyboard, VIRTIO_INPUT_CFG_ABS_INFO, ABS_X).is_empty());
+        // Unknown selectors have no payload.
+        assert!(read_payload(&mut keyboard, 0x42, 0).is_empty());
+
+        let mut mouse = Input::new("mouse0".to_string(), InputKind::Mouse).unwrap();
+        let buttons = read_payload(&mut mouse, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY);
+        assert_eq!(buttons.len(), 35);
+        assert_eq!(buttons[34], 0b111);
+        assert_eq!(
+            read_payload(&mut mouse, VIRTIO_INPUT_CFG_EV_BITS, EV_REL),
+            [0b11, 0b1]
+        );
+
+        let mut tablet = Input::new("tablet0".to_string(), InputKind::Tablet).unwrap();
+        assert_eq!(
+            read_payload(&mut tablet, VIRTIO_INPUT_CFG_EV_BITS, EV_ABS),
+            [0b11]
+        );
+        let abs_info = read_payload(&mut tablet, VIRTIO_INPUT_CFG_ABS_INFO, ABS_Y);
+        assert_eq!(abs_info.len(), 20);
+        assert_eq!(abs_info[4..8], ABS_MAX_VALUE.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_config() {
+        let mut input = Input::new("kbd0".to_string(), InputKind::Keyboard).unwrap();
+        input.write_config(1, &[0x01]);
+        input.write_config(0, &[VIRTIO_INPUT_CFG_EV_BITS]);
+        assert_eq!(input.config_selector(), (VIRTIO_INPUT_CFG_EV_BITS, 0x01));
+
+        // The rest of the configuration space is read-only.
+        let cfg_fails = METRICS.cfg_fails.count();
+        input.write_config(2, &[0xff]);
+        input.write_config(1, &[0, 0]);
+        assert_eq!(METRICS.cfg_fails.count(), cfg_fails + 2);
+        assert_eq!(input.config_selector(), (VIRTIO_INPUT_CFG_EV_BITS, 0x01));
+    }
+
+    #[test]
+    fn test_send_key_event() {
+        let mem = create_virtio_mem();
+        let input = Input::new("kbd0".to_string(), InputKind::Keyboard).unwrap();
+        let mut th = VirtioTestHelper::<Input>::new(&mem, input);
+
+        // Events cannot be sent before the guest drives the device.
+        assert!(matches!(
+            th.device().send_events(&[InputEvent::sync()]),
+            Err(InputError::NotActivated)
+        ));
+
+        th.activate_device(&mem);
+
+        // A key press of KEY_A.
+        let events = [InputEvent::new(EV_KEY, 30, 1), InputEvent::sync()];
+        th.add_desc_chain(EVENT_QUEUE, 0, &[(0, 8, VIRTQ_DESC_F_WRITE)]);
+        th.emulate_for_msec(100).unwrap();
+        check_metric_after_block!(
+            METRICS.events_sent,
+            1,
+            th.device().send_events(&events).unwrap()
+        );
+        let addr = GuestAddress(th.data_address());
+        let event: [u8; 8] = mem.read_obj(addr).unwrap();
+        assert_eq!(event, [0x01, 0, 30, 0, 1, 0, 0, 0]);
+        assert_eq!(th.device().queues[EVENT_QUEUE].next_used, Wrapping(1));
+        // The sync event waits for the next buffer of the guest.
+        assert_eq!(th.device().pending_events(), [InputEvent::sync()]);
+
+        th.add_desc_chain(EVENT_QUEUE, 0x100, &[(1, 8, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(METRICS.events_sent, 1, th.emulate_for_msec(100));
+        let event: [u8; 8] = mem.read_obj(addr.unchecked_add(0x100)).unwrap();
+        assert_eq!(event, [0; 8]);
+        assert_eq!(th.device().queues[EVENT_QUEUE].next_used, Wrapping(2));
+        assert!(th.device().pending_events().is_empty());
+
+        // Events the keyboard does not report are rejected.
+        assert!(matches!(
+            th.device()
+                .send_events(&[InputEvent::new(EV_REL, REL_X, 1)]),
+            Err(InputError::UnsupportedEvent(EV_REL, REL_X))
+        ));
+
+        // So are the events that do not fit with the ones waiting for the guest.
+        let events = vec![InputEvent::sync(); MAX_PENDING_EVENTS + 1];
+        check_metric_after_block!(
+            METRICS.events_dropped,
+            u64::try_from(MAX_PENDING_EVENTS).unwrap() + 1,
+            th.device().send_events(&events).unwrap_err()
+        );
+        assert!(th.device().pending_events().is_empty());
+
+        // A read-only buffer cannot hold an event, and the failure is recorded.
+        assert!(th.device().last_error().is_none());
+        th.add_desc_chain(EVENT_QUEUE, 0x200, &[(2, 8, 0)]);
+        check_metric_after_block!(
+            METRICS.event_fails,
+            1,
+            th.device().send_events(&[InputEvent::sync()]).unwrap()
+        );
+        let (_, err) = th.device().last_error().unwrap();
+        assert!(err.starts_with("Could not parse descriptor chain"));
+        assert_eq!(th.device().pending_events(), [InputEvent::sync()]);
+    }
+
+    #[test]
+    fn test_status_queue() {
+        let mem = create_virtio_mem();
+        let input = Input::new("kbd0".to_string(), InputKind::Keyboard).unwrap();
+        let mut th = VirtioTestHelper::<Input>::new(&mem, input);
+        th.activate_device(&mem);
+
+        // The guest turns on a LED.
+        th.add_desc_chain(STATUS_QUEUE, 0, &[(0, 8, 0)]);
+        check_metric_after_block!(METRICS.status_events, 1, th.emulate_for_msec(100));
+        assert_eq!(th.device().queues[STATUS_QUEUE].next_used, Wrapping(1));
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the metrics system for input devices.
+//!
+//! # Metrics format
+//! The metrics are flushed in JSON when requested by vmm::logger::metrics::METRICS.write().
+//!
+//! ## JSON example with metrics:
+//! ```json
+//!  "input": {
+//!     "activate_fails": "SharedIncMetric",
+//!     "cfg_fails": "SharedIncMetric",
+//!     "event_fails": "SharedIncMetric",
+//!     ...
+//!  }
+//! }
+//! ```
+//! Each `input` field in the example above is a serializable `InputDeviceMetrics` structure
+//! collecting metrics such as `activate_fails`, `events_sent` etc. for the input devices.
+//! There are no per device metrics, `input` represents the aggregate metrics of all the input
+//! devices.
+//!
+//! The system implements 1 type of metrics:
+//! * Shared Incremental Metrics (SharedIncMetrics) - dedicated for the metrics which need a counter
+//! (i.e the number of times an API request failed). These metrics are reset upon flush.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+
+use crate::logger::SharedIncMetric;
+
+/// Stores aggregated input metrics
+pub(super) static METRICS: InputDeviceMetrics = InputDeviceMetrics::new();
+
+/// Called by METRICS.flush(), this function facilitates serialization of input device metrics.
+pub fn flush_metrics<S: Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_map(Some(1))?;
+    seq.serialize_entry("input", &METRICS)?;
+    seq.end()
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct InputDeviceMetrics {
+    /// Number of device activation failures
+    pub activate_fails: SharedIncMetric,
+    /// Number of failures in reading or writing the configuration space
+    pub cfg_fails: SharedIncMetric,
+    /// Number of queue event handling failures
+    pub event_fails: SharedIncMetric,
+    /// Number of input events delivered to the guest
+    pub events_sent: SharedIncMetric,
+    /// Number of input events dropped because too many were waiting for the guest
+    pub events_dropped: SharedIncMetric,
+    /// Number of status updates received from the guest
+    pub status_events: SharedIncMetric,
+}
+impl InputDeviceMetrics {
+    /// Const default construction.
+    const fn new() -> Self {
+        Self {
+            activate_fails: SharedIncMetric::new(),
+            cfg_fails: SharedIncMetric::new(),
+            event_fails: SharedIncMetric::new(),
+            events_sent: SharedIncMetric::new(),
+            events_dropped: SharedIncMetric::new(),
+            status_events: SharedIncMetric::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::logger::IncMetric;
+
+    #[test]
+    fn test_input_dev_metrics() {
+        let input_metrics: InputDeviceMetrics = InputDeviceMetrics::new();
+        let input_metrics_local: String = serde_json::to_string(&input_metrics).unwrap();
+        // the 1st serialize flushes the metrics and resets values to 0 so that
+        // we can compare the values with local metrics.
+        serde_json::to_string(&METRICS).unwrap();
+        let input_metrics_global: String = serde_json::to_string(&METRICS).unwrap();
+        assert_eq!(input_metrics_local, input_metrics_global);
+        input_metrics.events_sent.inc();
+        assert_eq!(input_metrics.events_sent.count(), 1);
+    }
+}
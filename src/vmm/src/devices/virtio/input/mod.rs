@@ -0,0 +1,16 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod device;
+mod event_handler;
+pub mod metrics;
+pub mod persist;
+
+pub use self::device::{Input, InputError, InputEvent, InputKind};
+
+pub(crate) const INPUT_NUM_QUEUES: usize = 2;
+
+/// Queue on which the device delivers input events to the guest.
+pub(crate) const EVENT_QUEUE: usize = 0;
+/// Queue on which the guest sends status updates, e.g. the state of the keyboard LEDs.
+pub(crate) const STATUS_QUEUE: usize = 1;
@@ -0,0 +1,124 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring input devices.
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::input::{Input, InputError, InputEvent, InputKind, INPUT_NUM_QUEUES};
+use crate::devices::virtio::persist::{PersistError as VirtioStateError, VirtioDeviceState};
+use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
+use crate::devices::virtio::TYPE_INPUT;
+use crate::snapshot::Persist;
+use crate::vstate::memory::GuestMemoryMmap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputState {
+    id: String,
+    kind: InputKind,
+    config_select: u8,
+    config_subsel: u8,
+    pending_events: Vec<InputEvent>,
+    virtio_state: VirtioDeviceState,
+}
+
+#[derive(Debug)]
+pub struct InputConstructorArgs(GuestMemoryMmap);
+
+impl InputConstructorArgs {
+    pub fn new(mem: GuestMemoryMmap) -> Self {
+        Self(mem)
+    }
+}
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum InputPersistError {
+    /// Create input: {0}
+    CreateInput(#[from] InputError),
+    /// Virtio state: {0}
+    VirtioState(#[from] VirtioStateError),
+}
+
+impl Persist<'_> for Input {
+    type State = InputState;
+    type ConstructorArgs = InputConstructorArgs;
+    type Error = InputPersistError;
+
+    fn save(&self) -> Self::State {
+        let (config_select, config_subsel) = self.config_selector();
+        InputState {
+            id: self.id().to_string(),
+            kind: self.kind(),
+            config_select,
+            config_subsel,
+            pending_events: self.pending_events(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let queues = state.virtio_state.build_queues_checked(
+            &constructor_args.0,
+            TYPE_INPUT,
+            INPUT_NUM_QUEUES,
+            FIRECRACKER_MAX_QUEUE_SIZE,
+        )?;
+
+        let mut input = Input::new_with_queues(queues, state.id.clone(), state.kind)?;
+        input.set_avail_features(state.virtio_state.avail_features);
+        input.set_acked_features(state.virtio_state.acked_features);
+        input.set_irq_status(state.virtio_state.interrupt_status);
+        input.set_config_selector(state.config_select, state.config_subsel);
+        input.set_pending_events(&state.pending_events);
+        if state.virtio_state.activated {
+            input.set_activated(constructor_args.0);
+        }
+
+        Ok(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::devices::virtio::device::VirtioDevice;
+    use crate::devices::virtio::input::device::EV_KEY;
+    use crate::devices::virtio::test_utils::test::create_virtio_mem;
+    use crate::snapshot::Snapshot;
+
+    #[test]
+    fn test_persistence() {
+        let mut mem = vec![0u8; 4096];
+        let mut input = Input::new("tablet0".to_string(), InputKind::Tablet).unwrap();
+        input.set_config_selector(0x12, 0x01);
+        let events = [InputEvent::new(EV_KEY, 0x110, 1), InputEvent::sync()];
+        input.set_pending_events(&events);
+
+        Snapshot::serialize(&mut mem.as_mut_slice(), &input.save()).unwrap();
+
+        let guest_mem = create_virtio_mem();
+        let restored = Input::restore(
+            InputConstructorArgs(guest_mem),
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.device_type(), TYPE_INPUT);
+        assert_eq!(restored.id(), "tablet0");
+        assert_eq!(restored.kind(), InputKind::Tablet);
+        assert_eq!(restored.config_selector(), (0x12, 0x01));
+        assert_eq!(restored.pending_events(), events);
+        assert_eq!(restored.is_activated(), input.is_activated());
+        assert_eq!(restored.avail_features(), input.avail_features());
+        assert_eq!(restored.acked_features(), input.acked_features());
+        assert_eq!(
+            restored.interrupt_status().load(Ordering::Relaxed),
+            input.interrupt_status().load(Ordering::Relaxed)
+        );
+    }
+}
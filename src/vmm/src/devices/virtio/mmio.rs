@@ -8,13 +8,15 @@
 use std::fmt::Debug;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use utils::byte_order;
+use utils::time::{get_time_us, ClockType, TimestampUs};
 
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::device_status;
 use crate::devices::virtio::queue::Queue;
-use crate::logger::warn;
+use crate::logger::{info, warn, IncMetric, METRICS};
 use crate::vstate::memory::{GuestAddress, GuestMemoryMmap};
 
 // TODO crosvm uses 0 here, but IIRC virtio specified some other vendor id that should be used
@@ -58,6 +60,8 @@ pub struct MmioTransport {
     mem: GuestMemoryMmap,
     pub(crate) interrupt_status: Arc<AtomicU32>,
     pub is_vhost_user: bool,
+    // When the device was last activated by the driver.
+    pub(crate) activated_at: Option<TimestampUs>,
 }
 
 impl MmioTransport {
@@ -79,6 +83,23 @@ impl MmioTransport {
             mem,
             interrupt_status,
             is_vhost_user,
+            activated_at: None,
+        }
+    }
+
+    /// Time elapsed since the driver activated the device, if it is active.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.activated_at.as_ref().map(|activated_at| {
+            Duration::from_micros(
+                get_time_us(ClockType::Monotonic).saturating_sub(activated_at.time_us),
+            )
+        })
+    }
+
+    /// Counts the uptime of an active device from now on, as if it had just been activated.
+    pub fn restart_uptime(&mut self) {
+        if self.activated_at.is_some() {
+            self.activated_at = Some(TimestampUs::default());
         }
     }
 
@@ -92,6 +113,11 @@ impl MmioTransport {
         self.device.clone()
     }
 
+    /// Gets the guest memory accessed by the device.
+    pub fn mem(&self) -> &GuestMemoryMmap {
+        &self.mem
+    }
+
     fn check_device_status(&self, set: u32, clr: u32) -> bool {
         self.device_status & (set | clr) == set
     }
@@ -154,6 +180,7 @@ impl MmioTransport {
         self.queue_select = 0;
         self.interrupt_status.store(0, Ordering::SeqCst);
         self.device_status = device_status::INIT;
+        self.activated_at = None;
         // . Keep interrupt_evt and queue_evts as is. There may be pending notifications in those
         //   eventfds, but nothing will happen other than supurious wakeups.
         // . Do not reset config_generation and keep it monotonically increasing
@@ -182,6 +209,16 @@ impl MmioTransport {
             }
             FEATURES_OK if self.device_status == (ACKNOWLEDGE | DRIVER) => {
                 self.device_status = status;
+                let locked_device = self.locked_device();
+                let rejected_features = locked_device.rejected_features();
+                if rejected_features != 0 {
+                    info!(
+                        "The driver of the virtio device type {} did not acknowledge the offered \
+                         features {:#x}",
+                        locked_device.device_type(),
+                        rejected_features
+                    );
+                }
             }
             DRIVER_OK if self.device_status == (ACKNOWLEDGE | DRIVER | FEATURES_OK) => {
                 self.device_status = status;
@@ -190,6 +227,7 @@ impl MmioTransport {
                     self.locked_device()
                         .activate(self.mem.clone())
                         .expect("Failed to activate device");
+                    self.activated_at = Some(TimestampUs::default());
                 }
             }
             _ if (status & FAILED) != 0 => {
@@ -225,10 +263,16 @@ impl MmioTransport {
     }
 }
 
+/// Whether an access is valid for the registers before the config space. The driver must only
+/// use 32 bit wide and aligned accesses for them.
+fn is_register_access(offset: u64, len: usize) -> bool {
+    len == 4 && offset % 4 == 0
+}
+
 impl MmioTransport {
     pub fn bus_read(&mut self, offset: u64, data: &mut [u8]) {
         match offset {
-            0x00..=0xff if data.len() == 4 => {
+            0x00..=0xff if is_register_access(offset, data.len()) => {
                 let v = match offset {
                     0x0 => MMIO_MAGIC_VALUE,
                     0x04 => MMIO_VERSION,
@@ -279,6 +323,15 @@ impl MmioTransport {
                 byte_order::write_le_u32(data, v);
             }
             0x100..=0xfff => self.locked_device().read_config(offset - 0x100, data),
+            0x00..=0xff => {
+                // The data is left untouched.
+                warn!(
+                    "invalid virtio mmio register read width or alignment: 0x{:x}:0x{:x}",
+                    offset,
+                    data.len()
+                );
+                METRICS.vmm.mmio_invalid_accesses.inc();
+            }
             _ => {
                 warn!(
                     "invalid virtio mmio read: 0x{:x}:0x{:x}",
@@ -299,7 +352,7 @@ impl MmioTransport {
         }
 
         match offset {
-            0x00..=0xff if data.len() == 4 => {
+            0x00..=0xff if is_register_access(offset, data.len()) => {
                 let v = byte_order::read_le_u32(data);
                 match offset {
                     0x14 => self.features_select = v,
@@ -345,6 +398,14 @@ impl MmioTransport {
                     warn!("can not write to device config data area before driver is ready");
                 }
             }
+            0x00..=0xff => {
+                warn!(
+                    "invalid virtio mmio register write width or alignment: 0x{:x}:0x{:x}",
+                    offset,
+                    data.len()
+                );
+                METRICS.vmm.mmio_invalid_accesses.inc();
+            }
             _ => {
                 warn!(
                     "invalid virtio mmio write: 0x{:x}:0x{:x}",
@@ -584,6 +645,31 @@ pub(crate) mod tests {
         assert_eq!(buf[..], buf_copy[..]);
     }
 
+    #[test]
+    fn test_bus_device_access_width() {
+        let m = single_region_mem(0x1000);
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())), false);
+        let invalid_accesses = METRICS.vmm.mmio_invalid_accesses.count();
+
+        // A 2 byte read of a 4 byte register is rejected.
+        let mut buf = [0xaa; 2];
+        d.bus_read(0, &mut buf);
+        assert_eq!(buf, [0xaa; 2]);
+
+        // So is a misaligned 4 byte read.
+        let mut buf = [0; 4];
+        d.bus_read(0x02, &mut buf);
+        assert_eq!(buf, [0; 4]);
+
+        // A 2 byte write to a 4 byte register is ignored.
+        d.bus_write(0x30, &[1, 0]);
+        assert_eq!(d.queue_select, 0);
+        d.bus_write(0x30, &[1, 0, 0, 0]);
+        assert_eq!(d.queue_select, 1);
+
+        assert!(METRICS.vmm.mmio_invalid_accesses.count() >= invalid_accesses + 3);
+    }
+
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn test_bus_device_write() {
@@ -886,6 +972,26 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_uptime() {
+        let m = single_region_mem(0x1000);
+        let mut d = MmioTransport::new(m, Arc::new(Mutex::new(DummyDevice::new())), false);
+        assert_eq!(d.uptime(), None);
+        // Inactive devices have no uptime to restart.
+        d.restart_uptime();
+        assert_eq!(d.uptime(), None);
+
+        activate_device(&mut d);
+        std::thread::sleep(Duration::from_millis(10));
+        let uptime = d.uptime().unwrap();
+        assert!(uptime >= Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(d.uptime().unwrap() > uptime);
+
+        d.restart_uptime();
+        assert!(d.uptime().unwrap() < uptime);
+    }
+
     #[test]
     fn test_get_avail_features() {
         let dummy_dev = DummyDevice::new();
@@ -13,7 +13,9 @@ use std::io::Error as IOError;
 pub mod balloon;
 pub mod block;
 pub mod device;
+pub mod fs;
 pub mod gen;
+pub mod input;
 pub mod iovec;
 pub mod mmio;
 pub mod net;
@@ -52,6 +54,10 @@ pub const TYPE_BLOCK: u32 = 2;
 pub const TYPE_RNG: u32 = 4;
 /// Virtio balloon device ID.
 pub const TYPE_BALLOON: u32 = 5;
+/// Virtio input device ID.
+pub const TYPE_INPUT: u32 = 18;
+/// Virtio fs device ID.
+pub const TYPE_FS: u32 = 26;
 
 /// Offset from the base MMIO address of a virtio device used by the guest to notify the device of
 /// queue events.
@@ -68,6 +74,32 @@ pub enum ActivateError {
     VhostUser(vhost_user::VhostUserError),
 }
 
+/// Errors triggered when quiescing a VirtioDevice.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum QuiesceError {
+    /// The device cannot be quiesced.
+    NotSupported,
+    /// Timed out with {0} requests still in flight.
+    Timeout(usize),
+    /// Failed to flush the backing store: {0}
+    Flush(String),
+}
+
+/// Errors triggered when a VirtioDevice fails its self-test.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum SelfTestError {
+    /// Cannot read the backing file: {0}
+    Read(std::io::Error),
+    /// Cannot write the backing file: {0}
+    Write(std::io::Error),
+    /// The tap device rejected a loopback frame: {0}
+    Tap(std::io::Error),
+    /// The entropy source yielded no bytes: {0}
+    Entropy(String),
+    /// The host socket is not bound: {0}
+    Bind(std::io::Error),
+}
+
 /// Trait that helps in upcasting an object to Any
 pub trait AsAny {
     /// Return the immutable any encapsulated object.
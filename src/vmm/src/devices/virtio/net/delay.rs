@@ -0,0 +1,272 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Latency and jitter injection for the frames transmitted by a network device.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::rand::xor_pseudo_rng_u32;
+
+/// Maximum latency, and maximum jitter, of a frame, in milliseconds.
+pub const MAX_DELAY_MS: u64 = 60_000;
+/// Maximum number of frames held back, after which the TX queue is no longer processed.
+pub const MAX_DELAYED_FRAMES: usize = 4096;
+/// Maximum total size, in bytes, of the frames held back, after which the TX queue is no longer
+/// processed.
+pub const MAX_DELAYED_BYTES: usize = 16 << 20;
+
+/// Delay injected on the frames transmitted by a network device.
+///
+/// Each frame is held back for `latency_ms` milliseconds, plus a random jitter of up to
+/// `jitter_ms` milliseconds, before being written to the tap. Frames are never reordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetDelayConfig {
+    /// Base delay of a frame, in milliseconds.
+    pub latency_ms: u64,
+    /// Maximum random delay added to the base delay of a frame, in milliseconds.
+    #[serde(default)]
+    pub jitter_ms: u64,
+}
+
+/// Errors associated with the delay injection of a network device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum NetDelayError {
+    /// Delay injection requires a non-zero latency or jitter.
+    NoDelay,
+    /// Delay of {0} ms is above the maximum of {1} ms.
+    DelayTooLarge(u64, u64),
+    /// Cannot create the delay timer: {0}
+    Timer(std::io::Error),
+}
+
+/// Holds back transmitted frames according to a [`NetDelayConfig`].
+///
+/// The queue holds at most [`MAX_DELAYED_FRAMES`] frames and [`MAX_DELAYED_BYTES`] bytes,
+/// beyond which the device stops taking frames from the guest until some are written out.
+/// The held back frames are not saved in snapshots, they are dropped instead.
+pub struct FrameDelayQueue {
+    config: NetDelayConfig,
+    timer: TimerFd,
+    frames: VecDeque<(Instant, Vec<u8>)>,
+    // Total size of the held back frames.
+    bytes: usize,
+}
+
+// TODO Use `#[derive(Debug)]` when a new release of
+// [rust-timerfd](https://github.com/main--/rust-timerfd) is published that includes
+// https://github.com/main--/rust-timerfd/pull/12.
+impl fmt::Debug for FrameDelayQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameDelayQueue")
+            .field("config", &self.config)
+            .field("frames", &self.frames.len())
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+impl FrameDelayQueue {
+    /// Creates a new delay queue from the given config.
+    pub fn new(config: NetDelayConfig) -> Result<Self, NetDelayError> {
+        if config.latency_ms == 0 && config.jitter_ms == 0 {
+            return Err(NetDelayError::NoDelay);
+        }
+        if let Some(delay_ms) = [config.latency_ms, config.jitter_ms]
+            .into_iter()
+            .find(|delay_ms| *delay_ms > MAX_DELAY_MS)
+        {
+            return Err(NetDelayError::DelayTooLarge(delay_ms, MAX_DELAY_MS));
+        }
+
+        Ok(Self {
+            config,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true)
+                .map_err(NetDelayError::Timer)?,
+            frames: VecDeque::new(),
+            bytes: 0,
+        })
+    }
+
+    /// Returns the config of this delay queue.
+    pub fn config(&self) -> NetDelayConfig {
+        self.config
+    }
+
+    /// Number of frames held back.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if no frame is held back.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Total size, in bytes, of the frames held back.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns `true` if no more frames should be held back until some are written out.
+    pub fn is_full(&self) -> bool {
+        self.frames.len() >= MAX_DELAYED_FRAMES || self.bytes >= MAX_DELAYED_BYTES
+    }
+
+    /// Holds back a frame until its delay elapses.
+    ///
+    /// The caller stops pushing frames once the queue [is full](Self::is_full), so a queue holds
+    /// at most one frame more than the limits.
+    pub fn push(&mut self, frame: Vec<u8>) {
+        let jitter_ms = u64::from(xor_pseudo_rng_u32()) % self.config.jitter_ms.saturating_add(1);
+        let delay = Duration::from_millis(self.config.latency_ms.saturating_add(jitter_ms));
+        let now = Instant::now();
+        // The delays are bounded by `MAX_DELAY_MS`, so the deadline is representable.
+        let mut deadline = now.checked_add(delay).unwrap_or(now);
+        // A frame is never delivered before the frames transmitted earlier.
+        if let Some((last_deadline, _)) = self.frames.back() {
+            deadline = deadline.max(*last_deadline);
+        }
+
+        self.bytes += frame.len();
+        self.frames.push_back((deadline, frame));
+        if self.frames.len() == 1 {
+            self.arm_timer();
+        }
+    }
+
+    /// Returns the next frame whose delay elapsed, if any.
+    pub fn pop_due(&mut self) -> Option<Vec<u8>> {
+        match self.frames.front() {
+            Some((deadline, _)) if *deadline <= Instant::now() => {
+                let (_, frame) = self.frames.pop_front()?;
+                self.bytes -= frame.len();
+                Some(frame)
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a timer expiration. The timer is re-armed for the remaining frames.
+    pub fn on_timer(&mut self) {
+        self.timer.read();
+    }
+
+    /// Arms the timer for the next held back frame, if any.
+    pub fn arm_timer(&mut self) {
+        let state = match self.frames.front() {
+            // A zero duration would disarm the timer.
+            Some((deadline, _)) => TimerState::Oneshot(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_nanos(1)),
+            ),
+            None => TimerState::Disarmed,
+        };
+        self.timer.set_state(state, SetTimeFlags::Default);
+    }
+}
+
+impl AsRawFd for FrameDelayQueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_delay() {
+        let err = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: 0,
+            jitter_ms: 0,
+        })
+        .unwrap_err();
+        assert!(matches!(err, NetDelayError::NoDelay));
+    }
+
+    #[test]
+    fn test_delay_too_large() {
+        let err = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: MAX_DELAY_MS + 1,
+            jitter_ms: 0,
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            NetDelayError::DelayTooLarge(delay, MAX_DELAY_MS) if delay == MAX_DELAY_MS + 1
+        ));
+        let err = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: 0,
+            jitter_ms: u64::MAX,
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            NetDelayError::DelayTooLarge(delay, MAX_DELAY_MS) if delay == u64::MAX
+        ));
+
+        // The largest delays are accepted and can be pushed.
+        let mut queue = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: MAX_DELAY_MS,
+            jitter_ms: MAX_DELAY_MS,
+        })
+        .unwrap();
+        queue.push(vec![0]);
+        assert!(queue.pop_due().is_none());
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let mut queue = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: 1000,
+            jitter_ms: 0,
+        })
+        .unwrap();
+        for _ in 0..MAX_DELAYED_FRAMES - 1 {
+            queue.push(vec![0]);
+        }
+        assert!(!queue.is_full());
+        queue.push(vec![0]);
+        assert!(queue.is_full());
+
+        let mut queue = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: 1000,
+            jitter_ms: 0,
+        })
+        .unwrap();
+        queue.push(vec![0; MAX_DELAYED_BYTES - 1]);
+        assert!(!queue.is_full());
+        queue.push(vec![0]);
+        assert!(queue.is_full());
+    }
+
+    #[test]
+    fn test_frame_order() {
+        let mut queue = FrameDelayQueue::new(NetDelayConfig {
+            latency_ms: 10,
+            jitter_ms: 20,
+        })
+        .unwrap();
+        for i in 0..10u8 {
+            queue.push(vec![i]);
+        }
+        assert_eq!(queue.len(), 10);
+        assert_eq!(queue.bytes(), 10);
+        assert!(queue.pop_due().is_none());
+
+        std::thread::sleep(Duration::from_millis(30));
+        for i in 0..10u8 {
+            assert_eq!(queue.pop_due().unwrap(), vec![i]);
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.bytes(), 0);
+    }
+}
@@ -6,7 +6,7 @@
 // found in the THIRD-PARTY file.
 
 #[cfg(not(test))]
-use std::io::Read;
+use std::io::{Read, Write};
 use std::mem;
 use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicU32;
@@ -16,10 +16,11 @@ use libc::EAGAIN;
 use log::{error, warn};
 use utils::eventfd::EventFd;
 use utils::net::mac::MacAddr;
+use utils::time::TimestampUs;
 use utils::u64_to_usize;
 use vm_memory::GuestMemoryError;
 
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
 use crate::devices::virtio::gen::virtio_blk::VIRTIO_F_VERSION_1;
 use crate::devices::virtio::gen::virtio_net::{
     virtio_net_hdr_v1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
@@ -27,24 +28,29 @@ use crate::devices::virtio::gen::virtio_net::{
 };
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use crate::devices::virtio::iovec::IoVecBuffer;
+use crate::devices::virtio::net::delay::{FrameDelayQueue, NetDelayConfig};
+use crate::devices::virtio::net::flows::{FlowKey, FlowStats, FlowTable};
 use crate::devices::virtio::net::metrics::{NetDeviceMetrics, NetMetricsPerDevice};
 use crate::devices::virtio::net::tap::Tap;
 use crate::devices::virtio::net::{
     gen, NetError, NetQueue, MAX_BUFFER_SIZE, NET_QUEUE_SIZES, RX_INDEX, TX_INDEX,
 };
 use crate::devices::virtio::queue::{DescriptorChain, Queue};
-use crate::devices::virtio::{ActivateError, TYPE_NET};
+use crate::devices::virtio::{ActivateError, SelfTestError, TYPE_NET};
 use crate::devices::{report_net_event_fail, DeviceError};
 use crate::dumbo::pdu::arp::ETH_IPV4_FRAME_LEN;
-use crate::dumbo::pdu::ethernet::{EthernetFrame, PAYLOAD_OFFSET};
+use crate::dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_LOOPBACK, PAYLOAD_OFFSET};
 use crate::logger::{IncMetric, METRICS};
 use crate::mmds::data_store::Mmds;
 use crate::mmds::ns::MmdsNetworkStack;
-use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use crate::rate_limiter::{BucketUpdate, RateLimiter, RateLimiterBudgets, TokenType};
 use crate::vstate::memory::{ByteValued, Bytes, GuestMemoryMmap};
 
 const FRAME_HEADER_MAX_LEN: usize = PAYLOAD_OFFSET + ETH_IPV4_FRAME_LEN;
 
+// Minimum length of an Ethernet frame, without the frame check sequence.
+const ETH_MIN_FRAME_LEN: usize = 60;
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 enum FrontendError {
     /// Add user.
@@ -142,6 +148,16 @@ pub struct Net {
     /// Only if MMDS transport has been associated with it.
     pub mmds_ns: Option<MmdsNetworkStack>,
     pub(crate) metrics: Arc<NetDeviceMetrics>,
+    /// Whether the device processes its queues.
+    pub(crate) enabled: bool,
+    /// Frames transmitted by the guest and held back by delay injection.
+    pub(crate) tx_delay: Option<FrameDelayQueue>,
+    /// Per-flow statistics of the traffic, if enabled.
+    pub(crate) flow_stats: Option<FlowTable>,
+    /// Cap on the host memory held on behalf of the guest, if any.
+    pub(crate) host_memory_limit: Option<usize>,
+    /// Most recent error hit while processing the queues or the tap.
+    pub(crate) last_error: LastError,
 }
 
 impl Net {
@@ -197,6 +213,11 @@ impl Net {
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(NetError::EventFd)?,
             mmds_ns: None,
             metrics: NetMetricsPerDevice::alloc(id),
+            enabled: true,
+            tx_delay: None,
+            flow_stats: None,
+            host_memory_limit: None,
+            last_error: LastError::default(),
         })
     }
 
@@ -266,6 +287,23 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Returns the delay injected on transmitted frames, if any.
+    pub fn tx_delay(&self) -> Option<NetDelayConfig> {
+        self.tx_delay.as_ref().map(FrameDelayQueue::config)
+    }
+
+    /// Returns the traffic counters of the most recently active flows, the ones with the most
+    /// bytes first, or `None` if per-flow statistics are disabled.
+    pub fn flow_stats(&self) -> Option<Vec<(FlowKey, FlowStats)>> {
+        self.flow_stats.as_ref().map(FlowTable::top_flows)
+    }
+
+    // Reports a failure to process an event and keeps it as the most recent error.
+    fn report_event_fail(&mut self, err: DeviceError) {
+        self.last_error.record(&err);
+        report_net_event_fail(&self.metrics, err);
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -435,12 +473,14 @@ impl Net {
     // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
     //
     // Returns whether MMDS consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
         headers: &mut [u8],
         frame_iovec: &IoVecBuffer,
         tap: &mut Tap,
+        tx_delay: Option<&mut FrameDelayQueue>,
         guest_mac: Option<MacAddr>,
         net_metrics: &NetDeviceMetrics,
     ) -> Result<bool, NetError> {
@@ -490,10 +530,26 @@ impl Net {
             });
         }
 
+        // Delayed frames are written to the TAP when their delay elapses.
+        if let Some(tx_delay) = tx_delay {
+            let mut frame = vec![0u8; frame_iovec.len() as usize];
+            // Ok to unwrap here, because we are passing a buffer that has the exact size
+            // of the `IoVecBuffer`.
+            frame_iovec.read_exact_volatile_at(&mut frame, 0).unwrap();
+            tx_delay.push(frame);
+            return Ok(false);
+        }
+
         let _metric = net_metrics.tap_write_agg.record_latency_metrics();
-        match Self::write_tap(tap, frame_iovec) {
+        let res = Self::write_tap(tap, frame_iovec);
+        Self::account_tap_write(res, u64::from(frame_iovec.len()), net_metrics);
+        Ok(false)
+    }
+
+    // Updates the metrics after writing a frame of `len` bytes to the TAP.
+    fn account_tap_write(res: std::io::Result<usize>, len: u64, net_metrics: &NetDeviceMetrics) {
+        match res {
             Ok(_) => {
-                let len = u64::from(frame_iovec.len());
                 net_metrics.tx_bytes_count.add(len);
                 net_metrics.tx_packets_count.inc();
                 net_metrics.tx_count.inc();
@@ -503,7 +559,6 @@ impl Net {
                 net_metrics.tap_write_fails.inc();
             }
         };
-        Ok(false)
     }
 
     // We currently prioritize packets from the MMDS over regular network packets.
@@ -520,7 +575,12 @@ impl Net {
             }
         }
 
-        self.read_tap().map_err(NetError::IO)
+        let len = self.read_tap().map_err(NetError::IO)?;
+        if let Some(flow_stats) = self.flow_stats.as_mut() {
+            let frame = frame_bytes_from_buf(&self.rx_frame_buf[..len])?;
+            flow_stats.account(frame, frame.len());
+        }
+        Ok(len)
     }
 
     fn process_rx(&mut self) -> Result<(), DeviceError> {
@@ -572,7 +632,7 @@ impl Net {
     }
 
     fn resume_rx(&mut self) -> Result<(), DeviceError> {
-        if self.rx_deferred_frame {
+        if self.rx_deferred_frame && self.enabled {
             self.handle_deferred_frame()
         } else {
             Ok(())
@@ -580,6 +640,11 @@ impl Net {
     }
 
     fn process_tx(&mut self) -> Result<(), DeviceError> {
+        // Frames are left in the TX queue while the device is disabled.
+        if !self.enabled {
+            return Ok(());
+        }
+
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
 
@@ -592,6 +657,14 @@ impl Net {
         let tx_queue = &mut self.queues[TX_INDEX];
 
         while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
+            // Stop taking frames while the held back ones fill the delay queue, the delay timer
+            // resumes the processing once they drain.
+            let tx_delay = self.tx_delay.as_ref();
+            if tx_delay.map_or(false, FrameDelayQueue::is_full) {
+                tx_queue.undo_pop();
+                self.metrics.tx_delay_throttled.inc();
+                break;
+            }
             self.metrics
                 .tx_remaining_reqs_count
                 .add(tx_queue.len(mem).into());
@@ -618,22 +691,58 @@ impl Net {
                 continue;
             }
 
+            // Frames are only held in host memory by delay injection. Wait for the held back
+            // ones to drain if the frame doesn't fit in the limit, or drop it if it never will.
+            if let (Some(limit), Some(tx_delay)) = (self.host_memory_limit, tx_delay) {
+                let held = tx_delay.bytes();
+                let other = self
+                    .flow_stats
+                    .as_ref()
+                    .map_or(0, FlowTable::host_memory_estimate);
+                if held + other + buffer.len() as usize > limit {
+                    if held > 0 {
+                        tx_queue.undo_pop();
+                        self.metrics.tx_host_memory_throttled.inc();
+                        break;
+                    }
+                    self.metrics.tx_host_memory_rejected.inc();
+                    tx_queue
+                        .add_used(mem, head_index, 0)
+                        .map_err(DeviceError::QueueError)?;
+                    continue;
+                }
+            }
+
             if !Self::rate_limiter_consume_op(&mut self.tx_rate_limiter, u64::from(buffer.len())) {
                 tx_queue.undo_pop();
                 self.metrics.tx_rate_limiter_throttled.inc();
                 break;
             }
 
-            let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
+            let frame_consumed_by_mmds = match Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
                 &mut self.tx_rate_limiter,
                 &mut self.tx_frame_headers,
                 &buffer,
                 &mut self.tap,
+                self.tx_delay.as_mut(),
                 self.guest_mac,
                 &self.metrics,
-            )
-            .unwrap_or(false);
+            ) {
+                Ok(false) => {
+                    // The frame headers were read from the buffer, the frame went to the TAP.
+                    if let Some(flow_stats) = self.flow_stats.as_mut() {
+                        let len = buffer.len() as usize;
+                        let header_len = len.min(self.tx_frame_headers.len());
+                        flow_stats.account(
+                            &self.tx_frame_headers[vnet_hdr_len()..header_len],
+                            len - vnet_hdr_len(),
+                        );
+                    }
+                    false
+                }
+                res => res.unwrap_or(false),
+            };
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
                 // MMDS consumed this frame/request, let's also try to process the response.
                 process_rx_for_mmds = true;
@@ -688,7 +797,7 @@ impl Net {
     pub fn process_rx_queue_event(&mut self) {
         self.metrics.rx_queue_event_count.inc();
 
-        if let Err(err) = self.queue_evts[RX_INDEX].read() {
+        if let Err(err) = self.queues[RX_INDEX].read_kicks(&self.queue_evts[RX_INDEX]) {
             // rate limiters present but with _very high_ allowed rate
             error!("Failed to get rx queue event: {:?}", err);
             self.metrics.event_fails.inc();
@@ -697,7 +806,7 @@ impl Net {
         } else {
             // If the limiter is not blocked, resume the receiving of bytes.
             self.resume_rx()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                .unwrap_or_else(|err| self.report_event_fail(err));
         }
     }
 
@@ -706,6 +815,11 @@ impl Net {
         let mem = self.device_state.mem().unwrap();
         self.metrics.rx_tap_event_count.inc();
 
+        // Frames are left in the tap while the device is disabled.
+        if !self.enabled {
+            return;
+        }
+
         // While there are no available RX queue buffers and there's a deferred_frame
         // don't process any more incoming. Otherwise start processing a frame. In the
         // process the deferred_frame flag will be set in order to avoid freezing the
@@ -726,10 +840,10 @@ impl Net {
         // until we manage to receive this deferred frame.
         {
             self.handle_deferred_frame()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                .unwrap_or_else(|err| self.report_event_fail(err));
         } else {
             self.process_rx()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                .unwrap_or_else(|err| self.report_event_fail(err));
         }
     }
 
@@ -739,14 +853,14 @@ impl Net {
     /// buffer in the TX queue.
     pub fn process_tx_queue_event(&mut self) {
         self.metrics.tx_queue_event_count.inc();
-        if let Err(err) = self.queue_evts[TX_INDEX].read() {
+        if let Err(err) = self.queues[TX_INDEX].read_kicks(&self.queue_evts[TX_INDEX]) {
             error!("Failed to get tx queue event: {:?}", err);
             self.metrics.event_fails.inc();
         } else if !self.tx_rate_limiter.is_blocked()
         // If the limiter is not blocked, continue transmitting bytes.
         {
             self.process_tx()
-                .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                .unwrap_or_else(|err| self.report_event_fail(err));
         } else {
             self.metrics.tx_rate_limiter_throttled.inc();
         }
@@ -761,7 +875,7 @@ impl Net {
             Ok(_) => {
                 // There might be enough budget now to receive the frame.
                 self.resume_rx()
-                    .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                    .unwrap_or_else(|err| self.report_event_fail(err));
             }
             Err(err) => {
                 error!("Failed to get rx rate-limiter event: {:?}", err);
@@ -778,7 +892,7 @@ impl Net {
             Ok(_) => {
                 // There might be enough budget now to send the frame.
                 self.process_tx()
-                    .unwrap_or_else(|err| report_net_event_fail(&self.metrics, err));
+                    .unwrap_or_else(|err| self.report_event_fail(err));
             }
             Err(err) => {
                 error!("Failed to get tx rate-limiter event: {:?}", err);
@@ -787,6 +901,26 @@ impl Net {
         }
     }
 
+    /// Writes the transmitted frames whose delay elapsed to the TAP.
+    pub fn process_tx_delay_event(&mut self) {
+        let mut was_full = false;
+        if let Some(tx_delay) = self.tx_delay.as_mut() {
+            was_full = tx_delay.is_full();
+            tx_delay.on_timer();
+            while let Some(frame) = tx_delay.pop_due() {
+                let _metric = self.metrics.tap_write_agg.record_latency_metrics();
+                let res = self.tap.write(&frame);
+                Self::account_tap_write(res, frame.len() as u64, &self.metrics);
+            }
+            tx_delay.arm_timer();
+        }
+        // The TX queue may have been throttled by a full delay queue or the host memory limit.
+        if was_full || self.host_memory_limit.is_some() {
+            self.process_tx()
+                .unwrap_or_else(|err| self.report_event_fail(err));
+        }
+    }
+
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
         let _ = self.resume_rx();
@@ -878,6 +1012,78 @@ impl VirtioDevice for Net {
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn host_fd_count(&self) -> usize {
+        // Queue eventfds, interrupt eventfd and the tap.
+        self.queue_evts.len() + 2
+    }
+
+    fn host_memory_estimate(&self) -> usize {
+        // Frames held back by delay injection and the flow table.
+        self.tx_delay.as_ref().map_or(0, FrameDelayQueue::bytes)
+            + self
+                .flow_stats
+                .as_ref()
+                .map_or(0, FlowTable::host_memory_estimate)
+    }
+
+    fn set_host_memory_limit(&mut self, limit: Option<usize>) -> bool {
+        self.host_memory_limit = limit;
+        true
+    }
+
+    fn rate_limiter_state(&self) -> Vec<(&'static str, RateLimiterBudgets)> {
+        vec![
+            ("rx_rate_limiter", self.rx_rate_limiter.budgets()),
+            ("tx_rate_limiter", self.tx_rate_limiter.budgets()),
+        ]
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> bool {
+        let resume = enabled && !self.enabled;
+        self.enabled = enabled;
+        if resume && self.is_activated() {
+            // Pick up the frames that queued up while the device was disabled.
+            self.process_tap_rx_event();
+            self.process_tx()
+                .unwrap_or_else(|err| self.report_event_fail(err));
+        }
+        true
+    }
+
+    fn set_id(&mut self, id: &str) -> bool {
+        NetMetricsPerDevice::rename(&self.id, id.to_string());
+        self.id = id.to_string();
+        true
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
+
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        // Send a broadcast loopback frame, which hosts discard, through the tap.
+        let mut frame = [0u8; vnet_hdr_len() + ETH_MIN_FRAME_LEN];
+        EthernetFrame::write_incomplete(
+            &mut frame[vnet_hdr_len()..],
+            MacAddr::from_bytes_unchecked(&[0xff; 6]),
+            self.guest_mac.unwrap_or_default(),
+            ETHERTYPE_LOOPBACK,
+        )
+        .expect("Frame buffer too small");
+        // A reply function, so the frame ends at its first receiver.
+        frame[vnet_hdr_len() + PAYLOAD_OFFSET + 2] = 1;
+        self.tap.write_all(&frame).map_err(SelfTestError::Tap)
+    }
+
+    fn teardown(&mut self) {
+        // Release the tap interface now, so that the next microVM can open it by name.
+        self.tap.close();
+    }
 }
 
 #[cfg(test)]
@@ -885,6 +1091,7 @@ impl VirtioDevice for Net {
 pub mod tests {
     use std::io::Read;
     use std::net::Ipv4Addr;
+    use std::os::unix::io::AsRawFd;
     use std::str::FromStr;
     use std::time::Duration;
     use std::{io, mem, thread};
@@ -895,9 +1102,11 @@ pub mod tests {
     use crate::check_metric_after_block;
     use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
     use crate::devices::virtio::iovec::IoVecBuffer;
+    use crate::devices::virtio::net::delay::MAX_DELAYED_FRAMES;
     use crate::devices::virtio::net::device::{
         frame_bytes_from_buf, frame_bytes_from_buf_mut, frame_hdr_len, init_vnet_hdr, vnet_hdr_len,
     };
+    use crate::devices::virtio::net::flows::tests::udp_frame;
     use crate::devices::virtio::net::test_utils::test::TestHelper;
     use crate::devices::virtio::net::test_utils::{
         default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent, NetQueue, ReadTapMock,
@@ -910,7 +1119,7 @@ pub mod tests {
     use crate::dumbo::EthernetFrame;
     use crate::logger::IncMetric;
     use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket, TokenType};
-    use crate::vstate::memory::{Address, GuestMemory};
+    use crate::vstate::memory::{Address, GuestAddress, GuestMemory, GuestMemoryExtension};
 
     impl Net {
         pub(crate) fn read_tap(&mut self) -> io::Result<usize> {
@@ -1222,6 +1431,33 @@ pub mod tests {
         th.rxq.dtable[11].check_data(&frame[150..]);
     }
 
+    #[test]
+    fn test_rx_dirty_regions() {
+        let mut th = TestHelper::get_dirty_tracking();
+        th.activate_net();
+        th.net().tap.mocks.set_read_tap(ReadTapMock::TapFrame);
+
+        th.add_desc_chain(NetQueue::Rx, 0, &[(0, 4096, VIRTQ_DESC_F_WRITE)]);
+        let frame = inject_tap_tx_frame(&th.net(), 1000);
+        // Start a new checkpoint, setting up the queues dirtied guest memory.
+        th.mem.take_dirty_regions();
+        assert!(th.mem.take_dirty_regions().is_empty());
+
+        check_metric_after_block!(
+            th.net().metrics.rx_packets_count,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        // The pages of the Rx buffer were written by the device.
+        let rx_buf = GuestAddress(th.rxq.dtable[0].addr.get());
+        let dirty = th.mem.take_dirty_regions();
+        assert!(dirty.contains(rx_buf));
+        assert!(dirty.contains(rx_buf.unchecked_add(frame.len() as u64 - 1)));
+        // Taking the regions resets them.
+        assert!(th.mem.take_dirty_regions().is_empty());
+    }
+
     #[test]
     fn test_rx_multiple_frames() {
         let mut th = TestHelper::get_default();
@@ -1503,6 +1739,248 @@ pub mod tests {
         assert_eq!(&buf[..600], &frame_2[..600]);
     }
 
+    #[test]
+    fn test_per_device_metrics() {
+        let mut th = TestHelper::get_default();
+        let mut other = TestHelper::get_default();
+        assert_ne!(th.net().id(), other.net().id());
+        th.activate_net();
+
+        let other_tx_packets = other.net().metrics.tx_packets_count.count();
+        let other_tx_bytes = other.net().metrics.tx_bytes_count.count();
+
+        let desc_list = [(0, 1000, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 1000);
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        // Only the counters of the device that sent the frame moved.
+        assert_eq!(
+            other.net().metrics.tx_packets_count.count(),
+            other_tx_packets
+        );
+        assert_eq!(other.net().metrics.tx_bytes_count.count(), other_tx_bytes);
+    }
+
+    #[test]
+    fn test_tx_flow_stats() {
+        let mut th = TestHelper::get_default();
+        assert!(th.net().flow_stats().is_none());
+        th.net().flow_stats = Some(FlowTable::default());
+        th.activate_net();
+
+        // Two frames of a first flow and one frame of a second flow.
+        let flow_1 = udp_frame(([10, 0, 0, 2], 1000), ([10, 0, 0, 1], 80), 200);
+        let flow_2 = udp_frame(([10, 0, 0, 2], 2000), ([10, 0, 0, 1], 53), 500);
+        for (index, frame) in [&flow_1, &flow_1, &flow_2].into_iter().enumerate() {
+            let index = u16::try_from(index).unwrap();
+            let len = u32::try_from(vnet_hdr_len() + frame.len()).unwrap();
+            th.add_desc_chain(NetQueue::Tx, u64::from(index) * 1000, &[(index, len, 0)]);
+            let addr = GuestAddress::new(th.txq.dtable[usize::from(index)].addr.get());
+            th.mem
+                .write_slice(frame, addr.unchecked_add(vnet_hdr_len() as u64))
+                .unwrap();
+        }
+
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            3,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+
+        let flows = th.net().flow_stats().unwrap();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].0, FlowKey::from_frame(&flow_2).unwrap());
+        assert_eq!(
+            flows[0].1,
+            FlowStats {
+                bytes: 500,
+                packets: 1
+            }
+        );
+        assert_eq!(flows[1].0, FlowKey::from_frame(&flow_1).unwrap());
+        assert_eq!(
+            flows[1].1,
+            FlowStats {
+                bytes: 400,
+                packets: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_tx_delay() {
+        let mut th = TestHelper::get_default();
+        th.net().tx_delay = Some(
+            FrameDelayQueue::new(NetDelayConfig {
+                latency_ms: 100,
+                jitter_ms: 0,
+            })
+            .unwrap(),
+        );
+        assert_eq!(
+            th.net().tx_delay(),
+            Some(NetDelayConfig {
+                latency_ms: 100,
+                jitter_ms: 0,
+            })
+        );
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+        assert_eq!(th.net().host_memory_estimate(), 0);
+
+        let desc_list = [(0, 50, 0), (1, 100, 0), (2, 150, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, 300);
+        let start = std::time::Instant::now();
+
+        // The descriptor chain is used right away, but the frame is held back.
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            0,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        assert_eq!(th.txq.used.idx.get(), 1);
+        th.txq.check_used_elem(0, 0, 0);
+        assert_eq!(th.net().tx_delay.as_ref().unwrap().len(), 1);
+        assert_eq!(th.net().host_memory_estimate(), 300);
+
+        // The frame is written to the tap once the delay elapsed.
+        let tx_packets_count = th.net().metrics.tx_packets_count.count();
+        while th.net().metrics.tx_packets_count.count() == tx_packets_count {
+            th.event_manager.run_with_timeout(200).unwrap();
+        }
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+        assert!(th.net().tx_delay.as_ref().unwrap().is_empty());
+        assert_eq!(th.net().host_memory_estimate(), 0);
+        let mut buf = vec![0; 300];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert_eq!(&buf[..300], &frame[..300]);
+    }
+
+    #[test]
+    fn test_host_memory_limit() {
+        let mut th = TestHelper::get_default();
+        th.net().tx_delay = Some(
+            FrameDelayQueue::new(NetDelayConfig {
+                latency_ms: 50,
+                jitter_ms: 0,
+            })
+            .unwrap(),
+        );
+        assert!(th.net().set_host_memory_limit(Some(400)));
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        let desc_list = [(0, 50, 0), (1, 100, 0), (2, 150, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 300);
+        let desc_list = [(3, 100, 0), (4, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 500, &desc_list);
+        th.write_tx_frame(&desc_list, 200);
+
+        // The first frame is held back, the second one would exceed the limit and stays in the
+        // queue.
+        check_metric_after_block!(
+            th.net().metrics.tx_host_memory_throttled,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        assert_eq!(th.txq.used.idx.get(), 1);
+        assert_eq!(th.net().host_memory_estimate(), 300);
+
+        // The second frame is taken once the first one is written to the tap.
+        while th.net().metrics.tx_packets_count.count() < 2 {
+            th.event_manager.run_with_timeout(200).unwrap();
+        }
+        assert_eq!(th.txq.used.idx.get(), 2);
+        assert_eq!(th.net().host_memory_estimate(), 0);
+        let mut buf = vec![0; 300];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+
+        // A frame larger than the limit can never be held back, it is dropped.
+        let desc_list = [(5, 200, 0), (6, 200, 0), (7, 200, 0)];
+        th.add_desc_chain(NetQueue::Tx, 1000, &desc_list);
+        th.write_tx_frame(&desc_list, 600);
+        check_metric_after_block!(
+            th.net().metrics.tx_host_memory_rejected,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        assert_eq!(th.txq.used.idx.get(), 3);
+        assert!(th.net().tx_delay.as_ref().unwrap().is_empty());
+        assert_eq!(th.net().metrics.tx_packets_count.count(), 2);
+    }
+
+    #[test]
+    fn test_tx_delay_queue_full() {
+        let mut th = TestHelper::get_default();
+        th.net().tx_delay = Some(
+            FrameDelayQueue::new(NetDelayConfig {
+                latency_ms: 1000,
+                jitter_ms: 0,
+            })
+            .unwrap(),
+        );
+        th.activate_net();
+        for _ in 0..MAX_DELAYED_FRAMES {
+            th.net().tx_delay.as_mut().unwrap().push(vec![0]);
+        }
+
+        // The frame stays in the queue while the delay queue is full.
+        let desc_list = [(0, 50, 0), (1, 100, 0), (2, 150, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 300);
+        check_metric_after_block!(
+            th.net().metrics.tx_delay_throttled,
+            1,
+            th.simulate_event(NetEvent::TxQueue)
+        );
+        assert_eq!(th.txq.used.idx.get(), 0);
+        assert_eq!(
+            th.net().tx_delay.as_ref().unwrap().len(),
+            MAX_DELAYED_FRAMES
+        );
+    }
+
+    #[test]
+    fn test_set_enabled() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let tap_traffic_simulator = TapTrafficSimulator::new(if_index(&th.net().tap));
+
+        // No frames flow while the device is disabled.
+        assert!(th.net().is_enabled());
+        assert!(th.net().set_enabled(false));
+        assert!(!th.net().is_enabled());
+        let desc_list = [(0, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        let frame = th.write_tx_frame(&desc_list, 100);
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            0,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 0);
+        assert!(!tap_traffic_simulator.pop_rx_packet(&mut [0; 100]));
+
+        // Re-enabling the device sends the frame that queued up.
+        check_metric_after_block!(
+            th.net().metrics.tx_packets_count,
+            1,
+            assert!(th.net().set_enabled(true))
+        );
+        assert_eq!(th.txq.used.idx.get(), 1);
+        let mut buf = vec![0; 100];
+        assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
+        assert_eq!(&buf[..100], &frame[..100]);
+    }
+
     fn create_arp_request(
         src_mac: MacAddr,
         src_ip: Ipv4Addr,
@@ -1614,6 +2092,7 @@ pub mod tests {
                 &mut headers,
                 &buffer,
                 &mut net.tap,
+                None,
                 Some(not_guest_mac),
                 &net.metrics,
             )
@@ -1666,11 +2145,14 @@ pub mod tests {
 
         // Fake an avail buffer; this time, tap reading should error out.
         th.rxq.avail.idx.set(1);
+        assert!(th.net().last_error().is_none());
         check_metric_after_block!(
             th.net().metrics.tap_read_fails,
             1,
             th.simulate_event(NetEvent::Tap)
         );
+        let (_, last_error) = th.net().last_error().unwrap();
+        assert_eq!(last_error, DeviceError::FailedReadTap.to_string());
     }
 
     #[test]
@@ -2062,6 +2544,18 @@ pub mod tests {
         assert!(!&net.irq_trigger.has_pending_irq(IrqType::Vring));
     }
 
+    #[test]
+    fn test_teardown() {
+        let mut th = TestHelper::get_default();
+        th.activate_net();
+        let tap_name = th.net().iface_name();
+
+        th.net().teardown();
+        assert_eq!(th.net().tap.as_raw_fd(), -1);
+        // The tap interface was released with its fd.
+        Tap::open_named(&tap_name).unwrap();
+    }
+
     #[test]
     fn test_queues_notification_suppression() {
         let features = 1 << VIRTIO_RING_F_EVENT_IDX;
@@ -16,6 +16,7 @@ impl Net {
     const PROCESS_TAP_RX: u32 = 3;
     const PROCESS_RX_RATE_LIMITER: u32 = 4;
     const PROCESS_TX_RATE_LIMITER: u32 = 5;
+    const PROCESS_TX_DELAY: u32 = 6;
 
     fn register_runtime_events(&self, ops: &mut EventOps) {
         if let Err(err) = ops.add(Events::with_data(
@@ -53,6 +54,15 @@ impl Net {
         )) {
             error!("Failed to register tap event: {}", err);
         }
+        if let Some(tx_delay) = &self.tx_delay {
+            if let Err(err) = ops.add(Events::with_data(
+                tx_delay,
+                Self::PROCESS_TX_DELAY,
+                EventSet::IN,
+            )) {
+                error!("Failed to register tx delay event: {}", err);
+            }
+        }
     }
 
     fn register_activate_event(&self, ops: &mut EventOps) {
@@ -90,6 +100,7 @@ impl MutEventSubscriber for Net {
         let supported_events = EventSet::IN;
         if !supported_events.contains(event_set) {
             warn!(
+                target: &self.id,
                 "Received unknown event: {:?} from source: {:?}",
                 event_set, source
             );
@@ -104,13 +115,15 @@ impl MutEventSubscriber for Net {
                 Self::PROCESS_TAP_RX => self.process_tap_rx_event(),
                 Self::PROCESS_RX_RATE_LIMITER => self.process_rx_rate_limiter_event(),
                 Self::PROCESS_TX_RATE_LIMITER => self.process_tx_rate_limiter_event(),
+                Self::PROCESS_TX_DELAY => self.process_tx_delay_event(),
                 _ => {
-                    warn!("Net: Spurious event received: {:?}", source);
+                    warn!(target: &self.id, "Net: Spurious event received: {:?}", source);
                     self.metrics.event_fails.inc();
                 }
             }
         } else {
             warn!(
+                target: &self.id,
                 "Net: The device is not yet activated. Spurious event received: {:?}",
                 source
             );
@@ -0,0 +1,225 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-flow traffic statistics of a network device.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+use crate::dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_IPV4};
+use crate::dumbo::pdu::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+
+/// Maximum number of flows tracked by a [`FlowTable`].
+pub const MAX_TRACKED_FLOWS: usize = 64;
+
+// Offsets of the fields of an IPv4 header.
+const IPV4_PROTOCOL_OFFSET: usize = 9;
+const IPV4_SRC_ADDR_OFFSET: usize = 12;
+const IPV4_DST_ADDR_OFFSET: usize = 16;
+// Length of the source and destination ports at the start of TCP and UDP headers.
+const L4_PORTS_LEN: usize = 4;
+
+/// The 5-tuple identifying an IPv4 flow, as seen in the frames crossing the device.
+///
+/// The ports are 0 for protocols other than TCP and UDP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct FlowKey {
+    /// Source address.
+    pub src_addr: Ipv4Addr,
+    /// Destination address.
+    pub dst_addr: Ipv4Addr,
+    /// IP protocol number.
+    pub protocol: u8,
+    /// Source port.
+    pub src_port: u16,
+    /// Destination port.
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    /// Extracts the flow key of an ethernet frame, if it carries an IPv4 packet.
+    ///
+    /// Only the frame headers are needed, the payload may be truncated.
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        let eth = EthernetFrame::from_bytes(frame).ok()?;
+        if eth.ethertype() != ETHERTYPE_IPV4 {
+            return None;
+        }
+
+        let ip = eth.payload();
+        if ip.len() < IPV4_DST_ADDR_OFFSET + 4 {
+            return None;
+        }
+        let header_len = usize::from(ip[0] & 0x0f) * 4;
+        let protocol = ip[IPV4_PROTOCOL_OFFSET];
+        let addr = |offset: usize| {
+            Ipv4Addr::new(ip[offset], ip[offset + 1], ip[offset + 2], ip[offset + 3])
+        };
+
+        let (src_port, dst_port) = match ip.get(header_len..header_len + L4_PORTS_LEN) {
+            Some(ports) if protocol == PROTOCOL_TCP || protocol == PROTOCOL_UDP => (
+                u16::from_be_bytes([ports[0], ports[1]]),
+                u16::from_be_bytes([ports[2], ports[3]]),
+            ),
+            _ => (0, 0),
+        };
+
+        Some(FlowKey {
+            src_addr: addr(IPV4_SRC_ADDR_OFFSET),
+            dst_addr: addr(IPV4_DST_ADDR_OFFSET),
+            protocol,
+            src_port,
+            dst_port,
+        })
+    }
+}
+
+/// Traffic counters of a flow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FlowStats {
+    /// Number of bytes, L2 headers included.
+    pub bytes: u64,
+    /// Number of packets.
+    pub packets: u64,
+}
+
+/// Bounded table of the most recently active flows of a network device.
+///
+/// When the table is full, the least recently active flow makes room for a new one.
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    flows: HashMap<FlowKey, (FlowStats, u64)>,
+    // Incremented on every accounted frame, used to find the least recently active flow.
+    tick: u64,
+}
+
+impl FlowTable {
+    /// Accounts a frame of `len` bytes, starting with the headers in `frame`.
+    pub fn account(&mut self, frame: &[u8], len: usize) {
+        let key = match FlowKey::from_frame(frame) {
+            Some(key) => key,
+            None => return,
+        };
+
+        self.tick += 1;
+        if !self.flows.contains_key(&key) && self.flows.len() >= MAX_TRACKED_FLOWS {
+            let oldest = self
+                .flows
+                .iter()
+                .min_by_key(|(_, (_, last_seen))| *last_seen)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.flows.remove(&oldest);
+            }
+        }
+
+        let (stats, last_seen) = self.flows.entry(key).or_default();
+        stats.bytes += len as u64;
+        stats.packets += 1;
+        *last_seen = self.tick;
+    }
+
+    /// Number of tracked flows.
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Returns `true` if no flow is tracked.
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// Approximate host memory, in bytes, used by the tracked flows.
+    pub fn host_memory_estimate(&self) -> usize {
+        self.flows.len() * std::mem::size_of::<(FlowKey, (FlowStats, u64))>()
+    }
+
+    /// Returns the tracked flows, the ones with the most bytes first.
+    pub fn top_flows(&self) -> Vec<(FlowKey, FlowStats)> {
+        let mut flows: Vec<_> = self
+            .flows
+            .iter()
+            .map(|(key, (stats, _))| (*key, *stats))
+            .collect();
+        flows.sort_by(|(_, a), (_, b)| b.bytes.cmp(&a.bytes));
+        flows
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dumbo::pdu::ethernet::PAYLOAD_OFFSET;
+
+    /// Builds the headers of a UDP/IPv4 frame between the given endpoints.
+    pub(crate) fn udp_frame(src: ([u8; 4], u16), dst: ([u8; 4], u16), len: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; len];
+        frame[12..PAYLOAD_OFFSET].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let ip = &mut frame[PAYLOAD_OFFSET..];
+        ip[0] = 0x45;
+        ip[IPV4_PROTOCOL_OFFSET] = PROTOCOL_UDP;
+        ip[IPV4_SRC_ADDR_OFFSET..IPV4_SRC_ADDR_OFFSET + 4].copy_from_slice(&src.0);
+        ip[IPV4_DST_ADDR_OFFSET..IPV4_DST_ADDR_OFFSET + 4].copy_from_slice(&dst.0);
+        ip[20..22].copy_from_slice(&src.1.to_be_bytes());
+        ip[22..24].copy_from_slice(&dst.1.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_flow_key() {
+        let frame = udp_frame(([10, 0, 0, 2], 1234), ([10, 0, 0, 1], 53), 100);
+        assert_eq!(
+            FlowKey::from_frame(&frame).unwrap(),
+            FlowKey {
+                src_addr: Ipv4Addr::new(10, 0, 0, 2),
+                dst_addr: Ipv4Addr::new(10, 0, 0, 1),
+                protocol: PROTOCOL_UDP,
+                src_port: 1234,
+                dst_port: 53,
+            }
+        );
+
+        // Not an IPv4 frame.
+        let mut arp = frame.clone();
+        arp[12] = 0x08;
+        arp[13] = 0x06;
+        assert!(FlowKey::from_frame(&arp).is_none());
+        // Truncated IPv4 header.
+        assert!(FlowKey::from_frame(&frame[..PAYLOAD_OFFSET + 10]).is_none());
+    }
+
+    #[test]
+    fn test_flow_eviction() {
+        let mut table = FlowTable::default();
+        for port in 0..u16::try_from(MAX_TRACKED_FLOWS).unwrap() {
+            let frame = udp_frame(([10, 0, 0, 2], port), ([10, 0, 0, 1], 80), 64);
+            table.account(&frame, frame.len());
+        }
+        assert_eq!(table.len(), MAX_TRACKED_FLOWS);
+
+        // Refresh the first flow, so that the second one is the least recently active.
+        let first = udp_frame(([10, 0, 0, 2], 0), ([10, 0, 0, 1], 80), 64);
+        table.account(&first, first.len());
+        let new = udp_frame(([10, 0, 0, 3], 0), ([10, 0, 0, 1], 80), 64);
+        table.account(&new, new.len());
+
+        assert_eq!(table.len(), MAX_TRACKED_FLOWS);
+        let ports: Vec<_> = table
+            .top_flows()
+            .iter()
+            .filter(|(key, _)| key.src_addr == Ipv4Addr::new(10, 0, 0, 2))
+            .map(|(key, _)| key.src_port)
+            .collect();
+        assert!(ports.contains(&0));
+        assert!(!ports.contains(&1));
+        assert_eq!(
+            table.top_flows()[0].1,
+            FlowStats {
+                bytes: 128,
+                packets: 2
+            }
+        );
+    }
+}
@@ -111,6 +111,15 @@ impl NetMetricsPerDevice {
                 .or_insert_with(|| Arc::new(NetDeviceMetrics::default())),
         )
     }
+
+    /// Moves the metrics of the net device having id `old_id` under `new_id`, so that
+    /// they keep being reported after the device is renamed.
+    pub fn rename(old_id: &str, new_id: String) {
+        let mut per_device = METRICS.write().unwrap();
+        if let Some(metrics) = per_device.metrics.remove(old_id) {
+            per_device.metrics.insert(new_id, metrics);
+        }
+    }
 }
 
 /// Pool of Network-related metrics per device behind a lock to
@@ -198,6 +207,12 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_event_count: SharedIncMetric,
     /// Number of RX rate limiter throttling events.
     pub tx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of times the transmitting path was throttled by the host memory limit.
+    pub tx_host_memory_throttled: SharedIncMetric,
+    /// Number of frames dropped because they did not fit in the host memory limit.
+    pub tx_host_memory_rejected: SharedIncMetric,
+    /// Number of times the transmitting path was throttled by a full TX delay queue.
+    pub tx_delay_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
     /// Number of remaining requests in the TX queue.
@@ -263,6 +278,12 @@ impl NetDeviceMetrics {
             .add(other.tx_rate_limiter_event_count.fetch_diff());
         self.tx_rate_limiter_throttled
             .add(other.tx_rate_limiter_throttled.fetch_diff());
+        self.tx_host_memory_throttled
+            .add(other.tx_host_memory_throttled.fetch_diff());
+        self.tx_host_memory_rejected
+            .add(other.tx_host_memory_rejected.fetch_diff());
+        self.tx_delay_throttled
+            .add(other.tx_delay_throttled.fetch_diff());
         self.tx_spoofed_mac_count
             .add(other.tx_spoofed_mac_count.fetch_diff());
         self.tx_remaining_reqs_count
@@ -17,8 +17,10 @@ pub const RX_INDEX: usize = 0;
 /// The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
 
+pub mod delay;
 pub mod device;
 mod event_handler;
+pub mod flows;
 pub mod metrics;
 pub mod persist;
 mod tap;
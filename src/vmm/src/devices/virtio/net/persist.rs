@@ -43,6 +43,7 @@ pub struct NetState {
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    enabled: bool,
 }
 
 /// Auxiliary structure for creating a device when resuming from a snapshot.
@@ -83,6 +84,7 @@ impl Persist<'_> for Net {
                 guest_mac: self.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            enabled: self.enabled,
         }
     }
 
@@ -127,6 +129,7 @@ impl Persist<'_> for Net {
         net.irq_trigger.irq_status = Arc::new(AtomicU32::new(state.virtio_state.interrupt_status));
         net.avail_features = state.virtio_state.avail_features;
         net.acked_features = state.virtio_state.acked_features;
+        net.enabled = state.enabled;
 
         if state.virtio_state.activated {
             net.device_state = DeviceState::Activated(constructor_args.mem);
@@ -155,6 +158,7 @@ mod tests {
         let has_mmds_ns;
         let allow_mmds_requests;
         let virtio_state;
+        let enabled;
 
         // Create and save the net device.
         {
@@ -166,6 +170,7 @@ mod tests {
             has_mmds_ns = net.mmds_ns.is_some();
             allow_mmds_requests = has_mmds_ns && mmds_ds.is_some();
             virtio_state = VirtioDeviceState::from_device(&net);
+            enabled = net.enabled;
         }
 
         // Drop the initial net device so that we don't get an error when trying to recreate the
@@ -197,6 +202,7 @@ mod tests {
                     assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
                     assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
                     assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+                    assert_eq!(restored_net.enabled, enabled);
                 }
                 Err(NetPersistError::NoMmdsDataStore) => {
                     assert!(has_mmds_ns && !allow_mmds_requests)
@@ -220,5 +226,10 @@ mod tests {
         // Check what happens if the MMIODeviceManager does not give us the reference to the MMDS
         // data store. This will return an error.
         validate_save_and_restore(default_net(), None);
+
+        // The enabled state of the device is persisted.
+        let mut net = default_net_no_mmds();
+        net.enabled = false;
+        validate_save_and_restore(net, None);
     }
 }
@@ -43,15 +43,18 @@ const TUNTAP: ::std::os::raw::c_uint = 84;
 ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETOFFLOAD, TUNTAP, 208, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, TUNTAP, 216, ::std::os::raw::c_int);
+#[cfg(test)]
+ioctl_iow_nr!(TUNSETPERSIST, TUNTAP, 203, ::std::os::raw::c_int);
 
 /// Handle for a network tap interface.
 ///
 /// For now, this simply wraps the file descriptor for the tap device so methods
 /// can run ioctls on the interface. The tap interface fd will be closed when
-/// Tap goes out of scope, and the kernel will clean up the interface automatically.
+/// Tap goes out of scope or is closed, and the kernel will clean up the interface
+/// automatically.
 #[derive(Debug)]
 pub struct Tap {
-    tap_file: File,
+    tap_file: Option<File>,
     pub(crate) if_name: [u8; IFACE_NAME_MAX_LEN],
 
     #[cfg(test)]
@@ -146,7 +149,7 @@ impl Tap {
             .map_err(|io_error| TapError::IfreqExecuteError(io_error, if_name.to_owned()))?;
 
         Ok(Tap {
-            tap_file: tuntap,
+            tap_file: Some(tuntap),
             // SAFETY: Safe since only the name is accessed, and it's cloned out.
             if_name: unsafe { ifreq.ifr_ifrn.ifrn_name },
 
@@ -155,6 +158,19 @@ impl Tap {
         })
     }
 
+    // Returns the tap interface file, or an `EBADF` error if the tap was closed.
+    fn file(&self) -> Result<&File, IoError> {
+        self.tap_file
+            .as_ref()
+            .ok_or_else(|| IoError::from_raw_os_error(libc::EBADF))
+    }
+
+    /// Close the tap interface fd, so that the interface can be reused before the
+    /// `Tap` is dropped. Any later operation on the tap fails with `EBADF`.
+    pub fn close(&mut self) {
+        self.tap_file = None;
+    }
+
     /// Retrieve the interface's name as a str.
     pub fn if_name_as_str(&self) -> &str {
         let len = self
@@ -167,8 +183,9 @@ impl Tap {
 
     /// Set the offload flags for the tap interface.
     pub fn set_offload(&self, flags: c_uint) -> Result<(), TapError> {
+        let tap_file = self.file().map_err(TapError::SetOffloadFlags)?;
         // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
-        if unsafe { ioctl_with_val(&self.tap_file, TUNSETOFFLOAD(), c_ulong::from(flags)) } < 0 {
+        if unsafe { ioctl_with_val(tap_file, TUNSETOFFLOAD(), c_ulong::from(flags)) } < 0 {
             return Err(TapError::SetOffloadFlags(IoError::last_os_error()));
         }
 
@@ -177,22 +194,35 @@ impl Tap {
 
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<(), TapError> {
+        let tap_file = self.file().map_err(TapError::SetSizeOfVnetHdr)?;
         // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
-        if unsafe { ioctl_with_ref(&self.tap_file, TUNSETVNETHDRSZ(), &size) } < 0 {
+        if unsafe { ioctl_with_ref(tap_file, TUNSETVNETHDRSZ(), &size) } < 0 {
             return Err(TapError::SetSizeOfVnetHdr(IoError::last_os_error()));
         }
 
         Ok(())
     }
 
+    /// Make the tap interface outlive its fd, or be removed once its last fd is closed.
+    #[cfg(test)]
+    pub fn set_persist(&self, persist: bool) -> Result<(), IoError> {
+        // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
+        if unsafe { ioctl_with_val(self.file()?, TUNSETPERSIST(), c_ulong::from(persist)) } < 0 {
+            return Err(IoError::last_os_error());
+        }
+
+        Ok(())
+    }
+
     /// Write an `IoVecBuffer` to tap
     pub(crate) fn write_iovec(&mut self, buffer: &IoVecBuffer) -> Result<usize, IoError> {
         let iovcnt = i32::try_from(buffer.iovec_count()).unwrap();
         let iov = buffer.as_iovec_ptr();
+        let fd = self.file()?.as_raw_fd();
 
         // SAFETY: `writev` is safe. Called with a valid tap fd, the iovec pointer and length
         // is provide by the `IoVecBuffer` implementation and we check the return value.
-        let ret = unsafe { libc::writev(self.tap_file.as_raw_fd(), iov, iovcnt) };
+        let ret = unsafe { libc::writev(fd, iov, iovcnt) };
         if ret == -1 {
             return Err(IoError::last_os_error());
         }
@@ -202,13 +232,13 @@ impl Tap {
 
 impl Read for Tap {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
-        self.tap_file.read(buf)
+        self.file()?.read(buf)
     }
 }
 
 impl Write for Tap {
     fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
-        self.tap_file.write(buf)
+        self.file()?.write(buf)
     }
 
     fn flush(&mut self) -> Result<(), IoError> {
@@ -218,7 +248,7 @@ impl Write for Tap {
 
 impl AsRawFd for Tap {
     fn as_raw_fd(&self) -> RawFd {
-        self.tap_file.as_raw_fd()
+        self.tap_file.as_ref().map_or(-1, AsRawFd::as_raw_fd)
     }
 }
 
@@ -286,7 +316,7 @@ pub mod tests {
         tap.set_offload(0).unwrap();
 
         let faulty_tap = Tap {
-            tap_file: unsafe { File::from_raw_fd(-2) },
+            tap_file: Some(unsafe { File::from_raw_fd(-2) }),
             if_name: [0x01; 16],
             mocks: Default::default(),
         };
@@ -303,7 +333,28 @@ pub mod tests {
     #[test]
     fn test_raw_fd() {
         let tap = Tap::open_named("").unwrap();
-        assert_eq!(tap.as_raw_fd(), tap.tap_file.as_raw_fd());
+        assert_eq!(tap.as_raw_fd(), tap.tap_file.as_ref().unwrap().as_raw_fd());
+    }
+
+    #[test]
+    fn test_close() {
+        let mut tap = Tap::open_named("closetap").unwrap();
+        tap.close();
+        assert_eq!(tap.as_raw_fd(), -1);
+        assert_eq!(
+            tap.read(&mut [0u8; 16]).unwrap_err().raw_os_error(),
+            Some(libc::EBADF)
+        );
+        assert_eq!(
+            tap.write(&[0u8; 16]).unwrap_err().raw_os_error(),
+            Some(libc::EBADF)
+        );
+        assert_eq!(
+            tap.set_offload(0).unwrap_err().to_string(),
+            TapError::SetOffloadFlags(IoError::from_raw_os_error(libc::EBADF)).to_string()
+        );
+        // The interface is released, so it can be opened again.
+        Tap::open_named("closetap").unwrap();
     }
 
     #[test]
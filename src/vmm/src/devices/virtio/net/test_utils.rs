@@ -270,6 +270,31 @@ pub fn if_index(tap: &Tap) -> i32 {
     unsafe { ifreq.ifr_ifru.ifru_ivalue }
 }
 
+/// A tap interface that exists on the host, like the ones set up by users, until it is dropped.
+///
+/// It must be dropped after the devices using it, as it can only be removed once it is no longer
+/// in use.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct PersistentTap(String);
+
+#[cfg(test)]
+impl PersistentTap {
+    pub fn new(if_name: &str) -> Self {
+        Tap::open_named(if_name).unwrap().set_persist(true).unwrap();
+        PersistentTap(if_name.to_string())
+    }
+}
+
+#[cfg(test)]
+impl Drop for PersistentTap {
+    fn drop(&mut self) {
+        if let Ok(tap) = Tap::open_named(&self.0) {
+            tap.set_persist(false).unwrap();
+        }
+    }
+}
+
 /// Enable the tap interface.
 pub fn enable(tap: &Tap) {
     // Disable IPv6 router advertisment requests
@@ -365,7 +390,10 @@ pub mod test {
     use crate::devices::virtio::test_utils::{VirtQueue, VirtqDesc};
     use crate::logger::IncMetric;
     use crate::utilities::test_utils::single_region_mem;
-    use crate::vstate::memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
+    use crate::vmm_config::machine_config::HugePageConfig;
+    use crate::vstate::memory::{
+        Address, Bytes, GuestAddress, GuestMemoryExtension, GuestMemoryMmap,
+    };
 
     pub struct TestHelper<'a> {
         pub event_manager: EventManager<Arc<Mutex<Net>>>,
@@ -393,9 +421,24 @@ pub mod test {
         const QUEUE_SIZE: u16 = 16;
 
         pub fn get_default() -> TestHelper<'a> {
+            Self::with_mem(single_region_mem(2 * MAX_BUFFER_SIZE))
+        }
+
+        /// Same as `get_default`, but the guest memory tracks the pages written by the device.
+        pub fn get_dirty_tracking() -> TestHelper<'a> {
+            Self::with_mem(
+                GuestMemoryMmap::from_raw_regions(
+                    &[(GuestAddress(0), 2 * MAX_BUFFER_SIZE)],
+                    true,
+                    HugePageConfig::None,
+                )
+                .unwrap(),
+            )
+        }
+
+        fn with_mem(mem: GuestMemoryMmap) -> TestHelper<'a> {
             let mut event_manager = EventManager::new().unwrap();
             let mut net = default_net();
-            let mem = single_region_mem(2 * MAX_BUFFER_SIZE);
 
             // transmute mem_ref lifetime to 'a
             let mem_ref = unsafe { mem::transmute::<&GuestMemoryMmap, &'a GuestMemoryMmap>(&mem) };
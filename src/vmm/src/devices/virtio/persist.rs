@@ -8,6 +8,7 @@ use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
+use utils::time::TimestampUs;
 
 use crate::devices::virtio::device::VirtioDevice;
 use crate::devices::virtio::gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
@@ -82,6 +83,7 @@ impl Persist<'_> for Queue {
             next_used: state.next_used,
             uses_notif_suppression: false,
             num_added: state.num_added,
+            num_kicks: 0,
         })
     }
 }
@@ -177,6 +179,7 @@ pub struct MmioTransportState {
     queue_select: u32,
     device_status: u32,
     config_generation: u32,
+    uptime_us: Option<u64>,
 }
 
 /// Auxiliary structure for initializing the transport when resuming from a snapshot.
@@ -202,6 +205,9 @@ impl Persist<'_> for MmioTransport {
             queue_select: self.queue_select,
             device_status: self.device_status,
             config_generation: self.config_generation,
+            uptime_us: self
+                .uptime()
+                .map(|uptime| u64::try_from(uptime.as_micros()).unwrap_or(u64::MAX)),
         }
     }
 
@@ -219,6 +225,14 @@ impl Persist<'_> for MmioTransport {
         transport.queue_select = state.queue_select;
         transport.device_status = state.device_status;
         transport.config_generation = state.config_generation;
+        // The uptime carries over, as if the device had been activated that long ago.
+        transport.activated_at = state.uptime_us.map(|uptime_us| {
+            let now = TimestampUs::default();
+            TimestampUs {
+                time_us: now.time_us.saturating_sub(uptime_us),
+                cputime_us: now.cputime_us,
+            }
+        });
         Ok(transport)
     }
 }
@@ -235,7 +249,7 @@ mod tests {
     use crate::devices::virtio::net::test_utils::default_net;
     use crate::devices::virtio::net::Net;
     use crate::devices::virtio::test_utils::default_mem;
-    use crate::devices::virtio::vsock::{Vsock, VsockUnixBackend};
+    use crate::devices::virtio::vsock::{Vsock, VsockUnixBackend, MAX_CONNECTIONS};
     use crate::snapshot::Snapshot;
 
     const DEFAULT_QUEUE_MAX_SIZE: u16 = 256;
@@ -348,6 +362,7 @@ mod tests {
                 self.queue_select == other.queue_select &&
                 self.device_status == other.device_status &&
                 self.config_generation == other.config_generation &&
+                self.activated_at.is_some() == other.activated_at.is_some() &&
                 self.interrupt_status.load(Ordering::SeqCst) == other.interrupt_status.load(Ordering::SeqCst) &&
                 // Only checking equality of device type, actual device (de)ser is tested by that
                 // device's tests.
@@ -414,7 +429,8 @@ mod tests {
         // Remove the file so the path can be used by the socket.
         temp_uds_path.remove().unwrap();
         let uds_path = String::from(temp_uds_path.as_path().to_str().unwrap());
-        let backend = VsockUnixBackend::new(guest_cid, uds_path).unwrap();
+        let backend =
+            VsockUnixBackend::new(guest_cid, uds_path, MAX_CONNECTIONS, None, None).unwrap();
         let vsock = Vsock::new(guest_cid, backend).unwrap();
         let vsock = Arc::new(Mutex::new(vsock));
         let mmio_transport = MmioTransport::new(mem.clone(), vsock.clone(), false);
@@ -422,6 +438,31 @@ mod tests {
         (mmio_transport, mem, vsock)
     }
 
+    #[test]
+    fn test_mmiotransport_uptime_persistence() {
+        let mem = default_mem();
+        let device = Arc::new(Mutex::new(DummyDevice::new()));
+        let mut mmio_transport = MmioTransport::new(mem.clone(), device.clone(), false);
+        mmio_transport.activated_at = Some(TimestampUs::default());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let uptime = mmio_transport.uptime().unwrap();
+
+        let mut buf = vec![0; 4096];
+        Snapshot::serialize(&mut buf.as_mut_slice(), &mmio_transport.save()).unwrap();
+        let restored_mmio_transport = MmioTransport::restore(
+            MmioTransportConstructorArgs {
+                mem,
+                device,
+                is_vhost_user: false,
+            },
+            &Snapshot::deserialize(&mut buf.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        // The uptime carries over.
+        assert!(restored_mmio_transport.uptime().unwrap() >= uptime);
+    }
+
     #[test]
     fn test_block_over_mmiotransport_persistence() {
         let (mmio_transport, mem, block) = create_default_block();
@@ -9,6 +9,8 @@ use std::cmp::min;
 use std::num::Wrapping;
 use std::sync::atomic::{fence, Ordering};
 
+use utils::eventfd::EventFd;
+
 use crate::logger::error;
 use crate::vstate::memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap,
@@ -206,6 +208,8 @@ pub struct Queue {
     pub(crate) uses_notif_suppression: bool,
     /// The number of added used buffers since last guest kick
     pub(crate) num_added: Wrapping<u16>,
+    /// The number of times the driver kicked the device about this queue
+    pub(crate) num_kicks: u64,
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -223,6 +227,7 @@ impl Queue {
             next_used: Wrapping(0),
             uses_notif_suppression: false,
             num_added: Wrapping(0),
+            num_kicks: 0,
         }
     }
 
@@ -412,6 +417,17 @@ impl Queue {
         )
     }
 
+    /// Returns the descriptor chain starting at `index`, regardless of the avail ring.
+    ///
+    /// This is meant for chains that were already popped, e.g. to re-process a request.
+    pub fn get_desc_chain<'b, M: GuestMemory>(
+        &self,
+        mem: &'b M,
+        index: u16,
+    ) -> Option<DescriptorChain<'b, M>> {
+        DescriptorChain::checked_new(mem, self.desc_table, self.actual_size(), index)
+    }
+
     /// Undo the effects of the last `self.pop()` call.
     /// The caller can use this, if it was unable to consume the last popped descriptor chain.
     pub fn undo_pop(&mut self) {
@@ -468,6 +484,16 @@ impl Queue {
         Wrapping(mem.read_obj::<u16>(addr).unwrap())
     }
 
+    /// Fetch the used ring index (`virtq_used->idx`) from guest memory.
+    /// This is written by the device, to indicate the next slot that will be filled in the used
+    /// ring.
+    pub fn used_idx<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
+        // Bound checks for queue inner data have already been performed, at device activation time,
+        // via `self.is_valid()`, so it's safe to unwrap and use unchecked offsets here.
+        let addr = self.used_ring.unchecked_add(2);
+        Wrapping(mem.read_obj::<u16>(addr).unwrap())
+    }
+
     /// Get the value of the used event field of the avail ring.
     #[inline(always)]
     pub fn used_event<M: GuestMemory>(&self, mem: &M) -> Wrapping<u16> {
@@ -561,6 +587,15 @@ impl Queue {
 
         new - used_event - Wrapping(1) < new - old
     }
+
+    /// Consumes the kicks pending on `queue_evt`, the eventfd the driver writes to notify the
+    /// device about this queue, and adds them to the number of kicks of the queue.
+    pub fn read_kicks(&mut self, queue_evt: &EventFd) -> Result<(), std::io::Error> {
+        // The eventfd counter holds the number of writes since the last read.
+        let kicks = queue_evt.read()?;
+        self.num_kicks = self.num_kicks.wrapping_add(kicks);
+        Ok(())
+    }
 }
 
 #[cfg(kani)]
@@ -1469,6 +1504,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_kicks() {
+        let mut q = Queue::new(16);
+        let queue_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+
+        q.read_kicks(&queue_evt).unwrap_err();
+        assert_eq!(q.num_kicks, 0);
+
+        queue_evt.write(1).unwrap();
+        queue_evt.write(1).unwrap();
+        q.read_kicks(&queue_evt).unwrap();
+        assert_eq!(q.num_kicks, 2);
+        queue_evt.write(1).unwrap();
+        q.read_kicks(&queue_evt).unwrap();
+        assert_eq!(q.num_kicks, 3);
+    }
+
     #[test]
     fn test_try_enable_notification() {
         let m = &default_mem();
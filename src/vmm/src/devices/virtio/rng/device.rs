@@ -7,22 +7,35 @@ use std::sync::Arc;
 
 use aws_lc_rs::rand;
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 use vm_memory::GuestMemoryError;
 
 use super::metrics::METRICS;
 use super::{RNG_NUM_QUEUES, RNG_QUEUE};
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
 use crate::devices::virtio::gen::virtio_rng::VIRTIO_F_VERSION_1;
 use crate::devices::virtio::iovec::IoVecBufferMut;
 use crate::devices::virtio::queue::{Queue, FIRECRACKER_MAX_QUEUE_SIZE};
-use crate::devices::virtio::{ActivateError, TYPE_RNG};
+use crate::devices::virtio::{ActivateError, SelfTestError, TYPE_RNG};
 use crate::devices::DeviceError;
 use crate::logger::{debug, error, IncMetric};
-use crate::rate_limiter::{RateLimiter, TokenType};
+use crate::rate_limiter::{RateLimiter, RateLimiterBudgets, TokenType};
 use crate::vstate::memory::GuestMemoryMmap;
 
 pub const ENTROPY_DEV_ID: &str = "rng";
 
+/// Source of the random bytes served by the entropy device.
+pub type EntropySource = fn(&mut [u8]) -> Result<(), aws_lc_rs::error::Unspecified>;
+
+/// Number of bytes sampled from the entropy source by the quality check.
+const QUALITY_SAMPLE_LEN: usize = 256;
+/// Minimum number of distinct byte values in a sample. A uniform source yields about 162 of
+/// the 256 possible values.
+const QUALITY_MIN_DISTINCT_BYTES: usize = 64;
+/// Maximum deviation of the number of set bits in a sample from half of its bits. This is
+/// more than 11 standard deviations for a uniform source.
+const QUALITY_MAX_BIT_BIAS: u32 = 256;
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum EntropyError {
     /// Error while handling an Event file descriptor: {0}
@@ -31,6 +44,8 @@ pub enum EntropyError {
     GuestMemory(#[from] GuestMemoryError),
     /// Could not get random bytes: {0}
     Random(#[from] aws_lc_rs::error::Unspecified),
+    /// Entropy source failed the quality check: {0}
+    LowQuality(String),
 }
 
 #[derive(Debug)]
@@ -48,6 +63,18 @@ pub struct Entropy {
 
     // Device specific fields
     rate_limiter: RateLimiter,
+    // Maximum number of bytes served during the lifetime of the microVM, if any.
+    lifetime_byte_limit: Option<u64>,
+    // Number of bytes served during the lifetime of the microVM.
+    bytes_served: u64,
+    // Number of requests throttled by the rate limiter during the lifetime of the microVM.
+    requests_throttled: u64,
+    // Source of the random bytes.
+    source: EntropySource,
+    // Whether the source is sampled and checked for obvious breakage on activation.
+    quality_check: bool,
+    // Most recent error hit while serving the guest.
+    last_error: LastError,
 }
 
 impl Entropy {
@@ -75,6 +102,12 @@ impl Entropy {
             queue_events,
             irq_trigger,
             rate_limiter,
+            lifetime_byte_limit: None,
+            bytes_served: 0,
+            requests_throttled: 0,
+            source: rand::fill,
+            quality_check: false,
+            last_error: LastError::default(),
         })
     }
 
@@ -113,13 +146,15 @@ impl Entropy {
         }
 
         let mut rand_bytes = vec![0; iovec.len() as usize];
-        rand::fill(&mut rand_bytes).map_err(|err| {
+        (self.source)(&mut rand_bytes).map_err(|err| {
             METRICS.host_rng_fails.inc();
             err
         })?;
 
         // It is ok to unwrap here. We are writing `iovec.len()` bytes at offset 0.
         iovec.write_all_volatile_at(&rand_bytes, 0).unwrap();
+        // Don't leave the entropy handed to the guest behind in host memory.
+        utils::zeroize(&mut rand_bytes);
         Ok(iovec.len())
     }
 
@@ -133,8 +168,19 @@ impl Entropy {
             METRICS.entropy_event_count.inc();
 
             let bytes = match IoVecBufferMut::from_descriptor_chain(desc) {
+                // Once the lifetime limit is reached, requests are completed without any entropy.
+                Ok(iovec) if self.lifetime_limit_reached(u64::from(iovec.len())) => {
+                    debug!(
+                        target: ENTROPY_DEV_ID,
+                        "entropy: lifetime limit reached, denying request for {} bytes",
+                        iovec.len()
+                    );
+                    METRICS.entropy_lifetime_limit_denied.inc();
+                    0
+                }
                 Ok(mut iovec) => {
                     debug!(
+                        target: ENTROPY_DEV_ID,
                         "entropy: guest request for {} bytes of entropy",
                         iovec.len()
                     );
@@ -143,8 +189,9 @@ impl Entropy {
                     // If not enough budget is available, leave the request descriptor in the queue
                     // to handle once we do have budget.
                     if !Self::rate_limit_request(&mut self.rate_limiter, u64::from(iovec.len())) {
-                        debug!("entropy: throttling entropy queue");
+                        debug!(target: ENTROPY_DEV_ID, "entropy: throttling entropy queue");
                         METRICS.entropy_rate_limiter_throttled.inc();
+                        self.requests_throttled += 1;
                         self.queues[RNG_QUEUE].undo_pop();
                         break;
                     }
@@ -152,12 +199,15 @@ impl Entropy {
                     self.handle_one(&mut iovec).unwrap_or_else(|err| {
                         error!("entropy: {err}");
                         METRICS.entropy_event_fails.inc();
+                        self.last_error.record(err);
                         0
                     })
                 }
                 Err(err) => {
                     error!("entropy: Could not parse descriptor chain: {err}");
                     METRICS.entropy_event_fails.inc();
+                    self.last_error
+                        .record(format!("Could not parse descriptor chain: {err}"));
                     0
                 }
             };
@@ -165,12 +215,15 @@ impl Entropy {
             match self.queues[RNG_QUEUE].add_used(mem, index, bytes) {
                 Ok(_) => {
                     used_any = true;
+                    self.bytes_served += u64::from(bytes);
                     METRICS.entropy_bytes.add(bytes.into());
                 }
                 Err(err) => {
                     error!("entropy: Could not add used descriptor to queue: {err}");
                     Self::rate_limit_replenish_request(&mut self.rate_limiter, bytes.into());
                     METRICS.entropy_event_fails.inc();
+                    self.last_error
+                        .record(format!("Could not add used descriptor to queue: {err}"));
                     // If we are not able to add a buffer to the used queue, something
                     // is probably seriously wrong, so just stop processing additional
                     // buffers
@@ -180,15 +233,16 @@ impl Entropy {
         }
 
         if used_any {
-            self.signal_used_queue().unwrap_or_else(|err| {
+            if let Err(err) = self.signal_used_queue() {
                 error!("entropy: {err:?}");
-                METRICS.entropy_event_fails.inc()
-            });
+                METRICS.entropy_event_fails.inc();
+                self.last_error.record(err);
+            }
         }
     }
 
     pub(crate) fn process_entropy_queue_event(&mut self) {
-        if let Err(err) = self.queue_events[RNG_QUEUE].read() {
+        if let Err(err) = self.queues[RNG_QUEUE].read_kicks(&self.queue_events[RNG_QUEUE]) {
             error!("Failed to read entropy queue event: {err}");
             METRICS.entropy_event_fails.inc();
         } else if !self.rate_limiter.is_blocked() {
@@ -221,6 +275,84 @@ impl Entropy {
         &self.rate_limiter
     }
 
+    /// Maximum number of bytes served during the lifetime of the microVM, if any.
+    pub fn lifetime_byte_limit(&self) -> Option<u64> {
+        self.lifetime_byte_limit
+    }
+
+    /// Sets the maximum number of bytes served during the lifetime of the microVM.
+    pub fn set_lifetime_byte_limit(&mut self, limit: Option<u64>) {
+        self.lifetime_byte_limit = limit;
+    }
+
+    /// Number of bytes served during the lifetime of the microVM.
+    pub fn bytes_served(&self) -> u64 {
+        self.bytes_served
+    }
+
+    pub(crate) fn set_bytes_served(&mut self, bytes_served: u64) {
+        self.bytes_served = bytes_served;
+    }
+
+    /// Number of requests throttled by the rate limiter during the lifetime of the microVM.
+    pub fn requests_throttled(&self) -> u64 {
+        self.requests_throttled
+    }
+
+    pub(crate) fn set_requests_throttled(&mut self, requests_throttled: u64) {
+        self.requests_throttled = requests_throttled;
+    }
+
+    /// Whether the entropy source is checked on activation.
+    pub fn quality_check(&self) -> bool {
+        self.quality_check
+    }
+
+    /// Sets whether the entropy source is checked on activation.
+    pub fn set_quality_check(&mut self, quality_check: bool) {
+        self.quality_check = quality_check;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_source(&mut self, source: EntropySource) {
+        self.source = source;
+    }
+
+    /// Samples the entropy source and rejects it if the sample is obviously not random, such
+    /// as constant or heavily biased output. This is a sanity check against broken sources,
+    /// not a statistical test of their quality.
+    fn check_source_quality(&self) -> Result<(), EntropyError> {
+        let mut sample = [0u8; QUALITY_SAMPLE_LEN];
+        (self.source)(&mut sample)?;
+
+        let mut seen = [false; 256];
+        for byte in sample {
+            seen[usize::from(byte)] = true;
+        }
+        let distinct = seen.iter().filter(|seen| **seen).count();
+        if distinct < QUALITY_MIN_DISTINCT_BYTES {
+            return Err(EntropyError::LowQuality(format!(
+                "only {distinct} distinct byte values in {QUALITY_SAMPLE_LEN} bytes"
+            )));
+        }
+
+        let ones: u32 = sample.iter().map(|byte| byte.count_ones()).sum();
+        let half = u32::try_from(QUALITY_SAMPLE_LEN * 4).unwrap();
+        if ones.abs_diff(half) > QUALITY_MAX_BIT_BIAS {
+            return Err(EntropyError::LowQuality(format!(
+                "{ones} bits set out of {}",
+                2 * half
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn lifetime_limit_reached(&self, bytes: u64) -> bool {
+        self.lifetime_byte_limit
+            .is_some_and(|limit| self.bytes_served.saturating_add(bytes) > limit)
+    }
+
     pub(crate) fn set_avail_features(&mut self, features: u64) {
         self.avail_features = features;
     }
@@ -288,6 +420,13 @@ impl VirtioDevice for Entropy {
     }
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> Result<(), ActivateError> {
+        if self.quality_check {
+            self.check_source_quality().map_err(|err| {
+                error!("entropy: {err}");
+                METRICS.activate_fails.inc();
+                ActivateError::BadActivate
+            })?;
+        }
         self.activate_event.write(1).map_err(|err| {
             error!("entropy: Cannot write to activate_evt: {err}");
             METRICS.activate_fails.inc();
@@ -296,6 +435,19 @@ impl VirtioDevice for Entropy {
         self.device_state = DeviceState::Activated(mem);
         Ok(())
     }
+
+    fn rate_limiter_state(&self) -> Vec<(&'static str, RateLimiterBudgets)> {
+        vec![("rate_limiter", self.rate_limiter.budgets())]
+    }
+
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let mut rand_bytes = [0u8; 16];
+        (self.source)(&mut rand_bytes).map_err(|err| SelfTestError::Entropy(err.to_string()))
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
 }
 
 #[cfg(test)]
@@ -462,6 +614,8 @@ mod tests {
         assert_eq!(METRICS.entropy_event_count.count(), entropy_event_count + 1);
         assert_eq!(METRICS.entropy_bytes.count(), entropy_bytes);
         assert_eq!(METRICS.host_rng_fails.count(), host_rng_fails);
+        let (_, last_error) = th.device().last_error().unwrap();
+        assert!(last_error.starts_with("Could not parse descriptor chain"));
 
         // Add two good descriptors
         th.add_desc_chain(RNG_QUEUE, 0, &[(1, 10, VIRTQ_DESC_F_WRITE)]);
@@ -560,6 +714,56 @@ mod tests {
         assert!(!th.device().rate_limiter().is_blocked());
     }
 
+    #[test]
+    fn test_lifetime_byte_limit() {
+        let mem = create_virtio_mem();
+        let mut device = default_entropy();
+        device.set_lifetime_byte_limit(Some(100));
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, device);
+
+        th.activate_device(&mem);
+
+        // Requests within the limit are served.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            METRICS.entropy_bytes,
+            64,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().bytes_served(), 64);
+
+        // A request that would exceed the limit is completed without any bytes.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        let entropy_bytes = METRICS.entropy_bytes.count();
+        check_metric_after_block!(
+            METRICS.entropy_lifetime_limit_denied,
+            1,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(METRICS.entropy_bytes.count(), entropy_bytes);
+        assert_eq!(th.device().bytes_served(), 64);
+        let used = th.device().queues()[RNG_QUEUE].next_used;
+        assert_eq!(used.0, 2);
+
+        // The remaining budget can still be used.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 36, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            METRICS.entropy_bytes,
+            36,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().bytes_served(), 100);
+
+        // Once exhausted, any further request is denied.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 1, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            METRICS.entropy_lifetime_limit_denied,
+            1,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().bytes_served(), 100);
+    }
+
     #[test]
     fn test_ops_rate_limiter() {
         let mem = create_virtio_mem();
@@ -610,4 +814,90 @@ mod tests {
         // The rate limiter event should have processed the pending buffer as well
         assert_eq!(METRICS.entropy_bytes.count(), entropy_bytes + 128);
     }
+
+    #[test]
+    fn test_throttled_requests_count() {
+        let mem = create_virtio_mem();
+        // Rate Limiter with unlimited bandwidth and a single operation, which is not replenished
+        // during the test.
+        let device = Entropy::new(RateLimiter::new(0, 0, 0, 1, 0, 10_000).unwrap()).unwrap();
+        let mut th = VirtioTestHelper::<Entropy>::new(&mem, device);
+
+        th.activate_device(&mem);
+
+        // The first request drains the bucket.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            METRICS.entropy_bytes,
+            64,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().requests_throttled(), 0);
+        assert_eq!(th.device().bytes_served(), 64);
+
+        // Every attempt to serve the next request is throttled.
+        th.add_desc_chain(RNG_QUEUE, 0, &[(0, 64, VIRTQ_DESC_F_WRITE)]);
+        check_metric_after_block!(
+            METRICS.entropy_rate_limiter_throttled,
+            1,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().requests_throttled(), 1);
+        check_metric_after_block!(
+            METRICS.entropy_rate_limiter_throttled,
+            1,
+            th.device().process_entropy_queue()
+        );
+        assert_eq!(th.device().requests_throttled(), 2);
+        assert_eq!(th.device().bytes_served(), 64);
+    }
+
+    #[test]
+    fn test_quality_check() {
+        fn constant_source(buf: &mut [u8]) -> Result<(), aws_lc_rs::error::Unspecified> {
+            buf.fill(0xa5);
+            Ok(())
+        }
+        fn biased_source(buf: &mut [u8]) -> Result<(), aws_lc_rs::error::Unspecified> {
+            // Plenty of distinct byte values, but all with at least 5 of their 8 bits set.
+            let values = (0..=u8::MAX)
+                .filter(|value| value.count_ones() >= 5)
+                .cycle();
+            for (byte, value) in buf.iter_mut().zip(values) {
+                *byte = value;
+            }
+            Ok(())
+        }
+
+        let mem = create_virtio_mem();
+
+        // A constant source is rejected on activation.
+        let mut entropy_dev = default_entropy();
+        entropy_dev.set_source(constant_source);
+        entropy_dev.set_quality_check(true);
+        let err = entropy_dev.check_source_quality().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entropy source failed the quality check: only 1 distinct byte values in 256 bytes"
+        );
+        let activate_fails = METRICS.activate_fails.count();
+        entropy_dev.activate(mem.clone()).unwrap_err();
+        assert!(!entropy_dev.is_activated());
+        assert_eq!(METRICS.activate_fails.count(), activate_fails + 1);
+
+        // So is a heavily biased one.
+        entropy_dev.set_source(biased_source);
+        let err = entropy_dev.check_source_quality().unwrap_err();
+        assert!(err.to_string().ends_with("bits set out of 2048"), "{err}");
+
+        // The check is opt-in.
+        entropy_dev.set_quality_check(false);
+        entropy_dev.activate(mem.clone()).unwrap();
+
+        // The default source passes.
+        let mut entropy_dev = default_entropy();
+        entropy_dev.set_quality_check(true);
+        entropy_dev.activate(mem).unwrap();
+        assert!(entropy_dev.is_activated());
+    }
 }
@@ -64,6 +64,8 @@ pub(super) struct EntropyDeviceMetrics {
     pub entropy_rate_limiter_throttled: SharedIncMetric,
     /// Number of events associated with the rate limiter
     pub rate_limiter_event_count: SharedIncMetric,
+    /// Number of entropy requests denied because the lifetime limit was reached
+    pub entropy_lifetime_limit_denied: SharedIncMetric,
 }
 impl EntropyDeviceMetrics {
     /// Const default construction.
@@ -76,6 +78,7 @@ impl EntropyDeviceMetrics {
             host_rng_fails: SharedIncMetric::new(),
             entropy_rate_limiter_throttled: SharedIncMetric::new(),
             rate_limiter_event_count: SharedIncMetric::new(),
+            entropy_lifetime_limit_denied: SharedIncMetric::new(),
         }
     }
 }
@@ -18,6 +18,10 @@ use crate::vstate::memory::GuestMemoryMmap;
 pub struct EntropyState {
     virtio_state: VirtioDeviceState,
     rate_limiter_state: RateLimiterState,
+    lifetime_byte_limit: Option<u64>,
+    bytes_served: u64,
+    requests_throttled: u64,
+    quality_check: bool,
 }
 
 #[derive(Debug)]
@@ -48,6 +52,10 @@ impl Persist<'_> for Entropy {
         EntropyState {
             virtio_state: VirtioDeviceState::from_device(self),
             rate_limiter_state: self.rate_limiter().save(),
+            lifetime_byte_limit: self.lifetime_byte_limit(),
+            bytes_served: self.bytes_served(),
+            requests_throttled: self.requests_throttled(),
+            quality_check: self.quality_check(),
         }
     }
 
@@ -67,6 +75,10 @@ impl Persist<'_> for Entropy {
         entropy.set_avail_features(state.virtio_state.avail_features);
         entropy.set_acked_features(state.virtio_state.acked_features);
         entropy.set_irq_status(state.virtio_state.interrupt_status);
+        entropy.set_lifetime_byte_limit(state.lifetime_byte_limit);
+        entropy.set_bytes_served(state.bytes_served);
+        entropy.set_requests_throttled(state.requests_throttled);
+        entropy.set_quality_check(state.quality_check);
         if state.virtio_state.activated {
             entropy.set_activated(constructor_args.0);
         }
@@ -109,4 +121,45 @@ mod tests {
             entropy.interrupt_status().load(Ordering::Relaxed)
         );
     }
+
+    #[test]
+    fn test_lifetime_byte_limit_persistence() {
+        let mut mem = vec![0u8; 4096];
+        let mut entropy = Entropy::new(RateLimiter::default()).unwrap();
+        entropy.set_lifetime_byte_limit(Some(100));
+        // Pretend the limit was exhausted before taking the snapshot.
+        entropy.set_bytes_served(100);
+
+        Snapshot::serialize(&mut mem.as_mut_slice(), &entropy.save()).unwrap();
+
+        let guest_mem = create_virtio_mem();
+        let restored = Entropy::restore(
+            EntropyConstructorArgs(guest_mem),
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.lifetime_byte_limit(), Some(100));
+        assert_eq!(restored.bytes_served(), 100);
+    }
+
+    #[test]
+    fn test_counters_persistence() {
+        let mut mem = vec![0u8; 4096];
+        let mut entropy = Entropy::new(RateLimiter::default()).unwrap();
+        entropy.set_bytes_served(4096);
+        entropy.set_requests_throttled(7);
+
+        Snapshot::serialize(&mut mem.as_mut_slice(), &entropy.save()).unwrap();
+
+        let guest_mem = create_virtio_mem();
+        let restored = Entropy::restore(
+            EntropyConstructorArgs(guest_mem),
+            &Snapshot::deserialize(&mut mem.as_slice()).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.bytes_served(), 4096);
+        assert_eq!(restored.requests_throttled(), 7);
+    }
 }
@@ -4,6 +4,7 @@
 // Portions Copyright 2019 Intel Corporation. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::num::Wrapping;
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixStream;
 
@@ -42,6 +43,8 @@ pub enum VhostUserError {
     VhostUserSetVringNum(VhostError),
     /// Set vring addr failed: {0}
     VhostUserSetVringAddr(VhostError),
+    /// Get vring base failed: {0}
+    VhostUserGetVringBase(VhostError),
     /// Set vring base failed: {0}
     VhostUserSetVringBase(VhostError),
     /// Set vring call failed: {0}
@@ -110,6 +113,11 @@ pub trait VhostUserHandleBackend: Sized {
         unimplemented!()
     }
 
+    /// Stops the vring and gets the base offset in the available vring.
+    fn get_vring_base(&self, _queue_index: usize) -> Result<u32, vhost::Error> {
+        unimplemented!()
+    }
+
     /// Set the event file descriptor to signal when buffers are used.
     /// Bits (0-7) of the payload contain the vring index. Bit 8 is the invalid FD flag. This flag
     /// is set when there is no file descriptor in the ancillary data. This signals that polling
@@ -210,6 +218,11 @@ impl VhostUserHandleBackend for Frontend {
         <Frontend as VhostBackend>::set_vring_base(self, queue_index, base)
     }
 
+    /// Stops the vring and gets the base offset in the available vring.
+    fn get_vring_base(&self, queue_index: usize) -> Result<u32, vhost::Error> {
+        <Frontend as VhostBackend>::get_vring_base(self, queue_index)
+    }
+
     /// Set the event file descriptor to signal when buffers are used.
     /// Bits (0-7) of the payload contain the vring index. Bit 8 is the invalid FD flag. This flag
     /// is set when there is no file descriptor in the ancillary data. This signals that polling
@@ -394,6 +407,50 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
         Ok(())
     }
 
+    /// Stop a vring of the vhost-user backend and return the index of the next available
+    /// descriptor the backend would have processed. The vring stays stopped until it is set up
+    /// again with `setup_backend`.
+    fn get_vring_base(&self, queue_index: usize) -> Result<Wrapping<u16>, VhostUserError> {
+        let base = self
+            .vu
+            .get_vring_base(queue_index)
+            .map_err(VhostUserError::VhostUserGetVringBase)?;
+        // The vring index is a 16 bit value, the upper bits of the reply are unused.
+        #[allow(clippy::cast_possible_truncation)]
+        let base = base as u16;
+        Ok(Wrapping(base))
+    }
+
+    /// Stop the vrings of the backend to record in `queues` where the backend stopped, then
+    /// set them up again from there.
+    ///
+    /// Returns the number of requests the backend took from the queues without completing them.
+    pub fn save_vring_bases(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        queues: &mut [Queue],
+        queue_evts: &[EventFd],
+        irq_trigger: &IrqTrigger,
+    ) -> Result<u16, VhostUserError> {
+        let mut in_flight = 0u16;
+        for (queue_index, queue) in queues.iter_mut().enumerate() {
+            let next_avail = self.get_vring_base(queue_index)?;
+            let used_idx = queue.used_idx(mem);
+            in_flight = (next_avail - used_idx).0.saturating_add(in_flight);
+            queue.next_avail = next_avail;
+            queue.next_used = used_idx;
+        }
+
+        let queues: Vec<_> = queues
+            .iter()
+            .zip(queue_evts)
+            .enumerate()
+            .map(|(index, (queue, queue_evt))| (index, queue, queue_evt))
+            .collect();
+        self.setup_backend(mem, &queues, irq_trigger)?;
+        Ok(in_flight)
+    }
+
     /// Set up vhost-user backend. This includes updating memory table,
     /// sending information about virtio rings and enabling them.
     pub fn setup_backend(
@@ -435,8 +492,10 @@ impl<T: VhostUserHandleBackend> VhostUserHandleImpl<T> {
             self.vu
                 .set_vring_addr(*queue_index, &config_data)
                 .map_err(VhostUserError::VhostUserSetVringAddr)?;
+            // The backend resumes from the first request the frontend has not seen completed,
+            // which is the start of the ring unless the queue was restored from a snapshot.
             self.vu
-                .set_vring_base(*queue_index, queue.avail_idx(mem).0)
+                .set_vring_base(*queue_index, queue.next_avail.0)
                 .map_err(VhostUserError::VhostUserSetVringBase)?;
 
             // No matter the queue, we set irq_evt for signaling the guest that buffers were
@@ -914,7 +973,7 @@ mod tests {
                 avail_ring_addr: guest_memory.get_host_address(queue.avail_ring).unwrap() as u64,
                 log_addr: None,
             },
-            base: queue.avail_idx(&guest_memory).0,
+            base: queue.next_avail.0,
             call: irq_trigger.irq_evt.as_raw_fd(),
             kick: event_fd.as_raw_fd(),
             enable: true,
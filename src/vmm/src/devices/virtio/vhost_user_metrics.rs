@@ -104,6 +104,15 @@ impl VhostUserMetricsPerDevice {
                 .or_insert_with(|| Arc::new(VhostUserDeviceMetrics::default())),
         )
     }
+
+    /// Moves the metrics of the vhost_user device having id `old_id` under `new_id`, so that
+    /// they keep being reported after the device is renamed.
+    pub fn rename(old_id: &str, new_id: String) {
+        let mut per_device = METRICS.write().unwrap();
+        if let Some(metrics) = per_device.metrics.remove(old_id) {
+            per_device.metrics.insert(new_id, metrics);
+        }
+    }
 }
 
 /// Pool of vhost_user-related metrics per device behind a lock to
@@ -26,16 +26,17 @@ use std::sync::Arc;
 use log::{error, warn};
 use utils::byte_order;
 use utils::eventfd::EventFd;
+use utils::time::TimestampUs;
 
 use super::super::super::DeviceError;
 use super::defs::uapi;
 use super::packet::{VsockPacket, VSOCK_PKT_HDR_SIZE};
 use super::{defs, VsockBackend};
-use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, VirtioDevice};
+use crate::devices::virtio::device::{DeviceState, IrqTrigger, IrqType, LastError, VirtioDevice};
 use crate::devices::virtio::queue::Queue as VirtQueue;
 use crate::devices::virtio::vsock::metrics::METRICS;
 use crate::devices::virtio::vsock::VsockError;
-use crate::devices::virtio::ActivateError;
+use crate::devices::virtio::{ActivateError, SelfTestError};
 use crate::logger::IncMetric;
 use crate::vstate::memory::{Bytes, GuestMemoryMmap};
 
@@ -69,6 +70,8 @@ pub struct Vsock<B> {
     // continuous triggers from happening before the device gets activated.
     pub(crate) activate_evt: EventFd,
     pub(crate) device_state: DeviceState,
+    // Most recent error hit while processing the queues.
+    pub(crate) last_error: LastError,
 }
 
 // TODO: Detect / handle queue deadlock:
@@ -102,6 +105,7 @@ where
             irq_trigger: IrqTrigger::new().map_err(VsockError::EventFd)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(VsockError::EventFd)?,
             device_state: DeviceState::Inactive,
+            last_error: LastError::default(),
         })
     }
 
@@ -129,6 +133,11 @@ where
         &self.backend
     }
 
+    /// Provides mutable access to the backend.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> Result<(), DeviceError> {
@@ -175,6 +184,7 @@ where
                 }
                 Err(err) => {
                     warn!("vsock: RX queue error: {:?}. Discarding the package.", err);
+                    self.last_error.record(format!("RX queue error: {err}"));
                     0
                 }
             };
@@ -205,6 +215,8 @@ where
                 Ok(pkt) => pkt,
                 Err(err) => {
                     error!("vsock: error reading TX packet: {:?}", err);
+                    self.last_error
+                        .record(format!("Error reading TX packet: {err}"));
                     have_used = true;
                     self.queues[TXQ_INDEX]
                         .add_used(mem, index, 0)
@@ -352,6 +364,23 @@ where
     fn is_activated(&self) -> bool {
         self.device_state.is_activated()
     }
+
+    fn host_fd_count(&self) -> usize {
+        // Queue eventfds, interrupt eventfd and the host-side listening socket.
+        self.queue_events.len() + 2
+    }
+
+    fn teardown(&mut self) {
+        self.backend.teardown();
+    }
+
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        self.backend.self_test()
+    }
+
+    fn last_error(&self) -> Option<(TimestampUs, String)> {
+        self.last_error.get()
+    }
 }
 
 #[cfg(test)]
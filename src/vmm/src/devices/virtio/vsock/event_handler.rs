@@ -54,7 +54,7 @@ where
         }
 
         let mut raise_irq = false;
-        if let Err(err) = self.queue_events[RXQ_INDEX].read() {
+        if let Err(err) = self.queues[RXQ_INDEX].read_kicks(&self.queue_events[RXQ_INDEX]) {
             error!("Failed to get vsock rx queue event: {:?}", err);
             METRICS.rx_queue_event_fails.inc();
         } else if self.backend.has_pending_rx() {
@@ -72,7 +72,7 @@ where
         }
 
         let mut raise_irq = false;
-        if let Err(err) = self.queue_events[TXQ_INDEX].read() {
+        if let Err(err) = self.queues[TXQ_INDEX].read_kicks(&self.queue_events[TXQ_INDEX]) {
             error!("Failed to get vsock tx queue event: {:?}", err);
             METRICS.tx_queue_event_fails.inc();
         } else {
@@ -95,7 +95,7 @@ where
             return false;
         }
 
-        if let Err(err) = self.queue_events[EVQ_INDEX].read() {
+        if let Err(err) = self.queues[EVQ_INDEX].read_kicks(&self.queue_events[EVQ_INDEX]) {
             error!("Failed to consume vsock evq event: {:?}", err);
             METRICS.ev_queue_event_fails.inc();
         }
@@ -296,6 +296,8 @@ mod tests {
             // reached the backend.
             assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
             assert_eq!(ctx.device.backend.tx_ok_cnt, 0);
+            let (_, last_error) = ctx.device.last_error().unwrap();
+            assert!(last_error.starts_with("Error reading TX packet"));
         }
 
         // Test case: spurious TXQ_EVENT.
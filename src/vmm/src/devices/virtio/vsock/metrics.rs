@@ -86,6 +86,8 @@ pub(super) struct VsockDeviceMetrics {
     pub conns_killed: SharedIncMetric,
     /// Number of removed connections.
     pub conns_removed: SharedIncMetric,
+    /// Number of connections refused because the connection limit was reached.
+    pub conns_refused: SharedIncMetric,
     /// How many times the killq has been resynced.
     pub killq_resync: SharedIncMetric,
     /// How many flush fails have been seen.
@@ -94,6 +96,10 @@ pub(super) struct VsockDeviceMetrics {
     pub tx_write_fails: SharedIncMetric,
     /// Number of times read() has failed.
     pub rx_read_fails: SharedIncMetric,
+    /// Number of log records ingested from the guest logging port.
+    pub guest_log_records: SharedIncMetric,
+    /// Number of guest log records dropped because they were too long or too many.
+    pub guest_log_records_dropped: SharedIncMetric,
 }
 
 impl VsockDeviceMetrics {
@@ -117,10 +123,13 @@ impl VsockDeviceMetrics {
             conns_added: SharedIncMetric::new(),
             conns_killed: SharedIncMetric::new(),
             conns_removed: SharedIncMetric::new(),
+            conns_refused: SharedIncMetric::new(),
             killq_resync: SharedIncMetric::new(),
             tx_flush_fails: SharedIncMetric::new(),
             tx_write_fails: SharedIncMetric::new(),
             rx_read_fails: SharedIncMetric::new(),
+            guest_log_records: SharedIncMetric::new(),
+            guest_log_records_dropped: SharedIncMetric::new(),
         }
     }
 }
@@ -27,11 +27,12 @@ use utils::epoll::EventSet;
 use vm_memory::GuestMemoryError;
 
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
-pub use self::defs::VSOCK_DEV_ID;
+pub use self::defs::{VSOCK_DEV_ID, VSOCK_NUM_QUEUES};
 pub use self::device::Vsock;
-pub use self::unix::{VsockUnixBackend, VsockUnixBackendError};
+pub use self::unix::{QuiesceState, VsockUnixBackend, VsockUnixBackendError, MAX_CONNECTIONS};
 use crate::devices::virtio::iovec::IoVecError;
 use crate::devices::virtio::persist::PersistError as VirtioStateError;
+use crate::devices::virtio::SelfTestError;
 
 mod defs {
     use crate::devices::virtio::queue::FIRECRACKER_MAX_QUEUE_SIZE;
@@ -187,4 +188,12 @@ pub trait VsockChannel {
 /// The vsock backend, which is basically an epoll-event-driven vsock channel.
 /// Currently, the only implementation we have is `crate::devices::virtio::unix::muxer::VsockMuxer`,
 /// which translates guest-side vsock connections to host-side Unix domain socket connections.
-pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {}
+pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {
+    /// Releases the host resources of the backend before the process exits.
+    fn teardown(&mut self) {}
+
+    /// Checks that the host side of the backend is ready to accept connections.
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        Ok(())
+    }
+}
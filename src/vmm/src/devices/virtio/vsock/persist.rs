@@ -46,6 +46,12 @@ pub enum VsockBackendState {
 pub struct VsockUdsState {
     /// The path for the UDS socket.
     pub(crate) path: String,
+    /// The maximum number of concurrent connections.
+    pub(crate) max_connections: usize,
+    /// The guest port whose connections are ingested into the host log.
+    pub(crate) log_port: Option<u32>,
+    /// The guest port to which the guest agent connects for quiesce requests.
+    pub(crate) quiesce_port: Option<u32>,
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -72,6 +78,9 @@ impl Persist<'_> for VsockUnixBackend {
     fn save(&self) -> Self::State {
         VsockBackendState::Uds(VsockUdsState {
             path: self.host_sock_path.clone(),
+            max_connections: self.max_connections(),
+            log_port: self.log_port(),
+            quiesce_port: self.quiesce_port(),
         })
     }
 
@@ -83,6 +92,9 @@ impl Persist<'_> for VsockUnixBackend {
             VsockBackendState::Uds(uds_state) => Ok(VsockUnixBackend::new(
                 constructor_args.cid,
                 uds_state.path.clone(),
+                uds_state.max_connections,
+                uds_state.log_port,
+                uds_state.quiesce_port,
             )?),
         }
     }
@@ -135,6 +147,7 @@ where
 #[cfg(test)]
 pub(crate) mod tests {
     use utils::byte_order;
+    use utils::tempfile::TempFile;
 
     use super::device::AVAIL_FEATURES;
     use super::*;
@@ -151,6 +164,9 @@ pub(crate) mod tests {
         fn save(&self) -> Self::State {
             VsockBackendState::Uds(VsockUdsState {
                 path: "test".to_owned(),
+                max_connections: MAX_CONNECTIONS,
+                log_port: None,
+                quiesce_port: None,
             })
         }
 
@@ -161,6 +177,27 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_persist_max_connections() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let path = tmp_sock_file.as_path().to_str().unwrap().to_owned();
+        let backend = VsockUnixBackend::new(3, path.clone(), 5, Some(1024), Some(1025)).unwrap();
+
+        let mut mem = vec![0; 4096];
+        Snapshot::serialize(&mut mem.as_mut_slice(), &backend.save()).unwrap();
+        drop(backend);
+        std::fs::remove_file(&path).unwrap();
+
+        let restored_state: VsockBackendState = Snapshot::deserialize(&mut mem.as_slice()).unwrap();
+        let restored_backend =
+            VsockUnixBackend::restore(VsockUdsConstructorArgs { cid: 3 }, &restored_state).unwrap();
+        assert_eq!(restored_backend.max_connections(), 5);
+        assert_eq!(restored_backend.log_port(), Some(1024));
+        assert_eq!(restored_backend.quiesce_port(), Some(1025));
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_persist_uds_backend() {
         let ctx = TestContext::new();
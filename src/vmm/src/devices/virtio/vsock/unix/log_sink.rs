@@ -0,0 +1,126 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// `GuestLogSink` buffers the log records that the guest writes to the vsock logging port, until
+/// the muxer ingests them into the host log.
+///
+/// Records are newline-terminated. Both the number of buffered records and the length of a
+/// record are bounded, records beyond these bounds are dropped (and accounted for in the
+/// `guest_log_records_dropped` metric).
+use std::collections::VecDeque;
+
+use crate::devices::virtio::vsock::metrics::METRICS;
+use crate::logger::IncMetric;
+
+/// Maximum number of records buffered by the sink.
+pub const MAX_LOG_RECORDS: usize = 64;
+/// Maximum length of a record, in bytes.
+pub const MAX_LOG_RECORD_LEN: usize = 4096;
+
+/// Bounded buffer of guest log records.
+#[derive(Debug, Default)]
+pub struct GuestLogSink {
+    /// Complete records, waiting to be ingested.
+    records: VecDeque<String>,
+    /// The bytes of the record being written by the guest.
+    partial: Vec<u8>,
+    /// Whether the record being written by the guest exceeded `MAX_LOG_RECORD_LEN`.
+    partial_overflow: bool,
+}
+
+impl GuestLogSink {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of complete records waiting to be ingested.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns `true` if there are no complete records waiting to be ingested.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Appends bytes written by the guest, splitting them into records.
+    pub fn push_bytes(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let (chunk, complete) = match bytes.iter().position(|b| *b == b'\n') {
+                Some(pos) => (&bytes[..pos], true),
+                None => (bytes, false),
+            };
+            bytes = &bytes[(chunk.len() + usize::from(complete))..];
+
+            if self.partial.len() + chunk.len() > MAX_LOG_RECORD_LEN {
+                self.partial_overflow = true;
+            } else {
+                self.partial.extend_from_slice(chunk);
+            }
+            if complete {
+                self.end_record();
+            }
+        }
+    }
+
+    /// Ends the record being written by the guest, e.g. when the guest closes the connection.
+    pub fn end_record(&mut self) {
+        let record = std::mem::take(&mut self.partial);
+        let overflow = std::mem::replace(&mut self.partial_overflow, false);
+        if overflow || self.records.len() >= MAX_LOG_RECORDS {
+            METRICS.guest_log_records_dropped.inc();
+            return;
+        }
+        if !record.is_empty() {
+            self.records
+                .push_back(String::from_utf8_lossy(&record).into_owned());
+        }
+    }
+
+    /// Removes and returns the complete records.
+    pub fn drain(&mut self) -> impl Iterator<Item = String> + '_ {
+        self.records.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let mut sink = GuestLogSink::new();
+        sink.push_bytes(b"first");
+        assert!(sink.is_empty());
+        sink.push_bytes(b" record\n{\"level\": \"info\"}\n\npartial");
+        assert_eq!(sink.len(), 2);
+        assert_eq!(
+            sink.drain().collect::<Vec<_>>(),
+            vec!["first record", "{\"level\": \"info\"}"]
+        );
+
+        // The partial record is kept until it is ended.
+        sink.end_record();
+        assert_eq!(sink.drain().collect::<Vec<_>>(), vec!["partial"]);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut sink = GuestLogSink::new();
+        let dropped = METRICS.guest_log_records_dropped.count();
+
+        // Records longer than the maximum length are dropped.
+        sink.push_bytes(&[b'a'; MAX_LOG_RECORD_LEN]);
+        sink.push_bytes(b"a\nshort\n");
+        assert_eq!(sink.drain().collect::<Vec<_>>(), vec!["short"]);
+        assert_eq!(METRICS.guest_log_records_dropped.count(), dropped + 1);
+
+        // Records are dropped once the buffer is full.
+        for _ in 0..=MAX_LOG_RECORDS {
+            sink.push_bytes(b"record\n");
+        }
+        assert_eq!(sink.len(), MAX_LOG_RECORDS);
+        assert_eq!(METRICS.guest_log_records_dropped.count(), dropped + 2);
+    }
+}
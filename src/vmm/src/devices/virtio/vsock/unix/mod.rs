@@ -7,16 +7,21 @@
 /// `muxer::VsockMuxer`, a connection multiplexer that uses `super::csm::VsockConnection` for
 /// handling vsock connection states.
 /// Check out `muxer.rs` for a more detailed explanation of the inner workings of this backend.
+mod log_sink;
 mod muxer;
 mod muxer_killq;
 mod muxer_rxq;
+mod quiesce;
 
+pub use defs::MAX_CONNECTIONS;
 pub use muxer::VsockMuxer as VsockUnixBackend;
+pub use quiesce::QuiesceState;
 
 use crate::devices::virtio::vsock::csm::VsockConnectionBackend;
 
 mod defs {
-    /// Maximum number of established connections that we can handle.
+    /// Maximum number of established connections that we can handle, and the default limit of
+    /// concurrent connections of a vsock device.
     pub const MAX_CONNECTIONS: usize = 1023;
 
     /// Size of the muxer RX packet queue.
@@ -45,6 +50,8 @@ pub enum VsockUnixBackendError {
     UnixRead(std::io::Error),
     /// Muxer connection limit reached.
     TooManyConnections,
+    /// Error talking to the guest agent connected to the quiesce port: {0}
+    QuiesceAgent(std::io::Error),
 }
 
 type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
@@ -32,8 +32,10 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::Read;
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
 
 use log::{debug, error, info, warn};
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
@@ -42,10 +44,13 @@ use super::super::csm::ConnState;
 use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
 use super::super::{VsockBackend, VsockChannel, VsockEpollListener, VsockError};
+use super::log_sink::GuestLogSink;
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
+use super::quiesce::{QuiesceChannel, QuiesceState};
 use super::{defs, MuxerConnection, VsockUnixBackendError};
 use crate::devices::virtio::vsock::metrics::METRICS;
+use crate::devices::virtio::SelfTestError;
 use crate::logger::IncMetric;
 
 /// A unique identifier of a `MuxerConnection` object. Connections are stored in a hash map,
@@ -77,6 +82,12 @@ enum EpollListener {
     /// A listener interested in reading host `connect <port>` commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// A listener interested in reading the log records written by the guest to the logging
+    /// port. The stream is the host end of a connection to the logging port.
+    GuestLog(UnixStream),
+    /// A listener interested in reading the answers of the guest agent connected to the
+    /// quiesce port. The stream is held by the muxer's `QuiesceChannel`.
+    Quiesce,
 }
 
 /// The vsock connection multiplexer.
@@ -107,6 +118,17 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// Maximum number of concurrent connections. New connections beyond this limit are
+    /// refused.
+    max_connections: usize,
+    /// The guest port whose connections are ingested into the host log, if any.
+    log_port: Option<u32>,
+    /// The log records written by the guest to the logging port.
+    log_sink: GuestLogSink,
+    /// The guest port to which the guest agent connects for quiesce requests, if any.
+    quiesce_port: Option<u32>,
+    /// The connection of the guest agent to the quiesce port.
+    quiesce: QuiesceChannel,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -297,11 +319,40 @@ impl VsockEpollListener for VsockMuxer {
     }
 }
 
-impl VsockBackend for VsockMuxer {}
+impl VsockBackend for VsockMuxer {
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
+        // The listener is bound at creation, check that its socket file is still in place.
+        let metadata = std::fs::metadata(&self.host_sock_path).map_err(SelfTestError::Bind)?;
+        if !metadata.file_type().is_socket() {
+            return Err(SelfTestError::Bind(std::io::Error::from(
+                std::io::ErrorKind::AddrNotAvailable,
+            )));
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        // The socket file outlives the listener, remove it so that its path can be reused.
+        match std::fs::remove_file(&self.host_sock_path) {
+            Ok(()) => debug!("vsock: removed host socket {}", self.host_sock_path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => warn!(
+                "vsock: failed to remove host socket {}: {}",
+                self.host_sock_path, err
+            ),
+        }
+    }
+}
 
 impl VsockMuxer {
     /// Muxer constructor.
-    pub fn new(cid: u64, host_sock_path: String) -> Result<Self, VsockUnixBackendError> {
+    pub fn new(
+        cid: u64,
+        host_sock_path: String,
+        max_connections: usize,
+        log_port: Option<u32>,
+        quiesce_port: Option<u32>,
+    ) -> Result<Self, VsockUnixBackendError> {
         // Open/bind on the host Unix socket, so we can accept host-initiated
         // connections.
         let host_sock = UnixListener::bind(&host_sock_path)
@@ -314,11 +365,16 @@ impl VsockMuxer {
             host_sock_path,
             epoll: Epoll::new().map_err(VsockUnixBackendError::EpollFdCreate)?,
             rxq: MuxerRxQ::new(),
-            conn_map: HashMap::with_capacity(defs::MAX_CONNECTIONS),
-            listener_map: HashMap::with_capacity(defs::MAX_CONNECTIONS + 1),
+            conn_map: HashMap::with_capacity(max_connections),
+            listener_map: HashMap::with_capacity(max_connections + 1),
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
-            local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            local_port_set: HashSet::with_capacity(max_connections),
+            max_connections,
+            log_port,
+            log_sink: GuestLogSink::new(),
+            quiesce_port,
+            quiesce: QuiesceChannel::new(),
         };
 
         // Listen on the host initiated socket, for incoming connections.
@@ -331,6 +387,41 @@ impl VsockMuxer {
         &self.host_sock_path
     }
 
+    /// Return the maximum number of concurrent connections.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Return the guest port whose connections are ingested into the host log, if any.
+    pub fn log_port(&self) -> Option<u32> {
+        self.log_port
+    }
+
+    /// Return the guest port to which the guest agent connects for quiesce requests, if any.
+    pub fn quiesce_port(&self) -> Option<u32> {
+        self.quiesce_port
+    }
+
+    /// Ask the guest agent connected to the quiesce port to quiesce the guest, and to ack
+    /// within `timeout`.
+    pub fn request_quiesce(&mut self, timeout: Duration) -> Result<(), VsockUnixBackendError> {
+        self.quiesce
+            .request(timeout)
+            .map_err(VsockUnixBackendError::QuiesceAgent)
+    }
+
+    /// Ask the guest agent to undo the quiescing, if a quiesce was requested.
+    pub fn thaw(&mut self) -> Result<(), VsockUnixBackendError> {
+        self.quiesce
+            .thaw()
+            .map_err(VsockUnixBackendError::QuiesceAgent)
+    }
+
+    /// Return the state of the quiesce handshake with the guest agent.
+    pub fn quiesce_state(&self) -> QuiesceState {
+        self.quiesce.state()
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, event_set: EventSet) {
         debug!(
@@ -354,10 +445,11 @@ impl VsockMuxer {
 
             // A new host-initiated connection is ready to be accepted.
             Some(EpollListener::HostSock) => {
-                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                if self.conn_map.len() >= self.max_connections {
                     // If we're already maxed-out on connections, we'll just accept and
                     // immediately discard this potentially new one.
                     warn!("vsock: connection limit reached; refusing new host connection");
+                    METRICS.conns_refused.inc();
                     self.host_sock.accept().map(|_| 0).unwrap_or(0);
                     return;
                 }
@@ -409,6 +501,38 @@ impl VsockMuxer {
                 }
             }
 
+            // The guest wrote log records to the logging port, or closed its connection.
+            Some(EpollListener::GuestLog(stream)) => {
+                let mut buf = [0u8; 4096];
+                let closed = loop {
+                    match stream.read(&mut buf) {
+                        Ok(0) => break true,
+                        Ok(len) => self.log_sink.push_bytes(&buf[..len]),
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break false,
+                        Err(err) => {
+                            warn!("vsock: error reading guest log records: {:?}", err);
+                            break true;
+                        }
+                    }
+                };
+                if closed {
+                    self.log_sink.end_record();
+                    self.remove_listener(fd);
+                }
+                for record in self.log_sink.drain() {
+                    info!("[guest] {}", record);
+                    METRICS.guest_log_records.inc();
+                }
+            }
+
+            // The guest agent answered a quiesce request, or closed its connection.
+            Some(EpollListener::Quiesce) => {
+                if self.quiesce.process_input() {
+                    self.remove_listener(fd);
+                    self.quiesce.disconnect();
+                }
+            }
+
             _ => {
                 info!(
                     "vsock: unexpected event: fd={:?}, evset={:?}",
@@ -481,11 +605,12 @@ impl VsockMuxer {
         // - we are under no pressure to respect any accurate timing for connection termination.
         self.sweep_killq();
 
-        if self.conn_map.len() >= defs::MAX_CONNECTIONS {
+        if self.conn_map.len() >= self.max_connections {
             info!(
                 "vsock: muxer connection limit reached ({})",
-                defs::MAX_CONNECTIONS
+                self.max_connections
             );
+            METRICS.conns_refused.inc();
             return Err(VsockUnixBackendError::TooManyConnections);
         }
 
@@ -548,6 +673,8 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => EventSet::IN,
             EpollListener::HostSock => EventSet::IN,
+            EpollListener::GuestLog(_) => EventSet::IN,
+            EpollListener::Quiesce => EventSet::IN,
         };
 
         self.epoll
@@ -609,9 +736,16 @@ impl VsockMuxer {
     /// connection object will be created and added to the connection pool. On failure, a new
     /// RST packet will be scheduled for delivery to the guest.
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
-        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        let stream = if Some(pkt.dst_port()) == self.log_port {
+            self.connect_log_stream()
+        } else if Some(pkt.dst_port()) == self.quiesce_port {
+            self.connect_quiesce_stream()
+        } else {
+            let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+            UnixStream::connect(port_path)
+        };
 
-        UnixStream::connect(port_path)
+        stream
             .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
             .map_err(VsockUnixBackendError::UnixConnect)
             .and_then(|stream| {
@@ -633,6 +767,33 @@ impl VsockMuxer {
             .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
     }
 
+    /// Create a connection to the logging port.
+    ///
+    /// Returns one end of a Unix socket pair, the other end is read by the muxer, which
+    /// ingests the log records written by the guest into the host log.
+    fn connect_log_stream(&mut self) -> std::io::Result<UnixStream> {
+        let (stream, log_stream) = UnixStream::pair()?;
+        log_stream.set_nonblocking(true)?;
+        self.add_listener(log_stream.as_raw_fd(), EpollListener::GuestLog(log_stream))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
+        Ok(stream)
+    }
+
+    /// Create a connection to the quiesce port.
+    ///
+    /// Returns one end of a Unix socket pair, the other end is held by the muxer to send quiesce
+    /// requests to the guest agent. A new connection replaces the previous one.
+    fn connect_quiesce_stream(&mut self) -> std::io::Result<UnixStream> {
+        let (stream, agent_stream) = UnixStream::pair()?;
+        agent_stream.set_nonblocking(true)?;
+        self.remove_listener(self.quiesce.as_raw_fd());
+        self.quiesce.disconnect();
+        self.add_listener(agent_stream.as_raw_fd(), EpollListener::Quiesce)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
+        self.quiesce.connect(agent_stream);
+        Ok(stream)
+    }
+
     /// Perform an action that might mutate a connection's state.
     ///
     /// This is used as shorthand for repetitive tasks that need to be performed after a
@@ -794,6 +955,7 @@ mod tests {
     use utils::tempfile::TempFile;
 
     use super::super::super::csm::defs as csm_defs;
+    use super::super::quiesce::QUIESCE_REQUEST;
     use super::*;
     use crate::devices::virtio::vsock::device::{RXQ_INDEX, TXQ_INDEX};
     use crate::devices::virtio::vsock::test_utils;
@@ -813,7 +975,8 @@ mod tests {
 
     impl Drop for MuxerTestContext {
         fn drop(&mut self) {
-            std::fs::remove_file(self.muxer.host_sock_path.as_str()).unwrap();
+            // The socket file is already gone if the muxer was torn down.
+            let _ = std::fs::remove_file(self.muxer.host_sock_path.as_str());
         }
     }
 
@@ -845,7 +1008,9 @@ mod tests {
             )
             .unwrap();
 
-            let muxer = VsockMuxer::new(PEER_CID, get_file(name)).unwrap();
+            let muxer =
+                VsockMuxer::new(PEER_CID, get_file(name), defs::MAX_CONNECTIONS, None, None)
+                    .unwrap();
             Self {
                 _vsock_test_ctx: vsock_test_ctx,
                 rx_pkt,
@@ -999,6 +1164,18 @@ mod tests {
         assert_eq!(ctx.muxer.get_polled_evset(), EventSet::IN);
     }
 
+    #[test]
+    fn test_teardown() {
+        let mut ctx = MuxerTestContext::new("muxer_teardown");
+        let host_sock_path = ctx.muxer.host_sock_path().to_owned();
+        assert!(Path::new(&host_sock_path).exists());
+
+        ctx.muxer.teardown();
+        assert!(!Path::new(&host_sock_path).exists());
+        // Tearing down twice is harmless.
+        ctx.muxer.teardown();
+    }
+
     #[test]
     fn test_muxer_epoll_listener_regression() {
         let mut ctx = MuxerTestContext::new("muxer_epoll_listener");
@@ -1401,6 +1578,100 @@ mod tests {
         assert!(!ctx.muxer.has_pending_rx());
     }
 
+    #[test]
+    fn test_muxer_max_connections() {
+        let mut ctx = MuxerTestContext::new("muxer_max_connections");
+        ctx.muxer.max_connections = 2;
+        let local_port = 1026;
+        let peer_port_first = 1025;
+        let _listener = ctx.create_local_listener(local_port);
+
+        let conns_refused = METRICS.conns_refused.count();
+
+        // Connections up to the limit are accepted.
+        for peer_port in peer_port_first..peer_port_first + 2 {
+            ctx.init_tx_pkt(local_port, peer_port, uapi::VSOCK_OP_REQUEST);
+            ctx.send();
+            ctx.recv();
+            assert_eq!(ctx.rx_pkt.op(), uapi::VSOCK_OP_RESPONSE);
+            assert_eq!(ctx.rx_pkt.dst_port(), peer_port);
+        }
+        assert_eq!(ctx.muxer.conn_map.len(), 2);
+
+        // The next connection is refused with an RST.
+        ctx.init_tx_pkt(local_port, peer_port_first + 2, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.rx_pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(ctx.rx_pkt.src_port(), local_port);
+        assert_eq!(ctx.rx_pkt.dst_port(), peer_port_first + 2);
+        assert_eq!(ctx.muxer.conn_map.len(), 2);
+        assert_eq!(METRICS.conns_refused.count(), conns_refused + 1);
+    }
+
+    #[test]
+    fn test_muxer_log_port() {
+        let mut ctx = MuxerTestContext::new("muxer_log_port");
+        let log_port = 1030;
+        let peer_port = 1025;
+        ctx.muxer.log_port = Some(log_port);
+
+        // Connections to the logging port don't need a host listener.
+        ctx.init_tx_pkt(log_port, peer_port, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.rx_pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.rx_pkt.src_port(), log_port);
+        assert_eq!(ctx.rx_pkt.dst_port(), peer_port);
+
+        // Complete records written by the guest are ingested into the host log.
+        let records = METRICS.guest_log_records.count();
+        ctx.init_data_tx_pkt(log_port, peer_port, b"first\nsecond\npartial");
+        ctx.send();
+        ctx.notify_muxer();
+        assert_eq!(METRICS.guest_log_records.count(), records + 2);
+        assert!(ctx.muxer.log_sink.is_empty());
+    }
+
+    #[test]
+    fn test_muxer_quiesce_port() {
+        let mut ctx = MuxerTestContext::new("muxer_quiesce_port");
+        let quiesce_port = 1031;
+        let peer_port = 1025;
+        ctx.muxer.quiesce_port = Some(quiesce_port);
+        assert_eq!(ctx.muxer.quiesce_state(), QuiesceState::NoAgent);
+        ctx.muxer
+            .request_quiesce(Duration::from_secs(60))
+            .unwrap_err();
+
+        // The guest agent connects to the quiesce port.
+        ctx.init_tx_pkt(quiesce_port, peer_port, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.rx_pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.muxer.quiesce_state(), QuiesceState::Idle);
+
+        // The quiesce request is forwarded to the agent.
+        ctx.muxer.request_quiesce(Duration::from_secs(60)).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.rx_pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.rx_pkt.src_port(), quiesce_port);
+        assert_eq!(ctx.rx_pkt.dst_port(), peer_port);
+        let buf = test_utils::read_packet_data(&ctx.tx_pkt, QUIESCE_REQUEST.len());
+        assert_eq!(&buf, QUIESCE_REQUEST);
+        assert_eq!(ctx.muxer.quiesce_state(), QuiesceState::Requested);
+
+        // The guest is quiesced only once the agent acks.
+        ctx.init_data_tx_pkt(quiesce_port, peer_port, b"ACK\n");
+        ctx.send();
+        ctx.notify_muxer();
+        assert_eq!(ctx.muxer.quiesce_state(), QuiesceState::Quiesced);
+
+        ctx.muxer.thaw().unwrap();
+        assert_eq!(ctx.muxer.quiesce_state(), QuiesceState::Idle);
+    }
+
     #[test]
     fn test_regression_handshake() {
         // Address one of the issues found while fixing the following issue:
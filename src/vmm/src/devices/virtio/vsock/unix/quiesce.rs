@@ -0,0 +1,204 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// `QuiesceChannel` drives the quiesce handshake with the guest agent connected to the vsock
+/// quiesce port, used to take application-consistent snapshots.
+///
+/// The protocol is line-based. The host sends `QUIESCE` when a snapshot is about to be taken, and
+/// the agent answers `ACK` once it flushed the application buffers and froze the file systems.
+/// The host sends `THAW` when the microVM is resumed.
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Request sent to the agent to quiesce the guest.
+pub const QUIESCE_REQUEST: &[u8] = b"QUIESCE\n";
+/// Request sent to the agent to undo the quiescing.
+pub const THAW_REQUEST: &[u8] = b"THAW\n";
+/// Answer of the agent once the guest is quiesced.
+pub const QUIESCE_ACK: &str = "ACK";
+/// Maximum length of a line sent by the agent.
+const MAX_LINE_LEN: usize = 64;
+
+/// State of the quiesce handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum QuiesceState {
+    /// No agent is connected to the quiesce port.
+    NoAgent,
+    /// An agent is connected and no quiesce is in progress.
+    Idle,
+    /// The agent was asked to quiesce the guest and did not ack yet.
+    Requested,
+    /// The agent acked the quiesce request.
+    Quiesced,
+    /// The agent did not ack the quiesce request in time.
+    TimedOut,
+}
+
+/// The host end of the connection of the guest agent to the quiesce port.
+#[derive(Debug, Default)]
+pub struct QuiesceChannel {
+    stream: Option<UnixStream>,
+    /// The bytes of the line being written by the agent.
+    partial: Vec<u8>,
+    /// Deadline of the pending quiesce request, if any.
+    deadline: Option<Instant>,
+    acked: bool,
+}
+
+impl QuiesceChannel {
+    /// Creates a channel with no agent connected.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the state of the handshake.
+    pub fn state(&self) -> QuiesceState {
+        match (&self.stream, self.deadline) {
+            (None, _) => QuiesceState::NoAgent,
+            (Some(_), None) => QuiesceState::Idle,
+            (Some(_), Some(_)) if self.acked => QuiesceState::Quiesced,
+            (Some(_), Some(deadline)) if Instant::now() > deadline => QuiesceState::TimedOut,
+            (Some(_), Some(_)) => QuiesceState::Requested,
+        }
+    }
+
+    /// Uses `stream` to talk to the agent, replacing any previous connection.
+    pub fn connect(&mut self, stream: UnixStream) {
+        *self = Self {
+            stream: Some(stream),
+            ..Default::default()
+        };
+    }
+
+    /// Drops the connection to the agent.
+    pub fn disconnect(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Asks the agent to quiesce the guest, and to ack within `timeout`.
+    pub fn request(&mut self, timeout: Duration) -> std::io::Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected))?;
+        stream.write_all(QUIESCE_REQUEST)?;
+        self.deadline = Some(Instant::now() + timeout);
+        self.acked = false;
+        Ok(())
+    }
+
+    /// Asks the agent to undo the quiescing, if a quiesce was requested.
+    pub fn thaw(&mut self) -> std::io::Result<()> {
+        if self.deadline.take().is_none() {
+            return Ok(());
+        }
+        self.acked = false;
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_all(THAW_REQUEST),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the answers of the agent. Returns `true` if the agent closed the connection.
+    pub fn process_input(&mut self) -> bool {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return false,
+        };
+
+        let mut buf = [0u8; MAX_LINE_LEN];
+        let closed = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break true,
+                Ok(len) => self.partial.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+
+        while let Some(pos) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=pos).collect();
+            // Late acks don't count, the agent may have resumed the guest by then.
+            if String::from_utf8_lossy(&line).trim() == QUIESCE_ACK
+                && self.state() == QuiesceState::Requested
+            {
+                self.acked = true;
+            }
+        }
+        if self.partial.len() > MAX_LINE_LEN {
+            self.partial.clear();
+        }
+        closed
+    }
+}
+
+impl AsRawFd for QuiesceChannel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_ref().map_or(-1, |stream| stream.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected_channel() -> (QuiesceChannel, UnixStream) {
+        let (agent, host) = UnixStream::pair().unwrap();
+        host.set_nonblocking(true).unwrap();
+        let mut channel = QuiesceChannel::new();
+        channel.connect(host);
+        (channel, agent)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut channel, mut agent) = connected_channel();
+        assert_eq!(channel.state(), QuiesceState::Idle);
+
+        channel.request(Duration::from_secs(60)).unwrap();
+        assert_eq!(channel.state(), QuiesceState::Requested);
+        let mut buf = [0u8; QUIESCE_REQUEST.len()];
+        agent.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, QUIESCE_REQUEST);
+
+        // The ack may be split across reads.
+        agent.write_all(b"AC").unwrap();
+        assert!(!channel.process_input());
+        assert_eq!(channel.state(), QuiesceState::Requested);
+        agent.write_all(b"K\n").unwrap();
+        assert!(!channel.process_input());
+        assert_eq!(channel.state(), QuiesceState::Quiesced);
+
+        channel.thaw().unwrap();
+        assert_eq!(channel.state(), QuiesceState::Idle);
+        let mut buf = [0u8; THAW_REQUEST.len()];
+        agent.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, THAW_REQUEST);
+
+        drop(agent);
+        assert!(channel.process_input());
+        channel.disconnect();
+        assert_eq!(channel.state(), QuiesceState::NoAgent);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (mut channel, mut agent) = connected_channel();
+        channel.request(Duration::ZERO).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(channel.state(), QuiesceState::TimedOut);
+
+        // A late ack doesn't make the guest quiesced.
+        agent.write_all(b"ACK\n").unwrap();
+        channel.process_input();
+        assert_eq!(channel.state(), QuiesceState::TimedOut);
+
+        let mut channel = QuiesceChannel::new();
+        assert_eq!(channel.state(), QuiesceState::NoAgent);
+        channel.request(Duration::ZERO).unwrap_err();
+    }
+}
@@ -25,6 +25,8 @@ pub const PAYLOAD_OFFSET: usize = 14;
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 /// Ethertype value for IPv4 packets.
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Ethertype value for Ethernet configuration testing protocol (loopback) frames.
+pub const ETHERTYPE_LOOPBACK: u16 = 0x9000;
 
 /// Describes the errors which may occur when handling Ethernet frames.
 #[derive(Debug, PartialEq, Eq, thiserror::Error, displaydoc::Display)]
@@ -110,9 +110,10 @@ pub mod vmm_config;
 /// Module with virtual state structs.
 pub mod vstate;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::Duration;
@@ -135,13 +136,21 @@ use crate::arch::DeviceType;
 use crate::cpu_config::templates::CpuConfiguration;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
-use crate::device_manager::mmio::MMIODeviceManager;
-use crate::devices::legacy::{IER_RDA_BIT, IER_RDA_OFFSET};
+use crate::device_manager::mmio::{MMIODeviceManager, TimeSnapshot};
+use crate::devices::legacy::{
+    PanicWatcher, SerialDevice, SerialDeviceState, SerialFile, SerialOut, SerialOutputBuffering,
+    SerialPersistError, SerialRxTriggerLevel, SerialSocket, IER_RDA_BIT, IER_RDA_OFFSET,
+};
 use crate::devices::virtio::balloon::{
-    Balloon, BalloonConfig, BalloonError, BalloonStats, BALLOON_DEV_ID,
+    Balloon, BalloonConfig, BalloonError, BalloonStats, MemoryStats, BALLOON_DEV_ID,
 };
 use crate::devices::virtio::block::device::Block;
+use crate::devices::virtio::block::{BackingInfo, CacheType};
+use crate::devices::virtio::input::InputEvent;
 use crate::devices::virtio::net::Net;
+use crate::devices::virtio::vsock::{
+    QuiesceState, Vsock, VsockUnixBackend, VsockUnixBackendError, TYPE_VSOCK, VSOCK_DEV_ID,
+};
 use crate::devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_NET};
 use crate::logger::{error, info, warn, MetricsError, METRICS};
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
@@ -149,7 +158,7 @@ use crate::rate_limiter::BucketUpdate;
 use crate::snapshot::Persist;
 use crate::vmm_config::instance_info::{InstanceInfo, VmState};
 use crate::vstate::memory::{
-    GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion,
+    DirtyRegions, GuestMemory, GuestMemoryExtension, GuestMemoryMmap, GuestMemoryRegion,
 };
 use crate::vstate::vcpu::VcpuState;
 pub use crate::vstate::vcpu::{Vcpu, VcpuConfig, VcpuEvent, VcpuHandle, VcpuResponse};
@@ -229,6 +238,8 @@ pub enum VmmError {
     SeccompFilters(seccompiler::InstallationError),
     /// Error writing to the serial console: {0}
     Serial(io::Error),
+    /// Error asking the guest agent to quiesce the guest: {0}
+    GuestQuiesce(VsockUnixBackendError),
     /// Error creating timer fd: {0}
     TimerFd(io::Error),
     /// Error configuring the vcpu for boot: {0}
@@ -353,6 +364,12 @@ impl Vmm {
         self.mmio_device_manager.get_device(device_type, device_id)
     }
 
+    /// Returns the GSIs allocated to the microVM devices, in ascending order, along with the
+    /// device they are assigned to. Useful to debug IRQ conflicts.
+    pub fn irq_assignments(&self) -> &BTreeMap<u32, String> {
+        self.resource_allocator.gsi_map()
+    }
+
     /// Starts the microVM vcpus.
     ///
     /// # Errors
@@ -423,9 +440,29 @@ impl Vmm {
         }
 
         self.instance_info.state = VmState::Running;
+
+        if let Some(Err(err)) = self.with_vsock_backend(|backend| backend.thaw()) {
+            warn!("Failed to ask the guest agent to thaw the guest: {}", err);
+        }
         Ok(())
     }
 
+    /// Whether a vCPU exit, such as a reset requested by the guest, is waiting to be handled by
+    /// the event loop.
+    pub fn exit_pending(&self) -> bool {
+        // The exit eventfd is non-blocking, so reading it fails unless an exit is pending. The
+        // counter is written back right away for the event loop to handle the exit.
+        match self.vcpus_exit_evt.read() {
+            Ok(count) => {
+                if let Err(err) = self.vcpus_exit_evt.write(count) {
+                    error!("Failed to re-arm the vCPU exit event: {}", err);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
     /// Sends a pause command to the vCPUs.
     pub fn pause_vm(&mut self) -> Result<(), VmmError> {
         // Send the events.
@@ -448,19 +485,171 @@ impl Vmm {
         Ok(())
     }
 
+    /// Replaces the VMGenID generation ID with a new one, and notifies the guest about it.
+    #[cfg(target_arch = "x86_64")]
+    pub fn regenerate_vmgenid(&mut self) -> Result<(), VmmError> {
+        if let Some(vmgenid) = self.acpi_device_manager.vmgenid.as_mut() {
+            vmgenid.regenerate(&self.guest_memory)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current VMGenID generation ID, if the microVM has a VMGenID device.
+    #[cfg(target_arch = "x86_64")]
+    pub fn vmgenid_value(&self) -> Option<u128> {
+        self.acpi_device_manager.vmgenid_value()
+    }
+
+    /// Returns the fd signaled when the guest rings the VMGenID doorbell, if there is one.
+    #[cfg(target_arch = "x86_64")]
+    fn vmgenid_doorbell_fd(&self) -> Option<RawFd> {
+        self.acpi_device_manager
+            .vmgenid
+            .as_ref()
+            .map(|vmgenid| vmgenid.doorbell_evt.as_raw_fd())
+    }
+
+    /// Returns the fd signaled when the guest rings the VMGenID doorbell, if there is one.
+    #[cfg(target_arch = "aarch64")]
+    fn vmgenid_doorbell_fd(&self) -> Option<RawFd> {
+        None
+    }
+
     /// Returns a reference to the inner `GuestMemoryMmap` object.
     pub fn guest_memory(&self) -> &GuestMemoryMmap {
         &self.guest_memory
     }
 
+    /// Runs `f` on the serial console, if there is one.
+    fn with_serial<R>(&self, f: impl FnOnce(&mut SerialDevice<std::io::Stdin>) -> R) -> Option<R> {
+        #[cfg(target_arch = "aarch64")]
+        let serial_bus_device = self.get_bus_device(DeviceType::Serial, "Serial")?;
+        #[cfg(target_arch = "x86_64")]
+        let serial_bus_device = &self.pio_device_manager.stdio_serial;
+
+        let mut locked_device = serial_bus_device.lock().expect("Poisoned lock");
+        let serial = locked_device
+            .serial_mut()
+            .expect("Unexpected BusDeviceType");
+        Some(f(serial))
+    }
+
+    /// Returns the RX trigger level of the serial console.
+    pub fn serial_rx_trigger_level(&self) -> SerialRxTriggerLevel {
+        self.with_serial(|serial| serial.rx_trigger_level())
+            .unwrap_or_default()
+    }
+
+    /// Sets the number of received bytes raising the Received Data Available interrupt of the
+    /// serial console.
+    pub fn set_serial_rx_trigger_level(&self, level: SerialRxTriggerLevel) -> Result<(), VmmError> {
+        self.with_serial(|serial| serial.set_rx_trigger_level(level))
+            .unwrap_or(Ok(()))
+            .map_err(VmmError::TimerFd)
+    }
+
+    /// Returns whether the output of the serial console is forwarded byte by byte or one line
+    /// at a time.
+    pub fn serial_output_buffering(&self) -> SerialOutputBuffering {
+        self.with_serial(|serial| serial.output_buffering())
+            .unwrap_or_default()
+    }
+
+    /// Sets whether the output of the serial console is forwarded byte by byte or one line at a
+    /// time. Line buffering keeps the guest's lines whole when they are mixed with other output
+    /// on the same destination, such as the logs of the VMM.
+    pub fn set_serial_output_buffering(
+        &self,
+        buffering: SerialOutputBuffering,
+    ) -> Result<(), VmmError> {
+        self.with_serial(|serial| serial.set_output_buffering(buffering))
+            .unwrap_or(Ok(()))
+            .map_err(VmmError::Serial)
+    }
+
+    /// Restores the registers and the buffered input of the serial console.
+    pub fn restore_serial_state(
+        &self,
+        state: &SerialDeviceState,
+    ) -> Result<(), SerialPersistError> {
+        self.with_serial(|serial| serial.restore_state(state))
+            .unwrap_or(Ok(()))
+    }
+
+    /// Scans the output of the serial console for `signature`. The first guest panic seen is
+    /// counted in the `panic_count` serial metric, and the metrics are flushed right away.
+    pub fn watch_serial_for_panics(&self, signature: &str) {
+        let watcher = PanicWatcher::new(
+            signature,
+            Box::new(|| {
+                if let Err(err) = METRICS.write() {
+                    error!("Failed to write metrics after a guest panic: {}", err);
+                }
+            }),
+        );
+        self.with_serial(|serial| serial.watch_for_panics(watcher));
+    }
+
+    /// Streams the output of the serial console to the client of a Unix domain socket listening
+    /// on `path`. Output is dropped while no client is connected.
+    pub fn set_serial_output_socket(&self, path: &Path) -> Result<(), VmmError> {
+        let socket = SerialSocket::bind(path).map_err(VmmError::Serial)?;
+        self.with_serial(|serial| serial.set_output(SerialOut::UnixSocket(socket)));
+        Ok(())
+    }
+
+    /// Writes the output of the serial console to the file or named pipe at `path`, or back to
+    /// stdout if `path` is `None`. The input of the serial console is left untouched.
+    pub fn set_serial_output(&self, path: Option<&Path>) -> Result<(), VmmError> {
+        let out = match path {
+            Some(path) => SerialOut::File(SerialFile::open(path).map_err(VmmError::Serial)?),
+            None => SerialOut::Stdout(std::io::stdout()),
+        };
+        self.with_serial(|serial| serial.set_output(out));
+        Ok(())
+    }
+
+    /// Runs `f` on the backend of the vsock device, if there is one.
+    fn with_vsock_backend<R>(&self, f: impl FnOnce(&mut VsockUnixBackend) -> R) -> Option<R> {
+        let virtio_device = self
+            .get_bus_device(DeviceType::Virtio(TYPE_VSOCK), VSOCK_DEV_ID)?
+            .lock()
+            .expect("Poisoned lock")
+            .mmio_transport_ref()
+            .expect("Unexpected device type")
+            .device();
+        let mut locked_device = virtio_device.lock().expect("Poisoned lock");
+        let vsock = locked_device
+            .as_mut_any()
+            .downcast_mut::<Vsock<VsockUnixBackend>>()
+            .expect("Unexpected device type");
+        Some(f(vsock.backend_mut()))
+    }
+
+    /// Asks the guest agent connected to the vsock quiesce port to quiesce the guest, and to ack
+    /// within `timeout`.
+    pub fn request_guest_quiesce(&self, timeout: Duration) -> Result<(), VmmError> {
+        self.with_vsock_backend(|backend| backend.request_quiesce(timeout))
+            .unwrap_or(Err(VsockUnixBackendError::QuiesceAgent(
+                io::ErrorKind::NotConnected.into(),
+            )))
+            .map_err(VmmError::GuestQuiesce)
+    }
+
+    /// Returns the state of the quiesce handshake with the guest agent.
+    pub fn guest_quiesce_state(&self) -> QuiesceState {
+        self.with_vsock_backend(|backend| backend.quiesce_state())
+            .unwrap_or(QuiesceState::NoAgent)
+    }
+
     /// Sets RDA bit in serial console
     pub fn emulate_serial_init(&self) -> Result<(), EmulateSerialInitError> {
         // When restoring from a previously saved state, there is no serial
         // driver initialization, therefore the RDA (Received Data Available)
         // interrupt is not enabled. Because of that, the driver won't get
-        // notified of any bytes that we send to the guest. The clean solution
-        // would be to save the whole serial device state when we do the vm
-        // serialization. For now we set that bit manually
+        // notified of any bytes that we send to the guest. Snapshots now carry
+        // the whole serial device state; this is only a fallback for the ones
+        // taken before, where we set that bit manually.
 
         #[cfg(target_arch = "aarch64")]
         {
@@ -501,19 +690,32 @@ impl Vmm {
     /// Injects CTRL+ALT+DEL keystroke combo in the i8042 device.
     #[cfg(target_arch = "x86_64")]
     pub fn send_ctrl_alt_del(&mut self) -> Result<(), VmmError> {
+        self.pio_device_manager
+            .send_ctrl_alt_del()
+            .map_err(VmmError::I8042Error)
+    }
+
+    /// Restores the registers and the buffered input of the i8042 device.
+    #[cfg(target_arch = "x86_64")]
+    pub fn restore_i8042_state(
+        &self,
+        state: &crate::devices::legacy::I8042DeviceState,
+    ) -> Result<(), crate::devices::legacy::I8042DeviceError> {
         self.pio_device_manager
             .i8042
             .lock()
             .expect("i8042 lock was poisoned")
             .i8042_device_mut()
             .unwrap()
-            .trigger_ctrl_alt_del()
-            .map_err(VmmError::I8042Error)
+            .restore_state(state)
     }
 
     /// Saves the state of a paused Microvm.
     pub fn save_state(&mut self, vm_info: &VmInfo) -> Result<MicrovmState, MicrovmStateError> {
-        use self::MicrovmStateError::SaveVmState;
+        use self::MicrovmStateError::{SaveDevices, SaveVmState};
+        self.mmio_device_manager
+            .prepare_save()
+            .map_err(SaveDevices)?;
         let vcpu_states = self.save_vcpu_states()?;
         let vm_state = {
             #[cfg(target_arch = "x86_64")]
@@ -527,7 +729,22 @@ impl Vmm {
                 self.vm.save_state(&mpidrs).map_err(SaveVmState)?
             }
         };
-        let device_states = self.mmio_device_manager.save();
+        let mut device_states = self.mmio_device_manager.save();
+        device_states.serial_rx_trigger_level = self.serial_rx_trigger_level();
+        device_states.serial_output_buffering = self.serial_output_buffering();
+        device_states.serial_state = self.with_serial(|serial| serial.save());
+        #[cfg(target_arch = "x86_64")]
+        {
+            device_states.i8042_state = Some(
+                self.pio_device_manager
+                    .i8042
+                    .lock()
+                    .expect("i8042 lock was poisoned")
+                    .i8042_device_ref()
+                    .unwrap()
+                    .save(),
+            );
+        }
 
         let memory_state = self.guest_memory().describe();
         #[cfg(target_arch = "x86_64")]
@@ -631,6 +848,14 @@ impl Vmm {
         Ok(bitmap)
     }
 
+    /// Returns the guest memory written by devices since the last checkpoint, and starts a new
+    /// checkpoint.
+    ///
+    /// Snapshots also start a new checkpoint. Requires dirty page tracking.
+    pub fn take_dirty_since_checkpoint(&self) -> DirtyRegions {
+        self.guest_memory.take_dirty_regions()
+    }
+
     /// Enables or disables KVM dirty page tracking.
     pub fn set_dirty_page_tracking(&mut self, enable: bool) -> Result<(), VmmError> {
         // This function _always_ results in an ioctl update. The VMM is stateless in the sense
@@ -643,6 +868,24 @@ impl Vmm {
             .map_err(VmmError::Vm)
     }
 
+    /// Replaces the guest memory with `guest_memory` and notifies the devices of its new size.
+    ///
+    /// KVM can't resize or move an existing memory slot, so `guest_memory` must keep the
+    /// regions of the current guest memory and may only append new ones after them.
+    pub fn resize_guest_memory(
+        &mut self,
+        guest_memory: GuestMemoryMmap,
+        track_dirty_pages: bool,
+    ) -> Result<(), VmmError> {
+        self.vm
+            .set_kvm_memory_regions(&guest_memory, track_dirty_pages)
+            .map_err(VmmError::Vm)?;
+        self.guest_memory = guest_memory;
+        self.mmio_device_manager
+            .on_memory_resized(mem_size_mib(&self.guest_memory))
+            .map_err(VmmError::DeviceManager)
+    }
+
     /// Updates the path of the host file backing the emulated block device with id `drive_id`.
     /// We update the disk image on the device and its virtio configuration.
     pub fn update_block_device_path(
@@ -659,6 +902,23 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Returns the host file currently backing the block device with `drive_id` id.
+    pub fn block_backing_info(&self, drive_id: &str) -> Result<BackingInfo, VmmError> {
+        let mut backing_info = None;
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                backing_info = Some(block.backing_info());
+                Ok(())
+            })
+            .map_err(VmmError::DeviceManager)?;
+        Ok(backing_info.expect("Backing info not collected"))
+    }
+
+    /// Returns a JSON view of the runtime state of the attached devices, meant for debugging.
+    pub fn device_debug_dump(&self) -> serde_json::Value {
+        self.mmio_device_manager.debug_dump()
+    }
+
     /// Updates the rate limiter parameters for block device with `drive_id` id.
     pub fn update_block_rate_limiter(
         &mut self,
@@ -675,6 +935,36 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Grows the block device with `drive_id` id to `disk_size` bytes, after its backing file
+    /// grew on the host, and notifies the guest of the new capacity.
+    pub fn resize_block_device(&self, drive_id: &str, disk_size: u64) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .resize_block_device(drive_id, disk_size)
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Feeds `events` to the input device with `input_id` id, for delivery to the guest.
+    pub fn send_input_event(&self, input_id: &str, events: &[InputEvent]) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .send_input_event(input_id, events)
+            .map_err(VmmError::DeviceManager)
+    }
+
+    /// Switches the cache type of the block device with `drive_id` id.
+    pub fn update_block_cache_type(
+        &mut self,
+        drive_id: &str,
+        cache_type: CacheType,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block
+                    .set_cache_type(cache_type)
+                    .map_err(|err| err.to_string())
+            })
+            .map_err(VmmError::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for block device with `drive_id` id.
     pub fn update_vhost_user_block_config(&mut self, drive_id: &str) -> Result<(), VmmError> {
         self.mmio_device_manager
@@ -701,6 +991,19 @@ impl Vmm {
             .map_err(VmmError::DeviceManager)
     }
 
+    /// Stops or resumes the queue processing of the virtio device of type `virtio_type` with
+    /// `device_id` id.
+    pub fn set_device_enabled(
+        &self,
+        virtio_type: u32,
+        device_id: &str,
+        enabled: bool,
+    ) -> Result<(), VmmError> {
+        self.mmio_device_manager
+            .set_device_enabled(virtio_type, device_id, enabled)
+            .map_err(VmmError::DeviceManager)
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -753,6 +1056,25 @@ impl Vmm {
         }
     }
 
+    /// Returns the guest memory usage reported through the balloon statistics, if available.
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        self.mmio_device_manager.memory_stats()
+    }
+
+    /// Reads the time sources of the guest, including the VMClock device, if any.
+    pub fn time_sources(&self) -> TimeSnapshot {
+        #[allow(unused_mut)]
+        let mut time = self.mmio_device_manager.time_sources();
+        #[cfg(target_arch = "x86_64")]
+        if let Some(vmclock) = &self.acpi_device_manager.vmclock {
+            time.vmclock_disruption_marker = vmclock
+                .disruption_marker(&self.guest_memory)
+                .inspect_err(|err| error!("Failed to read the VMClock page: {err}"))
+                .ok();
+        }
+        time
+    }
+
     /// Updates configuration for the balloon device target size.
     pub fn update_balloon_config(&mut self, amount_mib: u32) -> Result<(), BalloonError> {
         // The balloon cannot have a target size greater than the size of
@@ -850,6 +1172,10 @@ impl Vmm {
         // (Vmm's Drop will also check if this list is empty).
         self.vcpus_handles.clear();
 
+        // Release the host resources of the devices, like socket files, that would
+        // otherwise outlive the process.
+        self.mmio_device_manager.teardown();
+
         // Break the main event loop, propagating the Vmm exit-code.
         self.shutdown_exit_code = Some(exit_code);
     }
@@ -955,6 +1281,13 @@ impl MutEventSubscriber for Vmm {
                 FcExitCode::Ok
             };
             self.stop(exit_code);
+        } else if Some(source) == self.vmgenid_doorbell_fd() && event_set == EventSet::IN {
+            #[cfg(target_arch = "x86_64")]
+            if let Some(vmgenid) = self.acpi_device_manager.vmgenid.as_mut() {
+                if let Err(err) = vmgenid.handle_doorbell(&self.guest_memory) {
+                    error!("Failed to handle the VMGenID doorbell: {}", err);
+                }
+            }
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
@@ -964,5 +1297,11 @@ impl MutEventSubscriber for Vmm {
         if let Err(err) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", err);
         }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(vmgenid) = self.acpi_device_manager.vmgenid.as_ref() {
+            if let Err(err) = ops.add(Events::new(&vmgenid.doorbell_evt, EventSet::IN)) {
+                error!("Failed to register VMGenID doorbell event: {}", err);
+            }
+        }
     }
 }
@@ -1,6 +1,7 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
@@ -28,7 +29,11 @@ pub static INSTANCE_ID: OnceLock<String> = OnceLock::new();
 /// Default values matching the swagger specification (`src/firecracker/swagger/firecracker.yaml`).
 pub static LOGGER: Logger = Logger(Mutex::new(LoggerConfiguration {
     target: None,
-    filter: LogFilter { module: None },
+    filter: LogFilter {
+        module: None,
+        level: DEFAULT_LEVEL,
+        device_levels: BTreeMap::new(),
+    },
     format: LogFormat {
         show_level: false,
         show_log_origin: false,
@@ -54,12 +59,11 @@ impl Logger {
     /// Applies the given logger configuration the logger.
     pub fn update(&self, config: LoggerConfig) -> Result<(), LoggerUpdateError> {
         let mut guard = self.0.lock().unwrap();
-        log::set_max_level(
-            config
-                .level
-                .map(log::LevelFilter::from)
-                .unwrap_or(DEFAULT_LEVEL),
-        );
+        guard.filter.level = config
+            .level
+            .map(log::LevelFilter::from)
+            .unwrap_or(DEFAULT_LEVEL);
+        log::set_max_level(guard.filter.max_level());
 
         if let Some(log_path) = config.log_path {
             let file = std::fs::OpenOptions::new()
@@ -90,11 +94,53 @@ impl Logger {
 
         Ok(())
     }
+
+    /// Overrides the level of the logs of the device with the given id, or drops the override
+    /// when `level` is `None`.
+    ///
+    /// Devices log with their id as the target of their records, so the override applies to
+    /// them independently of the level of the logger.
+    pub fn set_device_level(&self, device_id: &str, level: Option<LevelFilter>) {
+        let mut guard = self.0.lock().unwrap();
+        match level {
+            Some(level) => {
+                guard
+                    .filter
+                    .device_levels
+                    .insert(device_id.to_string(), level.into());
+            }
+            None => {
+                guard.filter.device_levels.remove(device_id);
+            }
+        }
+        log::set_max_level(guard.filter.max_level());
+    }
 }
 
 #[derive(Debug)]
 pub struct LogFilter {
     pub module: Option<String>,
+    pub level: log::LevelFilter,
+    pub device_levels: BTreeMap<String, log::LevelFilter>,
+}
+
+impl LogFilter {
+    // The level of the records of the given target.
+    fn level(&self, target: &str) -> log::LevelFilter {
+        self.device_levels
+            .get(target)
+            .copied()
+            .unwrap_or(self.level)
+    }
+
+    // The most verbose level any record can be logged at, which the `log` macros check before
+    // building the record.
+    fn max_level(&self) -> log::LevelFilter {
+        self.device_levels
+            .values()
+            .copied()
+            .fold(self.level, std::cmp::max)
+    }
 }
 #[derive(Debug)]
 pub struct LogFormat {
@@ -111,7 +157,8 @@ pub struct LoggerConfiguration {
 pub struct Logger(pub Mutex<LoggerConfiguration>);
 
 impl Log for Logger {
-    // No additional filters to <https://docs.rs/log/latest/log/fn.max_level.html>.
+    // No additional filters to <https://docs.rs/log/latest/log/fn.max_level.html>, the level of
+    // the records is checked in `log` along with their module.
     fn enabled(&self, _metadata: &Metadata) -> bool {
         true
     }
@@ -127,7 +174,8 @@ impl Log for Logger {
                 (Some(_), None) => false,
                 (None, _) => true,
             };
-            let enabled = enabled_module;
+            let enabled_level = record.level() <= guard.filter.level(record.target());
+            let enabled = enabled_module && enabled_level;
             if !enabled {
                 return;
             }
@@ -367,6 +415,8 @@ mod tests {
             target: Some(target),
             filter: LogFilter {
                 module: Some(String::from("module")),
+                level: DEFAULT_LEVEL,
+                device_levels: BTreeMap::new(),
             },
             format: LogFormat {
                 show_level: true,
@@ -403,4 +453,63 @@ mod tests {
 
         std::fs::remove_file(path).unwrap();
     }
+
+    #[test]
+    fn logger_device_level() {
+        let file = utils::tempfile::TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap().to_string();
+        drop(file);
+
+        let target = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let logger = Logger(Mutex::new(LoggerConfiguration {
+            target: Some(target),
+            filter: LogFilter {
+                module: None,
+                level: log::LevelFilter::Info,
+                device_levels: BTreeMap::new(),
+            },
+            format: LogFormat {
+                show_level: false,
+                show_log_origin: false,
+            },
+        }));
+        let log_debug = |target: &str, msg: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{msg}"))
+                    .level(Level::Debug)
+                    .target(target)
+                    .module_path(Some("vmm::devices"))
+                    .build(),
+            );
+        };
+
+        logger.set_device_level("rootfs", Some(LevelFilter::Debug));
+        assert_eq!(
+            logger.0.lock().unwrap().filter.max_level(),
+            log::LevelFilter::Debug
+        );
+        log_debug("rootfs", "verbose");
+        log_debug("scratch", "quiet");
+        log_debug("vmm::devices", "quiet");
+
+        logger.set_device_level("rootfs", None);
+        assert_eq!(
+            logger.0.lock().unwrap().filter.max_level(),
+            log::LevelFilter::Info
+        );
+        log_debug("rootfs", "quiet again");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("] verbose"), "{}", lines[0]);
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -47,6 +47,16 @@ pub enum OutputFormat {
     Imds,
 }
 
+/// Conflict resolution policy used when merging data into the MMDS data store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MmdsMergePolicy {
+    /// Merged values replace the existing ones.
+    #[default]
+    LastWriterWins,
+    /// The merge fails if it changes an existing value.
+    Reject,
+}
+
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 /// MMDS data store errors
 pub enum MmdsDatastoreError {
@@ -56,6 +66,8 @@ pub enum MmdsDatastoreError {
     NotFound,
     /// The MMDS data store is not initialized.
     NotInitialized,
+    /// The MMDS merge request conflicts with the existing value at `{0}`.
+    MergeConflict(String),
     /// Token Authority error: {0}
     TokenAuthority(#[from] TokenError),
     /// Cannot retrieve value. The value has an unsupported type.
@@ -174,6 +186,26 @@ impl Mmds {
         Ok(())
     }
 
+    /// Merges `data` into the MMDS data store. Objects are merged recursively, while
+    /// conflicting values are resolved according to `policy`.
+    pub fn merge_data(
+        &mut self,
+        data: Value,
+        policy: MmdsMergePolicy,
+    ) -> Result<(), MmdsDatastoreError> {
+        self.check_data_store_initialized()?;
+        let mut data_store_clone = self.data_store.clone();
+
+        merge_value(&mut data_store_clone, data, policy, "")?;
+        // It is safe to unwrap because our data store keys are all strings and
+        // we are using default serializer which does not return error.
+        if to_vec(&data_store_clone).unwrap().len() > self.data_store_limit {
+            return Err(MmdsDatastoreError::DataStoreLimitExceeded);
+        }
+        self.data_store = data_store_clone;
+        Ok(())
+    }
+
     /// return MMDS data store value
     /// We do not check size of data_store before returning a result because due
     /// to limit from put/patch the data_store can not be bigger than the limit
@@ -276,6 +308,39 @@ impl Mmds {
     }
 }
 
+// Recursively merges `data` into `target`, where `path` is the location of `target` in the
+// data store.
+fn merge_value(
+    target: &mut Value,
+    data: Value,
+    policy: MmdsMergePolicy,
+    path: &str,
+) -> Result<(), MmdsDatastoreError> {
+    match (target, data) {
+        (Value::Object(target), Value::Object(data)) => {
+            for (key, value) in data {
+                let path = format!("{path}/{key}");
+                match target.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value, policy, &path)?,
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, data) => {
+            if *target != data {
+                if policy == MmdsMergePolicy::Reject {
+                    let path = if path.is_empty() { "/" } else { path };
+                    return Err(MmdsDatastoreError::MergeConflict(path.to_string()));
+                }
+                *target = data;
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -565,6 +630,83 @@ mod tests {
         assert_eq!(mmds.get_data_str().len(), 72);
     }
 
+    #[test]
+    fn test_merge_data() {
+        let mut mmds = Mmds::default();
+        let data: Value = serde_json::from_str(r#"{"key": "value"}"#).unwrap();
+        assert_eq!(
+            mmds.merge_data(data, MmdsMergePolicy::LastWriterWins)
+                .unwrap_err()
+                .to_string(),
+            MmdsDatastoreError::NotInitialized.to_string()
+        );
+
+        let data = r#"{
+            "name": {
+                "first": "John"
+            },
+            "age": "43"
+        }"#;
+        let data_store: Value = serde_json::from_str(data).unwrap();
+        mmds.put_data(data_store).unwrap();
+
+        // New keys are added, including nested ones.
+        let data = r#"{
+            "name": {
+                "second": "Doe"
+            },
+            "city": "Seattle"
+        }"#;
+        let merged: Value = serde_json::from_str(data).unwrap();
+        mmds.merge_data(merged, MmdsMergePolicy::Reject).unwrap();
+        let data = r#"{
+            "name": {
+                "first": "John",
+                "second": "Doe"
+            },
+            "age": "43",
+            "city": "Seattle"
+        }"#;
+        let expected: Value = serde_json::from_str(data).unwrap();
+        assert_eq!(mmds.data_store_value(), expected);
+
+        // Merging identical values is not a conflict.
+        let merged: Value = serde_json::from_str(r#"{"age": "43", "extra": "value"}"#).unwrap();
+        mmds.merge_data(merged, MmdsMergePolicy::Reject).unwrap();
+        assert_eq!(mmds.data_store_value()["extra"], "value");
+
+        // Conflicting values are rejected without changing the data store.
+        let data = r#"{"name": {"first": "Jane"}, "new": "key"}"#;
+        let merged: Value = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            mmds.merge_data(merged.clone(), MmdsMergePolicy::Reject)
+                .unwrap_err()
+                .to_string(),
+            MmdsDatastoreError::MergeConflict("/name/first".to_string()).to_string()
+        );
+        assert_eq!(mmds.data_store_value()["name"]["first"], "John");
+        assert!(mmds.data_store_value().get("new").is_none());
+
+        // Or replace the existing ones.
+        mmds.merge_data(merged, MmdsMergePolicy::LastWriterWins)
+            .unwrap();
+        assert_eq!(mmds.data_store_value()["name"]["first"], "Jane");
+        assert_eq!(mmds.data_store_value()["name"]["second"], "Doe");
+        assert_eq!(mmds.data_store_value()["new"], "key");
+
+        // The data store limit applies to the merged result.
+        let filling = (0..51200).map(|_| "X").collect::<String>();
+        let data = "{\"big\": \"".to_string() + &filling + "\"}";
+        let merged: Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(
+            mmds.merge_data(merged, MmdsMergePolicy::LastWriterWins)
+                .unwrap_err()
+                .to_string(),
+            MmdsDatastoreError::DataStoreLimitExceeded.to_string()
+        );
+        assert!(mmds.data_store_value().get("big").is_none());
+    }
+
     #[test]
     fn test_put_size_limit() {
         let mut mmds = Mmds::default();
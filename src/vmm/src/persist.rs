@@ -10,6 +10,7 @@ use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use seccompiler::BpfThreadMap;
 use semver::Version;
@@ -26,9 +27,11 @@ use crate::cpu_config::templates::StaticCpuTemplate;
 use crate::cpu_config::x86_64::cpuid::common::get_vendor_id_from_host;
 #[cfg(target_arch = "x86_64")]
 use crate::cpu_config::x86_64::cpuid::CpuidTrait;
+use crate::device_manager::mmio::MmioError;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::ACPIDeviceManagerState;
 use crate::device_manager::persist::{DevicePersistError, DeviceStates};
+use crate::devices::virtio::vsock::QuiesceState;
 use crate::logger::{info, warn};
 use crate::resources::VmResources;
 use crate::snapshot::Snapshot;
@@ -126,6 +129,8 @@ pub enum MicrovmStateError {
     RestoreVcpuState(vstate::vcpu::VcpuError),
     /// Cannot restore Vm state: {0}
     RestoreVmState(vstate::vm::VmError),
+    /// Cannot save devices: {0}
+    SaveDevices(DevicePersistError),
     /// Cannot save Vcpu state: {0}
     SaveVcpuState(vstate::vcpu::VcpuError),
     /// Cannot save Vm state: {0}
@@ -155,12 +160,20 @@ pub enum CreateSnapshotError {
     SerializeMicrovmState(crate::snapshot::SnapshotError),
     /// Cannot perform {0} on the snapshot backing file: {1}
     SnapshotBackingFile(&'static str, io::Error),
+    /// Estimated snapshot size of {0} bytes exceeds the cap of {1} bytes
+    SnapshotTooLarge(usize, usize),
+    /// The guest agent did not quiesce the guest: {0:?}
+    GuestNotQuiesced(QuiesceState),
+    /// The guest requested a reset that is not handled yet.
+    GuestResetPending,
+    /// Cannot quiesce the devices: {0}
+    DeviceQuiesce(MmioError),
     /// Size mismatch when writing diff snapshot on top of base layer: base layer size is {0} but diff layer is size {1}.
     SnapshotBackingFileLengthMismatch(u64, u64),
 }
 
 /// Snapshot version
-pub const SNAPSHOT_VERSION: Version = Version::new(2, 0, 0);
+pub const SNAPSHOT_VERSION: Version = Version::new(3, 0, 0);
 
 /// Creates a Microvm snapshot.
 pub fn create_snapshot(
@@ -168,6 +181,37 @@ pub fn create_snapshot(
     vm_info: &VmInfo,
     params: &CreateSnapshotParams,
 ) -> Result<(), CreateSnapshotError> {
+    if params.require_guest_quiesce {
+        let state = vmm.guest_quiesce_state();
+        if state != QuiesceState::Quiesced {
+            return Err(CreateSnapshotError::GuestNotQuiesced(state));
+        }
+    }
+
+    if let Some(max_size) = params.max_snapshot_size {
+        let estimate = vmm.mmio_device_manager.estimate_snapshot_size();
+        info!(
+            "Estimated snapshot size: {} bytes (cap: {} bytes)",
+            estimate, max_size
+        );
+        if estimate > max_size {
+            return Err(CreateSnapshotError::SnapshotTooLarge(estimate, max_size));
+        }
+    }
+
+    // The vCPUs are paused, so the guest can't request a reset while the snapshot is saved: only
+    // a reset requested before the pause may still be waiting for the event loop, which handles
+    // it once the snapshot is saved.
+    if params.abort_on_guest_reset && vmm.exit_pending() {
+        return Err(CreateSnapshotError::GuestResetPending);
+    }
+
+    if let Some(timeout_ms) = params.quiesce_timeout_ms {
+        vmm.mmio_device_manager
+            .quiesce(Duration::from_millis(timeout_ms))
+            .map_err(CreateSnapshotError::DeviceQuiesce)?;
+    }
+
     let microvm_state = vmm
         .save_state(vm_info)
         .map_err(CreateSnapshotError::MicrovmState)?;
@@ -400,7 +444,8 @@ pub fn restore_from_snapshot(
     params: &LoadSnapshotParams,
     vm_resources: &mut VmResources,
 ) -> Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path)?;
+    let microvm_state =
+        snapshot_state_from_file(&params.snapshot_path, params.relaxed_version_check)?;
     let track_dirty_pages = params.enable_diff_snapshots;
 
     let vcpu_count = microvm_state
@@ -418,6 +463,7 @@ pub fn restore_from_snapshot(
             cpu_template: Some(microvm_state.vm_info.cpu_template),
             track_dirty_pages: Some(track_dirty_pages),
             huge_pages: Some(microvm_state.vm_info.huge_pages),
+            host_fd_limit: None,
         })
         .map_err(BuildMicrovmFromSnapshotError::VmUpdateConfig)?;
 
@@ -449,7 +495,7 @@ pub fn restore_from_snapshot(
         )
         .map_err(RestoreFromSnapshotGuestMemoryError::Uffd)?,
     };
-    builder::build_microvm_from_snapshot(
+    let vmm = builder::build_microvm_from_snapshot(
         instance_info,
         event_manager,
         microvm_state,
@@ -458,7 +504,15 @@ pub fn restore_from_snapshot(
         seccomp_filters,
         vm_resources,
     )
-    .map_err(RestoreFromSnapshotError::Build)
+    .map_err(RestoreFromSnapshotError::Build)?;
+    // The restored devices are a new activation unless asked otherwise.
+    if !params.preserve_device_uptime {
+        vmm.lock()
+            .expect("Poisoned lock")
+            .mmio_device_manager
+            .restart_device_uptimes();
+    }
+    Ok(vmm)
 }
 
 /// Error type for [`snapshot_state_from_file`]
@@ -474,15 +528,19 @@ pub enum SnapshotStateFromFileError {
 
 fn snapshot_state_from_file(
     snapshot_path: &Path,
+    relaxed_version_check: bool,
 ) -> Result<MicrovmState, SnapshotStateFromFileError> {
     let snapshot = Snapshot::new(SNAPSHOT_VERSION);
     let mut snapshot_reader =
         File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
     let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotStateFromFileError::Meta)?;
     let snapshot_len = u64_to_usize(metadata.len());
-    let state: MicrovmState = snapshot
-        .load_with_version_check(&mut snapshot_reader, snapshot_len)
-        .map_err(SnapshotStateFromFileError::Load)?;
+    let state: MicrovmState = if relaxed_version_check {
+        snapshot.load_with_relaxed_version_check(&mut snapshot_reader, snapshot_len)
+    } else {
+        snapshot.load_with_version_check(&mut snapshot_reader, snapshot_len)
+    }
+    .map_err(SnapshotStateFromFileError::Load)?;
     Ok(state)
 }
 
@@ -631,21 +689,23 @@ mod tests {
     use utils::tempfile::TempFile;
 
     use super::*;
-    #[cfg(target_arch = "x86_64")]
-    use crate::builder::tests::insert_vmgenid_device;
     use crate::builder::tests::{
         default_kernel_cmdline, default_vmm, insert_balloon_device, insert_block_devices,
         insert_net_device, insert_vsock_device, CustomBlockConfig,
     };
+    #[cfg(target_arch = "x86_64")]
+    use crate::builder::tests::{insert_vmclock_device, insert_vmgenid_device};
     #[cfg(target_arch = "aarch64")]
     use crate::construct_kvm_mpidrs;
     use crate::devices::virtio::block::CacheType;
     use crate::snapshot::Persist;
+    use crate::utilities::mock_resources::NOISY_KERNEL_IMAGE;
+    use crate::utilities::test_utils::create_vmm;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vstate::memory::GuestMemoryRegionState;
-    use crate::Vmm;
+    use crate::{FcExitCode, Vmm};
 
     fn default_vmm_with_devices() -> Vmm {
         let mut event_manager = EventManager::new().expect("Cannot create EventManager");
@@ -678,6 +738,8 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
         };
         insert_net_device(
             &mut vmm,
@@ -695,10 +757,28 @@ mod tests {
 
         #[cfg(target_arch = "x86_64")]
         insert_vmgenid_device(&mut vmm);
+        #[cfg(target_arch = "x86_64")]
+        insert_vmclock_device(&mut vmm);
 
         vmm
     }
 
+    #[test]
+    fn test_time_sources() {
+        let vmm = default_vmm_with_devices();
+        let time = vmm.time_sources();
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert_eq!(time.vmclock_disruption_marker, Some(0));
+            vmm.acpi_device_manager
+                .post_restore_vmclock(vmm.guest_memory())
+                .unwrap();
+            assert_eq!(vmm.time_sources().vmclock_disruption_marker, Some(1));
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(time.vmclock_disruption_marker, None);
+    }
+
     #[test]
     fn test_microvm_state_snapshot() {
         let vmm = default_vmm_with_devices();
@@ -804,4 +884,51 @@ mod tests {
 
         assert_eq!(uffd_regions, deserialized);
     }
+
+    #[test]
+    fn test_guest_reset_during_snapshot() {
+        let (vmm, _) = create_vmm(Some(NOISY_KERNEL_IMAGE), false, true);
+        vmm.lock().unwrap().pause_vm().unwrap();
+        // The guest requests a reset right before the snapshot.
+        vmm.lock().unwrap().vcpus_exit_evt.write(1).unwrap();
+        assert!(vmm.lock().unwrap().exit_pending());
+
+        let snapshot_file = TempFile::new().unwrap();
+        let memory_file = TempFile::new().unwrap();
+        let mut params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: memory_file.as_path().to_path_buf(),
+            max_snapshot_size: None,
+            require_guest_quiesce: false,
+            abort_on_guest_reset: true,
+            quiesce_timeout_ms: None,
+        };
+        let vm_info = VmInfo {
+            mem_size_mib: 1u64,
+            ..Default::default()
+        };
+
+        let err = create_snapshot(&mut vmm.lock().unwrap(), &vm_info, &params).unwrap_err();
+        assert!(
+            matches!(err, CreateSnapshotError::GuestResetPending),
+            "{err:?}"
+        );
+        // Nothing was written.
+        assert_eq!(snapshot_file.as_file().metadata().unwrap().len(), 0);
+        assert_eq!(memory_file.as_file().metadata().unwrap().len(), 0);
+
+        // The snapshot is saved in full, and the reset is left to the event loop.
+        params.abort_on_guest_reset = false;
+        create_snapshot(&mut vmm.lock().unwrap(), &vm_info, &params).unwrap();
+        assert!(vmm.lock().unwrap().exit_pending());
+        let snapshot_len = u64_to_usize(snapshot_file.as_file().metadata().unwrap().len());
+        let (state, _) =
+            Snapshot::load::<_, MicrovmState>(&mut snapshot_file.as_file(), snapshot_len).unwrap();
+        assert_eq!(state.vm_info, vm_info);
+        // Checking for the reset left the exit event for the event loop.
+        assert_eq!(vmm.lock().unwrap().vcpus_exit_evt.read().unwrap(), 1);
+
+        vmm.lock().unwrap().stop(FcExitCode::Ok);
+    }
 }
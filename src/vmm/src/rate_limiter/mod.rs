@@ -7,6 +7,7 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
 use std::{fmt, io};
 
+use serde::Serialize;
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
 pub mod persist;
@@ -262,6 +263,43 @@ impl TokenBucket {
     pub fn initial_one_time_burst(&self) -> u64 {
         self.initial_one_time_burst
     }
+
+    /// Returns the current levels of the bucket, accounting for the tokens replenished since
+    /// the last time the bucket saw activity.
+    pub fn levels(&self) -> TokenBucketLevels {
+        let mut bucket = self.clone();
+        bucket.auto_replenish();
+        TokenBucketLevels {
+            budget: bucket.budget,
+            one_time_burst: bucket.one_time_burst,
+            capacity: bucket.size,
+            refill_time_ms: bucket.refill_time,
+        }
+    }
+}
+
+/// Point-in-time view of the levels of a `TokenBucket`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenBucketLevels {
+    /// Tokens currently available, one time burst notwithstanding.
+    pub budget: u64,
+    /// Remaining one time burst tokens.
+    pub one_time_burst: u64,
+    /// Total capacity of the bucket.
+    pub capacity: u64,
+    /// Time in milliseconds in which the bucket refills `capacity` tokens.
+    pub refill_time_ms: u64,
+}
+
+/// Point-in-time view of the budgets of a `RateLimiter`.
+///
+/// A bucket is `None` when limiting is disabled for its token type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RateLimiterBudgets {
+    /// Levels of the bandwidth token bucket.
+    pub bandwidth: Option<TokenBucketLevels>,
+    /// Levels of the ops token bucket.
+    pub ops: Option<TokenBucketLevels>,
 }
 
 /// Enum that describes the type of token used.
@@ -483,16 +521,24 @@ impl RateLimiter {
     }
 
     /// Updates the parameters of the token buckets associated with this RateLimiter.
-    // TODO: Please note that, right now, the buckets become full after being updated.
+    ///
+    /// An updated bucket keeps the budget of the bucket it replaces, capped to its new size, and
+    /// refills at its new rate from then on. A bucket enabled by the update starts full.
     pub fn update_buckets(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
-        match bytes {
-            BucketUpdate::Disabled => self.bandwidth = None,
-            BucketUpdate::Update(tb) => self.bandwidth = Some(tb),
-            BucketUpdate::None => (),
-        };
-        match ops {
-            BucketUpdate::Disabled => self.ops = None,
-            BucketUpdate::Update(tb) => self.ops = Some(tb),
+        Self::update_bucket(&mut self.bandwidth, bytes);
+        Self::update_bucket(&mut self.ops, ops);
+    }
+
+    fn update_bucket(bucket: &mut Option<TokenBucket>, update: BucketUpdate) {
+        match update {
+            BucketUpdate::Disabled => *bucket = None,
+            BucketUpdate::Update(mut tb) => {
+                if let Some(old) = bucket.as_mut() {
+                    old.auto_replenish();
+                    tb.budget = std::cmp::min(old.budget, tb.size);
+                }
+                *bucket = Some(tb);
+            }
             BucketUpdate::None => (),
         };
     }
@@ -506,6 +552,14 @@ impl RateLimiter {
     pub fn ops(&self) -> Option<&TokenBucket> {
         self.ops.as_ref()
     }
+
+    /// Returns the current budgets of both token buckets.
+    pub fn budgets(&self) -> RateLimiterBudgets {
+        RateLimiterBudgets {
+            bandwidth: self.bandwidth.as_ref().map(TokenBucket::levels),
+            ops: self.ops.as_ref().map(TokenBucket::levels),
+        }
+    }
 }
 
 impl AsRawFd for RateLimiter {
@@ -1174,12 +1228,75 @@ pub(crate) mod tests {
         x.bandwidth.as_mut().unwrap().last_update = new_bw.last_update;
         x.ops.as_mut().unwrap().last_update = new_ops.last_update;
 
+        // The bandwidth budget is capped to the new size, while the ops budget is carried over.
         assert_eq!(x.bandwidth, Some(new_bw));
+        assert_eq!(x.ops.as_ref().unwrap().budget(), 10);
+        x.ops.as_mut().unwrap().budget = new_ops.budget;
         assert_eq!(x.ops, Some(new_ops));
 
         x.update_buckets(BucketUpdate::Disabled, BucketUpdate::Disabled);
         assert_eq!(x.bandwidth, None);
         assert_eq!(x.ops, None);
+
+        // A bucket enabled by an update starts full.
+        x.update_buckets(
+            BucketUpdate::Update(TokenBucket::new(100, 0, 1_000_000).unwrap()),
+            BucketUpdate::None,
+        );
+        assert_eq!(x.bandwidth.as_ref().unwrap().budget(), 100);
+    }
+
+    #[test]
+    fn test_update_buckets_keeps_budget() {
+        // Refill rate of 1 token per second, so the budget doesn't change during the test.
+        let mut x = RateLimiter::new(1000, 0, 1_000_000, 0, 0, 0).unwrap();
+        assert!(x.consume(700, TokenType::Bytes));
+
+        // Growing the bucket doesn't grant the tokens consumed so far.
+        x.update_buckets(
+            BucketUpdate::Update(TokenBucket::new(2000, 0, 1_000_000).unwrap()),
+            BucketUpdate::None,
+        );
+        assert_eq!(x.bandwidth().unwrap().capacity(), 2000);
+        assert_eq!(x.bandwidth().unwrap().budget(), 300);
+
+        // Shrinking it caps the budget to the new size.
+        x.update_buckets(
+            BucketUpdate::Update(TokenBucket::new(100, 0, 1_000_000).unwrap()),
+            BucketUpdate::None,
+        );
+        assert_eq!(x.bandwidth().unwrap().capacity(), 100);
+        assert_eq!(x.bandwidth().unwrap().budget(), 100);
+        assert!(x.consume(100, TokenType::Bytes));
+        assert!(!x.consume(1, TokenType::Bytes));
+    }
+
+    #[test]
+    fn test_rate_limiter_budgets() {
+        // Only limit bandwidth, at a refill rate of 1 token per second.
+        let mut l = RateLimiter::new(1000, 100, 1_000_000, 0, 0, 0).unwrap();
+        let budgets = l.budgets();
+        assert!(budgets.ops.is_none());
+        assert_eq!(
+            budgets.bandwidth,
+            Some(TokenBucketLevels {
+                budget: 1000,
+                one_time_burst: 100,
+                capacity: 1000,
+                refill_time_ms: 1_000_000,
+            })
+        );
+
+        // The one time burst is consumed first.
+        assert!(l.consume(300, TokenType::Bytes));
+        let bandwidth = l.budgets().bandwidth.unwrap();
+        assert_eq!(bandwidth.one_time_burst, 0);
+        assert_eq!(bandwidth.budget, 800);
+
+        assert_eq!(
+            RateLimiter::default().budgets(),
+            RateLimiterBudgets::default()
+        );
     }
 
     #[test]
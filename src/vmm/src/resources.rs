@@ -7,9 +7,18 @@ use std::sync::{Arc, Mutex, MutexGuard};
 
 use serde::{Deserialize, Serialize};
 use utils::net::ipv4addr::is_link_local_valid;
+use utils::u64_to_usize;
 
 use crate::cpu_config::templates::CustomCpuTemplate;
 use crate::device_manager::persist::SharedDeviceType;
+use crate::devices::virtio::balloon::BALLOON_NUM_QUEUES;
+use crate::devices::virtio::block::virtio::BLOCK_NUM_QUEUES;
+use crate::devices::virtio::device::VirtioDevice;
+use crate::devices::virtio::fs::NUM_QUEUES as FS_NUM_QUEUES;
+use crate::devices::virtio::input::INPUT_NUM_QUEUES;
+use crate::devices::virtio::net::NET_NUM_QUEUES;
+use crate::devices::virtio::rng::RNG_NUM_QUEUES;
+use crate::devices::virtio::vsock::VSOCK_NUM_QUEUES;
 use crate::logger::{info, log_dev_preview_warning};
 use crate::mmds;
 use crate::mmds::data_store::{Mmds, MmdsVersion};
@@ -18,15 +27,19 @@ use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
     BootConfig, BootSource, BootSourceConfig, BootSourceConfigError,
 };
+use crate::vmm_config::clock::ClockConfig;
 use crate::vmm_config::drive::*;
 use crate::vmm_config::entropy::*;
+use crate::vmm_config::fs::*;
+use crate::vmm_config::input::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{
-    HugePageConfig, MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
+    HostFdLimitError, HugePageConfig, MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
 };
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
+use crate::vmm_config::serial::{SerialConfig, SerialConfigError};
 use crate::vmm_config::vsock::*;
 
 /// Errors encountered when configuring microVM resources.
@@ -52,12 +65,24 @@ pub enum ResourcesError {
     MmdsConfig(#[from] MmdsConfigError),
     /// Network device error: {0}
     NetDevice(#[from] NetworkInterfaceError),
+    /// Serial console config error: {0}
+    SerialConfig(#[from] SerialConfigError),
     /// VM config error: {0}
     VmConfig(#[from] VmConfigError),
     /// Vsock device error: {0}
     VsockDevice(#[from] VsockConfigError),
     /// Entropy device error: {0}
     EntropyDevice(#[from] EntropyDeviceError),
+    /// Fs device error: {0}
+    FsDevice(#[from] FsConfigError),
+    /// Input device error: {0}
+    InputDevice(#[from] InputConfigError),
+    /// MMIO address {1:#x} of device {0} is not aligned to the MMIO range length.
+    MisalignedMmioAddress(String, u64),
+    /// MMIO range of device {0} at {1:#x} is outside of the MMIO window.
+    MmioRangeOutOfWindow(String, u64),
+    /// MMIO ranges of devices {0} and {1} overlap.
+    OverlappingMmioRanges(String, String),
 }
 
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
@@ -85,6 +110,14 @@ pub struct VmmConfig {
     vsock_device: Option<VsockDeviceConfig>,
     #[serde(rename = "entropy")]
     entropy_device: Option<EntropyDeviceConfig>,
+    #[serde(rename = "fs", default)]
+    fs_devices: Vec<FsDeviceConfig>,
+    #[serde(rename = "input", default)]
+    input_devices: Vec<InputDeviceConfig>,
+    #[serde(rename = "serial")]
+    serial: Option<SerialConfig>,
+    #[serde(rename = "clock")]
+    clock: Option<ClockConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -105,6 +138,14 @@ pub struct VmResources {
     pub net_builder: NetBuilder,
     /// The entropy device builder.
     pub entropy: EntropyDeviceBuilder,
+    /// The fs devices builder.
+    pub fs: FsBuilder,
+    /// The input devices builder.
+    pub input: InputBuilder,
+    /// The serial console configuration.
+    pub serial: SerialConfig,
+    /// The clock devices configuration.
+    pub clock: ClockConfig,
     /// The optional Mmds data store.
     // This is initialised on demand (if ever used), so that we don't allocate it unless it's
     // actually used.
@@ -113,6 +154,8 @@ pub struct VmResources {
     pub mmds_size_limit: usize,
     /// Whether or not to load boot timer device.
     pub boot_timer: bool,
+    /// Whether or not to run the self-test of the devices before booting.
+    pub device_self_test: bool,
 }
 
 impl VmResources {
@@ -183,6 +226,22 @@ impl VmResources {
             resources.build_entropy_device(entropy_device_config)?;
         }
 
+        for fs_config in vmm_config.fs_devices.into_iter() {
+            resources.build_fs_device(fs_config)?;
+        }
+
+        for input_config in vmm_config.input_devices.into_iter() {
+            resources.build_input_device(input_config)?;
+        }
+
+        if let Some(serial_config) = vmm_config.serial {
+            resources.set_serial_config(serial_config)?;
+        }
+
+        if let Some(clock_config) = vmm_config.clock {
+            resources.set_clock_config(clock_config);
+        }
+
         Ok(resources)
     }
 
@@ -224,11 +283,17 @@ impl VmResources {
             }
 
             SharedDeviceType::Vsock(vsock) => {
-                self.vsock.set_device(vsock);
+                self.vsock.set_device(vsock)?;
             }
             SharedDeviceType::Entropy(entropy) => {
                 self.entropy.set_device(entropy);
             }
+            SharedDeviceType::Input(input) => {
+                self.input.add_device(input);
+            }
+            SharedDeviceType::Fs(fs) => {
+                self.fs.add_device(fs);
+            }
         }
 
         Ok(())
@@ -276,6 +341,13 @@ impl VmResources {
             return Err(VmConfigError::InitrdAndHugePages);
         }
 
+        if let Some(limit) = updated.host_fd_limit {
+            let in_use = self.host_fd_count();
+            if in_use > limit {
+                return Err(VmConfigError::HostFdLimitTooLow(limit, in_use));
+            }
+        }
+
         self.vm_config = updated;
 
         Ok(())
@@ -344,6 +416,8 @@ impl VmResources {
             return Err(BalloonConfigError::HugePages);
         }
 
+        let released = self.balloon.get().map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(BALLOON_NUM_QUEUES, false))?;
         self.balloon.set(config)
     }
 
@@ -375,6 +449,13 @@ impl VmResources {
         &mut self,
         block_device_config: BlockDeviceConfig,
     ) -> Result<(), DriveError> {
+        let released = self
+            .block
+            .devices
+            .iter()
+            .find(|dev| dev.lock().expect("Poisoned lock").id() == block_device_config.drive_id)
+            .map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(BLOCK_NUM_QUEUES, true))?;
         self.block.insert(block_device_config)
     }
 
@@ -383,12 +464,20 @@ impl VmResources {
         &mut self,
         body: NetworkInterfaceConfig,
     ) -> Result<(), NetworkInterfaceError> {
+        let released = self
+            .net_builder
+            .iter()
+            .find(|dev| dev.lock().expect("Poisoned lock").id() == &body.iface_id)
+            .map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(NET_NUM_QUEUES, true))?;
         let _ = self.net_builder.build(body)?;
         Ok(())
     }
 
     /// Sets a vsock device to be attached when the VM starts.
     pub fn set_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<(), VsockConfigError> {
+        let released = self.vsock.get().map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(VSOCK_NUM_QUEUES, true))?;
         self.vsock.insert(config)
     }
 
@@ -397,9 +486,74 @@ impl VmResources {
         &mut self,
         body: EntropyDeviceConfig,
     ) -> Result<(), EntropyDeviceError> {
+        let released = self.entropy.get().map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(RNG_NUM_QUEUES, false))?;
         self.entropy.insert(body)
     }
 
+    /// Builds an fs device to be attached when the VM starts.
+    pub fn build_fs_device(&mut self, body: FsDeviceConfig) -> Result<(), FsConfigError> {
+        let released = self
+            .fs
+            .devices
+            .iter()
+            .find(|dev| dev.lock().expect("Poisoned lock").id == body.fs_id)
+            .map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(u64_to_usize(FS_NUM_QUEUES), true))?;
+        self.fs.insert(body)
+    }
+
+    /// Builds an input device to be attached when the VM starts.
+    pub fn build_input_device(&mut self, body: InputDeviceConfig) -> Result<(), InputConfigError> {
+        let released = self
+            .input
+            .devices
+            .iter()
+            .find(|dev| dev.lock().expect("Poisoned lock").id() == body.input_id)
+            .map_or(0, host_fds);
+        self.check_host_fd_limit(released, device_host_fds(INPUT_NUM_QUEUES, false))?;
+        self.input.insert(body)
+    }
+
+    /// Approximate number of host fds owned by the configured devices.
+    pub fn host_fd_count(&self) -> usize {
+        self.block.devices.iter().map(host_fds).sum::<usize>()
+            + self.net_builder.iter().map(host_fds).sum::<usize>()
+            + self.vsock.get().map_or(0, host_fds)
+            + self.balloon.get().map_or(0, host_fds)
+            + self.entropy.get().map_or(0, host_fds)
+            + self.fs.devices.iter().map(host_fds).sum::<usize>()
+            + self.input.devices.iter().map(host_fds).sum::<usize>()
+    }
+
+    // Checks that replacing a device owning `released` host fds with one owning `requested`
+    // host fds stays within the host fd limit. Runs before the new device opens any fd.
+    fn check_host_fd_limit(
+        &self,
+        released: usize,
+        requested: usize,
+    ) -> Result<(), HostFdLimitError> {
+        let in_use = self.host_fd_count() - released;
+        match self.vm_config.host_fd_limit {
+            Some(limit) if in_use + requested > limit => {
+                Err(HostFdLimitError(limit, in_use, requested))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the configuration of the serial console.
+    pub fn set_serial_config(&mut self, config: SerialConfig) -> Result<(), SerialConfigError> {
+        config.validate()?;
+        self.serial = config;
+        Ok(())
+    }
+
+    /// Sets the configuration of the clock devices.
+    pub fn set_clock_config(&mut self, config: ClockConfig) {
+        self.clock = config;
+    }
+
     /// Setter for mmds config.
     pub fn set_mmds_config(
         &mut self,
@@ -475,6 +629,17 @@ impl VmResources {
     }
 }
 
+// Host fds owned by a configured device.
+fn host_fds<T: VirtioDevice>(device: &Arc<Mutex<T>>) -> usize {
+    device.lock().expect("Poisoned lock").host_fd_count()
+}
+
+// Host fds owned by a device with `num_queues` queues: the queue eventfds, the interrupt eventfd
+// and its host backend (backing file, tap or socket), if any.
+fn device_host_fds(num_queues: usize, host_backend: bool) -> usize {
+    num_queues + 1 + usize::from(host_backend)
+}
+
 impl From<&VmResources> for VmmConfig {
     fn from(resources: &VmResources) -> Self {
         VmmConfig {
@@ -489,6 +654,10 @@ impl From<&VmResources> for VmmConfig {
             net_devices: resources.net_builder.configs(),
             vsock_device: resources.vsock.config(),
             entropy_device: resources.entropy.config(),
+            fs_devices: resources.fs.configs(),
+            input_devices: resources.input.configs(),
+            serial: Some(resources.serial.clone()),
+            clock: Some(resources.clock.clone()),
         }
     }
 }
@@ -510,6 +679,8 @@ mod tests {
     use crate::devices::virtio::balloon::Balloon;
     use crate::devices::virtio::block::virtio::VirtioBlockError;
     use crate::devices::virtio::block::{BlockError, CacheType};
+    use crate::devices::virtio::net::test_utils::PersistentTap;
+    use crate::devices::virtio::net::TapError;
     use crate::devices::virtio::vsock::VSOCK_DEV_ID;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{
@@ -536,12 +707,16 @@ mod tests {
             guest_mac: Some(MacAddr::from_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            tx_delay: None,
+            flow_stats: false,
         }
     }
 
     fn default_net_builder() -> NetBuilder {
         let mut net_builder = NetBuilder::new();
-        net_builder.build(default_net_cfg()).unwrap();
+        // The tap is created on the fly, as it does not exist beforehand.
+        let net = NetBuilder::create_net(default_net_cfg()).unwrap();
+        net_builder.add_device(Arc::new(Mutex::new(net)));
 
         net_builder
     }
@@ -559,6 +734,15 @@ mod tests {
                 path_on_host: Some(tmp_file.as_path().to_str().unwrap().to_string()),
                 rate_limiter: Some(RateLimiterConfig::default()),
                 file_engine_type: None,
+                interrupt_moderation: None,
+                io_retry: None,
+                geometry: None,
+                discard: None,
+                max_chain_len: None,
+                request_record_path: None,
+                restore_notification_window_ms: None,
+                write_batch: None,
+                fd_on_host: None,
 
                 socket: None,
             },
@@ -597,8 +781,13 @@ mod tests {
             net_builder: default_net_builder(),
             mmds: None,
             boot_timer: false,
+            device_self_test: false,
             mmds_size_limit: HTTP_MAX_PAYLOAD_SIZE,
             entropy: Default::default(),
+            fs: Default::default(),
+            input: Default::default(),
+            serial: Default::default(),
+            clock: Default::default(),
         }
     }
 
@@ -626,6 +815,11 @@ mod tests {
 
     #[test]
     fn test_from_json() {
+        let _taps = [
+            PersistentTap::new("hostname7"),
+            PersistentTap::new("hostname8"),
+            PersistentTap::new("hostname9"),
+        ];
         let kernel_file = TempFile::new().unwrap();
         let rootfs_file = TempFile::new().unwrap();
         let default_instance_info = InstanceInfo::default();
@@ -927,8 +1121,8 @@ mod tests {
         assert!(
             matches!(
                 error,
-                ResourcesError::NetDevice(NetworkInterfaceError::CreateNetworkDevice(
-                    crate::devices::virtio::net::NetError::TapOpen { .. },
+                ResourcesError::NetDevice(NetworkInterfaceError::OpenTap(
+                    TapError::IfreqExecuteError(..)
                 ))
             ),
             "{:?}",
@@ -1123,6 +1317,10 @@ mod tests {
 
     #[test]
     fn test_cast_to_vmm_config() {
+        let _taps = [
+            PersistentTap::new("hostname9"),
+            PersistentTap::new("hostname10"),
+        ];
         // No mmds config.
         {
             let kernel_file = TempFile::new().unwrap();
@@ -1332,6 +1530,7 @@ mod tests {
             cpu_template: Some(StaticCpuTemplate::V1N1),
             track_dirty_pages: Some(false),
             huge_pages: Some(HugePageConfig::None),
+            host_fd_limit: Some(None),
         };
 
         assert_ne!(
@@ -1479,6 +1678,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_serial_config() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.serial, SerialConfig::default());
+
+        let serial_config = SerialConfig {
+            panic_signature: Some("Kernel panic".to_string()),
+            ..Default::default()
+        };
+        vm_resources
+            .set_serial_config(serial_config.clone())
+            .unwrap();
+        assert_eq!(vm_resources.serial, serial_config);
+
+        // An invalid configuration leaves the previous one in place.
+        vm_resources
+            .set_serial_config(SerialConfig {
+                panic_signature: Some(String::new()),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(vm_resources.serial, serial_config);
+    }
+
+    #[test]
+    fn test_set_clock_config() {
+        let mut vm_resources = default_vm_resources();
+        // The VMClock device is opt-in.
+        assert!(!vm_resources.clock.vmclock);
+
+        vm_resources.set_clock_config(ClockConfig {
+            vmclock: true,
+            host_time: true,
+        });
+        assert!(vm_resources.clock.vmclock);
+        assert!(vm_resources.clock.host_time);
+    }
+
     #[test]
     fn test_set_entropy_device() {
         let mut vm_resources = default_vm_resources();
@@ -1592,16 +1829,65 @@ mod tests {
 
     #[test]
     fn test_set_net_device() {
+        let _tap = PersistentTap::new("dummy_path2");
         let mut vm_resources = default_vm_resources();
 
         // Clone the existing net config in order to obtain a new one.
         let mut new_net_device_cfg = default_net_cfg();
         new_net_device_cfg.iface_id = "new_net_if".to_string();
-        new_net_device_cfg.guest_mac = Some(MacAddr::from_str("01:23:45:67:89:0c").unwrap());
+        new_net_device_cfg.guest_mac = Some(MacAddr::from_str("02:23:45:67:89:0c").unwrap());
         new_net_device_cfg.host_dev_name = "dummy_path2".to_string();
         assert_eq!(vm_resources.net_builder.len(), 1);
 
         vm_resources.build_net_device(new_net_device_cfg).unwrap();
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
+
+    #[test]
+    fn test_host_fd_limit() {
+        let mut vm_resources = default_vm_resources();
+        // A block and a net device, with their queue eventfds, interrupt eventfd and backend.
+        let in_use = vm_resources.host_fd_count();
+        assert_eq!(in_use, 3 + 4);
+
+        // The limit cannot be set below the fds already in use.
+        assert_eq!(
+            vm_resources.update_vm_config(&MachineConfigUpdate {
+                host_fd_limit: Some(Some(in_use - 1)),
+                ..Default::default()
+            }),
+            Err(VmConfigError::HostFdLimitTooLow(in_use - 1, in_use))
+        );
+        vm_resources
+            .update_vm_config(&MachineConfigUpdate {
+                host_fd_limit: Some(Some(in_use + 1)),
+                ..Default::default()
+            })
+            .unwrap();
+
+        // The limit is checked before the backing file is opened.
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.drive_id = "block2".to_string();
+        block_cfg.path_on_host = Some("/invalid/path".to_string());
+        assert_eq!(
+            vm_resources
+                .set_block_device(block_cfg)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Attaching the device would exceed the host fd limit of {}: {} fds in use, 3 \
+                 requested.",
+                in_use + 1,
+                in_use
+            )
+        );
+        assert_eq!(vm_resources.block.devices.len(), 1);
+        assert_eq!(vm_resources.host_fd_count(), in_use);
+
+        // Replacing a device releases the fds of the old one.
+        let (block_cfg, _file) = default_block_cfg();
+        vm_resources.set_block_device(block_cfg).unwrap();
+        assert_eq!(vm_resources.block.devices.len(), 1);
+        assert_eq!(vm_resources.host_fd_count(), in_use);
+    }
 }
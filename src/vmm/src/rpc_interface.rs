@@ -3,6 +3,7 @@
 
 use std::fmt::{self, Debug};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
 use seccompiler::BpfThreadMap;
 use serde_json::Value;
@@ -20,6 +21,9 @@ use super::{
 };
 use crate::builder::StartMicrovmError;
 use crate::cpu_config::templates::{CustomCpuTemplate, GuestConfigError};
+use crate::devices::virtio::block::BackingInfo;
+use crate::devices::virtio::vsock::QuiesceState;
+use crate::devices::virtio::{TYPE_BLOCK, TYPE_NET};
 use crate::logger::{info, warn, LoggerConfig, *};
 use crate::mmds::data_store::{self, Mmds};
 use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
@@ -29,17 +33,23 @@ use crate::vmm_config::balloon::{
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
+use crate::vmm_config::clock::ClockConfig;
 use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
 use crate::vmm_config::entropy::{EntropyDeviceConfig, EntropyDeviceError};
+use crate::vmm_config::fs::{FsConfigError, FsDeviceConfig};
+use crate::vmm_config::input::{InputConfigError, InputDeviceConfig};
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::machine_config::{MachineConfig, MachineConfigUpdate, VmConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
-use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError, MmdsMergeConfig};
 use crate::vmm_config::net::{
     NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
+use crate::vmm_config::serial::{SerialConfig, SerialConfigError, SerialOutputUpdate};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, QuiesceGuestParams, SnapshotType,
+};
+use crate::vmm_config::vsock::{VsockBuilder, VsockConfigError, VsockDeviceConfig};
 use crate::vmm_config::{self, RateLimiterUpdate};
 use crate::EventManager;
 
@@ -63,8 +73,16 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the host file backing the block device with the given id. This action can only be
+    /// called after the microVM has booted.
+    GetBlockBackingInfo(String),
+    /// Get the runtime state of the attached devices, for debugging. This action can only be
+    /// called after the microVM has booted.
+    GetDeviceDebugDump,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
+    /// Get the state of the quiesce handshake with the guest agent.
+    GetGuestQuiesceState,
     /// Get MMDS contents.
     GetMMDS,
     /// Get the machine configuration of the microVM.
@@ -78,6 +96,12 @@ pub enum VmmAction {
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
     InsertBlockDevice(BlockDeviceConfig),
+    /// Add a new fs device or update one that already exists using the `FsDeviceConfig` as
+    /// input. This action can only be called before the microVM has booted.
+    InsertFsDevice(FsDeviceConfig),
+    /// Add a new input device or update one that already exists using the `InputDeviceConfig`
+    /// as input. This action can only be called before the microVM has booted.
+    InsertInputDevice(InputDeviceConfig),
     /// Add a new network interface config or update one that already exists using the
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
@@ -86,6 +110,8 @@ pub enum VmmAction {
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
     LoadSnapshot(LoadSnapshotParams),
+    /// Merge data into the MMDS contents, resolving conflicts according to the given policy.
+    MergeMMDS(MmdsMergeConfig),
     /// Partial update of the MMDS contents.
     PatchMMDS(Value),
     /// Pause the guest, by pausing the microVM VCPUs.
@@ -94,6 +120,9 @@ pub enum VmmAction {
     PutMMDS(Value),
     /// Configure the guest vCPU features.
     PutCpuConfiguration(CustomCpuTemplate),
+    /// Ask the guest agent connected to the vsock quiesce port to quiesce the guest, using the
+    /// `QuiesceGuestParams` as input. This action can only be called after the microVM has booted.
+    QuiesceGuest(QuiesceGuestParams),
     /// Resume the guest, by resuming the microVM VCPUs.
     Resume,
     /// Set the balloon device or update the one that already exists using the
@@ -109,6 +138,12 @@ pub enum VmmAction {
     /// Set the entropy device using `EntropyDeviceConfig` as input. This action can only be called
     /// before the microVM has booted.
     SetEntropyDevice(EntropyDeviceConfig),
+    /// Set the configuration of the serial console using `SerialConfig` as input. This action
+    /// can only be called before the microVM has booted.
+    SetSerialConfig(SerialConfig),
+    /// Set the configuration of the clock devices using `ClockConfig` as input. This action can
+    /// only be called before the microVM has booted.
+    SetClockConfig(ClockConfig),
     /// Launch the microVM. This action can only be called before the microVM has booted.
     StartMicroVm,
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
@@ -124,6 +159,8 @@ pub enum VmmAction {
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
+    /// Redirect the output of the serial console, after microVM start.
+    UpdateSerialOutput(SerialOutputUpdate),
     /// Update the microVM configuration (memory & vcpu) using `VmUpdateConfig` as input. This
     /// action can only be called before the microVM has booted.
     UpdateVmConfiguration(MachineConfigUpdate),
@@ -144,6 +181,10 @@ pub enum VmmActionError {
     DriveConfig(#[from] DriveError),
     /// Entropy device error: {0}
     EntropyDevice(#[from] EntropyDeviceError),
+    /// Fs device config error: {0}
+    FsConfig(#[from] FsConfigError),
+    /// Input device config error: {0}
+    InputConfig(#[from] InputConfigError),
     /// Internal VMM error: {0}
     InternalVmm(#[from] VmmError),
     /// Load snapshot error: {0}
@@ -170,6 +211,8 @@ pub enum VmmActionError {
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// Serial console config error: {0}
+    SerialConfig(#[from] SerialConfigError),
     /// Start microvm error: {0}
     StartMicrovm(#[from] StartMicrovmError),
     /// Vsock config error: {0}
@@ -185,10 +228,16 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The host file backing a block device.
+    BlockBackingInfo(BackingInfo),
+    /// The runtime state of the attached devices.
+    DeviceDebugDump(serde_json::Value),
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
+    /// The state of the quiesce handshake with the guest agent.
+    GuestQuiesceState(QuiesceState),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(MachineConfig),
     /// Mmds contents.
@@ -223,6 +272,20 @@ trait MmdsRequestHandler {
             })
     }
 
+    fn merge_mmds(&mut self, config: MmdsMergeConfig) -> Result<VmmData, VmmActionError> {
+        self.mmds()
+            .merge_data(config.data, config.policy)
+            .map(|()| VmmData::Empty)
+            .map_err(|err| match err {
+                data_store::MmdsDatastoreError::DataStoreLimitExceeded => {
+                    VmmActionError::MmdsLimitExceeded(
+                        data_store::MmdsDatastoreError::DataStoreLimitExceeded,
+                    )
+                }
+                _ => VmmActionError::Mmds(err),
+            })
+    }
+
     fn put_mmds(&mut self, value: serde_json::Value) -> Result<VmmData, VmmActionError> {
         self.mmds()
             .put_data(value)
@@ -332,6 +395,8 @@ impl<'a> PrebootApiController<'a> {
         to_api: &std::sync::mpsc::Sender<ApiResponse>,
         api_event_fd: &utils::eventfd::EventFd,
         boot_timer_enabled: bool,
+        device_self_test: bool,
+        max_guest_cid: Option<u32>,
         mmds_size_limit: usize,
         metadata_json: Option<&str>,
     ) -> Result<(VmResources, Arc<Mutex<Vmm>>), BuildMicrovmFromRequestsError> {
@@ -343,6 +408,8 @@ impl<'a> PrebootApiController<'a> {
         {
             vm_resources.mmds_size_limit = mmds_size_limit;
             vm_resources.boot_timer = boot_timer_enabled;
+            vm_resources.device_self_test = device_self_test;
+            vm_resources.vsock = VsockBuilder::with_max_guest_cid(max_guest_cid);
         }
 
         // Init the data store from file, if present.
@@ -426,10 +493,13 @@ impl<'a> PrebootApiController<'a> {
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(self.instance_info.clone())),
             GetVmmVersion => Ok(VmmData::VmmVersion(self.instance_info.vmm_version.clone())),
             InsertBlockDevice(config) => self.insert_block_device(config),
+            InsertFsDevice(config) => self.insert_fs_device(config),
+            InsertInputDevice(config) => self.insert_input_device(config),
             InsertNetworkDevice(config) => self.insert_net_device(config),
             LoadSnapshot(config) => self
                 .load_snapshot(&config)
                 .map_err(VmmActionError::LoadSnapshot),
+            MergeMMDS(config) => self.merge_mmds(config),
             PatchMMDS(value) => self.patch_mmds(value),
             PutCpuConfiguration(custom_cpu_template) => {
                 self.set_custom_cpu_template(custom_cpu_template)
@@ -441,16 +511,23 @@ impl<'a> PrebootApiController<'a> {
             StartMicroVm => self.start_microvm(),
             UpdateVmConfiguration(config) => self.update_vm_config(config),
             SetEntropyDevice(config) => self.set_entropy_device(config),
+            SetSerialConfig(config) => self.set_serial_config(config),
+            SetClockConfig(config) => self.set_clock_config(config),
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
             | FlushMetrics
+            | GetGuestQuiesceState
             | Pause
+            | QuiesceGuest(_)
             | Resume
             | GetBalloonStats
+            | GetBlockBackingInfo(_)
+            | GetDeviceDebugDump
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
-            | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            | UpdateNetworkInterface(_)
+            | UpdateSerialOutput(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
         }
@@ -472,6 +549,22 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::DriveConfig)
     }
 
+    fn insert_fs_device(&mut self, cfg: FsDeviceConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .build_fs_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::FsConfig)
+    }
+
+    fn insert_input_device(&mut self, cfg: InputDeviceConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources
+            .build_input_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::InputConfig)
+    }
+
     fn insert_net_device(
         &mut self,
         cfg: NetworkInterfaceConfig,
@@ -537,6 +630,19 @@ impl<'a> PrebootApiController<'a> {
         Ok(VmmData::Empty)
     }
 
+    // The serial console of a restored microVM is configured too, so this does not commit to
+    // the boot path.
+    fn set_serial_config(&mut self, cfg: SerialConfig) -> Result<VmmData, VmmActionError> {
+        self.vm_resources.set_serial_config(cfg)?;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_clock_config(&mut self, cfg: ClockConfig) -> Result<VmmData, VmmActionError> {
+        self.boot_path = true;
+        self.vm_resources.set_clock_config(cfg);
+        Ok(VmmData::Empty)
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn start_microvm(&mut self) -> Result<VmmData, VmmActionError> {
@@ -646,7 +752,23 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetBlockBackingInfo(drive_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .block_backing_info(&drive_id)
+                .map(VmmData::BlockBackingInfo)
+                .map_err(|err| VmmActionError::DriveConfig(DriveError::BackingInfo(err))),
+            GetDeviceDebugDump => Ok(VmmData::DeviceDebugDump(
+                self.vmm.lock().expect("Poisoned lock").device_debug_dump(),
+            )),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
+            GetGuestQuiesceState => Ok(VmmData::GuestQuiesceState(
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .guest_quiesce_state(),
+            )),
             GetMMDS => self.get_mmds(),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(MachineConfig::from(
                 &self.vm_resources.vm_config,
@@ -657,9 +779,17 @@ impl RuntimeApiController {
             GetVmmVersion => Ok(VmmData::VmmVersion(
                 self.vmm.lock().expect("Poisoned lock").version(),
             )),
+            MergeMMDS(config) => self.merge_mmds(config),
             PatchMMDS(value) => self.patch_mmds(value),
             Pause => self.pause(),
             PutMMDS(value) => self.put_mmds(value),
+            QuiesceGuest(params) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .request_guest_quiesce(Duration::from_millis(params.timeout_ms))
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::InternalVmm),
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
@@ -679,12 +809,26 @@ impl RuntimeApiController {
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
             UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateSerialOutput(update) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_serial_output(update.output_path.as_deref())
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::InternalVmm),
+            // MMIO devices are laid out at boot, there is no slot for a new one.
+            InsertBlockDevice(_) => Err(VmmActionError::NotSupported(
+                "Hot-plugging a block device requires a PCI transport; MMIO devices are fixed at \
+                 boot."
+                    .to_string(),
+            )),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
-            | InsertBlockDevice(_)
+            | InsertFsDevice(_)
+            | InsertInputDevice(_)
             | InsertNetworkDevice(_)
             | LoadSnapshot(_)
             | PutCpuConfiguration(_)
@@ -692,6 +836,8 @@ impl RuntimeApiController {
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
             | SetEntropyDevice(_)
+            | SetSerialConfig(_)
+            | SetClockConfig(_)
             | StartMicroVm
             | UpdateVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
@@ -802,7 +948,9 @@ impl RuntimeApiController {
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device, update the disk image on the
     ///    device and its virtio configuration
-    ///  - rate limiter configuration.
+    ///  - rate limiter configuration
+    ///  - cache type
+    ///  - enabled state.
     fn update_block_device(
         &mut self,
         new_cfg: BlockDeviceUpdateConfig,
@@ -810,7 +958,11 @@ impl RuntimeApiController {
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
 
         // vhost-user-block updates
-        if new_cfg.path_on_host.is_none() && new_cfg.rate_limiter.is_none() {
+        if new_cfg.path_on_host.is_none()
+            && new_cfg.rate_limiter.is_none()
+            && new_cfg.cache_type.is_none()
+            && new_cfg.enabled.is_none()
+        {
             vmm.update_vhost_user_block_config(&new_cfg.drive_id)
                 .map(|()| VmmData::Empty)
                 .map_err(DriveError::DeviceUpdate)?;
@@ -831,6 +983,16 @@ impl RuntimeApiController {
             .map(|()| VmmData::Empty)
             .map_err(DriveError::DeviceUpdate)?;
         }
+        if let Some(cache_type) = new_cfg.cache_type {
+            vmm.update_block_cache_type(&new_cfg.drive_id, cache_type)
+                .map(|()| VmmData::Empty)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
+        if let Some(enabled) = new_cfg.enabled {
+            vmm.set_device_enabled(TYPE_BLOCK, &new_cfg.drive_id, enabled)
+                .map(|()| VmmData::Empty)
+                .map_err(DriveError::DeviceUpdate)?;
+        }
         Ok(VmmData::Empty)
     }
 
@@ -839,26 +1001,27 @@ impl RuntimeApiController {
         &mut self,
         new_cfg: NetworkInterfaceUpdateConfig,
     ) -> Result<VmmData, VmmActionError> {
-        self.vmm
-            .lock()
-            .expect("Poisoned lock")
-            .update_net_rate_limiters(
-                &new_cfg.iface_id,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
-                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
-            )
-            .map(|()| VmmData::Empty)
-            .map_err(NetworkInterfaceError::DeviceUpdate)
-            .map_err(VmmActionError::NetworkConfig)
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        vmm.update_net_rate_limiters(
+            &new_cfg.iface_id,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
+            RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+        )
+        .map_err(NetworkInterfaceError::DeviceUpdate)?;
+        if let Some(enabled) = new_cfg.enabled {
+            vmm.set_device_enabled(TYPE_NET, &new_cfg.iface_id, enabled)
+                .map_err(NetworkInterfaceError::DeviceUpdate)?;
+        }
+        Ok(VmmData::Empty)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     use seccompiler::BpfThreadMap;
 
@@ -867,9 +1030,10 @@ mod tests {
     use crate::cpu_config::templates::{CpuTemplateType, StaticCpuTemplate};
     use crate::devices::virtio::balloon::{BalloonConfig, BalloonError};
     use crate::devices::virtio::block::CacheType;
+    use crate::devices::virtio::input::{InputError, InputKind};
     use crate::devices::virtio::rng::EntropyError;
     use crate::devices::virtio::vsock::VsockError;
-    use crate::mmds::data_store::MmdsVersion;
+    use crate::mmds::data_store::{MmdsMergePolicy, MmdsVersion};
     use crate::vmm_config::balloon::BalloonBuilder;
     use crate::vmm_config::machine_config::VmConfig;
     use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
@@ -899,6 +1063,9 @@ mod tests {
                     | (StartMicrovm(_), StartMicrovm(_))
                     | (VsockConfig(_), VsockConfig(_))
                     | (EntropyDevice(_), EntropyDevice(_))
+                    | (FsConfig(_), FsConfig(_))
+                    | (InputConfig(_), InputConfig(_))
+                    | (SerialConfig(_), SerialConfig(_))
             )
         }
     }
@@ -917,6 +1084,10 @@ mod tests {
         vsock_set: bool,
         net_set: bool,
         entropy_set: bool,
+        fs_set: bool,
+        input_set: bool,
+        serial_set: bool,
+        pub clock: ClockConfig,
         pub mmds: Option<Arc<Mutex<Mmds>>>,
         pub mmds_size_limit: usize,
         pub boot_timer: bool,
@@ -1025,6 +1196,32 @@ mod tests {
             Ok(())
         }
 
+        pub fn build_fs_device(&mut self, cfg: FsDeviceConfig) -> Result<(), FsConfigError> {
+            if self.force_errors {
+                return Err(FsConfigError::TagAlreadyExists(cfg.tag));
+            }
+            self.fs_set = true;
+            Ok(())
+        }
+
+        pub fn build_input_device(&mut self, _: InputDeviceConfig) -> Result<(), InputConfigError> {
+            if self.force_errors {
+                return Err(InputConfigError::CreateInputDevice(InputError::EventFd(
+                    io::Error::from_raw_os_error(0),
+                )));
+            }
+            self.input_set = true;
+            Ok(())
+        }
+
+        pub fn set_serial_config(&mut self, _: SerialConfig) -> Result<(), SerialConfigError> {
+            if self.force_errors {
+                return Err(SerialConfigError::EmptyPanicSignature);
+            }
+            self.serial_set = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(
             &mut self,
             mmds_config: MmdsConfig,
@@ -1058,6 +1255,10 @@ mod tests {
         pub fn set_custom_cpu_template(&mut self, cpu_template: CustomCpuTemplate) {
             self.vm_config.set_custom_cpu_template(cpu_template);
         }
+
+        pub fn set_clock_config(&mut self, config: ClockConfig) {
+            self.clock = config;
+        }
     }
 
     impl From<&MockVmRes> for VmmConfig {
@@ -1091,7 +1292,11 @@ mod tests {
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_block_device_vhost_user_config_called: bool,
+        pub update_block_cache_type_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub set_device_enabled_called: bool,
+        pub request_guest_quiesce_called: bool,
+        pub set_serial_output_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1156,6 +1361,29 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_serial_output(&mut self, _: Option<&Path>) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::Serial(io::ErrorKind::PermissionDenied.into()));
+            }
+            self.set_serial_output_called = true;
+            Ok(())
+        }
+
+        pub fn block_backing_info(&self, _: &str) -> Result<BackingInfo, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::DeviceNotFound,
+                ));
+            }
+            Ok(BackingInfo {
+                path: String::new(),
+                read_only: false,
+                direct: false,
+                cache_type: CacheType::Unsafe,
+                file_engine_type: None,
+            })
+        }
+
         pub fn update_block_device_path(&mut self, _: &str, _: String) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1175,6 +1403,16 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_block_cache_type(&mut self, _: &str, _: CacheType) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.update_block_cache_type_called = true;
+            Ok(())
+        }
+
         pub fn update_vhost_user_block_config(&mut self, _: &str) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -1202,6 +1440,36 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_device_enabled(&mut self, _: u32, _: &str, _: bool) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::MmioError::InvalidDeviceType,
+                ));
+            }
+            self.set_device_enabled_called = true;
+            Ok(())
+        }
+
+        pub fn request_guest_quiesce(&mut self, _: Duration) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::GuestQuiesce(
+                    crate::devices::virtio::vsock::VsockUnixBackendError::QuiesceAgent(
+                        std::io::ErrorKind::NotConnected.into(),
+                    ),
+                ));
+            }
+            self.request_guest_quiesce_called = true;
+            Ok(())
+        }
+
+        pub fn guest_quiesce_state(&self) -> QuiesceState {
+            QuiesceState::NoAgent
+        }
+
+        pub fn device_debug_dump(&self) -> serde_json::Value {
+            serde_json::json!({ "host_fd_count": 0, "devices": [] })
+        }
+
         pub fn instance_info(&self) -> InstanceInfo {
             InstanceInfo::default()
         }
@@ -1398,6 +1666,15 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -1422,6 +1699,8 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1434,6 +1713,8 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
         });
         check_preboot_request_err(
             req,
@@ -1449,6 +1730,9 @@ mod tests {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            max_connections: None,
+            log_port: None,
+            quiesce_port: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1459,6 +1743,9 @@ mod tests {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            max_connections: None,
+            log_port: None,
+            quiesce_port: None,
         });
         check_preboot_request_err(
             req,
@@ -1477,6 +1764,80 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_preboot_set_serial_config() {
+        let req = VmmAction::SetSerialConfig(SerialConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.serial_set);
+        });
+
+        let req = VmmAction::SetSerialConfig(SerialConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::SerialConfig(SerialConfigError::EmptyPanicSignature),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_clock_config() {
+        let req = VmmAction::SetClockConfig(ClockConfig {
+            vmclock: true,
+            host_time: true,
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.clock.vmclock);
+            assert!(vm_res.clock.host_time);
+        });
+    }
+
+    #[test]
+    fn test_preboot_insert_fs_device() {
+        let req = VmmAction::InsertFsDevice(FsDeviceConfig {
+            fs_id: String::new(),
+            tag: String::new(),
+            socket: String::new(),
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.fs_set);
+        });
+
+        let req = VmmAction::InsertFsDevice(FsDeviceConfig {
+            fs_id: String::new(),
+            tag: String::new(),
+            socket: String::new(),
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::FsConfig(FsConfigError::TagAlreadyExists(String::new())),
+        );
+    }
+
+    #[test]
+    fn test_preboot_insert_input_device() {
+        let req = VmmAction::InsertInputDevice(InputDeviceConfig {
+            input_id: String::new(),
+            kind: InputKind::Keyboard,
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.input_set);
+        });
+
+        let req = VmmAction::InsertInputDevice(InputDeviceConfig {
+            input_id: String::new(),
+            kind: InputKind::Keyboard,
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::InputConfig(InputConfigError::CreateInputDevice(InputError::EventFd(
+                io::Error::from_raw_os_error(0),
+            ))),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
@@ -1721,6 +2082,74 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_merge_mmds() {
+        let mmds = Arc::new(Mutex::new(Mmds::default()));
+        check_runtime_request_with_mmds(
+            VmmAction::PutMMDS(serde_json::from_str(r#"{"key1": "value1"}"#).unwrap()),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(result, Ok(VmmData::Empty));
+            },
+        );
+
+        check_runtime_request_with_mmds(
+            VmmAction::MergeMMDS(MmdsMergeConfig {
+                data: serde_json::from_str(r#"{"key2": "value2"}"#).unwrap(),
+                policy: MmdsMergePolicy::Reject,
+            }),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(result, Ok(VmmData::Empty));
+            },
+        );
+        check_runtime_request_with_mmds(VmmAction::GetMMDS, mmds.clone(), |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MmdsValue(
+                    serde_json::from_str(r#"{"key1": "value1", "key2": "value2"}"#).unwrap()
+                ))
+            );
+        });
+
+        // Conflicting values are rejected.
+        check_runtime_request_with_mmds(
+            VmmAction::MergeMMDS(MmdsMergeConfig {
+                data: serde_json::from_str(r#"{"key1": "other"}"#).unwrap(),
+                policy: MmdsMergePolicy::Reject,
+            }),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(
+                    result,
+                    Err(VmmActionError::Mmds(
+                        data_store::MmdsDatastoreError::MergeConflict("/key1".to_string())
+                    ))
+                );
+            },
+        );
+
+        // Unless the last writer wins.
+        check_runtime_request_with_mmds(
+            VmmAction::MergeMMDS(MmdsMergeConfig {
+                data: serde_json::from_str(r#"{"key1": "other"}"#).unwrap(),
+                policy: MmdsMergePolicy::LastWriterWins,
+            }),
+            mmds.clone(),
+            |result, _| {
+                assert_eq!(result, Ok(VmmData::Empty));
+            },
+        );
+        check_runtime_request_with_mmds(VmmAction::GetMMDS, mmds, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::MmdsValue(
+                    serde_json::from_str(r#"{"key1": "other", "key2": "value2"}"#).unwrap()
+                ))
+            );
+        });
+    }
+
     #[test]
     fn test_preboot_load_snapshot() {
         let mut vm_resources = MockVmRes::default();
@@ -1737,6 +2166,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1753,6 +2184,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: true,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1781,6 +2214,14 @@ mod tests {
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetBlockBackingInfo(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetDeviceDebugDump,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1800,6 +2241,7 @@ mod tests {
                 iface_id: String::new(),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                enabled: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1808,9 +2250,25 @@ mod tests {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                max_snapshot_size: None,
+                require_guest_quiesce: false,
+                abort_on_guest_reset: false,
+                quiesce_timeout_ms: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::QuiesceGuest(QuiesceGuestParams { timeout_ms: 0 }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetGuestQuiesceState,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::UpdateSerialOutput(SerialOutputUpdate::default()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         #[cfg(target_arch = "x86_64")]
         check_preboot_request_err(
             VmmAction::SendCtrlAltDel,
@@ -1879,6 +2337,33 @@ mod tests {
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
     }
 
+    #[test]
+    fn test_runtime_quiesce_guest() {
+        let req = VmmAction::QuiesceGuest(QuiesceGuestParams { timeout_ms: 1000 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.request_guest_quiesce_called)
+        });
+
+        let req = VmmAction::QuiesceGuest(QuiesceGuestParams { timeout_ms: 1000 });
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::GuestQuiesce(
+                crate::devices::virtio::vsock::VsockUnixBackendError::QuiesceAgent(
+                    std::io::ErrorKind::NotConnected.into(),
+                ),
+            )),
+        );
+
+        let req = VmmAction::GetGuestQuiesceState;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::GuestQuiesceState(QuiesceState::NoAgent))
+            );
+        });
+    }
+
     #[test]
     fn test_runtime_resume() {
         let req = VmmAction::Resume;
@@ -1976,6 +2461,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_update_serial_output() {
+        let req = VmmAction::UpdateSerialOutput(SerialOutputUpdate {
+            output_path: Some(PathBuf::from("serial.out")),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_serial_output_called);
+        });
+
+        let req = VmmAction::UpdateSerialOutput(SerialOutputUpdate::default());
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::Serial(io::ErrorKind::PermissionDenied.into())),
+        );
+    }
+
+    #[test]
+    fn test_runtime_block_backing_info() {
+        let req = VmmAction::GetBlockBackingInfo(String::new());
+        check_runtime_request(req, |result, _| {
+            assert!(matches!(result, Ok(VmmData::BlockBackingInfo(_))));
+        });
+
+        let req = VmmAction::GetBlockBackingInfo(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::BackingInfo(VmmError::DeviceManager(
+                crate::device_manager::mmio::MmioError::DeviceNotFound,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_device_debug_dump() {
+        let req = VmmAction::GetDeviceDebugDump;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::DeviceDebugDump(
+                    serde_json::json!({ "host_fd_count": 0, "devices": [] })
+                ))
+            );
+        });
+    }
+
     #[test]
     fn test_runtime_update_block_device_path() {
         let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
@@ -2018,6 +2549,26 @@ mod tests {
                 crate::device_manager::mmio::MmioError::InvalidDeviceType,
             ))),
         );
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            cache_type: Some(CacheType::Writeback),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_cache_type_called);
+            assert!(!vmm.update_block_device_vhost_user_config_called);
+        });
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            enabled: Some(false),
+            ..Default::default()
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_device_enabled_called);
+            assert!(!vmm.update_block_device_vhost_user_config_called);
+        });
     }
 
     #[test]
@@ -2026,6 +2577,7 @@ mod tests {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            enabled: None,
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -2036,6 +2588,7 @@ mod tests {
             iface_id: String::new(),
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            enabled: None,
         });
         check_runtime_request_err(
             req,
@@ -2043,6 +2596,18 @@ mod tests {
                 VmmError::DeviceManager(crate::device_manager::mmio::MmioError::InvalidDeviceType),
             )),
         );
+
+        let req = VmmAction::UpdateNetworkInterface(NetworkInterfaceUpdateConfig {
+            iface_id: String::new(),
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            enabled: Some(false),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_net_rate_limiters_called);
+            assert!(vmm.set_device_enabled_called);
+        });
     }
 
     #[test]
@@ -2078,10 +2643,23 @@ mod tests {
                 path_on_host: Some(String::new()),
                 rate_limiter: None,
                 file_engine_type: None,
+                interrupt_moderation: None,
+                io_retry: None,
+                geometry: None,
+                discard: None,
+                max_chain_len: None,
+                request_record_path: None,
+                restore_notification_window_ms: None,
+                write_batch: None,
+                fd_on_host: None,
 
                 socket: None,
             }),
-            VmmActionError::OperationNotSupportedPostBoot,
+            VmmActionError::NotSupported(
+                "Hot-plugging a block device requires a PCI transport; MMIO devices are fixed at \
+                 boot."
+                    .to_string(),
+            ),
         );
         check_runtime_request_err(
             VmmAction::InsertNetworkDevice(NetworkInterfaceConfig {
@@ -2090,6 +2668,8 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                tx_delay: None,
+                flow_stats: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2098,6 +2678,9 @@ mod tests {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                max_connections: None,
+                log_port: None,
+                quiesce_port: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2110,6 +2693,9 @@ mod tests {
                 vsock_id: Some(String::new()),
                 guest_cid: 0,
                 uds_path: String::new(),
+                max_connections: None,
+                log_port: None,
+                quiesce_port: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2134,6 +2720,8 @@ mod tests {
                 },
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                relaxed_version_check: false,
+                preserve_device_uptime: false,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2141,6 +2729,29 @@ mod tests {
             VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        check_runtime_request_err(
+            VmmAction::SetSerialConfig(SerialConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetClockConfig(ClockConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertFsDevice(FsDeviceConfig {
+                fs_id: String::new(),
+                tag: String::new(),
+                socket: String::new(),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertInputDevice(InputDeviceConfig {
+                input_id: String::new(),
+                kind: InputKind::Keyboard,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
     }
 
     fn verify_load_snap_disallowed_after_boot_resources(res: VmmAction, res_name: &str) {
@@ -2160,6 +2771,8 @@ mod tests {
             },
             enable_diff_snapshots: false,
             resume_vm: false,
+            relaxed_version_check: false,
+            preserve_device_uptime: false,
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -2188,6 +2801,15 @@ mod tests {
             path_on_host: Some(String::new()),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -2201,6 +2823,8 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            tx_delay: None,
+            flow_stats: false,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
@@ -2211,6 +2835,9 @@ mod tests {
             vsock_id: Some(String::new()),
             guest_cid: 0,
             uds_path: String::new(),
+            max_connections: None,
+            log_port: None,
+            quiesce_port: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
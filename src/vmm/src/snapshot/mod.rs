@@ -35,17 +35,21 @@ use semver::Version;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::logger::warn;
 use crate::snapshot::crc::{CRC64Reader, CRC64Writer};
 pub use crate::snapshot::persist::Persist;
 
+const SNAPSHOT_MAGIC_ID_X86_64: u64 = 0x0710_1984_8664_0000u64;
+const SNAPSHOT_MAGIC_ID_AARCH64: u64 = 0x0710_1984_AAAA_0000u64;
+
 #[cfg(target_arch = "x86_64")]
-const SNAPSHOT_MAGIC_ID: u64 = 0x0710_1984_8664_0000u64;
+const SNAPSHOT_MAGIC_ID: u64 = SNAPSHOT_MAGIC_ID_X86_64;
 
 /// Constant bounding how much memory bincode may allocate during vmstate file deserialization
 const VM_STATE_DESERIALIZE_LIMIT: u64 = 10_485_760; // 10MiB
 
 #[cfg(target_arch = "aarch64")]
-const SNAPSHOT_MAGIC_ID: u64 = 0x0710_1984_AAAA_0000u64;
+const SNAPSHOT_MAGIC_ID: u64 = SNAPSHOT_MAGIC_ID_AARCH64;
 
 /// Error definitions for the Snapshot API.
 #[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq)]
@@ -82,6 +86,15 @@ impl SnapshotHdr {
     }
 }
 
+/// Information about the producer of a snapshot, read from its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    /// Snapshot data version.
+    pub version: Version,
+    /// Architecture of the host that produced the snapshot.
+    pub arch: &'static str,
+}
+
 /// Firecracker snapshot type
 ///
 /// A type used to store and load Firecracker snapshots of a particular version
@@ -106,6 +119,26 @@ impl Snapshot {
         Ok(hdr.version)
     }
 
+    /// Reads the snapshot header, without reading the state that follows it.
+    ///
+    /// Unlike [`Snapshot::get_format_version`], this also accepts snapshots produced on other
+    /// architectures, and reports which one produced the snapshot.
+    pub fn peek_version<T>(reader: &mut T) -> Result<SnapshotInfo, SnapshotError>
+    where
+        T: Read + Debug,
+    {
+        let hdr: SnapshotHdr = Self::deserialize(reader)?;
+        let arch = match hdr.magic {
+            SNAPSHOT_MAGIC_ID_X86_64 => "x86_64",
+            SNAPSHOT_MAGIC_ID_AARCH64 => "aarch64",
+            magic => return Err(SnapshotError::InvalidMagic(magic)),
+        };
+        Ok(SnapshotInfo {
+            version: hdr.version,
+            arch,
+        })
+    }
+
     /// Helper function to deserialize an object from a reader
     pub fn deserialize<T, O>(reader: &mut T) -> Result<O, SnapshotError>
     where
@@ -150,6 +183,20 @@ impl Snapshot {
 
     /// Load a snapshot from a reader and validate its CRC
     pub fn load<T, O>(reader: &mut T, snapshot_len: usize) -> Result<(O, Version), SnapshotError>
+    where
+        T: Read + Debug,
+        O: DeserializeOwned + Debug,
+    {
+        let (data, version, _) = Self::load_with_trailing_len(reader, snapshot_len)?;
+        Ok((data, version))
+    }
+
+    /// Load a snapshot from a reader and validate its CRC, also returning the number of bytes
+    /// left unread after the state.
+    fn load_with_trailing_len<T, O>(
+        reader: &mut T,
+        snapshot_len: usize,
+    ) -> Result<(O, Version, usize), SnapshotError>
     where
         T: Read + Debug,
         O: DeserializeOwned + Debug,
@@ -177,7 +224,8 @@ impl Snapshot {
         }
 
         let mut snapshot_slice: &[u8] = snapshot.as_mut_slice();
-        Snapshot::unchecked_load::<_, O>(&mut snapshot_slice)
+        let (data, version) = Snapshot::unchecked_load::<_, O>(&mut snapshot_slice)?;
+        Ok((data, version, snapshot_slice.len()))
     }
 
     /// Load a snapshot from a reader object and perform a snapshot version check
@@ -198,6 +246,40 @@ impl Snapshot {
         }
     }
 
+    /// Load a snapshot from a reader object, also accepting newer minor versions of our format.
+    ///
+    /// This is meant for rolling back to an older Firecracker. The state is not self-describing,
+    /// so fields a newer version added can only be ignored when they were appended at the end of
+    /// the state; any other layout change most likely fails to deserialize.
+    pub fn load_with_relaxed_version_check<T, O>(
+        &self,
+        reader: &mut T,
+        snapshot_len: usize,
+    ) -> Result<O, SnapshotError>
+    where
+        T: Read + Debug,
+        O: DeserializeOwned + Debug,
+    {
+        let (data, version, trailing_len) =
+            Snapshot::load_with_trailing_len::<_, O>(reader, snapshot_len)?;
+        if version.major != self.version.major {
+            return Err(SnapshotError::InvalidFormatVersion(version));
+        }
+        if version.minor > self.version.minor {
+            warn!(
+                "Loading snapshot of newer format version {} with relaxed version check",
+                version
+            );
+        }
+        if trailing_len > 0 {
+            warn!(
+                "Ignoring {} bytes of unknown snapshot state after the known fields",
+                trailing_len
+            );
+        }
+        Ok(data)
+    }
+
     /// Saves a snapshot and include a CRC64 checksum.
     pub fn save<T, O>(&self, writer: &mut T, object: &O) -> Result<(), SnapshotError>
     where
@@ -250,6 +332,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_peek_version() {
+        let snapshot = Snapshot::new(Version::new(3, 1, 4));
+        let state = vec![42u8; 64];
+        let mut snapshot_data = vec![0u8; 200];
+        snapshot
+            .save(&mut snapshot_data.as_mut_slice(), &state)
+            .unwrap();
+
+        let mut reader = snapshot_data.as_slice();
+        let info = Snapshot::peek_version(&mut reader).unwrap();
+        assert_eq!(info.version, Version::new(3, 1, 4));
+        assert_eq!(info.arch, std::env::consts::ARCH);
+        // Only the header was read, the state follows.
+        let peeked: Vec<u8> = Snapshot::deserialize(&mut reader).unwrap();
+        assert_eq!(peeked, state);
+
+        // Snapshots of other architectures are recognized.
+        let mut hdr_data = vec![0u8; 64];
+        let other_hdr = SnapshotHdr {
+            magic: SNAPSHOT_MAGIC_ID_AARCH64 ^ SNAPSHOT_MAGIC_ID_X86_64 ^ SNAPSHOT_MAGIC_ID,
+            version: Version::new(2, 0, 0),
+        };
+        Snapshot::serialize(&mut hdr_data.as_mut_slice(), &other_hdr).unwrap();
+        let info = Snapshot::peek_version(&mut hdr_data.as_slice()).unwrap();
+        assert_eq!(info.version, Version::new(2, 0, 0));
+        assert_ne!(info.arch, std::env::consts::ARCH);
+
+        snapshot_data[0] = 0x01;
+        assert!(matches!(
+            Snapshot::peek_version(&mut snapshot_data.as_slice()),
+            Err(SnapshotError::InvalidMagic(_))
+        ));
+    }
+
     #[test]
     fn test_bad_snapshot_size() {
         let snapshot_data = vec![0u8; 1];
@@ -386,4 +503,50 @@ mod tests {
             .load_with_version_check::<_, u8>(&mut data.as_slice(), data.len())
             .unwrap();
     }
+    #[test]
+    fn test_relaxed_version_check() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct OldState {
+            a: u32,
+        }
+        #[derive(Debug, Serialize, Deserialize)]
+        struct NewState {
+            a: u32,
+            b: u64,
+        }
+
+        // A newer minor version appended a field to the state.
+        let mut data = Vec::new();
+        let snapshot = Snapshot::new(Version::new(1, 4, 0));
+        snapshot
+            .save(&mut data, &NewState { a: 42, b: 0xdead })
+            .unwrap();
+
+        let snapshot = Snapshot::new(Version::new(1, 3, 0));
+        assert!(matches!(
+            snapshot.load_with_version_check::<_, OldState>(&mut data.as_slice(), data.len()),
+            Err(SnapshotError::InvalidFormatVersion(_))
+        ));
+        let state: OldState = snapshot
+            .load_with_relaxed_version_check(&mut data.as_slice(), data.len())
+            .unwrap();
+        assert_eq!(state.a, 42);
+
+        // Major versions still have to match.
+        let snapshot = Snapshot::new(Version::new(2, 3, 0));
+        assert!(matches!(
+            snapshot
+                .load_with_relaxed_version_check::<_, OldState>(&mut data.as_slice(), data.len()),
+            Err(SnapshotError::InvalidFormatVersion(_))
+        ));
+
+        // The CRC is still validated.
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+        let snapshot = Snapshot::new(Version::new(1, 3, 0));
+        assert!(matches!(
+            snapshot.load_with_relaxed_version_check::<_, OldState>(&mut data.as_slice(), len),
+            Err(SnapshotError::Crc64(_))
+        ));
+    }
 }
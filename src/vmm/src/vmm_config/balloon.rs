@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 pub use crate::devices::virtio::balloon::device::BalloonStats;
 pub use crate::devices::virtio::balloon::BALLOON_DEV_ID;
 use crate::devices::virtio::balloon::{Balloon, BalloonConfig};
+use crate::vmm_config::machine_config::HostFdLimitError;
 
 type MutexBalloon = Arc<Mutex<Balloon>>;
 
@@ -30,6 +31,8 @@ pub enum BalloonConfigError {
     UpdateFailure(std::io::Error),
     /// Firecracker's huge pages support is incompatible with memory ballooning.
     HugePages,
+    /// {0}
+    HostFdLimit(HostFdLimitError),
 }
 
 /// This struct represents the strongly typed equivalent of the json body
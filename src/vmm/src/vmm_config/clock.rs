@@ -0,0 +1,20 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Auxiliary module for configuring the clock devices of the microVM.
+
+use serde::{Deserialize, Serialize};
+
+/// Strongly typed structure used to describe the clock devices of the microVM.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockConfig {
+    /// Whether to attach a VMClock device, which tells the guest that its clock was disrupted
+    /// after a snapshot restore. The device is only available on x86_64.
+    #[serde(default)]
+    pub vmclock: bool,
+    /// Whether to attach a device exposing the host time to the guest through a periodically
+    /// refreshed memory page. The device is only available on x86_64.
+    #[serde(default)]
+    pub host_time: bool,
+}
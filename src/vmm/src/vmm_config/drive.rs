@@ -3,25 +3,35 @@
 
 use std::collections::VecDeque;
 use std::io;
+use std::os::unix::io::RawFd;
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
 use super::RateLimiterConfig;
 use crate::devices::virtio::block::device::Block;
+pub use crate::devices::virtio::block::virtio::batch::WriteBatchConfig;
 pub use crate::devices::virtio::block::virtio::device::FileEngineType;
+pub use crate::devices::virtio::block::virtio::geometry::DiskGeometryConfig;
+pub use crate::devices::virtio::block::virtio::moderation::InterruptModerationConfig;
+pub use crate::devices::virtio::block::virtio::retry::IoRetryConfig;
 use crate::devices::virtio::block::{BlockError, CacheType};
+use crate::vmm_config::machine_config::HostFdLimitError;
 use crate::VmmError;
 
 /// Errors associated with the operations allowed on a drive.
 #[derive(Debug, thiserror::Error, displaydoc::Display)]
 pub enum DriveError {
+    /// Unable to get the backing file of the block device: {0}
+    BackingInfo(VmmError),
     /// Unable to create the virtio block device: {0}
     CreateBlockDevice(BlockError),
     /// Cannot create RateLimiter: {0}
     CreateRateLimiter(io::Error),
     /// Unable to patch the block device: {0} Please verify the request arguments.
     DeviceUpdate(VmmError),
+    /// {0}
+    HostFdLimit(#[from] HostFdLimitError),
     /// A root block device already exists!
     RootBlockDeviceAlreadyAdded,
 }
@@ -58,6 +68,25 @@ pub struct BlockDeviceConfig {
     // pub file_engine_type: FileEngineType,
     #[serde(rename = "io_engine")]
     pub file_engine_type: Option<FileEngineType>,
+    /// Interrupt moderation for request completions.
+    pub interrupt_moderation: Option<InterruptModerationConfig>,
+    /// I/O retry policy for backing store errors.
+    pub io_retry: Option<IoRetryConfig>,
+    /// C/H/S geometry reported to the guest.
+    pub geometry: Option<DiskGeometryConfig>,
+    /// If set to true, discard and write zeroes requests are offered to the guest.
+    pub discard: Option<bool>,
+    /// Maximum number of descriptors in a request's descriptor chain.
+    pub max_chain_len: Option<u16>,
+    /// Path of a file to record the requests submitted by the guest to, for debugging.
+    pub request_record_path: Option<String>,
+    /// Window, in milliseconds, during which queue notifications are coalesced after a restore.
+    pub restore_notification_window_ms: Option<u64>,
+    /// Batching of sequential write requests.
+    pub write_batch: Option<WriteBatchConfig>,
+    /// File descriptor of the drive, already open and inherited from the parent process.
+    /// Mutually exclusive with `path_on_host`.
+    pub fd_on_host: Option<RawFd>,
 
     // VhostUserBlock specific fields
     /// Path to the vhost-user socket.
@@ -77,6 +106,11 @@ pub struct BlockDeviceUpdateConfig {
     pub path_on_host: Option<String>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// New cache type.
+    pub cache_type: Option<CacheType>,
+    /// Stops or resumes the processing of the device queues. A disabled device stays attached
+    /// and keeps its configuration.
+    pub enabled: Option<bool>,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -202,6 +236,15 @@ mod tests {
                 path_on_host: self.path_on_host.clone(),
                 rate_limiter: self.rate_limiter,
                 file_engine_type: self.file_engine_type,
+                interrupt_moderation: self.interrupt_moderation,
+                io_retry: self.io_retry,
+                geometry: self.geometry,
+                discard: self.discard,
+                max_chain_len: self.max_chain_len,
+                request_record_path: self.request_record_path.clone(),
+                restore_notification_window_ms: self.restore_notification_window_ms,
+                write_batch: self.write_batch,
+                fd_on_host: self.fd_on_host,
 
                 socket: self.socket.clone(),
             }
@@ -229,6 +272,15 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -261,6 +313,15 @@ mod tests {
             path_on_host: Some(dummy_path),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -290,6 +351,15 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -306,6 +376,15 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -333,6 +412,15 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -349,6 +437,15 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -365,6 +462,15 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -406,6 +512,15 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -422,6 +537,15 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -438,6 +562,15 @@ mod tests {
             path_on_host: Some(dummy_path_3),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -480,6 +613,15 @@ mod tests {
             path_on_host: Some(dummy_path_1.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -496,6 +638,15 @@ mod tests {
             path_on_host: Some(dummy_path_2.clone()),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -560,6 +711,15 @@ mod tests {
             path_on_host: Some(dummy_path_1),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -576,6 +736,15 @@ mod tests {
             path_on_host: Some(dummy_path_2),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -602,6 +771,15 @@ mod tests {
             path_on_host: Some(dummy_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: Some(FileEngineType::Sync),
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
@@ -630,6 +808,15 @@ mod tests {
             path_on_host: Some(backing_file.as_path().to_str().unwrap().to_string()),
             rate_limiter: None,
             file_engine_type: None,
+            interrupt_moderation: None,
+            io_retry: None,
+            geometry: None,
+            discard: None,
+            max_chain_len: None,
+            request_record_path: None,
+            restore_notification_window_ms: None,
+            write_batch: None,
+            fd_on_host: None,
 
             socket: None,
         };
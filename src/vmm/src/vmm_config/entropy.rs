@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use super::RateLimiterConfig;
 use crate::devices::virtio::rng::{Entropy, EntropyError};
+use crate::vmm_config::machine_config::HostFdLimitError;
 
 /// This struct represents the strongly typed equivalent of the json body from entropy device
 /// related requests.
@@ -16,6 +17,11 @@ use crate::devices::virtio::rng::{Entropy, EntropyError};
 pub struct EntropyDeviceConfig {
     /// Configuration for RateLimiter of Entropy device
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// Maximum number of bytes served during the lifetime of the microVM
+    pub lifetime_byte_limit: Option<u64>,
+    /// Sample the entropy source on activation and reject it if it is obviously broken
+    #[serde(default)]
+    pub quality_check: bool,
 }
 
 impl From<&Entropy> for EntropyDeviceConfig {
@@ -23,6 +29,8 @@ impl From<&Entropy> for EntropyDeviceConfig {
         let rate_limiter: RateLimiterConfig = dev.rate_limiter().into();
         EntropyDeviceConfig {
             rate_limiter: rate_limiter.into_option(),
+            lifetime_byte_limit: dev.lifetime_byte_limit(),
+            quality_check: dev.quality_check(),
         }
     }
 }
@@ -35,6 +43,8 @@ pub enum EntropyDeviceError {
     CreateDevice(#[from] EntropyError),
     /// Could not create RateLimiter from configuration: {0}
     CreateRateLimiter(#[from] std::io::Error),
+    /// {0}
+    HostFdLimit(#[from] HostFdLimitError),
 }
 
 /// A builder type used to construct an Entropy device
@@ -56,7 +66,10 @@ impl EntropyDeviceBuilder {
             .rate_limiter
             .map(RateLimiterConfig::try_into)
             .transpose()?;
-        let dev = Arc::new(Mutex::new(Entropy::new(rate_limiter.unwrap_or_default())?));
+        let mut entropy = Entropy::new(rate_limiter.unwrap_or_default())?;
+        entropy.set_lifetime_byte_limit(config.lifetime_byte_limit);
+        entropy.set_quality_check(config.quality_check);
+        let dev = Arc::new(Mutex::new(entropy));
         self.0 = Some(dev.clone());
 
         Ok(dev)
@@ -100,6 +113,14 @@ mod tests {
         builder.insert(config.clone()).unwrap();
         assert!(builder.get().is_some());
         assert_eq!(builder.config().unwrap(), config);
+
+        let config = EntropyDeviceConfig {
+            rate_limiter: None,
+            lifetime_byte_limit: Some(4096),
+            quality_check: true,
+        };
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.config().unwrap(), config);
     }
 
     #[test]
@@ -0,0 +1,108 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::fs::device::VhostUserFs;
+use crate::devices::virtio::fs::VhostUserFsError;
+use crate::vmm_config::machine_config::HostFdLimitError;
+
+/// This struct represents the strongly typed equivalent of the json body from fs device
+/// related requests.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsDeviceConfig {
+    /// Unique identifier of the device.
+    pub fs_id: String,
+    /// Tag under which the guest mounts the shared directory.
+    pub tag: String,
+    /// Socket path of the vhost-user backend serving the shared directory.
+    pub socket: String,
+}
+
+/// Errors associated with the operations allowed on an fs device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum FsConfigError {
+    /// Unable to create the fs device: {0}
+    CreateFsDevice(#[from] VhostUserFsError),
+    /// {0}
+    HostFdLimit(#[from] HostFdLimitError),
+    /// The tag {0} is already in use by another fs device.
+    TagAlreadyExists(String),
+}
+
+/// Builder for a list of fs devices.
+#[derive(Debug, Default)]
+pub struct FsBuilder {
+    /// The list of fs devices.
+    pub devices: Vec<Arc<Mutex<VhostUserFs>>>,
+}
+
+impl FsBuilder {
+    /// Creates an empty list of fs devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a new fs device, or updates the one with the same id.
+    pub fn insert(&mut self, config: FsDeviceConfig) -> Result<(), FsConfigError> {
+        let position = self
+            .devices
+            .iter()
+            .position(|dev| dev.lock().expect("Poisoned lock").id == config.fs_id);
+
+        let tag_in_use = self.devices.iter().enumerate().any(|(index, dev)| {
+            Some(index) != position && dev.lock().expect("Poisoned lock").tag == config.tag
+        });
+        if tag_in_use {
+            return Err(FsConfigError::TagAlreadyExists(config.tag));
+        }
+
+        let device = Arc::new(Mutex::new(VhostUserFs::new(config)?));
+        match position {
+            Some(index) => self.devices[index] = device,
+            None => self.devices.push(device),
+        }
+        Ok(())
+    }
+
+    /// Adds an existing fs device, e.g. restored from a snapshot.
+    pub fn add_device(&mut self, device: Arc<Mutex<VhostUserFs>>) {
+        self.devices.push(device);
+    }
+
+    /// Returns the configurations of the fs devices.
+    pub fn configs(&self) -> Vec<FsDeviceConfig> {
+        self.devices
+            .iter()
+            .map(|dev| dev.lock().expect("Poisoned lock").config())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_missing_backend() {
+        let mut builder = FsBuilder::new();
+        let config = FsDeviceConfig {
+            fs_id: "fs0".to_string(),
+            tag: "shared".to_string(),
+            socket: "/nonexistent/fs.sock".to_string(),
+        };
+        let err = builder.insert(config).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                FsConfigError::CreateFsDevice(VhostUserFsError::VhostUser(_))
+            ),
+            "{:?}",
+            err
+        );
+        assert!(builder.devices.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::virtio::input::{Input, InputError, InputKind};
+use crate::vmm_config::machine_config::HostFdLimitError;
+
+/// This struct represents the strongly typed equivalent of the json body from input device
+/// related requests.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputDeviceConfig {
+    /// Unique identifier of the device.
+    pub input_id: String,
+    /// Kind of input device.
+    #[serde(default)]
+    pub kind: InputKind,
+}
+
+impl From<&Input> for InputDeviceConfig {
+    fn from(dev: &Input) -> Self {
+        InputDeviceConfig {
+            input_id: dev.id().to_string(),
+            kind: dev.kind(),
+        }
+    }
+}
+
+/// Errors associated with the operations allowed on an input device.
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum InputConfigError {
+    /// Unable to create the input device: {0}
+    CreateInputDevice(#[from] InputError),
+    /// {0}
+    HostFdLimit(#[from] HostFdLimitError),
+}
+
+/// Builder for a list of input devices.
+#[derive(Debug, Default)]
+pub struct InputBuilder {
+    /// The list of input devices.
+    pub devices: Vec<Arc<Mutex<Input>>>,
+}
+
+impl InputBuilder {
+    /// Creates an empty list of input devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a new input device, or updates the one with the same id.
+    pub fn insert(&mut self, config: InputDeviceConfig) -> Result<(), InputConfigError> {
+        let position = self
+            .devices
+            .iter()
+            .position(|dev| dev.lock().expect("Poisoned lock").id() == config.input_id);
+
+        let device = Arc::new(Mutex::new(Input::new(config.input_id, config.kind)?));
+        match position {
+            Some(index) => self.devices[index] = device,
+            None => self.devices.push(device),
+        }
+        Ok(())
+    }
+
+    /// Adds an existing input device, e.g. restored from a snapshot.
+    pub fn add_device(&mut self, device: Arc<Mutex<Input>>) {
+        self.devices.push(device);
+    }
+
+    /// Returns the configurations of the input devices.
+    pub fn configs(&self) -> Vec<InputDeviceConfig> {
+        self.devices
+            .iter()
+            .map(|dev| InputDeviceConfig::from(&*dev.lock().expect("Poisoned lock")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut builder = InputBuilder::new();
+        let keyboard = InputDeviceConfig {
+            input_id: "input0".to_string(),
+            kind: InputKind::Keyboard,
+        };
+        let tablet = InputDeviceConfig {
+            input_id: "input1".to_string(),
+            kind: InputKind::Tablet,
+        };
+        builder.insert(keyboard.clone()).unwrap();
+        builder.insert(tablet.clone()).unwrap();
+        assert_eq!(builder.configs(), [keyboard, tablet.clone()]);
+
+        // A device with the same id is replaced.
+        let mouse = InputDeviceConfig {
+            input_id: "input0".to_string(),
+            kind: InputKind::Mouse,
+        };
+        builder.insert(mouse.clone()).unwrap();
+        assert_eq!(builder.configs(), [mouse, tablet]);
+    }
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 use std::fmt::Debug;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use utils::kernel_version;
 use utils::kernel_version::KernelVersion;
 
@@ -37,8 +37,14 @@ pub enum VmConfigError {
     BalloonAndHugePages,
     /// Firecracker's huge pages support is incompatible with initrds.
     InitrdAndHugePages,
+    /// The host fd limit of {0} is below the {1} host fds already used by the configured devices.
+    HostFdLimitTooLow(usize, usize),
 }
 
+/// Attaching the device would exceed the host fd limit of {0}: {1} fds in use, {2} requested.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub struct HostFdLimitError(pub usize, pub usize, pub usize);
+
 // We cannot do a `KernelVersion(kernel_version::Error)` variant because `kernel_version::Error`
 // does not implement `PartialEq, Eq` (due to containing an io error).
 impl From<kernel_version::KernelVersionError> for VmConfigError {
@@ -123,6 +129,9 @@ pub struct MachineConfig {
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default)]
     pub huge_pages: HugePageConfig,
+    /// Maximum number of host fds the devices of the microVM may own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_fd_limit: Option<usize>,
 }
 
 impl Default for MachineConfig {
@@ -158,6 +167,24 @@ pub struct MachineConfigUpdate {
     /// Configures what page size Firecracker should use to back guest memory.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub huge_pages: Option<HugePageConfig>,
+    /// Maximum number of host fds the devices of the microVM may own. `Some(None)`, sent as
+    /// `null`, removes the limit.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub host_fd_limit: Option<Option<usize>>,
+}
+
+/// Deserializes a field that is present, even as `null`, into `Some`, so that an absent field
+/// (`None`) can be told apart from a cleared one (`Some(None)`).
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 impl MachineConfigUpdate {
@@ -178,6 +205,7 @@ impl From<MachineConfig> for MachineConfigUpdate {
             cpu_template: cfg.cpu_template,
             track_dirty_pages: Some(cfg.track_dirty_pages),
             huge_pages: Some(cfg.huge_pages),
+            host_fd_limit: Some(cfg.host_fd_limit),
         }
     }
 }
@@ -197,6 +225,8 @@ pub struct VmConfig {
     pub track_dirty_pages: bool,
     /// Configures what page size Firecracker should use to back guest memory.
     pub huge_pages: HugePageConfig,
+    /// Maximum number of host fds the devices of the microVM may own.
+    pub host_fd_limit: Option<usize>,
 }
 
 impl VmConfig {
@@ -254,6 +284,7 @@ impl VmConfig {
             cpu_template,
             track_dirty_pages: update.track_dirty_pages.unwrap_or(self.track_dirty_pages),
             huge_pages: page_config,
+            host_fd_limit: update.host_fd_limit.unwrap_or(self.host_fd_limit),
         })
     }
 }
@@ -267,6 +298,7 @@ impl Default for VmConfig {
             cpu_template: None,
             track_dirty_pages: false,
             huge_pages: HugePageConfig::None,
+            host_fd_limit: None,
         }
     }
 }
@@ -280,6 +312,7 @@ impl From<&VmConfig> for MachineConfig {
             cpu_template: value.cpu_template.as_ref().map(|template| template.into()),
             track_dirty_pages: value.track_dirty_pages,
             huge_pages: value.huge_pages,
+            host_fd_limit: value.host_fd_limit,
         }
     }
 }
@@ -289,7 +322,7 @@ mod tests {
     use utils::kernel_version::KernelVersion;
 
     use crate::vmm_config::machine_config::{
-        HugePageConfig, MachineConfigUpdate, VmConfig, VmConfigError,
+        HugePageConfig, MachineConfig, MachineConfigUpdate, VmConfig, VmConfigError,
     };
 
     #[test]
@@ -306,4 +339,38 @@ mod tests {
             assert_eq!(err, VmConfigError::HugetlbfsNotSupported)
         }
     }
+
+    #[test]
+    fn test_update_host_fd_limit() {
+        let base_config = VmConfig::default();
+
+        let update: MachineConfigUpdate = serde_json::from_str(r#"{"host_fd_limit": 16}"#).unwrap();
+        assert_eq!(update.host_fd_limit, Some(Some(16)));
+        let config = base_config.update(&update).unwrap();
+        assert_eq!(config.host_fd_limit, Some(16));
+
+        // An update without the field keeps the limit.
+        let update: MachineConfigUpdate = serde_json::from_str(r#"{"vcpu_count": 2}"#).unwrap();
+        assert_eq!(update.host_fd_limit, None);
+        let config = config.update(&update).unwrap();
+        assert_eq!(config.host_fd_limit, Some(16));
+
+        // A `null` limit clears it.
+        let update: MachineConfigUpdate =
+            serde_json::from_str(r#"{"host_fd_limit": null}"#).unwrap();
+        assert_eq!(update.host_fd_limit, Some(None));
+        assert!(!update.is_empty());
+        let config = config.update(&update).unwrap();
+        assert_eq!(config.host_fd_limit, None);
+
+        // A full machine configuration without a limit clears it as well.
+        let config = config
+            .update(&MachineConfigUpdate {
+                host_fd_limit: Some(Some(16)),
+                ..Default::default()
+            })
+            .unwrap();
+        let update = MachineConfigUpdate::from(MachineConfig::from(&base_config));
+        assert_eq!(config.update(&update).unwrap().host_fd_limit, None);
+    }
 }
@@ -3,9 +3,10 @@
 use std::net::Ipv4Addr;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::mmds::data_store;
-use crate::mmds::data_store::MmdsVersion;
+use crate::mmds::data_store::{MmdsMergePolicy, MmdsVersion};
 
 /// Keeps the MMDS configuration.
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -20,6 +21,17 @@ pub struct MmdsConfig {
     pub ipv4_address: Option<Ipv4Addr>,
 }
 
+/// Data merged into the MMDS data store, on top of the existing contents.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsMergeConfig {
+    /// Data to merge.
+    pub data: Value,
+    /// How values conflicting with the existing ones are handled.
+    #[serde(default)]
+    pub policy: MmdsMergePolicy,
+}
+
 impl MmdsConfig {
     /// Returns the MMDS version configured.
     pub fn version(&self) -> MmdsVersion {
@@ -16,10 +16,16 @@ use crate::rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring the clock devices of the microVM.
+pub mod clock;
 /// Wrapper for configuring the block devices.
 pub mod drive;
 /// Wrapper for configuring the entropy device attached to the microVM.
 pub mod entropy;
+/// Wrapper for configuring the fs devices attached to the microVM.
+pub mod fs;
+/// Wrapper for configuring the input devices attached to the microVM.
+pub mod input;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the memory and CPU of the microVM.
@@ -30,6 +36,8 @@ pub mod metrics;
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
 pub mod net;
+/// Wrapper for configuring the serial console.
+pub mod serial;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
 /// Wrapper for configuring the vsock devices attached to the microVM.
@@ -56,6 +64,20 @@ pub struct TokenBucketConfig {
     pub refill_time: u64,
 }
 
+impl TokenBucketConfig {
+    /// A bucket is valid if it is either disabled (zero size and refill time) or it can be
+    /// created with the given parameters.
+    fn is_valid(&self) -> bool {
+        (self.size == 0 && self.refill_time == 0)
+            || TokenBucket::new(
+                self.size,
+                self.one_time_burst.unwrap_or(0),
+                self.refill_time,
+            )
+            .is_some()
+    }
+}
+
 impl From<&TokenBucket> for TokenBucketConfig {
     fn from(tb: &TokenBucket) -> Self {
         let one_time_burst = match tb.initial_one_time_burst() {
@@ -162,6 +184,12 @@ impl RateLimiterConfig {
             None
         }
     }
+
+    /// Checks that every configured token bucket is either explicitly disabled or valid,
+    /// rather than being silently disabled at creation.
+    pub fn is_valid(&self) -> bool {
+        self.bandwidth.map_or(true, |tb| tb.is_valid()) && self.ops.map_or(true, |tb| tb.is_valid())
+    }
 }
 
 /// Create and opens a File for writing to it.
@@ -207,6 +235,29 @@ mod tests {
         assert_eq!(rl.ops().unwrap().refill_time_ms(), REFILL_TIME * 2);
     }
 
+    #[test]
+    fn test_rate_limiter_config_is_valid() {
+        assert!(RateLimiterConfig::default().is_valid());
+
+        let mut rlconf = RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: SIZE,
+                one_time_burst: None,
+                refill_time: REFILL_TIME,
+            }),
+            ops: Some(TokenBucketConfig::default()),
+        };
+        assert!(rlconf.is_valid());
+
+        // A zero refill time would silently disable the bucket.
+        rlconf.bandwidth.as_mut().unwrap().refill_time = 0;
+        assert!(!rlconf.is_valid());
+
+        // The refill time overflows when converted to nanoseconds.
+        rlconf.bandwidth.as_mut().unwrap().refill_time = u64::MAX;
+        assert!(!rlconf.is_valid());
+    }
+
     #[test]
     fn test_generate_configs() {
         let bw_tb_cfg = TokenBucketConfig {
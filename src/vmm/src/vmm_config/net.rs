@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::convert::TryInto;
+use std::ffi::CString;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 
@@ -9,7 +10,11 @@ use serde::{Deserialize, Serialize};
 use utils::net::mac::MacAddr;
 
 use super::RateLimiterConfig;
-use crate::devices::virtio::net::{Net, TapError};
+pub use crate::devices::virtio::net::delay::NetDelayConfig;
+use crate::devices::virtio::net::delay::{FrameDelayQueue, NetDelayError};
+use crate::devices::virtio::net::flows::FlowTable;
+use crate::devices::virtio::net::{Net, Tap, TapError};
+use crate::vmm_config::machine_config::HostFdLimitError;
 use crate::VmmError;
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
@@ -27,6 +32,46 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Delay injected on transmitted packages.
+    pub tx_delay: Option<NetDelayConfig>,
+    /// Whether to keep per-flow statistics of the traffic.
+    #[serde(default)]
+    pub flow_stats: bool,
+}
+
+impl NetworkInterfaceConfig {
+    /// Checks the config without creating the device: the guest MAC must be a unicast address,
+    /// the rate limiters must be valid and the tap must exist and be usable.
+    pub fn validate(&self) -> Result<(), NetworkInterfaceError> {
+        if let Some(mac) = self.guest_mac {
+            let bytes = mac.get_bytes();
+            // Multicast (and broadcast) addresses have the least significant bit of the first
+            // octet set.
+            if bytes[0] & 0x01 != 0 || bytes.iter().all(|byte| *byte == 0) {
+                return Err(NetworkInterfaceError::InvalidGuestMac(mac.to_string()));
+            }
+        }
+        if !self.rx_rate_limiter.map_or(true, |rl| rl.is_valid()) {
+            return Err(NetworkInterfaceError::InvalidRateLimiter("RX"));
+        }
+        if !self.tx_rate_limiter.map_or(true, |rl| rl.is_valid()) {
+            return Err(NetworkInterfaceError::InvalidRateLimiter("TX"));
+        }
+
+        // Opening a tap that does not exist would create a transient one, so check for it
+        // first. This asks the kernel rather than sysfs, which is not mounted in the jail.
+        let exists = CString::new(self.host_dev_name.as_str()).is_ok_and(|if_name| {
+            // SAFETY: `if_name` is a valid null-terminated string.
+            unsafe { libc::if_nametoindex(if_name.as_ptr()) != 0 }
+        });
+        if !exists {
+            return Err(NetworkInterfaceError::TapNotFound(
+                self.host_dev_name.clone(),
+            ));
+        }
+        Tap::open_named(&self.host_dev_name)?;
+        Ok(())
+    }
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -39,12 +84,14 @@ impl From<&Net> for NetworkInterfaceConfig {
             guest_mac: net.guest_mac().copied(),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            tx_delay: net.tx_delay(),
+            flow_stats: net.flow_stats.is_some(),
         }
     }
 }
 
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
-/// can be updated.
+/// and the enabled state can be updated.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceUpdateConfig {
@@ -56,6 +103,9 @@ pub struct NetworkInterfaceUpdateConfig {
     /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
     /// is missing, it will not be nullified, but left unchanged.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Stops or resumes the processing of the device queues. A disabled device stays attached
+    /// and keeps its configuration.
+    pub enabled: Option<bool>,
 }
 
 /// Errors associated with the operations allowed on a net device.
@@ -67,10 +117,20 @@ pub enum NetworkInterfaceError {
     CreateRateLimiter(#[from] std::io::Error),
     /// Unable to update the net device: {0}
     DeviceUpdate(#[from] VmmError),
+    /// {0}
+    HostFdLimit(#[from] HostFdLimitError),
     /// The MAC address is already in use: {0}
     GuestMacAddressInUse(String),
+    /// The guest MAC address is not a valid unicast address: {0}
+    InvalidGuestMac(String),
+    /// Invalid {0} rate limiter: every token bucket must either have a zero size and refill time, which disables it, or a non-zero size and a refill time between 1 and u64::MAX / 1000000 ms.
+    InvalidRateLimiter(&'static str),
     /// Cannot open/create the tap device: {0}
     OpenTap(#[from] TapError),
+    /// Invalid TX delay: {0}
+    TxDelay(#[from] NetDelayError),
+    /// The tap device {0} does not exist on the host.
+    TapNotFound(String),
 }
 
 /// Builder for a list of network devices.
@@ -134,6 +194,9 @@ impl NetBuilder {
             self.net_devices.swap_remove(index);
         }
 
+        // The tap of the replaced device is closed by now, so it can be checked too.
+        netif_config.validate()?;
+
         // Add new device.
         let net = Arc::new(Mutex::new(Self::create_net(netif_config)?));
         self.net_devices.push(net.clone());
@@ -154,15 +217,20 @@ impl NetBuilder {
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
+        let tx_delay = cfg.tx_delay.map(FrameDelayQueue::new).transpose()?;
+
         // Create and return the Net device
-        crate::devices::virtio::net::Net::new(
+        let mut net = crate::devices::virtio::net::Net::new(
             cfg.iface_id,
             &cfg.host_dev_name,
             cfg.guest_mac,
             rx_rate_limiter.unwrap_or_default(),
             tx_rate_limiter.unwrap_or_default(),
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.tx_delay = tx_delay;
+        net.flow_stats = cfg.flow_stats.then(FlowTable::default);
+        Ok(net)
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -180,7 +248,9 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::devices::virtio::net::test_utils::PersistentTap;
     use crate::rate_limiter::RateLimiter;
+    use crate::vmm_config::TokenBucketConfig;
 
     impl NetBuilder {
         pub fn len(&self) -> usize {
@@ -199,6 +269,8 @@ mod tests {
             guest_mac: Some(MacAddr::from_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            tx_delay: None,
+            flow_stats: false,
         }
     }
 
@@ -210,17 +282,20 @@ mod tests {
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                tx_delay: self.tx_delay,
+                flow_stats: self.flow_stats,
             }
         }
     }
 
     #[test]
     fn test_insert() {
+        let _taps = [PersistentTap::new("dev1"), PersistentTap::new("dev2")];
         let mut net_builder = NetBuilder::new();
 
         let id_1 = "id_1";
         let mut host_dev_name_1 = "dev1";
-        let mut guest_mac_1 = "01:23:45:67:89:0a";
+        let mut guest_mac_1 = "02:23:45:67:89:0a";
 
         // Test create.
         let netif_1 = create_netif(id_1, host_dev_name_1, guest_mac_1);
@@ -228,7 +303,7 @@ mod tests {
         assert_eq!(net_builder.net_devices.len(), 1);
 
         // Test update mac address (this test does not modify the tap).
-        guest_mac_1 = "01:23:45:67:89:0b";
+        guest_mac_1 = "02:23:45:67:89:0b";
         let netif_1 = create_netif(id_1, host_dev_name_1, guest_mac_1);
 
         net_builder.build(netif_1).unwrap();
@@ -243,11 +318,12 @@ mod tests {
 
     #[test]
     fn test_insert_error_cases() {
+        let _taps = [PersistentTap::new("dev3"), PersistentTap::new("dev4")];
         let mut net_builder = NetBuilder::new();
 
         let id_1 = "id_1";
         let host_dev_name_1 = "dev3";
-        let guest_mac_1 = "01:23:45:67:89:0a";
+        let guest_mac_1 = "02:23:45:67:89:0a";
 
         // Adding the first valid network config.
         let netif_1 = create_netif(id_1, host_dev_name_1, guest_mac_1);
@@ -257,7 +333,7 @@ mod tests {
         // Error Case: Add new network config with the same mac as netif_1.
         let id_2 = "id_2";
         let host_dev_name_2 = "dev4";
-        let guest_mac_2 = "01:23:45:67:89:0b";
+        let guest_mac_2 = "02:23:45:67:89:0b";
 
         let netif_2 = create_netif(id_2, host_dev_name_2, guest_mac_1);
         let expected_error = NetworkInterfaceError::GuestMacAddressInUse(guest_mac_1.into());
@@ -271,12 +347,10 @@ mod tests {
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
         assert_eq!(
             net_builder.build(netif_2).err().unwrap().to_string(),
-            NetworkInterfaceError::CreateNetworkDevice(
-                crate::devices::virtio::net::NetError::TapOpen(TapError::IfreqExecuteError(
-                    std::io::Error::from_raw_os_error(16),
-                    host_dev_name_1.to_string()
-                ))
-            )
+            NetworkInterfaceError::OpenTap(TapError::IfreqExecuteError(
+                std::io::Error::from_raw_os_error(libc::EBUSY),
+                host_dev_name_1.to_string()
+            ))
             .to_string()
         );
         assert_eq!(net_builder.net_devices.len(), 1);
@@ -298,21 +372,61 @@ mod tests {
         let netif_2 = create_netif(id_2, host_dev_name_1, guest_mac_2);
         assert_eq!(
             net_builder.build(netif_2).err().unwrap().to_string(),
-            NetworkInterfaceError::CreateNetworkDevice(
-                crate::devices::virtio::net::NetError::TapOpen(TapError::IfreqExecuteError(
-                    std::io::Error::from_raw_os_error(16),
-                    host_dev_name_1.to_string()
-                ))
-            )
+            NetworkInterfaceError::OpenTap(TapError::IfreqExecuteError(
+                std::io::Error::from_raw_os_error(libc::EBUSY),
+                host_dev_name_1.to_string()
+            ))
             .to_string()
         );
     }
 
+    #[test]
+    fn test_validate() {
+        // The tap does not exist.
+        let netif = create_netif("id", "notap-validate", "02:23:45:67:89:0a");
+        assert_eq!(
+            netif.validate().unwrap_err().to_string(),
+            NetworkInterfaceError::TapNotFound("notap-validate".to_string()).to_string()
+        );
+
+        // Multicast guest MAC.
+        let netif = create_netif("id", "notap-validate", "01:23:45:67:89:0a");
+        assert_eq!(
+            netif.validate().unwrap_err().to_string(),
+            NetworkInterfaceError::InvalidGuestMac("01:23:45:67:89:0a".to_string()).to_string()
+        );
+
+        // Bucket that would be silently disabled.
+        let mut netif = create_netif("id", "notap-validate", "02:23:45:67:89:0a");
+        netif.tx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 1024,
+                one_time_burst: None,
+                refill_time: 0,
+            }),
+            ops: None,
+        });
+        assert_eq!(
+            netif.validate().unwrap_err().to_string(),
+            NetworkInterfaceError::InvalidRateLimiter("TX").to_string()
+        );
+
+        // Building the device runs the same checks.
+        let mut net_builder = NetBuilder::new();
+        let netif = create_netif("id", "notap-validate", "02:23:45:67:89:0a");
+        assert_eq!(
+            net_builder.build(netif).unwrap_err().to_string(),
+            NetworkInterfaceError::TapNotFound("notap-validate".to_string()).to_string()
+        );
+        assert!(net_builder.is_empty());
+    }
+
     #[test]
     fn test_net_config() {
+        let _tap = PersistentTap::new("dev");
         let net_id = "id";
         let host_dev_name = "dev";
-        let guest_mac = "01:23:45:67:89:0b";
+        let guest_mac = "02:23:45:67:89:0b";
 
         let net_if_cfg = create_netif(net_id, host_dev_name, guest_mac);
         assert_eq!(
@@ -0,0 +1,122 @@
+// Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Auxiliary module for configuring the serial console.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Strongly typed structure used to describe the serial console.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialConfig {
+    /// Signature of a guest panic, such as "Kernel panic", to look for in the serial output.
+    /// Guest panics are not detected if it is not set.
+    pub panic_signature: Option<String>,
+    /// Unix domain socket to stream the serial output to, instead of stdout. The output is dropped
+    /// while no client is connected.
+    pub output_socket: Option<PathBuf>,
+    /// File or named pipe to write the serial output to, instead of stdout. The output is
+    /// dropped while the path does not exist or while a named pipe has no reader.
+    pub output_path: Option<PathBuf>,
+}
+
+/// Strongly typed structure used to redirect the output of the serial console after boot.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialOutputUpdate {
+    /// File or named pipe to write the serial output to. The output goes back to stdout if it is
+    /// not set.
+    pub output_path: Option<PathBuf>,
+}
+
+/// Errors associated with actions on the `SerialConfig`.
+#[derive(Debug, thiserror::Error, displaydoc::Display, PartialEq, Eq)]
+pub enum SerialConfigError {
+    /// The panic signature cannot be empty.
+    EmptyPanicSignature,
+    /// The path of the serial output socket cannot be empty.
+    EmptyOutputSocket,
+    /// The path of the serial output file cannot be empty.
+    EmptyOutputPath,
+    /// The serial output cannot be both streamed to a socket and written to a file.
+    ConflictingOutputs,
+}
+
+impl SerialConfig {
+    /// Checks that the configuration can be applied to the serial console.
+    pub fn validate(&self) -> Result<(), SerialConfigError> {
+        if self.panic_signature.as_deref() == Some("") {
+            return Err(SerialConfigError::EmptyPanicSignature);
+        }
+        if self
+            .output_socket
+            .as_ref()
+            .is_some_and(|path| path.as_os_str().is_empty())
+        {
+            return Err(SerialConfigError::EmptyOutputSocket);
+        }
+        match self.output_path.as_ref() {
+            Some(path) if path.as_os_str().is_empty() => Err(SerialConfigError::EmptyOutputPath),
+            Some(_) if self.output_socket.is_some() => Err(SerialConfigError::ConflictingOutputs),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        SerialConfig::default().validate().unwrap();
+        SerialConfig {
+            panic_signature: Some("Kernel panic".to_string()),
+            output_socket: Some(PathBuf::from("serial.sock")),
+            output_path: None,
+        }
+        .validate()
+        .unwrap();
+        SerialConfig {
+            output_path: Some(PathBuf::from("serial.out")),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+        assert_eq!(
+            SerialConfig {
+                panic_signature: Some(String::new()),
+                ..Default::default()
+            }
+            .validate(),
+            Err(SerialConfigError::EmptyPanicSignature)
+        );
+        assert_eq!(
+            SerialConfig {
+                output_socket: Some(PathBuf::new()),
+                ..Default::default()
+            }
+            .validate(),
+            Err(SerialConfigError::EmptyOutputSocket)
+        );
+        assert_eq!(
+            SerialConfig {
+                output_path: Some(PathBuf::new()),
+                ..Default::default()
+            }
+            .validate(),
+            Err(SerialConfigError::EmptyOutputPath)
+        );
+        assert_eq!(
+            SerialConfig {
+                output_socket: Some(PathBuf::from("serial.sock")),
+                output_path: Some(PathBuf::from("serial.out")),
+                ..Default::default()
+            }
+            .validate(),
+            Err(SerialConfigError::ConflictingOutputs)
+        );
+    }
+}
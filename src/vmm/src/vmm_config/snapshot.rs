@@ -45,6 +45,29 @@ pub struct CreateSnapshotParams {
     pub snapshot_path: PathBuf,
     /// Path to the file that will contain the guest memory.
     pub mem_file_path: PathBuf,
+    /// Optional cap, in bytes, on the estimated size of the microVM state file.
+    /// The snapshot fails before anything is written if the estimate exceeds it.
+    #[serde(default)]
+    pub max_snapshot_size: Option<usize>,
+    /// When set to true, the snapshot fails unless the guest agent acked a quiesce request.
+    #[serde(default)]
+    pub require_guest_quiesce: bool,
+    /// When set to true, the snapshot fails if the guest requested a reset that is not handled
+    /// yet. Otherwise the snapshot is saved and the reset is honored afterwards.
+    #[serde(default)]
+    pub abort_on_guest_reset: bool,
+    /// When set, the devices are quiesced before saving and the snapshot fails unless their
+    /// in-flight requests complete within this many milliseconds.
+    #[serde(default)]
+    pub quiesce_timeout_ms: Option<u64>,
+}
+
+/// Stores the configuration used to ask the guest agent to quiesce the guest.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuiesceGuestParams {
+    /// Time, in milliseconds, given to the guest agent to ack the quiesce request.
+    pub timeout_ms: u64,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -60,6 +83,12 @@ pub struct LoadSnapshotParams {
     /// When set to true, the vm is also resumed if the snapshot load
     /// is successful.
     pub resume_vm: bool,
+    /// When set to true, snapshots of a newer minor format version are accepted, ignoring
+    /// state appended after the fields known to this version.
+    pub relaxed_version_check: bool,
+    /// When set to true, the devices keep counting their uptime from their activation before
+    /// the snapshot was taken, instead of from the restore.
+    pub preserve_device_uptime: bool,
 }
 
 /// Stores the configuration for loading a snapshot that is provided by the user.
@@ -82,6 +111,12 @@ pub struct LoadSnapshotConfig {
     /// Whether or not to resume the vm post snapshot load.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Whether or not to accept snapshots of a newer minor format version.
+    #[serde(default)]
+    pub relaxed_version_check: bool,
+    /// Whether or not the devices keep their uptime from before the snapshot.
+    #[serde(default)]
+    pub preserve_device_uptime: bool,
 }
 
 /// Stores the configuration used for managing snapshot memory.
@@ -6,7 +6,10 @@ use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 
-use crate::devices::virtio::vsock::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use crate::devices::virtio::vsock::{
+    Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError, MAX_CONNECTIONS,
+};
+use crate::vmm_config::machine_config::HostFdLimitError;
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
@@ -17,6 +20,14 @@ pub enum VsockConfigError {
     CreateVsockBackend(VsockUnixBackendError),
     /// Cannot create vsock device: {0}
     CreateVsockDevice(VsockError),
+    /// Invalid maximum number of vsock connections: {0}
+    #[from(ignore)]
+    InvalidMaxConnections(u32),
+    /// Guest CID {0} is above the maximum guest CID of {1}
+    #[from(ignore)]
+    GuestCidAboveMax(u64, u32),
+    /// {0}
+    HostFdLimit(HostFdLimitError),
 }
 
 /// This struct represents the strongly typed equivalent of the json body
@@ -32,6 +43,15 @@ pub struct VsockDeviceConfig {
     pub guest_cid: u32,
     /// Path to local unix socket.
     pub uds_path: String,
+    /// Maximum number of concurrent connections.
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    /// Guest port whose connections are ingested into the host log.
+    #[serde(default)]
+    pub log_port: Option<u32>,
+    /// Guest port to which the guest agent connects to handle quiesce requests.
+    #[serde(default)]
+    pub quiesce_port: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -47,6 +67,9 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
             vsock_id: None,
             guest_cid: u32::try_from(vsock_lock.cid()).unwrap(),
             uds_path: vsock.uds_path.clone(),
+            max_connections: Some(u32::try_from(vsock_lock.backend().max_connections()).unwrap()),
+            log_port: vsock_lock.backend().log_port(),
+            quiesce_port: vsock_lock.backend().quiesce_port(),
         }
     }
 }
@@ -55,16 +78,58 @@ impl From<&VsockAndUnixPath> for VsockDeviceConfig {
 #[derive(Debug, Default)]
 pub struct VsockBuilder {
     inner: Option<VsockAndUnixPath>,
+    max_guest_cid: Option<u32>,
 }
 
 impl VsockBuilder {
     /// Creates an empty Vsock with Unix backend Store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self::default()
+    }
+
+    /// Creates an empty Vsock with Unix backend Store, which rejects guest CIDs above
+    /// `max_guest_cid`.
+    pub fn with_max_guest_cid(max_guest_cid: Option<u32>) -> Self {
+        Self {
+            inner: None,
+            max_guest_cid,
+        }
     }
 
-    /// Inserts an existing vsock device.
-    pub fn set_device(&mut self, device: Arc<Mutex<Vsock<VsockUnixBackend>>>) {
+    /// Sets the maximum guest CID accepted for the vsock device, checking it against the device
+    /// already inserted, if any.
+    pub fn set_max_guest_cid(
+        &mut self,
+        max_guest_cid: Option<u32>,
+    ) -> Result<(), VsockConfigError> {
+        if let (Some(max), Some(pair)) = (max_guest_cid, self.inner.as_ref()) {
+            Self::check_guest_cid(pair.vsock.lock().expect("Poisoned lock").cid(), Some(max))?;
+        }
+        self.max_guest_cid = max_guest_cid;
+        Ok(())
+    }
+
+    /// Returns the maximum guest CID accepted for the vsock device, if any.
+    pub fn max_guest_cid(&self) -> Option<u32> {
+        self.max_guest_cid
+    }
+
+    fn check_guest_cid(cid: u64, max_guest_cid: Option<u32>) -> Result<(), VsockConfigError> {
+        match max_guest_cid {
+            Some(max) if cid > u64::from(max) => Err(VsockConfigError::GuestCidAboveMax(cid, max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Inserts an existing vsock device, e.g. restored from a snapshot.
+    pub fn set_device(
+        &mut self,
+        device: Arc<Mutex<Vsock<VsockUnixBackend>>>,
+    ) -> Result<(), VsockConfigError> {
+        Self::check_guest_cid(
+            device.lock().expect("Poisoned lock").cid(),
+            self.max_guest_cid,
+        )?;
         self.inner = Some(VsockAndUnixPath {
             uds_path: device
                 .lock()
@@ -74,11 +139,13 @@ impl VsockBuilder {
                 .to_owned(),
             vsock: device.clone(),
         });
+        Ok(())
     }
 
     /// Inserts a Unix backend Vsock in the store.
     /// If an entry already exists, it will overwrite it.
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<(), VsockConfigError> {
+        Self::check_guest_cid(u64::from(cfg.guest_cid), self.max_guest_cid)?;
         // Make sure to drop the old one and remove the socket before creating a new one.
         if let Some(existing) = self.inner.take() {
             std::fs::remove_file(existing.uds_path).map_err(VsockUnixBackendError::UnixBind)?;
@@ -99,7 +166,19 @@ impl VsockBuilder {
     pub fn create_unixsock_vsock(
         cfg: VsockDeviceConfig,
     ) -> Result<Vsock<VsockUnixBackend>, VsockConfigError> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)?;
+        let max_connections = cfg.max_connections.map_or(Ok(MAX_CONNECTIONS), |max| {
+            usize::try_from(max)
+                .ok()
+                .filter(|max| (1..=MAX_CONNECTIONS).contains(max))
+                .ok_or(VsockConfigError::InvalidMaxConnections(max))
+        })?;
+        let backend = VsockUnixBackend::new(
+            u64::from(cfg.guest_cid),
+            cfg.uds_path,
+            max_connections,
+            cfg.log_port,
+            cfg.quiesce_port,
+        )?;
 
         Vsock::new(u64::from(cfg.guest_cid), backend).map_err(VsockConfigError::CreateVsockDevice)
     }
@@ -122,6 +201,9 @@ pub(crate) mod tests {
             vsock_id: None,
             guest_cid: 3,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            max_connections: Some(u32::try_from(MAX_CONNECTIONS).unwrap()),
+            log_port: None,
+            quiesce_port: None,
         }
     }
 
@@ -133,6 +215,36 @@ pub(crate) mod tests {
         VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
     }
 
+    #[test]
+    fn test_vsock_max_connections() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+
+        for max in [0, u32::try_from(MAX_CONNECTIONS).unwrap() + 1] {
+            vsock_config.max_connections = Some(max);
+            assert!(matches!(
+                VsockBuilder::create_unixsock_vsock(vsock_config.clone()),
+                Err(VsockConfigError::InvalidMaxConnections(m)) if m == max
+            ));
+        }
+
+        let mut vsock_builder = VsockBuilder::new();
+        vsock_config.max_connections = Some(2);
+        vsock_builder.insert(vsock_config.clone()).unwrap();
+        assert_eq!(vsock_builder.config().unwrap(), vsock_config);
+        assert_eq!(
+            vsock_builder
+                .get()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .backend()
+                .max_connections(),
+            2
+        );
+    }
+
     #[test]
     fn test_vsock_insert() {
         let mut store = VsockBuilder::new();
@@ -151,6 +263,42 @@ pub(crate) mod tests {
         assert_eq!(vsock.lock().unwrap().cid(), u64::from(new_cid));
     }
 
+    #[test]
+    fn test_vsock_max_guest_cid() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.guest_cid = 100;
+
+        // A CID above the maximum is rejected, leaving the builder empty.
+        let mut store = VsockBuilder::with_max_guest_cid(Some(99));
+        assert!(matches!(
+            store.insert(vsock_config.clone()),
+            Err(VsockConfigError::GuestCidAboveMax(100, 99))
+        ));
+        assert!(store.get().is_none());
+
+        store.set_max_guest_cid(Some(100)).unwrap();
+        store.insert(vsock_config.clone()).unwrap();
+        assert_eq!(store.config().unwrap(), vsock_config);
+
+        // Lowering the maximum below the CID of the current device fails.
+        assert!(matches!(
+            store.set_max_guest_cid(Some(3)),
+            Err(VsockConfigError::GuestCidAboveMax(100, 3))
+        ));
+        assert_eq!(store.max_guest_cid(), Some(100));
+
+        // Restored devices are checked as well.
+        let device = store.get().unwrap().clone();
+        let mut store = VsockBuilder::with_max_guest_cid(Some(3));
+        assert!(matches!(
+            store.set_device(device),
+            Err(VsockConfigError::GuestCidAboveMax(100, 3))
+        ));
+        assert!(store.get().is_none());
+    }
+
     #[test]
     fn test_vsock_config() {
         let mut vsock_builder = VsockBuilder::new();
@@ -171,12 +319,20 @@ pub(crate) mod tests {
         tmp_sock_file.remove().unwrap();
         let vsock = Vsock::new(
             0,
-            VsockUnixBackend::new(1, tmp_sock_file.as_path().to_str().unwrap().to_string())
-                .unwrap(),
+            VsockUnixBackend::new(
+                1,
+                tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                MAX_CONNECTIONS,
+                None,
+                None,
+            )
+            .unwrap(),
         )
         .unwrap();
 
-        vsock_builder.set_device(Arc::new(Mutex::new(vsock)));
+        vsock_builder
+            .set_device(Arc::new(Mutex::new(vsock)))
+            .unwrap();
         assert!(vsock_builder.inner.is_some());
         assert_eq!(
             vsock_builder.inner.unwrap().uds_path,
@@ -110,6 +110,31 @@ where
 
     /// Store the dirty bitmap in internal store
     fn store_dirty_bitmap(&self, dirty_bitmap: &DirtyBitmap, page_size: usize);
+
+    /// Returns the regions written by the VMM since the memory region bitmaps were last reset,
+    /// and resets the bitmaps.
+    fn take_dirty_regions(&self) -> DirtyRegions;
+}
+
+/// Page-aligned guest memory ranges written by the VMM, e.g. by devices.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DirtyRegions {
+    /// Non-overlapping ranges, as start address and length in bytes, in ascending order.
+    pub regions: Vec<(GuestAddress, usize)>,
+}
+
+impl DirtyRegions {
+    /// Returns `true` if no guest memory was written.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Returns `true` if the page containing `addr` was written.
+    pub fn contains(&self, addr: GuestAddress) -> bool {
+        self.regions
+            .iter()
+            .any(|(start, len)| addr >= *start && addr.0 - start.0 < *len as u64)
+    }
 }
 
 /// State of a guest memory region saved to file/buffer.
@@ -385,6 +410,47 @@ impl GuestMemoryExtension for GuestMemoryMmap {
             }
         });
     }
+
+    /// Returns the regions written by the VMM since the memory region bitmaps were last reset,
+    /// and resets the bitmaps.
+    ///
+    /// The bitmaps are shared with diff snapshots, so the pages returned here are not part of
+    /// the next diff snapshot. Regions are empty if dirty page tracking is disabled.
+    fn take_dirty_regions(&self) -> DirtyRegions {
+        let mut dirty_regions = DirtyRegions::default();
+        let page_size = match get_page_size() {
+            Ok(page_size) => page_size,
+            Err(_) => return dirty_regions,
+        };
+
+        for region in self.iter() {
+            let bitmap = match region.bitmap() {
+                Some(bitmap) => bitmap,
+                None => continue,
+            };
+
+            let mut dirty_batch: Option<(GuestAddress, usize)> = None;
+            for page_offset in (0..u64_to_usize(region.len())).step_by(page_size) {
+                if bitmap.dirty_at(page_offset) {
+                    match dirty_batch.as_mut() {
+                        Some((_, len)) => *len += page_size,
+                        None => {
+                            dirty_batch = Some((
+                                region.start_addr().unchecked_add(page_offset as u64),
+                                page_size,
+                            ))
+                        }
+                    }
+                } else if let Some(batch) = dirty_batch.take() {
+                    dirty_regions.regions.push(batch);
+                }
+            }
+            dirty_regions.regions.extend(dirty_batch);
+            bitmap.reset();
+        }
+
+        dirty_regions
+    }
 }
 
 fn create_memfd(
@@ -20,16 +20,16 @@ fn create_serial(
     let serial_in = MockSerialInput(pipe);
     let kick_stdin_evt = EventFdTrigger::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
 
-    Arc::new(Mutex::new(SerialWrapper {
-        serial: Serial::with_events(
+    Arc::new(Mutex::new(SerialWrapper::new(
+        Serial::with_events(
             EventFdTrigger::new(EventFd::new(EFD_NONBLOCK).unwrap()),
             SerialEventsWrapper {
                 buffer_ready_event_fd: Some(kick_stdin_evt.try_clone().unwrap()),
             },
             SerialOut::Stdout(std::io::stdout()),
         ),
-        input: Some(Box::new(serial_in)),
-    }))
+        Some(Box::new(serial_in)),
+    )))
 }
 
 #[derive(Debug)]
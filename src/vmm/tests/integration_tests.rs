@@ -7,7 +7,10 @@ use std::time::Duration;
 
 use utils::tempfile::TempFile;
 use vmm::builder::{build_and_boot_microvm, build_microvm_from_snapshot};
-use vmm::persist::{self, snapshot_state_sanity_check, MicrovmState, MicrovmStateError, VmInfo};
+use vmm::devices::virtio::vsock::QuiesceState;
+use vmm::persist::{
+    self, snapshot_state_sanity_check, CreateSnapshotError, MicrovmState, MicrovmStateError, VmInfo,
+};
 use vmm::resources::VmResources;
 use vmm::seccomp_filters::get_empty_filters;
 use vmm::snapshot::Snapshot;
@@ -189,6 +192,10 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
         snapshot_type,
         snapshot_path: snapshot_file.as_path().to_path_buf(),
         mem_file_path: memory_file.as_path().to_path_buf(),
+        max_snapshot_size: None,
+        require_guest_quiesce: false,
+        abort_on_guest_reset: false,
+        quiesce_timeout_ms: None,
     };
     let vm_info = VmInfo {
         mem_size_mib: 1u64,
@@ -221,6 +228,84 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
     (snapshot_file, memory_file)
 }
 
+#[test]
+fn test_snapshot_size_cap() {
+    let snapshot_file = TempFile::new().unwrap();
+    let memory_file = TempFile::new().unwrap();
+
+    let (vmm, _) = create_vmm(Some(NOISY_KERNEL_IMAGE), false, true);
+    vmm.lock().unwrap().pause_vm().unwrap();
+
+    let snapshot_params = CreateSnapshotParams {
+        snapshot_type: SnapshotType::Full,
+        snapshot_path: snapshot_file.as_path().to_path_buf(),
+        mem_file_path: memory_file.as_path().to_path_buf(),
+        max_snapshot_size: Some(0),
+        require_guest_quiesce: false,
+        abort_on_guest_reset: false,
+        quiesce_timeout_ms: None,
+    };
+    let vm_info = VmInfo {
+        mem_size_mib: 1u64,
+        ..Default::default()
+    };
+    let err =
+        persist::create_snapshot(&mut vmm.lock().unwrap(), &vm_info, &snapshot_params).unwrap_err();
+    assert!(
+        matches!(err, CreateSnapshotError::SnapshotTooLarge(size, 0) if size > 0),
+        "{:?}",
+        err
+    );
+    // Nothing was written.
+    assert_eq!(snapshot_file.as_file().metadata().unwrap().len(), 0);
+    assert_eq!(memory_file.as_file().metadata().unwrap().len(), 0);
+
+    vmm.lock().unwrap().stop(FcExitCode::Ok);
+}
+
+#[test]
+fn test_snapshot_requires_guest_quiesce() {
+    let snapshot_file = TempFile::new().unwrap();
+    let memory_file = TempFile::new().unwrap();
+
+    let (vmm, _) = create_vmm(Some(NOISY_KERNEL_IMAGE), false, true);
+    vmm.lock().unwrap().pause_vm().unwrap();
+
+    let snapshot_params = CreateSnapshotParams {
+        snapshot_type: SnapshotType::Full,
+        snapshot_path: snapshot_file.as_path().to_path_buf(),
+        mem_file_path: memory_file.as_path().to_path_buf(),
+        max_snapshot_size: None,
+        require_guest_quiesce: true,
+        abort_on_guest_reset: false,
+        quiesce_timeout_ms: None,
+    };
+    let vm_info = VmInfo {
+        mem_size_mib: 1u64,
+        ..Default::default()
+    };
+    // No vsock device, hence no guest agent to ack the quiesce request.
+    vmm.lock()
+        .unwrap()
+        .request_guest_quiesce(Duration::from_secs(1))
+        .unwrap_err();
+    let err =
+        persist::create_snapshot(&mut vmm.lock().unwrap(), &vm_info, &snapshot_params).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            CreateSnapshotError::GuestNotQuiesced(QuiesceState::NoAgent)
+        ),
+        "{:?}",
+        err
+    );
+    // Nothing was written.
+    assert_eq!(snapshot_file.as_file().metadata().unwrap().len(), 0);
+    assert_eq!(memory_file.as_file().metadata().unwrap().len(), 0);
+
+    vmm.lock().unwrap().stop(FcExitCode::Ok);
+}
+
 fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
     use vmm::vstate::memory::{GuestMemoryExtension, GuestMemoryMmap};
 
@@ -106,6 +106,7 @@ class Api:
         self.describe = Resource(self, "/")
         self.vm = Resource(self, "/vm")
         self.vm_config = Resource(self, "/vm/config")
+        self.vm_devices = Resource(self, "/vm/devices")
         self.actions = Resource(self, "/actions")
         self.boot = Resource(self, "/boot-source")
         self.drive = Resource(self, "/drives", "drive_id")
@@ -121,5 +122,8 @@ class Api:
         self.vsock = Resource(self, "/vsock")
         self.snapshot_create = Resource(self, "/snapshot/create")
         self.snapshot_load = Resource(self, "/snapshot/load")
+        self.snapshot_quiesce = Resource(self, "/snapshot/quiesce")
         self.cpu_config = Resource(self, "/cpu-config")
         self.entropy = Resource(self, "/entropy")
+        self.fs = Resource(self, "/fs", "fs_id")
+        self.input = Resource(self, "/input", "input_id")
@@ -91,6 +91,15 @@ def validate_fc_metrics(metrics):
         "rate_limiter_throttled_events",
         "io_engine_throttled_events",
         "remaining_reqs_count",
+        "io_retry_count",
+        "discard_count",
+        "discard_sectors",
+        "write_zeroes_count",
+        "write_zeroes_sectors",
+        "flush_ignored_count",
+        "suppressed_queue_events",
+        "write_batch_count",
+        "chains_too_long_count",
         {"read_agg": latency_agg_metrics_fields},
         {"write_agg": latency_agg_metrics_fields},
     ]
@@ -121,6 +130,9 @@ def validate_fc_metrics(metrics):
         "tx_queue_event_count",
         "tx_rate_limiter_event_count",
         "tx_rate_limiter_throttled",
+        "tx_host_memory_throttled",
+        "tx_host_memory_rejected",
+        "tx_delay_throttled",
         "tx_spoofed_mac_count",
         "tx_remaining_reqs_count",
         {"tap_write_agg": latency_agg_metrics_fields},
@@ -135,6 +147,7 @@ def validate_fc_metrics(metrics):
         ],
         "balloon": [
             "activate_fails",
+            "cfg_fails",
             "inflate_count",
             "stats_updates_count",
             "stats_update_fails",
@@ -156,6 +169,7 @@ def validate_fc_metrics(metrics):
             "error_count",
             "missed_read_count",
             "missed_write_count",
+            "overflow_drop_count",
             "read_count",
             "reset_count",
             "write_count",
@@ -241,12 +255,15 @@ def validate_fc_metrics(metrics):
         "vmm": [
             "device_events",
             "panic_count",
+            "mmio_invalid_accesses",
         ],
         "uart": [
+            "dropped_bytes_count",
             "error_count",
             "flush_count",
             "missed_read_count",
             "missed_write_count",
+            "panic_count",
             "read_count",
             "write_count",
         ],
@@ -276,10 +293,13 @@ def validate_fc_metrics(metrics):
             "conns_added",
             "conns_killed",
             "conns_removed",
+            "conns_refused",
             "killq_resync",
             "tx_flush_fails",
             "tx_write_fails",
             "rx_read_fails",
+            "guest_log_records",
+            "guest_log_records_dropped",
         ],
         "entropy": [
             "activate_fails",
@@ -289,6 +309,15 @@ def validate_fc_metrics(metrics):
             "host_rng_fails",
             "entropy_rate_limiter_throttled",
             "rate_limiter_event_count",
+            "entropy_lifetime_limit_denied",
+        ],
+        "input": [
+            "activate_fails",
+            "cfg_fails",
+            "event_fails",
+            "events_sent",
+            "events_dropped",
+            "status_events",
         ],
     }
 
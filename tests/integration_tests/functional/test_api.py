@@ -202,7 +202,7 @@ def test_net_api_put_update_pre_boot(uvm_plain):
     second_if_name = "second_tap"
     tap2 = net_tools.Tap(second_if_name, test_microvm.netns.id)
     test_microvm.api.network.put(
-        iface_id="2", guest_mac="07:00:00:00:00:01", host_dev_name=tap2.name
+        iface_id="2", guest_mac="0a:00:00:00:00:01", host_dev_name=tap2.name
     )
 
     # Updates to a network interface with an unavailable MAC are not allowed.
@@ -219,7 +219,7 @@ def test_net_api_put_update_pre_boot(uvm_plain):
     )
 
     # Updates to a network interface with an unavailable name are not allowed.
-    expected_msg = "Could not create the network device"
+    expected_msg = "Cannot open/create the tap device"
     with pytest.raises(RuntimeError, match=expected_msg):
         test_microvm.api.network.put(
             iface_id="1", host_dev_name=second_if_name, guest_mac="06:00:00:00:00:01"
@@ -515,8 +515,8 @@ def test_api_put_update_post_boot(uvm_plain, io_engine):
             iface_id="1", host_dev_name=tap1.name, guest_mac="06:00:00:00:00:02"
         )
 
-    # Block device update is not allowed after boot.
-    with pytest.raises(RuntimeError, match=NOT_SUPPORTED_AFTER_START):
+    # Block device hot-plug needs a PCI transport, MMIO devices are fixed at boot.
+    with pytest.raises(RuntimeError, match="requires a PCI transport"):
         test_microvm.api.drive.put(
             drive_id="rootfs",
             path_on_host=test_microvm.jailer.jailed_path(test_microvm.rootfs_file),
@@ -826,6 +826,14 @@ def _drive_patch(test_microvm):
             },
         )
 
+    # Patches of the cache type for vhost-user block are not allowed.
+    with pytest.raises(RuntimeError, match=expected_msg):
+        test_microvm.api.drive.patch(drive_id="scratch_vub", cache_type="Writeback")
+
+    # vhost-user block devices cannot be disabled.
+    with pytest.raises(RuntimeError, match="Device scratch_vub cannot be disabled"):
+        test_microvm.api.drive.patch(drive_id="scratch_vub", enabled=False)
+
     drive_path = "foo.bar"
 
     # Cannot patch drive permissions post boot.
@@ -887,6 +895,13 @@ def _drive_patch(test_microvm):
             },
         )
 
+    # Updates to `cache_type` are allowed.
+    test_microvm.api.drive.patch(drive_id="scratch", cache_type="Writeback")
+
+    # Drives can be disabled and enabled again.
+    test_microvm.api.drive.patch(drive_id="scratch", enabled=False)
+    test_microvm.api.drive.patch(drive_id="scratch", enabled=True)
+
     # Validate full vm configuration after patching drives.
     response = test_microvm.api.vm_config.get().json()
     assert response["drives"] == [
@@ -899,13 +914,22 @@ def _drive_patch(test_microvm):
             "path_on_host": "/ubuntu-22.04.squashfs",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "interrupt_moderation": None,
+            "io_retry": None,
+            "geometry": None,
+            "discard": False,
+            "max_chain_len": 256,
+            "request_record_path": None,
+            "restore_notification_window_ms": None,
+            "write_batch": None,
+            "fd_on_host": None,
             "socket": None,
         },
         {
             "drive_id": "scratch",
             "partuuid": None,
             "is_root_device": False,
-            "cache_type": "Unsafe",
+            "cache_type": "Writeback",
             "is_read_only": False,
             "path_on_host": "/scratch_new.ext4",
             "rate_limiter": {
@@ -913,6 +937,15 @@ def _drive_patch(test_microvm):
                 "ops": {"size": 500, "one_time_burst": None, "refill_time": 100},
             },
             "io_engine": "Async" if is_io_uring_supported() else "Sync",
+            "interrupt_moderation": None,
+            "io_retry": None,
+            "geometry": None,
+            "discard": False,
+            "max_chain_len": 256,
+            "request_record_path": None,
+            "restore_notification_window_ms": None,
+            "write_batch": None,
+            "fd_on_host": None,
             "socket": None,
         },
         {
@@ -924,6 +957,15 @@ def _drive_patch(test_microvm):
             "path_on_host": None,
             "rate_limiter": None,
             "io_engine": None,
+            "interrupt_moderation": None,
+            "io_retry": None,
+            "geometry": None,
+            "discard": None,
+            "max_chain_len": None,
+            "request_record_path": None,
+            "restore_notification_window_ms": None,
+            "write_batch": None,
+            "fd_on_host": None,
             "socket": str(
                 Path("/")
                 / test_microvm.disks_vhost_user["scratch_vub"].socket_path.name
@@ -1009,6 +1051,9 @@ def test_api_entropy(uvm_plain):
     # Overwriting an existing should be OK.
     test_microvm.api.entropy.put()
 
+    # Setting a lifetime limit should be OK.
+    test_microvm.api.entropy.put(lifetime_byte_limit=4096)
+
     # Start the microvm
     test_microvm.start()
 
@@ -1119,6 +1164,15 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
             "path_on_host": f"/{uvm_nano.rootfs_file.name}",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "interrupt_moderation": None,
+            "io_retry": None,
+            "geometry": None,
+            "discard": False,
+            "max_chain_len": 256,
+            "request_record_path": None,
+            "restore_notification_window_ms": None,
+            "write_batch": None,
+            "fd_on_host": None,
             "socket": None,
         }
     ]
@@ -1133,7 +1187,13 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
 
     # Add a vsock device.
     uvm_nano.api.vsock.put(guest_cid=15, uds_path="vsock.sock")
-    setup_cfg["vsock"] = {"guest_cid": 15, "uds_path": "vsock.sock"}
+    setup_cfg["vsock"] = {
+        "guest_cid": 15,
+        "uds_path": "vsock.sock",
+        "max_connections": 1023,
+        "log_port": None,
+        "quiesce_port": None,
+    }
 
     setup_cfg["logger"] = None
     setup_cfg["metrics"] = None
@@ -1163,6 +1223,8 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
             "host_dev_name": net_iface.tap_name,
             "rx_rate_limiter": None,
             "tx_rate_limiter": tx_rl,
+            "tx_delay": None,
+            "flow_stats": False,
         }
     ]
 
@@ -1190,12 +1252,58 @@ def test_get_full_config_after_restoring_snapshot(microvm_factory, uvm_nano):
     # We should expect a null entropy device
     expected_cfg["entropy"] = None
 
+    # We should expect no fs device
+    expected_cfg["fs"] = []
+
+    # We should expect no input device
+    expected_cfg["input"] = []
+
+    # We should expect the default serial console configuration
+    expected_cfg["serial"] = {
+        "panic_signature": None,
+        "output_socket": None,
+        "output_path": None,
+    }
+
+    # We should expect no VMClock device
+    expected_cfg["clock"] = {"vmclock": False, "host_time": False}
+
     # Validate full vm configuration post-restore.
     response = uvm2.api.vm_config.get().json()
     assert response != setup_cfg
     assert response == expected_cfg
 
 
+def test_get_device_debug_dump(uvm_plain):
+    """
+    Test the runtime state reported for the attached devices.
+    """
+    test_microvm = uvm_plain
+    test_microvm.spawn()
+    test_microvm.basic_config()
+    iface_id = test_microvm.add_net_iface().dev_name
+
+    with pytest.raises(RuntimeError, match=NOT_SUPPORTED_BEFORE_START):
+        test_microvm.api.vm_devices.get()
+
+    test_microvm.start()
+
+    dump = test_microvm.api.vm_devices.get().json()
+    devices = {device["id"]: device for device in dump["devices"]}
+    assert devices["rootfs"]["device_type"] == {"Virtio": 2}
+    assert devices[iface_id]["device_type"] == {"Virtio": 1}
+    for dev_id in ["rootfs", iface_id]:
+        device = devices[dev_id]
+        assert device["transport"] == "mmio"
+        assert device["irqs"]
+        assert device["virtio"]["activated"]
+        assert device["virtio"]["uptime_us"] > 0
+        assert device["virtio"]["enabled"]
+        assert device["virtio"]["queues"]
+    assert dump["host_fd_count"] >= 2
+    assert dump["host_memory"] == 0
+
+
 def test_get_full_config(uvm_plain):
     """
     Test the reported configuration of a microVM configured with all resources.
@@ -1230,6 +1338,15 @@ def test_get_full_config(uvm_plain):
             "path_on_host": "/ubuntu-22.04.squashfs",
             "rate_limiter": None,
             "io_engine": "Sync",
+            "interrupt_moderation": None,
+            "io_retry": None,
+            "geometry": None,
+            "discard": False,
+            "max_chain_len": 256,
+            "request_record_path": None,
+            "restore_notification_window_ms": None,
+            "write_batch": None,
+            "fd_on_host": None,
             "socket": None,
         }
     ]
@@ -1244,7 +1361,13 @@ def test_get_full_config(uvm_plain):
 
     # Add a vsock device.
     response = test_microvm.api.vsock.put(guest_cid=15, uds_path="vsock.sock")
-    expected_cfg["vsock"] = {"guest_cid": 15, "uds_path": "vsock.sock"}
+    expected_cfg["vsock"] = {
+        "guest_cid": 15,
+        "uds_path": "vsock.sock",
+        "max_connections": 1023,
+        "log_port": None,
+        "quiesce_port": None,
+    }
 
     # Add a net device.
     iface_id = "1"
@@ -1268,6 +1391,8 @@ def test_get_full_config(uvm_plain):
             "guest_mac": "06:00:00:00:00:01",
             "rx_rate_limiter": None,
             "tx_rate_limiter": tx_rl,
+            "tx_delay": None,
+            "flow_stats": False,
         }
     ]
 
@@ -1290,6 +1415,22 @@ def test_get_full_config(uvm_plain):
     # We should expect a null entropy device
     expected_cfg["entropy"] = None
 
+    # We should expect no fs device
+    expected_cfg["fs"] = []
+
+    # We should expect no input device
+    expected_cfg["input"] = []
+
+    # We should expect the default serial console configuration
+    expected_cfg["serial"] = {
+        "panic_signature": None,
+        "output_socket": None,
+        "output_path": None,
+    }
+
+    # We should expect no VMClock device
+    expected_cfg["clock"] = {"vmclock": False, "host_time": False}
+
     # Getting full vm configuration should be available pre-boot.
     response = test_microvm.api.vm_config.get()
     assert response.json() == expected_cfg
@@ -0,0 +1,67 @@
+# Copyright 2024 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests for the vhost-user virtio-fs device."""
+
+import os
+import shutil
+import subprocess
+import time
+from pathlib import Path
+
+import pytest
+
+VIRTIOFSD = shutil.which("virtiofsd") or "/usr/libexec/virtiofsd"
+
+
+@pytest.mark.skipif(
+    not os.path.exists(VIRTIOFSD), reason="virtiofsd backend is not available"
+)
+def test_fs_shared_dir(uvm_plain_any):
+    """
+    Check that a host directory shared through virtio-fs is visible in the guest.
+    """
+    vm = uvm_plain_any
+    vm.spawn()
+    vm.basic_config()
+    vm.add_net_iface()
+
+    shared_dir = Path(vm.chroot()) / "shared"
+    shared_dir.mkdir()
+    (shared_dir / "hello.txt").write_text("hello from the host\n")
+
+    socket_path = Path(vm.chroot()) / "fs0_vhost_user.sock"
+    backend = subprocess.Popen(
+        [
+            VIRTIOFSD,
+            "--socket-path",
+            str(socket_path),
+            "--shared-dir",
+            str(shared_dir),
+            "--sandbox",
+            "none",
+        ]
+    )
+    try:
+        # Give the backend time to initialise.
+        time.sleep(1)
+        assert backend.poll() is None, "backend is not up"
+        os.chown(socket_path, vm.jailer.uid, vm.jailer.gid)
+
+        # The tag must fit in the config space of the device.
+        with pytest.raises(RuntimeError):
+            vm.api.fs.put(fs_id="fs0", tag="t" * 37, socket="/" + socket_path.name)
+
+        vm.api.fs.put(fs_id="fs0", tag="shared", socket="/" + socket_path.name)
+        assert vm.api.vm_config.get().json()["fs"] == [
+            {"fs_id": "fs0", "tag": "shared", "socket": "/" + socket_path.name}
+        ]
+
+        vm.start()
+
+        vm.ssh.check_output("mkdir -p /mnt/shared")
+        vm.ssh.check_output("mount -t virtiofs shared /mnt/shared")
+        _, stdout, _ = vm.ssh.check_output("cat /mnt/shared/hello.txt")
+        assert stdout == "hello from the host\n"
+    finally:
+        backend.terminate()
+        backend.wait()
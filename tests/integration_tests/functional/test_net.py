@@ -66,7 +66,7 @@ def test_multi_queue_unsupported(uvm_plain):
     utils.check_output(f"ip link set {tapname} netns {microvm.netns.id}")
 
     expected_msg = re.escape(
-        "Could not create the network device: Open tap device failed:"
+        "Cannot open/create the tap device:"
         " Error while creating ifreq structure: Invalid argument (os error 22)."
         f" Invalid TUN/TAP Backend provided by {tapname}. Check our documentation on setting"
         " up the network devices."